@@ -0,0 +1,137 @@
+//! Benchmarks of the interpreter running representative workloads
+//!
+//! Each workload repeats the body of a loop `ITERATIONS` times in one
+//! function, as the interpreter does not take jumps, and the function is run
+//! once per iteration of the benchmark. Throughput is reported in iterations
+//! of the loop, so the results of workloads of different lengths compare.
+
+use std::cell::RefCell;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use tstack::asm;
+use tstack::Engine;
+
+/// The number of times the body of each workload is repeated in a run
+const ITERATIONS: usize = 256;
+
+/// Functions called by the workloads
+const LIBRARY: &str = "
+    .func square
+        DUPE_1
+        MUL
+        RETURN
+    .func depth_1
+        CALL_C depth_2
+        RETURN
+    .func depth_2
+        CALL_C depth_3
+        RETURN
+    .func depth_3
+        CALL_C depth_4
+        RETURN
+    .func depth_4
+        RETURN";
+
+/// Create an engine running `body` `ITERATIONS` times after `setup`, with the
+/// string its `host.append` function appends characters to
+fn engine(setup: &str, body: &str) -> (Engine, Rc<RefCell<String>>) {
+    let source = format!(
+        ".export main
+            {}
+            .rept {}
+            {}
+            .endr
+            RETURN
+        {}",
+        setup, ITERATIONS, body, LIBRARY
+    );
+    let module = asm::assemble("bench", &source, Path::new(".")).unwrap();
+    let mut engine = Engine::new();
+    engine.set_stdout(io::sink());
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.register_fn("host", "twice", |value: u64| value * 2).unwrap();
+    let text = Rc::new(RefCell::new(String::new()));
+    let appended = Rc::clone(&text);
+    engine
+        .register_fn("host", "append", move |c: u64| {
+            appended.borrow_mut().push(char::from_u32(c as u32).unwrap_or('?'))
+        })
+        .unwrap();
+    engine.link().unwrap();
+    (engine, text)
+}
+
+/// Benchmark workloads, each given as the name, setup, and body of the loop
+fn bench_workloads(c: &mut Criterion, group: &str, workloads: &[(&str, &str, &str)]) {
+    let mut group = c.benchmark_group(group);
+    group.throughput(Throughput::Elements(ITERATIONS as u64));
+    for (name, setup, body) in workloads {
+        let (mut engine, text) = engine(setup, body);
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                engine.stack.clear();
+                text.borrow_mut().clear();
+                engine.run(0, 0).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_arithmetic(c: &mut Criterion) {
+    bench_workloads(
+        c,
+        "arithmetic",
+        &[
+            ("integer", "CONST_1", "CONST_3\n MUL\n CONST_U16 1000\n ADD\n CONST_8\n DIV"),
+            ("float", "CONST_1\n ITOF", "CONST_3\n ITOF\n FMUL\n CONST_2\n ITOF\n FDIV"),
+            ("immediate", "CONST_1", "MUL_C 3\n ADD_C 1000\n DIV_C 8"),
+        ],
+    );
+}
+
+fn bench_calls(c: &mut Criterion) {
+    bench_workloads(
+        c,
+        "calls",
+        &[
+            ("bytecode", "", "CONST_2\n CALL_C square\n POP_1"),
+            ("nested", "", "CALL_C depth_1"),
+            ("native", "", "CONST_2\n CALL_EXT_C host.twice\n POP_1"),
+            ("reference", "", "CONST_2\n FUNC_REF_C square\n CALL_REF\n POP_1"),
+        ],
+    );
+}
+
+fn bench_shuffling(c: &mut Criterion) {
+    bench_workloads(
+        c,
+        "shuffling",
+        &[
+            ("dupe_pop", "CONST_1\n CONST_2", "DUPE_1\n DUPE_C 2\n POP_C 2\n POP_1"),
+            ("swap", "CONST_1\n CONST_2\n CONST_3", "SWAP_1\n SWAP_C 2"),
+            (
+                "rotate",
+                "CONST_1\n CONST_2\n CONST_3\n CONST_4",
+                "ROTATE_1_C 3\n ROTATE_1_C 2\n ROTATE_1_C 4",
+            ),
+        ],
+    );
+}
+
+fn bench_strings(c: &mut Criterion) {
+    bench_workloads(
+        c,
+        "strings",
+        &[
+            ("print", "", "CONST_U16 12345\n PRINT_U64\n CONST_N1\n PRINT_I64"),
+            ("append", "", "CONST_U16 0x61\n CALL_EXT_C host.append"),
+        ],
+    );
+}
+
+criterion_group!(benches, bench_arithmetic, bench_calls, bench_shuffling, bench_strings);
+criterion_main!(benches);
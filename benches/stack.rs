@@ -0,0 +1,66 @@
+//! Benchmarks of the instructions operating on many stack values at once
+//!
+//! Each benchmark fills the stack with `n` values and then runs a block of
+//! one instruction operating on all of them, for several sizes of `n`.
+
+use std::rc::Rc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tstack::bytecode::Instruction;
+use tstack::module::{Module, SymbolDef};
+use tstack::Engine;
+
+/// The number of times each instruction is repeated in a run
+const REPEAT: usize = 64;
+
+/// The numbers of values each instruction operates on
+const SIZES: [u16; 3] = [16, 256, 4096];
+
+/// A function building the instructions run over `n` values
+type Body = fn(u16) -> Vec<Instruction>;
+
+/// Create an engine running `body` over a stack of `n` values
+fn engine(n: u16, body: &[Instruction]) -> Engine {
+    let mut code = vec![Instruction::Const1];
+    let mut depth = 1;
+    while depth < n {
+        code.push(Instruction::DupeC(depth));
+        depth *= 2;
+    }
+    for _ in 0..REPEAT {
+        code.extend_from_slice(body);
+    }
+    code.push(Instruction::Return);
+
+    let main = SymbolDef { exported: true, ..SymbolDef::new("main") };
+    let module = Module::from_instructions("bench", [(main, code)]).unwrap();
+    let mut engine = Engine::new();
+    engine.maxstack = 2 * n as usize;
+    engine.add_module(Rc::new(module)).unwrap();
+    engine
+}
+
+fn bench_stack(c: &mut Criterion) {
+    let cases: [(&str, Body); 3] = [
+        ("dupe_pop", |n| vec![Instruction::DupeC(n), Instruction::PopC(n)]),
+        ("swap", |n| vec![Instruction::SwapC(n)]),
+        ("rotate", |n| vec![Instruction::Rotate1C(n)]),
+    ];
+    for (name, body) in cases {
+        let mut group = c.benchmark_group(name);
+        for n in SIZES {
+            let mut engine = engine(n, &body(n));
+            group.throughput(Throughput::Elements((REPEAT * n as usize) as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, _| {
+                b.iter(|| {
+                    engine.stack.clear();
+                    engine.run(0, 0).unwrap();
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_stack);
+criterion_main!(benches);
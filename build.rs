@@ -0,0 +1,23 @@
+fn main() {
+    #[cfg(feature = "capi")]
+    capi::generate_header();
+}
+
+#[cfg(feature = "capi")]
+mod capi {
+    use std::path::PathBuf;
+
+    /// Generate the C header declaring the functions of the `capi` module
+    pub fn generate_header() {
+        println!("cargo:rerun-if-changed=src/capi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        let dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+        let config = cbindgen::Config::from_file(dir.join("cbindgen.toml")).unwrap();
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(dir.join("src/capi.rs"))
+            .generate()
+            .expect("unable to generate the C header")
+            .write_to_file(dir.join("include/tstack.h"));
+    }
+}
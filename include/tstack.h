@@ -0,0 +1,194 @@
+#ifndef TSTACK_H
+#define TSTACK_H
+
+/* Generated from src/capi.rs when building with the `capi` feature; do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/*
+ Returned by functions which succeeded
+ */
+#define TS_OK 0
+
+/*
+ Returned by functions which failed
+ */
+#define TS_ERROR -1
+
+/*
+ An engine created by `ts_engine_new`
+ */
+typedef struct TsEngine TsEngine;
+
+/*
+ The stack given to a native function registered from C
+ */
+typedef struct TsStack TsStack;
+
+/*
+ A native function registered from C
+ */
+typedef int32_t (*TsNativeFn)(struct TsStack *stack, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Create an engine
+
+ The engine must be freed with `ts_engine_free`.
+ */
+struct TsEngine *ts_engine_new(void);
+
+/*
+ Free an engine created by `ts_engine_new`
+
+ # Safety
+ `engine` must be null or an engine which has not been freed.
+ */
+void ts_engine_free(struct TsEngine *engine);
+
+/*
+ Get the message of the last failure of an engine
+
+ The message is empty if nothing has failed, and is valid until the next
+ call taking the engine.
+
+ # Safety
+ `engine` must be a valid engine.
+ */
+const char *ts_engine_error(const struct TsEngine *engine);
+
+/*
+ Load a module from a buffer in the binary format, returning its ID
+
+ # Safety
+ `engine` must be a valid engine, and `data` must point to `len` bytes.
+ */
+int64_t ts_engine_load(struct TsEngine *engine, const uint8_t *data, size_t len);
+
+/*
+ Load a module from a `.tsb` file, returning its ID
+
+ # Safety
+ `engine` must be a valid engine, and `path` a nul terminated string.
+ */
+int64_t ts_engine_load_file(struct TsEngine *engine, const char *path);
+
+/*
+ Link the external symbols and globals of the loaded modules
+
+ # Safety
+ `engine` must be a valid engine.
+ */
+int32_t ts_engine_link(struct TsEngine *engine);
+
+/*
+ Register a native function, returning its symbol ID
+
+ `user_data` is passed to each call of the function, and must stay valid
+ for as long as the engine.
+
+ # Safety
+ `engine` must be a valid engine, and `module` and `symbol` nul terminated
+ strings.
+ */
+int64_t ts_engine_register(struct TsEngine *engine,
+                           const char *module,
+                           const char *symbol,
+                           TsNativeFn function,
+                           void *user_data);
+
+/*
+ Find the module and symbol IDs of an exported symbol
+
+ # Safety
+ `engine` must be a valid engine, `module` and `symbol` nul terminated
+ strings, and `module_id` and `symbol_id` valid pointers.
+ */
+int32_t ts_engine_find(struct TsEngine *engine,
+                       const char *module,
+                       const char *symbol,
+                       uint32_t *module_id,
+                       uint32_t *symbol_id);
+
+/*
+ Run an exported symbol
+
+ # Safety
+ `engine` must be a valid engine.
+ */
+int32_t ts_engine_run(struct TsEngine *engine, uint32_t module_id, uint32_t symbol_id);
+
+/*
+ Get the number of values on the stack of an engine
+
+ # Safety
+ `engine` must be a valid engine.
+ */
+size_t ts_engine_stack_len(const struct TsEngine *engine);
+
+/*
+ Get the values on the stack of an engine, with the top of the stack last
+
+ The values are valid until the next call taking the engine.
+
+ # Safety
+ `engine` must be a valid engine.
+ */
+const uint64_t *ts_engine_stack(const struct TsEngine *engine);
+
+/*
+ Push a value onto the stack of an engine
+
+ # Safety
+ `engine` must be a valid engine.
+ */
+int32_t ts_engine_push(struct TsEngine *engine, uint64_t value);
+
+/*
+ Remove every value from the stack of an engine
+
+ # Safety
+ `engine` must be a valid engine.
+ */
+void ts_engine_clear(struct TsEngine *engine);
+
+/*
+ Get the number of values on the stack given to a native function
+
+ # Safety
+ `stack` must be the stack given to the running native function.
+ */
+size_t ts_stack_len(const struct TsStack *stack);
+
+/*
+ Pop the value on the top of the stack given to a native function
+
+ If the stack is empty, this fails and the native function faults with a
+ stack underflow when it returns any status other than `TS_OK`.
+
+ # Safety
+ `stack` must be the stack given to the running native function, and
+ `value` a valid pointer.
+ */
+int32_t ts_stack_pop(struct TsStack *stack, uint64_t *value);
+
+/*
+ Push a value onto the stack given to a native function
+
+ # Safety
+ `stack` must be the stack given to the running native function.
+ */
+int32_t ts_stack_push(struct TsStack *stack, uint64_t value);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TSTACK_H */
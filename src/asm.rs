@@ -0,0 +1,471 @@
+//! A text assembler for hand written programs
+//!
+//! Each line of the source holds an instruction, a label, or a directive, and
+//! `;` starts a comment which runs to the end of the line. Instructions are
+//! written in the form produced by the `Display` implementation of
+//! `Instruction`, and code belongs to the symbol started by the closest
+//! `.func` or `.export` directive above it:
+//!
+//! ```text
+//! .const COUNT 4
+//! .include "util.tasm"    ; defines the `square` symbol
+//!
+//! .export main
+//!     CONST_U16 COUNT
+//! top:
+//!     DUPE_1
+//!     CALL_C square
+//!     CALL_EXT_C host.log
+//!     CONST_1
+//!     SWAP_1
+//!     SUB
+//!     DUPE_1
+//!     JMP_NZ.REL top
+//!     RETURN
+//! ```
+//!
+//! The directives are:
+//!
+//!| Directive              | Description
+//!|------------------------|------------
+//!| `.func NAME`           | Start a local symbol
+//!| `.export NAME`         | Start an exported local symbol
+//!| `.const NAME value`    | Define a constant which may be used in place of any number
+//!| `.include "file"`      | Assemble the lines of another file in place of the directive
+//!| `.rept count` `.endr`  | Assemble the lines between the directives `count` times
+//!
+//! Numbers are decimal, or hexadecimal when prefixed with `0x`, and may be
+//! negative. Constants must be defined before they are used, and the value of
+//! a constant may itself be a constant. Included files are resolved against
+//! the directory of the file including them, and errors in an included file
+//! report the line within that file.
+//!
+//! Operands may also be names: `CALL_C` and `FUNC_REF_C` take the name of a
+//! local symbol, `CALL_EXT_C` and `FUNC_REF_EXT_C` take a name qualified by
+//! its module, which becomes an external symbol of the module, and jumps take
+//! a label. Jumps to labels are written without a source, as in
+//! `JMP_Z.REL done`, as the smallest encoding which fits the offset is
+//! chosen; only relative jumps may target labels. `FAULT` takes its message
+//! as a quoted string, as in `FAULT "index out of range"`, which is added to
+//! the strings of the module. Labels are local to their symbol. Nothing is
+//! added to the code of a symbol, so each symbol must end with its own
+//! `RETURN` or `HALT`.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::builder::{self, BytecodeBuilder, Label};
+use crate::bytecode::{jump, Instruction};
+use crate::errors::CompileError;
+use crate::module::{ExternalSymbol, LocalSymbol, Module};
+
+/// The deepest `.include` directives may be nested
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Assemble the source of a program into a module with the given name
+///
+/// `dir` is the directory the files named by `.include` directives in the
+/// source are resolved against.
+pub fn assemble(name: &str, source: &str, dir: &Path) -> Result<Module, CompileError> {
+    let mut expander = Expander { constants: HashMap::new(), depth: 0 };
+    let mut lines = vec![];
+    let source: Vec<(&str, u32)> =
+        source.lines().enumerate().map(|(i, text)| (text, i as u32 + 1)).collect();
+    expander.expand(&source, dir, &mut lines)?;
+
+    let mut symbols: HashMap<String, u16> = HashMap::new();
+    for (line, number) in lines.iter() {
+        if let Line::Symbol(name, _) = line {
+            let id = symbols.len() as u16;
+            if symbols.insert(name.clone(), id).is_some() {
+                return Err(CompileError::DuplicateName(*number, name.clone()));
+            }
+        }
+    }
+
+    let mut module = Module::new(name);
+    let mut imports: Vec<(String, String)> = vec![];
+    let mut messages: Vec<String> = vec![];
+    let mut current: Option<SymbolAssembler> = None;
+    for (line, number) in lines {
+        match line {
+            Line::Symbol(name, exported) => {
+                if let Some(symbol) = current.take() {
+                    symbol.finish(&mut module)?;
+                }
+                current = Some(SymbolAssembler::new(name, exported));
+            }
+            Line::Label(name) => match &mut current {
+                Some(symbol) => symbol.label(name, number)?,
+                None => return Err(syntax(number, "label outside of a symbol")),
+            },
+            Line::Instruction(mnemonic, operands) => match &mut current {
+                Some(symbol) => symbol.instruction(
+                    &mnemonic,
+                    operands,
+                    number,
+                    &symbols,
+                    &mut imports,
+                    &mut messages,
+                )?,
+                None => return Err(syntax(number, "instruction outside of a symbol")),
+            },
+        }
+    }
+    if let Some(symbol) = current {
+        symbol.finish(&mut module)?;
+    }
+    // Messages follow the name of each symbol, which is where `instruction`
+    // numbered them from
+    module.strings.extend(messages);
+    for (from, symbol) in imports {
+        module.strings.push(from);
+        module.strings.push(symbol);
+        module.external_symbols.push(ExternalSymbol {
+            module_name_id: module.strings.len() as u32 - 2,
+            module_id: 0,
+            symbol_name_id: module.strings.len() as u32 - 1,
+            symbol_id: 0,
+            signature: None,
+            version_req: None,
+            alias: None,
+        });
+    }
+    Ok(module)
+}
+
+fn syntax(line: u32, message: &str) -> CompileError {
+    CompileError::InvalidSyntax(line, String::from(message))
+}
+
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn parse_number(text: &str) -> Option<i128> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i128::from_str_radix(hex, 16).ok()?,
+        None if digits.starts_with(|c: char| c.is_ascii_digit()) => digits.parse().ok()?,
+        None => return None,
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Remove the comment from a line of source, if any
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..index],
+            _ => (),
+        }
+    }
+    text
+}
+
+/// An operand of an instruction
+enum Operand {
+    Number(i128),
+    Name(String),
+    /// A quoted string, without the quotes
+    Text(String),
+}
+
+/// A line of source, after directives have been expanded
+enum Line {
+    /// The start of a symbol, and if it is exported
+    Symbol(String, bool),
+    Label(String),
+    /// The mnemonic and operands of an instruction
+    Instruction(String, Vec<Operand>),
+}
+
+/// The state of expanding the directives of the source
+struct Expander {
+    constants: HashMap<String, i128>,
+    /// The number of `.include` directives being expanded
+    depth: usize,
+}
+
+impl Expander {
+    /// Get the value of a number or constant
+    fn value(&self, text: &str, line: u32) -> Result<i128, CompileError> {
+        if let Some(value) = parse_number(text) {
+            return Ok(value);
+        }
+        match self.constants.get(text) {
+            Some(value) => Ok(*value),
+            None if is_name(text) => Err(CompileError::UndefinedName(line, String::from(text))),
+            None => Err(syntax(line, &format!("expected a number, found `{}`", text))),
+        }
+    }
+
+    /// Expand the directives of the given lines of source
+    fn expand(
+        &mut self,
+        lines: &[(&str, u32)],
+        dir: &Path,
+        out: &mut Vec<(Line, u32)>,
+    ) -> Result<(), CompileError> {
+        let mut index = 0;
+        while index < lines.len() {
+            let (text, line) = lines[index];
+            index += 1;
+            let text = strip_comment(text).trim();
+            if text.is_empty() {
+                continue;
+            }
+            let (head, rest) = match text.split_once(char::is_whitespace) {
+                Some((head, rest)) => (head, rest.trim()),
+                None => (text, ""),
+            };
+            match head {
+                ".func" | ".export" => {
+                    if !is_name(rest) {
+                        return Err(syntax(line, &format!("expected a name after `{}`", head)));
+                    }
+                    out.push((Line::Symbol(String::from(rest), head == ".export"), line));
+                }
+                ".const" => {
+                    let (name, value) = match rest.split_once(char::is_whitespace) {
+                        Some((name, value)) if is_name(name) => (name, value.trim()),
+                        _ => return Err(syntax(line, "expected a name and value after `.const`")),
+                    };
+                    let value = self.value(value, line)?;
+                    if self.constants.insert(String::from(name), value).is_some() {
+                        return Err(CompileError::DuplicateName(line, String::from(name)));
+                    }
+                }
+                ".include" => {
+                    let file = match rest.strip_prefix('"').and_then(|r| r.strip_suffix('"')) {
+                        Some(file) => dir.join(file),
+                        None => return Err(syntax(line, "expected a quoted file name")),
+                    };
+                    if self.depth == MAX_INCLUDE_DEPTH {
+                        return Err(syntax(line, "includes are nested too deeply"));
+                    }
+                    let source = std::fs::read_to_string(&file).map_err(|e| {
+                        CompileError::InvalidInclude(line, format!("{}: {}", file.display(), e))
+                    })?;
+                    let source: Vec<(&str, u32)> =
+                        source.lines().enumerate().map(|(i, text)| (text, i as u32 + 1)).collect();
+                    let dir = file.parent().unwrap_or(dir);
+                    self.depth += 1;
+                    self.expand(&source, dir, out)?;
+                    self.depth -= 1;
+                }
+                ".rept" => {
+                    let count = self.value(rest, line)?;
+                    let count = match usize::try_from(count) {
+                        Ok(count) => count,
+                        Err(_) => return Err(syntax(line, "negative `.rept` count")),
+                    };
+                    let end = index
+                        + find_endr(&lines[index..])
+                            .ok_or_else(|| syntax(line, "`.rept` is not ended with `.endr`"))?;
+                    for _ in 0..count {
+                        self.expand(&lines[index..end], dir, out)?;
+                    }
+                    index = end + 1;
+                }
+                ".endr" => return Err(syntax(line, "`.endr` without a `.rept`")),
+                _ if head.starts_with('.') => {
+                    return Err(CompileError::Unsupported(line, format!("directive `{}`", head)));
+                }
+                _ => match text.strip_suffix(':') {
+                    Some(label) if is_name(label) => {
+                        out.push((Line::Label(String::from(label)), line));
+                    }
+                    _ => {
+                        let mut operands = vec![];
+                        let text = rest.strip_prefix('"').and_then(|r| r.strip_suffix('"'));
+                        if let Some(text) = text {
+                            operands.push(Operand::Text(String::from(text)));
+                        } else if !rest.is_empty() {
+                            for operand in rest.split(',').map(str::trim) {
+                                operands.push(match self.constants.get(operand) {
+                                    Some(value) => Operand::Number(*value),
+                                    None if is_name(operand) => {
+                                        Operand::Name(String::from(operand))
+                                    }
+                                    None => Operand::Number(self.value(operand, line)?),
+                                });
+                            }
+                        }
+                        out.push((Line::Instruction(String::from(head), operands), line));
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Find the index of the `.endr` matching a `.rept` in the lines following it
+fn find_endr(lines: &[(&str, u32)]) -> Option<usize> {
+    let mut depth = 0;
+    for (index, (text, _)) in lines.iter().enumerate() {
+        match strip_comment(text).split_whitespace().next() {
+            Some(".rept") => depth += 1,
+            Some(".endr") if depth == 0 => return Some(index),
+            Some(".endr") => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// The code of a symbol being assembled
+struct SymbolAssembler {
+    name: String,
+    exported: bool,
+    builder: BytecodeBuilder,
+    /// The label of each name, the line it is first used on, and if it has
+    /// been bound
+    labels: HashMap<String, (Label, u32, bool)>,
+}
+
+impl SymbolAssembler {
+    fn new(name: String, exported: bool) -> SymbolAssembler {
+        SymbolAssembler { name, exported, builder: BytecodeBuilder::new(), labels: HashMap::new() }
+    }
+
+    fn label_for(&mut self, name: String, line: u32) -> &mut (Label, u32, bool) {
+        let builder = &mut self.builder;
+        self.labels.entry(name).or_insert_with(|| (builder.label(), line, false))
+    }
+
+    /// Bind a label to the current position
+    fn label(&mut self, name: String, line: u32) -> Result<(), CompileError> {
+        let (label, _, bound) = *self.label_for(name.clone(), line);
+        if bound {
+            return Err(CompileError::DuplicateName(line, name));
+        }
+        self.labels.get_mut(&name).unwrap().2 = true;
+        // The label has not been bound before, so this can not fail
+        self.builder.bind(label).unwrap();
+        Ok(())
+    }
+
+    /// Assemble an instruction
+    fn instruction(
+        &mut self,
+        mnemonic: &str,
+        operands: Vec<Operand>,
+        line: u32,
+        symbols: &HashMap<String, u16>,
+        imports: &mut Vec<(String, String)>,
+        messages: &mut Vec<String>,
+    ) -> Result<(), CompileError> {
+        let mut args = vec![];
+        let mut name = None;
+        for operand in operands {
+            match operand {
+                Operand::Number(value) => args.push(value),
+                Operand::Name(text) => name = Some(text),
+                Operand::Text(text) if mnemonic == "FAULT" => {
+                    let index = match messages.iter().position(|m| *m == text) {
+                        Some(index) => index,
+                        None => {
+                            messages.push(text);
+                            messages.len() - 1
+                        }
+                    };
+                    args.push((symbols.len() + index) as i128);
+                }
+                Operand::Text(_) => {
+                    return Err(syntax(line, &format!("`{}` does not take a string", mnemonic)));
+                }
+            }
+        }
+        let name = match name {
+            Some(name) if args.is_empty() => name,
+            None => {
+                return match Instruction::from_parts(mnemonic, &args) {
+                    Some(instruction) => {
+                        self.builder.emit_instruction(instruction);
+                        Ok(())
+                    }
+                    None => Err(syntax(line, &format!("invalid instruction `{}`", mnemonic))),
+                };
+            }
+            Some(_) => return Err(syntax(line, &format!("invalid operands for `{}`", mnemonic))),
+        };
+        match mnemonic {
+            "CALL_C" | "FUNC_REF_C" => match symbols.get(&name) {
+                Some(&id) if mnemonic == "CALL_C" => {
+                    self.builder.emit_instruction(Instruction::CallC(id))
+                }
+                Some(&id) => self.builder.emit_instruction(Instruction::FuncRefC(id)),
+                None => return Err(CompileError::UndefinedName(line, name)),
+            },
+            "CALL_EXT_C" | "FUNC_REF_EXT_C" => {
+                let (module, symbol) = match name.rsplit_once('.') {
+                    Some((module, symbol)) => (String::from(module), String::from(symbol)),
+                    None => return Err(syntax(line, "external symbols are named `module.name`")),
+                };
+                let index = match imports.iter().position(|i| i.0 == module && i.1 == symbol) {
+                    Some(index) => index,
+                    None => {
+                        imports.push((module, symbol));
+                        imports.len() - 1
+                    }
+                };
+                self.builder.emit_instruction(match mnemonic {
+                    "CALL_EXT_C" => Instruction::CallExtC(index as u16),
+                    _ => Instruction::FuncRefExtC(index as u16),
+                });
+            }
+            _ => {
+                // A jump to a label is written as `JMP[_COND].REL`; parse it
+                // with a placeholder source to find the condition
+                let jump = match mnemonic.strip_suffix(".REL") {
+                    Some(head) => Instruction::parse(&format!("{}.C16.REL 0", head)),
+                    None => None,
+                };
+                let condition = match jump {
+                    Some(Instruction::Jump(jump)) => jump.condition,
+                    _ if mnemonic.starts_with("JMP") => {
+                        let message = format!("`{}` can not target a label", mnemonic);
+                        return Err(syntax(line, &message));
+                    }
+                    _ => return Err(CompileError::UndefinedName(line, name)),
+                };
+                let (label, _, _) = *self.label_for(name, line);
+                self.builder.jump(jump::MODE_RELATIVE, condition, label);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the symbol to the end of the module
+    fn finish(self, module: &mut Module) -> Result<(), CompileError> {
+        let mut unbound: Vec<_> = self.labels.iter().filter(|(_, l)| !l.2).collect();
+        unbound.sort_by_key(|(_, l)| l.1);
+        if let Some((name, (_, line, _))) = unbound.first() {
+            return Err(CompileError::UndefinedName(*line, (*name).clone()));
+        }
+        // Every label is bound, so building can not fail
+        let code = self.builder.build().unwrap();
+        let offset = module.bytecode.len() as u32;
+        module.strings.push(self.name);
+        module.local_symbols.push(LocalSymbol {
+            name_id: module.strings.len() as u32 - 1,
+            code_offset: offset,
+            exported: self.exported,
+            signature: None,
+        });
+        for mut relocation in builder::symbol_relocations(&code) {
+            relocation.offset += offset;
+            module.relocations.push(relocation);
+        }
+        module.bytecode.to_mut().extend_from_slice(&code);
+        Ok(())
+    }
+}
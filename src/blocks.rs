@@ -0,0 +1,62 @@
+//! Basic blocks of bytecode, run by the engine as a unit
+//!
+//! A basic block is a run of instructions which execute one after another,
+//! ending with an instruction which may transfer control elsewhere or hand it
+//! to the host: a jump, a call or return, or a system instruction, which may
+//! halt, fault, or wait on the host. The engine checks fuel and the deadline
+//! once when it enters a block rather than before every instruction.
+
+use crate::bytecode::groups;
+use crate::optimize::{instruction_len, split};
+
+/// The number of instructions left in the basic block at each offset of the
+/// bytecode of a module
+pub(crate) struct Blocks {
+    remaining: Vec<u16>,
+}
+
+impl Blocks {
+    /// Find the basic blocks of the given bytecode
+    pub(crate) fn new(code: &[u16]) -> Blocks {
+        let mut ends = vec![None; code.len()];
+        let mut offset = 0;
+        while offset < code.len() {
+            let len = match instruction_len(code, offset) {
+                Some(len) => len,
+                None => break,
+            };
+            let (group, _) = split(code[offset]);
+            let ends_block = matches!(group, groups::JUMP | groups::FUNCTION | groups::SYSTEM);
+            ends[offset] = Some((len, ends_block));
+            offset += len;
+        }
+
+        // Each instruction continues the block of the instruction after it,
+        // so count back from the end of the bytecode
+        let mut remaining = vec![0u16; code.len()];
+        for offset in (0..code.len()).rev() {
+            remaining[offset] = match ends[offset] {
+                Some((_, true)) => 1,
+                Some((len, false)) => {
+                    let next = remaining.get(offset + len).copied().unwrap_or(0);
+                    next.saturating_add(1)
+                }
+                None => 0,
+            };
+        }
+        Blocks { remaining }
+    }
+
+    /// Get the number of instructions left in the block at an offset,
+    /// including the instruction at the offset
+    ///
+    /// Returns zero if the offset is not the start of an instruction.
+    pub(crate) fn remaining(&self, offset: usize) -> usize {
+        self.remaining.get(offset).copied().unwrap_or(0) as usize
+    }
+
+    /// Get the number of words of bytecode the blocks were found in
+    pub(crate) fn len(&self) -> usize {
+        self.remaining.len()
+    }
+}
@@ -0,0 +1,266 @@
+//! Emitting bytecode with symbolic jump targets
+//!
+//! Computing jump offsets by hand is error prone, as every instruction added
+//! or removed between a jump and its target changes the offset. The
+//! `BytecodeBuilder` lets jumps refer to labels instead; labels may be bound
+//! before or after the jumps which use them, and are resolved when the
+//! bytecode is built.
+//!
+//! ```
+//! use tstack::builder::BytecodeBuilder;
+//! use tstack::bytecode::jump;
+//!
+//! let mut builder = BytecodeBuilder::new();
+//! let done = builder.label();
+//! builder.emit(tstack::inst_stack!(CONST_0));
+//! builder.jump(jump::MODE_RELATIVE, Some(jump::TYPE_Z), done);
+//! builder.emit(tstack::inst_sys!(PRINT_STACK));
+//! builder.bind(done).unwrap();
+//! builder.emit(tstack::inst_sys!(HALT));
+//! let code = builder.build().unwrap();
+//! assert_eq!(code[2], 1);
+//! ```
+
+use crate::bytecode::{groups, jump, write_words, Instruction};
+use crate::errors::BuildError;
+use crate::module::{Relocation, RelocationKind};
+
+/// A position in the bytecode being built
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Label(u32);
+
+impl Label {
+    /// Get the ID of the label within its builder
+    pub fn id(&self) -> u32 {
+        self.0
+    }
+}
+
+/// A jump to a label, whose encoding is chosen when the bytecode is built
+struct Jump {
+    /// The index of the item the jump follows
+    item: usize,
+    /// The mode and condition bits of the jump opcode
+    data: u8,
+    label: Label,
+    /// The number of operand words used to encode the target
+    words: usize,
+}
+
+/// An emitter for bytecode whose jumps target labels
+///
+/// Each jump is encoded with the smallest constant source (`SRC_C16`,
+/// `SRC_C32`, or `SRC_C64`) which can hold its target. As widening one jump
+/// may push another jump's target out of range, encodings are chosen by
+/// starting every jump at `SRC_C16` and widening jumps until all of them fit.
+pub struct BytecodeBuilder {
+    /// The code emitted between jumps
+    items: Vec<Vec<u16>>,
+    jumps: Vec<Jump>,
+    /// The item index and offset within the item each label is bound to
+    labels: Vec<Option<(usize, usize)>>,
+}
+
+impl BytecodeBuilder {
+    /// Create an empty builder
+    pub fn new() -> BytecodeBuilder {
+        BytecodeBuilder { items: vec![vec![]], jumps: vec![], labels: vec![] }
+    }
+
+    /// Append a single word to the bytecode
+    pub fn emit(&mut self, word: u16) {
+        self.current().push(word);
+    }
+
+    /// Append a sequence of words to the bytecode
+    pub fn emit_words(&mut self, words: &[u16]) {
+        self.current().extend_from_slice(words);
+    }
+
+    /// Append an encoded instruction to the bytecode
+    pub fn emit_instruction(&mut self, instruction: Instruction) {
+        instruction.encode(self.current());
+    }
+
+    /// Create a new label which is not yet bound to a position
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label((self.labels.len() - 1) as u32)
+    }
+
+    /// Bind a label to the current end of the bytecode
+    pub fn bind(&mut self, label: Label) -> Result<(), BuildError> {
+        let position = (self.items.len() - 1, self.current().len());
+        match self.labels.get_mut(label.0 as usize) {
+            Some(Some(_)) => Err(BuildError::DuplicateLabel(label.0)),
+            Some(slot) => {
+                *slot = Some(position);
+                Ok(())
+            }
+            None => Err(BuildError::InvalidLabel(label.0)),
+        }
+    }
+
+    /// Append a jump to a label
+    ///
+    /// `mode` is one of `jump::MODE_ABSOLUTE` or `jump::MODE_RELATIVE`, and
+    /// `condition` is the jump type (such as `jump::TYPE_Z`) of a conditional
+    /// jump, or `None` for an unconditional jump.
+    pub fn jump(&mut self, mode: u8, condition: Option<u8>, label: Label) {
+        let data = match condition {
+            Some(condition) => {
+                (mode & jump::MODE_MASK) | jump::CONDITIONAL_TRUE | (condition & jump::TYPE_MASK)
+            }
+            None => (mode & jump::MODE_MASK) | jump::CONDITIONAL_FALSE,
+        };
+        self.jumps.push(Jump { item: self.items.len() - 1, data, label, words: 1 });
+        self.items.push(vec![]);
+    }
+
+    /// Append code pushing `1` if a jump condition holds, and `0` otherwise
+    ///
+    /// `condition` is a jump type such as `jump::TYPE_LT`; the values it
+    /// compares are consumed, as they are by a conditional jump.
+    pub fn condition_value(&mut self, condition: u8) {
+        let (yes, done) = (self.label(), self.label());
+        self.jump(jump::MODE_RELATIVE, Some(condition), yes);
+        self.emit_instruction(Instruction::Const0);
+        self.jump(jump::MODE_RELATIVE, None, done);
+        // Both labels are new, so binding them can not fail
+        self.bind(yes).unwrap();
+        self.emit_instruction(Instruction::Const1);
+        self.bind(done).unwrap();
+    }
+
+    /// Resolve all labels and produce the bytecode
+    pub fn build(mut self) -> Result<Vec<u16>, BuildError> {
+        for jump in self.jumps.iter() {
+            match self.labels.get(jump.label.0 as usize) {
+                Some(Some(_)) => (),
+                Some(None) => return Err(BuildError::UnboundLabel(jump.label.0)),
+                None => return Err(BuildError::InvalidLabel(jump.label.0)),
+            }
+        }
+
+        // Widening a jump only ever moves other targets further away, so this
+        // terminates once every jump has settled on an encoding.
+        let starts = loop {
+            let starts = self.layout();
+            let mut changed = false;
+            for jump in self.jumps.iter_mut() {
+                let (item, offset) = self.labels[jump.label.0 as usize].unwrap();
+                let target = starts[item] + offset;
+                let end = starts[jump.item + 1];
+                while !fits(jump.data, jump.words, target, end) {
+                    jump.words *= 2;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break starts;
+            }
+        };
+
+        let mut code = Vec::with_capacity(starts[self.items.len()]);
+        let mut jumps = self.jumps.iter().peekable();
+        for (index, item) in self.items.iter().enumerate() {
+            code.extend_from_slice(item);
+            if let Some(jump) = jumps.next_if(|j| j.item == index) {
+                let (item, offset) = self.labels[jump.label.0 as usize].unwrap();
+                let target = starts[item] + offset;
+                let src = match jump.words {
+                    1 => jump::SRC_C16,
+                    2 => jump::SRC_C32,
+                    _ => jump::SRC_C64,
+                };
+                let opcode = ((groups::JUMP as u16) << 8) | (jump.data | src) as u16;
+                let value = encode_target(jump.data, target, code.len() + 1 + jump.words);
+                let start = code.len();
+                code.push(opcode);
+                code.resize(start + 1 + jump.words, 0);
+                write_words(&mut code, start + 1, jump.words, value);
+            }
+        }
+        Ok(code)
+    }
+
+    fn current(&mut self) -> &mut Vec<u16> {
+        self.items.last_mut().unwrap()
+    }
+
+    /// Compute the start offset of each item with the current jump encodings
+    ///
+    /// The returned vector has an extra entry holding the total length.
+    fn layout(&self) -> Vec<usize> {
+        let mut starts = Vec::with_capacity(self.items.len() + 1);
+        let mut offset = 0;
+        let mut jumps = self.jumps.iter().peekable();
+        for (index, item) in self.items.iter().enumerate() {
+            starts.push(offset);
+            offset += item.len();
+            if let Some(jump) = jumps.next_if(|j| j.item == index) {
+                offset += 1 + jump.words;
+            }
+        }
+        starts.push(offset);
+        starts
+    }
+}
+
+impl Default for BytecodeBuilder {
+    fn default() -> Self {
+        BytecodeBuilder::new()
+    }
+}
+
+/// Get the operand value encoding a jump target
+///
+/// `end` is the offset following the jump instruction.
+fn encode_target(data: u8, target: usize, end: usize) -> u64 {
+    if data & jump::MODE_MASK == jump::MODE_RELATIVE {
+        (target as i64 - end as i64) as u64
+    } else {
+        target as u64
+    }
+}
+
+/// Check if a jump target can be encoded in the given number of words
+fn fits(data: u8, words: usize, target: usize, end: usize) -> bool {
+    let value = encode_target(data, target, end);
+    if data & jump::MODE_MASK == jump::MODE_RELATIVE {
+        match words {
+            1 => i16::try_from(value as i64).is_ok(),
+            2 => i32::try_from(value as i64).is_ok(),
+            _ => true,
+        }
+    } else {
+        match words {
+            1 => u16::try_from(value).is_ok(),
+            2 => u32::try_from(value).is_ok(),
+            _ => true,
+        }
+    }
+}
+
+/// Find the relocations of the symbol operands of calls and references in
+/// built bytecode
+///
+/// The bytecode must consist of instructions only, as produced by a builder.
+pub(crate) fn symbol_relocations(code: &[u16]) -> Vec<Relocation> {
+    let mut relocations = vec![];
+    let mut offset = 0;
+    while let Ok((instruction, len)) = Instruction::decode(&code[offset..]) {
+        let kind = match instruction {
+            Instruction::CallC(_) | Instruction::FuncRefC(_) => Some(RelocationKind::LocalSymbol),
+            Instruction::CallExtC(_) | Instruction::FuncRefExtC(_) => {
+                Some(RelocationKind::ExternalSymbol)
+            }
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            relocations.push(Relocation { offset: offset as u32 + 1, words: 1, kind });
+        }
+        offset += len;
+    }
+    relocations
+}
@@ -5,6 +5,17 @@
 //!
 //! Each bytecode value is a 16-bit value where the first 8 bits are the group
 //! code, and the final 8 bits are the 'data' for that group.
+//!
+//! Inline operands wider than 16 bits are split across consecutive words, most
+//! significant word first, so `0x0001_0002` as a `u32` is stored as the words
+//! `[0x0001, 0x0002]`. This order is part of the bytecode definition, and does
+//! not depend on the host the bytecode is built or run on; use `encode_u32`,
+//! `encode_u64`, `decode_u32`, and `decode_u64` rather than splitting values
+//! by hand.
+
+mod instruction;
+
+pub use self::instruction::{op, Instruction, Jump, JumpSource};
 
 /// The mask for the group byte
 pub const GROUP_MASK: u16 = 0xFF00;
@@ -21,6 +32,153 @@ pub const DATA_MASK: u16 = 0x00FF;
 /// by zero doesn't actually do anything.
 pub const DATA_SHIFT: u16 = 0;
 
+/// Read a value stored across `words` consecutive bytecode values
+///
+/// Values are stored most significant word first, matching the layout of the
+/// inline constants read by the engine. The caller must ensure the words are
+/// in bounds.
+pub(crate) fn read_words(bytecode: &[u16], offset: usize, words: usize) -> u64 {
+    bytecode[offset..offset + words].iter().fold(0, |acc, w| (acc << 16) | (*w as u64))
+}
+
+/// Write a value across `words` consecutive bytecode values
+///
+/// This is the inverse of `read_words`; any bits of `value` which do not fit
+/// in the given number of words are discarded.
+pub(crate) fn write_words(bytecode: &mut [u16], offset: usize, words: usize, value: u64) {
+    for i in 0..words {
+        bytecode[offset + i] = (value >> (16 * (words - i - 1))) as u16;
+    }
+}
+
+/// Append a `u32` to bytecode as two words, most significant word first
+pub fn encode_u32(code: &mut Vec<u16>, value: u32) {
+    code.extend_from_slice(&[(value >> 16) as u16, value as u16]);
+}
+
+/// Append a `u64` to bytecode as four words, most significant word first
+pub fn encode_u64(code: &mut Vec<u16>, value: u64) {
+    code.extend_from_slice(&[
+        (value >> 48) as u16,
+        (value >> 32) as u16,
+        (value >> 16) as u16,
+        value as u16,
+    ]);
+}
+
+/// Read a `u32` from the first two words of `code`
+///
+/// This is the inverse of `encode_u32`. Returns `None` if `code` holds fewer
+/// than two words.
+pub fn decode_u32(code: &[u16]) -> Option<u32> {
+    code.get(..2).map(|words| read_words(words, 0, 2) as u32)
+}
+
+/// Read a `u64` from the first four words of `code`
+///
+/// This is the inverse of `encode_u64`. Returns `None` if `code` holds fewer
+/// than four words.
+pub fn decode_u64(code: &[u16]) -> Option<u64> {
+    code.get(..4).map(|words| read_words(words, 0, 4))
+}
+
+/// Get the number of inline operand words which follow an instruction
+///
+/// Returns `None` if the opcode is not a defined instruction.
+pub fn operand_count(opcode: u16) -> Option<usize> {
+    let group = ((opcode & GROUP_MASK) >> GROUP_SHIFT) as u8;
+    let value = ((opcode & DATA_MASK) >> DATA_SHIFT) as u8;
+    match group {
+        groups::SYSTEM => match value {
+            sys::NOP..=sys::TIME => Some(0),
+            sys::FAULT => Some(1),
+            _ => None,
+        },
+        groups::STACK => match value {
+            stack::CONST_U32 | stack::CONST_I32 => Some(2),
+            stack::CONST_U64 => Some(4),
+            stack::CONST_U16
+            | stack::CONST_I16
+            | stack::DUPE_C
+            | stack::SWAP_C
+            | stack::ROTATE_C
+            | stack::ROTATE_1_C
+            | stack::POP_C
+            | stack::GET_U8_C
+            | stack::GET_U16_C
+            | stack::GET_U32_C
+            | stack::GET_U64_C
+            | stack::GET_I8_C
+            | stack::GET_I16_C
+            | stack::GET_I32_C
+            | stack::GET_F32_C
+            | stack::SET_8_C
+            | stack::SET_16_C
+            | stack::SET_32_C
+            | stack::SET_64_C
+            | stack::SET_F32_C
+            | stack::RESERVE_C
+            | stack::DATA_GET_C
+            | stack::DATA_RW_GET_C
+            | stack::DATA_RW_SET_C
+            | stack::GLOBAL_GET_C
+            | stack::GLOBAL_SET_C
+            | stack::GLOBAL_EXT_GET_C
+            | stack::GLOBAL_EXT_SET_C => Some(1),
+            stack::CONST_0..=stack::GLOBAL_EXT_SET_C => Some(0),
+            _ => None,
+        },
+        groups::JUMP => match value & jump::SRC_MASK {
+            jump::SRC_C16 => Some(1),
+            jump::SRC_C32 => Some(2),
+            jump::SRC_C64 => Some(4),
+            _ => Some(0),
+        },
+        groups::MATH => match value {
+            math::CLAMP_C | math::ICLAMP_C => Some(2),
+            math::ADD_C
+            | math::SUB_C
+            | math::MUL_C
+            | math::DIV_C
+            | math::IDIV_C
+            | math::MOD_C
+            | math::IMOD_C
+            | math::DIVMOD_C
+            | math::IDIVMOD_C
+            | math::FMA_C
+            | math::POW_C
+            | math::POW_C_R
+            | math::IPOW_C
+            | math::IPOW_C_R
+            | math::MAX_C
+            | math::IMAX_C
+            | math::MIN_C
+            | math::IMIN_C
+            | math::NMIN_C
+            | math::NIMIN_C
+            | math::NMAX_C
+            | math::NIMAX_C
+            | math::DIFF_C
+            | math::SUM_C => Some(1),
+            math::ADD..=math::ICLAMP_C | math::NMIN_C..=math::SUM => Some(0),
+            _ => None,
+        },
+        groups::FPMATH => match value {
+            fpmath::FADD..=fpmath::FTOI => Some(0),
+            _ => None,
+        },
+        groups::FUNCTION => match value {
+            function::CALL_C
+            | function::CALL_EXT_C
+            | function::FUNC_REF_C
+            | function::FUNC_REF_EXT_C => Some(1),
+            function::RETURN | function::CALL_REF => Some(0),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 /// Generate a stack instruction
 ///
 /// # Examples
@@ -73,6 +231,41 @@ macro_rules! inst_math {
     };
 }
 
+/// Generate a floating point math instruction
+///
+/// # Examples
+/// ```
+/// use tstack;
+/// let bytes = &[
+///     tstack::inst_fpmath!(FADD),
+/// ];
+/// ```
+#[macro_export]
+macro_rules! inst_fpmath {
+    ($instr:ident) => {
+        ((($crate::bytecode::groups::FPMATH as u16) << 8)
+            | ($crate::bytecode::fpmath::$instr as u16))
+    };
+}
+
+/// Generate a function instruction
+///
+/// # Examples
+/// ```
+/// use tstack;
+/// let bytes = &[
+///     tstack::inst_function!(CALL_C), 0x0001,
+///     tstack::inst_function!(RETURN),
+/// ];
+/// ```
+#[macro_export]
+macro_rules! inst_function {
+    ($instr:ident) => {
+        ((($crate::bytecode::groups::FUNCTION as u16) << 8)
+            | ($crate::bytecode::function::$instr as u16))
+    };
+}
+
 /// Generate a jump instruction
 ///
 /// # Examples
@@ -125,23 +318,106 @@ pub mod groups {
 ///| Constant    | ID   | Args | Stack     | Description
 ///|-------------|------|------|-----------|------------
 ///| NOP         |`0x00`|      |           | Do nothing
-///| HALT        |`0x01`|      |           | Stop execution normally
+///| HALT        |`0x01`|      |`[v] -> []`| Stop execution normally with the exit value `$v`[^s0]
 ///| PRINT_STACK |`0x01`|      |           | Debug print the contents of the stack as u64 values
 ///| PRINT_U64   |`0x02`|      |`[a] -> []`| Debug print the topmost stack value as u64
 ///| PRINT_I64   |`0x03`|      |`[a] -> []`| Debug print the topmost stack value as i64
 ///| PRINT_F32   |`0x04`|      |`[a] -> []`| Debug print the topmost stack value as f32 (truncating)
 ///| PRINT_F64   |`0x05`|      |`[a] -> []`| Debug print the topmost stack value as f64
-///| FAULT       |`0xFF`|      |           | Force a fault
+///| BREAKPOINT  |`0x07`|      |           | Stop a debug session before running; does nothing otherwise
+///| ARG_COUNT   |`0x08`|      |`[] -> [n]`| Push the number of arguments given to the program[^s1]
+///| ARG_GET     |`0x09`|      |`[i,d,c] -> [n]`| Copy argument `$i` to the writable data segment[^s1][^s2]
+///| ENV_GET     |`0x0A`|      |`[k,l,d,c] -> [n]`| Copy the environment variable named by the `$l` bytes at `rw[$k]` to the writable data segment[^s1][^s2]
+///| OPEN        |`0x0B`|      |`[p,l,m] -> [h]`| Open the file named by the `$l` bytes at `rw[$p]` in mode `$m`, pushing its handle[^s3]
+///| READ        |`0x0C`|      |`[h,d,c] -> [n]`| Read at most `$c * 8` bytes from stream `$h` to the writable data segment[^s2][^s4]
+///| WRITE       |`0x0D`|      |`[h,s,l] -> [n]`| Write the `$l` bytes at `rw[$s]` to stream `$h`[^s4]
+///| CLOSE       |`0x0E`|      |`[h] -> []`| Close stream `$h`[^s4]
+///| CONNECT     |`0x0F`|      |`[a,l] -> [h]`| Open a TCP connection to the `host:port` address named by the `$l` bytes at `rw[$a]`, pushing its handle[^s5]
+///| LOG         |`0x10`|      |`[s,l] -> []`| Write the `$l` bytes at `rw[$s]` as a line to the standard error stream[^s6]
+///| RANDOM      |`0x11`|      |`[] -> [r]`| Push a random value drawn from the random source of the engine[^s7]
+///| RANDOM_BELOW|`0x12`|      |`[n] -> [r]`| Push a random value less than `$n`, drawn uniformly; `0` behaves like `RANDOM`[^s7]
+///| TIME        |`0x13`|      |`[] -> [t]`| Push the time read from the clock of the engine, in nanoseconds[^s8]
+///| FAULT       |`0xFF`| `m`  |`[c,v..,n] -> []`| Fault with the code `$c`, the message in string `m` of the module, and the `$n` values `$v`[^s9]
+///
+/// [^s0]: Every call in progress is abandoned, including those of native
+///     functions which called back into bytecode, and the run ends without
+///     checking the signature of its entry point; the exit value is read
+///     afterwards with `Engine::halt_value`.
+///
+/// The `PRINT_*` instructions write lines to the standard output stream of
+/// the engine, and fault with `BytecodeError::PermissionDenied` unless the
+/// policy of the engine grants `Capability::Io`.
+///
+/// [^s1]: Faults with `BytecodeError::PermissionDenied` unless the policy of
+///     the engine grants `Capability::Syscall`. The arguments and environment
+///     are those the host gave the engine, not those of the process.
+///
+/// [^s2]: The UTF-8 bytes of the value are written to at most `$c` values of
+///     the writable data segment from index `$d`, packed eight to a value in
+///     little endian order with the last value padded with zeroes. The length
+///     of the value in bytes is pushed, which is larger than `$c * 8` if the
+///     value was truncated, or `-1` if there is no such value. `READ` pushes
+///     the number of bytes read instead, which is `0` at the end of the
+///     stream.
+///
+/// [^s3]: The path must lie beneath a directory preopened by the host; see
+///     the `stream` module. The mode is `0` to read, `1` to create or
+///     truncate and write, or `2` to create or extend and write. `0` is
+///     pushed if the file can not be opened.
+///
+/// [^s4]: Faults with `BytecodeError::PermissionDenied` unless the policy of
+///     the engine grants `Capability::Io`. `-1` is pushed if reading or
+///     writing fails; faults with `BytecodeError::InvalidHandle` if `$h` is
+///     not a stream. The standard streams of the engine are always open as
+///     `1` for input, `2` for output and `3` for errors, and can not be
+///     closed.
+///
+/// [^s5]: Faults with `BytecodeError::PermissionDenied` unless the policy of
+///     the engine grants `Capability::Network`, which is denied by default.
+///     `0` is pushed if the connection can not be opened. The connection is a
+///     stream read and written like a file.
+///
+/// [^s6]: Faults with `BytecodeError::PermissionDenied` unless the policy of
+///     the engine grants `Capability::Io`. Failing to write is not a fault.
+///
+/// [^s7]: Faults with `BytecodeError::PermissionDenied` unless the policy of
+///     the engine grants `Capability::Random`. Every random value bytecode
+///     sees comes from the `RandomSource` of the engine, so hosts may replace
+///     it to make runs repeatable; see the `host` module.
+///
+/// [^s8]: Faults with `BytecodeError::PermissionDenied` unless the policy of
+///     the engine grants `Capability::Clock`. The time is measured from a
+///     fixed point chosen by the clock, so only the difference between two
+///     times is meaningful.
+///
+/// [^s9]: Ends the run with `BytecodeError::UserFault`, for assertions and
+///     other errors found by bytecode itself. The values are given in the
+///     order they were pushed, and faults with `BytecodeError::StackUnderflow`
+///     if fewer than `$n` are on the stack. Verifying a module checks `m`
+///     refers to one of its strings.
 #[rustfmt::skip]
 pub mod sys {
-    pub const NOP:         u8 = 0x00;
-    pub const HALT:        u8 = 0x01;
-    pub const PRINT_STACK: u8 = 0x02;
-    pub const PRINT_U64:   u8 = 0x03;
-    pub const PRINT_I64:   u8 = 0x04;
-    pub const PRINT_F32:   u8 = 0x05;
-    pub const PRINT_F64:   u8 = 0x06;
-    pub const BREAKPOINT:  u8 = 0x07;
+    pub const NOP:          u8 = 0x00;
+    pub const HALT:         u8 = 0x01;
+    pub const PRINT_STACK:  u8 = 0x02;
+    pub const PRINT_U64:    u8 = 0x03;
+    pub const PRINT_I64:    u8 = 0x04;
+    pub const PRINT_F32:    u8 = 0x05;
+    pub const PRINT_F64:    u8 = 0x06;
+    pub const BREAKPOINT:   u8 = 0x07;
+    pub const ARG_COUNT:    u8 = 0x08;
+    pub const ARG_GET:      u8 = 0x09;
+    pub const ENV_GET:      u8 = 0x0A;
+    pub const OPEN:         u8 = 0x0B;
+    pub const READ:         u8 = 0x0C;
+    pub const WRITE:        u8 = 0x0D;
+    pub const CLOSE:        u8 = 0x0E;
+    pub const CONNECT:      u8 = 0x0F;
+    pub const LOG:          u8 = 0x10;
+    pub const RANDOM:       u8 = 0x11;
+    pub const RANDOM_BELOW: u8 = 0x12;
+    pub const TIME:         u8 = 0x13;
+    pub const FAULT:        u8 = 0xFF;
 }
 
 /// Stack and frame manipulation instruction data byte values.
@@ -170,10 +446,10 @@ pub mod sys {
 ///| SWAP       |`0x13`|       |`[a1...a$n,n] -> [a$n...a$1]`        | Reverse the topmost `$n` stack elements
 ///| SWAP_1     |`0x14`|       |`[a,b]        -> [b,a]`              | Swap the two topmost stack elements
 ///| SWAP_C     |`0x15`|`c:u16`|`[a1...a$c]   -> [a$c...a1]`         | Reverse the topmost `$c` stack elements
-///| ROTATE     |`0x16`|       |`[a1...a$n,n,p] -> [a$p...a$n,a1...a$p-1]`| Rotate the topmost `$p` stack elements back `$n` places
-///| ROTATE_1   |`0x17`|       |`[a1...a$n,n] -> [a$n,a1...a$n-1]`   | Rotate the topmost element back `$n` places
-///| ROTATE_C   |`0x18`|`c:u16`|`[a1...a$c,p] -> [a$p...a$c,a1...a$p-1]`| Rotate the topmost `$p` stack elements back `$c` places
-///| ROTATE_1_C |`0x19`|`c:u16`|`[a1...a$c]   -> [a$c,a1...a$c-1]`   | Rotate the topmost element back `$c` places
+///| ROTATE     |`0x16`|       |`[a1...a$n,n,p] -> [a$p...a$n,a1...a$p-1]`| Rotate the topmost `$n` stack elements to start from the `$p`th[^n7]
+///| ROTATE_1   |`0x17`|       |`[a1...a$n,n] -> [a$n,a1...a$n-1]`   | Move the topmost element below the `$n-1` elements under it[^n7]
+///| ROTATE_C   |`0x18`|`c:u16`|`[a1...a$c,p] -> [a$p...a$c,a1...a$p-1]`| Rotate the topmost `$c` stack elements to start from the `$p`th[^n7]
+///| ROTATE_1_C |`0x19`|`c:u16`|`[a1...a$c]   -> [a$c,a1...a$c-1]`   | Move the topmost element below the `$c-1` elements under it[^n7]
 ///| POP        |`0x1A`|       |`[a1...a$n,n] -> []`                 | Remove the topmost `$n` elements from the stack
 ///| POP_1      |`0x1B`|       |`[a]          -> []`                 | Remove the topmost element from the stack
 ///| POP_C      |`0x1C`|`c:u16`|`[a1...a$c]   -> []`                 | Remove the topmost `$c` elements from the stack
@@ -191,6 +467,8 @@ pub mod sys {
 ///| GET_I16_C  |`0x28`|`c:u16`|`[]  -> [local[$c/4]]`               | Sign extend and push 16-bit local at index `$c/4`[^n1]
 ///| GET_I32    |`0x29`|       |`[n] -> [local[$n/2]]`               | Sign extend and push 32-bit local at index `$n/2`[^n1]
 ///| GET_I32_C  |`0x2A`|`c:u16`|`[]  -> [local[$c/2]]`               | Sign extend and push 32-bit local at index `$c/2`[^n1]
+///| GET_F32    |`0x2B`|       |`[n] -> [local[$n/2]]`               | Widen the 32-bit float local at index `$n/2` and push it[^n1]
+///| GET_F32_C  |`0x2C`|`c:u16`|`[]  -> [local[$c/2]]`               | Widen the 32-bit float local at index `$c/2` and push it[^n1]
 ///| SET_U8     |`0x2D`|       |`[v,n] -> []; local[$n/8]=$v`[^n2]   | Truncate `$v` to 8-bits and save to local at index `$n/8`[^n3]
 ///| SET_U8_C   |`0x2E`|`c:u16`|`[v]   -> []; local[$c/8]=$v`[^n2]   | Truncate `$v` to 8-bits and save to local at index `$c/8`[^n3]
 ///| SET_U16    |`0x2F`|       |`[v,n] -> []; local[$n/4]=$v`[^n2]   | Truncate `$v` to 16-bits and save to local at index `$n/4`[^n3]
@@ -199,11 +477,23 @@ pub mod sys {
 ///| SET_U32_C  |`0x32`|`c:u16`|`[v]   -> []; local[$c/2]=$v`[^n2]   | Truncate `$v` to 32-bits and save to local at index `$c/2`[^n3]
 ///| SET_U64    |`0x33`|       |`[v,n] -> []; local[$n]=$v`[^n2]     | Save `$v` to local at index `$n`[^n3]
 ///| SET_U64_C  |`0x34`|`c:u16`|`[v]   -> []; local[$c]=$v`[^n2]     | Save `$v` to local at index `$c`[^n3]
+///| SET_F32    |`0x35`|       |`[v,n] -> []; local[$n/2]=$v`[^n2]   | Narrow `$v` to a 32-bit float and save to local at index `$n/2`[^n3]
+///| SET_F32_C  |`0x36`|`c:u16`|`[v]   -> []; local[$c/2]=$v`[^n2]   | Narrow `$v` to a 32-bit float and save to local at index `$c/2`[^n3]
 ///| STACK_SIZE |`0x37`|       |`[a1...a$n] -> [a1...a$n,n]`         | Push the size of the stack to the stack
 ///| PUSH_STACK |`0x38`|       |`[a1...a$n] -> [a1...a$n,n\|]`       | Push the size of the stack and set the new stack base 1 past it
 ///| POP_STACK  |`0x39`|       |`[a1...a$n,n\|b1...] -> [a1...a$n,b1...]`| Fetch previous stack size, subtract from current stack base, and shift other elements
-///| RESERVE    |`0x3A`|       |`[n] -> []`                          | Extend or reduce the number of locals reserved by `$n`[^n4]
-///| RESERVE_C  |`0x3B`|`c:i16`|                                     | Extend or reduce the number of locals reserved by `$c`[^n4]
+///| RESERVE_C  |`0x3A`|`c:i16`|`[]  -> []`                          | Extend or reduce the number of locals reserved by `$c`[^n4]
+///| RESERVE_N  |`0x3B`|       |`[n] -> []`                          | Extend or reduce the number of locals reserved by `$n`[^n4]
+///| DATA_GET   |`0x3C`|       |`[n] -> [data[$n]]`                  | Push the value at index `$n` of the read-only data segment[^n5]
+///| DATA_GET_C |`0x3D`|`c:u16`|`[]  -> [data[$c]]`                  | Push the value at index `$c` of the read-only data segment[^n5]
+///| DATA_RW_GET|`0x3E`|       |`[n] -> [rw[$n]]`                    | Push the value at index `$n` of the writable data segment[^n5]
+///| DATA_RW_GET_C|`0x3F`|`c:u16`|`[] -> [rw[$c]]`                   | Push the value at index `$c` of the writable data segment[^n5]
+///| DATA_RW_SET|`0x40`|       |`[v,n] -> []; rw[$n]=$v`             | Save `$v` to index `$n` of the writable data segment[^n5]
+///| DATA_RW_SET_C|`0x41`|`c:u16`|`[v] -> []; rw[$c]=$v`             | Save `$v` to index `$c` of the writable data segment[^n5]
+///| GLOBAL_GET_C|`0x42`|`c:u16`|`[] -> [global[$c]]`               | Push the value of global `$c`[^n6]
+///| GLOBAL_SET_C|`0x43`|`c:u16`|`[v] -> []; global[$c]=$v`         | Save `$v` to global `$c`[^n6]
+///| GLOBAL_EXT_GET_C|`0x44`|`c:u16`|`[] -> [extglobal[$c]]`        | Push the value of external global `$c`[^n6]
+///| GLOBAL_EXT_SET_C|`0x45`|`c:u16`|`[v] -> []; extglobal[$c]=$v`  | Save `$v` to external global `$c`[^n6]
 ///
 ///
 /// [^n1]: The index for types smaller than 64-bits are for values packed
@@ -211,9 +501,9 @@ pub mod sys {
 ///     the actual index will be calculated as `$index / 8`, with the value
 ///     shifted by `8 * ($index % 8)` and then masked by `0xFF`.
 ///
-/// [^n2]: Set instructions operate on the local storage within the current
-///     frame. The adjusted index being written to must have already been
-///     reserved by an instance of `RESERVE` or `RESERVE_C`.
+/// [^n2]: Get and set instructions operate on the local storage within the
+///     current frame. The adjusted index being read or written must have
+///     already been reserved by an instance of `RESERVE_C` or `RESERVE_N`.
 ///
 /// [^n3]: Set instructions pack values into an adjusted index. E.g. for an
 ///     8-bit value, the actual index will be calculated as `$index / 8`, with
@@ -225,7 +515,24 @@ pub mod sys {
 ///     storage by that many values. If the result of the instruction would
 ///     result in a local storage size `n` where `$n > 255` or `$n < 0`, the
 ///     machine will fault. E.g. `reserve.c 0x7FFF` will always fault, as it
-///     attempts to reserve ~32,000 locals.
+///     attempts to reserve ~32,000 locals. Locals start zeroed, and are
+///     released when the symbol returns.
+///
+/// [^n5]: Data instructions operate on the data segments of the module the
+///     executing code belongs to. The read-only segment is shared between all
+///     engines the module is loaded into, and has no set instructions. The
+///     writable segment is copied from the module's initial values for each
+///     engine. Accessing an index outside of a segment faults.
+///
+/// [^n6]: Global instructions take an index into the global table of the
+///     executing module, or into its external global table, which the linker
+///     binds to a global exported by another module. Each engine holds its own
+///     copy of every global.
+///
+/// [^n7]: Rotation positions count from `1` for the deepest of the rotated
+///     elements, so rotating from position `1` leaves them unchanged. A
+///     position of `0` or past the topmost element faults, as does rotating
+///     no elements.
 #[rustfmt::skip]
 pub mod stack {
     pub const CONST_0:    u8 = 0x00;
@@ -288,6 +595,19 @@ pub mod stack {
     pub const POP_STACK:  u8 = 0x39;
     pub const RESERVE_C:  u8 = 0x3A;
     pub const RESERVE_N:  u8 = 0x3B;
+    pub const DATA_GET:      u8 = 0x3C;
+    pub const DATA_GET_C:    u8 = 0x3D;
+    pub const DATA_RW_GET:   u8 = 0x3E;
+    pub const DATA_RW_GET_C: u8 = 0x3F;
+    pub const DATA_RW_SET:   u8 = 0x40;
+    pub const DATA_RW_SET_C: u8 = 0x41;
+    pub const GLOBAL_GET_C:     u8 = 0x42;
+    pub const GLOBAL_SET_C:     u8 = 0x43;
+    pub const GLOBAL_EXT_GET_C: u8 = 0x44;
+    pub const GLOBAL_EXT_SET_C: u8 = 0x45;
+
+    /// The largest number of locals a symbol can reserve
+    pub const MAX_LOCALS: usize = 255;
 }
 
 /// Values for decoding the jump instructions
@@ -306,6 +626,10 @@ pub mod stack {
 /// * `C` -- the conditional flag
 /// * `T` -- the type of jump conditional
 ///
+/// Relative jumps add their source to the offset following the jump
+/// instruction. A jump taking its source from the stack pops it before the
+/// values its condition tests.
+///
 /// # Examples
 ///
 /// Absolute unconditional jump from 16-bit const source:
@@ -529,3 +853,61 @@ pub mod math {
     pub const SUM_C:     u8 = 0xFE;
     pub const SUM:       u8 = 0xFF;
 }
+
+/// Instructions which perform math on 64-bit floating point stack operands
+///
+/// Operands are the bits of `f64` values, as pushed by `CONST_U64`. As with
+/// the integer math group, binary operations take the topmost value as their
+/// left hand side.
+///
+///| Constant  | ID   | Args | Stack              | Description
+///|-----------|------|------|--------------------|-------------
+///| FADD      |`0x00`|      |`[a,b] -> [b+a]`    | Add two values on the stack
+///| FSUB      |`0x01`|      |`[a,b] -> [b-a]`    | Subtract values on the stack
+///| FMUL      |`0x02`|      |`[a,b] -> [b*a]`    | Multiply two values on the stack
+///| FDIV      |`0x03`|      |`[a,b] -> [b/a]`    | Divide two values on the stack
+///| FNEG      |`0x04`|      |`[a]   -> [-a]`     | Negate the value on the stack
+///| ITOF      |`0x05`|      |`[a]   -> [f64(a)]` | Convert a signed integer to the nearest float
+///| FTOI      |`0x06`|      |`[a]   -> [i64(a)]` | Convert a float to a signed integer, truncating and saturating
+#[rustfmt::skip]
+pub mod fpmath {
+    pub const FADD: u8 = 0x00;
+    pub const FSUB: u8 = 0x01;
+    pub const FMUL: u8 = 0x02;
+    pub const FDIV: u8 = 0x03;
+    pub const FNEG: u8 = 0x04;
+    pub const ITOF: u8 = 0x05;
+    pub const FTOI: u8 = 0x06;
+}
+
+/// Instructions which call and return from symbols
+///
+///| Constant       | ID   | Args  | Description
+///|----------------|------|-------|------------
+///| CALL_C         |`0x00`|`c:u16`| Call local symbol `$c` of the executing module[^f1]
+///| CALL_EXT_C     |`0x01`|`c:u16`| Call the symbol bound to external symbol `$c` of the executing module[^f1]
+///| RETURN         |`0x02`|       | Return to the caller, or stop execution if there is none[^f2]
+///| FUNC_REF_C     |`0x03`|`c:u16`| Push a reference to local symbol `$c` of the executing module[^f3]
+///| FUNC_REF_EXT_C |`0x04`|`c:u16`| Push a reference to the symbol bound to external symbol `$c` of the executing module[^f3]
+///| CALL_REF       |`0x05`|       | Pop a reference and call the symbol it refers to[^f1][^f4]
+///
+/// [^f1]: Arguments are passed, and results returned, on the stack. The
+///     called symbol starts with no locals reserved.
+///
+/// [^f2]: The locals reserved by the returning symbol are released.
+///
+/// [^f3]: A reference packs the ID of the module of the symbol into the high
+///     32 bits and the ID of the symbol into the low 32 bits, as read by
+///     `native::FuncRef`.
+///
+/// [^f4]: Only exported symbols may be called by reference, unless the
+///     symbol belongs to the executing module.
+#[rustfmt::skip]
+pub mod function {
+    pub const CALL_C:         u8 = 0x00;
+    pub const CALL_EXT_C:     u8 = 0x01;
+    pub const RETURN:         u8 = 0x02;
+    pub const FUNC_REF_C:     u8 = 0x03;
+    pub const FUNC_REF_EXT_C: u8 = 0x04;
+    pub const CALL_REF:       u8 = 0x05;
+}
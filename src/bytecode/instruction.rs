@@ -0,0 +1,645 @@
+//! A decoded representation of instructions
+//!
+//! The `Instruction` type pairs an opcode with its inline operands, and can be
+//! decoded from and encoded to bytecode. Tools which need to inspect or
+//! produce bytecode should use it instead of working with raw words.
+
+use super::{
+    encode_u32, encode_u64, fpmath, function, groups, jump, math, operand_count, read_words, stack,
+    sys,
+};
+use crate::errors::BytecodeError;
+
+/// An inline operand of an instruction
+trait Operand: Copy {
+    /// The number of bytecode words the operand takes
+    const WORDS: usize;
+
+    fn read(code: &[u16], offset: usize) -> Self;
+
+    fn write(self, code: &mut Vec<u16>);
+
+    fn to_i128(self) -> i128;
+
+    fn from_i128(value: i128) -> Option<Self>;
+}
+
+impl Operand for u16 {
+    const WORDS: usize = 1;
+
+    fn read(code: &[u16], offset: usize) -> u16 {
+        code[offset]
+    }
+
+    fn write(self, code: &mut Vec<u16>) {
+        code.push(self);
+    }
+
+    fn to_i128(self) -> i128 {
+        self as i128
+    }
+
+    fn from_i128(value: i128) -> Option<u16> {
+        u16::try_from(value).ok()
+    }
+}
+
+impl Operand for i16 {
+    const WORDS: usize = 1;
+
+    fn read(code: &[u16], offset: usize) -> i16 {
+        code[offset] as i16
+    }
+
+    fn write(self, code: &mut Vec<u16>) {
+        code.push(self as u16);
+    }
+
+    fn to_i128(self) -> i128 {
+        self as i128
+    }
+
+    fn from_i128(value: i128) -> Option<i16> {
+        i16::try_from(value).ok()
+    }
+}
+
+impl Operand for u32 {
+    const WORDS: usize = 2;
+
+    fn read(code: &[u16], offset: usize) -> u32 {
+        read_words(code, offset, 2) as u32
+    }
+
+    fn write(self, code: &mut Vec<u16>) {
+        encode_u32(code, self);
+    }
+
+    fn to_i128(self) -> i128 {
+        self as i128
+    }
+
+    fn from_i128(value: i128) -> Option<u32> {
+        u32::try_from(value).ok()
+    }
+}
+
+impl Operand for i32 {
+    const WORDS: usize = 2;
+
+    fn read(code: &[u16], offset: usize) -> i32 {
+        read_words(code, offset, 2) as u32 as i32
+    }
+
+    fn write(self, code: &mut Vec<u16>) {
+        (self as u32).write(code);
+    }
+
+    fn to_i128(self) -> i128 {
+        self as i128
+    }
+
+    fn from_i128(value: i128) -> Option<i32> {
+        i32::try_from(value).ok()
+    }
+}
+
+impl Operand for u64 {
+    const WORDS: usize = 4;
+
+    fn read(code: &[u16], offset: usize) -> u64 {
+        read_words(code, offset, 4)
+    }
+
+    fn write(self, code: &mut Vec<u16>) {
+        encode_u64(code, self);
+    }
+
+    fn to_i128(self) -> i128 {
+        self as i128
+    }
+
+    fn from_i128(value: i128) -> Option<u64> {
+        u64::try_from(value).ok()
+    }
+}
+
+/// The source of a jump target
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JumpSource {
+    /// A 16-bit inline constant
+    C16(u16),
+    /// A 32-bit inline constant
+    C32(u32),
+    /// A 64-bit inline constant
+    C64(u64),
+    /// A value taken from the stack
+    Dynamic,
+}
+
+/// A decoded jump instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Jump {
+    /// Where the target comes from
+    pub source: JumpSource,
+    /// If the target is an offset from the end of the jump instruction
+    /// rather than an absolute offset
+    pub relative: bool,
+    /// The condition type (one of the `jump::TYPE_*` values) of a conditional
+    /// jump, or `None` if the jump is unconditional
+    pub condition: Option<u8>,
+}
+
+impl Jump {
+    fn data(&self) -> u8 {
+        let src = match self.source {
+            JumpSource::C16(_) => jump::SRC_C16,
+            JumpSource::C32(_) => jump::SRC_C32,
+            JumpSource::C64(_) => jump::SRC_C64,
+            JumpSource::Dynamic => jump::SRC_DYN,
+        };
+        let mode = if self.relative { jump::MODE_RELATIVE } else { jump::MODE_ABSOLUTE };
+        match self.condition {
+            Some(condition) => src | mode | jump::CONDITIONAL_TRUE | (condition & jump::TYPE_MASK),
+            None => src | mode | jump::CONDITIONAL_FALSE,
+        }
+    }
+
+    fn decode(data: u8, code: &[u16]) -> Jump {
+        let source = match data & jump::SRC_MASK {
+            jump::SRC_C16 => JumpSource::C16(u16::read(code, 1)),
+            jump::SRC_C32 => JumpSource::C32(u32::read(code, 1)),
+            jump::SRC_C64 => JumpSource::C64(u64::read(code, 1)),
+            _ => JumpSource::Dynamic,
+        };
+        let condition = if data & jump::CONDITIONAL_MASK == jump::CONDITIONAL_TRUE {
+            Some(data & jump::TYPE_MASK)
+        } else {
+            None
+        };
+        Jump { source, relative: data & jump::MODE_MASK == jump::MODE_RELATIVE, condition }
+    }
+
+    /// Get the mnemonic of the jump
+    ///
+    /// Jump mnemonics are `JMP`, followed by `_` and the condition type for
+    /// conditional jumps, the source (`.C16`, `.C32`, `.C64`, or `.DYN`), and
+    /// `.REL` for relative jumps. For example, `JMP_NZ.C16.REL`.
+    pub fn mnemonic(&self) -> String {
+        let mut mnemonic = String::from("JMP");
+        if let Some(condition) = self.condition {
+            let name = CONDITIONS.iter().find(|(_, c)| *c == condition & jump::TYPE_MASK);
+            mnemonic.push('_');
+            mnemonic.push_str(name.map(|(n, _)| *n).unwrap_or("Z"));
+        }
+        mnemonic.push_str(match self.source {
+            JumpSource::C16(_) => ".C16",
+            JumpSource::C32(_) => ".C32",
+            JumpSource::C64(_) => ".C64",
+            JumpSource::Dynamic => ".DYN",
+        });
+        if self.relative {
+            mnemonic.push_str(".REL");
+        }
+        mnemonic
+    }
+
+    /// Get the inline operand of the jump; relative targets are signed
+    fn args(&self) -> Vec<i128> {
+        let value = match self.source {
+            JumpSource::C16(v) if self.relative => v as i16 as i128,
+            JumpSource::C32(v) if self.relative => v as i32 as i128,
+            JumpSource::C64(v) if self.relative => v as i64 as i128,
+            JumpSource::C16(v) => v as i128,
+            JumpSource::C32(v) => v as i128,
+            JumpSource::C64(v) => v as i128,
+            JumpSource::Dynamic => return vec![],
+        };
+        vec![value]
+    }
+
+    fn from_parts(mnemonic: &str, args: &[i128]) -> Option<Jump> {
+        let mut parts = mnemonic.split('.');
+        let condition = match parts.next()?.strip_prefix("JMP")? {
+            "" => None,
+            name => {
+                let name = name.strip_prefix('_')?;
+                Some(CONDITIONS.iter().find(|(n, _)| *n == name)?.1)
+            }
+        };
+        let source = parts.next()?;
+        let relative = match parts.next() {
+            Some("REL") => true,
+            Some(_) => return None,
+            None => false,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+
+        let value = match (source, args) {
+            ("DYN", []) => None,
+            ("DYN", _) => return None,
+            (_, [value]) => Some(*value),
+            _ => return None,
+        };
+        let source = match (source, value) {
+            ("C16", Some(v)) if relative => JumpSource::C16(i16::from_i128(v)? as u16),
+            ("C32", Some(v)) if relative => JumpSource::C32(i32::from_i128(v)? as u32),
+            ("C64", Some(v)) if relative => JumpSource::C64(i64::try_from(v).ok()? as u64),
+            ("C16", Some(v)) => JumpSource::C16(u16::from_i128(v)?),
+            ("C32", Some(v)) => JumpSource::C32(u32::from_i128(v)?),
+            ("C64", Some(v)) => JumpSource::C64(u64::from_i128(v)?),
+            ("DYN", None) => JumpSource::Dynamic,
+            _ => return None,
+        };
+        Some(Jump { source, relative, condition })
+    }
+}
+
+/// The names of the jump condition types, as used in mnemonics
+const CONDITIONS: [(&str, u8); 16] = [
+    ("Z", jump::TYPE_Z),
+    ("NZ", jump::TYPE_NZ),
+    ("POS", jump::TYPE_POS),
+    ("NEG", jump::TYPE_NEG),
+    ("GZ", jump::TYPE_GZ),
+    ("LZ", jump::TYPE_LZ),
+    ("EQ", jump::TYPE_EQ),
+    ("NEQ", jump::TYPE_NEQ),
+    ("GT", jump::TYPE_GT),
+    ("GTS", jump::TYPE_GTS),
+    ("LT", jump::TYPE_LT),
+    ("LTS", jump::TYPE_LTS),
+    ("GE", jump::TYPE_GE),
+    ("GES", jump::TYPE_GES),
+    ("LE", jump::TYPE_LE),
+    ("LES", jump::TYPE_LES),
+];
+
+macro_rules! instructions {
+    ($(
+        $group:ident => $module:ident {
+            $( $variant:ident $(( $($arg:ident: $ty:ty),+ ))? = $code:ident, )*
+        }
+    )*) => {
+        /// A single decoded instruction
+        ///
+        /// Each variant corresponds to one of the opcode constants of the
+        /// `sys`, `stack`, `math`, `fpmath`, and `function` modules, carrying
+        /// the inline operands of the instruction. Jumps are represented by a
+        /// single variant, as the jump opcode is a bitfield.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum Instruction {
+            $($( $variant $(( $($ty),+ ))?, )*)*
+            Jump(Jump),
+        }
+
+        impl Instruction {
+            /// Decode the instruction at the start of the given bytecode
+            ///
+            /// Returns the instruction and the number of words it takes. The
+            /// condition type bits of unconditional jumps are ignored, so such
+            /// jumps are encoded without them.
+            #[allow(unused_assignments, unused_mut, unused_variables)]
+            pub fn decode(code: &[u16]) -> Result<(Instruction, usize), BytecodeError> {
+                let opcode = match code.first() {
+                    Some(opcode) => *opcode,
+                    None => return Err(BytecodeError::InvalidAddress(0)),
+                };
+                let operands = match operand_count(opcode) {
+                    Some(count) => count,
+                    None => return Err(BytecodeError::BadOpcode(opcode)),
+                };
+                if code.len() <= operands {
+                    return Err(BytecodeError::code_data(opcode, operands as u64));
+                }
+                let group = (opcode >> 8) as u8;
+                let data = opcode as u8;
+                let instruction = match (group, data) {
+                    $($(
+                        (groups::$group, $module::$code) => {
+                            let mut offset = 1;
+                            $($(
+                                let $arg = <$ty as Operand>::read(code, offset);
+                                offset += <$ty as Operand>::WORDS;
+                            )+)?
+                            Instruction::$variant $(( $($arg),+ ))?
+                        }
+                    )*)*
+                    (groups::JUMP, data) => Instruction::Jump(Jump::decode(data, code)),
+                    _ => return Err(BytecodeError::BadOpcode(opcode)),
+                };
+                Ok((instruction, operands + 1))
+            }
+
+            /// Append the encoded instruction to the given bytecode
+            pub fn encode(&self, code: &mut Vec<u16>) {
+                code.push(self.opcode());
+                match *self {
+                    $($(
+                        Instruction::$variant $(( $($arg),+ ))? => {
+                            $($( $arg.write(code); )+)?
+                        }
+                    )*)*
+                    Instruction::Jump(jump) => match jump.source {
+                        JumpSource::C16(v) => v.write(code),
+                        JumpSource::C32(v) => v.write(code),
+                        JumpSource::C64(v) => v.write(code),
+                        JumpSource::Dynamic => (),
+                    },
+                }
+            }
+
+            /// Get the opcode of the instruction
+            pub fn opcode(&self) -> u16 {
+                let (group, data) = match self {
+                    $($(
+                        Instruction::$variant { .. } => (groups::$group, $module::$code),
+                    )*)*
+                    Instruction::Jump(jump) => (groups::JUMP, jump.data()),
+                };
+                ((group as u16) << 8) | data as u16
+            }
+
+            /// Get the number of words the encoded instruction takes
+            pub fn size(&self) -> usize {
+                1 + operand_count(self.opcode()).unwrap_or(0)
+            }
+        }
+
+        impl Instruction {
+            /// Get the mnemonic of the instruction
+            ///
+            /// The mnemonic is the name of the opcode constant, such as
+            /// `CONST_U16`; see `Jump::mnemonic` for the mnemonics of jumps.
+            pub fn mnemonic(&self) -> String {
+                match self {
+                    $($(
+                        Instruction::$variant { .. } => String::from(stringify!($code)),
+                    )*)*
+                    Instruction::Jump(jump) => jump.mnemonic(),
+                }
+            }
+
+            /// Get the values of the inline operands
+            pub(crate) fn args(&self) -> Vec<i128> {
+                match *self {
+                    $($(
+                        Instruction::$variant $(( $($arg),+ ))? => {
+                            vec![$($( $arg.to_i128() ),+)?]
+                        }
+                    )*)*
+                    Instruction::Jump(jump) => jump.args(),
+                }
+            }
+
+            /// Build an instruction from its mnemonic and operand values
+            ///
+            /// Returns `None` if the mnemonic is unknown, the wrong number of
+            /// operands is given, or an operand is out of range.
+            pub(crate) fn from_parts(mnemonic: &str, args: &[i128]) -> Option<Instruction> {
+                match mnemonic {
+                    $($(
+                        stringify!($code) => {
+                            let mut args = args.iter();
+                            $($(
+                                let $arg = <$ty as Operand>::from_i128(*args.next()?)?;
+                            )+)?
+                            if args.next().is_some() {
+                                return None;
+                            }
+                            Some(Instruction::$variant $(( $($arg),+ ))?)
+                        }
+                    )*)*
+                    _ => Jump::from_parts(mnemonic, args).map(Instruction::Jump),
+                }
+            }
+        }
+
+        /// The full opcodes of the instructions
+        ///
+        /// Each constant is the group byte and data byte of the opcode
+        /// constant of the same name in the `sys`, `stack`, `math`, `fpmath`,
+        /// and `function` modules. These allow matching on a whole opcode at
+        /// once:
+        ///
+        /// ```
+        /// use tstack::bytecode::op;
+        ///
+        /// assert_eq!(op::ADD, tstack::inst_math!(ADD));
+        /// let pushes = |opcode| matches!(opcode, op::CONST_0 | op::CONST_1 | op::DUPE_1);
+        /// assert!(pushes(tstack::inst_stack!(CONST_1)));
+        /// ```
+        pub mod op {
+            use super::{fpmath, function, groups, math, stack, sys};
+
+            $($(
+                pub const $code: u16 = ((groups::$group as u16) << 8) | $module::$code as u16;
+            )*)*
+        }
+    };
+}
+
+impl Instruction {
+    /// Parse the textual form of an instruction
+    ///
+    /// The text is the mnemonic followed by a comma separated list of
+    /// operands, as produced by the `Display` implementation.
+    pub fn parse(text: &str) -> Option<Instruction> {
+        let text = text.trim();
+        let (mnemonic, rest) = match text.split_once(char::is_whitespace) {
+            Some((mnemonic, rest)) => (mnemonic, rest.trim()),
+            None => (text, ""),
+        };
+        let args = if rest.is_empty() {
+            vec![]
+        } else {
+            rest.split(',').map(|a| a.trim().parse().ok()).collect::<Option<Vec<i128>>>()?
+        };
+        Instruction::from_parts(mnemonic, &args)
+    }
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.mnemonic())?;
+        let relative = matches!(self, Instruction::Jump(Jump { relative: true, .. }));
+        for (i, arg) in self.args().iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            if relative {
+                write!(f, "{}{:+}", separator, arg)?;
+            } else {
+                write!(f, "{}{}", separator, arg)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+instructions! {
+    SYSTEM => sys {
+        Nop = NOP,
+        Halt = HALT,
+        PrintStack = PRINT_STACK,
+        PrintU64 = PRINT_U64,
+        PrintI64 = PRINT_I64,
+        PrintF32 = PRINT_F32,
+        PrintF64 = PRINT_F64,
+        Breakpoint = BREAKPOINT,
+        ArgCount = ARG_COUNT,
+        ArgGet = ARG_GET,
+        EnvGet = ENV_GET,
+        Open = OPEN,
+        Read = READ,
+        Write = WRITE,
+        Close = CLOSE,
+        Connect = CONNECT,
+        Log = LOG,
+        Random = RANDOM,
+        RandomBelow = RANDOM_BELOW,
+        Time = TIME,
+        Fault(m: u16) = FAULT,
+    }
+    STACK => stack {
+        Const0 = CONST_0,
+        Const1 = CONST_1,
+        Const2 = CONST_2,
+        Const3 = CONST_3,
+        Const4 = CONST_4,
+        Const8 = CONST_8,
+        Const16 = CONST_16,
+        Const32 = CONST_32,
+        Const64 = CONST_64,
+        Const128 = CONST_128,
+        ConstN1 = CONST_N1,
+        ConstU16(c: u16) = CONST_U16,
+        ConstU32(c: u32) = CONST_U32,
+        ConstU64(c: u64) = CONST_U64,
+        ConstI16(c: i16) = CONST_I16,
+        ConstI32(c: i32) = CONST_I32,
+        Dupe = DUPE,
+        Dupe1 = DUPE_1,
+        DupeC(c: u16) = DUPE_C,
+        Swap = SWAP,
+        Swap1 = SWAP_1,
+        SwapC(c: u16) = SWAP_C,
+        Rotate = ROTATE,
+        Rotate1 = ROTATE_1,
+        RotateC(c: u16) = ROTATE_C,
+        Rotate1C(c: u16) = ROTATE_1_C,
+        Pop = POP,
+        Pop1 = POP_1,
+        PopC(c: u16) = POP_C,
+        GetU8 = GET_U8,
+        GetU8C(c: u16) = GET_U8_C,
+        GetU16 = GET_U16,
+        GetU16C(c: u16) = GET_U16_C,
+        GetU32 = GET_U32,
+        GetU32C(c: u16) = GET_U32_C,
+        GetU64 = GET_U64,
+        GetU64C(c: u16) = GET_U64_C,
+        GetI8 = GET_I8,
+        GetI8C(c: u16) = GET_I8_C,
+        GetI16 = GET_I16,
+        GetI16C(c: u16) = GET_I16_C,
+        GetI32 = GET_I32,
+        GetI32C(c: u16) = GET_I32_C,
+        GetF32 = GET_F32,
+        GetF32C(c: u16) = GET_F32_C,
+        Set8 = SET_8,
+        Set8C(c: u16) = SET_8_C,
+        Set16 = SET_16,
+        Set16C(c: u16) = SET_16_C,
+        Set32 = SET_32,
+        Set32C(c: u16) = SET_32_C,
+        Set64 = SET_64,
+        Set64C(c: u16) = SET_64_C,
+        SetF32 = SET_F32,
+        SetF32C(c: u16) = SET_F32_C,
+        StackSize = STACK_SIZE,
+        PushStack = PUSH_STACK,
+        PopStack = POP_STACK,
+        ReserveC(c: u16) = RESERVE_C,
+        ReserveN = RESERVE_N,
+        DataGet = DATA_GET,
+        DataGetC(c: u16) = DATA_GET_C,
+        DataRwGet = DATA_RW_GET,
+        DataRwGetC(c: u16) = DATA_RW_GET_C,
+        DataRwSet = DATA_RW_SET,
+        DataRwSetC(c: u16) = DATA_RW_SET_C,
+        GlobalGetC(c: u16) = GLOBAL_GET_C,
+        GlobalSetC(c: u16) = GLOBAL_SET_C,
+        GlobalExtGetC(c: u16) = GLOBAL_EXT_GET_C,
+        GlobalExtSetC(c: u16) = GLOBAL_EXT_SET_C,
+    }
+    MATH => math {
+        Add = ADD,
+        AddC(c: u16) = ADD_C,
+        Sub = SUB,
+        SubC(c: u16) = SUB_C,
+        Mul = MUL,
+        MulC(c: u16) = MUL_C,
+        Div = DIV,
+        DivC(c: u16) = DIV_C,
+        Idiv = IDIV,
+        IdivC(c: i16) = IDIV_C,
+        Mod = MOD,
+        ModC(c: u16) = MOD_C,
+        Imod = IMOD,
+        ImodC(c: i16) = IMOD_C,
+        Divmod = DIVMOD,
+        DivmodC(c: u16) = DIVMOD_C,
+        Idivmod = IDIVMOD,
+        IdivmodC(c: i16) = IDIVMOD_C,
+        Fma = FMA,
+        FmaC(c: u16) = FMA_C,
+        Pow = POW,
+        PowC(c: u16) = POW_C,
+        PowCR(c: u16) = POW_C_R,
+        Ipow = IPOW,
+        IpowC(c: i16) = IPOW_C,
+        IpowCR(c: i16) = IPOW_C_R,
+        Max = MAX,
+        MaxC(c: u16) = MAX_C,
+        Imax = IMAX,
+        ImaxC(c: i16) = IMAX_C,
+        Min = MIN,
+        MinC(c: u16) = MIN_C,
+        Imin = IMIN,
+        IminC(c: i16) = IMIN_C,
+        Clamp = CLAMP,
+        ClampC(upper: u16, lower: u16) = CLAMP_C,
+        Iclamp = ICLAMP,
+        IclampC(upper: i16, lower: i16) = ICLAMP_C,
+        NminC(c: u16) = NMIN_C,
+        Nmin = NMIN,
+        NiminC(c: u16) = NIMIN_C,
+        Nimin = NIMIN,
+        NmaxC(c: u16) = NMAX_C,
+        Nmax = NMAX,
+        NimaxC(c: u16) = NIMAX_C,
+        Nimax = NIMAX,
+        DiffC(c: u16) = DIFF_C,
+        Diff = DIFF,
+        SumC(c: u16) = SUM_C,
+        Sum = SUM,
+    }
+    FPMATH => fpmath {
+        Fadd = FADD,
+        Fsub = FSUB,
+        Fmul = FMUL,
+        Fdiv = FDIV,
+        Fneg = FNEG,
+        Itof = ITOF,
+        Ftoi = FTOI,
+    }
+    FUNCTION => function {
+        CallC(c: u16) = CALL_C,
+        CallExtC(c: u16) = CALL_EXT_C,
+        Return = RETURN,
+        FuncRefC(c: u16) = FUNC_REF_C,
+        FuncRefExtC(c: u16) = FUNC_REF_EXT_C,
+        CallRef = CALL_REF,
+    }
+}
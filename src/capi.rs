@@ -0,0 +1,329 @@
+//! C interface to the engine
+//!
+//! With the `capi` feature enabled, the library exports `extern "C"`
+//! functions for embedding the engine in hosts written in other languages.
+//! The crate is built as a `cdylib` alongside the Rust library, and the
+//! declarations of these functions are generated into `include/tstack.h`
+//! when the feature is built.
+//!
+//! Functions returning `int32_t` return `TS_OK` on success and `TS_ERROR` on
+//! failure; functions returning an ID return it as a non-negative `int64_t`,
+//! or `TS_ERROR` on failure. The message of the last failure of an engine is
+//! read with `ts_engine_error`.
+//!
+//! A native function registered from C is given the stack of the engine and
+//! the user data pointer given when it was registered, and returns `TS_OK`
+//! to continue or any other value to fault the calling code.
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::errors::BytecodeError;
+use crate::native::StackView;
+use crate::{format, Engine};
+
+/// Returned by functions which succeeded
+pub const TS_OK: i32 = 0;
+/// Returned by functions which failed
+pub const TS_ERROR: i32 = -1;
+
+/// An engine created by `ts_engine_new`
+pub struct TsEngine {
+    engine: Engine,
+    /// The message of the last failure
+    error: CString,
+}
+
+/// The stack given to a native function registered from C
+pub struct TsStack<'a, 'b> {
+    view: &'a mut StackView<'b>,
+    /// The fault raised by the last failed stack operation, if any
+    fault: Option<BytecodeError>,
+}
+
+/// A native function registered from C
+pub type TsNativeFn = extern "C" fn(stack: *mut TsStack, user_data: *mut c_void) -> i32;
+
+impl TsEngine {
+    /// Record the outcome of an operation, returning its status
+    fn status<E: std::fmt::Display>(&mut self, result: Result<(), E>) -> i32 {
+        match result {
+            Ok(()) => TS_OK,
+            Err(error) => self.fail(error),
+        }
+    }
+
+    /// Record the outcome of an operation returning an ID
+    fn id<E: std::fmt::Display>(&mut self, result: Result<u32, E>) -> i64 {
+        match result {
+            Ok(id) => id as i64,
+            Err(error) => self.fail(error) as i64,
+        }
+    }
+
+    fn fail<E: std::fmt::Display>(&mut self, error: E) -> i32 {
+        let message = error.to_string().replace('\0', " ");
+        self.error = CString::new(message).unwrap_or_default();
+        TS_ERROR
+    }
+}
+
+/// Read a string argument
+unsafe fn string<'a>(value: *const c_char) -> Result<&'a str, String> {
+    if value.is_null() {
+        return Err(String::from("null string"));
+    }
+    CStr::from_ptr(value).to_str().map_err(|_| String::from("string is not valid UTF-8"))
+}
+
+/// Create an engine
+///
+/// The engine must be freed with `ts_engine_free`.
+#[no_mangle]
+pub extern "C" fn ts_engine_new() -> *mut TsEngine {
+    Box::into_raw(Box::new(TsEngine { engine: Engine::new(), error: CString::default() }))
+}
+
+/// Free an engine created by `ts_engine_new`
+///
+/// # Safety
+/// `engine` must be null or an engine which has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ts_engine_free(engine: *mut TsEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// Get the message of the last failure of an engine
+///
+/// The message is empty if nothing has failed, and is valid until the next
+/// call taking the engine.
+///
+/// # Safety
+/// `engine` must be a valid engine.
+#[no_mangle]
+pub unsafe extern "C" fn ts_engine_error(engine: *const TsEngine) -> *const c_char {
+    (*engine).error.as_ptr()
+}
+
+/// Load a module from a buffer in the binary format, returning its ID
+///
+/// # Safety
+/// `engine` must be a valid engine, and `data` must point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ts_engine_load(engine: *mut TsEngine, data: *const u8, len: usize) -> i64 {
+    let engine = &mut *engine;
+    if data.is_null() {
+        return engine.fail("null module data") as i64;
+    }
+    let result = match format::read(std::slice::from_raw_parts(data, len)) {
+        Ok(module) => engine.engine.add_module(Rc::new(module)).map_err(|e| e.to_string()),
+        Err(error) => Err(error.to_string()),
+    };
+    engine.id(result)
+}
+
+/// Load a module from a `.tsb` file, returning its ID
+///
+/// # Safety
+/// `engine` must be a valid engine, and `path` a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ts_engine_load_file(engine: *mut TsEngine, path: *const c_char) -> i64 {
+    let engine = &mut *engine;
+    let bytes = string(path).and_then(|path| {
+        std::fs::read(Path::new(path)).map_err(|e| format!("can not read {}: {}", path, e))
+    });
+    match bytes {
+        Ok(bytes) => ts_engine_load(engine, bytes.as_ptr(), bytes.len()),
+        Err(error) => engine.fail(error) as i64,
+    }
+}
+
+/// Link the external symbols and globals of the loaded modules
+///
+/// # Safety
+/// `engine` must be a valid engine.
+#[no_mangle]
+pub unsafe extern "C" fn ts_engine_link(engine: *mut TsEngine) -> i32 {
+    let engine = &mut *engine;
+    let result = engine.engine.link();
+    engine.status(result)
+}
+
+/// Register a native function, returning its symbol ID
+///
+/// `user_data` is passed to each call of the function, and must stay valid
+/// for as long as the engine.
+///
+/// # Safety
+/// `engine` must be a valid engine, and `module` and `symbol` nul terminated
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn ts_engine_register(
+    engine: *mut TsEngine,
+    module: *const c_char,
+    symbol: *const c_char,
+    function: TsNativeFn,
+    user_data: *mut c_void,
+) -> i64 {
+    let engine = &mut *engine;
+    let names = string(module).and_then(|module| Ok((module, string(symbol)?)));
+    let (module, symbol) = match names {
+        Ok(names) => names,
+        Err(error) => return engine.fail(error) as i64,
+    };
+    let native = move |view: &mut StackView| {
+        let mut stack = TsStack { view, fault: None };
+        let status = function(&mut stack, user_data);
+        match (status, stack.fault) {
+            (TS_OK, _) => Ok(()),
+            (_, Some(fault)) => Err(fault),
+            (status, None) => {
+                Err(BytecodeError::NativeError(format!("native function returned {}", status)))
+            }
+        }
+    };
+    let result = engine.engine.register_native(module, symbol, native);
+    engine.id(result)
+}
+
+/// Find the module and symbol IDs of an exported symbol
+///
+/// # Safety
+/// `engine` must be a valid engine, `module` and `symbol` nul terminated
+/// strings, and `module_id` and `symbol_id` valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn ts_engine_find(
+    engine: *mut TsEngine,
+    module: *const c_char,
+    symbol: *const c_char,
+    module_id: *mut u32,
+    symbol_id: *mut u32,
+) -> i32 {
+    let engine = &mut *engine;
+    let ids = string(module).and_then(|module| {
+        let symbol = string(symbol)?;
+        let missing = || format!("symbol {} of module {} is not loaded", symbol, module);
+        let func = engine.engine.find_symbol(module, symbol).ok_or_else(missing)?;
+        Ok((func.module_id, func.symbol_id))
+    });
+    match ids {
+        Ok((module, symbol)) => {
+            *module_id = module;
+            *symbol_id = symbol;
+            TS_OK
+        }
+        Err(error) => engine.fail(error),
+    }
+}
+
+/// Run an exported symbol
+///
+/// # Safety
+/// `engine` must be a valid engine.
+#[no_mangle]
+pub unsafe extern "C" fn ts_engine_run(
+    engine: *mut TsEngine,
+    module_id: u32,
+    symbol_id: u32,
+) -> i32 {
+    let engine = &mut *engine;
+    let result = engine.engine.run(module_id, symbol_id);
+    engine.status(result)
+}
+
+/// Get the number of values on the stack of an engine
+///
+/// # Safety
+/// `engine` must be a valid engine.
+#[no_mangle]
+pub unsafe extern "C" fn ts_engine_stack_len(engine: *const TsEngine) -> usize {
+    (*engine).engine.stack.len()
+}
+
+/// Get the values on the stack of an engine, with the top of the stack last
+///
+/// The values are valid until the next call taking the engine.
+///
+/// # Safety
+/// `engine` must be a valid engine.
+#[no_mangle]
+pub unsafe extern "C" fn ts_engine_stack(engine: *const TsEngine) -> *const u64 {
+    (*engine).engine.stack.as_ptr()
+}
+
+/// Push a value onto the stack of an engine
+///
+/// # Safety
+/// `engine` must be a valid engine.
+#[no_mangle]
+pub unsafe extern "C" fn ts_engine_push(engine: *mut TsEngine, value: u64) -> i32 {
+    let engine = &mut *engine;
+    if engine.engine.stack.len() >= engine.engine.maxstack {
+        return engine.fail("the stack is full");
+    }
+    if !engine.engine.reserve_stack(1) {
+        return engine.fail("out of memory");
+    }
+    engine.engine.stack.push(value);
+    TS_OK
+}
+
+/// Remove every value from the stack of an engine
+///
+/// # Safety
+/// `engine` must be a valid engine.
+#[no_mangle]
+pub unsafe extern "C" fn ts_engine_clear(engine: *mut TsEngine) {
+    (*engine).engine.stack.clear();
+}
+
+/// Get the number of values on the stack given to a native function
+///
+/// # Safety
+/// `stack` must be the stack given to the running native function.
+#[no_mangle]
+pub unsafe extern "C" fn ts_stack_len(stack: *const TsStack) -> usize {
+    (*stack).view.len()
+}
+
+/// Pop the value on the top of the stack given to a native function
+///
+/// If the stack is empty, this fails and the native function faults with a
+/// stack underflow when it returns any status other than `TS_OK`.
+///
+/// # Safety
+/// `stack` must be the stack given to the running native function, and
+/// `value` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn ts_stack_pop(stack: *mut TsStack, value: *mut u64) -> i32 {
+    let stack = &mut *stack;
+    match stack.view.pop() {
+        Ok(popped) => {
+            *value = popped;
+            TS_OK
+        }
+        Err(fault) => {
+            stack.fault = Some(fault);
+            TS_ERROR
+        }
+    }
+}
+
+/// Push a value onto the stack given to a native function
+///
+/// # Safety
+/// `stack` must be the stack given to the running native function.
+#[no_mangle]
+pub unsafe extern "C" fn ts_stack_push(stack: *mut TsStack, value: u64) -> i32 {
+    let stack = &mut *stack;
+    match stack.view.push(value) {
+        Ok(()) => TS_OK,
+        Err(fault) => {
+            stack.fault = Some(fault);
+            TS_ERROR
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! The `analyze` command
+//!
+//! The given files hold the output of runs of real workloads: the trace
+//! printed by `run --trace` or `--trace=stack`, or the statistics printed by
+//! `--stats`, which include the number of times each sequence of
+//! instructions ran. A file of `-` is read from standard input. The counts of
+//! every file are merged, and the pairs and triples of instructions which ran
+//! most often are printed to standard output, with their opcodes, as
+//! candidates for fusion into superinstructions:
+//!
+//! ```text
+//! pairs
+//! CONST_1 + ADD                                   120  0x0101 0x0300
+//! triples
+//! CONST_U16 + CONST_1 + ADD                        96  0x010b 0x0101 0x0300
+//! ```
+//!
+//! `--top N` sets the number of each printed, 20 unless given. Lines which
+//! are neither trace lines nor sequence counts are ignored, so the whole of
+//! the standard error of a run may be given. The opcodes of a candidate
+//! make the pattern of a peephole rewrite fusing it; see the `fusion` module.
+
+use std::io::Read;
+use std::path::PathBuf;
+
+use tstack::bytecode::Instruction;
+use tstack::fusion::{self, Sequences};
+
+/// The options of the `analyze` command
+pub(crate) struct AnalyzeOptions {
+    /// The files to read traces and statistics from
+    pub(crate) files: Vec<PathBuf>,
+    /// The number of candidates of each length to print
+    pub(crate) top: usize,
+}
+
+/// Read a file, or standard input for `-`
+fn read(path: &PathBuf) -> Result<String, String> {
+    let mut text = String::new();
+    let result = match path.to_str() {
+        Some("-") => std::io::stdin().read_to_string(&mut text).map(|_| text),
+        _ => std::fs::read_to_string(path),
+    };
+    result.map_err(|e| format!("can not read {}: {}", path.display(), e))
+}
+
+/// Parse a sequence count printed by `--stats`, as `CONST_1 + ADD   120`
+fn parse_count(line: &str) -> Option<(Vec<u16>, u64)> {
+    let (sequence, count) = line.trim().rsplit_once(' ')?;
+    let opcodes = fusion::parse_sequence(sequence)?;
+    if opcodes.len() < 2 {
+        return None;
+    }
+    Some((opcodes, count.parse().ok()?))
+}
+
+/// Parse a trace line, as `main:0004 CONST_U16 7`, into the module, offset,
+/// and instruction
+fn parse_trace(line: &str) -> Option<(&str, usize, Instruction)> {
+    // `--trace=stack` follows the instruction with the stack
+    let line = line.split_once(" [").map_or(line, |(line, _)| line);
+    let (location, instruction) = line.split_once(' ')?;
+    let (module, offset) = location.rsplit_once(':')?;
+    Some((module, offset.parse().ok()?, Instruction::parse(instruction)?))
+}
+
+/// Count the sequences in the output of a run
+fn count(sequences: &mut Sequences, text: &str) {
+    let mut next: Option<(&str, usize)> = None;
+    for line in text.lines() {
+        if let Some((opcodes, count)) = parse_count(line) {
+            sequences.add(&opcodes, count);
+            continue;
+        }
+        match parse_trace(line) {
+            Some((module, offset, instruction)) => {
+                if next != Some((module, offset)) {
+                    sequences.split();
+                }
+                next = Some((module, offset + instruction.size()));
+                sequences.push(&instruction);
+            }
+            None => {
+                // Any other line, such as a warning or fault, interrupts the
+                // instructions around it
+                sequences.split();
+                next = None;
+            }
+        }
+    }
+    sequences.split();
+}
+
+/// Print the most frequent sequences of instructions in traces
+pub(crate) fn analyze(options: &AnalyzeOptions) -> Result<u8, String> {
+    let mut sequences = Sequences::new();
+    for path in options.files.iter() {
+        count(&mut sequences, &read(path)?);
+    }
+    for (title, len) in [("pairs", 2), ("triples", 3)] {
+        println!("{}", title);
+        for candidate in sequences.candidates(len).iter().take(options.top) {
+            let opcodes: Vec<String> =
+                candidate.opcodes.iter().map(|opcode| format!("{:#06x}", opcode)).collect();
+            println!(
+                "{:<40} {:>10}  {}",
+                candidate.to_string(),
+                candidate.count,
+                opcodes.join(" ")
+            );
+        }
+    }
+    Ok(0)
+}
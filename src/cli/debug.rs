@@ -0,0 +1,243 @@
+//! The `debug` command
+//!
+//! The program is started stopped before its first instruction, and is run
+//! by the commands read from standard input:
+//!
+//!| Command                 | Effect
+//!|-------------------------|-------
+//!| `step [N]`, `s [N]`     | Run the next instruction, or the next `N`
+//!| `continue`, `c`         | Run until the next breakpoint or the end of the program
+//!| `break LOCATION`, `b`   | Set a breakpoint
+//!| `delete LOCATION`, `d`  | Remove a breakpoint
+//!| `breakpoints`           | List the breakpoints
+//!| `stack`                 | Print the stack
+//!| `where`                 | Print the location of the next instruction
+//!| `list`, `l`             | Disassemble the symbol the next instruction is in
+//!| `quit`, `q`             | End the session
+//!
+//! A location is a symbol or bytecode offset, in the module of the next
+//! instruction or in the module named before a `:`, as in `main:helper` or
+//! `main:12`. `BREAKPOINT` instructions stop the program like breakpoints.
+
+use std::io::{BufRead, Write};
+
+use tstack::bytecode::Instruction;
+use tstack::debugger::{DebugSession, Location, Stop};
+use tstack::Engine;
+
+use crate::{describe_fault, prepare, Recorders, RunOptions};
+
+const HELP: &str = "commands: step [N], continue, break LOCATION, delete LOCATION, breakpoints,
+          stack, where, list, quit";
+
+/// Describe a location in the bytecode
+fn describe(engine: &Engine, location: Location) -> String {
+    let module = &engine.modules[location.module_id as usize];
+    let offset = location.offset as u32;
+    let mut text = match module.symbol_at(offset) {
+        Some(symbol) => {
+            format!("{}:{}+{}", module.name, symbol.name().unwrap_or("?"), offset - symbol.offset())
+        }
+        None => format!("{}:{}", module.name, offset),
+    };
+    if let Some(source) = module.source_location(offset) {
+        text.push_str(&format!(" ({})", source));
+    }
+    text
+}
+
+/// Disassemble the instruction at a location
+fn instruction_at(engine: &Engine, location: Location) -> String {
+    engine.modules[location.module_id as usize].disassemble(location.offset)
+}
+
+/// Resolve a location given to `break` or `delete`
+fn parse_location(session: &DebugSession, text: &str) -> Result<Location, String> {
+    let engine = session.engine();
+    let (module_id, rest) = match text.split_once(':') {
+        Some((name, rest)) => match engine.module_lookup.get(name) {
+            Some(id) => (*id, rest),
+            None => return Err(format!("module {} is not loaded", name)),
+        },
+        None => match session.location() {
+            Some(location) => (location.module_id, text),
+            None => return Err(String::from("the program has finished")),
+        },
+    };
+    let module = &engine.modules[module_id as usize];
+    let offset = match rest.parse::<usize>() {
+        Ok(offset) if offset < module.bytecode.len() => offset,
+        Ok(offset) => return Err(format!("offset {} is past the end of {}", offset, module.name)),
+        Err(_) => match module.find_symbol(rest) {
+            Some(id) => module.symbol(id).unwrap().offset() as usize,
+            None => return Err(format!("module {} has no symbol {}", module.name, rest)),
+        },
+    };
+    Ok(Location { module_id, offset })
+}
+
+/// Disassemble the symbol the next instruction is in
+fn list(session: &DebugSession) {
+    let current = match session.location() {
+        Some(location) => location,
+        None => return println!("the program has finished"),
+    };
+    let engine = session.engine();
+    let module = &engine.modules[current.module_id as usize];
+    let (start, end) = match module.symbol_at(current.offset as u32) {
+        Some(symbol) => (symbol.offset() as usize, symbol.offset() as usize + symbol.len()),
+        None => (current.offset, current.offset + 1),
+    };
+    let breakpoints: Vec<Location> = session.breakpoints().collect();
+    let mut offset = start;
+    while offset < end {
+        let location = Location { module_id: current.module_id, offset };
+        let marker = match (location == current, breakpoints.contains(&location)) {
+            (true, _) => "=>",
+            (false, true) => " *",
+            (false, false) => "  ",
+        };
+        println!("{} {:04}  {}", marker, offset, instruction_at(engine, location));
+        offset += Instruction::decode(&module.bytecode[offset..]).map_or(1, |(_, len)| len);
+    }
+}
+
+/// Print where the program stopped
+fn show(session: &DebugSession) {
+    if let Some(location) = session.location() {
+        let engine = session.engine();
+        println!("{}  {}", describe(engine, location), instruction_at(engine, location));
+    }
+}
+
+/// Report the result of running the program, returning the exit status if
+/// it ended
+fn report(session: &DebugSession, result: Result<Stop, String>) -> Option<u8> {
+    match result {
+        Ok(Stop::Finished) => {
+            let value = session.engine().halt_value();
+            match value {
+                Some(value) => println!("program halted with {}", value),
+                None => println!("program finished"),
+            }
+            println!("stack: {:?}", session.engine().stack);
+            Some(value.map_or(0, |value| value.min(255) as u8))
+        }
+        Ok(stop) => {
+            if stop == Stop::Breakpoint {
+                print!("breakpoint at ");
+            }
+            show(session);
+            None
+        }
+        Err(error) => {
+            println!("error: {}", error);
+            Some(1)
+        }
+    }
+}
+
+/// Debug a program, returning the exit status of the process
+pub(crate) fn debug(options: &RunOptions) -> Result<u8, String> {
+    let (mut engine, module_id, symbol_id) = prepare(options)?;
+    let recorders = Recorders::attach(&mut engine, options);
+    let mut session = match DebugSession::new(&mut engine, module_id, symbol_id) {
+        Ok(session) => session,
+        Err(fault) => return Err(describe_fault(&fault)),
+    };
+    let mut status = None;
+    if session.is_finished() {
+        status = report(&session, Ok(Stop::Finished));
+    } else {
+        show(&session);
+    }
+
+    let mut stdout = std::io::stdout();
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        print!("(tdb) ");
+        let _ = stdout.flush();
+        let line = match lines.next() {
+            Some(line) => line.map_err(|e| e.to_string())?,
+            None => {
+                println!();
+                break;
+            }
+        };
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => continue,
+        };
+        let argument = words.next();
+        let running = matches!(command, "s" | "step" | "c" | "continue");
+        if running && status.is_some() {
+            println!("the program has finished");
+            continue;
+        }
+        match command {
+            "s" | "step" => {
+                let count = match argument.map(str::parse::<usize>) {
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => {
+                        println!("invalid count {}", argument.unwrap());
+                        continue;
+                    }
+                    None => 1,
+                };
+                let mut result = Ok(Stop::Step);
+                for _ in 0..count {
+                    result = session.step();
+                    if !matches!(result, Ok(Stop::Step)) {
+                        break;
+                    }
+                }
+                let result = result.map_err(|fault| describe_fault(&fault));
+                status = report(&session, result);
+            }
+            "c" | "continue" => {
+                let result = session.resume().map_err(|fault| describe_fault(&fault));
+                status = report(&session, result);
+            }
+            "b" | "break" | "d" | "delete" => {
+                let location = match argument.map(|text| parse_location(&session, text)) {
+                    Some(Ok(location)) => location,
+                    Some(Err(error)) => {
+                        println!("{}", error);
+                        continue;
+                    }
+                    None => {
+                        println!("{} requires a location", command);
+                        continue;
+                    }
+                };
+                let text = describe(session.engine(), location);
+                if command.starts_with('b') {
+                    session.add_breakpoint(location);
+                    println!("breakpoint set at {}", text);
+                } else if session.remove_breakpoint(location) {
+                    println!("breakpoint removed from {}", text);
+                } else {
+                    println!("no breakpoint at {}", text);
+                }
+            }
+            "breakpoints" => {
+                for location in session.breakpoints() {
+                    println!("{}", describe(session.engine(), location));
+                }
+            }
+            "stack" => println!("{:?}", session.engine().stack),
+            "where" => match session.is_finished() {
+                true => println!("the program has finished"),
+                false => show(&session),
+            },
+            "l" | "list" => list(&session),
+            "help" => println!("{}", HELP),
+            "q" | "quit" => break,
+            _ => println!("unknown command {}; {}", command, HELP),
+        }
+    }
+    drop(session);
+    recorders.write(&engine, options)?;
+    Ok(status.unwrap_or(0))
+}
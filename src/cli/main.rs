@@ -0,0 +1,686 @@
+//! The command line interface of the engine
+//!
+//! ```text
+//! tstack run <file.tsb>... [--entry module:symbol] [--trace[=opcodes|stack|calls]]
+//!            [--max-stack N] [--max-call-depth N] [--fuel N] [--timeout T]
+//!            [--stats] [--coverage <out.json>] [--asm] [--watch] [-- ARG...]
+//! tstack repl [file.tsb]... [--lang]
+//! tstack debug <file.tsb>... [run options]
+//! tstack profile <file.tsb>... [run options] [--json]
+//! tstack link <file.tsb>... -o <out.tsb>
+//! tstack analyze <trace.txt>... [--top N]
+//! ```
+//!
+//! `run` loads the given module files, links them, and runs the entry
+//! symbol, which is `main` of the first module unless `--entry` names
+//! another. Faults are printed with the module and symbol they were raised
+//! in and the calls leading to it, and the process exits with a nonzero
+//! status. `--trace` prints a trace of the run to standard error: every
+//! instruction run, every instruction with the stack it runs on, or every
+//! call and return.
+//!
+//! The limit options bound what the run may use, so bytecode which is not
+//! trusted may be run from the command line: the depth of the stack, the
+//! number of calls in progress, the number of instructions run, and the time
+//! taken, given in seconds or with a suffix of `s` or `ms`. A run exceeding a
+//! limit faults.
+//!
+//! Modules which fail verification are not run, and every problem found in
+//! them is printed. Suspicious code found by the `lint` module, such as
+//! instructions which can never run, is printed to standard error as
+//! warnings before running.
+//!
+//! Module files ending in `.tasm` are assembly source, and are assembled
+//! into a module named after the file. A module file of `-` is read from
+//! standard input, so a program may be piped straight into the engine; it is
+//! assembly source with `--asm`, and is then named `main`.
+//!
+//! `--stats` prints the number of times each opcode ran and the deepest the
+//! stack was to standard error once the run ends, followed by the number of
+//! times each pair and triple of instructions ran one after another, and
+//! `--coverage` writes the number of times each instruction ran to a JSON
+//! file, as an object of the form:
+//!
+//! ```text
+//! {
+//!   "modules": [
+//!     {
+//!       "module": "main",
+//!       "instructions": 4,
+//!       "covered": 2,
+//!       "counts": [{"offset": 0, "count": 1}, {"offset": 1, "count": 1}],
+//!       "uncovered": [2, 3]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Both are written even if the run faults.
+//!
+//! `--watch` runs the program again, with the same options, each time one
+//! of its module files changes, reassembling those which are assembly
+//! source, until the process is interrupted. Faults are printed as usual but
+//! do not end the process.
+//!
+//! The arguments after `--` are given to the program, which reads them with
+//! `ARG_COUNT` and `ARG_GET`.
+//!
+//! A program which stops with `HALT` exits with the value it halted with as
+//! the status of the process, clamped to 255, and one which returns from its
+//! entry point exits with 0. Errors and faults exit with 1.
+//!
+//! `repl` reads entries from standard input, one per line, and runs each
+//! against the same engine, printing the stack after each. Entries are
+//! instructions in the assembly language, or expressions of the `lang`
+//! module with `--lang`; they may call the symbols of the given module
+//! files. The lines `:asm` and `:lang` switch between the two, `:clear`
+//! empties the stack, and `:quit` ends the session.
+//!
+//! `debug` runs a program as `run` does, under an interactive debugger; see
+//! the `debug` module.
+//!
+//! `profile` runs a program as `run` does, then prints the symbols and
+//! opcodes it ran the most instructions in; see the `profile` module.
+//!
+//! `link` merges the given module files into a single module with the static
+//! linker, resolving the symbols they import from each other, and writes it
+//! to the output file. The merged module takes the name of the first.
+//!
+//! `analyze` reads the traces and statistics printed by runs of real
+//! workloads, and prints the sequences of instructions which ran most often
+//! as candidates for superinstructions; see the `analyze` module.
+
+mod analyze;
+mod debug;
+mod profile;
+
+use std::cell::RefCell;
+use std::io::{BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use tstack::config::EngineConfig;
+use tstack::coverage::Coverage;
+use tstack::errors::Fault;
+use tstack::fusion::Sequences;
+use tstack::module::Module;
+use tstack::profile::Profile;
+use tstack::trace::TraceEvent;
+use tstack::{asm, lang, linker, lint, verify};
+
+use crate::analyze::AnalyzeOptions;
+use crate::profile::ProfileOptions;
+
+const USAGE: &str =
+    "usage: tstack run <file.tsb>... [--entry module:symbol] [--trace[=opcodes|stack|calls]]
+                  [--max-stack N] [--max-call-depth N] [--fuel N] [--timeout T]
+                  [--stats] [--coverage <out.json>] [--asm] [--watch] [-- ARG...]
+       tstack repl [file.tsb]... [--lang]
+       tstack debug <file.tsb>... [run options]
+       tstack profile <file.tsb>... [run options] [--json]
+       tstack link <file.tsb>... -o <out.tsb>
+       tstack analyze <trace.txt>... [--top N]";
+
+/// A command given on the command line
+enum Command {
+    Analyze(AnalyzeOptions),
+    Debug(RunOptions),
+    Help,
+    Link(LinkOptions),
+    Profile(ProfileOptions),
+    Repl(ReplOptions),
+    Run(RunOptions),
+}
+
+/// The options of the `run` command
+struct RunOptions {
+    /// The module files to load, the first of which holds the default entry
+    files: Vec<PathBuf>,
+    /// If a module read from standard input is assembly source
+    asm: bool,
+    /// The module and symbol to run, if not `main` of the first module
+    entry: Option<(String, String)>,
+    /// What to trace while running, if anything
+    trace: Option<Trace>,
+    /// The limits to run with
+    config: EngineConfig,
+    /// If opcode counts are printed once the run ends
+    stats: bool,
+    /// The file to write coverage to, if any
+    coverage: Option<PathBuf>,
+    /// If the program is run again whenever its module files change
+    watch: bool,
+    /// The arguments given to the program
+    args: Vec<String>,
+}
+
+/// What `--trace` prints
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Trace {
+    Opcodes,
+    Stack,
+    Calls,
+}
+
+/// The options of the `link` command
+struct LinkOptions {
+    /// The module files to merge, the first of which names the result
+    files: Vec<PathBuf>,
+    /// The file to write the merged module to
+    output: PathBuf,
+}
+
+/// The options of the `repl` command
+struct ReplOptions {
+    /// The module files to load
+    files: Vec<PathBuf>,
+    /// If entries are expressions rather than instructions
+    lang: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Command, String> {
+    match args.first().map(|s| s.as_str()) {
+        Some("analyze") => parse_analyze(&args[1..]).map(Command::Analyze),
+        Some("debug") => parse_run(&args[1..]).and_then(no_watch).map(Command::Debug),
+        Some("link") => parse_link(&args[1..]).map(Command::Link),
+        Some("profile") => parse_profile(&args[1..]).map(Command::Profile),
+        Some("repl") => parse_repl(&args[1..]).map(Command::Repl),
+        Some("run") => parse_run(&args[1..]).map(Command::Run),
+        Some("help" | "-h" | "--help") => Ok(Command::Help),
+        Some(command) => Err(format!("unknown command {}", command)),
+        None => Err(String::from("no command given")),
+    }
+}
+
+fn parse_run(args: &[String]) -> Result<RunOptions, String> {
+    let mut options = RunOptions {
+        files: Vec::new(),
+        asm: false,
+        entry: None,
+        trace: None,
+        config: EngineConfig::default(),
+        stats: false,
+        coverage: None,
+        watch: false,
+        args: Vec::new(),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--entry" => {
+                let entry = args.next().ok_or("--entry requires a value")?;
+                let (module, symbol) = entry
+                    .split_once(':')
+                    .ok_or_else(|| format!("entry {} is not of the form module:symbol", entry))?;
+                options.entry = Some((String::from(module), String::from(symbol)));
+            }
+            "--max-stack" => options.config.max_stack = parse_value(arg, args.next())?,
+            "--max-call-depth" => options.config.max_frames = parse_value(arg, args.next())?,
+            "--fuel" => options.config.fuel = Some(parse_value(arg, args.next())?),
+            "--timeout" => options.config.timeout = Some(parse_duration(args.next())?),
+            "--trace" | "--trace=opcodes" => options.trace = Some(Trace::Opcodes),
+            "--trace=stack" => options.trace = Some(Trace::Stack),
+            "--trace=calls" => options.trace = Some(Trace::Calls),
+            "--stats" => options.stats = true,
+            "--asm" => options.asm = true,
+            "--watch" => options.watch = true,
+            "--coverage" => {
+                let path = args.next().ok_or("--coverage requires a value")?;
+                options.coverage = Some(PathBuf::from(path));
+            }
+            "--" => options.args.extend(args.by_ref().cloned()),
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            file => options.files.push(PathBuf::from(file)),
+        }
+    }
+    if options.files.is_empty() {
+        return Err(String::from("no module files given"));
+    }
+    Ok(options)
+}
+
+/// Parse the value of an option taking a number
+fn parse_value<T: FromStr>(flag: &str, value: Option<&String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} requires a value", flag))?;
+    value.parse().map_err(|_| format!("invalid value {} for {}", value, flag))
+}
+
+/// Parse the value of `--timeout`
+fn parse_duration(value: Option<&String>) -> Result<Duration, String> {
+    let value = value.ok_or("--timeout requires a value")?;
+    let invalid = || format!("invalid value {} for --timeout", value);
+    if let Some(millis) = value.strip_suffix("ms") {
+        return millis.parse().map(Duration::from_millis).map_err(|_| invalid());
+    }
+    let seconds: f64 = value.strip_suffix('s').unwrap_or(value).parse().map_err(|_| invalid())?;
+    Duration::try_from_secs_f64(seconds).map_err(|_| invalid())
+}
+
+fn parse_link(args: &[String]) -> Result<LinkOptions, String> {
+    let mut files = Vec::new();
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                output = Some(PathBuf::from(args.next().ok_or("-o requires a value")?))
+            }
+            flag if flag.starts_with('-') => return Err(format!("unknown option {}", flag)),
+            file => files.push(PathBuf::from(file)),
+        }
+    }
+    if files.is_empty() {
+        return Err(String::from("no module files given"));
+    }
+    let output = output.ok_or("no output file given")?;
+    Ok(LinkOptions { files, output })
+}
+
+fn parse_analyze(args: &[String]) -> Result<AnalyzeOptions, String> {
+    let mut options = AnalyzeOptions { files: Vec::new(), top: 20 };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--top" => options.top = parse_value(arg, args.next())?,
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            file => options.files.push(PathBuf::from(file)),
+        }
+    }
+    if options.files.is_empty() {
+        return Err(String::from("no trace files given"));
+    }
+    Ok(options)
+}
+
+fn parse_profile(args: &[String]) -> Result<ProfileOptions, String> {
+    // Arguments after `--` belong to the program
+    let end = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+    let json = args[..end].iter().any(|arg| arg == "--json");
+    let args: Vec<String> = args
+        .iter()
+        .enumerate()
+        .filter(|(i, arg)| *i >= end || *arg != "--json")
+        .map(|(_, arg)| arg.clone())
+        .collect();
+    Ok(ProfileOptions { run: no_watch(parse_run(&args)?)?, json })
+}
+
+/// Reject `--watch` for commands other than `run`
+fn no_watch(options: RunOptions) -> Result<RunOptions, String> {
+    match options.watch {
+        true => Err(String::from("--watch is only supported by run")),
+        false => Ok(options),
+    }
+}
+
+fn parse_repl(args: &[String]) -> Result<ReplOptions, String> {
+    let mut options = ReplOptions { files: Vec::new(), lang: false };
+    for arg in args {
+        match arg.as_str() {
+            "--lang" => options.lang = true,
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            file => options.files.push(PathBuf::from(file)),
+        }
+    }
+    Ok(options)
+}
+
+/// Load a module file, or standard input for `-`, assembling it if it is
+/// assembly source: a `.tasm` file, or standard input if `asm` is set
+fn load(path: &Path, asm: bool) -> Result<Module, String> {
+    let stdin = path == Path::new("-");
+    let asm = match stdin {
+        true => asm,
+        false => path.extension().is_some_and(|extension| extension == "tasm"),
+    };
+    let (name, bytes) = match stdin {
+        true => {
+            let mut bytes = Vec::new();
+            let result = std::io::stdin().read_to_end(&mut bytes);
+            (String::from("standard input"), result.map(|_| bytes))
+        }
+        false => (path.display().to_string(), std::fs::read(path)),
+    };
+    let bytes = bytes.map_err(|e| format!("can not read {}: {}", name, e))?;
+    if !asm {
+        return Module::from_bytes(&bytes).map_err(|e| format!("can not load {}: {}", name, e));
+    }
+    let source = String::from_utf8(bytes).map_err(|_| format!("{} is not UTF-8", name))?;
+    let (module, dir) = match stdin {
+        true => ("main", Path::new(".")),
+        false => (
+            path.file_stem().and_then(|s| s.to_str()).unwrap_or("main"),
+            path.parent().unwrap_or(Path::new(".")),
+        ),
+    };
+    asm::assemble(module, &source, dir).map_err(|e| format!("can not assemble {}: {}", name, e))
+}
+
+/// Describe a fault and the calls leading to it, with the source lines
+/// they were made at if the modules have debug info
+fn describe_fault(fault: &Fault) -> String {
+    let mut text = fault.message();
+    for (index, location) in fault.backtrace().iter().enumerate() {
+        text.push_str(if index == 0 { "\n  in " } else { "\n  called from " });
+        text.push_str(&location.to_string());
+    }
+    text
+}
+
+/// Create an engine with the given module files loaded, returning the names
+/// of the modules
+fn load_all(files: &[PathBuf], asm: bool) -> Result<(tstack::Engine, Vec<String>), String> {
+    let mut engine = tstack::Engine::new();
+    let mut names = Vec::new();
+    for path in files.iter() {
+        let module = load(path, asm)?;
+        let diagnostics = verify::diagnose(&module);
+        if diagnostics.has_errors() {
+            let mut text = format!("module {} is invalid", module.name);
+            for error in diagnostics.errors() {
+                text.push_str(&format!("\n  {}", error.render(&module)));
+            }
+            return Err(text);
+        }
+        for warning in diagnostics.warnings().chain(&lint::lint(&module)) {
+            eprintln!("{}", warning.render(&module));
+        }
+        names.push(module.name.clone());
+        engine.add_module(Rc::new(module)).map_err(|e| e.to_string())?;
+    }
+    engine.link().map_err(|e| e.to_string())?;
+    Ok((engine, names))
+}
+
+/// Print a trace event to standard error
+fn print_trace(trace: Trace, event: &TraceEvent) {
+    match (trace, event) {
+        (Trace::Opcodes | Trace::Stack, TraceEvent::Instruction { module, offset, stack, .. }) => {
+            let instruction = match event.instruction() {
+                Some(instruction) => instruction.to_string(),
+                None => format!("{:#06x}", module.bytecode[*offset]),
+            };
+            match trace {
+                Trace::Stack => {
+                    eprintln!("{}:{:04} {:<24} {:?}", module.name, offset, instruction, stack)
+                }
+                _ => eprintln!("{}:{:04} {}", module.name, offset, instruction),
+            }
+        }
+        (Trace::Calls, TraceEvent::Call { module, symbol_id, depth, .. }) => {
+            let symbol = module.symbol(*symbol_id).and_then(|s| s.name()).unwrap_or("?");
+            eprintln!("{:indent$}call {}:{}", "", module.name, symbol, indent = depth * 2);
+        }
+        (Trace::Calls, TraceEvent::Return { depth }) => {
+            eprintln!("{:indent$}return", "", indent = depth * 2);
+        }
+        _ => (),
+    }
+}
+
+/// Give trace events to a hook as well as any already set
+fn add_trace_hook(engine: &mut tstack::Engine, mut hook: impl FnMut(&TraceEvent) + 'static) {
+    let mut previous = engine.clear_trace_hook();
+    engine.set_trace_hook(move |event: &TraceEvent| {
+        if let Some(previous) = previous.as_mut() {
+            previous(event);
+        }
+        hook(event);
+    });
+}
+
+/// Quote a string for JSON
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Write the coverage of every module with bytecode as JSON
+fn coverage_json(engine: &tstack::Engine, coverage: &Coverage) -> String {
+    let mut modules = Vec::new();
+    for (id, module) in engine.modules.iter().enumerate() {
+        let report = coverage.report(id as u32, module);
+        if report.instructions == 0 {
+            continue;
+        }
+        let counts: Vec<String> = report
+            .counts
+            .iter()
+            .map(|(offset, count)| format!("{{\"offset\": {}, \"count\": {}}}", offset, count))
+            .collect();
+        let uncovered: Vec<String> = report.uncovered.iter().map(|o| o.to_string()).collect();
+        modules.push(
+            [
+                String::from("    {"),
+                format!("      \"module\": {},", json_string(&report.module)),
+                format!("      \"instructions\": {},", report.instructions),
+                format!("      \"covered\": {},", report.counts.len()),
+                format!("      \"counts\": [{}],", counts.join(", ")),
+                format!("      \"uncovered\": [{}]", uncovered.join(", ")),
+                String::from("    }"),
+            ]
+            .join("\n"),
+        );
+    }
+    format!("{{\n  \"modules\": [\n{}\n  ]\n}}\n", modules.join(",\n"))
+}
+
+/// What `--stats` and `--coverage` record of a run
+struct Recorders {
+    stats: Option<Rc<RefCell<(Profile, Sequences)>>>,
+    coverage: Option<Rc<RefCell<Coverage>>>,
+}
+
+impl Recorders {
+    /// Start recording what the options ask for
+    fn attach(engine: &mut tstack::Engine, options: &RunOptions) -> Recorders {
+        let stats =
+            options.stats.then(|| Rc::new(RefCell::new((Profile::new(), Sequences::new()))));
+        if let Some(stats) = &stats {
+            let stats = Rc::clone(stats);
+            add_trace_hook(engine, move |event| {
+                let (profile, sequences) = &mut *stats.borrow_mut();
+                profile.record(event);
+                sequences.record(event);
+            });
+        }
+        let coverage = options.coverage.as_ref().map(|_| Rc::new(RefCell::new(Coverage::new())));
+        if let Some(coverage) = &coverage {
+            let coverage = Rc::clone(coverage);
+            add_trace_hook(engine, move |event| coverage.borrow_mut().record(event));
+        }
+        Recorders { stats, coverage }
+    }
+
+    /// Print the opcode and sequence counts and write the coverage file
+    fn write(&self, engine: &tstack::Engine, options: &RunOptions) -> Result<(), String> {
+        if let Some(stats) = &self.stats {
+            let (profile, sequences) = &*stats.borrow();
+            eprintln!("instructions: {}", profile.instructions());
+            eprintln!("max stack: {}", engine.stack_high_water());
+            for (opcode, count) in profile.opcodes() {
+                eprintln!("{:<24} {:>10}", opcode, count);
+            }
+            for candidate in (2..=tstack::fusion::MAX_LEN).flat_map(|len| sequences.candidates(len))
+            {
+                eprintln!("{:<40} {:>10}", candidate.to_string(), candidate.count);
+            }
+        }
+        if let (Some(coverage), Some(path)) = (&self.coverage, &options.coverage) {
+            let json = coverage_json(engine, &coverage.borrow());
+            std::fs::write(path, json)
+                .map_err(|e| format!("can not write {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Create the engine for a program, returning it with the IDs of the module
+/// and symbol to run
+fn prepare(options: &RunOptions) -> Result<(tstack::Engine, u32, u32), String> {
+    let (mut engine, names) = load_all(&options.files, options.asm)?;
+    if let Some(trace) = options.trace {
+        engine.set_trace_hook(move |event: &TraceEvent| print_trace(trace, event));
+    }
+    engine.configure(&options.config);
+    engine.args = options.args.clone();
+
+    let (module, symbol) = match &options.entry {
+        Some((module, symbol)) => (module.as_str(), symbol.as_str()),
+        None => (names[0].as_str(), "main"),
+    };
+    let module_id = *engine
+        .module_lookup
+        .get(module)
+        .ok_or_else(|| format!("module {} is not loaded", module))?;
+    let symbol_id = engine.modules[module_id as usize]
+        .find_symbol(symbol)
+        .ok_or_else(|| format!("module {} has no symbol {}", module, symbol))?;
+    Ok((engine, module_id, symbol_id))
+}
+
+/// Run a program, returning the exit status of the process
+fn run(options: &RunOptions) -> Result<u8, String> {
+    let (mut engine, module_id, symbol_id) = prepare(options)?;
+    let recorders = Recorders::attach(&mut engine, options);
+    let result = engine.run(module_id, symbol_id);
+    recorders.write(&engine, options)?;
+    result.map_err(|fault| describe_fault(&fault))?;
+    Ok(engine.halt_value().map_or(0, |value| value.min(255) as u8))
+}
+
+/// Merge module files into one
+fn link(options: &LinkOptions) -> Result<(), String> {
+    let modules = options
+        .files
+        .iter()
+        .map(|path| load(path, false))
+        .collect::<Result<Vec<Module>, String>>()?;
+    let module = linker::merge(&modules).map_err(|e| e.to_string())?;
+    std::fs::write(&options.output, module.to_bytes())
+        .map_err(|e| format!("can not write {}: {}", options.output.display(), e))
+}
+
+/// How often `--watch` checks the module files for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Get the times the module files were last modified
+fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files.iter().map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok()).collect()
+}
+
+/// Run a program each time its module files change, forever
+fn watch(options: &RunOptions) -> Result<u8, String> {
+    if options.files.iter().any(|path| path == Path::new("-")) {
+        return Err(String::from("--watch can not be used with standard input"));
+    }
+    loop {
+        let modified = modified_times(&options.files);
+        match run(options) {
+            Ok(status) => eprintln!("exited with status {}", status),
+            Err(error) => eprintln!("error: {}", error),
+        }
+        eprintln!("waiting for changes");
+        while modified_times(&options.files) == modified {
+            std::thread::sleep(WATCH_INTERVAL);
+        }
+    }
+}
+
+/// The name of the module entries are compiled into
+const REPL_MODULE: &str = "repl";
+
+/// Compile an entry of the REPL into a module with a single symbol
+fn compile_entry(line: &str, lang: bool) -> Result<Module, String> {
+    let result = match lang {
+        true => lang::compile(REPL_MODULE, &format!("export fn entry() {{ return {}; }}", line)),
+        false => asm::assemble(REPL_MODULE, &format!(".export entry\n{}", line), Path::new(".")),
+    };
+    result.map_err(|e| e.to_string())
+}
+
+/// Compile and run an entry of the REPL
+fn run_entry(engine: &mut tstack::Engine, line: &str, lang: bool) -> Result<(), String> {
+    let module = Rc::new(compile_entry(line, lang)?);
+    let result = match engine.module_lookup.contains_key(REPL_MODULE) {
+        true => engine.replace_module(REPL_MODULE, module),
+        false => engine.add_module(module).and_then(|id| engine.link().map(|_| id)),
+    };
+    let module_id = result.map_err(|e| e.to_string())?;
+    engine.run(module_id, 0).map_err(|fault| fault.to_string())
+}
+
+fn repl(options: &ReplOptions) -> Result<(), String> {
+    let (mut engine, _) = load_all(&options.files, false)?;
+    let mut lang = options.lang;
+    let mut stdout = std::io::stdout();
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        print!("> ");
+        let _ = stdout.flush();
+        let line = match lines.next() {
+            Some(line) => line.map_err(|e| e.to_string())?,
+            None => {
+                println!();
+                break;
+            }
+        };
+        match line.trim() {
+            "" => continue,
+            ":quit" => break,
+            ":asm" => lang = false,
+            ":lang" => lang = true,
+            ":clear" => engine.stack.clear(),
+            entry => {
+                if let Err(error) = run_entry(&mut engine, entry, lang) {
+                    println!("error: {}", error);
+                }
+            }
+        }
+        println!("{:?}", engine.stack);
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match parse_args(&args) {
+        Ok(command) => command,
+        Err(error) => {
+            eprintln!("error: {}\n{}", error, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let result = match command {
+        Command::Analyze(options) => analyze::analyze(&options),
+        Command::Debug(options) => debug::debug(&options),
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(0)
+        }
+        Command::Link(options) => link(&options).map(|_| 0),
+        Command::Profile(options) => profile::profile(&options),
+        Command::Repl(options) => repl(&options).map(|_| 0),
+        Command::Run(options) if options.watch => watch(&options),
+        Command::Run(options) => run(&options),
+    };
+    match result {
+        Ok(status) => ExitCode::from(status),
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
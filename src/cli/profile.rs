@@ -0,0 +1,99 @@
+//! The `profile` command
+//!
+//! The program is run as with `run`, counting the instructions run by each
+//! symbol and the number of times each opcode ran. Once the program ends,
+//! even by faulting, the counts are printed to standard output, hottest
+//! first, as tables or with `--json` as an object of the form:
+//!
+//! ```text
+//! {
+//!   "instructions": 12,
+//!   "symbols": [
+//!     {"module": "main", "symbol": "main", "calls": 1, "instructions": 8, "total_instructions": 12}
+//!   ],
+//!   "opcodes": [{"opcode": "ADD", "count": 2}]
+//! }
+//! ```
+//!
+//! The `instructions` of a symbol are those it ran itself, and its
+//! `total_instructions` include those run by the symbols it called.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use tstack::profile::Profile;
+
+use crate::{add_trace_hook, describe_fault, json_string, prepare, Recorders, RunOptions};
+
+/// The options of the `profile` command
+pub(crate) struct ProfileOptions {
+    /// The options of the run to profile
+    pub(crate) run: RunOptions,
+    /// If the report is printed as JSON
+    pub(crate) json: bool,
+}
+
+fn print_json(profile: &Profile) {
+    let symbols: Vec<String> = profile
+        .symbols()
+        .iter()
+        .map(|s| {
+            format!(
+                "    {{\"module\": {}, \"symbol\": {}, \"calls\": {}, \"instructions\": {}, \"total_instructions\": {}}}",
+                json_string(&s.module),
+                json_string(&s.symbol),
+                s.calls,
+                s.instructions,
+                s.total_instructions
+            )
+        })
+        .collect();
+    let opcodes: Vec<String> = profile
+        .opcodes()
+        .iter()
+        .map(|(opcode, count)| {
+            format!("    {{\"opcode\": {}, \"count\": {}}}", json_string(opcode), count)
+        })
+        .collect();
+    println!("{{");
+    println!("  \"instructions\": {},", profile.instructions());
+    println!("  \"symbols\": [\n{}\n  ],", symbols.join(",\n"));
+    println!("  \"opcodes\": [\n{}\n  ]", opcodes.join(",\n"));
+    println!("}}");
+}
+
+fn print_tables(profile: &Profile) {
+    println!("instructions: {}", profile.instructions());
+    println!();
+    println!("{:<32} {:>10} {:>12} {:>12}", "symbol", "calls", "self", "total");
+    for symbol in profile.symbols() {
+        let name = format!("{}:{}", symbol.module, symbol.symbol);
+        println!(
+            "{:<32} {:>10} {:>12} {:>12}",
+            name, symbol.calls, symbol.instructions, symbol.total_instructions
+        );
+    }
+    println!();
+    println!("{:<32} {:>10}", "opcode", "count");
+    for (opcode, count) in profile.opcodes() {
+        println!("{:<32} {:>10}", opcode, count);
+    }
+}
+
+/// Profile a program, returning the exit status of the process
+pub(crate) fn profile(options: &ProfileOptions) -> Result<u8, String> {
+    let (mut engine, module_id, symbol_id) = prepare(&options.run)?;
+    let profile = Rc::new(RefCell::new(Profile::new()));
+    let recorder = Rc::clone(&profile);
+    add_trace_hook(&mut engine, move |event| recorder.borrow_mut().record(event));
+    let recorders = Recorders::attach(&mut engine, &options.run);
+    let result = engine.run(module_id, symbol_id);
+    recorders.write(&engine, &options.run)?;
+
+    match options.json {
+        true => print_json(&profile.borrow()),
+        false => print_tables(&profile.borrow()),
+    }
+    result.map_err(|fault| describe_fault(&fault))?;
+    Ok(engine.halt_value().map_or(0, |value| value.min(255) as u8))
+}
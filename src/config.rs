@@ -0,0 +1,207 @@
+//! Limits and settings an engine is created with
+//!
+//! Hosts running bytecode they do not trust bound the resources it may use
+//! with an `EngineConfig`, starting from the defaults:
+//!
+//! ```
+//! use std::time::Duration;
+//! use tstack::config::EngineConfig;
+//!
+//! let engine = tstack::Engine::with_config(EngineConfig {
+//!     max_stack: 1024,
+//!     fuel: Some(1_000_000),
+//!     timeout: Some(Duration::from_secs(1)),
+//!     ..EngineConfig::default()
+//! });
+//! assert_eq!(engine.maxstack, 1024);
+//! ```
+
+use std::time::Duration;
+
+/// The bits of the NaN pushed by floating point instructions when
+/// `EngineConfig::canonicalize_nan` is set: positive, quiet, and without a
+/// payload
+pub const CANONICAL_NAN: u64 = 0x7ff8_0000_0000_0000;
+
+/// The bits of the NaN written to 32 bit float locals when
+/// `EngineConfig::canonicalize_nan` is set
+pub const CANONICAL_NAN_F32: u32 = 0x7fc0_0000;
+
+/// The limits and settings of an engine
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EngineConfig {
+    /// The maximum depth of the stack; see `Engine::maxstack`
+    pub max_stack: usize,
+    /// The maximum number of calls which may be in progress at once; see
+    /// `Engine::maxframes`
+    pub max_frames: usize,
+    /// The maximum number of calls from native functions back into bytecode
+    /// which may be in progress at once; see `Engine::maxreentry`
+    pub max_reentry: usize,
+    /// The fuel to run bytecode with, or `None` to not meter running; see
+    /// `Engine::fuel`
+    pub fuel: Option<u64>,
+    /// How long running may take from when the config is applied, or `None`
+    /// for no limit; see `Engine::set_timeout`
+    pub timeout: Option<Duration>,
+    /// If faults carry a copy of the state of the engine when they were
+    /// raised; see `Engine::snapshot_faults`
+    pub snapshot_faults: bool,
+    /// If each value on the stack carries a tag of its type; see
+    /// `Engine::tag_stack`
+    pub tag_stack: bool,
+    /// The conditions which fault rather than being allowed; see
+    /// `Engine::strict`
+    pub strict: Strictness,
+    /// What integer arithmetic which overflows does; see `Engine::overflow`
+    pub overflow: Overflow,
+    /// If floating point instructions push NaN results as `CANONICAL_NAN`;
+    /// see `Engine::canonicalize_nan`
+    pub canonicalize_nan: bool,
+    /// How the stack grows as values are pushed; see `Engine::stack_growth`
+    pub stack_growth: StackGrowth,
+    /// A depth of the stack at which to warn the host rather than fault, or
+    /// `None` for no warning; see `Engine::soft_stack_limit`
+    pub soft_stack_limit: Option<usize>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            max_stack: 0x8FFF,
+            max_frames: 1024,
+            max_reentry: 64,
+            fuel: None,
+            timeout: None,
+            snapshot_faults: false,
+            tag_stack: false,
+            strict: Strictness::default(),
+            overflow: Overflow::default(),
+            canonicalize_nan: false,
+            stack_growth: StackGrowth::default(),
+            soft_stack_limit: None,
+        }
+    }
+}
+
+/// How the stack of an engine grows as values are pushed
+///
+/// Growing the stack reallocates it, which takes time in proportion to its
+/// depth at the instruction which happens to push past its capacity. Hosts
+/// sensitive to latency may reserve the whole stack up front, or grow it a
+/// fixed amount at a time:
+///
+/// ```
+/// use tstack::config::{EngineConfig, StackGrowth};
+///
+/// let engine = tstack::Engine::with_config(EngineConfig {
+///     max_stack: 4096,
+///     stack_growth: StackGrowth::Reserve,
+///     ..EngineConfig::default()
+/// });
+/// assert!(engine.stack.capacity() >= 4096);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StackGrowth {
+    /// Grow the stack when it is full, doubling its capacity, and keep what
+    /// it grows to
+    #[default]
+    OnDemand,
+    /// Reserve room for `max_stack` values when the config is applied, so
+    /// the stack never grows while running
+    Reserve,
+    /// Grow the stack by the given number of values when it is full
+    ///
+    /// Once a run ends, room more than two chunks beyond the deepest the
+    /// stack was during the run is released, leaving one chunk spare. Runs
+    /// reaching about the same depth as those before them neither grow nor
+    /// shrink the stack.
+    Chunked(usize),
+}
+
+/// What integer arithmetic does when its result overflows
+///
+/// The `ADD`, `SUB`, and `MUL` instructions of the math group, and their
+/// forms taking a constant, treat their operands as unsigned. By default a
+/// result which does not fit wraps around, as the hardware does, but the same
+/// bytecode may be run saturating or faulting instead, such as under test:
+///
+/// ```
+/// use std::path::Path;
+/// use std::rc::Rc;
+/// use tstack::config::{EngineConfig, Overflow};
+///
+/// let module = tstack::asm::assemble(
+///     "main",
+///     ".export main\n CONST_N1\n CONST_2\n ADD\n CONST_2\n CONST_1\n SUB\n RETURN",
+///     Path::new("."),
+/// )
+/// .unwrap();
+/// let mut engine = tstack::Engine::with_config(EngineConfig {
+///     overflow: Overflow::Saturate,
+///     ..EngineConfig::default()
+/// });
+/// engine.add_module(Rc::new(module)).unwrap();
+/// engine.run(0, 0).unwrap();
+/// assert_eq!(engine.stack, vec![u64::MAX, 0]);
+/// ```
+///
+/// Setting `Strictness::overflow` faults whatever the policy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Keep the low 64 bits of the result
+    #[default]
+    Wrap,
+    /// Give the largest value for results too large, and `0` for results
+    /// below zero
+    Saturate,
+    /// Fault with `BytecodeError::Overflow`
+    Fault,
+}
+
+/// Conditions which are allowed by default, but fault in strict mode
+///
+/// Each condition is legal, but is rarely what a compiler means to generate,
+/// so compiler authors may turn them into faults to find bugs in the code
+/// they generate at the instruction which caused them:
+///
+/// ```
+/// use std::path::Path;
+/// use std::rc::Rc;
+/// use tstack::config::{EngineConfig, Strictness};
+/// use tstack::errors::BytecodeError;
+///
+/// let module = tstack::asm::assemble(
+///     "main",
+///     ".export main\n CONST_N1\n CONST_1\n ADD\n RETURN",
+///     Path::new("."),
+/// )
+/// .unwrap();
+/// let mut engine = tstack::Engine::with_config(EngineConfig {
+///     strict: Strictness::all(),
+///     ..EngineConfig::default()
+/// });
+/// engine.add_module(Rc::new(module)).unwrap();
+/// let fault = engine.run(0, 0).unwrap_err();
+/// assert!(matches!(fault.error(), BytecodeError::Overflow(_)));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Strictness {
+    /// Integer arithmetic which wraps around faults with
+    /// `BytecodeError::Overflow`
+    pub overflow: bool,
+    /// Floating point arithmetic which produces NaN from operands which are
+    /// not NaN faults with `BytecodeError::NotANumber`
+    pub nan: bool,
+    /// A symbol without a signature which returns with the stack deeper than
+    /// when it was called faults with `BytecodeError::UnusedValues`, as if it
+    /// had declared no outputs
+    pub unused_values: bool,
+}
+
+impl Strictness {
+    /// Fault on every condition
+    pub fn all() -> Strictness {
+        Strictness { overflow: true, nan: true, unused_values: true }
+    }
+}
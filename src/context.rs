@@ -2,6 +2,7 @@
 
 use std::rc::Rc;
 
+use crate::bytecode;
 use crate::errors::BytecodeError;
 use crate::module::Module;
 
@@ -9,24 +10,47 @@ use crate::module::Module;
 ///
 /// The Context struct contains an 'execution context' within the virtual
 /// machine, holding the currently executing module (which contains the
-/// bytecode) and an instruction pointer into it.
+/// bytecode), its ID within the engine, and an instruction pointer into it.
 ///
 /// When combined with a stack, this allows for function calls both within
 /// and between modules.
 pub struct Context {
     module: Rc<Module>,
+    module_id: u32,
     offset: usize,
     current: u16,
 }
 
 impl Context {
     /// Create a new context with the given module and instruction pointer
-    pub fn new(module: Rc<Module>, offset: usize) -> Result<Context, BytecodeError> {
-        if offset >= (*module).bytecode.len() {
+    pub fn new(
+        module: Rc<Module>,
+        module_id: u32,
+        offset: usize,
+    ) -> Result<Context, BytecodeError> {
+        if offset >= module.bytecode.len() {
             return Err(BytecodeError::InvalidAddress(offset));
         }
-        let current = (*module).bytecode[offset];
-        Ok(Context { module: module, offset: offset, current: current })
+        let current = module.bytecode[offset];
+        Ok(Context { module, module_id, offset, current })
+    }
+
+    /// Get the currently executing module
+    #[inline]
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    /// Get the ID of the currently executing module
+    #[inline]
+    pub fn module_id(&self) -> u32 {
+        self.module_id
+    }
+
+    /// Get the offset of the next value in the bytecode
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
     }
 
     /// Get the currently executing opcode
@@ -37,29 +61,41 @@ impl Context {
 
     /// Fetch the next opcode and increment the current instruction pointer
     #[inline]
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<u16> {
         if !self.has_next() {
             return None;
         }
-        let v = (*(self.module)).bytecode[self.offset];
+        let v = self.module.bytecode[self.offset];
         self.offset += 1;
 
         Some(v)
     }
 
+    /// Move the instruction pointer to an offset within the bytecode, or to
+    /// its end
+    #[inline]
+    pub fn jump(&mut self, offset: usize) -> Result<(), BytecodeError> {
+        if offset > self.module.bytecode.len() {
+            return Err(BytecodeError::InvalidAddress(offset));
+        }
+        self.offset = offset;
+        Ok(())
+    }
+
     /// Check if there exists an opcode at the current offset
     #[inline]
     pub fn has_next(&self) -> bool {
-        self.offset < (*(self.module)).bytecode.len()
+        self.offset < self.module.bytecode.len()
     }
 
     /// Use the next value in the bytecode as a constant u16 value
     #[inline]
     pub fn cval_u16(&mut self) -> Result<u16, BytecodeError> {
-        if (*(self.module)).bytecode.len() - self.offset < 1 {
+        if self.module.bytecode.len() - self.offset < 1 {
             return Err(BytecodeError::code_data(self.current, 1));
         }
-        let v = (*(self.module)).bytecode[self.offset];
+        let v = self.module.bytecode[self.offset];
         self.offset += 1;
         Ok(v)
     }
@@ -67,11 +103,11 @@ impl Context {
     /// Use the next two values in the bytecode as two u16 values
     #[inline]
     pub fn cval_u16_2(&mut self) -> Result<(u16, u16), BytecodeError> {
-        if (*(self.module)).bytecode.len() - self.offset < 2 {
+        if self.module.bytecode.len() - self.offset < 2 {
             return Err(BytecodeError::code_data(self.current, 2));
         }
-        let v1 = (*(self.module)).bytecode[self.offset];
-        let v2 = (*(self.module)).bytecode[self.offset + 1];
+        let v1 = self.module.bytecode[self.offset];
+        let v2 = self.module.bytecode[self.offset + 1];
         self.offset += 2;
         Ok((v1, v2))
     }
@@ -79,13 +115,13 @@ impl Context {
     /// Use the next four values in the bytecode as four u16 values
     #[inline]
     pub fn cval_u16_4(&mut self) -> Result<(u16, u16, u16, u16), BytecodeError> {
-        if (*(self.module)).bytecode.len() - self.offset < 4 {
+        if self.module.bytecode.len() - self.offset < 4 {
             return Err(BytecodeError::code_data(self.current, 4));
         }
-        let v1 = (*(self.module)).bytecode[self.offset];
-        let v2 = (*(self.module)).bytecode[self.offset + 1];
-        let v3 = (*(self.module)).bytecode[self.offset + 2];
-        let v4 = (*(self.module)).bytecode[self.offset + 3];
+        let v1 = self.module.bytecode[self.offset];
+        let v2 = self.module.bytecode[self.offset + 1];
+        let v3 = self.module.bytecode[self.offset + 2];
+        let v4 = self.module.bytecode[self.offset + 3];
         self.offset += 4;
         Ok((v1, v2, v3, v4))
     }
@@ -94,13 +130,13 @@ impl Context {
     #[inline]
     pub fn cval_u32(&mut self) -> Result<u32, BytecodeError> {
         let (v1, v2) = self.cval_u16_2()?;
-        Ok(((v1 as u32) << 16) | (v2 as u32))
+        Ok(bytecode::decode_u32(&[v1, v2]).unwrap())
     }
 
     /// Use the next four values in the bytecode as a single u64 value
     #[inline]
     pub fn cval_u64(&mut self) -> Result<u64, BytecodeError> {
         let (v1, v2, v3, v4) = self.cval_u16_4()?;
-        Ok(((v1 as u64) << 48) | ((v2 as u64) << 32) | ((v3 as u64) << 16) | (v4 as u64))
+        Ok(bytecode::decode_u64(&[v1, v2, v3, v4]).unwrap())
     }
 }
@@ -0,0 +1,98 @@
+//! Recording which bytecode an engine ran
+//!
+//! A `Coverage` is built from the `TraceEvent`s of an engine, and counts the
+//! times the instruction at each offset of each module ran. Comparing the
+//! offsets which ran against the instructions of a module shows the code a
+//! test suite never reaches:
+//!
+//! ```
+//! use std::cell::RefCell;
+//! use std::path::Path;
+//! use std::rc::Rc;
+//! use tstack::coverage::Coverage;
+//! use tstack::trace::TraceEvent;
+//!
+//! let module = tstack::asm::assemble(
+//!     "main",
+//!     ".export main\n CONST_1\n RETURN\n.export unused\n CONST_2\n RETURN",
+//!     Path::new("."),
+//! )
+//! .unwrap();
+//! let mut engine = tstack::Engine::new();
+//! engine.add_module(Rc::new(module)).unwrap();
+//!
+//! let coverage = Rc::new(RefCell::new(Coverage::new()));
+//! let recorder = Rc::clone(&coverage);
+//! engine.set_trace_hook(move |event: &TraceEvent| recorder.borrow_mut().record(event));
+//! engine.run(0, 0).unwrap();
+//!
+//! let report = coverage.borrow().report(0, &engine.modules[0]);
+//! assert_eq!(report.counts, vec![(0, 1), (1, 1)]);
+//! assert_eq!(report.uncovered, vec![2, 3]);
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::bytecode::Instruction;
+use crate::module::Module;
+use crate::trace::TraceEvent;
+
+/// The coverage of a single module
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleCoverage {
+    /// The name of the module
+    pub module: String,
+    /// The number of instructions in the bytecode of the module
+    pub instructions: usize,
+    /// The offsets of the instructions which ran, with the number of times
+    /// each ran, in order of offset
+    pub counts: Vec<(usize, u64)>,
+    /// The offsets of the instructions which did not run, in order
+    pub uncovered: Vec<usize>,
+}
+
+/// Execution counts by module and offset
+#[derive(Clone, Debug, Default)]
+pub struct Coverage {
+    modules: HashMap<u32, BTreeMap<usize, u64>>,
+}
+
+impl Coverage {
+    /// Create an empty coverage map
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    /// Count an event
+    pub fn record(&mut self, event: &TraceEvent) {
+        if let TraceEvent::Instruction { module_id, offset, .. } = event {
+            *self.modules.entry(*module_id).or_default().entry(*offset).or_insert(0) += 1;
+        }
+    }
+
+    /// Get the number of times the instruction at an offset ran
+    pub fn count(&self, module_id: u32, offset: usize) -> u64 {
+        self.modules.get(&module_id).and_then(|m| m.get(&offset)).copied().unwrap_or(0)
+    }
+
+    /// Describe the coverage of a module
+    ///
+    /// The module must be the one with the ID `module_id` in the engine the
+    /// events were recorded from. Its instructions are found by decoding its
+    /// bytecode from the start; words which do not decode count as single
+    /// instructions.
+    pub fn report(&self, module_id: u32, module: &Module) -> ModuleCoverage {
+        let mut report =
+            ModuleCoverage { module: module.name.clone(), ..ModuleCoverage::default() };
+        let mut offset = 0;
+        while offset < module.bytecode.len() {
+            report.instructions += 1;
+            match self.count(module_id, offset) {
+                0 => report.uncovered.push(offset),
+                count => report.counts.push((offset, count)),
+            }
+            offset += Instruction::decode(&module.bytecode[offset..]).map_or(1, |(_, len)| len);
+        }
+        report
+    }
+}
@@ -0,0 +1,176 @@
+//! Running bytecode one instruction at a time
+//!
+//! A `DebugSession` runs a symbol like `Engine::run`, but stops whenever the
+//! host asks it to: after a single instruction with `step`, or with `resume`
+//! at the next breakpoint, either one set by the host or a `BREAKPOINT`
+//! instruction in the bytecode. While the session is stopped the host may
+//! inspect the engine, which is how `tstack debug` shows the stack and the
+//! code around the next instruction.
+//!
+//! ```
+//! use std::path::Path;
+//! use std::rc::Rc;
+//! use tstack::debugger::{DebugSession, Stop};
+//!
+//! let module = tstack::asm::assemble(
+//!     "main",
+//!     ".export main\n CONST_1\n BREAKPOINT\n CONST_2\n RETURN",
+//!     Path::new("."),
+//! )
+//! .unwrap();
+//! let mut engine = tstack::Engine::new();
+//! engine.add_module(Rc::new(module)).unwrap();
+//!
+//! let mut session = DebugSession::new(&mut engine, 0, 0).unwrap();
+//! assert_eq!(session.resume().unwrap(), Stop::Breakpoint);
+//! assert_eq!(session.engine().stack, vec![1]);
+//! assert_eq!(session.resume().unwrap(), Stop::Finished);
+//! assert_eq!(session.engine().stack, vec![1, 2]);
+//! ```
+//!
+//! Native functions, including any bytecode they call back into, run to
+//! completion within a single step.
+
+use std::collections::BTreeSet;
+
+use crate::errors::Fault;
+use crate::{Engine, Entry};
+
+/// A position in the bytecode of a loaded module
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location {
+    /// The ID of the module
+    pub module_id: u32,
+    /// The offset of the instruction in the bytecode of the module
+    pub offset: usize,
+}
+
+/// Why a session stopped running
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// A single instruction was run
+    Step,
+    /// The next instruction has a breakpoint
+    Breakpoint,
+    /// The run ended, by returning from its entry point or halting
+    Finished,
+}
+
+/// A run of a symbol which stops at the request of the host
+pub struct DebugSession<'a> {
+    engine: &'a mut Engine,
+    /// The entry point of the run, or `None` once it has ended
+    entry: Option<Entry>,
+    /// The IDs of the module and symbol run, to locate faults
+    module_id: u32,
+    symbol_id: u32,
+    breakpoints: BTreeSet<Location>,
+}
+
+impl<'a> DebugSession<'a> {
+    /// Start running a symbol, stopping before its first instruction
+    ///
+    /// The symbol must be exported, as for `Engine::run`. Native functions
+    /// are run to completion, so the session starts finished.
+    pub fn new(
+        engine: &'a mut Engine,
+        module_id: u32,
+        symbol_id: u32,
+    ) -> Result<DebugSession<'a>, Fault> {
+        let entry = engine.begin(module_id, symbol_id);
+        if !matches!(entry, Ok(Some(_))) {
+            engine.end(&entry);
+        }
+        let entry = entry.map_err(|error| engine.locate(error, module_id, symbol_id))?;
+        Ok(DebugSession { engine, entry, module_id, symbol_id, breakpoints: BTreeSet::new() })
+    }
+
+    /// Get the engine running the session
+    pub fn engine(&self) -> &Engine {
+        self.engine
+    }
+
+    /// Get the engine running the session, to change its state
+    pub fn engine_mut(&mut self) -> &mut Engine {
+        self.engine
+    }
+
+    /// Check if the run has ended
+    pub fn is_finished(&self) -> bool {
+        self.entry.is_none()
+    }
+
+    /// Get the location of the next instruction, or `None` if the run has
+    /// ended
+    pub fn location(&self) -> Option<Location> {
+        self.entry.as_ref()?;
+        let context = &self.engine.context;
+        Some(Location { module_id: context.module_id(), offset: context.offset() })
+    }
+
+    /// Stop before the instruction at a location when resuming
+    ///
+    /// Returns `false` if there already was a breakpoint at the location.
+    pub fn add_breakpoint(&mut self, location: Location) -> bool {
+        self.breakpoints.insert(location)
+    }
+
+    /// Remove the breakpoint at a location, returning `false` if there was
+    /// none
+    pub fn remove_breakpoint(&mut self, location: Location) -> bool {
+        self.breakpoints.remove(&location)
+    }
+
+    /// Get the breakpoints set by the host, in order of location
+    pub fn breakpoints(&self) -> impl Iterator<Item = Location> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Run the next instruction
+    ///
+    /// If the instruction faults, the run ends as it would for `Engine::run`
+    /// and the fault is returned.
+    pub fn step(&mut self) -> Result<Stop, Fault> {
+        let entry = match &self.entry {
+            Some(entry) => entry,
+            None => return Ok(Stop::Finished),
+        };
+        let result = match self.engine.step() {
+            Ok(false) => return Ok(Stop::Step),
+            Ok(true) => self.engine.finish(entry),
+            Err(error) => Err(error),
+        };
+        self.entry = None;
+        self.engine.end(&result);
+        match result {
+            Ok(()) => Ok(Stop::Finished),
+            Err(error) => Err(self.engine.locate(error, self.module_id, self.symbol_id)),
+        }
+    }
+
+    /// Run instructions until the next breakpoint or the end of the run
+    ///
+    /// At least one instruction is run, so resuming at a breakpoint moves
+    /// past it.
+    pub fn resume(&mut self) -> Result<Stop, Fault> {
+        loop {
+            if self.step()? == Stop::Finished {
+                return Ok(Stop::Finished);
+            }
+            if self.at_breakpoint() {
+                return Ok(Stop::Breakpoint);
+            }
+        }
+    }
+
+    /// Check if the next instruction has a breakpoint
+    fn at_breakpoint(&self) -> bool {
+        let location = match self.location() {
+            Some(location) => location,
+            None => return false,
+        };
+        let module = self.engine.context.module();
+        self.breakpoints.contains(&location)
+            || module.bytecode.get(location.offset) == Some(&crate::inst_sys!(BREAKPOINT))
+    }
+}
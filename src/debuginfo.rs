@@ -0,0 +1,86 @@
+//! Optional debug information carried by modules
+//!
+//! Frontend compilers may attach a `DebugInfo` section to the modules they
+//! produce, mapping bytecode offsets back to locations in the source the
+//! module was compiled from, and naming the locals of each symbol. None of
+//! this information is needed to execute a module; it exists purely so that
+//! faults and tooling can talk about the program in terms of its source.
+//!
+//! File and local names are stored as indices into the string table of the
+//! module the debug info belongs to.
+
+/// A mapping from a bytecode offset to a source location
+///
+/// The location applies to every instruction from `offset` up to the offset of
+/// the next entry in the line table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineEntry {
+    /// The bytecode offset this entry starts at
+    pub offset: u32,
+    /// The index within the module string table of the source file name
+    pub file_id: u32,
+    /// The 1-based line within the source file
+    pub line: u32,
+    /// The 1-based column within the line, or 0 if unknown
+    pub column: u32,
+}
+
+/// The name of a local variable of a symbol
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LocalName {
+    /// The local symbol the variable belongs to
+    pub symbol_id: u32,
+    /// The index of the local within the frame of the symbol
+    pub index: u32,
+    /// The index within the module string table of the variable name
+    pub name_id: u32,
+}
+
+/// A resolved source location
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceLocation<'a> {
+    /// The name of the source file
+    pub file: &'a str,
+    /// The 1-based line within the source file
+    pub line: u32,
+    /// The 1-based column within the line, or 0 if unknown
+    pub column: u32,
+}
+
+impl std::fmt::Display for SourceLocation<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.column == 0 {
+            write!(f, "{}:{}", self.file, self.line)
+        } else {
+            write!(f, "{}:{}:{}", self.file, self.line, self.column)
+        }
+    }
+}
+
+/// The debug information section of a module
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DebugInfo {
+    /// The line table, sorted by bytecode offset
+    pub lines: Vec<LineEntry>,
+    /// The names of symbol locals
+    pub locals: Vec<LocalName>,
+}
+
+impl DebugInfo {
+    /// Find the line table entry covering the given bytecode offset
+    pub fn line_entry(&self, offset: u32) -> Option<&LineEntry> {
+        let index = self.lines.partition_point(|e| e.offset <= offset);
+        if index == 0 {
+            return None;
+        }
+        Some(&self.lines[index - 1])
+    }
+
+    /// Find the string ID of the name of a local of the given symbol
+    pub fn local_name_id(&self, symbol_id: u32, index: u32) -> Option<u32> {
+        self.locals.iter().find(|l| l.symbol_id == symbol_id && l.index == index).map(|l| l.name_id)
+    }
+}
@@ -0,0 +1,203 @@
+//! Problems found in modules, with where they were found
+//!
+//! Checks of a module, such as `verify::diagnose` and `lint::lint`, report
+//! every problem they find as a `Diagnostic` rather than stopping at the
+//! first. Each names the kind of problem, how severe it is, and the symbol,
+//! bytecode offset, or string it was found at, so front-ends may point at the
+//! offending source:
+//!
+//! ```
+//! use std::path::Path;
+//! use tstack::diagnostics::{DiagnosticKind, Severity};
+//!
+//! let mut module =
+//!     tstack::asm::assemble("main", ".export main\n CONST_1\n RETURN", Path::new(".")).unwrap();
+//! module.bytecode.to_mut().insert(1, 0xffff);
+//! let diagnostics = tstack::verify::diagnose(&module);
+//! let diagnostic = diagnostics.iter().next().unwrap();
+//! assert_eq!(diagnostic.kind, DiagnosticKind::BadOpcode);
+//! assert_eq!(diagnostic.severity, Severity::Warning);
+//! assert_eq!(diagnostic.render(&module), "main:1: warning: word 0xffff is not an instruction");
+//! ```
+
+use crate::module::Module;
+
+/// How serious a problem is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Severity {
+    /// The module may be loaded, but is likely not what was meant
+    Warning,
+    /// The module may not be loaded
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// The kind of problem a diagnostic reports
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DiagnosticKind {
+    /// A word of bytecode does not decode to an instruction
+    BadOpcode,
+    /// An instruction divides by a constant zero
+    DivideByZero,
+    /// An instruction refers to data outside of the data segments
+    InvalidDataAccess,
+    /// An instruction refers to a global the module does not have
+    InvalidGlobalAccess,
+    /// A relocation does not cover a value within the bytecode
+    InvalidRelocation,
+    /// A symbol does not have the stack effect its signature declares
+    InvalidStackEffect,
+    /// A name refers to a string the module does not have
+    InvalidString,
+    /// A symbol starts outside of the bytecode
+    InvalidSymbol,
+    /// Instructions can never run
+    UnreachableCode,
+    /// A value is pushed and then popped without being used
+    UnusedValue,
+}
+
+/// A problem found in a module
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostic {
+    /// The kind of problem
+    pub kind: DiagnosticKind,
+    /// How serious the problem is
+    pub severity: Severity,
+    /// A description of the problem
+    pub message: String,
+    /// The ID of the local symbol the problem was found in, if any
+    pub symbol: Option<u32>,
+    /// The offset in the bytecode the problem was found at, if any
+    pub offset: Option<u32>,
+    /// The index of the string the problem concerns, if any
+    pub string: Option<u32>,
+}
+
+impl Diagnostic {
+    /// Create a diagnostic which has no location
+    pub fn new(kind: DiagnosticKind, severity: Severity, message: String) -> Diagnostic {
+        Diagnostic { kind, severity, message, symbol: None, offset: None, string: None }
+    }
+
+    /// Create a diagnostic at an offset in the bytecode
+    pub fn at_offset(
+        kind: DiagnosticKind,
+        severity: Severity,
+        message: String,
+        offset: u32,
+    ) -> Diagnostic {
+        Diagnostic { offset: Some(offset), ..Diagnostic::new(kind, severity, message) }
+    }
+
+    /// Describe the diagnostic, prefixed with where it was found in a module
+    ///
+    /// The location is given as the source location of the offset if the
+    /// module has debug info, and otherwise as the name of the module
+    /// followed by the offset or the name of the symbol, as in `main:12` or
+    /// `main:helper`.
+    pub fn render(&self, module: &Module) -> String {
+        let source = self.offset.and_then(|offset| module.source_location(offset));
+        let location = match (source, self.offset, self.symbol) {
+            (Some(source), _, _) => source.to_string(),
+            (None, Some(offset), _) => format!("{}:{}", module.name, offset),
+            (None, None, Some(id)) => match module.symbol(id).and_then(|s| s.name()) {
+                Some(name) => format!("{}:{}", module.name, name),
+                None => format!("{}:symbol {}", module.name, id),
+            },
+            (None, None, None) => module.name.clone(),
+        };
+        format!("{}: {}", location, self)
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// The problems found in a module, in the order they were found
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostics {
+    entries: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    /// Create an empty list of diagnostics
+    pub fn new() -> Diagnostics {
+        Diagnostics::default()
+    }
+
+    /// Add a diagnostic to the list
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.entries.push(diagnostic);
+    }
+
+    /// Get the number of diagnostics
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if no problems were found
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over the diagnostics
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.entries.iter()
+    }
+
+    /// Iterate over the diagnostics which prevent the module being loaded
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.entries.iter().filter(|d| d.severity == Severity::Error)
+    }
+
+    /// Iterate over the diagnostics which do not prevent the module being
+    /// loaded
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.entries.iter().filter(|d| d.severity == Severity::Warning)
+    }
+
+    /// Check if any of the diagnostics are errors
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+}
+
+impl Extend<Diagnostic> for Diagnostics {
+    fn extend<T: IntoIterator<Item = Diagnostic>>(&mut self, iter: T) {
+        self.entries.extend(iter);
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
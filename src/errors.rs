@@ -1,12 +1,120 @@
 //! Definitions of error types the engine can return
 
+use crate::bytecode::Instruction;
+use crate::host::Capability;
+use crate::tags::Tag;
+use crate::Engine;
+
+/// The kind of problem an error code belongs to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorCategory {
+    /// The stack or the calls in progress exceeded a limit, or did not hold
+    /// the values required
+    Stack,
+    /// Bytecode or a module could not be decoded, or is malformed
+    Decode,
+    /// Data, globals, or host objects were accessed which do not exist
+    Memory,
+    /// Modules, symbols, or globals could not be found or linked together
+    Link,
+    /// The host refused or failed an operation, or stopped a run
+    Host,
+    /// An arithmetic operation has no result
+    Arithmetic,
+    /// Bytecode raised a fault of its own
+    User,
+}
+
+impl std::fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ErrorCategory::Stack => write!(f, "stack"),
+            ErrorCategory::Decode => write!(f, "decode"),
+            ErrorCategory::Memory => write!(f, "memory"),
+            ErrorCategory::Link => write!(f, "link"),
+            ErrorCategory::Host => write!(f, "host"),
+            ErrorCategory::Arithmetic => write!(f, "arithmetic"),
+            ErrorCategory::User => write!(f, "user"),
+        }
+    }
+}
+
+/// A stable number identifying a kind of error
+///
+/// Codes are written as `E` followed by four digits, such as `E0101` for
+/// `BytecodeError::StackUnderflow`, and never change meaning once assigned,
+/// so hosts may match and log them without matching every variant. The
+/// first two digits give the category:
+///
+///| Codes   | Category
+///|---------|---------
+///| `E01xx` | `ErrorCategory::Stack`
+///| `E02xx` | `ErrorCategory::Decode`
+///| `E03xx` | `ErrorCategory::Memory`
+///| `E04xx` | `ErrorCategory::Link`
+///| `E05xx` | `ErrorCategory::Host`
+///| `E06xx` | `ErrorCategory::Arithmetic`
+///| `E07xx` | `ErrorCategory::User`
+///
+/// Codes below `xx10` are those of `BytecodeError`, and the rest those of
+/// `ModuleError`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ErrorCode(u16);
+
+impl ErrorCode {
+    /// Get the number of the code, such as `101` for `E0101`
+    pub fn number(self) -> u16 {
+        self.0
+    }
+
+    /// Get the category of the code
+    pub fn category(self) -> ErrorCategory {
+        match self.0 / 100 {
+            1 => ErrorCategory::Stack,
+            2 => ErrorCategory::Decode,
+            3 => ErrorCategory::Memory,
+            4 => ErrorCategory::Link,
+            5 => ErrorCategory::Host,
+            6 => ErrorCategory::Arithmetic,
+            _ => ErrorCategory::User,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "E{:04}", self.0)
+    }
+}
+
 /// Information about a number of required values
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RequiredValues {
     instruction: u16,
     required: u64,
 }
 
+/// Information about a symbol which violated its declared signature
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignatureViolation {
+    symbol: u32,
+    expected: u64,
+    actual: u64,
+}
+
+/// Information about a value consumed with the wrong type tag
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TagMismatch {
+    instruction: u16,
+    expected: Tag,
+    actual: Tag,
+}
+
 /// Instruction fault error type
 ///
 /// This is the error type for instruction faults when the engine is running.
@@ -19,22 +127,84 @@ pub struct RequiredValues {
 /// Currently there is no way for running code to handle faults, though it is
 /// planned to add a signals like interface for registering fault handlers.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BytecodeError {
+    BadInputs(SignatureViolation),
     BadOpcode(u16),
+    BadOutputs(SignatureViolation),
     CodeData(RequiredValues),
+    DeadlineExceeded(u16),
+    DisallowedOpcode(u16),
+    DivideByZero(u16),
+    FrameOverflow(u16),
     InvalidAddress(usize),
+    InvalidData(u64),
+    InvalidGlobal(u32),
+    InvalidHandle(u64),
+    InvalidLocal(u64),
     InvalidModule(u32),
+    InvalidReserve(i64),
+    InvalidRotation(u64),
     InvalidSymbol(u32),
+    NativeError(String),
+    NotANumber(u16),
+    OutOfFuel(u16),
+    OutOfMemory(u16),
+    Overflow(u16),
+    PermissionDenied(Capability),
+    PrivateSymbol(u32),
     StackOverflow(u16),
     StackUnderflow(RequiredValues),
+    TagMismatch(TagMismatch),
+    UnresolvedSymbol(String, String),
+    UnusedValues(SignatureViolation),
+    UserFault { code: u64, message: String, values: Vec<u64> },
+}
+
+/// The symbol an error refers to, by name if known and otherwise by ID
+struct SymbolName<'a>(u32, Option<(&'a str, &'a str)>);
+
+impl std::fmt::Display for SymbolName<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.1 {
+            Some((module, symbol)) => write!(f, "{} of module {}", symbol, module),
+            None => write!(f, "ID {}", self.0),
+        }
+    }
 }
 
 impl std::fmt::Display for BytecodeError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.write(f, None)
+    }
+}
+
+impl BytecodeError {
+    /// Describe the error, naming the symbol it refers to by the given
+    /// module and symbol names rather than by ID
+    fn write(&self, f: &mut std::fmt::Formatter, name: Option<(&str, &str)>) -> std::fmt::Result {
         match self {
+            BytecodeError::BadInputs(s) => {
+                write!(
+                    f,
+                    "symbol {} requires {} inputs; {} values available",
+                    SymbolName(s.symbol, name),
+                    s.expected,
+                    s.actual
+                )
+            }
             BytecodeError::BadOpcode(v) => {
                 write!(f, "invalid opcode {:#06x}", v)
             }
+            BytecodeError::BadOutputs(s) => {
+                write!(
+                    f,
+                    "symbol {} must return {} values; {} values returned",
+                    SymbolName(s.symbol, name),
+                    s.expected,
+                    s.actual
+                )
+            }
             BytecodeError::CodeData(r) => {
                 write!(
                     f,
@@ -42,15 +212,66 @@ impl std::fmt::Display for BytecodeError {
                     r.instruction, r.required
                 )
             }
+            BytecodeError::DeadlineExceeded(opcode) => {
+                write!(f, "deadline exceeded before opcode {:#06x}", opcode)
+            }
+            BytecodeError::DisallowedOpcode(opcode) => {
+                write!(f, "opcode {:#06x} is not in the instruction set of the engine", opcode)
+            }
+            BytecodeError::DivideByZero(opcode) => {
+                write!(f, "division by zero on opcode {:#06x}", opcode)
+            }
+            BytecodeError::FrameOverflow(i) => {
+                write!(f, "call depth exceeded maximum allowed on opcode {}", i)
+            }
             BytecodeError::InvalidAddress(addr) => {
                 write!(f, "invalid address {}", addr)
             }
+            BytecodeError::InvalidData(index) => {
+                write!(f, "invalid data index {}", index)
+            }
+            BytecodeError::InvalidGlobal(id) => {
+                write!(f, "invalid global ID {}", id)
+            }
+            BytecodeError::InvalidHandle(handle) => {
+                write!(f, "invalid handle {:#x}", handle)
+            }
+            BytecodeError::InvalidLocal(index) => {
+                write!(f, "invalid local index {}", index)
+            }
             BytecodeError::InvalidModule(id) => {
                 write!(f, "invalid module ID {}", id)
             }
+            BytecodeError::InvalidReserve(count) => {
+                write!(f, "invalid number of locals {}", count)
+            }
+            BytecodeError::InvalidRotation(position) => {
+                write!(f, "invalid rotation position {}", position)
+            }
             BytecodeError::InvalidSymbol(id) => {
                 write!(f, "invalid symbol ID {}", id)
             }
+            BytecodeError::NativeError(message) => {
+                write!(f, "native function failed: {}", message)
+            }
+            BytecodeError::NotANumber(opcode) => {
+                write!(f, "opcode {:#06x} produced NaN", opcode)
+            }
+            BytecodeError::OutOfFuel(opcode) => {
+                write!(f, "out of fuel before opcode {:#06x}", opcode)
+            }
+            BytecodeError::OutOfMemory(opcode) => {
+                write!(f, "out of memory on opcode {:#06x}", opcode)
+            }
+            BytecodeError::Overflow(opcode) => {
+                write!(f, "arithmetic overflow on opcode {:#06x}", opcode)
+            }
+            BytecodeError::PermissionDenied(capability) => {
+                write!(f, "permission denied: {} is not allowed", capability)
+            }
+            BytecodeError::PrivateSymbol(id) => {
+                write!(f, "symbol {} is not exported", SymbolName(*id, name))
+            }
             BytecodeError::StackOverflow(i) => {
                 write!(f, "stack size exceeded maximum allowed on opcode {}", i)
             }
@@ -61,11 +282,85 @@ impl std::fmt::Display for BytecodeError {
                     r.instruction, r.required
                 )
             }
+            BytecodeError::TagMismatch(t) => {
+                write!(
+                    f,
+                    "opcode {:#06x} requires a value tagged {}; found {}",
+                    t.instruction, t.expected, t.actual
+                )
+            }
+            BytecodeError::UnresolvedSymbol(module, symbol) => {
+                write!(f, "symbol {} of module {} is not loaded", symbol, module)
+            }
+            BytecodeError::UnusedValues(s) => {
+                write!(
+                    f,
+                    "symbol {} left {} values it does not declare as outputs",
+                    SymbolName(s.symbol, name),
+                    s.actual
+                )
+            }
+            BytecodeError::UserFault { code, message, values } => {
+                write!(f, "fault {}: {}", code, message)?;
+                if !values.is_empty() {
+                    let values: Vec<_> = values.iter().map(u64::to_string).collect();
+                    write!(f, " ({})", values.join(", "))?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
+impl From<String> for BytecodeError {
+    fn from(message: String) -> Self {
+        BytecodeError::NativeError(message)
+    }
+}
+
+impl From<&str> for BytecodeError {
+    fn from(message: &str) -> Self {
+        BytecodeError::NativeError(String::from(message))
+    }
+}
+
 impl BytecodeError {
+    /// Get the stable code of the error; see `ErrorCode`
+    pub fn code(&self) -> ErrorCode {
+        ErrorCode(match self {
+            BytecodeError::StackUnderflow(_) => 101,
+            BytecodeError::StackOverflow(_) => 102,
+            BytecodeError::FrameOverflow(_) => 103,
+            BytecodeError::BadInputs(_) => 104,
+            BytecodeError::BadOutputs(_) => 105,
+            BytecodeError::InvalidRotation(_) => 106,
+            BytecodeError::UnusedValues(_) => 107,
+            BytecodeError::TagMismatch(_) => 108,
+            BytecodeError::BadOpcode(_) => 201,
+            BytecodeError::CodeData(_) => 202,
+            BytecodeError::InvalidAddress(_) => 203,
+            BytecodeError::InvalidData(_) => 301,
+            BytecodeError::InvalidGlobal(_) => 302,
+            BytecodeError::InvalidHandle(_) => 303,
+            BytecodeError::InvalidLocal(_) => 304,
+            BytecodeError::InvalidReserve(_) => 305,
+            BytecodeError::InvalidModule(_) => 401,
+            BytecodeError::InvalidSymbol(_) => 402,
+            BytecodeError::PrivateSymbol(_) => 403,
+            BytecodeError::UnresolvedSymbol(_, _) => 404,
+            BytecodeError::NativeError(_) => 501,
+            BytecodeError::PermissionDenied(_) => 502,
+            BytecodeError::OutOfFuel(_) => 503,
+            BytecodeError::DeadlineExceeded(_) => 504,
+            BytecodeError::OutOfMemory(_) => 505,
+            BytecodeError::DisallowedOpcode(_) => 506,
+            BytecodeError::DivideByZero(_) => 601,
+            BytecodeError::Overflow(_) => 602,
+            BytecodeError::NotANumber(_) => 603,
+            BytecodeError::UserFault { .. } => 701,
+        })
+    }
+
     /// Create a new BytecodeError::StackOverflow error
     pub fn stack_overflow(opcode: u16) -> BytecodeError {
         BytecodeError::StackOverflow(opcode)
@@ -81,6 +376,26 @@ impl BytecodeError {
         BytecodeError::CodeData(RequiredValues { instruction: opcode, required: req })
     }
 
+    /// Create a new BytecodeError::BadInputs error
+    pub fn bad_inputs(symbol: u32, expected: u64, actual: u64) -> BytecodeError {
+        BytecodeError::BadInputs(SignatureViolation { symbol, expected, actual })
+    }
+
+    /// Create a new BytecodeError::BadOutputs error
+    pub fn bad_outputs(symbol: u32, expected: u64, actual: u64) -> BytecodeError {
+        BytecodeError::BadOutputs(SignatureViolation { symbol, expected, actual })
+    }
+
+    /// Create a new BytecodeError::UnusedValues error
+    pub fn unused_values(symbol: u32, actual: u64) -> BytecodeError {
+        BytecodeError::UnusedValues(SignatureViolation { symbol, expected: 0, actual })
+    }
+
+    /// Create a new BytecodeError::TagMismatch error
+    pub fn tag_mismatch(opcode: u16, expected: Tag, actual: Tag) -> BytecodeError {
+        BytecodeError::TagMismatch(TagMismatch { instruction: opcode, expected, actual })
+    }
+
     /// Check if the BytecodeError is a BytecodeError::StackOverflow instance
     pub fn is_stack_overflow(&self) -> bool {
         if let BytecodeError::StackOverflow(_) = self {
@@ -105,6 +420,22 @@ impl BytecodeError {
         false
     }
 
+    /// Check if the BytecodeError is a BytecodeError::BadInputs instance
+    pub fn is_bad_inputs(&self) -> bool {
+        if let BytecodeError::BadInputs(_) = self {
+            return true;
+        }
+        false
+    }
+
+    /// Check if the BytecodeError is a BytecodeError::BadOutputs instance
+    pub fn is_bad_outputs(&self) -> bool {
+        if let BytecodeError::BadOutputs(_) = self {
+            return true;
+        }
+        false
+    }
+
     /// Check if the BytecodeError is a BytecodeError::BadOpcode instance
     pub fn is_bad_opcode(&self) -> bool {
         if let BytecodeError::BadOpcode(_) = self {
@@ -114,22 +445,515 @@ impl BytecodeError {
     }
 }
 
+/// The number of instructions either side of the faulting one shown by
+/// `Fault::report`
+pub const REPORT_WINDOW: usize = 3;
+
+/// The number of values from the top of the stack shown by `Fault::report`
+pub const REPORT_STACK: usize = 8;
+
+/// The place in the bytecode a fault was raised at
+///
+/// Locations are displayed with their source location where the module has
+/// debug info, as in `main:helper at main.src:12`, and otherwise with their
+/// offset, as in `main:helper at offset 5`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FaultLocation {
+    /// The name of the module
+    pub module: String,
+    /// The name of the symbol the code belongs to, if it has one
+    pub symbol: Option<String>,
+    /// The offset of the instruction in the bytecode of the module
+    pub offset: usize,
+    /// The source location of the instruction, as given by the line table
+    /// of the module, if it has debug info
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub source: Option<String>,
+}
+
+impl std::fmt::Display for FaultLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let symbol = self.symbol.as_deref().unwrap_or("?");
+        match &self.source {
+            Some(source) => write!(f, "{}:{} at {}", self.module, symbol, source),
+            None => write!(f, "{}:{} at offset {}", self.module, symbol, self.offset),
+        }
+    }
+}
+
+/// A copy of the state of an engine when a fault was raised
+///
+/// The snapshot belongs to the fault, so stays as it was after the engine is
+/// reset or runs again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FaultSnapshot {
+    /// The ID of the module running when the fault was raised
+    pub module_id: u32,
+    /// The offset of the faulting instruction in the bytecode of the module,
+    /// or of the entry point if no instruction ran
+    pub offset: usize,
+    /// The stack as the faulting instruction left it, bottom first
+    pub stack: Vec<u64>,
+    /// The locals reserved by the innermost call in progress, by index
+    pub locals: Vec<u64>,
+}
+
+/// A fault which ended a run, with where it was raised
+///
+/// Faults are raised as a `BytecodeError`, by instructions and by native
+/// functions alike; when one ends a run the engine records the instruction
+/// which was running, and the calls in progress which led to it. Faults
+/// raised before any instruction ran, such as a missing input of the entry
+/// point, are located at the entry point, and those naming an entry point
+/// which does not exist have no location.
+///
+/// With the `serde` feature, faults serialize as their error, backtrace, and
+/// snapshot, so hosts may hand them on as structured data rather than as a
+/// `report`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Fault {
+    error: BytecodeError,
+    /// The location of the fault followed by those of the calls leading to
+    /// it, innermost first
+    backtrace: Vec<FaultLocation>,
+    snapshot: Option<Box<FaultSnapshot>>,
+    /// The names of the module and symbol the error refers to by ID
+    symbol: Option<Box<(String, String)>>,
+}
+
+/// The error of a fault, naming the symbol it refers to if known
+struct FaultMessage<'a>(&'a Fault);
+
+impl std::fmt::Display for FaultMessage<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.error.write(f, self.0.symbol())
+    }
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.location() {
+            Some(location) => write!(f, "{} in {}", FaultMessage(self), location),
+            None => write!(f, "{}", FaultMessage(self)),
+        }
+    }
+}
+
+impl From<BytecodeError> for Fault {
+    fn from(error: BytecodeError) -> Self {
+        Fault::with_backtrace(error, Vec::new())
+    }
+}
+
+impl Fault {
+    /// Create a fault raised at a location
+    pub fn new(error: BytecodeError, location: Option<FaultLocation>) -> Fault {
+        Fault::with_backtrace(error, location.into_iter().collect())
+    }
+
+    /// Create a fault with a backtrace, the first location of which is
+    /// where the fault was raised
+    pub fn with_backtrace(error: BytecodeError, backtrace: Vec<FaultLocation>) -> Fault {
+        Fault { error, backtrace, snapshot: None, symbol: None }
+    }
+
+    /// Name the module and symbol the error refers to by ID
+    pub fn with_symbol(mut self, module: String, symbol: String) -> Fault {
+        self.symbol = Some(Box::new((module, symbol)));
+        self
+    }
+
+    /// Get the names of the module and symbol the error refers to by ID, if
+    /// the engine could resolve them
+    ///
+    /// This is the symbol which was called with too few inputs, returned the
+    /// wrong number of outputs, or is not exported; the `Display` form of
+    /// the fault names it in place of its ID.
+    pub fn symbol(&self) -> Option<(&str, &str)> {
+        self.symbol.as_deref().map(|(module, symbol)| (module.as_str(), symbol.as_str()))
+    }
+
+    /// Attach a snapshot of the state of the engine to the fault
+    pub fn with_snapshot(mut self, snapshot: FaultSnapshot) -> Fault {
+        self.snapshot = Some(Box::new(snapshot));
+        self
+    }
+
+    /// Get the state of the engine when the fault was raised, if the engine
+    /// had `Engine::snapshot_faults` set
+    pub fn snapshot(&self) -> Option<&FaultSnapshot> {
+        self.snapshot.as_deref()
+    }
+
+    /// Get the error raised
+    pub fn error(&self) -> &BytecodeError {
+        &self.error
+    }
+
+    /// Describe the error raised, naming the symbol it refers to where the
+    /// `Display` form of the error gives its ID
+    pub fn message(&self) -> String {
+        FaultMessage(self).to_string()
+    }
+
+    /// Get the stable code of the error raised; see `ErrorCode`
+    pub fn code(&self) -> ErrorCode {
+        self.error.code()
+    }
+
+    /// Get the error raised, discarding the location
+    pub fn into_error(self) -> BytecodeError {
+        self.error
+    }
+
+    /// Get where the fault was raised, if known
+    pub fn location(&self) -> Option<&FaultLocation> {
+        self.backtrace.first()
+    }
+
+    /// Get where the fault was raised followed by the calls in progress,
+    /// innermost first
+    ///
+    /// Each call is located at its call instruction. Faults raised by bytecode
+    /// a native function called back into are located at the call to the
+    /// native function, as the calls it made have ended by then.
+    pub fn backtrace(&self) -> &[FaultLocation] {
+        &self.backtrace
+    }
+
+    /// Format the backtrace with one numbered location per line, in the
+    /// manner of a panic backtrace
+    pub fn format_backtrace(&self) -> String {
+        let mut text = String::new();
+        for (index, location) in self.backtrace.iter().enumerate() {
+            text.push_str(&format!("{:>4}: {}\n", index, location));
+        }
+        text
+    }
+
+    /// Describe the fault and the state of an engine it ended a run of, over
+    /// several lines
+    ///
+    /// The report gives the error with its code, the backtrace with source
+    /// locations where the modules have debug info, the instructions within
+    /// `REPORT_WINDOW` of the faulting one in its symbol, the top
+    /// `REPORT_STACK` values of the stack, topmost first, and the locals of
+    /// the innermost call, if it reserved any. The engine must be the one
+    /// which ran; the stack and locals are taken from the snapshot of the
+    /// fault if it has one, and otherwise from the engine as it is now, which
+    /// is only their state at the fault if the engine has not run or been
+    /// reset since.
+    pub fn report(&self, engine: &Engine) -> String {
+        let mut text = format!("error[{}]: {}\n", self.code(), FaultMessage(self));
+        for (index, location) in self.backtrace.iter().enumerate() {
+            let prefix = if index == 0 { "in" } else { "called from" };
+            text.push_str(&format!("  {} {}\n", prefix, location));
+        }
+
+        if let Some(location) = self.location() {
+            if let Some(module) = engine.module(&location.module) {
+                let (start, end) = match module.symbol_at(location.offset as u32) {
+                    Some(symbol) => {
+                        (symbol.offset() as usize, symbol.offset() as usize + symbol.len())
+                    }
+                    None => (0, module.bytecode.len()),
+                };
+                let mut offsets = Vec::new();
+                let mut offset = start;
+                while offset < end.min(module.bytecode.len()) {
+                    offsets.push(offset);
+                    offset += Instruction::decode(&module.bytecode[offset..]).map_or(1, |i| i.1);
+                }
+                if let Some(index) = offsets.iter().position(|&o| o == location.offset) {
+                    let first = index.saturating_sub(REPORT_WINDOW);
+                    let last = (index + REPORT_WINDOW + 1).min(offsets.len());
+                    text.push_str("\ncode:\n");
+                    for &offset in &offsets[first..last] {
+                        let marker = if offset == location.offset { "=>" } else { "  " };
+                        let instruction = module.disassemble(offset);
+                        text.push_str(&format!("  {} {:04}  {}\n", marker, offset, instruction));
+                    }
+                }
+            }
+        }
+
+        let stack = self.snapshot.as_ref().map_or(&engine.stack, |s| &s.stack);
+        match stack.len() {
+            0 => text.push_str("\nstack: empty\n"),
+            len if len > REPORT_STACK => {
+                text.push_str(&format!("\nstack (top {} of {}):\n", REPORT_STACK, len))
+            }
+            len => text.push_str(&format!("\nstack ({}):\n", len)),
+        }
+        for value in stack.iter().rev().take(REPORT_STACK) {
+            text.push_str(&format!("  {:#018x}  {}\n", value, value));
+        }
+
+        let locals = self.snapshot.as_ref().map_or(engine.locals(), |s| &s.locals);
+        if !locals.is_empty() {
+            text.push_str(&format!("\nlocals ({}):\n", locals.len()));
+            for (index, value) in locals.iter().enumerate() {
+                text.push_str(&format!("  {:>4}: {:#018x}  {}\n", index, value, value));
+            }
+        }
+        text
+    }
+}
+
 /// A compound error type for errors when defining modules
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ModuleError {
+    DependencyCycle(Vec<String>),
+    DuplicateSymbol(String),
+    GlobalTypeMismatch(String, String),
+    InvalidDataAccess(u32),
+    InvalidGlobalAccess(u32),
+    InvalidJson(String),
     InvalidName(String),
+    InvalidRelocation(u32),
+    InvalidStackEffect(u32),
+    InvalidString(u32),
+    InvalidSymbol(u32),
     NameCollision(String),
+    NoModules,
+    PrivateGlobal(String, String),
+    PrivateSymbol(String, String),
+    RelocationOverflow(u32),
+    SignatureMismatch(String, String),
+    UnknownModule(String),
+    UnresolvedGlobal(String, String),
+    UnresolvedImports(Vec<(String, ModuleError)>),
+    UnresolvedSymbol(String, String),
+    VerificationFailed(String, String),
+    VersionMismatch(String, String, String),
+}
+
+impl ModuleError {
+    /// Get the stable code of the error; see `ErrorCode`
+    pub fn code(&self) -> ErrorCode {
+        ErrorCode(match self {
+            ModuleError::InvalidDataAccess(_) => 210,
+            ModuleError::InvalidGlobalAccess(_) => 211,
+            ModuleError::InvalidJson(_) => 212,
+            ModuleError::InvalidName(_) => 213,
+            ModuleError::InvalidRelocation(_) => 214,
+            ModuleError::InvalidString(_) => 215,
+            ModuleError::InvalidSymbol(_) => 216,
+            ModuleError::RelocationOverflow(_) => 217,
+            ModuleError::VerificationFailed(_, _) => 218,
+            ModuleError::InvalidStackEffect(_) => 219,
+            ModuleError::DependencyCycle(_) => 410,
+            ModuleError::DuplicateSymbol(_) => 411,
+            ModuleError::GlobalTypeMismatch(_, _) => 412,
+            ModuleError::NameCollision(_) => 413,
+            ModuleError::NoModules => 414,
+            ModuleError::PrivateGlobal(_, _) => 415,
+            ModuleError::PrivateSymbol(_, _) => 416,
+            ModuleError::SignatureMismatch(_, _) => 417,
+            ModuleError::UnknownModule(_) => 418,
+            ModuleError::UnresolvedGlobal(_, _) => 419,
+            ModuleError::UnresolvedImports(_) => 420,
+            ModuleError::UnresolvedSymbol(_, _) => 421,
+            ModuleError::VersionMismatch(_, _, _) => 422,
+        })
+    }
 }
 
 impl std::fmt::Display for ModuleError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
+            ModuleError::DependencyCycle(path) => {
+                write!(f, "dependency cycle {}", path.join(" -> "))
+            }
+            ModuleError::DuplicateSymbol(name) => {
+                write!(f, "symbol {} defined more than once", name)
+            }
+            ModuleError::GlobalTypeMismatch(module, global) => {
+                write!(f, "type of imported global {} does not match module {}", global, module)
+            }
+            ModuleError::InvalidDataAccess(offset) => {
+                write!(f, "instruction at offset {} accesses data outside of its segment", offset)
+            }
+            ModuleError::InvalidGlobalAccess(offset) => {
+                write!(f, "instruction at offset {} accesses an undefined global", offset)
+            }
+            ModuleError::InvalidJson(message) => {
+                write!(f, "invalid module JSON: {}", message)
+            }
             ModuleError::InvalidName(name) => {
                 write!(f, "invalid module name {}", name)
             }
+            ModuleError::InvalidRelocation(offset) => {
+                write!(f, "invalid relocation at offset {}", offset)
+            }
+            ModuleError::InvalidStackEffect(id) => {
+                write!(f, "symbol {} does not have the stack effect it declares", id)
+            }
+            ModuleError::InvalidString(id) => {
+                write!(f, "invalid string ID {}", id)
+            }
+            ModuleError::InvalidSymbol(id) => {
+                write!(f, "invalid symbol ID {}", id)
+            }
             ModuleError::NameCollision(name) => {
                 write!(f, "module {} already defined", name)
             }
+            ModuleError::NoModules => {
+                write!(f, "no modules given")
+            }
+            ModuleError::PrivateGlobal(module, global) => {
+                write!(f, "global {} in module {} is not exported", global, module)
+            }
+            ModuleError::PrivateSymbol(module, symbol) => {
+                write!(f, "symbol {} in module {} is not exported", symbol, module)
+            }
+            ModuleError::RelocationOverflow(offset) => {
+                write!(f, "relocated value at offset {} does not fit", offset)
+            }
+            ModuleError::SignatureMismatch(module, symbol) => {
+                write!(
+                    f,
+                    "signature of imported symbol {} does not match module {}",
+                    symbol, module
+                )
+            }
+            ModuleError::UnknownModule(name) => {
+                write!(f, "module {} is not loaded", name)
+            }
+            ModuleError::UnresolvedGlobal(module, global) => {
+                write!(f, "unresolved global {} in module {}", global, module)
+            }
+            ModuleError::UnresolvedImports(errors) => {
+                write!(f, "{} unresolved imports", errors.len())?;
+                for (module, error) in errors.iter() {
+                    write!(f, "\n  in module {}: {}", module, error)?;
+                }
+                Ok(())
+            }
+            ModuleError::UnresolvedSymbol(module, symbol) => {
+                write!(f, "unresolved symbol {} in module {}", symbol, module)
+            }
+            ModuleError::VerificationFailed(module, message) => {
+                write!(f, "module {} failed verification: {}", module, message)
+            }
+            ModuleError::VersionMismatch(module, req, version) => {
+                write!(
+                    f,
+                    "module {} version {} does not satisfy requirement {}",
+                    module, version, req
+                )
+            }
+        }
+    }
+}
+
+/// An error when building bytecode
+#[derive(Clone, Debug)]
+pub enum BuildError {
+    DuplicateLabel(u32),
+    InvalidLabel(u32),
+    UnboundLabel(u32),
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BuildError::DuplicateLabel(id) => {
+                write!(f, "label {} bound more than once", id)
+            }
+            BuildError::InvalidLabel(id) => {
+                write!(f, "label {} does not belong to this builder", id)
+            }
+            BuildError::UnboundLabel(id) => {
+                write!(f, "label {} is never bound", id)
+            }
+        }
+    }
+}
+
+/// An error when compiling source code into a module
+///
+/// Each variant holds the line of the source the error was found on, and a
+/// description of the offending item.
+#[derive(Clone, Debug)]
+pub enum CompileError {
+    DuplicateName(u32, String),
+    InvalidInclude(u32, String),
+    InvalidSyntax(u32, String),
+    UndefinedName(u32, String),
+    Unsupported(u32, String),
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CompileError::DuplicateName(line, name) => {
+                write!(f, "line {}: {} is defined more than once", line, name)
+            }
+            CompileError::InvalidInclude(line, message) => {
+                write!(f, "line {}: can not include {}", line, message)
+            }
+            CompileError::InvalidSyntax(line, message) => {
+                write!(f, "line {}: invalid syntax: {}", line, message)
+            }
+            CompileError::UndefinedName(line, name) => {
+                write!(f, "line {}: {} is not defined", line, name)
+            }
+            CompileError::Unsupported(line, item) => {
+                write!(f, "line {}: {} is not supported", line, item)
+            }
+        }
+    }
+}
+
+/// An error when reading or writing the binary module format
+#[derive(Clone, Debug)]
+pub enum FormatError {
+    BadMagic,
+    Compression(String),
+    HashMismatch,
+    InvalidUtf8(usize),
+    InvalidValue(usize),
+    Truncated(usize),
+    UnsupportedAbi(u16),
+    UnsupportedCompression(u16),
+    UnsupportedVersion(u16),
+}
+
+impl std::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FormatError::BadMagic => {
+                write!(f, "not a module file")
+            }
+            FormatError::Compression(message) => {
+                write!(f, "compression error: {}", message)
+            }
+            FormatError::HashMismatch => {
+                write!(f, "module content does not match its hash")
+            }
+            FormatError::InvalidUtf8(offset) => {
+                write!(f, "invalid UTF-8 string at offset {}", offset)
+            }
+            FormatError::InvalidValue(offset) => {
+                write!(f, "invalid value at offset {}", offset)
+            }
+            FormatError::Truncated(offset) => {
+                write!(f, "unexpected end of data at offset {}", offset)
+            }
+            FormatError::UnsupportedAbi(version) => {
+                write!(f, "unsupported ABI version {}", version)
+            }
+            FormatError::UnsupportedCompression(method) => {
+                write!(f, "unsupported compression method {}", method)
+            }
+            FormatError::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {}", version)
+            }
         }
     }
 }
@@ -0,0 +1,608 @@
+//! The binary module format
+//!
+//! A module file starts with a fixed header:
+//!
+//!| Field   | Size | Description
+//!|---------|------|------------
+//!| magic   | 4    | The bytes `TSTK`
+//!| version | 2    | The version of the format; currently `5`
+//!| flags   | 2    | Header flags, see below
+//!| abi     | 2    | The ABI version of the bytecode; not present in version `1`
+//!
+//! If the `FLAG_HASH` flag is set, the header is followed by the 32 byte
+//! content hash of the module (see `Module::content_hash`), which is checked
+//! when the module is read. The module sections follow, in order: the name,
+//! version, string table, read-only data, writable data, local symbols,
+//! external symbols, globals, external globals, bytecode, relocations, symbol
+//! lookup table, and debug info. All integers are
+//! stored little endian, and strings are stored as a `u32` byte length followed
+//! by UTF-8 bytes. Bytecode is stored as a `u32` word count followed by the
+//! words, each little endian; operands spanning several words keep the word
+//! order defined in the `bytecode` module, so a module reads the same on every
+//! host regardless of its native byte order.
+//!
+//! The low bits of the flags select the compression applied to the string
+//! table and bytecode sections, which hold the bulk of most modules. A
+//! compressed section is stored as its uncompressed length and its compressed
+//! length, both `u32`, followed by the compressed bytes. Deflate compression
+//! requires the `deflate` feature, and zstd compression the `zstd` feature;
+//! reading or writing a module using a compression method which is not enabled
+//! fails with `FormatError::UnsupportedCompression`.
+//!
+//! # Compatibility
+//!
+//! The format version describes the layout of the file, and the ABI version
+//! the meaning of the bytecode in it. Both are checked before anything else is
+//! decoded, so an artifact which can not be run fails with an explicit error
+//! rather than being misread:
+//!
+//! - Files of any format version from `MIN_FORMAT_VERSION` to `FORMAT_VERSION`
+//!   can be read; other versions fail with `FormatError::UnsupportedVersion`.
+//!   Files are always written with `FORMAT_VERSION`. Version `1` files have
+//!   no ABI field, and are read as ABI version `1`. Files before version `3`
+//!   have no writable data section, and are read with an empty one; files
+//!   before version `4` likewise have no globals or external globals, and
+//!   external symbols in files before version `5` have no alias.
+//! - Modules with any ABI version from `Engine::MIN_ABI_VERSION` to
+//!   `Engine::ABI_VERSION` can be read; other versions fail with
+//!   `FormatError::UnsupportedAbi`. Changes which only add instructions raise
+//!   the ABI version, while changes to the meaning of existing bytecode also
+//!   raise the minimum ABI version.
+
+mod view;
+
+pub use self::view::{ModuleView, Strings, Words};
+
+use crate::errors::FormatError;
+use crate::hash::{self, Digest, Sha256};
+use crate::module::{GlobalType, Module, RelocationKind, Signature};
+use crate::Engine;
+
+/// The bytes every module file starts with
+pub const MAGIC: [u8; 4] = *b"TSTK";
+
+/// The version of the format written by this library
+pub const FORMAT_VERSION: u16 = 5;
+
+/// The oldest version of the format which can be read
+pub const MIN_FORMAT_VERSION: u16 = 1;
+
+/// Header flag bits selecting the compression method
+pub const FLAG_COMPRESSION_MASK: u16 = 0x0003;
+
+/// Header flag set when the header is followed by the content hash
+pub const FLAG_HASH: u16 = 0x0004;
+
+/// The ways the large sections of a module file may be compressed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// Sections are stored as-is
+    None,
+    /// Sections are compressed with deflate
+    Deflate,
+    /// Sections are compressed with zstd
+    Zstd,
+}
+
+impl Compression {
+    /// Get the header flag bits for the compression method
+    pub fn flags(&self) -> u16 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    /// Get the compression method selected by the header flags
+    pub fn from_flags(flags: u16) -> Result<Compression, FormatError> {
+        match flags & FLAG_COMPRESSION_MASK {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Deflate),
+            2 => Ok(Compression::Zstd),
+            other => Err(FormatError::UnsupportedCompression(other)),
+        }
+    }
+
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>, FormatError> {
+        match self {
+            Compression::None => Ok(bytes.to_vec()),
+            #[cfg(feature = "deflate")]
+            Compression::Deflate => {
+                use std::io::Write;
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes).map_err(|e| FormatError::Compression(e.to_string()))?;
+                encoder.finish().map_err(|e| FormatError::Compression(e.to_string()))
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                zstd::bulk::compress(bytes, 0).map_err(|e| FormatError::Compression(e.to_string()))
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(FormatError::UnsupportedCompression(self.flags())),
+        }
+    }
+
+    #[allow(unused_variables)]
+    fn decompress(&self, bytes: &[u8], len: usize) -> Result<Vec<u8>, FormatError> {
+        let out = match self {
+            Compression::None => bytes.to_vec(),
+            #[cfg(feature = "deflate")]
+            Compression::Deflate => {
+                read_section(flate2::read::DeflateDecoder::new(bytes), bytes, len)?
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                let decoder = zstd::stream::read::Decoder::with_buffer(bytes)
+                    .map_err(|e| FormatError::Compression(e.to_string()))?;
+                read_section(decoder, bytes, len)?
+            }
+            #[allow(unreachable_patterns)]
+            _ => return Err(FormatError::UnsupportedCompression(self.flags())),
+        };
+        if out.len() != len {
+            return Err(FormatError::Compression(format!(
+                "expected {} bytes, decompressed {}",
+                len,
+                out.len()
+            )));
+        }
+        Ok(out)
+    }
+}
+
+/// The most deflate expands its input, used to bound the memory set aside for
+/// a compressed section before it has been decompressed
+#[cfg(any(feature = "deflate", feature = "zstd"))]
+const MAX_RATIO: usize = 1032;
+
+/// Decompress a section expected to be `len` bytes, reading at most one byte
+/// more so that a longer section is caught
+///
+/// The length is only trusted as far as the compressed bytes could expand to;
+/// sections which expand further grow as they are read.
+#[cfg(any(feature = "deflate", feature = "zstd"))]
+fn read_section(
+    decoder: impl std::io::Read,
+    bytes: &[u8],
+    len: usize,
+) -> Result<Vec<u8>, FormatError> {
+    use std::io::Read;
+    let mut out = Vec::with_capacity(len.min(bytes.len().saturating_mul(MAX_RATIO)));
+    decoder
+        .take(len as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|e| FormatError::Compression(e.to_string()))?;
+    Ok(out)
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(len as u32);
+    }
+
+    fn string(&mut self, value: &str) {
+        self.len(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn signature(&mut self, signature: &Option<Signature>) {
+        match signature {
+            Some(s) => {
+                self.u8(1);
+                self.u16(s.inputs);
+                self.u16(s.outputs);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    /// Write a section, compressing it if requested
+    fn section(
+        &mut self,
+        compression: Compression,
+        write: impl FnOnce(&mut Writer),
+    ) -> Result<(), FormatError> {
+        if compression == Compression::None {
+            write(self);
+            return Ok(());
+        }
+        let mut section = Writer { bytes: Vec::new() };
+        write(&mut section);
+        let compressed = compression.compress(&section.bytes)?;
+        self.len(section.bytes.len());
+        self.len(compressed.len());
+        self.bytes.extend_from_slice(&compressed);
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    /// The hash of the uncompressed encoding, and the offset it has covered
+    hash: Option<(Sha256, usize)>,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+        if self.bytes.len() - self.offset < len {
+            return Err(FormatError::Truncated(self.offset));
+        }
+        let bytes = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, FormatError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, FormatError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, FormatError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Read a count of items, each taking at least `size` bytes
+    fn count(&mut self, size: usize) -> Result<usize, FormatError> {
+        let offset = self.offset;
+        let count = self.u32()? as usize;
+        // Reject counts which can not fit in the remaining bytes up front, so
+        // a corrupt count does not cause a huge allocation.
+        if count.saturating_mul(size) > self.bytes.len() - self.offset {
+            return Err(FormatError::Truncated(offset));
+        }
+        Ok(count)
+    }
+
+    fn str(&mut self) -> Result<&'a str, FormatError> {
+        let offset = self.offset;
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        std::str::from_utf8(bytes).map_err(|_| FormatError::InvalidUtf8(offset))
+    }
+
+    fn string(&mut self) -> Result<String, FormatError> {
+        self.str().map(String::from)
+    }
+
+    fn flag(&mut self) -> Result<bool, FormatError> {
+        let offset = self.offset;
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(FormatError::InvalidValue(offset)),
+        }
+    }
+
+    fn global_type(&mut self) -> Result<GlobalType, FormatError> {
+        let offset = self.offset;
+        match self.u8()? {
+            0 => Ok(GlobalType::Int),
+            1 => Ok(GlobalType::Float),
+            _ => Err(FormatError::InvalidValue(offset)),
+        }
+    }
+
+    fn signature(&mut self) -> Result<Option<Signature>, FormatError> {
+        if !self.flag()? {
+            return Ok(None);
+        }
+        Ok(Some(Signature { inputs: self.u16()?, outputs: self.u16()? }))
+    }
+
+    /// Read a section, decompressing it if needed
+    fn section<T>(
+        &mut self,
+        compression: Compression,
+        read: impl FnOnce(&mut Reader) -> Result<T, FormatError>,
+    ) -> Result<T, FormatError> {
+        if compression == Compression::None {
+            return read(self);
+        }
+        let start = self.offset;
+        let len = self.u32()? as usize;
+        let stored = self.u32()? as usize;
+        let offset = self.offset;
+        let bytes = compression.decompress(self.take(stored)?, len)?;
+        // The hash covers the section as if it were not compressed
+        if let Some((hash, from)) = &mut self.hash {
+            hash.update(&self.bytes[*from..start]);
+            hash.update(&bytes);
+            *from = self.offset;
+        }
+        let mut section = Reader { bytes: &bytes, offset: 0, hash: None };
+        let value = read(&mut section)?;
+        if section.offset != bytes.len() {
+            return Err(FormatError::InvalidValue(offset));
+        }
+        Ok(value)
+    }
+
+    /// Start hashing the encoding from the current offset
+    fn start_hash(&mut self) {
+        self.hash = Some((Sha256::new(), self.offset));
+    }
+
+    /// Get the hash of the encoding read since `start_hash` was called
+    fn finish_hash(&mut self) -> Option<Digest> {
+        let (mut hash, from) = self.hash.take()?;
+        hash.update(&self.bytes[from..self.offset]);
+        Some(hash.finish())
+    }
+}
+
+fn relocation_kind(kind: RelocationKind) -> u8 {
+    match kind {
+        RelocationKind::Code => 0,
+        RelocationKind::LocalSymbol => 1,
+        RelocationKind::ExternalSymbol => 2,
+        RelocationKind::String => 3,
+        RelocationKind::Data => 4,
+        RelocationKind::WritableData => 5,
+        RelocationKind::Global => 6,
+        RelocationKind::ExternalGlobal => 7,
+    }
+}
+
+fn global_type(ty: GlobalType) -> u8 {
+    match ty {
+        GlobalType::Int => 0,
+        GlobalType::Float => 1,
+    }
+}
+
+/// Write a module in the binary format
+///
+/// The content hash of the module is always included.
+pub fn write(module: &Module, compression: Compression) -> Result<Vec<u8>, FormatError> {
+    let mut w = Writer { bytes: Vec::new() };
+    w.bytes.extend_from_slice(&MAGIC);
+    w.u16(FORMAT_VERSION);
+    w.u16(compression.flags() | FLAG_HASH);
+    w.u16(Engine::ABI_VERSION);
+    w.bytes.extend_from_slice(&module.content_hash());
+    write_sections(&mut w, module, compression)?;
+    Ok(w.bytes)
+}
+
+fn write_sections(
+    w: &mut Writer,
+    module: &Module,
+    compression: Compression,
+) -> Result<(), FormatError> {
+    w.string(&module.name);
+    w.u32(module.version.major);
+    w.u32(module.version.minor);
+    w.u32(module.version.patch);
+    w.section(compression, |w| {
+        w.len(module.strings.len());
+        for index in 0..module.strings.len() {
+            let bytes = module.strings.bytes(index).unwrap();
+            w.len(bytes.len());
+            w.bytes.extend_from_slice(bytes);
+        }
+    })?;
+    w.len(module.data.len());
+    for d in module.data.iter() {
+        w.u64(*d);
+    }
+    w.len(module.writable_data.len());
+    for d in module.writable_data.iter() {
+        w.u64(*d);
+    }
+
+    w.len(module.local_symbols.len());
+    for s in module.local_symbols.iter() {
+        w.u32(s.name_id);
+        w.u32(s.code_offset);
+        w.u8(s.exported as u8);
+        w.signature(&s.signature);
+    }
+    w.len(module.external_symbols.len());
+    for s in module.external_symbols.iter() {
+        w.u32(s.module_name_id);
+        w.u32(s.symbol_name_id);
+        w.signature(&s.signature);
+        match &s.version_req {
+            Some(req) => {
+                w.u8(1);
+                w.string(&req.to_string());
+            }
+            None => w.u8(0),
+        }
+        match s.alias {
+            Some(alias) => {
+                w.u8(1);
+                w.u32(alias);
+            }
+            None => w.u8(0),
+        }
+    }
+    w.len(module.globals.len());
+    for g in module.globals.iter() {
+        w.u32(g.name_id);
+        w.u8(global_type(g.ty));
+        w.u8(g.exported as u8);
+        w.u64(g.initial);
+    }
+    w.len(module.external_globals.len());
+    for g in module.external_globals.iter() {
+        w.u32(g.module_name_id);
+        w.u32(g.global_name_id);
+        w.u8(global_type(g.ty));
+    }
+
+    w.section(compression, |w| {
+        w.len(module.bytecode.len());
+        for word in module.bytecode.iter() {
+            w.u16(*word);
+        }
+    })?;
+    w.len(module.relocations.len());
+    for r in module.relocations.iter() {
+        w.u32(r.offset);
+        w.u8(r.words);
+        w.u8(relocation_kind(r.kind));
+    }
+
+    // Sort the lookup table so the same module always has the same encoding
+    let mut lookup: Vec<_> = module.symbol_lookup.iter().collect();
+    lookup.sort();
+    w.len(lookup.len());
+    for (name, id) in lookup {
+        w.string(name);
+        w.u32(*id);
+    }
+
+    match &module.debug {
+        Some(debug) => {
+            w.u8(1);
+            w.len(debug.lines.len());
+            for l in debug.lines.iter() {
+                w.u32(l.offset);
+                w.u32(l.file_id);
+                w.u32(l.line);
+                w.u32(l.column);
+            }
+            w.len(debug.locals.len());
+            for l in debug.locals.iter() {
+                w.u32(l.symbol_id);
+                w.u32(l.index);
+                w.u32(l.name_id);
+            }
+        }
+        None => w.u8(0),
+    }
+    Ok(())
+}
+
+/// Read a module in the binary format
+///
+/// Strings are checked to be valid UTF-8 as the module uses them rather than
+/// when it is read; see `Strings`.
+pub fn read(bytes: &[u8]) -> Result<Module, FormatError> {
+    Ok(view(bytes)?.to_module())
+}
+
+/// Read a module in the binary format from a buffer which is never freed
+///
+/// The string table and bytecode of the module borrow from `bytes` rather
+/// than being copied when they are uncompressed, and for the bytecode,
+/// suitably aligned. This suits modules embedded in the program or mapped
+/// into memory for the life of the process.
+pub fn read_static(bytes: &'static [u8]) -> Result<Module, FormatError> {
+    Ok(view(bytes)?.into_module())
+}
+
+/// Read a module in the binary format without copying its bulk sections
+///
+/// The string table and bytecode of the returned view borrow from `bytes`
+/// unless they are compressed. Strings are checked to be valid UTF-8 as they
+/// are used rather than when the view is read.
+pub fn view(bytes: &[u8]) -> Result<ModuleView<'_>, FormatError> {
+    ModuleView::parse(bytes)
+}
+
+/// Get the content hash stored in the header of an encoded module, if any
+pub fn stored_hash(bytes: &[u8]) -> Option<Digest> {
+    read_header(bytes).ok()?.hash
+}
+
+/// The header of an encoded module
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    /// The format version of the file
+    pub version: u16,
+    /// The compression applied to the bulk sections
+    pub compression: Compression,
+    /// The ABI version of the bytecode
+    pub abi_version: u16,
+    /// The content hash of the module, if it was stored
+    pub hash: Option<Digest>,
+}
+
+/// Read the header of an encoded module
+///
+/// This checks the format version, but not the ABI version, so tools may
+/// inspect the header of modules which this engine can not run.
+pub fn read_header(bytes: &[u8]) -> Result<Header, FormatError> {
+    let mut r = Reader { bytes, offset: 0, hash: None };
+    header(&mut r)
+}
+
+fn header(r: &mut Reader) -> Result<Header, FormatError> {
+    if r.take(4).map_err(|_| FormatError::BadMagic)? != MAGIC {
+        return Err(FormatError::BadMagic);
+    }
+    let version = r.u16()?;
+    if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&version) {
+        return Err(FormatError::UnsupportedVersion(version));
+    }
+    let flags = r.u16()?;
+    let compression = Compression::from_flags(flags)?;
+    let abi_version = if version >= 2 { r.u16()? } else { 1 };
+    let hash = if flags & FLAG_HASH != 0 { Some(r.take(32)?.try_into().unwrap()) } else { None };
+    Ok(Header { version, compression, abi_version, hash })
+}
+
+impl Module {
+    /// Write the module in the uncompressed binary format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // Writing without compression can not fail
+        write(self, Compression::None).unwrap()
+    }
+
+    /// Compute the content hash of the module
+    ///
+    /// The hash is the SHA-256 digest of the uncompressed encoding of the
+    /// module sections, so it does not depend on how a module file is
+    /// compressed. As the IDs external symbols are linked to are not part of
+    /// the encoding, linking a module does not change its hash.
+    pub fn content_hash(&self) -> Digest {
+        let mut w = Writer { bytes: Vec::new() };
+        // Writing without compression can not fail
+        write_sections(&mut w, self, Compression::None).unwrap();
+        hash::sha256(&w.bytes)
+    }
+
+    /// Read a module in the binary format
+    pub fn from_bytes(bytes: &[u8]) -> Result<Module, FormatError> {
+        read(bytes)
+    }
+
+    /// Read a module in the binary format without copying its bulk sections
+    ///
+    /// See `format::read_static`.
+    pub fn from_static_bytes(bytes: &'static [u8]) -> Result<Module, FormatError> {
+        read_static(bytes)
+    }
+}
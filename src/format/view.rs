@@ -0,0 +1,541 @@
+//! Reading modules without copying their bulk sections
+//!
+//! Large modules are usually dominated by their string table and bytecode. A
+//! `ModuleView` reads the small sections of a module, but leaves the strings
+//! and bytecode in the buffer it was read from, so a module in a memory mapped
+//! file or a static byte array can be inspected without copying it. Sections
+//! which are compressed are necessarily decompressed into owned buffers.
+//!
+//! A view of a buffer which lives for the rest of the program can be turned
+//! into a `Module` with `into_module`, which keeps borrowing the bytecode
+//! when the host is little endian and the section happens to be aligned for
+//! `u16` values. This is how `format::read_static` loads a module without
+//! copying its code.
+//!
+//! Strings are not decoded when the view is read; only their offsets are
+//! recorded, and each string is checked to be valid UTF-8 when it is used. A
+//! module with thousands of strings of which few are ever looked at can be
+//! read without paying for the rest.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use super::{header, Compression, Header, Reader};
+use crate::debuginfo::{DebugInfo, LineEntry, LocalName};
+use crate::errors::FormatError;
+use crate::module::{
+    ExternalGlobal, ExternalSymbol, Global, LocalSymbol, Module, Relocation, RelocationKind,
+};
+use crate::version::{Version, VersionReq};
+use crate::Engine;
+
+/// Bytecode stored as little endian words in a byte buffer
+#[derive(Clone, Debug, PartialEq)]
+pub struct Words<'a> {
+    bytes: Cow<'a, [u8]>,
+}
+
+impl<'a> Words<'a> {
+    /// Get the number of words
+    pub fn len(&self) -> usize {
+        self.bytes.len() / 2
+    }
+
+    /// Check if there are no words
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Get the word at the given index
+    pub fn get(&self, index: usize) -> Option<u16> {
+        let bytes = self.bytes.get(index * 2..index * 2 + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Iterate over the words
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        self.bytes.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    /// Copy the words into a vector
+    pub fn to_vec(&self) -> Vec<u16> {
+        self.iter().collect()
+    }
+
+    /// Get the words as native `u16` values
+    ///
+    /// The words are borrowed from the buffer when their bytes are already
+    /// laid out as native values, and copied otherwise.
+    pub fn into_words(self) -> Cow<'a, [u16]> {
+        if let Cow::Borrowed(bytes) = self.bytes {
+            if cfg!(target_endian = "little") {
+                // SAFETY: every bit pattern is a valid u16, and `align_to`
+                // only puts correctly aligned bytes in the middle slice
+                let (head, words, tail) = unsafe { bytes.align_to::<u16>() };
+                if head.is_empty() && tail.is_empty() {
+                    return Cow::Borrowed(words);
+                }
+            }
+        }
+        Cow::Owned(self.to_vec())
+    }
+}
+
+/// A string table stored as length prefixed UTF-8 strings in a byte buffer
+///
+/// Strings are decoded each time they are accessed, so a string which is not
+/// valid UTF-8 is only reported when it is used. This is the string table of
+/// every `Module`, so modules read from the binary format keep their strings
+/// undecoded too; strings pushed onto a table are appended to its buffer.
+#[derive(Clone, Default)]
+pub struct Strings<'a> {
+    bytes: Cow<'a, [u8]>,
+    /// The offset in `bytes` of the length of each string
+    offsets: Vec<u32>,
+    /// The offset of `bytes` in the buffer it was read from, for errors
+    base: usize,
+}
+
+impl<'a> Strings<'a> {
+    /// Create an empty string table
+    pub fn new() -> Strings<'a> {
+        Strings::default()
+    }
+
+    /// Get the number of strings
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Check if there are no strings
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Check if the strings borrow from the buffer they were read from
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self.bytes, Cow::Borrowed(_))
+    }
+
+    /// Get the string at the given index
+    ///
+    /// Returns `None` if there is no such string, or if it is not valid UTF-8;
+    /// use `decode` to tell the two apart.
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.decode(index)?.ok()
+    }
+
+    /// Get the bytes of the string at the given index without decoding them
+    pub fn bytes(&self, index: usize) -> Option<&[u8]> {
+        let offset = *self.offsets.get(index)? as usize;
+        let len = u32::from_le_bytes(self.bytes[offset..offset + 4].try_into().unwrap());
+        Some(&self.bytes[offset + 4..offset + 4 + len as usize])
+    }
+
+    /// Decode the string at the given index
+    ///
+    /// Returns `None` if there is no such string.
+    pub fn decode(&self, index: usize) -> Option<Result<&str, FormatError>> {
+        let offset = *self.offsets.get(index)? as usize;
+        let bytes = self.bytes(index)?;
+        Some(std::str::from_utf8(bytes).map_err(|_| FormatError::InvalidUtf8(self.base + offset)))
+    }
+
+    /// Iterate over the strings, decoding each
+    pub fn iter(&self) -> impl Iterator<Item = Result<&str, FormatError>> + '_ {
+        (0..self.len()).filter_map(|index| self.decode(index))
+    }
+
+    /// Decode all of the strings into a vector
+    pub fn to_vec(&self) -> Result<Vec<String>, FormatError> {
+        self.iter().map(|s| s.map(String::from)).collect()
+    }
+
+    /// Check if the table holds the given string
+    pub fn contains(&self, string: &str) -> bool {
+        (0..self.len()).any(|index| self.bytes(index) == Some(string.as_bytes()))
+    }
+
+    /// Add a string to the end of the table
+    pub fn push(&mut self, string: impl AsRef<str>) {
+        self.push_bytes(string.as_ref().as_bytes());
+    }
+
+    /// Replace the string at the given index
+    ///
+    /// The new string is appended to the buffer, leaving the bytes of the old
+    /// one unused until the table is written out.
+    ///
+    /// # Panics
+    /// Panics if there is no string at the index.
+    pub fn set(&mut self, index: usize, string: impl AsRef<str>) {
+        assert!(index < self.len(), "no string {} in a table of {}", index, self.len());
+        self.push(string);
+        self.offsets.swap_remove(index);
+    }
+
+    /// Add the strings of another table to the end of this one
+    ///
+    /// The strings are copied without being decoded.
+    pub fn append(&mut self, other: &Strings) {
+        for index in 0..other.len() {
+            self.push_bytes(other.bytes(index).unwrap());
+        }
+    }
+
+    /// Copy the strings out of the buffer they were read from, if borrowed
+    pub fn into_owned(self) -> Strings<'static> {
+        Strings {
+            bytes: Cow::Owned(self.bytes.into_owned()),
+            offsets: self.offsets,
+            base: self.base,
+        }
+    }
+
+    /// Add a string to the end of the table without checking it is UTF-8
+    pub(crate) fn push_bytes(&mut self, string: &[u8]) {
+        let bytes = self.bytes.to_mut();
+        self.offsets.push(bytes.len() as u32);
+        bytes.extend_from_slice(&(string.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(string);
+    }
+}
+
+impl std::ops::Index<usize> for Strings<'_> {
+    type Output = str;
+
+    /// Get the string at the given index
+    ///
+    /// # Panics
+    /// Panics if there is no such string, or if it is not valid UTF-8.
+    fn index(&self, index: usize) -> &str {
+        match self.decode(index) {
+            Some(Ok(string)) => string,
+            Some(Err(error)) => panic!("string {}: {}", index, error),
+            None => panic!("no string {} in a table of {}", index, self.len()),
+        }
+    }
+}
+
+impl PartialEq for Strings<'_> {
+    fn eq(&self, other: &Strings) -> bool {
+        self.len() == other.len() && (0..self.len()).all(|i| self.bytes(i) == other.bytes(i))
+    }
+}
+
+impl<S: AsRef<str>> PartialEq<Vec<S>> for Strings<'_> {
+    fn eq(&self, other: &Vec<S>) -> bool {
+        self.len() == other.len()
+            && other.iter().enumerate().all(|(i, s)| self.bytes(i) == Some(s.as_ref().as_bytes()))
+    }
+}
+
+impl std::fmt::Debug for Strings<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let strings = (0..self.len()).map(|i| String::from_utf8_lossy(self.bytes(i).unwrap()));
+        f.debug_list().entries(strings).finish()
+    }
+}
+
+impl<S: AsRef<str>> Extend<S> for Strings<'_> {
+    fn extend<I: IntoIterator<Item = S>>(&mut self, strings: I) {
+        for string in strings {
+            self.push(string);
+        }
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for Strings<'_> {
+    fn from_iter<I: IntoIterator<Item = S>>(strings: I) -> Self {
+        let mut table = Strings::new();
+        table.extend(strings);
+        table
+    }
+}
+
+impl<S: AsRef<str>> From<Vec<S>> for Strings<'_> {
+    fn from(strings: Vec<S>) -> Self {
+        strings.into_iter().collect()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Strings<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let strings = self.to_vec().map_err(serde::ser::Error::custom)?;
+        strings.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Strings<'_> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<String>::deserialize(deserializer).map(Strings::from)
+    }
+}
+
+/// A module read from a byte buffer
+///
+/// The fields match those of `Module`, except that the name, string table,
+/// and bytecode borrow from the buffer when possible. Use `to_module` to get a
+/// module which can be loaded into an engine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModuleView<'a> {
+    pub name: &'a str,
+    pub version: Version,
+    pub strings: Strings<'a>,
+    pub data: Vec<u64>,
+    pub writable_data: Vec<u64>,
+    pub local_symbols: Vec<LocalSymbol>,
+    pub external_symbols: Vec<ExternalSymbol>,
+    pub globals: Vec<Global>,
+    pub external_globals: Vec<ExternalGlobal>,
+    pub bytecode: Words<'a>,
+    pub relocations: Vec<Relocation>,
+    pub symbol_lookup: HashMap<String, u32>,
+    pub debug: Option<DebugInfo>,
+}
+
+impl<'a> ModuleView<'a> {
+    /// Read a module view from a buffer holding a module in the binary format
+    ///
+    /// If the module has a content hash, it is checked against the contents.
+    /// Modules with an ABI version the engine does not support are rejected.
+    pub fn parse(bytes: &'a [u8]) -> Result<ModuleView<'a>, FormatError> {
+        let mut r = Reader { bytes, offset: 0, hash: None };
+        let Header { version: format_version, compression, abi_version, hash } = header(&mut r)?;
+        if !(Engine::MIN_ABI_VERSION..=Engine::ABI_VERSION).contains(&abi_version) {
+            return Err(FormatError::UnsupportedAbi(abi_version));
+        }
+        if hash.is_some() {
+            r.start_hash();
+        }
+
+        let name = r.str()?;
+        let version = Version::new(r.u32()?, r.u32()?, r.u32()?);
+        let strings = if compression == Compression::None {
+            read_strings(&mut r)?
+        } else {
+            r.section(compression, |r| {
+                let Strings { bytes, offsets, base } = read_strings(r)?;
+                Ok(Strings { bytes: Cow::Owned(bytes.into_owned()), offsets, base })
+            })?
+        };
+        let count = r.count(8)?;
+        let data = (0..count).map(|_| r.u64()).collect::<Result<Vec<_>, _>>()?;
+        let writable_data = if format_version >= 3 {
+            let count = r.count(8)?;
+            (0..count).map(|_| r.u64()).collect::<Result<Vec<_>, _>>()?
+        } else {
+            vec![]
+        };
+
+        let count = r.count(10)?;
+        let mut local_symbols = Vec::with_capacity(count);
+        for _ in 0..count {
+            local_symbols.push(LocalSymbol {
+                name_id: r.u32()?,
+                code_offset: r.u32()?,
+                exported: r.flag()?,
+                signature: r.signature()?,
+            });
+        }
+        let count = r.count(10)?;
+        let mut external_symbols = Vec::with_capacity(count);
+        for _ in 0..count {
+            let module_name_id = r.u32()?;
+            let symbol_name_id = r.u32()?;
+            let signature = r.signature()?;
+            let version_req = if r.flag()? {
+                let offset = r.offset;
+                Some(VersionReq::parse(r.str()?).ok_or(FormatError::InvalidValue(offset))?)
+            } else {
+                None
+            };
+            let alias = if format_version >= 5 && r.flag()? { Some(r.u32()?) } else { None };
+            external_symbols.push(ExternalSymbol {
+                module_name_id,
+                module_id: 0,
+                symbol_name_id,
+                symbol_id: 0,
+                signature,
+                version_req,
+                alias,
+            });
+        }
+
+        let mut globals = vec![];
+        let mut external_globals = vec![];
+        if format_version >= 4 {
+            let count = r.count(14)?;
+            globals.reserve(count);
+            for _ in 0..count {
+                globals.push(Global {
+                    name_id: r.u32()?,
+                    ty: r.global_type()?,
+                    exported: r.flag()?,
+                    initial: r.u64()?,
+                });
+            }
+            let count = r.count(9)?;
+            external_globals.reserve(count);
+            for _ in 0..count {
+                external_globals.push(ExternalGlobal {
+                    module_name_id: r.u32()?,
+                    module_id: 0,
+                    global_name_id: r.u32()?,
+                    global_id: 0,
+                    ty: r.global_type()?,
+                });
+            }
+        }
+
+        let bytecode = if compression == Compression::None {
+            read_words(&mut r)?
+        } else {
+            r.section(compression, |r| {
+                Ok(Words { bytes: Cow::Owned(read_words(r)?.bytes.into_owned()) })
+            })?
+        };
+        let count = r.count(6)?;
+        let mut relocations = Vec::with_capacity(count);
+        for _ in 0..count {
+            let offset = r.u32()?;
+            let words = r.u8()?;
+            let at = r.offset;
+            let kind = match r.u8()? {
+                0 => RelocationKind::Code,
+                1 => RelocationKind::LocalSymbol,
+                2 => RelocationKind::ExternalSymbol,
+                3 => RelocationKind::String,
+                4 => RelocationKind::Data,
+                5 => RelocationKind::WritableData,
+                6 => RelocationKind::Global,
+                7 => RelocationKind::ExternalGlobal,
+                _ => return Err(FormatError::InvalidValue(at)),
+            };
+            relocations.push(Relocation { offset, words, kind });
+        }
+
+        let count = r.count(8)?;
+        let mut symbol_lookup = HashMap::with_capacity(count);
+        for _ in 0..count {
+            let name = r.string()?;
+            symbol_lookup.insert(name, r.u32()?);
+        }
+
+        let debug = if r.flag()? {
+            let count = r.count(16)?;
+            let mut lines = Vec::with_capacity(count);
+            for _ in 0..count {
+                lines.push(LineEntry {
+                    offset: r.u32()?,
+                    file_id: r.u32()?,
+                    line: r.u32()?,
+                    column: r.u32()?,
+                });
+            }
+            let count = r.count(12)?;
+            let mut locals = Vec::with_capacity(count);
+            for _ in 0..count {
+                locals.push(LocalName { symbol_id: r.u32()?, index: r.u32()?, name_id: r.u32()? });
+            }
+            Some(DebugInfo { lines, locals })
+        } else {
+            None
+        };
+        if r.offset != bytes.len() {
+            return Err(FormatError::InvalidValue(r.offset));
+        }
+        if hash.is_some() && r.finish_hash() != hash {
+            return Err(FormatError::HashMismatch);
+        }
+
+        Ok(ModuleView {
+            name,
+            version,
+            strings,
+            data,
+            writable_data,
+            local_symbols,
+            external_symbols,
+            globals,
+            external_globals,
+            bytecode,
+            relocations,
+            symbol_lookup,
+            debug,
+        })
+    }
+
+    /// Get the string with the given ID
+    ///
+    /// Returns `None` if there is no such string, or if it is not valid UTF-8.
+    pub fn string(&self, id: u32) -> Option<&str> {
+        self.strings.get(id as usize)
+    }
+
+    /// Copy the view into an owned module
+    ///
+    /// The string table is copied without being decoded, so strings which are
+    /// not valid UTF-8 are only reported when the module uses them.
+    pub fn to_module(&self) -> Module {
+        Module {
+            name: String::from(self.name),
+            version: self.version,
+            strings: self.strings.clone().into_owned(),
+            data: self.data.clone(),
+            writable_data: self.writable_data.clone(),
+            local_symbols: self.local_symbols.clone(),
+            external_symbols: self.external_symbols.clone(),
+            globals: self.globals.clone(),
+            external_globals: self.external_globals.clone(),
+            bytecode: Cow::Owned(self.bytecode.to_vec()),
+            relocations: self.relocations.clone(),
+            symbol_lookup: self.symbol_lookup.clone(),
+            debug: self.debug.clone(),
+        }
+    }
+}
+
+impl ModuleView<'static> {
+    /// Turn the view into a module, borrowing its bytecode where possible
+    ///
+    /// Unlike `to_module`, the string table is not copied, and the bytecode
+    /// is only copied when it can not be used in place; see
+    /// `Words::into_words`.
+    pub fn into_module(self) -> Module {
+        Module {
+            name: String::from(self.name),
+            version: self.version,
+            strings: self.strings,
+            data: self.data,
+            writable_data: self.writable_data,
+            local_symbols: self.local_symbols,
+            external_symbols: self.external_symbols,
+            globals: self.globals,
+            external_globals: self.external_globals,
+            bytecode: self.bytecode.into_words(),
+            relocations: self.relocations,
+            symbol_lookup: self.symbol_lookup,
+            debug: self.debug,
+        }
+    }
+}
+
+fn read_strings<'a>(r: &mut Reader<'a>) -> Result<Strings<'a>, FormatError> {
+    let count = r.count(4)?;
+    let base = r.offset;
+    let mut offsets = Vec::with_capacity(count);
+    for _ in 0..count {
+        offsets.push((r.offset - base) as u32);
+        let len = r.u32()? as usize;
+        r.take(len)?;
+    }
+    Ok(Strings { bytes: Cow::Borrowed(&r.bytes[base..r.offset]), offsets, base })
+}
+
+fn read_words<'a>(r: &mut Reader<'a>) -> Result<Words<'a>, FormatError> {
+    let count = r.count(2)?;
+    Ok(Words { bytes: Cow::Borrowed(r.take(count * 2)?) })
+}
@@ -0,0 +1,335 @@
+//! A compiler for a small Forth-like language
+//!
+//! As the engine is stack based, Forth maps onto it almost directly, which
+//! makes it a quick way to write test programs. Words are separated by
+//! whitespace; a definition `: name ... ;` compiles the words between the
+//! name and the `;` into an exported symbol, and words outside of definitions
+//! are compiled into an exported symbol named `main`:
+//!
+//! ```text
+//! \ Print the squares of 10 down to 1
+//! : square ( n -- n*n ) dup * ;
+//! : squares ( n -- ) begin dup square . 1 - dup 0= until drop ;
+//! 10 squares
+//! ```
+//!
+//! The built in words are:
+//!
+//!| Words                          | Description
+//!|--------------------------------|------------
+//!| `+ - * / mod negate`           | Signed integer arithmetic
+//!| `= <> < > <= >= 0=`            | Signed comparisons, giving `1` or `0`
+//!| `dup drop swap over rot -rot`  | Stack manipulation
+//!| `. .s`                         | Print the top of the stack, or the whole stack
+//!| `if else then`                 | Run code if the top of the stack is not zero
+//!| `begin until`, `begin again`   | Loop until the top of the stack is not zero, or forever
+//!| `begin while repeat`           | Loop while the top of the stack is not zero
+//!| `exit recurse`                 | Return from, or call, the word being defined
+//!
+//! Numbers are decimal, or hexadecimal when prefixed with `$`. Comments are
+//! written in parentheses, or start with `\` and run to the end of the line.
+//! Unlike standard Forth, words are case sensitive, a word must be defined
+//! before it is used, and true flags are `1` rather than `-1`. Symbols do not
+//! declare a signature, as the stack effect of a word is not checked.
+
+use std::collections::HashMap;
+
+use crate::builder::{self, BytecodeBuilder, Label};
+use crate::bytecode::{jump, Instruction};
+use crate::errors::CompileError;
+use crate::module::{LocalSymbol, Module};
+use crate::optimize;
+
+/// Compile the source of a program into a module with the given name
+pub fn compile(name: &str, source: &str) -> Result<Module, CompileError> {
+    let mut module = Module::new(name);
+    let mut words: HashMap<String, u16> = HashMap::new();
+    let mut main = Definition::new(None);
+    let mut current: Option<(String, Definition, u32)> = None;
+
+    let mut tokens = tokenize(source)?.into_iter();
+    while let Some((word, line)) = tokens.next() {
+        match word.as_str() {
+            ":" => {
+                if current.is_some() {
+                    return Err(syntax(line, "`:` inside a definition"));
+                }
+                let name = match tokens.next() {
+                    Some((name, _)) => name,
+                    None => return Err(syntax(line, "expected a name after `:`")),
+                };
+                if words.contains_key(&name) || builtin(&name) {
+                    return Err(CompileError::DuplicateName(line, name));
+                }
+                let id = module.local_symbols.len() as u16;
+                current = Some((name, Definition::new(Some(id)), line));
+            }
+            ";" => {
+                let (name, definition, _) = match current.take() {
+                    Some(current) => current,
+                    None => return Err(syntax(line, "`;` outside of a definition")),
+                };
+                let code = definition.finish()?;
+                words.insert(name.clone(), module.local_symbols.len() as u16);
+                add_symbol(&mut module, name, code);
+            }
+            _ => {
+                let definition = match &mut current {
+                    Some((_, definition, _)) => definition,
+                    None => &mut main,
+                };
+                definition.word(&word, line, &words)?;
+            }
+        }
+    }
+    if let Some((name, _, line)) = current {
+        return Err(syntax(line, &format!("definition of `{}` is not ended with `;`", name)));
+    }
+    if let Some(line) = main.line {
+        if words.contains_key("main") {
+            return Err(CompileError::DuplicateName(line, String::from("main")));
+        }
+        add_symbol(&mut module, String::from("main"), main.finish()?);
+    }
+    Ok(module)
+}
+
+fn syntax(line: u32, message: &str) -> CompileError {
+    CompileError::InvalidSyntax(line, String::from(message))
+}
+
+/// Add an exported symbol with the given code to the end of the module
+fn add_symbol(module: &mut Module, name: String, code: Vec<u16>) {
+    let offset = module.bytecode.len() as u32;
+    module.strings.push(name);
+    module.local_symbols.push(LocalSymbol {
+        name_id: module.strings.len() as u32 - 1,
+        code_offset: offset,
+        exported: true,
+        signature: None,
+    });
+    for mut relocation in builder::symbol_relocations(&code) {
+        relocation.offset += offset;
+        module.relocations.push(relocation);
+    }
+    module.bytecode.to_mut().extend_from_slice(&code);
+}
+
+/// Split the source into words, each with the line it is on, skipping
+/// comments
+fn tokenize(source: &str) -> Result<Vec<(String, u32)>, CompileError> {
+    let mut tokens = vec![];
+    let mut comment = None;
+    for (index, text) in source.lines().enumerate() {
+        let line = index as u32 + 1;
+        for word in text.split_whitespace() {
+            if comment.is_some() {
+                if word.ends_with(')') {
+                    comment = None;
+                }
+                continue;
+            }
+            match word {
+                "\\" => break,
+                "(" => comment = Some(line),
+                _ => tokens.push((String::from(word), line)),
+            }
+        }
+    }
+    match comment {
+        Some(line) => Err(syntax(line, "unterminated comment")),
+        None => Ok(tokens),
+    }
+}
+
+const CONTROL_WORDS: [&str; 10] =
+    ["if", "else", "then", "begin", "until", "again", "while", "repeat", "exit", "recurse"];
+
+/// Check if a word is built in
+fn builtin(word: &str) -> bool {
+    simple(word).is_some() || comparison(word).is_some() || CONTROL_WORDS.contains(&word)
+}
+
+/// Get the instructions a built in word which does not jump compiles to
+fn simple(word: &str) -> Option<&'static [Instruction]> {
+    let code: &[Instruction] = match word {
+        "+" => &[Instruction::Add],
+        "-" => &[Instruction::Swap1, Instruction::Sub],
+        "*" => &[Instruction::Mul],
+        "/" => &[Instruction::Swap1, Instruction::Idiv],
+        "mod" => &[Instruction::Swap1, Instruction::Imod],
+        "negate" => &[Instruction::Const0, Instruction::Sub],
+        "dup" => &[Instruction::Dupe1],
+        "drop" => &[Instruction::Pop1],
+        "swap" => &[Instruction::Swap1],
+        "over" => &[Instruction::DupeC(2), Instruction::Pop1],
+        "rot" => &[Instruction::Rotate1C(3), Instruction::Rotate1C(3)],
+        "-rot" => &[Instruction::Rotate1C(3)],
+        "." => &[Instruction::PrintI64],
+        ".s" => &[Instruction::PrintStack],
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// Get the jump condition computing a comparison word
+///
+/// The jump conditions compare the topmost value against the one below it,
+/// which is the right hand operand in Forth, so the ordered conditions are
+/// reversed.
+fn comparison(word: &str) -> Option<u8> {
+    let condition = match word {
+        "=" => jump::TYPE_EQ,
+        "<>" => jump::TYPE_NEQ,
+        "<" => jump::TYPE_GTS,
+        ">" => jump::TYPE_LTS,
+        "<=" => jump::TYPE_GES,
+        ">=" => jump::TYPE_LES,
+        "0=" => jump::TYPE_Z,
+        _ => return None,
+    };
+    Some(condition)
+}
+
+fn parse_number(word: &str) -> Option<u64> {
+    let (negative, digits) = match word.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, word),
+    };
+    let value = match digits.strip_prefix('$') {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None if digits.starts_with(|c: char| c.is_ascii_digit()) => digits.parse().ok()?,
+        None => return None,
+    };
+    Some(if negative { value.wrapping_neg() } else { value })
+}
+
+/// An open control structure
+enum Control {
+    /// An `if`, and the label of its `else` branch
+    If(Label),
+    /// An `else`, and the label of its `then`
+    Else(Label),
+    /// A `begin`, and the label of the start of the loop
+    Begin(Label),
+    /// A `while`, and the labels of the start and end of the loop
+    While(Label, Label),
+}
+
+/// The code of a word being defined
+struct Definition {
+    /// The ID of the symbol being defined, if any
+    id: Option<u16>,
+    builder: BytecodeBuilder,
+    /// The line of the first word compiled into the definition
+    line: Option<u32>,
+    control: Vec<(Control, u32)>,
+}
+
+impl Definition {
+    fn new(id: Option<u16>) -> Definition {
+        Definition { id, builder: BytecodeBuilder::new(), line: None, control: vec![] }
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.builder.emit_instruction(instruction);
+    }
+
+    fn bind(&mut self, label: Label) {
+        self.builder.bind(label).unwrap();
+    }
+
+    /// Compile a word
+    fn word(
+        &mut self,
+        word: &str,
+        line: u32,
+        words: &HashMap<String, u16>,
+    ) -> Result<(), CompileError> {
+        self.line.get_or_insert(line);
+        if let Some(code) = simple(word) {
+            for instruction in code.iter() {
+                self.emit(*instruction);
+            }
+            return Ok(());
+        }
+        if let Some(condition) = comparison(word) {
+            self.builder.condition_value(condition);
+            return Ok(());
+        }
+        let mismatched =
+            || syntax(line, &format!("`{}` does not match an open control word", word));
+        match word {
+            "if" => {
+                let otherwise = self.builder.label();
+                self.builder.jump(jump::MODE_RELATIVE, Some(jump::TYPE_Z), otherwise);
+                self.control.push((Control::If(otherwise), line));
+            }
+            "else" => match self.control.pop() {
+                Some((Control::If(otherwise), line)) => {
+                    let end = self.builder.label();
+                    self.builder.jump(jump::MODE_RELATIVE, None, end);
+                    self.bind(otherwise);
+                    self.control.push((Control::Else(end), line));
+                }
+                _ => return Err(mismatched()),
+            },
+            "then" => match self.control.pop() {
+                Some((Control::If(label) | Control::Else(label), _)) => self.bind(label),
+                _ => return Err(mismatched()),
+            },
+            "begin" => {
+                let top = self.builder.label();
+                self.bind(top);
+                self.control.push((Control::Begin(top), line));
+            }
+            "until" | "again" => match self.control.pop() {
+                Some((Control::Begin(top), _)) => {
+                    let condition = if word == "until" { Some(jump::TYPE_Z) } else { None };
+                    self.builder.jump(jump::MODE_RELATIVE, condition, top);
+                }
+                _ => return Err(mismatched()),
+            },
+            "while" => match self.control.pop() {
+                Some((Control::Begin(top), line)) => {
+                    let end = self.builder.label();
+                    self.builder.jump(jump::MODE_RELATIVE, Some(jump::TYPE_Z), end);
+                    self.control.push((Control::While(top, end), line));
+                }
+                _ => return Err(mismatched()),
+            },
+            "repeat" => match self.control.pop() {
+                Some((Control::While(top, end), _)) => {
+                    self.builder.jump(jump::MODE_RELATIVE, None, top);
+                    self.bind(end);
+                }
+                _ => return Err(mismatched()),
+            },
+            "exit" => self.emit(Instruction::Return),
+            "recurse" => match self.id {
+                Some(id) => self.emit(Instruction::CallC(id)),
+                None => return Err(syntax(line, "`recurse` outside of a definition")),
+            },
+            _ => {
+                if let Some(id) = words.get(word) {
+                    self.emit(Instruction::CallC(*id));
+                } else if let Some(value) = parse_number(word) {
+                    self.builder.emit_words(&optimize::encode_constant(value));
+                } else {
+                    return Err(CompileError::UndefinedName(line, String::from(word)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish the definition, producing its bytecode
+    fn finish(mut self) -> Result<Vec<u16>, CompileError> {
+        if let Some((_, line)) = self.control.last() {
+            return Err(syntax(*line, "control word is not closed"));
+        }
+        self.emit(Instruction::Return);
+        // Every label is bound once its control structure is closed
+        Ok(self.builder.build().unwrap())
+    }
+}
@@ -0,0 +1,183 @@
+//! Finding sequences of instructions worth fusing
+//!
+//! A superinstruction does the work of a common sequence of instructions with
+//! a single dispatch. `Sequences` counts the pairs and triples of instructions
+//! which run one after another in real workloads, so the most frequent may be
+//! chosen as candidates for fusion:
+//!
+//! ```
+//! use std::cell::RefCell;
+//! use std::path::Path;
+//! use std::rc::Rc;
+//! use tstack::fusion::Sequences;
+//! use tstack::trace::TraceEvent;
+//!
+//! let module = tstack::asm::assemble(
+//!     "main",
+//!     ".export main\n CONST_1\n CONST_2\n ADD\n CONST_2\n ADD\n RETURN",
+//!     Path::new("."),
+//! )
+//! .unwrap();
+//! let mut engine = tstack::Engine::new();
+//! engine.add_module(Rc::new(module)).unwrap();
+//!
+//! let sequences = Rc::new(RefCell::new(Sequences::new()));
+//! let recorder = Rc::clone(&sequences);
+//! engine.set_trace_hook(move |event: &TraceEvent| recorder.borrow_mut().record(event));
+//! engine.run(0, 0).unwrap();
+//!
+//! let pairs = sequences.borrow().candidates(2);
+//! assert_eq!(pairs[0].to_string(), "CONST_2 + ADD");
+//! assert_eq!(pairs[0].count, 2);
+//! ```
+//!
+//! Sequences never cross the end of a basic block: they end at jumps, calls,
+//! returns, and system instructions, and wherever execution continues
+//! somewhere other than the next instruction. Each candidate gives the
+//! pattern of the peephole optimizer matching it, for a rewrite which
+//! replaces the sequence with its superinstruction.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::bytecode::{groups, Instruction};
+use crate::optimize::{split, Match};
+use crate::trace::TraceEvent;
+
+/// The longest sequences counted
+pub const MAX_LEN: usize = 3;
+
+/// A sequence of instructions and the number of times it ran
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    /// The opcodes of the instructions, in the order they ran
+    pub opcodes: Vec<u16>,
+    /// The number of times the sequence ran
+    pub count: u64,
+}
+
+impl Candidate {
+    /// Get the mnemonics of the instructions
+    pub fn mnemonics(&self) -> Vec<String> {
+        self.opcodes.iter().map(|opcode| mnemonic(*opcode)).collect()
+    }
+
+    /// Get the matches of an optimizer pattern for the sequence
+    pub fn pattern(&self) -> Vec<Match> {
+        self.opcodes.iter().map(|opcode| Match::Opcode(*opcode)).collect()
+    }
+}
+
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mnemonics().join(" + "))
+    }
+}
+
+/// Counts of the sequences of instructions run
+#[derive(Clone, Debug, Default)]
+pub struct Sequences {
+    /// The opcodes of the last instructions run in the current block, oldest
+    /// first
+    recent: Vec<u16>,
+    /// The module and offset of the instruction following the last one run
+    next: Option<(u32, usize)>,
+    counts: HashMap<Vec<u16>, u64>,
+}
+
+impl Sequences {
+    /// Create an empty set of counts
+    pub fn new() -> Sequences {
+        Sequences::default()
+    }
+
+    /// Count an event
+    ///
+    /// An instruction which does not follow the last one run, as after a
+    /// taken jump, starts a new sequence.
+    pub fn record(&mut self, event: &TraceEvent) {
+        match event {
+            TraceEvent::Instruction { module_id, offset, .. } => {
+                if self.next != Some((*module_id, *offset)) {
+                    self.split();
+                }
+                match event.instruction() {
+                    Some(instruction) => {
+                        self.next = Some((*module_id, offset + instruction.size()));
+                        self.push(&instruction);
+                    }
+                    None => self.split(),
+                }
+            }
+            TraceEvent::Call { .. } | TraceEvent::Return { .. } => self.split(),
+        }
+    }
+
+    /// Count the sequences ending with an instruction run after those already
+    /// pushed
+    pub fn push(&mut self, instruction: &Instruction) {
+        let opcode = instruction.opcode();
+        if self.recent.len() == MAX_LEN {
+            self.recent.remove(0);
+        }
+        self.recent.push(opcode);
+        for start in 0..self.recent.len().saturating_sub(1) {
+            let sequence = &self.recent[start..];
+            match self.counts.get_mut(sequence) {
+                Some(count) => *count += 1,
+                None => {
+                    self.counts.insert(sequence.to_vec(), 1);
+                }
+            }
+        }
+
+        let (group, _) = split(opcode);
+        if matches!(group, groups::JUMP | groups::FUNCTION | groups::SYSTEM) {
+            self.split();
+        }
+    }
+
+    /// End the current sequence, so the next instruction pushed starts a new
+    /// one
+    pub fn split(&mut self) {
+        self.recent.clear();
+        self.next = None;
+    }
+
+    /// Add to the count of a sequence, as when merging counts from several
+    /// workloads
+    pub fn add(&mut self, opcodes: &[u16], count: u64) {
+        *self.counts.entry(opcodes.to_vec()).or_insert(0) += count;
+    }
+
+    /// Get the sequences of `len` instructions, most frequent first
+    pub fn candidates(&self, len: usize) -> Vec<Candidate> {
+        let mut candidates: Vec<Candidate> = self
+            .counts
+            .iter()
+            .filter(|(opcodes, _)| opcodes.len() == len)
+            .map(|(opcodes, count)| Candidate { opcodes: opcodes.clone(), count: *count })
+            .collect();
+        candidates.sort_by(|a, b| b.count.cmp(&a.count).then(a.opcodes.cmp(&b.opcodes)));
+        candidates
+    }
+}
+
+/// Get the mnemonic of an opcode, or the opcode in hex if it is not defined
+fn mnemonic(opcode: u16) -> String {
+    match Instruction::decode(&[opcode, 0, 0, 0, 0]) {
+        Ok((instruction, _)) => instruction.mnemonic(),
+        Err(_) => format!("{:#06x}", opcode),
+    }
+}
+
+/// Get the opcode of a mnemonic
+fn opcode(mnemonic: &str) -> Option<u16> {
+    (0..=2).find_map(|n| Instruction::from_parts(mnemonic, &[0; 2][..n])).map(|i| i.opcode())
+}
+
+/// Parse a sequence of mnemonics separated by `+`, as a `Candidate` is
+/// displayed, into opcodes
+pub fn parse_sequence(text: &str) -> Option<Vec<u16>> {
+    text.split('+').map(|mnemonic| opcode(mnemonic.trim())).collect()
+}
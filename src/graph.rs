@@ -0,0 +1,223 @@
+//! The dependency graph between modules
+//!
+//! Each module imports symbols and globals from other modules through its
+//! external symbol and global tables. The `DependencyGraph` collects these
+//! imports by module name, so the
+//! structure of an application can be inspected, visualized, and validated
+//! before it is linked or run.
+
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::errors::ModuleError;
+use crate::module::Module;
+use crate::version::VersionReq;
+
+/// The kind of item imported by a module
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportKind {
+    /// A symbol, from the external symbol table
+    Symbol,
+    /// A global, from the external global table
+    Global,
+}
+
+/// A symbol or global imported by one module from another
+#[derive(Clone, Debug, PartialEq)]
+pub struct Import {
+    /// The kind of the imported item
+    pub kind: ImportKind,
+    /// The name of the imported symbol or global
+    pub symbol: String,
+    /// The version the imported module is required to match, if any
+    pub version_req: Option<VersionReq>,
+}
+
+/// The imports of one module from another
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dependency {
+    /// The name of the importing module
+    pub from: String,
+    /// The name of the imported module
+    pub to: String,
+    /// The imported items, symbols before globals, in the order they are
+    /// first imported
+    pub imports: Vec<Import>,
+}
+
+/// The modules of an application and the dependencies between them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DependencyGraph {
+    /// The names of the modules, in the order they were given
+    pub modules: Vec<String>,
+    /// The dependencies between modules, ordered by importing module
+    pub dependencies: Vec<Dependency>,
+}
+
+impl DependencyGraph {
+    /// Build the dependency graph of the given modules
+    ///
+    /// Imports are collected by name, so the modules do not need to be linked,
+    /// and imports from modules which are not in `modules` are included.
+    pub fn new(modules: &[Rc<Module>]) -> Result<DependencyGraph, ModuleError> {
+        let mut graph = DependencyGraph::default();
+        for module in modules.iter() {
+            graph.modules.push(module.name.clone());
+            let first = graph.dependencies.len();
+            for ext in module.external_symbols.iter() {
+                let import = Import {
+                    kind: ImportKind::Symbol,
+                    symbol: String::from(string(module, ext.symbol_name_id)?),
+                    version_req: ext.version_req.clone(),
+                };
+                graph.add_import(first, module, string(module, ext.module_name_id)?, import);
+            }
+            for ext in module.external_globals.iter() {
+                let import = Import {
+                    kind: ImportKind::Global,
+                    symbol: String::from(string(module, ext.global_name_id)?),
+                    version_req: None,
+                };
+                graph.add_import(first, module, string(module, ext.module_name_id)?, import);
+            }
+        }
+        Ok(graph)
+    }
+
+    /// Add an import of `module` to the dependencies starting at `first`
+    fn add_import(&mut self, first: usize, module: &Module, to: &str, import: Import) {
+        let index = match self.dependencies[first..].iter().position(|d| d.to == to) {
+            Some(index) => first + index,
+            None => {
+                self.dependencies.push(Dependency {
+                    from: module.name.clone(),
+                    to: String::from(to),
+                    imports: vec![],
+                });
+                self.dependencies.len() - 1
+            }
+        };
+        let imports = &mut self.dependencies[index].imports;
+        if !imports.contains(&import) {
+            imports.push(import);
+        }
+    }
+
+    /// Iterate over the dependencies of the named module
+    pub fn dependencies_of<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Dependency> {
+        self.dependencies.iter().filter(move |d| d.from == name)
+    }
+
+    /// Iterate over the dependencies on the named module
+    pub fn dependents_of<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Dependency> {
+        self.dependencies.iter().filter(move |d| d.to == name)
+    }
+
+    /// Get the names of imported modules which are not in the graph
+    pub fn missing_modules(&self) -> Vec<&str> {
+        let mut missing: Vec<&str> = vec![];
+        for dep in self.dependencies.iter() {
+            if !self.modules.contains(&dep.to) && !missing.contains(&dep.to.as_str()) {
+                missing.push(&dep.to);
+            }
+        }
+        missing
+    }
+
+    /// Find a cycle in the dependencies between the modules in the graph
+    ///
+    /// The cycle is returned as the path of module names, starting and ending
+    /// with the same module. Modules importing their own symbols are not
+    /// considered a cycle.
+    pub fn find_cycle(&self) -> Option<Vec<String>> {
+        let mut edges = vec![vec![]; self.modules.len()];
+        for dep in self.dependencies.iter() {
+            let from = self.modules.iter().position(|m| *m == dep.from);
+            let to = self.modules.iter().position(|m| *m == dep.to);
+            if let (Some(from), Some(to)) = (from, to) {
+                edges[from].push(to);
+            }
+        }
+        let cycle = find_cycle(&edges)?;
+        Some(cycle.into_iter().map(|i| self.modules[i].clone()).collect())
+    }
+
+    /// Write the graph in the Graphviz DOT language
+    ///
+    /// Each dependency is an edge labelled with the imported symbols and
+    /// globals.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph modules {\n");
+        for name in self.modules.iter() {
+            writeln!(out, "    {:?};", name).unwrap();
+        }
+        for dep in self.dependencies.iter() {
+            let symbols: Vec<&str> = dep.imports.iter().map(|i| i.symbol.as_str()).collect();
+            writeln!(out, "    {:?} -> {:?} [label={:?}];", dep.from, dep.to, symbols.join(", "))
+                .unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// Find a cycle in a graph given as the list of successors of each node
+///
+/// The cycle is returned as a path of nodes starting and ending with the same
+/// node. Edges from a node to itself are ignored.
+pub(crate) fn find_cycle(edges: &[Vec<usize>]) -> Option<Vec<usize>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        New,
+        Active,
+        Done,
+    }
+
+    let mut state = vec![State::New; edges.len()];
+    for root in 0..edges.len() {
+        if state[root] != State::New {
+            continue;
+        }
+        // Depth first search, keeping the path to the current node and the
+        // index of the next edge to follow from each node on it
+        let mut path = vec![(root, 0)];
+        state[root] = State::Active;
+        while let Some((node, next)) = path.last_mut() {
+            let node = *node;
+            match edges[node].get(*next) {
+                Some(&succ) => {
+                    *next += 1;
+                    if succ == node {
+                        continue;
+                    }
+                    match state[succ] {
+                        State::New => {
+                            state[succ] = State::Active;
+                            path.push((succ, 0));
+                        }
+                        State::Active => {
+                            let start = path.iter().position(|(n, _)| *n == succ).unwrap();
+                            let mut cycle: Vec<usize> =
+                                path[start..].iter().map(|(n, _)| *n).collect();
+                            cycle.push(succ);
+                            return Some(cycle);
+                        }
+                        State::Done => (),
+                    }
+                }
+                None => {
+                    state[node] = State::Done;
+                    path.pop();
+                }
+            }
+        }
+    }
+    None
+}
+
+fn string(module: &Module, id: u32) -> Result<&str, ModuleError> {
+    match module.strings.get(id as usize) {
+        Some(s) => Ok(s),
+        None => Err(ModuleError::InvalidString(id)),
+    }
+}
@@ -0,0 +1,148 @@
+//! Opaque handles to host objects
+//!
+//! Native functions often need to give bytecode access to a host resource,
+//! such as a file or a game entity, which can not be represented as a stack
+//! value. The engine keeps a table of such objects, and bytecode is handed an
+//! opaque `u64` handle to each instead of a pointer.
+//!
+//! A handle packs the index of its slot in the table into the low 32 bits and
+//! the generation of the slot into the high 32 bits. The generation of a slot
+//! changes each time the slot is reused, so a handle which has been removed
+//! never refers to a later object, and `0` is never a valid handle.
+//!
+//! Objects still in the table when it is cleared, such as when the engine
+//! owning it faults, is reset, or is dropped, are released so that guest
+//! code leaking handles can not leak host resources. Releasing an object
+//! drops it, or passes it to the drop hook registered for its type.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/// A slot of the handle table
+struct Slot {
+    generation: u32,
+    value: Option<Box<dyn Any>>,
+}
+
+/// A function releasing an object of a specific type
+type DropHook = Box<dyn Fn(Box<dyn Any>)>;
+
+/// A table of host objects, indexed by handle
+#[derive(Default)]
+pub struct HandleTable {
+    slots: Vec<Slot>,
+    /// The indices of the empty slots
+    free: Vec<u32>,
+    hooks: HashMap<TypeId, DropHook>,
+}
+
+impl HandleTable {
+    /// Create an empty table
+    pub fn new() -> HandleTable {
+        HandleTable { slots: vec![], free: vec![], hooks: HashMap::new() }
+    }
+
+    /// Set the function releasing objects of type `T` when the table is
+    /// cleared
+    ///
+    /// The hook is given ownership of each object of the type which is still
+    /// in the table when it is cleared, replacing any previous hook for the
+    /// type. Objects taken out with `remove` are not passed to the hook.
+    pub fn set_drop_hook<T: Any>(&mut self, hook: impl Fn(T) + 'static) {
+        let hook = move |value: Box<dyn Any>| {
+            // Hooks are only called with values of the type they are set for
+            if let Ok(value) = value.downcast::<T>() {
+                hook(*value);
+            }
+        };
+        self.hooks.insert(TypeId::of::<T>(), Box::new(hook));
+    }
+
+    /// Add an object to the table, returning its handle
+    pub fn insert<T: Any>(&mut self, value: T) -> u64 {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot { generation: 0, value: None });
+                self.slots.len() as u32 - 1
+            }
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.generation = slot.generation.wrapping_add(1).max(1);
+        slot.value = Some(Box::new(value));
+        ((slot.generation as u64) << 32) | index as u64
+    }
+
+    fn slot(&self, handle: u64) -> Option<&Slot> {
+        let slot = self.slots.get((handle & 0xFFFF_FFFF) as usize)?;
+        if slot.generation as u64 == handle >> 32 && slot.value.is_some() {
+            Some(slot)
+        } else {
+            None
+        }
+    }
+
+    /// Check if a handle refers to an object in the table
+    pub fn contains(&self, handle: u64) -> bool {
+        self.slot(handle).is_some()
+    }
+
+    /// Get the object a handle refers to
+    ///
+    /// Returns `None` if the handle is invalid or the object is not a `T`.
+    pub fn get<T: Any>(&self, handle: u64) -> Option<&T> {
+        self.slot(handle)?.value.as_ref()?.downcast_ref()
+    }
+
+    /// Get the object a handle refers to mutably
+    ///
+    /// Returns `None` if the handle is invalid or the object is not a `T`.
+    pub fn get_mut<T: Any>(&mut self, handle: u64) -> Option<&mut T> {
+        self.slot(handle)?;
+        let slot = &mut self.slots[(handle & 0xFFFF_FFFF) as usize];
+        slot.value.as_mut()?.downcast_mut()
+    }
+
+    /// Remove an object from the table, returning it
+    ///
+    /// Returns `None`, leaving the table unchanged, if the handle is invalid
+    /// or the object is not a `T`.
+    pub fn remove<T: Any>(&mut self, handle: u64) -> Option<T> {
+        self.get::<T>(handle)?;
+        let index = (handle & 0xFFFF_FFFF) as u32;
+        let value = self.slots[index as usize].value.take()?;
+        self.free.push(index);
+        value.downcast().ok().map(|value| *value)
+    }
+
+    /// Release every object in the table
+    ///
+    /// Handles to the released objects are no longer valid.
+    pub fn clear(&mut self) {
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if let Some(value) = slot.value.take() {
+                self.free.push(index as u32);
+                match self.hooks.get(&(*value).type_id()) {
+                    Some(hook) => hook(value),
+                    None => drop(value),
+                }
+            }
+        }
+    }
+
+    /// Get the number of objects in the table
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    /// Check if the table is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Drop for HandleTable {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
@@ -0,0 +1,422 @@
+//! Services the engine takes from its host
+//!
+//! The engine does not touch the standard streams of the process, read the
+//! time, or draw random values directly. It reads and writes three standard
+//! streams of its own, and reads the time and random values through the
+//! `Clock` and `RandomSource` traits. Each engine starts with defaults for
+//! the target it is built for, which a host may replace with
+//! `Engine::set_stdin`, `Engine::set_stdout`, `Engine::set_stderr`,
+//! `Engine::set_clock` and `Engine::set_random_source`:
+//!
+//!| Service           | Default                       | On `wasm32` targets
+//!|-------------------|-------------------------------|--------------------
+//!| Standard streams  | The streams of the process    | Input is empty and output is discarded
+//!| `Clock`           | `SystemClock`                 | The clock stays at zero
+//!| `RandomSource`    | `EntropySource`               | The sequence is the same on every run
+//!
+//! The defaults never panic for lack of a platform service, so the engine
+//! runs in the browser as is; a web playground, like a test, would give it
+//! `SharedBuffer`s to feed input and collect output, and a clock reading the
+//! time of the page.
+//!
+//! Tests of time dependent code use a `ManualClock`, which only moves when
+//! the test advances it.
+//!
+//! Besides `EntropySource`, the module provides random sources for runs
+//! which must be repeated exactly: `Xoshiro256` produces the same sequence
+//! for the same seed, `RecordingSource` keeps the values another source
+//! produces, and `ReplaySource` gives recorded values back in order, so a
+//! run which failed with entropy may be replayed under a debugger.
+//!
+//! Which of these services running code may reach is controlled by the
+//! `HostPolicy` of the engine. The policy grants or denies each class of
+//! interaction with the host, given by `Capability`; an instruction or native
+//! function using a denied capability faults with
+//! `BytecodeError::PermissionDenied`. Engines allow everything other than
+//! opening network connections by default, so hosts running untrusted
+//! bytecode should start from `HostPolicy::deny_all` and grant only what the
+//! bytecode needs:
+//!
+//! ```
+//! use tstack::host::{Capability, HostPolicy};
+//!
+//! let mut engine = tstack::Engine::new();
+//! engine.policy = HostPolicy::deny_all().grant(Capability::Clock);
+//! assert!(!engine.policy.allows(Capability::Io));
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::errors::BytecodeError;
+
+/// A class of interaction with the host
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Capability {
+    /// Reading the clock of the engine
+    Clock,
+    /// Printing, and reading or writing files and streams
+    Io,
+    /// Calling functions of native modules
+    Native,
+    /// Opening network connections
+    Network,
+    /// Drawing values from the random source of the engine
+    Random,
+    /// Querying the process the engine runs in, such as its environment
+    Syscall,
+}
+
+impl Capability {
+    /// Every capability
+    pub const ALL: [Capability; 6] = [
+        Capability::Clock,
+        Capability::Io,
+        Capability::Native,
+        Capability::Network,
+        Capability::Random,
+        Capability::Syscall,
+    ];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl std::fmt::Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Capability::Clock => write!(f, "clock"),
+            Capability::Io => write!(f, "I/O"),
+            Capability::Native => write!(f, "native modules"),
+            Capability::Network => write!(f, "network connections"),
+            Capability::Random => write!(f, "random values"),
+            Capability::Syscall => write!(f, "system calls"),
+        }
+    }
+}
+
+/// The capabilities granted to running code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HostPolicy {
+    granted: u8,
+}
+
+impl HostPolicy {
+    /// Create a policy granting every capability
+    pub fn allow_all() -> HostPolicy {
+        Capability::ALL.into_iter().fold(HostPolicy::deny_all(), HostPolicy::grant)
+    }
+
+    /// Create a policy granting no capabilities
+    pub fn deny_all() -> HostPolicy {
+        HostPolicy { granted: 0 }
+    }
+
+    /// Grant a capability
+    pub fn grant(self, capability: Capability) -> HostPolicy {
+        HostPolicy { granted: self.granted | capability.bit() }
+    }
+
+    /// Deny a capability
+    pub fn deny(self, capability: Capability) -> HostPolicy {
+        HostPolicy { granted: self.granted & !capability.bit() }
+    }
+
+    /// Check if a capability is granted
+    pub fn allows(&self, capability: Capability) -> bool {
+        self.granted & capability.bit() != 0
+    }
+
+    /// Check a capability is granted, faulting with
+    /// `BytecodeError::PermissionDenied` if it is not
+    pub fn check(&self, capability: Capability) -> Result<(), BytecodeError> {
+        match self.allows(capability) {
+            true => Ok(()),
+            false => Err(BytecodeError::PermissionDenied(capability)),
+        }
+    }
+}
+
+/// The policy engines start with, granting every capability other than
+/// `Capability::Network`
+impl Default for HostPolicy {
+    fn default() -> Self {
+        HostPolicy::allow_all().deny(Capability::Network)
+    }
+}
+
+/// A monotonic source of time
+pub trait Clock {
+    /// Get the time elapsed since a fixed point, such as the creation of the
+    /// clock
+    fn now(&self) -> Duration;
+}
+
+/// A source of random values
+pub trait RandomSource {
+    /// Get the next random value
+    fn next_u64(&mut self) -> u64;
+}
+
+/// An in-memory stream of bytes
+///
+/// Bytes written to the buffer are appended to it, and reads take bytes from
+/// its start. Clones share the same bytes, so a host may give a buffer to an
+/// engine as a standard stream and keep a clone to feed it input or collect
+/// its output.
+#[derive(Clone, Debug, Default)]
+pub struct SharedBuffer {
+    bytes: Rc<RefCell<VecDeque<u8>>>,
+}
+
+impl SharedBuffer {
+    /// Create an empty buffer
+    pub fn new() -> SharedBuffer {
+        SharedBuffer::default()
+    }
+
+    /// Create a buffer holding the given bytes
+    pub fn with_contents(bytes: &[u8]) -> SharedBuffer {
+        SharedBuffer { bytes: Rc::new(RefCell::new(bytes.iter().copied().collect())) }
+    }
+
+    /// Get a copy of the bytes in the buffer
+    pub fn contents(&self) -> Vec<u8> {
+        self.bytes.borrow().iter().copied().collect()
+    }
+
+    /// Remove and return the bytes in the buffer as text, replacing invalid
+    /// UTF-8
+    pub fn take_string(&self) -> String {
+        let bytes: Vec<u8> = self.bytes.borrow_mut().drain(..).collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Read for SharedBuffer {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.bytes.borrow_mut().read(buf)
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.bytes.borrow_mut().extend(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A clock reading the monotonic clock of the system
+///
+/// The time is measured from the creation of the clock. Targets without a
+/// system clock, such as `wasm32-unknown-unknown`, read zero.
+#[derive(Clone, Copy, Debug)]
+pub struct SystemClock {
+    #[cfg(not(target_arch = "wasm32"))]
+    start: std::time::Instant,
+}
+
+impl SystemClock {
+    /// Create a clock starting at the current time
+    pub fn new() -> SystemClock {
+        SystemClock {
+            #[cfg(not(target_arch = "wasm32"))]
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.start.elapsed();
+        #[cfg(target_arch = "wasm32")]
+        return Duration::ZERO;
+    }
+}
+
+/// A clock which only moves when it is told to
+///
+/// Clones share the same time, so a test may give a clock to an engine and
+/// keep a clone to advance it, for example from a native function, to check
+/// timing code and deadlines without waiting.
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+    time: Rc<Cell<Duration>>,
+}
+
+impl ManualClock {
+    /// Create a clock reading zero
+    pub fn new() -> ManualClock {
+        ManualClock::default()
+    }
+
+    /// Set the time of the clock
+    pub fn set(&self, time: Duration) {
+        self.time.set(time);
+    }
+
+    /// Move the clock forward
+    pub fn advance(&self, by: Duration) {
+        self.time.set(self.time.get().saturating_add(by));
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.time.get()
+    }
+}
+
+/// A random source keyed by the entropy of the operating system
+///
+/// Values are produced by hashing a counter with keys the standard library
+/// draws from the operating system. This is not suitable for cryptography.
+/// Targets without a source of entropy produce the same sequence every run.
+#[derive(Clone, Debug)]
+pub struct EntropySource {
+    keys: RandomState,
+    counter: u64,
+}
+
+impl EntropySource {
+    /// Create a source with new keys
+    pub fn new() -> EntropySource {
+        EntropySource { keys: RandomState::new(), counter: 0 }
+    }
+}
+
+impl Default for EntropySource {
+    fn default() -> Self {
+        EntropySource::new()
+    }
+}
+
+impl RandomSource for EntropySource {
+    fn next_u64(&mut self) -> u64 {
+        let mut hasher = self.keys.build_hasher();
+        hasher.write_u64(self.counter);
+        self.counter = self.counter.wrapping_add(1);
+        hasher.finish()
+    }
+}
+
+/// A random source producing the sequence of a seed
+///
+/// This is the xoshiro256** generator, with its state filled from the seed
+/// by SplitMix64. It is fast and passes common statistical tests, but is not
+/// suitable for cryptography.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Xoshiro256 {
+    state: [u64; 4],
+}
+
+impl Xoshiro256 {
+    /// Create a source producing the sequence of the given seed
+    pub fn new(seed: u64) -> Xoshiro256 {
+        let mut seed = seed;
+        let mut state = [0; 4];
+        for word in state.iter_mut() {
+            seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            *word = z ^ (z >> 31);
+        }
+        Xoshiro256 { state }
+    }
+}
+
+impl RandomSource for Xoshiro256 {
+    fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+}
+
+/// A random source keeping the values drawn from another
+///
+/// Clones share the recorded values, so a host may give the source to an
+/// engine and keep a clone to read what the engine drew, for example to
+/// replay the run with `ReplaySource`.
+#[derive(Clone)]
+pub struct RecordingSource<R> {
+    source: Rc<RefCell<R>>,
+    values: Rc<RefCell<Vec<u64>>>,
+}
+
+impl<R: RandomSource> RecordingSource<R> {
+    /// Create a source recording the values drawn from `source`
+    pub fn new(source: R) -> RecordingSource<R> {
+        RecordingSource {
+            source: Rc::new(RefCell::new(source)),
+            values: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Get a copy of the values drawn so far
+    pub fn values(&self) -> Vec<u64> {
+        self.values.borrow().clone()
+    }
+}
+
+impl<R: RandomSource> RandomSource for RecordingSource<R> {
+    fn next_u64(&mut self) -> u64 {
+        let value = self.source.borrow_mut().next_u64();
+        self.values.borrow_mut().push(value);
+        value
+    }
+}
+
+/// A random source giving back recorded values in order
+///
+/// Once every value has been given, the values are given again from the
+/// start. An empty recording gives zero.
+#[derive(Clone, Debug, Default)]
+pub struct ReplaySource {
+    values: Vec<u64>,
+    next: usize,
+}
+
+impl ReplaySource {
+    /// Create a source giving back the given values
+    pub fn new(values: Vec<u64>) -> ReplaySource {
+        ReplaySource { values, next: 0 }
+    }
+}
+
+impl RandomSource for ReplaySource {
+    fn next_u64(&mut self) -> u64 {
+        if self.values.is_empty() {
+            return 0;
+        }
+        let value = self.values[self.next % self.values.len()];
+        self.next = (self.next + 1) % self.values.len();
+        value
+    }
+}
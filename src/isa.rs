@@ -0,0 +1,136 @@
+//! Profiles of the instructions running code may use
+//!
+//! `HostPolicy` controls which services of the host running code may reach;
+//! an `InstructionSet` controls which instructions it may run at all. An
+//! engine given one faults with `BytecodeError::DisallowedOpcode` at the
+//! first instruction outside of it, so the same modules may be run with
+//! different levels of trust:
+//!
+//! ```
+//! use std::path::Path;
+//! use std::rc::Rc;
+//! use tstack::errors::BytecodeError;
+//! use tstack::isa::InstructionSet;
+//!
+//! let module = tstack::asm::assemble(
+//!     "main",
+//!     ".export main\n CONST_1\n CONST_2\n ADD\n PRINT_U64\n RETURN",
+//!     Path::new("."),
+//! )
+//! .unwrap();
+//! let mut engine = tstack::Engine::new();
+//! engine.add_module(Rc::new(module)).unwrap();
+//! engine.instruction_set = Some(InstructionSet::compute());
+//! let fault = engine.run(0, 0).unwrap_err();
+//! assert!(matches!(fault.error(), BytecodeError::DisallowedOpcode(_)));
+//! assert_eq!(engine.stack, vec![] as Vec<u64>);
+//! ```
+//!
+//! Sets are built from every or no opcode by allowing or denying single
+//! opcodes, as given by `bytecode::op`, or whole groups, as given by
+//! `bytecode::groups`.
+
+use std::fmt;
+
+use crate::bytecode::groups;
+use crate::errors::BytecodeError;
+
+/// The number of words in the bitmap of every 16 bit opcode
+const WORDS: usize = (u16::MAX as usize + 1) / 64;
+
+/// A set of the opcodes running code may use
+#[derive(Clone, PartialEq, Eq)]
+pub struct InstructionSet {
+    allowed: Box<[u64]>,
+}
+
+impl InstructionSet {
+    /// Create a set allowing every opcode
+    pub fn all() -> InstructionSet {
+        InstructionSet { allowed: vec![u64::MAX; WORDS].into_boxed_slice() }
+    }
+
+    /// Create a set allowing no opcodes
+    pub fn none() -> InstructionSet {
+        InstructionSet { allowed: vec![0; WORDS].into_boxed_slice() }
+    }
+
+    /// Create a set for code which only computes, allowing every opcode other
+    /// than the system instructions
+    ///
+    /// Code run with the set may not print, reach the host through system
+    /// instructions, halt, or fault with a message, but may still call
+    /// native functions the `HostPolicy` of the engine allows.
+    pub fn compute() -> InstructionSet {
+        InstructionSet::all().deny_group(groups::SYSTEM)
+    }
+
+    /// Allow an opcode
+    pub fn allow(mut self, opcode: u16) -> InstructionSet {
+        self.allowed[opcode as usize / 64] |= 1 << (opcode % 64);
+        self
+    }
+
+    /// Deny an opcode
+    pub fn deny(mut self, opcode: u16) -> InstructionSet {
+        self.allowed[opcode as usize / 64] &= !(1 << (opcode % 64));
+        self
+    }
+
+    /// Allow every opcode of a group
+    pub fn allow_group(mut self, group: u8) -> InstructionSet {
+        self.group_mut(group).fill(u64::MAX);
+        self
+    }
+
+    /// Deny every opcode of a group
+    pub fn deny_group(mut self, group: u8) -> InstructionSet {
+        self.group_mut(group).fill(0);
+        self
+    }
+
+    fn group_mut(&mut self, group: u8) -> &mut [u64] {
+        // Each group is 256 opcodes, or four words
+        let start = group as usize * 4;
+        &mut self.allowed[start..start + 4]
+    }
+
+    /// Check if an opcode is allowed
+    #[inline]
+    pub fn allows(&self, opcode: u16) -> bool {
+        self.allowed[opcode as usize / 64] & (1 << (opcode % 64)) != 0
+    }
+
+    /// Check an opcode is allowed, faulting with
+    /// `BytecodeError::DisallowedOpcode` if it is not
+    #[inline]
+    pub fn check(&self, opcode: u16) -> Result<(), BytecodeError> {
+        match self.allows(opcode) {
+            true => Ok(()),
+            false => Err(BytecodeError::DisallowedOpcode(opcode)),
+        }
+    }
+
+    /// Get the number of opcodes allowed
+    pub fn len(&self) -> usize {
+        self.allowed.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    /// Check if no opcodes are allowed
+    pub fn is_empty(&self) -> bool {
+        self.allowed.iter().all(|word| *word == 0)
+    }
+}
+
+/// The set engines would start with, allowing every opcode
+impl Default for InstructionSet {
+    fn default() -> Self {
+        InstructionSet::all()
+    }
+}
+
+impl fmt::Debug for InstructionSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InstructionSet").field("allowed", &self.len()).finish()
+    }
+}
@@ -0,0 +1,329 @@
+//! The canonical JSON representation of modules
+//!
+//! Modules are written as a single JSON object with the following fields, in
+//! this order:
+//!
+//!| Field              | Type     | Description
+//!|--------------------|----------|------------
+//!| `name`             | string   | The module name
+//!| `version`          | string   | The module version, as `major.minor.patch`
+//!| `strings`          | array    | The string table
+//!| `data`             | array    | The read-only data segment, as unsigned integers
+//!| `writable_data`    | array    | The initial writable data segment; may be omitted if empty
+//!| `local_symbols`    | array    | Local symbol objects
+//!| `external_symbols` | array    | External symbol objects
+//!| `globals`          | array    | Global objects; may be omitted if empty
+//!| `external_globals` | array    | External global objects; may be omitted if empty
+//!| `bytecode`         | array    | Instruction objects
+//!| `relocations`      | array    | Relocation objects
+//!| `symbol_lookup`    | object   | Symbol names mapped to local symbol IDs, sorted by name
+//!| `debug`            | object   | The debug info section; omitted if the module has none
+//!
+//! Local symbols are objects with the fields `name` (a string ID), `offset`
+//! (the bytecode offset), `exported`, and optionally `signature`, an object
+//! with `inputs` and `outputs` counts.
+//!
+//! External symbols are objects with the fields `module` and `symbol` (string
+//! IDs of the module and symbol names), and optionally `signature`,
+//! `version_req`, a version requirement string, and `alias`, the string ID of
+//! the name the symbol is imported as. The IDs an external symbol is
+//! resolved to are not part of the representation, as they are only known once
+//! the module is linked.
+//!
+//! Globals are objects with the fields `name` (a string ID), `type`, one of
+//! `"Int"` or `"Float"`, `exported`, and `initial`, the initial value as an
+//! unsigned integer; float globals hold the bits of their value. External
+//! globals are objects with the fields `module`, `global`, and `type`.
+//!
+//! Each instruction is an object with an `op` field holding its mnemonic, and
+//! an `args` field holding its inline operands, which is omitted if the
+//! instruction has none; for example `{"op": "CONST_U16", "args": [5]}`. The
+//! targets of relative jumps are signed. Words which do not decode to an
+//! instruction are written as `{"raw": word}`.
+//!
+//! Relocations are objects with the fields `offset`, `words`, and `kind`, one
+//! of `"Code"`, `"LocalSymbol"`, `"ExternalSymbol"`, `"String"`, `"Data"`,
+//! `"WritableData"`, `"Global"`, or `"ExternalGlobal"`.
+//! The debug section has the fields `lines`, holding objects with the fields
+//! `offset`, `file_id`, `line`, and `column`, and `locals`, holding objects
+//! with the fields `symbol_id`, `index`, and `name_id`.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Number;
+
+use crate::bytecode::Instruction;
+use crate::debuginfo::DebugInfo;
+use crate::errors::ModuleError;
+use crate::module::{
+    ExternalGlobal, ExternalSymbol, Global, GlobalType, LocalSymbol, Module, Relocation, Signature,
+};
+use crate::version::{Version, VersionReq};
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonModule {
+    name: String,
+    version: String,
+    strings: Vec<String>,
+    data: Vec<u64>,
+    #[serde(default)]
+    writable_data: Vec<u64>,
+    local_symbols: Vec<JsonLocalSymbol>,
+    external_symbols: Vec<JsonExternalSymbol>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    globals: Vec<JsonGlobal>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    external_globals: Vec<JsonExternalGlobal>,
+    bytecode: Vec<JsonWord>,
+    #[serde(default)]
+    relocations: Vec<Relocation>,
+    #[serde(default)]
+    symbol_lookup: BTreeMap<String, u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    debug: Option<DebugInfo>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonLocalSymbol {
+    name: u32,
+    offset: u32,
+    exported: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<Signature>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonExternalSymbol {
+    module: u32,
+    symbol: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<Signature>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version_req: Option<VersionReq>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    alias: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonGlobal {
+    name: u32,
+    #[serde(rename = "type")]
+    ty: GlobalType,
+    exported: bool,
+    initial: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonExternalGlobal {
+    module: u32,
+    global: u32,
+    #[serde(rename = "type")]
+    ty: GlobalType,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum JsonWord {
+    Instruction {
+        op: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        args: Vec<Number>,
+    },
+    Raw {
+        raw: u16,
+    },
+}
+
+fn to_number(value: i128) -> Number {
+    if value < 0 {
+        Number::from(value as i64)
+    } else {
+        Number::from(value as u64)
+    }
+}
+
+fn from_number(value: &Number) -> Option<i128> {
+    match value.as_u64() {
+        Some(v) => Some(v as i128),
+        None => value.as_i64().map(|v| v as i128),
+    }
+}
+
+impl Module {
+    /// Write the module in its canonical JSON representation
+    ///
+    /// See the `json` module for the schema.
+    pub fn to_json(&self) -> String {
+        let mut bytecode = Vec::new();
+        let mut offset = 0;
+        while offset < self.bytecode.len() {
+            match Instruction::decode(&self.bytecode[offset..]) {
+                Ok((instruction, len)) => {
+                    bytecode.push(JsonWord::Instruction {
+                        op: instruction.mnemonic(),
+                        args: instruction.args().into_iter().map(to_number).collect(),
+                    });
+                    offset += len;
+                }
+                Err(_) => {
+                    bytecode.push(JsonWord::Raw { raw: self.bytecode[offset] });
+                    offset += 1;
+                }
+            }
+        }
+
+        let module = JsonModule {
+            name: self.name.clone(),
+            version: self.version.to_string(),
+            // JSON can only hold valid strings, so any others are replaced
+            strings: (0..self.strings.len())
+                .map(|i| String::from_utf8_lossy(self.strings.bytes(i).unwrap()).into_owned())
+                .collect(),
+            data: self.data.clone(),
+            writable_data: self.writable_data.clone(),
+            local_symbols: self
+                .local_symbols
+                .iter()
+                .map(|s| JsonLocalSymbol {
+                    name: s.name_id,
+                    offset: s.code_offset,
+                    exported: s.exported,
+                    signature: s.signature,
+                })
+                .collect(),
+            external_symbols: self
+                .external_symbols
+                .iter()
+                .map(|s| JsonExternalSymbol {
+                    module: s.module_name_id,
+                    symbol: s.symbol_name_id,
+                    signature: s.signature,
+                    version_req: s.version_req.clone(),
+                    alias: s.alias,
+                })
+                .collect(),
+            globals: self
+                .globals
+                .iter()
+                .map(|g| JsonGlobal {
+                    name: g.name_id,
+                    ty: g.ty,
+                    exported: g.exported,
+                    initial: g.initial,
+                })
+                .collect(),
+            external_globals: self
+                .external_globals
+                .iter()
+                .map(|g| JsonExternalGlobal {
+                    module: g.module_name_id,
+                    global: g.global_name_id,
+                    ty: g.ty,
+                })
+                .collect(),
+            bytecode,
+            relocations: self.relocations.clone(),
+            symbol_lookup: self.symbol_lookup.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+            debug: self.debug.clone(),
+        };
+        // Serializing plain data to a string can not fail
+        serde_json::to_string_pretty(&module).unwrap()
+    }
+
+    /// Read a module from its canonical JSON representation
+    pub fn from_json(text: &str) -> Result<Module, ModuleError> {
+        let module: JsonModule =
+            serde_json::from_str(text).map_err(|e| ModuleError::InvalidJson(e.to_string()))?;
+        let version = match Version::parse(&module.version) {
+            Some(version) => version,
+            None => {
+                return Err(ModuleError::InvalidJson(format!("invalid version {}", module.version)))
+            }
+        };
+
+        let mut bytecode = Vec::new();
+        for (index, word) in module.bytecode.iter().enumerate() {
+            match word {
+                JsonWord::Instruction { op, args } => {
+                    let instruction = args
+                        .iter()
+                        .map(from_number)
+                        .collect::<Option<Vec<_>>>()
+                        .and_then(|args| Instruction::from_parts(op, &args));
+                    match instruction {
+                        Some(instruction) => instruction.encode(&mut bytecode),
+                        None => {
+                            return Err(ModuleError::InvalidJson(format!(
+                                "invalid instruction {} at index {}",
+                                op, index
+                            )))
+                        }
+                    }
+                }
+                JsonWord::Raw { raw } => bytecode.push(*raw),
+            }
+        }
+
+        Ok(Module {
+            name: module.name,
+            version,
+            strings: module.strings.into(),
+            data: module.data,
+            writable_data: module.writable_data,
+            local_symbols: module
+                .local_symbols
+                .into_iter()
+                .map(|s| LocalSymbol {
+                    name_id: s.name,
+                    code_offset: s.offset,
+                    exported: s.exported,
+                    signature: s.signature,
+                })
+                .collect(),
+            external_symbols: module
+                .external_symbols
+                .into_iter()
+                .map(|s| ExternalSymbol {
+                    module_name_id: s.module,
+                    module_id: 0,
+                    symbol_name_id: s.symbol,
+                    symbol_id: 0,
+                    signature: s.signature,
+                    version_req: s.version_req,
+                    alias: s.alias,
+                })
+                .collect(),
+            globals: module
+                .globals
+                .into_iter()
+                .map(|g| Global {
+                    name_id: g.name,
+                    ty: g.ty,
+                    exported: g.exported,
+                    initial: g.initial,
+                })
+                .collect(),
+            external_globals: module
+                .external_globals
+                .into_iter()
+                .map(|g| ExternalGlobal {
+                    module_name_id: g.module,
+                    module_id: 0,
+                    global_name_id: g.global,
+                    global_id: 0,
+                    ty: g.ty,
+                })
+                .collect(),
+            bytecode: bytecode.into(),
+            relocations: module.relocations,
+            symbol_lookup: module.symbol_lookup.into_iter().collect::<HashMap<_, _>>(),
+            debug: module.debug,
+        })
+    }
+}
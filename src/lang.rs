@@ -0,0 +1,720 @@
+//! A compiler for a small infix expression language
+//!
+//! The language is a convenient way to write programs for the engine, and an
+//! executable reference for how common constructs map onto the instruction
+//! set. All values are 64-bit integers. A program is a list of functions:
+//!
+//! ```text
+//! // Functions which are exported may be used as entry points
+//! export fn main() {
+//!     let n = 10;
+//!     let total = 0;
+//!     while n > 0 {
+//!         total = total + square(n);
+//!         n = n - 1;
+//!     }
+//!     if total >= 100 && !(total == 385) {
+//!         print total;
+//!     } else {
+//!         print -1;
+//!     }
+//!     return host.log(total);
+//! }
+//!
+//! fn square(x) {
+//!     return x * x;
+//! }
+//! ```
+//!
+//! Statements are `let` declarations, assignments, `if`/`else`, `while`,
+//! `return`, `print`, and expression statements. Expressions are integer
+//! literals, variables, calls, the unary operators `-` and `!`, and the binary
+//! operators `* / % + - < <= > >= == != && ||`, from the tightest binding to
+//! the loosest, with comparisons and division being signed. `&&` and `||`
+//! only evaluate their right hand side when needed, and evaluate to `0` or
+//! `1`. Comments start with `//` and run to the end of the line.
+//!
+//! Every function becomes a local symbol which takes its parameters from the
+//! stack and leaves a single result, which is `0` if the function ends
+//! without a `return`. Parameters and variables are held in the locals of the
+//! symbol. Calls to a name qualified by a module, such as `host.log(total)`,
+//! become external symbols of the module, and are expected to leave a single
+//! result as well.
+
+use std::collections::HashMap;
+
+use crate::builder::{self, BytecodeBuilder, Label};
+use crate::bytecode::stack::MAX_LOCALS;
+use crate::bytecode::{jump, Instruction};
+use crate::errors::CompileError;
+use crate::module::{ExternalSymbol, LocalSymbol, Module, Signature};
+use crate::optimize;
+
+/// The deepest blocks and expressions may be nested
+///
+/// Long chains of binary operators count as nesting too, since each operator
+/// nests the ones before it.
+const MAX_DEPTH: usize = 128;
+
+const KEYWORDS: [&str; 8] = ["else", "export", "fn", "if", "let", "print", "return", "while"];
+
+/// Compile the source of a program into a module with the given name
+pub fn compile(name: &str, source: &str) -> Result<Module, CompileError> {
+    let mut parser = Parser { tokens: tokenize(source)?, pos: 0, depth: 0 };
+    let mut functions = vec![];
+    while parser.peek() != &Token::End {
+        functions.push(parser.function()?);
+    }
+
+    let mut signatures = HashMap::new();
+    for (id, function) in functions.iter().enumerate() {
+        let entry = (id as u32, function.params.len());
+        if signatures.insert(function.name.clone(), entry).is_some() {
+            return Err(CompileError::DuplicateName(function.line, function.name.clone()));
+        }
+    }
+
+    let mut module = Module::new(name);
+    let mut imports = vec![];
+    for function in functions.iter() {
+        let code = FunctionCompiler::new(&signatures, &mut imports, function)?.compile()?;
+        module.strings.push(function.name.clone());
+        module.local_symbols.push(LocalSymbol {
+            name_id: module.strings.len() as u32 - 1,
+            code_offset: module.bytecode.len() as u32,
+            exported: function.exported,
+            signature: Some(Signature { inputs: function.params.len() as u16, outputs: 1 }),
+        });
+        let offset = module.bytecode.len() as u32;
+        for mut relocation in builder::symbol_relocations(&code) {
+            relocation.offset += offset;
+            module.relocations.push(relocation);
+        }
+        module.bytecode.to_mut().extend_from_slice(&code);
+    }
+    for (from, symbol, args) in imports {
+        module.strings.push(from);
+        module.strings.push(symbol);
+        module.external_symbols.push(ExternalSymbol {
+            module_name_id: module.strings.len() as u32 - 2,
+            module_id: 0,
+            symbol_name_id: module.strings.len() as u32 - 1,
+            symbol_id: 0,
+            signature: Some(Signature { inputs: args as u16, outputs: 1 }),
+            version_req: None,
+            alias: None,
+        });
+    }
+    Ok(module)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Int(u64),
+    Ident(String),
+    Punct(&'static str),
+    End,
+}
+
+const PUNCTUATION: [&str; 22] = [
+    "<=", ">=", "==", "!=", "&&", "||", "(", ")", "{", "}", ",", ";", "=", "+", "-", "*", "/", "%",
+    "<", ">", "!", ".",
+];
+
+/// Split the source into tokens, each with the line it is on
+fn tokenize(source: &str) -> Result<Vec<(Token, u32)>, CompileError> {
+    let mut tokens = vec![];
+    for (index, text) in source.lines().enumerate() {
+        let line = index as u32 + 1;
+        let text = match text.find("//") {
+            Some(comment) => &text[..comment],
+            None => text,
+        };
+        let mut rest = text.trim_start();
+        while !rest.is_empty() {
+            let c = rest.chars().next().unwrap();
+            let len = if c.is_ascii_digit() {
+                let len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+                let digits = &rest[..len];
+                let value = match digits.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => digits.parse(),
+                };
+                match value {
+                    Ok(value) => tokens.push((Token::Int(value), line)),
+                    Err(_) => {
+                        let message = format!("invalid number `{}`", digits);
+                        return Err(CompileError::InvalidSyntax(line, message));
+                    }
+                }
+                len
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                tokens.push((Token::Ident(String::from(&rest[..len])), line));
+                len
+            } else {
+                match PUNCTUATION.iter().find(|p| rest.starts_with(**p)) {
+                    Some(punct) => {
+                        tokens.push((Token::Punct(punct), line));
+                        punct.len()
+                    }
+                    None => {
+                        let message = format!("unexpected character `{}`", c);
+                        return Err(CompileError::InvalidSyntax(line, message));
+                    }
+                }
+            };
+            rest = rest[len..].trim_start();
+        }
+    }
+    let line = source.lines().count().max(1) as u32;
+    tokens.push((Token::End, line));
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Int(u64),
+    Var(String, u32),
+    Call { module: Option<String>, name: String, args: Vec<Expr>, line: u32 },
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Stmt {
+    Let(String, Expr, u32),
+    Assign(String, Expr, u32),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Print(Expr),
+    Expr(Expr),
+}
+
+struct Function {
+    name: String,
+    exported: bool,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+    line: u32,
+}
+
+struct Parser {
+    tokens: Vec<(Token, u32)>,
+    pos: usize,
+    /// How deeply the blocks and expressions being parsed are nested
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> u32 {
+        self.tokens[self.pos].1
+    }
+
+    fn error(&self, expected: &str) -> CompileError {
+        let found = match self.peek() {
+            Token::Int(value) => value.to_string(),
+            Token::Ident(name) => name.clone(),
+            Token::Punct(punct) => String::from(*punct),
+            Token::End => String::from("end of input"),
+        };
+        CompileError::InvalidSyntax(
+            self.line(),
+            format!("expected {}, found `{}`", expected, found),
+        )
+    }
+
+    /// Go one level deeper into a block or expression
+    fn nest(&mut self) -> Result<(), CompileError> {
+        if self.depth == MAX_DEPTH {
+            let message = format!("nesting deeper than {} levels", MAX_DEPTH);
+            return Err(CompileError::Unsupported(self.line(), message));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Consume the given punctuation or keyword if it is next
+    fn accept(&mut self, text: &str) -> bool {
+        let matches = match self.peek() {
+            Token::Punct(punct) => *punct == text,
+            Token::Ident(name) => name == text,
+            _ => false,
+        };
+        if matches {
+            self.pos += 1;
+        }
+        matches
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), CompileError> {
+        if self.accept(text) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("`{}`", text)))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, CompileError> {
+        match self.peek() {
+            Token::Ident(name) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.error("a name")),
+        }
+    }
+
+    fn function(&mut self) -> Result<Function, CompileError> {
+        let line = self.line();
+        let exported = self.accept("export");
+        self.expect("fn")?;
+        let name = self.ident()?;
+        self.expect("(")?;
+        let mut params: Vec<String> = vec![];
+        while !self.accept(")") {
+            if !params.is_empty() {
+                self.expect(",")?;
+            }
+            let param_line = self.line();
+            let param = self.ident()?;
+            if params.contains(&param) {
+                return Err(CompileError::DuplicateName(param_line, param));
+            }
+            params.push(param);
+        }
+        let body = self.block()?;
+        Ok(Function { name, exported, params, body, line })
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect("{")?;
+        self.nest()?;
+        let mut body = vec![];
+        while !self.accept("}") {
+            body.push(self.statement()?);
+        }
+        self.depth -= 1;
+        Ok(body)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, CompileError> {
+        let line = self.line();
+        let stmt = if self.accept("let") {
+            let name = self.ident()?;
+            self.expect("=")?;
+            Stmt::Let(name, self.expr()?, line)
+        } else if self.accept("if") {
+            return self.if_statement();
+        } else if self.accept("while") {
+            let condition = self.expr()?;
+            return Ok(Stmt::While(condition, self.block()?));
+        } else if self.accept("return") {
+            if self.accept(";") {
+                return Ok(Stmt::Return(None));
+            }
+            Stmt::Return(Some(self.expr()?))
+        } else if self.accept("print") {
+            Stmt::Print(self.expr()?)
+        } else if matches!(self.peek(), Token::Ident(_))
+            && self.tokens[self.pos + 1].0 == Token::Punct("=")
+        {
+            let name = self.ident()?;
+            self.expect("=")?;
+            Stmt::Assign(name, self.expr()?, line)
+        } else {
+            Stmt::Expr(self.expr()?)
+        };
+        self.expect(";")?;
+        Ok(stmt)
+    }
+
+    /// Parse an `if` statement following the `if` keyword
+    fn if_statement(&mut self) -> Result<Stmt, CompileError> {
+        let condition = self.expr()?;
+        let then = self.block()?;
+        let otherwise = if !self.accept("else") {
+            vec![]
+        } else if self.accept("if") {
+            self.nest()?;
+            let otherwise = vec![self.if_statement()?];
+            self.depth -= 1;
+            otherwise
+        } else {
+            self.block()?
+        };
+        Ok(Stmt::If(condition, then, otherwise))
+    }
+
+    fn expr(&mut self) -> Result<Expr, CompileError> {
+        self.binary(0)
+    }
+
+    /// Parse a binary expression whose operators bind at least as tightly as
+    /// the given level of `LEVELS`
+    fn binary(&mut self, level: usize) -> Result<Expr, CompileError> {
+        const LEVELS: [&[(&str, BinaryOp)]; 6] = [
+            &[("||", BinaryOp::Or)],
+            &[("&&", BinaryOp::And)],
+            &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+            &[("<=", BinaryOp::Le), (">=", BinaryOp::Ge), ("<", BinaryOp::Lt), (">", BinaryOp::Gt)],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            &[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Rem)],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let depth = self.depth;
+        let mut lhs = self.binary(level + 1)?;
+        'operators: loop {
+            for (text, op) in LEVELS[level].iter() {
+                if self.accept(text) {
+                    self.nest()?;
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'operators;
+                }
+            }
+            self.depth = depth;
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        if self.accept("-") {
+            self.nest()?;
+            let expr = match self.unary()? {
+                Expr::Int(value) => Expr::Int(value.wrapping_neg()),
+                operand => Expr::Neg(Box::new(operand)),
+            };
+            self.depth -= 1;
+            return Ok(expr);
+        }
+        if self.accept("!") {
+            self.nest()?;
+            let expr = Expr::Not(Box::new(self.unary()?));
+            self.depth -= 1;
+            return Ok(expr);
+        }
+        let line = self.line();
+        if let Token::Int(value) = self.peek() {
+            let value = *value;
+            self.pos += 1;
+            return Ok(Expr::Int(value));
+        }
+        if self.accept("(") {
+            self.nest()?;
+            let expr = self.expr()?;
+            self.expect(")")?;
+            self.depth -= 1;
+            return Ok(expr);
+        }
+        let mut path = vec![self.ident()?];
+        while self.accept(".") {
+            path.push(self.ident()?);
+        }
+        if !self.accept("(") {
+            if path.len() > 1 {
+                return Err(self.error("`(`"));
+            }
+            return Ok(Expr::Var(path.pop().unwrap(), line));
+        }
+        self.nest()?;
+        let mut args = vec![];
+        while !self.accept(")") {
+            if !args.is_empty() {
+                self.expect(",")?;
+            }
+            args.push(self.expr()?);
+        }
+        self.depth -= 1;
+        let name = path.pop().unwrap();
+        let module = if path.is_empty() { None } else { Some(path.join(".")) };
+        Ok(Expr::Call { module, name, args, line })
+    }
+}
+
+/// Count the variables declared by a list of statements
+fn count_variables(body: &[Stmt]) -> usize {
+    body.iter()
+        .map(|stmt| match stmt {
+            Stmt::Let(..) => 1,
+            Stmt::If(_, then, otherwise) => count_variables(then) + count_variables(otherwise),
+            Stmt::While(_, body) => count_variables(body),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// The compiler for the body of a single function
+struct FunctionCompiler<'a> {
+    /// The ID and number of parameters of each function, by name
+    signatures: &'a HashMap<String, (u32, usize)>,
+    /// The module, symbol, and number of arguments of each external symbol
+    imports: &'a mut Vec<(String, String, usize)>,
+    function: &'a Function,
+    /// The locals of each nested block, by name
+    scopes: Vec<HashMap<String, u16>>,
+    locals: usize,
+    builder: BytecodeBuilder,
+}
+
+impl<'a> FunctionCompiler<'a> {
+    fn new(
+        signatures: &'a HashMap<String, (u32, usize)>,
+        imports: &'a mut Vec<(String, String, usize)>,
+        function: &'a Function,
+    ) -> Result<FunctionCompiler<'a>, CompileError> {
+        let count = function.params.len() + count_variables(&function.body);
+        if count > MAX_LOCALS {
+            let message = format!("{} parameters and variables", count);
+            return Err(CompileError::Unsupported(function.line, message));
+        }
+        let params = function.params.iter().enumerate().map(|(i, p)| (p.clone(), i as u16));
+        Ok(FunctionCompiler {
+            signatures,
+            imports,
+            function,
+            scopes: vec![params.collect()],
+            locals: function.params.len(),
+            builder: BytecodeBuilder::new(),
+        })
+    }
+
+    fn compile(mut self) -> Result<Vec<u16>, CompileError> {
+        let params = self.function.params.len();
+        let count = params + count_variables(&self.function.body);
+        if count > 0 {
+            self.emit(Instruction::ReserveC(count as u16));
+        }
+        for index in (0..params).rev() {
+            self.emit(Instruction::Set64C(index as u16));
+        }
+        let function = self.function;
+        self.statements(&function.body)?;
+        if !matches!(function.body.last(), Some(Stmt::Return(_))) {
+            self.emit(Instruction::Const0);
+            self.emit(Instruction::Return);
+        }
+        // Every label is bound by the statement which created it
+        Ok(self.builder.build().unwrap())
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.builder.emit_instruction(instruction);
+    }
+
+    fn bind(&mut self, label: Label) {
+        self.builder.bind(label).unwrap();
+    }
+
+    fn variable(&self, name: &str, line: u32) -> Result<u16, CompileError> {
+        match self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            Some(index) => Ok(*index),
+            None => Err(CompileError::UndefinedName(line, String::from(name))),
+        }
+    }
+
+    /// Compile the statements of a nested block
+    fn block(&mut self, body: &[Stmt]) -> Result<(), CompileError> {
+        self.scopes.push(HashMap::new());
+        self.statements(body)?;
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn statements(&mut self, body: &[Stmt]) -> Result<(), CompileError> {
+        for stmt in body.iter() {
+            self.statement(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        match stmt {
+            Stmt::Let(name, value, line) => {
+                self.expr(value)?;
+                let scope = self.scopes.last_mut().unwrap();
+                if scope.contains_key(name) {
+                    return Err(CompileError::DuplicateName(*line, name.clone()));
+                }
+                let index = self.locals as u16;
+                scope.insert(name.clone(), index);
+                self.locals += 1;
+                self.emit(Instruction::Set64C(index));
+            }
+            Stmt::Assign(name, value, line) => {
+                let index = self.variable(name, *line)?;
+                self.expr(value)?;
+                self.emit(Instruction::Set64C(index));
+            }
+            Stmt::If(condition, then, otherwise) => {
+                let (other, end) = (self.builder.label(), self.builder.label());
+                self.expr(condition)?;
+                self.builder.jump(jump::MODE_RELATIVE, Some(jump::TYPE_Z), other);
+                self.block(then)?;
+                if !otherwise.is_empty() {
+                    self.builder.jump(jump::MODE_RELATIVE, None, end);
+                }
+                self.bind(other);
+                self.block(otherwise)?;
+                self.bind(end);
+            }
+            Stmt::While(condition, body) => {
+                let (top, end) = (self.builder.label(), self.builder.label());
+                self.bind(top);
+                self.expr(condition)?;
+                self.builder.jump(jump::MODE_RELATIVE, Some(jump::TYPE_Z), end);
+                self.block(body)?;
+                self.builder.jump(jump::MODE_RELATIVE, None, top);
+                self.bind(end);
+            }
+            Stmt::Return(value) => {
+                match value {
+                    Some(value) => self.expr(value)?,
+                    None => self.emit(Instruction::Const0),
+                }
+                self.emit(Instruction::Return);
+            }
+            Stmt::Print(value) => {
+                self.expr(value)?;
+                self.emit(Instruction::PrintI64);
+            }
+            Stmt::Expr(value) => {
+                self.expr(value)?;
+                self.emit(Instruction::Pop1);
+            }
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<(), CompileError> {
+        match expr {
+            Expr::Int(value) => self.builder.emit_words(&optimize::encode_constant(*value)),
+            Expr::Var(name, line) => {
+                let index = self.variable(name, *line)?;
+                self.emit(Instruction::GetU64C(index));
+            }
+            Expr::Call { module, name, args, line } => self.call(module, name, args, *line)?,
+            Expr::Neg(operand) => {
+                self.expr(operand)?;
+                self.emit(Instruction::Const0);
+                self.emit(Instruction::Sub);
+            }
+            Expr::Not(operand) => {
+                self.expr(operand)?;
+                self.builder.condition_value(jump::TYPE_Z);
+            }
+            Expr::Binary(op @ (BinaryOp::And | BinaryOp::Or), lhs, rhs) => {
+                // Jump to `short` as soon as the result is known
+                let (short, done) = (self.builder.label(), self.builder.label());
+                let (condition, result) = match op {
+                    BinaryOp::And => (jump::TYPE_Z, Instruction::Const0),
+                    _ => (jump::TYPE_NZ, Instruction::Const1),
+                };
+                self.expr(lhs)?;
+                self.builder.jump(jump::MODE_RELATIVE, Some(condition), short);
+                self.expr(rhs)?;
+                self.builder.jump(jump::MODE_RELATIVE, Some(condition), short);
+                self.emit(if *op == BinaryOp::And {
+                    Instruction::Const1
+                } else {
+                    Instruction::Const0
+                });
+                self.builder.jump(jump::MODE_RELATIVE, None, done);
+                self.bind(short);
+                self.emit(result);
+                self.bind(done);
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                self.expr(lhs)?;
+                self.expr(rhs)?;
+                // The right hand side is on top of the stack; the arithmetic
+                // instructions take the top as their left hand side, and the
+                // jump conditions compare the top against the value below it
+                match op {
+                    BinaryOp::Add => self.emit(Instruction::Add),
+                    BinaryOp::Mul => self.emit(Instruction::Mul),
+                    BinaryOp::Sub | BinaryOp::Div | BinaryOp::Rem => {
+                        self.emit(Instruction::Swap1);
+                        self.emit(match op {
+                            BinaryOp::Sub => Instruction::Sub,
+                            BinaryOp::Div => Instruction::Idiv,
+                            _ => Instruction::Imod,
+                        });
+                    }
+                    BinaryOp::Lt => self.builder.condition_value(jump::TYPE_GTS),
+                    BinaryOp::Le => self.builder.condition_value(jump::TYPE_GES),
+                    BinaryOp::Gt => self.builder.condition_value(jump::TYPE_LTS),
+                    BinaryOp::Ge => self.builder.condition_value(jump::TYPE_LES),
+                    BinaryOp::Eq => self.builder.condition_value(jump::TYPE_EQ),
+                    BinaryOp::Ne => self.builder.condition_value(jump::TYPE_NEQ),
+                    BinaryOp::And | BinaryOp::Or => unreachable!(),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn call(
+        &mut self,
+        module: &Option<String>,
+        name: &str,
+        args: &[Expr],
+        line: u32,
+    ) -> Result<(), CompileError> {
+        let (instruction, params) = match module {
+            None => match self.signatures.get(name) {
+                Some((id, params)) => (Instruction::CallC(*id as u16), *params),
+                None => return Err(CompileError::UndefinedName(line, String::from(name))),
+            },
+            Some(module) => {
+                let found = self.imports.iter().position(|(m, s, _)| m == module && s == name);
+                let index = match found {
+                    Some(index) => index,
+                    None => {
+                        self.imports.push((module.clone(), String::from(name), args.len()));
+                        self.imports.len() - 1
+                    }
+                };
+                (Instruction::CallExtC(index as u16), self.imports[index].2)
+            }
+        };
+        if args.len() != params {
+            let message =
+                format!("`{}` takes {} arguments, but is given {}", name, params, args.len());
+            return Err(CompileError::InvalidSyntax(line, message));
+        }
+        for arg in args.iter() {
+            self.expr(arg)?;
+        }
+        self.emit(instruction);
+        Ok(())
+    }
+}
@@ -1,18 +1,136 @@
 #[macro_use]
 mod macros;
 
+pub mod asm;
+mod blocks;
+pub mod builder;
 pub mod bytecode;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod config;
 pub mod context;
+pub mod coverage;
+pub mod debugger;
+pub mod debuginfo;
+pub mod diagnostics;
 pub mod errors;
+pub mod format;
+pub mod forth;
+pub mod fusion;
+pub mod graph;
+pub mod handle;
+pub mod hash;
+pub mod host;
+pub mod isa;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod lang;
+pub mod linker;
+pub mod lint;
+pub mod memory;
+pub mod metrics;
 pub mod module;
+pub mod native;
+pub mod optimize;
+pub mod profile;
+pub mod segment;
+pub mod stream;
+mod symtab;
+pub mod tags;
+pub mod trace;
+pub mod value;
+pub mod verify;
+pub mod version;
+#[cfg(feature = "wasm-frontend")]
+pub mod wasm;
 
-use std::collections::HashMap;
-use std::num::Wrapping;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::time::Duration;
 
-use self::errors::{BytecodeError, ModuleError};
+use self::errors::{BytecodeError, Fault, FaultLocation, FaultSnapshot, ModuleError};
+use blocks::Blocks;
+use bytecode::stack::MAX_LOCALS;
+use bytecode::{groups, jump, op, Instruction};
+use config::{EngineConfig, Overflow, StackGrowth, Strictness, CANONICAL_NAN, CANONICAL_NAN_F32};
 use context::Context;
-use module::Module;
+use graph::DependencyGraph;
+use handle::HandleTable;
+use hash::Digest;
+use host::{Capability, Clock, EntropySource, HostPolicy, RandomSource, SystemClock};
+use isa::InstructionSet;
+use memory::{Memory, MemoryPool};
+use metrics::{Metrics, MetricsSink};
+use module::{LocalSymbol, Module, Signature, Symbol};
+use native::{FromStack, FuncRef, NativeFn, NativeFunction, StackView};
+use segment::Segment;
+use stream::{OpenMode, Preopen, ReadOnly, Stream, StreamBox, WriteOnly};
+use symtab::SymbolTable;
+use tags::Tag;
+use trace::{TraceEvent, TraceHook};
+
+pub use value::Value;
+
+/// A check run on every module before it is loaded into an engine
+///
+/// The verifier is given the module and its content hash, and returns an
+/// error message if the module must not be loaded.
+pub type ModuleVerifier = Box<dyn Fn(&Module, &Digest) -> Result<(), String>>;
+
+/// A hook called when the stack of a run first grows past the soft stack
+/// limit of an engine
+///
+/// The hook is given the depth of the stack. The run goes on as before.
+pub type StackLimitHook = Box<dyn FnMut(usize)>;
+
+/// The tables the engine decodes from each module it loads
+struct Tables {
+    symbols: HashMap<String, u32>,
+    blocks: Blocks,
+    symtab: SymbolTable,
+}
+
+impl Tables {
+    fn new(module: &Module) -> Tables {
+        Tables {
+            symbols: module.symbol_index(),
+            blocks: Blocks::new(&module.bytecode),
+            symtab: SymbolTable::new(module),
+        }
+    }
+}
+
+/// A call in progress, resumed when the symbol it called returns
+struct Frame {
+    /// The context of the caller, positioned after the call instruction
+    caller: Context,
+    /// The ID of the called symbol within its module
+    symbol_id: u32,
+    /// The declared signature of the called symbol
+    signature: Option<Signature>,
+    /// The depth of the stack below the inputs of the called symbol
+    base: usize,
+    /// The start of the locals of the caller within the locals of the engine
+    locals: usize,
+    /// If the accesses of locals by the caller are proven in bounds
+    proven: bool,
+    /// If the call was made by a native function, which waits for it to
+    /// return
+    host: bool,
+}
+
+/// The entry point of a run in progress
+pub(crate) struct Entry {
+    /// The ID of the entry point within its module
+    symbol_id: u32,
+    /// The declared signature of the entry point
+    signature: Option<Signature>,
+    /// The depth of the stack below the inputs of the entry point
+    base: usize,
+}
 
 /// The virtual machine engine
 pub struct Engine {
@@ -22,222 +140,2120 @@ pub struct Engine {
     /// The maximum depth of the stack
     pub maxstack: usize,
 
+    /// How the stack grows as values are pushed; see `StackGrowth`
+    pub stack_growth: StackGrowth,
+
+    /// A depth of the stack below `maxstack` at which to warn rather than
+    /// fault, or `None` for no warning
+    ///
+    /// The first time the stack of a run grows past the soft limit, the
+    /// stack limit hook is called. The depth is checked before every
+    /// instruction and when the run ends. See `set_stack_limit_hook`.
+    pub soft_stack_limit: Option<usize>,
+
+    /// The maximum number of calls which may be in progress at once
+    pub maxframes: usize,
+
+    /// The maximum number of calls from native functions back into bytecode
+    /// which may be in progress at once
+    pub maxreentry: usize,
+
+    /// The fuel left to run bytecode with, or `None` if running is not
+    /// metered
+    ///
+    /// Every instruction consumes one unit of fuel; an instruction run with
+    /// none left faults with `BytecodeError::OutOfFuel`, so a host may bound
+    /// the work done by untrusted bytecode.
+    pub fuel: Option<u64>,
+
+    /// The time on the clock of the engine after which running faults, or
+    /// `None` if running is not limited in time
+    ///
+    /// The clock is read before every basic block of instructions while a
+    /// deadline is set, and a block entered after the deadline faults with
+    /// `BytecodeError::DeadlineExceeded` before its first instruction. A block
+    /// ends at every jump, call, return, and system instruction. See
+    /// `set_timeout`.
+    pub deadline: Option<Duration>,
+
+    /// If the faults returned by runs carry a snapshot of the state of the
+    /// engine when they were raised
+    ///
+    /// Snapshots copy the stack, so are off by default; see `FaultSnapshot`.
+    pub snapshot_faults: bool,
+
+    /// If each value on the stack carries a tag of its type, checked by the
+    /// instructions consuming it
+    ///
+    /// Tagging runs instructions one at a time, so is off by default; see
+    /// the `tags` module.
+    pub tag_stack: bool,
+
+    /// The tags of the values on the stack, while `tag_stack` is set
+    tags: Vec<Tag>,
+
+    /// The conditions which fault rather than being allowed; see
+    /// `Strictness`
+    pub strict: Strictness,
+
+    /// What integer arithmetic which overflows does, unless `strict` faults
+    /// on it
+    pub overflow: Overflow,
+
+    /// If floating point instructions push NaN results as `CANONICAL_NAN`,
+    /// rather than with the sign and payload the platform produces
+    ///
+    /// The bits of a NaN result are not specified, and differ between
+    /// platforms, so hosts needing runs to be reproducible bit for bit set
+    /// this; see `config::CANONICAL_NAN`.
+    pub canonicalize_nan: bool,
+
+    /// The capabilities granted to running code
+    pub policy: HostPolicy,
+
+    /// The opcodes running code may use, or `None` if it may use every one
+    ///
+    /// An instruction outside of the set faults with
+    /// `BytecodeError::DisallowedOpcode` before it runs; see the `isa`
+    /// module.
+    pub instruction_set: Option<InstructionSet>,
+
+    /// The arguments of the program, read by `ARG_GET`
+    pub args: Vec<String>,
+
+    /// The environment variables of the program, read by `ENV_GET`
+    ///
+    /// This starts empty rather than holding the environment of the process,
+    /// which a host must copy in to expose.
+    pub env: HashMap<String, String>,
+
     /// The list of loaded modules
     pub modules: Vec<Rc<Module>>,
 
     /// A lookup between module names and module id
     pub module_lookup: HashMap<String, u32>,
 
+    /// Module aliases, mapped to the names of the modules they stand for
+    pub module_aliases: HashMap<String, String>,
+
     pub context: Context,
+
+    /// The writable data segments of the loaded modules, indexed by module ID
+    ///
+    /// Segments share the initial values of their module until written to.
+    pub data: Vec<Segment>,
+
+    /// The values of the globals of the loaded modules, indexed by module ID
+    pub globals: Vec<Segment>,
+
+    /// The names of the local symbols of the loaded modules, mapped to their
+    /// IDs and indexed by module ID
+    symbols: Vec<HashMap<String, u32>>,
+
+    /// The basic blocks of the bytecode of the loaded modules, indexed by
+    /// module ID
+    blocks: Vec<Blocks>,
+
+    /// The symbols of the loaded modules as read by calls, indexed by module
+    /// ID
+    symtabs: Vec<SymbolTable>,
+
+    verifier: Option<ModuleVerifier>,
+
+    /// The functions of native modules, indexed by module ID and symbol ID
+    natives: HashMap<u32, Vec<NativeFn>>,
+
+    /// The calls in progress, innermost last
+    frames: Vec<Frame>,
+
+    /// The locals of the calls in progress, in the order of the calls
+    ///
+    /// The locals reserved by the innermost call run from `locals_base` to
+    /// the end, and those of each caller end where the locals of the call it
+    /// made start.
+    locals: Vec<u64>,
+    locals_base: usize,
+    /// If verification proved every access of a local by the innermost call
+    /// within the locals it reserves, so they need not be checked
+    proven_locals: bool,
+
+    /// The number of calls from native functions in progress
+    reentries: usize,
+
+    /// The depth of the stack below the inputs of the current run, which
+    /// the stack is truncated to if the run faults
+    run_base: usize,
+
+    /// The deepest the stack has been during the current run
+    stack_peak: usize,
+
+    /// The hook called when the stack passes the soft limit, and the depth
+    /// past which it is next called, which is `usize::MAX` once it has been
+    /// called during the current run
+    stack_limit_hook: Option<StackLimitHook>,
+    stack_alarm: usize,
+
+    /// The host objects handed to bytecode
+    handles: HandleTable,
+
+    /// The buffer host instructions read and write bytes through, kept
+    /// between instructions so they need not allocate their own
+    scratch: Vec<u8>,
+
+    /// The pool the memory for the stack, calls, and locals is taken from
+    memory: Memory,
+
+    /// The directories bytecode may open files beneath
+    preopens: Vec<Preopen>,
+
+    /// The standard streams, read and written by bytecode through the
+    /// handles `stream::STDIN`, `stream::STDOUT` and `stream::STDERR`
+    stdin: StreamBox,
+    stdout: StreamBox,
+    stderr: StreamBox,
+
+    /// The clock time is read from
+    clock: Box<dyn Clock>,
+
+    /// The source random values are drawn from
+    random: Box<dyn RandomSource>,
+
+    /// The counters of the work done by the engine
+    metrics: Metrics,
+
+    /// The receiver of the counters, and the number of instructions between
+    /// reports to it during a run, or zero to only report at the end of runs
+    metrics_sink: Option<Box<dyn MetricsSink>>,
+    metrics_interval: u64,
+
+    /// The exit value given to `HALT` during the last run, if it halted
+    halted: Option<u64>,
+
+    /// The hook given every trace event, if there is one, and the number of
+    /// calls it has been told of which have not returned
+    trace: Option<TraceHook>,
+    trace_depth: usize,
+
+    /// The module ID and offset of the instruction which raised the last
+    /// fault of the current run, followed by those of the calls in progress
+    /// when it was raised, innermost first
+    fault_trace: Vec<(u32, usize)>,
+
+    /// The stack when the last fault of the current run was raised, if
+    /// `snapshot_faults` is set
+    fault_stack: Option<Vec<u64>>,
+
+    /// The locals of the innermost call when the last fault of the current
+    /// run was raised, kept alongside `fault_stack`
+    fault_locals: Vec<u64>,
+
+    /// The module and symbol IDs of the symbol the last fault of the current
+    /// run refers to, if it was raised by a call other than the entry point
+    fault_symbol: Option<(u32, u32)>,
+}
+
+/// Find the offset of the call instruction a call returns to `offset` from
+///
+/// Instructions are decoded from the start of the symbol holding the call, as
+/// the size of the call instruction is not known from where it returns to.
+fn call_site(module: &Module, offset: usize) -> usize {
+    let before = offset.saturating_sub(1);
+    let mut site = match module.symbol_at(before as u32) {
+        Some(symbol) => symbol.offset() as usize,
+        None => return before,
+    };
+    while site < before {
+        match Instruction::decode(&module.bytecode[site..]) {
+            Ok((_, size)) if site + size < offset => site += size,
+            Ok(_) => break,
+            Err(_) => return before,
+        }
+    }
+    site
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
 }
 
 impl Engine {
-    /// Create a new Engine instance
+    /// The version of the bytecode ABI implemented by the engine
+    ///
+    /// See the `format` module for the compatibility policy.
+    pub const ABI_VERSION: u16 = 16;
+
+    /// The oldest version of the bytecode ABI the engine can run
+    pub const MIN_ABI_VERSION: u16 = 1;
+
+    /// Create a new Engine instance with the default config
     pub fn new() -> Engine {
-        Engine {
+        Engine::with_config(EngineConfig::default())
+    }
+
+    /// Create a new Engine instance with the given config
+    pub fn with_config(config: EngineConfig) -> Engine {
+        let mut engine = Engine {
             stack: Vec::new(),
-            maxstack: 0x8FFF,
+            maxstack: 0,
+            stack_growth: StackGrowth::default(),
+            soft_stack_limit: None,
+            maxframes: 0,
+            maxreentry: 0,
+            fuel: None,
+            deadline: None,
+            snapshot_faults: false,
+            tag_stack: false,
+            tags: Vec::new(),
+            strict: Strictness::default(),
+            overflow: Overflow::default(),
+            canonicalize_nan: false,
+            policy: HostPolicy::default(),
+            instruction_set: None,
+            args: Vec::new(),
+            env: HashMap::new(),
             modules: Vec::new(),
             module_lookup: HashMap::new(),
-            context: Context::new(
-                Rc::new(Module {
-                    name: String::from(""),
-                    strings: vec![],
-                    data: vec![],
-                    local_symbols: vec![],
-                    external_symbols: vec![],
-                    bytecode: vec![inst_sys!(NOP)],
-                    symbol_lookup: HashMap::new(),
-                }),
-                0,
-            )
-            .unwrap(),
+            module_aliases: HashMap::new(),
+            context: Engine::empty_context(),
+            data: Vec::new(),
+            globals: Vec::new(),
+            symbols: Vec::new(),
+            blocks: Vec::new(),
+            symtabs: Vec::new(),
+            verifier: None,
+            natives: HashMap::new(),
+            frames: Vec::new(),
+            locals: Vec::new(),
+            locals_base: 0,
+            proven_locals: false,
+            reentries: 0,
+            run_base: 0,
+            stack_peak: 0,
+            stack_limit_hook: None,
+            stack_alarm: usize::MAX,
+            handles: HandleTable::new(),
+            scratch: Vec::new(),
+            memory: Memory::default(),
+            preopens: Vec::new(),
+            stdin: Box::new(ReadOnly(std::io::stdin())),
+            stdout: Box::new(WriteOnly(std::io::stdout())),
+            stderr: Box::new(WriteOnly(std::io::stderr())),
+            clock: Box::new(SystemClock::new()),
+            random: Box::new(EntropySource::new()),
+            metrics: Metrics::default(),
+            metrics_sink: None,
+            metrics_interval: 0,
+            halted: None,
+            trace: None,
+            trace_depth: 0,
+            fault_trace: Vec::new(),
+            fault_stack: None,
+            fault_locals: Vec::new(),
+            fault_symbol: None,
+        };
+        engine.configure(&config);
+        engine
+    }
+
+    /// Apply the limits of a config to the engine
+    ///
+    /// The fuel of the engine is replaced by that of the config, and a
+    /// timeout in the config sets the deadline from the current time on the
+    /// clock of the engine, so hosts apply configs just before running.
+    pub fn configure(&mut self, config: &EngineConfig) {
+        self.maxstack = config.max_stack;
+        self.stack_growth = config.stack_growth;
+        self.soft_stack_limit = config.soft_stack_limit;
+        self.maxframes = config.max_frames;
+        self.maxreentry = config.max_reentry;
+        self.fuel = config.fuel;
+        self.snapshot_faults = config.snapshot_faults;
+        self.tag_stack = config.tag_stack;
+        self.strict = config.strict;
+        self.overflow = config.overflow;
+        self.canonicalize_nan = config.canonicalize_nan;
+        self.deadline = config.timeout.map(|timeout| self.clock.now().saturating_add(timeout));
+        if self.stack_growth == StackGrowth::Reserve {
+            // Without room in the memory pool, the stack grows as it is used
+            // and faults when the pool runs out
+            self.reserve_stack(self.maxstack.saturating_sub(self.stack.len()));
         }
     }
 
-    /// Add the given module to the engine
+    fn empty_context() -> Context {
+        Context::new(
+            Rc::new(Module { bytecode: Cow::Owned(vec![inst_sys!(NOP)]), ..Module::new("") }),
+            0,
+            0,
+        )
+        .unwrap()
+    }
+
+    /// Set the check run on every module added to the engine
     ///
-    /// This adds the given module to the engine, registering the name of the
-    /// module to the next available ID.
-    pub fn add_module(&mut self, module: Rc<Module>) -> Result<u32, ModuleError> {
-        if let Some(_) = self.module_lookup.get(&module.name) {
-            return Err(ModuleError::NameCollision(module.name.clone()));
+    /// Hosts loading modules from untrusted sources may use the verifier to
+    /// enforce provenance, for example by checking a detached signature over
+    /// the content hash of the module. The verifier runs on modules given to
+    /// `add_module` and `replace_module`; modules which fail verification are
+    /// rejected with `ModuleError::VerificationFailed`.
+    pub fn set_module_verifier(
+        &mut self,
+        verifier: impl Fn(&Module, &Digest) -> Result<(), String> + 'static,
+    ) {
+        self.verifier = Some(Box::new(verifier));
+    }
+
+    fn verify(&self, module: &Module) -> Result<(), ModuleError> {
+        verify::verify(module)?;
+        match &self.verifier {
+            Some(verifier) => verifier(module, &module.content_hash())
+                .map_err(|e| ModuleError::VerificationFailed(module.name.clone(), e)),
+            None => Ok(()),
         }
+    }
 
-        let module_id = self.modules.len();
+    /// Set the standard input stream, which starts as that of the process
+    pub fn set_stdin(&mut self, stdin: impl Read + 'static) {
+        self.stdin = Box::new(ReadOnly(stdin));
+    }
 
-        self.module_lookup.insert(module.name.clone(), module_id as u32);
-        self.modules.push(Rc::clone(&module));
+    /// Set the standard output stream, which starts as that of the process
+    ///
+    /// The `PRINT_*` instructions write lines to this stream.
+    pub fn set_stdout(&mut self, stdout: impl Write + 'static) {
+        self.stdout = Box::new(WriteOnly(stdout));
+    }
 
-        Ok(module_id as u32)
+    /// Set the standard error stream, which starts as that of the process
+    ///
+    /// The `LOG` instruction writes lines to this stream.
+    pub fn set_stderr(&mut self, stderr: impl Write + 'static) {
+        self.stderr = Box::new(WriteOnly(stderr));
     }
 
-    fn get_context(&self, module_id: u32, symbol_id: u32) -> Result<Context, BytecodeError> {
-        if module_id as u64 >= self.modules.len() as u64 {
-            return Err(BytecodeError::InvalidModule(module_id));
+    /// Set the clock time is read from
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Set the deadline to `timeout` after the time on the clock of the engine
+    ///
+    /// The deadline is not reset between runs, so it bounds every run until
+    /// it is changed.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.deadline = Some(self.clock.now().saturating_add(timeout));
+    }
+
+    /// Set the source random values are drawn from
+    pub fn set_random_source(&mut self, random: impl RandomSource + 'static) {
+        self.random = Box::new(random);
+    }
+
+    /// Get the counters of the work done by the engine
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Set the receiver of the counters of the engine
+    ///
+    /// The sink is given the counters at the end of every run started with
+    /// `run` or `call_ref`, whether or not it faulted. If `interval` is given,
+    /// it is also given them every `interval` instructions during a run, so
+    /// long runs may be observed as they progress.
+    pub fn set_metrics_sink(&mut self, sink: impl MetricsSink + 'static, interval: Option<u64>) {
+        self.metrics_sink = Some(Box::new(sink));
+        self.metrics_interval = interval.unwrap_or(0);
+    }
+
+    /// Give the counters to the metrics sink, if there is one
+    fn report_metrics(&mut self) {
+        self.metrics.max_stack = self.metrics.max_stack.max(self.stack.len());
+        if let Some(sink) = self.metrics_sink.as_mut() {
+            sink.record(&self.metrics);
         }
-        let module = &self.modules[module_id as usize];
-        if symbol_id as u64 >= module.local_symbols.len() as u64 {
-            return Err(BytecodeError::InvalidSymbol(symbol_id));
+    }
+
+    /// Draw a random value less than `bound`, or any value if it is zero
+    ///
+    /// Values from the top of the range which would favour small results are
+    /// redrawn, so every result is equally likely.
+    fn random_below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            return self.random.next_u64();
+        }
+        let limit = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.random.next_u64();
+            if value < limit {
+                return value % bound;
+            }
         }
-        let symbol = &module.local_symbols[symbol_id as usize];
-        Ok(Context::new(Rc::clone(module), symbol.code_offset as usize)?)
     }
 
-    /// Run the bytecode for the given module and symbol IDs
+    /// Set the hook given every trace event of the engine
     ///
-    /// This will execute the symbol in the module corresponding to the given
-    /// IDs.
-    pub fn run(&mut self, module_id: u32, symbol_id: u32) -> Result<(), BytecodeError> {
-        self.context = self.get_context(module_id, symbol_id)?;
-
-        while self.context.has_next() {
-            let opcode = self.context.next().unwrap();
-            let group = ((opcode & bytecode::GROUP_MASK) >> bytecode::GROUP_SHIFT) as u8;
-            let value = ((opcode & bytecode::DATA_MASK) >> bytecode::DATA_SHIFT) as u8;
-            println!("Opcode: {:#06x} ({:#04x}|{:#04x})", opcode, group, value);
-            match group {
-                bytecode::groups::SYSTEM => self.op_system(opcode, value)?,
-                bytecode::groups::STACK => self.op_stack(opcode, value)?,
-                bytecode::groups::MATH => self.op_math(opcode, value)?,
-                _ => {
-                    // Unimplemented
-                    return Err(BytecodeError::BadOpcode(opcode));
-                }
-            };
+    /// See the `trace` module.
+    pub fn set_trace_hook(&mut self, hook: impl FnMut(&TraceEvent) + 'static) {
+        self.trace = Some(Box::new(hook));
+    }
+
+    /// Remove the trace hook, returning it
+    pub fn clear_trace_hook(&mut self) -> Option<TraceHook> {
+        self.trace.take()
+    }
+
+    /// Give a call event to the trace hook, if there is one
+    fn trace_call(&mut self, module_id: u32, symbol_id: u32) {
+        if let Some(hook) = self.trace.as_mut() {
+            let module = &self.modules[module_id as usize];
+            let depth = self.trace_depth;
+            hook(&TraceEvent::Call { module, module_id, symbol_id, depth });
+            self.trace_depth += 1;
         }
+    }
 
-        Ok(())
+    /// Give a return event to the trace hook, if there is one
+    fn trace_return(&mut self) {
+        if let Some(hook) = self.trace.as_mut() {
+            self.trace_depth = self.trace_depth.saturating_sub(1);
+            hook(&TraceEvent::Return { depth: self.trace_depth });
+        }
     }
 
-    fn op_system(&mut self, opcode: u16, value: u8) -> Result<(), BytecodeError> {
-        match value {
-            bytecode::sys::NOP => (),
-            bytecode::sys::PRINT_STACK => {
-                println!("Stack: {:?}", self.stack);
-            }
-            bytecode::sys::PRINT_U64 => {
-                let value = popstack1!(self, opcode);
-                println!("PRINT: {}", value);
-            }
-            bytecode::sys::PRINT_I64 => {
-                let value = popstack1!(self, opcode);
-                println!("PRINT: {}", value as i64);
-            }
-            bytecode::sys::PRINT_F32 => {
-                let value = popstack1!(self, opcode);
-                println!("PRINT: {}", f32::from_bits(value as u32));
-            }
-            bytecode::sys::PRINT_F64 => {
-                let value = popstack1!(self, opcode);
-                println!("PRINT: {}", f64::from_bits(value));
-            }
-            _ => {
-                // Unimplemented
-                return Err(BytecodeError::BadOpcode(opcode));
+    /// Make imports from the module `alias` resolve to the module `target`
+    ///
+    /// Aliases are applied when modules are linked, and take precedence over
+    /// loaded modules of the same name, so an implementation may be swapped
+    /// for another (e.g. a mock in tests) without rebuilding the modules which
+    /// import it. The target must be loaded by the time the modules are
+    /// linked; any previous alias of the same name is replaced.
+    pub fn alias_module(&mut self, alias: &str, target: &str) -> Result<(), ModuleError> {
+        for name in [alias, target] {
+            if !module::is_valid_name(name) {
+                return Err(ModuleError::InvalidName(String::from(name)));
             }
         }
+        self.module_aliases.insert(String::from(alias), String::from(target));
         Ok(())
     }
 
-    fn op_stack(&mut self, opcode: u16, value: u8) -> Result<(), BytecodeError> {
-        match value {
-            //bytecode::stack::CONST_0 => self.stack.push(0),
-            bytecode::stack::CONST_0 => pushstack!(self, opcode, 0),
-            bytecode::stack::CONST_1 => pushstack!(self, opcode, 1),
-            bytecode::stack::CONST_2 => pushstack!(self, opcode, 2),
-            bytecode::stack::CONST_3 => pushstack!(self, opcode, 3),
-            bytecode::stack::CONST_4 => pushstack!(self, opcode, 4),
-            bytecode::stack::CONST_8 => pushstack!(self, opcode, 8),
-            bytecode::stack::CONST_16 => pushstack!(self, opcode, 16),
-            bytecode::stack::CONST_32 => pushstack!(self, opcode, 32),
-            bytecode::stack::CONST_64 => pushstack!(self, opcode, 64),
-            bytecode::stack::CONST_128 => pushstack!(self, opcode, 128),
-            bytecode::stack::CONST_N1 => pushstack!(self, opcode, (-1 as i64)),
-            bytecode::stack::CONST_U16 => pushstack!(self, opcode, self.context.cval_u16()?),
-            bytecode::stack::CONST_U32 => pushstack!(self, opcode, self.context.cval_u32()?),
-            bytecode::stack::CONST_U64 => pushstack!(self, opcode, self.context.cval_u64()?),
-            bytecode::stack::CONST_I16 => {
-                pushstack!(self, opcode, ((self.context.cval_u16()? as i16) as i64))
-            }
-            bytecode::stack::CONST_I32 => {
-                pushstack!(self, opcode, ((self.context.cval_u32()? as i32) as i64))
-            }
-            bytecode::stack::DUPE => {
-                let num = popstack1!(self, opcode);
-                checkstack!(self, opcode, num);
-                if (self.stack.len() as u64) < num {
-                    return Err(BytecodeError::stack_underflow(opcode, num));
-                }
-                // We can be 100% certain num fits within usize due to the above
-                // checks
-                let base = self.stack.len() - (num as usize);
-                for i in 0..(num as usize) {
-                    self.stack.push(self.stack[base + i]);
-                }
-            }
-            bytecode::stack::DUPE_1 => {
-                checkstack!(self, opcode, 1);
-                if self.stack.len() < 1 {
-                    return Err(BytecodeError::stack_underflow(opcode, 1));
-                }
-                self.stack.push(self.stack[self.stack.len() - 1]);
-            }
-            bytecode::stack::DUPE_C => {
-                let num = self.context.cval_u16()? as usize;
-                checkstack!(self, opcode, num as u64);
-                if self.stack.len() < num {
-                    return Err(BytecodeError::stack_underflow(opcode, num as u64));
-                }
-                let base = self.stack.len() - num;
-                for i in 0..num {
-                    self.stack.push(self.stack[base + i]);
-                }
-            }
-            _ => {
-                return Err(BytecodeError::BadOpcode(opcode));
-            }
+    /// Remove a module alias, returning the name of the module it stood for
+    pub fn remove_alias(&mut self, alias: &str) -> Option<String> {
+        self.module_aliases.remove(alias)
+    }
+
+    /// Get the table of module names used to link imports, including aliases
+    fn link_lookup(&self) -> Result<HashMap<String, u32>, ModuleError> {
+        let mut lookup = self.module_lookup.clone();
+        for (alias, target) in self.module_aliases.iter() {
+            match self.module_lookup.get(target) {
+                Some(id) => lookup.insert(alias.clone(), *id),
+                None => return Err(ModuleError::UnknownModule(target.clone())),
+            };
         }
-        Ok(())
+        Ok(lookup)
     }
 
-    fn op_math(&mut self, opcode: u16, value: u8) -> Result<(), BytecodeError> {
-        match value {
-            bytecode::math::ADD => {
-                let (v1, v2) = popstack2!(self, opcode);
-                self.stack.push((Wrapping(v1) + Wrapping(v2)).0);
+    /// Add the given module to the engine
+    ///
+    /// This adds the given module to the engine, registering the name of the
+    /// module to the next available ID. The name must be valid according to
+    /// `module::is_valid_name`.
+    pub fn add_module(&mut self, module: Rc<Module>) -> Result<u32, ModuleError> {
+        if !module::is_valid_name(&module.name) {
+            return Err(ModuleError::InvalidName(module.name.clone()));
+        }
+        if self.module_lookup.contains_key(&module.name) {
+            return Err(ModuleError::NameCollision(module.name.clone()));
+        }
+        self.verify(&module)?;
+        let tables = Tables::new(&module);
+        Ok(self.insert_module(module, tables))
+    }
+
+    /// Add several modules to the engine at once
+    ///
+    /// This is `add_module` for each of the modules in order, except that the
+    /// modules are all verified before any is added, so either all of them are
+    /// added or none are. With the `rayon` feature, the modules are verified
+    /// and decoded across the rayon thread pool, though a verifier set with
+    /// `set_module_verifier` is still called for each module in turn. Returns
+    /// the IDs of the modules, in the order they were given.
+    pub fn add_modules(&mut self, modules: Vec<Rc<Module>>) -> Result<Vec<u32>, ModuleError> {
+        let mut names = HashSet::new();
+        for module in modules.iter() {
+            if !module::is_valid_name(&module.name) {
+                return Err(ModuleError::InvalidName(module.name.clone()));
             }
-            bytecode::math::ADD_C => {
-                let c = self.context.cval_u16()? as u64;
-                let v = popstack1!(self, opcode);
-                self.stack.push((Wrapping(c) + Wrapping(v)).0);
+            if self.module_lookup.contains_key(&module.name) || !names.insert(&module.name) {
+                return Err(ModuleError::NameCollision(module.name.clone()));
             }
-            bytecode::math::SUB => {
-                let (v1, v2) = popstack2!(self, opcode);
-                self.stack.push((Wrapping(v1) - Wrapping(v2)).0);
+        }
+
+        let hash = self.verifier.is_some();
+        let prepare = |module: &Module| -> Result<(Tables, Option<Digest>), ModuleError> {
+            verify::verify(module)?;
+            let digest = if hash { Some(module.content_hash()) } else { None };
+            Ok((Tables::new(module), digest))
+        };
+        let shared: Vec<&Module> = modules.iter().map(|m| m.as_ref()).collect();
+        #[cfg(feature = "rayon")]
+        let prepared: Vec<_> = {
+            use rayon::prelude::*;
+            shared.into_par_iter().map(prepare).collect()
+        };
+        #[cfg(not(feature = "rayon"))]
+        let prepared: Vec<_> = shared.into_iter().map(prepare).collect();
+
+        let mut tables = Vec::with_capacity(modules.len());
+        for (module, result) in modules.iter().zip(prepared) {
+            let (table, digest) = result?;
+            if let (Some(verifier), Some(digest)) = (&self.verifier, digest) {
+                verifier(module, &digest)
+                    .map_err(|e| ModuleError::VerificationFailed(module.name.clone(), e))?;
             }
-            bytecode::math::SUB_C => {
-                let c = self.context.cval_u16()? as u64;
-                let v = popstack1!(self, opcode);
-                self.stack.push((Wrapping(v) - Wrapping(c)).0);
+            tables.push(table);
+        }
+        Ok(modules.into_iter().zip(tables).map(|(m, t)| self.insert_module(m, t)).collect())
+    }
+
+    /// Register a verified module under the next available ID
+    fn insert_module(&mut self, module: Rc<Module>, tables: Tables) -> u32 {
+        let module_id = self.modules.len();
+
+        self.module_lookup.insert(module.name.clone(), module_id as u32);
+        self.data.push(Segment::data(&module));
+        self.globals.push(Segment::globals(&module));
+        self.symbols.push(tables.symbols);
+        self.blocks.push(tables.blocks);
+        self.symtabs.push(tables.symtab);
+        self.modules.push(Rc::clone(&module));
+
+        emit_log!(debug, "loaded module {} as ID {}", module.name, module_id);
+        module_id as u32
+    }
+
+    /// Register a native function as a symbol of a native module
+    ///
+    /// The native module named `module` is created the first time a function
+    /// is registered in it, and is loaded like any other module; the function
+    /// is added to it as an exported symbol named `symbol`, without a declared
+    /// signature. Bytecode calls the function through an external symbol, so
+    /// modules using it must be linked after it is registered. Returns the ID
+    /// of the new symbol.
+    ///
+    /// Functions can not be added to modules loaded with `add_module`.
+    pub fn register_native(
+        &mut self,
+        module: &str,
+        symbol: &str,
+        function: impl Fn(&mut StackView) -> Result<(), BytecodeError> + 'static,
+    ) -> Result<u32, ModuleError> {
+        self.add_native(module, symbol, Rc::new(function), None)
+    }
+
+    /// Register a typed function as a symbol of a native module
+    ///
+    /// This is `register_native` for functions taking and returning values
+    /// which convert to and from stack values, such as `fn(u64, f64) -> i64`.
+    /// The arguments are popped from the stack, with the last argument on
+    /// top, and the results are pushed in their place; the symbol is declared
+    /// with the signature of the function. See the `native` module for the
+    /// types which may be used.
+    pub fn register_fn<Args>(
+        &mut self,
+        module: &str,
+        symbol: &str,
+        function: impl NativeFunction<Args> + 'static,
+    ) -> Result<u32, ModuleError> {
+        let signature = Some(function.signature());
+        let native: NativeFn = Rc::new(move |stack: &mut StackView| function.call(stack));
+        self.add_native(module, symbol, native, signature)
+    }
+
+    fn add_native(
+        &mut self,
+        module: &str,
+        symbol: &str,
+        function: NativeFn,
+        signature: Option<Signature>,
+    ) -> Result<u32, ModuleError> {
+        if !module::is_valid_name(module) {
+            return Err(ModuleError::InvalidName(String::from(module)));
+        }
+        let module_id = match self.module_lookup.get(module) {
+            Some(id) if self.natives.contains_key(id) => *id,
+            Some(_) => return Err(ModuleError::NameCollision(String::from(module))),
+            None => {
+                let module_id = self.modules.len() as u32;
+                self.module_lookup.insert(String::from(module), module_id);
+                self.data.push(Segment::from(vec![]));
+                self.globals.push(Segment::from(vec![]));
+                self.symbols.push(HashMap::new());
+                self.blocks.push(Blocks::new(&[]));
+                self.symtabs.push(SymbolTable::default());
+                self.modules.push(Rc::new(Engine::native_module(module)));
+                self.natives.insert(module_id, vec![]);
+                module_id
             }
-            bytecode::math::MUL => {
-                let (v1, v2) = popstack2!(self, opcode);
-                self.stack.push((Wrapping(v1) * Wrapping(v2)).0);
+        };
+        let target = Rc::make_mut(&mut self.modules[module_id as usize]);
+        if target.find_symbol(symbol).is_some() {
+            return Err(ModuleError::DuplicateSymbol(String::from(symbol)));
+        }
+        target.strings.push(String::from(symbol));
+        target.local_symbols.push(LocalSymbol {
+            name_id: target.strings.len() as u32 - 1,
+            code_offset: 0,
+            exported: true,
+            signature,
+        });
+        let symbol_id = target.local_symbols.len() as u32 - 1;
+        self.symbols[module_id as usize].entry(String::from(symbol)).or_insert(symbol_id);
+        self.symtabs[module_id as usize].push_native(signature);
+        let functions = self.natives.get_mut(&module_id).unwrap();
+        functions.push(function);
+        Ok(functions.len() as u32 - 1)
+    }
+
+    fn native_module(name: &str) -> Module {
+        Module::new(name)
+    }
+
+    /// Get the native function of a symbol, if it is one
+    fn native(&self, module_id: u32, symbol_id: u32) -> Option<NativeFn> {
+        self.natives.get(&module_id)?.get(symbol_id as usize).cloned()
+    }
+
+    /// Add a host object to the handle table, returning its handle
+    ///
+    /// Handles are opaque values which native functions may give to bytecode
+    /// in place of the object; see the `handle` module.
+    pub fn insert_handle<T: std::any::Any>(&mut self, value: T) -> u64 {
+        self.handles.insert(value)
+    }
+
+    /// Add a stream to the handle table, returning its handle
+    ///
+    /// Bytecode reads and writes the stream through the handle with `READ`
+    /// and `WRITE`; see the `stream` module.
+    pub fn insert_stream(&mut self, stream: impl Stream + 'static) -> u64 {
+        self.handles.insert::<StreamBox>(Box::new(stream))
+    }
+
+    /// Allow bytecode to open files beneath a directory with `OPEN`
+    ///
+    /// Bytecode names the directory by `name`, followed by a `/` and the path
+    /// of a file within it. Files may only be created or written if
+    /// `writable` is set. A directory preopened later under the same name
+    /// takes precedence.
+    pub fn preopen(&mut self, name: &str, dir: impl Into<PathBuf>, writable: bool) {
+        self.preopens.push(Preopen { name: String::from(name), dir: dir.into(), writable });
+    }
+
+    /// Get the host object a handle refers to
+    ///
+    /// Returns `None` if the handle is invalid or the object is not a `T`.
+    pub fn get_handle<T: std::any::Any>(&self, handle: u64) -> Option<&T> {
+        self.handles.get(handle)
+    }
+
+    /// Get the host object a handle refers to mutably
+    pub fn get_handle_mut<T: std::any::Any>(&mut self, handle: u64) -> Option<&mut T> {
+        self.handles.get_mut(handle)
+    }
+
+    /// Remove a host object from the handle table, returning it
+    ///
+    /// Returns `None`, leaving the handle valid, if the handle is invalid or
+    /// the object is not a `T`.
+    pub fn remove_handle<T: std::any::Any>(&mut self, handle: u64) -> Option<T> {
+        self.handles.remove(handle)
+    }
+
+    /// Set the function releasing host objects of type `T`
+    ///
+    /// Objects still in the handle table when the engine faults, is reset, or
+    /// is dropped are released, by the hook for their type if there is one.
+    pub fn set_handle_drop_hook<T: std::any::Any>(&mut self, hook: impl Fn(T) + 'static) {
+        self.handles.set_drop_hook(hook);
+    }
+
+    /// Get the table of host objects
+    pub fn handles(&self) -> &HandleTable {
+        &self.handles
+    }
+
+    /// Reset the execution state of the engine
+    ///
+    /// The stack is cleared, any calls in progress are abandoned, and every
+    /// host object in the handle table is released. Loaded modules, and the
+    /// data and globals of each, are kept.
+    pub fn reset(&mut self) {
+        self.stack.clear();
+        self.frames.clear();
+        self.locals.clear();
+        self.locals_base = 0;
+        self.proven_locals = false;
+        self.context = Engine::empty_context();
+        self.handles.clear();
+    }
+
+    /// Iterate over the loaded modules, in order of their IDs
+    pub fn modules(&self) -> impl ExactSizeIterator<Item = &Module> {
+        self.modules.iter().map(|m| m.as_ref())
+    }
+
+    /// Get the loaded module with the given name
+    pub fn module(&self, name: &str) -> Option<&Module> {
+        self.module_lookup.get(name).map(|id| self.modules[*id as usize].as_ref())
+    }
+
+    /// Look up a symbol of a loaded module by name
+    ///
+    /// Names are looked up in a table built as each module is added, so this
+    /// does not scan the symbols of the module. The reference returned may be
+    /// kept and given to `call_ref`, or its IDs to `run`, to call the symbol
+    /// again without looking up its name.
+    pub fn find_symbol(&self, module: &str, symbol: &str) -> Option<FuncRef> {
+        let module_id = *self.module_lookup.get(module)?;
+        let symbol_id = match self.symbols.get(module_id as usize) {
+            Some(symbols) => *symbols.get(symbol)?,
+            None => self.modules[module_id as usize].find_symbol(symbol)?,
+        };
+        Some(FuncRef::new(module_id, symbol_id))
+    }
+
+    /// Get the dependency graph of the loaded modules
+    ///
+    /// The graph lists which symbols each module imports from which other
+    /// modules, and may be built before the modules are linked.
+    pub fn dependency_graph(&self) -> Result<DependencyGraph, ModuleError> {
+        DependencyGraph::new(&self.modules)
+    }
+
+    /// Resolve the external symbols of all loaded modules
+    ///
+    /// This must be called after all of the modules a program needs have been
+    /// added, and before running any bytecode which uses external symbols.
+    /// Imports from an aliased module resolve to the module it stands for.
+    pub fn link(&mut self) -> Result<(), ModuleError> {
+        let result = self.link_lookup().and_then(|lookup| linker::link(&mut self.modules, &lookup));
+        self.rebind();
+        match &result {
+            Ok(()) => emit_log!(debug, "linked {} modules", self.modules.len()),
+            Err(_error) => emit_log!(warn, "linking failed: {}", _error),
+        }
+        result
+    }
+
+    /// Replace a loaded module with a new version
+    ///
+    /// The module registered under `name` is swapped for `module`, keeping the
+    /// same module ID, and all loaded modules are re-linked so that modules
+    /// depending on it bind to the new symbols. The replacement is atomic; if
+    /// linking fails, the engine is left unchanged and the error is returned.
+    ///
+    /// As the current execution context may reference the old module, it is
+    /// reset by a successful replacement.
+    pub fn replace_module(&mut self, name: &str, module: Rc<Module>) -> Result<u32, ModuleError> {
+        let module_id = match self.module_lookup.get(name) {
+            Some(id) if self.natives.contains_key(id) => {
+                return Err(ModuleError::NameCollision(String::from(name)))
             }
-            bytecode::math::MUL_C => {
-                let c = self.context.cval_u16()? as u64;
-                let v = popstack1!(self, opcode);
-                self.stack.push((Wrapping(v) * Wrapping(c)).0);
+            Some(id) => *id,
+            None => return Err(ModuleError::UnknownModule(String::from(name))),
+        };
+        if module.name != name {
+            return Err(ModuleError::InvalidName(module.name.clone()));
+        }
+        self.verify(&module)?;
+
+        let data = Segment::data(&module);
+        let globals = Segment::globals(&module);
+        let Tables { symbols, blocks, symtab } = Tables::new(&module);
+        let mut modules = self.modules.clone();
+        modules[module_id as usize] = module;
+        linker::link(&mut modules, &self.link_lookup()?)?;
+
+        self.modules = modules;
+        self.data[module_id as usize] = data;
+        self.globals[module_id as usize] = globals;
+        self.symbols[module_id as usize] = symbols;
+        self.blocks[module_id as usize] = blocks;
+        self.symtabs[module_id as usize] = symtab;
+        self.rebind();
+        self.context = Engine::empty_context();
+        emit_log!(debug, "replaced module {} (ID {})", name, module_id);
+        Ok(module_id)
+    }
+
+    /// Update the tables of the loaded modules after linking
+    ///
+    /// This binds the external symbols in the symbol tables to the symbols the
+    /// linker resolved them to. Linking also copies any module it updates which
+    /// is held elsewhere, including by the segments sharing it, so the segments
+    /// are pointed at the engine's copy.
+    fn rebind(&mut self) {
+        let segments = self.data.iter_mut().zip(self.globals.iter_mut());
+        for ((module, symtab), (data, globals)) in
+            self.modules.iter().zip(self.symtabs.iter_mut()).zip(segments)
+        {
+            symtab.bind(module);
+            data.rebind(module);
+            globals.rebind(module);
+        }
+    }
+
+    fn get_context(&self, module_id: u32, symbol_id: u32) -> Result<Context, BytecodeError> {
+        let symbol = match self.symtabs.get(module_id as usize) {
+            Some(symtab) => symtab.get(symbol_id).ok_or(BytecodeError::InvalidSymbol(symbol_id))?,
+            None => return Err(BytecodeError::InvalidModule(module_id)),
+        };
+        if !symbol.exported {
+            return Err(BytecodeError::PrivateSymbol(symbol_id));
+        }
+        let module = Rc::clone(&self.modules[module_id as usize]);
+        Context::new(module, module_id, symbol.offset as usize)
+    }
+
+    /// Run the bytecode for the given module and symbol IDs
+    ///
+    /// This will execute the symbol in the module corresponding to the given
+    /// IDs. Only exported symbols may be used as an entry point.
+    ///
+    /// If the symbol declares a signature, the stack must hold at least as
+    /// many values as the symbol takes as inputs, and the symbol must leave
+    /// exactly as many values as it declares as outputs in their place. The
+    /// same holds for every symbol called while running.
+    ///
+    /// If running faults, the calls in progress are abandoned, the stack is
+    /// truncated to its depth below the inputs of the symbol, and every host
+    /// object in the handle table is released, so the engine may go on to
+    /// run other symbols. The fault is returned with the location it was
+    /// raised at.
+    ///
+    /// Running does not allocate, other than to grow the stack, the calls in
+    /// progress, and their locals the first time a run needs them deeper, to
+    /// copy a writable data segment or the globals of a module when first
+    /// written to, and to open files and connections. What the engine grows
+    /// is kept for later runs, unless a chunked `stack_growth` releases it,
+    /// so running a program again allocates nothing.
+    /// Faults, trace hooks, and native functions may allocate.
+    pub fn run(&mut self, module_id: u32, symbol_id: u32) -> Result<(), Fault> {
+        let result = self.execute(module_id, symbol_id);
+        if let Err(_error) = &result {
+            emit_log!(
+                debug,
+                "fault running symbol {} of module {}: {}",
+                symbol_id,
+                module_id,
+                _error
+            );
+        }
+        self.end(&result);
+        result.map_err(|error| self.locate(error, module_id, symbol_id))
+    }
+
+    /// Call the symbol a function reference refers to, returning its results
+    ///
+    /// The arguments are pushed and the symbol is run to completion, as by
+    /// `run`; the values it leaves above the stack depth before the call are
+    /// popped and returned. This allows the host to call a function handed to
+    /// it by bytecode after the native function it was given to has returned.
+    /// Only exported symbols may be called.
+    ///
+    /// If the call faults, the calls in progress are abandoned, the stack is
+    /// restored to its depth before the call, and every host object in the
+    /// handle table is released.
+    pub fn call_ref(&mut self, func: FuncRef, args: &[u64]) -> Result<Vec<u64>, Fault> {
+        self.frames.clear();
+        self.locals.clear();
+        self.locals_base = 0;
+        self.proven_locals = false;
+        self.run_base = self.stack.len();
+        self.watch_stack_from_start();
+        self.trace_depth = 0;
+        self.halted = None;
+        self.fault_trace.clear();
+        self.fault_stack = None;
+        self.fault_symbol = None;
+        let result = self.reenter(inst_function!(CALL_REF), func.module_id, func.symbol_id, args);
+        if let Err(_error) = &result {
+            emit_log!(debug, "fault calling {:?}: {}", func, _error);
+        }
+        self.end(&result);
+        result.map_err(|error| self.locate(error, func.module_id, func.symbol_id))
+    }
+
+    /// Add the location of the instruction which raised a fault and the
+    /// calls leading to it, or of the entry point if no instruction ran,
+    /// and a snapshot if `snapshot_faults` is set
+    pub(crate) fn locate(&self, error: BytecodeError, module_id: u32, symbol_id: u32) -> Fault {
+        let (named_module, named_symbol) = self.fault_symbol.unwrap_or((module_id, symbol_id));
+        let named = match error {
+            BytecodeError::BadInputs(_)
+            | BytecodeError::BadOutputs(_)
+            | BytecodeError::PrivateSymbol(_)
+            | BytecodeError::UnusedValues(_) => {
+                self.modules.get(named_module as usize).and_then(|module| {
+                    let symbol = module.symbol(named_symbol)?.name()?;
+                    Some((module.name.clone(), String::from(symbol)))
+                })
+            }
+            _ => None,
+        };
+        let mut fault = self.backtrace(error, module_id, symbol_id);
+        if let Some((module, symbol)) = named {
+            fault = fault.with_symbol(module, symbol);
+        }
+        if !self.snapshot_faults {
+            return fault;
+        }
+        let (module_id, offset) = match self.fault_trace.first() {
+            Some(&location) => location,
+            None => match self.modules.get(module_id as usize).and_then(|m| m.symbol(symbol_id)) {
+                Some(symbol) => (module_id, symbol.offset() as usize),
+                None => return fault,
+            },
+        };
+        let (stack, locals) = match &self.fault_stack {
+            Some(stack) => (stack.clone(), self.fault_locals.clone()),
+            None => (self.stack.clone(), self.locals().to_vec()),
+        };
+        fault.with_snapshot(FaultSnapshot { module_id, offset, stack, locals })
+    }
+
+    /// Build a fault with the location it was raised at and the calls
+    /// leading to it
+    fn backtrace(&self, error: BytecodeError, module_id: u32, symbol_id: u32) -> Fault {
+        let located = |module: &Module, symbol: Option<Symbol>, offset| FaultLocation {
+            module: module.name.clone(),
+            symbol: symbol.and_then(|s| s.name()).map(String::from),
+            offset,
+            source: module.source_location(offset as u32).map(|s| s.to_string()),
+        };
+        if self.fault_trace.is_empty() {
+            let location = self.modules.get(module_id as usize).and_then(|module| {
+                let symbol = module.symbol(symbol_id)?;
+                Some(located(module, Some(symbol), symbol.offset() as usize))
+            });
+            return Fault::new(error, location);
+        }
+        let backtrace = self
+            .fault_trace
+            .iter()
+            .map(|&(module_id, offset)| {
+                let module = &self.modules[module_id as usize];
+                located(module, module.symbol_at(offset as u32), offset)
+            })
+            .collect();
+        Fault::with_backtrace(error, backtrace)
+    }
+
+    /// Record the symbol a fault raised by a call refers to, returning the
+    /// fault
+    fn symbol_fault(
+        &mut self,
+        module_id: u32,
+        symbol_id: u32,
+        error: BytecodeError,
+    ) -> BytecodeError {
+        self.fault_symbol = Some((module_id, symbol_id));
+        error
+    }
+
+    /// Record where a fault was raised, and the call instructions of the
+    /// calls in progress
+    fn record_fault(&mut self, module_id: u32, offset: usize) {
+        self.fault_trace.clear();
+        self.fault_trace.push((module_id, offset));
+        for frame in self.frames.iter().rev() {
+            let caller = &frame.caller;
+            let offset = call_site(caller.module(), caller.offset());
+            self.fault_trace.push((caller.module_id(), offset));
+        }
+        if self.snapshot_faults {
+            self.fault_stack = Some(self.stack.clone());
+            self.fault_locals = self.locals().to_vec();
+        }
+    }
+
+    fn execute(&mut self, module_id: u32, symbol_id: u32) -> Result<(), BytecodeError> {
+        match self.begin(module_id, symbol_id)? {
+            Some(entry) => {
+                self.interpret()?;
+                self.finish(&entry)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Start a run of a symbol, without running any of its instructions
+    ///
+    /// Returns the entry point of the run, or `None` if the symbol is a native
+    /// function, which is run to completion.
+    pub(crate) fn begin(
+        &mut self,
+        module_id: u32,
+        symbol_id: u32,
+    ) -> Result<Option<Entry>, BytecodeError> {
+        self.trace_depth = 0;
+        self.halted = None;
+        self.fault_trace.clear();
+        self.fault_stack = None;
+        self.fault_symbol = None;
+        let native = self.native(module_id, symbol_id);
+        if native.is_none() {
+            self.context = self.get_context(module_id, symbol_id)?;
+        }
+        self.frames.clear();
+        self.locals.clear();
+        self.locals_base = 0;
+        self.run_base = self.stack.len();
+        self.watch_stack_from_start();
+
+        let symbol = self.symtabs[module_id as usize].get(symbol_id);
+        let signature = symbol.and_then(|s| s.signature);
+        let base = self.check_inputs(symbol_id, signature)?;
+        self.enter_locals(inst_function!(CALL_EXT_C), symbol.and_then(|s| s.locals))?;
+        self.run_base = base;
+        self.metrics.calls += 1;
+        self.trace_call(module_id, symbol_id);
+        if let Some(native) = native {
+            self.policy.check(Capability::Native)?;
+            native(&mut StackView::new(self, inst_function!(CALL_EXT_C)))?;
+            self.trace_return();
+            self.check_outputs(symbol_id, signature, base)?;
+            return Ok(None);
+        }
+        Ok(Some(Entry { symbol_id, signature, base }))
+    }
+
+    /// Check the results of a run once its entry point has returned
+    pub(crate) fn finish(&self, entry: &Entry) -> Result<(), BytecodeError> {
+        if self.halted.is_some() {
+            return Ok(());
+        }
+        self.check_outputs(entry.symbol_id, entry.signature, entry.base)
+    }
+
+    /// Count a run which ended, unwinding one which faulted and releasing
+    /// its host objects, and report the counters to the metrics sink
+    pub(crate) fn end<T>(&mut self, result: &Result<T, BytecodeError>) {
+        self.watch_stack();
+        if result.is_err() {
+            self.metrics.faults += 1;
+            if self.snapshot_faults && self.fault_stack.is_none() {
+                self.fault_stack = Some(self.stack.clone());
+                self.fault_locals = self.locals().to_vec();
+            }
+            self.stack.truncate(self.run_base);
+            self.frames.clear();
+            self.locals.clear();
+            self.locals_base = 0;
+            self.proven_locals = false;
+            self.context = Engine::empty_context();
+            self.trace_depth = 0;
+            self.handles.clear();
+        }
+        self.release_stack();
+        self.report_metrics();
+    }
+
+    /// Set the hook called when the stack of a run first grows past
+    /// `soft_stack_limit`
+    ///
+    /// Hosts may use this to learn that a program comes close to the limit
+    /// of the stack before it faults there.
+    pub fn set_stack_limit_hook(&mut self, hook: impl FnMut(usize) + 'static) {
+        self.stack_limit_hook = Some(Box::new(hook));
+    }
+
+    /// Get the greatest number of values the stack held during the run in
+    /// progress, or the last run if none is
+    ///
+    /// Like the soft limit, this is measured before every instruction and
+    /// when the run ends, and counts the values on the stack before the run
+    /// started.
+    pub fn stack_high_water(&self) -> usize {
+        self.stack_peak
+    }
+
+    /// Start tracking the depth of the stack for a new run
+    fn watch_stack_from_start(&mut self) {
+        self.stack_peak = self.stack.len();
+        self.stack_alarm = match (self.soft_stack_limit, &self.stack_limit_hook) {
+            (Some(limit), Some(_)) => limit,
+            _ => usize::MAX,
+        };
+    }
+
+    /// Track the depth of the stack, calling the stack limit hook if it has
+    /// grown past the soft limit
+    #[inline]
+    fn watch_stack(&mut self) {
+        let depth = self.stack.len();
+        self.metrics.max_stack = self.metrics.max_stack.max(depth);
+        self.stack_peak = self.stack_peak.max(depth);
+        if depth > self.stack_alarm {
+            self.pass_soft_limit();
+        }
+    }
+
+    #[cold]
+    fn pass_soft_limit(&mut self) {
+        self.stack_alarm = usize::MAX;
+        if let Some(hook) = self.stack_limit_hook.as_mut() {
+            hook(self.stack.len());
+        }
+    }
+
+    /// Set the pool the memory for the stack, calls, and locals of the engine
+    /// is counted against; see the `memory` module
+    pub fn set_memory_pool(&mut self, pool: impl MemoryPool + 'static) {
+        self.memory.set_pool(Box::new(pool));
+    }
+
+    /// Get the number of bytes held by the stack, calls, and locals
+    fn footprint(&self) -> usize {
+        self.stack.capacity() * std::mem::size_of::<u64>()
+            + self.frames.capacity() * std::mem::size_of::<Frame>()
+            + self.locals.capacity() * std::mem::size_of::<u64>()
+    }
+
+    /// Take `bytes` more than the engine holds from its memory pool,
+    /// returning `false` if the pool can not spare them
+    fn acquire(&mut self, bytes: usize) -> bool {
+        let footprint = self.footprint().saturating_add(bytes);
+        self.memory.hold(footprint)
+    }
+
+    /// Check the stack may grow by `count` values, and make room for them
+    #[inline]
+    pub(crate) fn check_stack(&mut self, count: u64) -> bool {
+        (self.maxstack.saturating_sub(self.stack.len()) as u64) >= count
+            && self.reserve_stack(count as usize)
+    }
+
+    /// Get the fault of an instruction for which `check_stack` failed
+    #[cold]
+    pub(crate) fn stack_fault(&self, opcode: u16, count: u64) -> BytecodeError {
+        if (self.maxstack.saturating_sub(self.stack.len()) as u64) < count {
+            BytecodeError::stack_overflow(opcode)
+        } else {
+            BytecodeError::OutOfMemory(opcode)
+        }
+    }
+
+    /// Make room on the stack for `count` more values, growing it as set by
+    /// `stack_growth` if it is full, and returning `false` if the memory
+    /// pool can not spare the room or it can not be allocated
+    #[inline]
+    pub(crate) fn reserve_stack(&mut self, count: usize) -> bool {
+        self.stack.capacity() - self.stack.len() >= count || self.grow_stack(count)
+    }
+
+    #[cold]
+    fn grow_stack(&mut self, count: usize) -> bool {
+        let needed = self.stack.len().saturating_add(count);
+        let capacity = match self.stack_growth {
+            StackGrowth::OnDemand => needed.max(self.stack.capacity() * 2).max(4),
+            StackGrowth::Reserve => self.maxstack.max(needed),
+            StackGrowth::Chunked(chunk) => {
+                let grown = self.stack.capacity().saturating_add(chunk.max(1));
+                grown.clamp(needed, self.maxstack.max(needed))
+            }
+        };
+        // A stack too large to count in bytes is too large to hold
+        let grown = capacity - self.stack.capacity();
+        let bytes = match grown.checked_mul(std::mem::size_of::<u64>()) {
+            Some(bytes) => bytes,
+            None => return false,
+        };
+        if !self.acquire(bytes) {
+            return false;
+        }
+        self.stack.try_reserve_exact(capacity - self.stack.len()).is_ok()
+    }
+
+    /// Make room for another call, returning `false` if the memory pool can
+    /// not spare it or it can not be allocated
+    #[cold]
+    fn grow_frames(&mut self) -> bool {
+        let capacity = (self.frames.capacity() * 2).clamp(4, self.maxframes.max(4));
+        let bytes = (capacity - self.frames.capacity()) * std::mem::size_of::<Frame>();
+        if !self.acquire(bytes) {
+            return false;
+        }
+        self.frames.try_reserve_exact(capacity - self.frames.len()).is_ok()
+    }
+
+    /// Make room for `len` locals, returning `false` if the memory pool can
+    /// not spare it or it can not be allocated
+    #[cold]
+    fn grow_locals(&mut self, len: usize) -> bool {
+        let capacity = len.max(self.locals.capacity() * 2);
+        let bytes = (capacity - self.locals.capacity()) * std::mem::size_of::<u64>();
+        if !self.acquire(bytes) {
+            return false;
+        }
+        self.locals.try_reserve_exact(capacity - self.locals.len()).is_ok()
+    }
+
+    /// Release the room on a chunked stack beyond what the run which ended
+    /// needed, with a chunk spare
+    fn release_stack(&mut self) {
+        if let StackGrowth::Chunked(chunk) = self.stack_growth {
+            let chunk = chunk.max(1);
+            let keep = self.stack_peak.max(self.stack.len()).saturating_add(chunk);
+            if self.stack.capacity() > keep.saturating_add(chunk) {
+                self.stack.shrink_to(keep);
+                self.memory.hold(self.footprint());
+            }
+        }
+    }
+
+    /// Get the locals reserved by the innermost call of the run in progress
+    pub fn locals(&self) -> &[u64] {
+        &self.locals[self.locals_base..]
+    }
+
+    /// Get the exit value given to `HALT` if the last run halted
+    ///
+    /// This is `None` if the last run, started by `run` or `call_ref`, ended
+    /// by returning from its entry point or faulted.
+    pub fn halt_value(&self) -> Option<u64> {
+        self.halted
+    }
+
+    /// Run instructions until the symbol entered last returns
+    ///
+    /// Instructions are run a basic block at a time where possible, with fuel
+    /// and the deadline checked once for the block.
+    fn interpret(&mut self) -> Result<(), BytecodeError> {
+        loop {
+            let done = match self.block_len() {
+                0 => self.step()?,
+                count => self.step_block(count)?,
+            };
+            if done {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Get the number of instructions which may be run as a block from the
+    /// next, or zero if the next must be run on its own
+    ///
+    /// Instructions are run on their own while a trace hook or a metrics
+    /// interval is set, which observe every instruction, while the stack is
+    /// tagged, and while there is too little fuel left to run the whole
+    /// block.
+    fn block_len(&self) -> usize {
+        if self.trace.is_some() || self.metrics_interval != 0 || self.tag_stack {
+            return 0;
+        }
+        let count = match self.blocks.get(self.context.module_id() as usize) {
+            Some(blocks) if blocks.len() == self.context.module().bytecode.len() => {
+                blocks.remaining(self.context.offset())
+            }
+            _ => 0,
+        };
+        match self.fuel {
+            Some(fuel) if fuel < count as u64 => 0,
+            _ => count,
+        }
+    }
+
+    /// Run up to `count` instructions of a basic block, returning `true` if
+    /// the symbol entered last returned or the run halted
+    ///
+    /// The fuel of the whole block is consumed before it is entered, and that
+    /// of any instructions not run is returned if an instruction faults.
+    fn step_block(&mut self, count: usize) -> Result<bool, BytecodeError> {
+        if let Some(deadline) = self.deadline {
+            if self.clock.now() >= deadline {
+                let (module_id, offset) = (self.context.module_id(), self.context.offset());
+                self.record_fault(module_id, offset);
+                let opcode = self.context.module().bytecode[offset];
+                return Err(BytecodeError::DeadlineExceeded(opcode));
+            }
+        }
+        if let Some(fuel) = self.fuel {
+            self.fuel = Some(fuel - count as u64);
+        }
+        let mut ran = 0;
+        let result = loop {
+            let (module_id, offset) = (self.context.module_id(), self.context.offset());
+            self.watch_stack();
+            ran += 1;
+            match self.check_opcode().and_then(|()| self.dispatch()) {
+                Ok(false) if ran < count => (),
+                Ok(done) => break Ok(done),
+                Err(error) => {
+                    self.record_fault(module_id, offset);
+                    break Err(error);
+                }
+            }
+        };
+        self.metrics.instructions += ran as u64;
+        if let Some(fuel) = self.fuel {
+            self.fuel = Some(fuel + (count - ran) as u64);
+            self.metrics.fuel += ran as u64;
+        }
+        result
+    }
+
+    /// Run the next instruction, returning `true` if the symbol entered last
+    /// returned or the run halted
+    ///
+    /// A fault records the location of the instruction. Faults raised by
+    /// bytecode a native function called back into are recorded again when
+    /// they reach the instruction which called the native function.
+    pub(crate) fn step(&mut self) -> Result<bool, BytecodeError> {
+        let (module_id, offset) = (self.context.module_id(), self.context.offset());
+        let result = self.step_instruction();
+        if result.is_err() {
+            self.record_fault(module_id, offset);
+        }
+        result
+    }
+
+    /// Run the next instruction, without recording where faults are raised
+    fn step_instruction(&mut self) -> Result<bool, BytecodeError> {
+        if self.context.has_next() {
+            if let Some(fuel) = self.fuel {
+                if fuel == 0 {
+                    let offset = self.context.offset();
+                    return Err(BytecodeError::OutOfFuel(self.context.module().bytecode[offset]));
+                }
+                self.fuel = Some(fuel - 1);
+                self.metrics.fuel += 1;
+            }
+            if let Some(deadline) = self.deadline {
+                if self.clock.now() >= deadline {
+                    let offset = self.context.offset();
+                    return Err(BytecodeError::DeadlineExceeded(
+                        self.context.module().bytecode[offset],
+                    ));
+                }
+            }
+            self.metrics.instructions += 1;
+            self.watch_stack();
+            if self.metrics_interval != 0
+                && self.metrics.instructions.is_multiple_of(self.metrics_interval)
+            {
+                self.report_metrics();
+            }
+            if let Some(hook) = self.trace.as_mut() {
+                hook(&TraceEvent::Instruction {
+                    module: self.context.module(),
+                    module_id: self.context.module_id(),
+                    offset: self.context.offset(),
+                    stack: &self.stack,
+                });
+            }
+            self.check_opcode()?;
+            if self.tag_stack {
+                self.check_tags()?;
+            }
+            return self.dispatch();
+        }
+        // Running off the end of the bytecode only ends the entry symbol
+        match self.frames.last() {
+            Some(frame) if frame.host => {
+                Err(BytecodeError::InvalidAddress(self.context.module().bytecode.len()))
+            }
+            _ => Ok(true),
+        }
+    }
+
+    /// Check the next instruction is in the instruction set of the engine
+    #[inline]
+    fn check_opcode(&self) -> Result<(), BytecodeError> {
+        match &self.instruction_set {
+            Some(set) => set.check(self.context.module().bytecode[self.context.offset()]),
+            None => Ok(()),
+        }
+    }
+
+    /// Check the tags of the values the next instruction consumes, and tag
+    /// the values it pushes
+    #[cold]
+    fn check_tags(&mut self) -> Result<(), BytecodeError> {
+        self.sync_tags();
+        let code = &self.context.module().bytecode[self.context.offset()..];
+        match Instruction::decode(code) {
+            Ok((instruction, _)) => tags::apply(&instruction, &self.stack, &mut self.tags),
+            // The instruction faults when it is dispatched
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Match the tags to the depth of the stack, tagging values pushed other
+    /// than by instructions as `Tag::Any`
+    pub(crate) fn sync_tags(&mut self) {
+        self.tags.resize(self.stack.len(), Tag::Any);
+    }
+
+    /// Get the tags of the values on the stack, with the top of the stack
+    /// last
+    ///
+    /// The tags are kept while `tag_stack` is set, and are those of the
+    /// stack as of the last instruction run; values the host has pushed
+    /// since are tagged `Tag::Any`.
+    pub fn stack_tags(&self) -> Vec<Tag> {
+        let mut tags = self.tags.clone();
+        tags.resize(self.stack.len(), Tag::Any);
+        tags
+    }
+
+    /// Get the values on the stack, with the top of the stack last
+    ///
+    /// Values are read as the types they are tagged with while `tag_stack`
+    /// is set, and otherwise as `Value::U64`.
+    pub fn stack_values(&self) -> Vec<Value> {
+        match self.tag_stack {
+            true => {
+                let tags = self.stack_tags();
+                self.stack
+                    .iter()
+                    .zip(tags)
+                    .map(|(bits, tag)| Value::from_bits(*bits, tag))
+                    .collect()
+            }
+            false => self.stack.iter().map(|bits| Value::U64(*bits)).collect(),
+        }
+    }
+
+    /// Push a value onto the stack, tagged with its type while `tag_stack`
+    /// is set
+    ///
+    /// Like pushing onto `stack`, this does not check the depth of the stack
+    /// against `maxstack`; the symbol run next faults if it pushes beyond it.
+    pub fn push_value(&mut self, value: impl Into<Value>) {
+        let value = value.into();
+        if self.tag_stack {
+            self.sync_tags();
+            self.tags.push(value.tag());
+        }
+        self.stack.push(value.to_bits());
+    }
+
+    /// Push an unsigned integer onto the stack; see `push_value`
+    pub fn push_u64(&mut self, value: u64) {
+        self.push_value(value);
+    }
+
+    /// Push a signed integer onto the stack; see `push_value`
+    pub fn push_i64(&mut self, value: i64) {
+        self.push_value(value);
+    }
+
+    /// Push a 32 bit float onto the stack, in the low bits of the value; see
+    /// `push_value`
+    pub fn push_f32(&mut self, value: f32) {
+        self.push_value(value);
+    }
+
+    /// Push a 64 bit float onto the stack; see `push_value`
+    pub fn push_f64(&mut self, value: f64) {
+        self.push_value(value);
+    }
+
+    /// Push a boolean onto the stack, as `1` or `0`; see `push_value`
+    pub fn push_bool(&mut self, value: bool) {
+        self.push_value(value);
+    }
+
+    /// Push a function reference onto the stack; see `push_value`
+    pub fn push_func_ref(&mut self, value: FuncRef) {
+        self.push_value(value);
+    }
+
+    /// Pop the value on the top of the stack
+    ///
+    /// Fails with `BytecodeError::StackUnderflow` if the stack is empty. The
+    /// other typed pops read the value as native function arguments are
+    /// read; see `FromStack`.
+    pub fn pop_u64(&mut self) -> Result<u64, BytecodeError> {
+        // No instruction is running, so the fault names opcode 0
+        let value = self.stack.pop().ok_or(BytecodeError::stack_underflow(0, 1))?;
+        if self.tag_stack {
+            self.sync_tags();
+        }
+        Ok(value)
+    }
+
+    /// Pop a signed integer from the top of the stack; see `pop_u64`
+    pub fn pop_i64(&mut self) -> Result<i64, BytecodeError> {
+        self.pop_u64().map(i64::from_stack)
+    }
+
+    /// Pop a 32 bit float from the low bits of the top of the stack; see
+    /// `pop_u64`
+    pub fn pop_f32(&mut self) -> Result<f32, BytecodeError> {
+        self.pop_u64().map(f32::from_stack)
+    }
+
+    /// Pop a 64 bit float from the top of the stack; see `pop_u64`
+    pub fn pop_f64(&mut self) -> Result<f64, BytecodeError> {
+        self.pop_u64().map(f64::from_stack)
+    }
+
+    /// Pop a boolean from the top of the stack, which is `true` unless the
+    /// value is `0`; see `pop_u64`
+    pub fn pop_bool(&mut self) -> Result<bool, BytecodeError> {
+        self.pop_u64().map(bool::from_stack)
+    }
+
+    /// Pop a function reference from the top of the stack; see `pop_u64`
+    pub fn pop_func_ref(&mut self) -> Result<FuncRef, BytecodeError> {
+        self.pop_u64().map(FuncRef::from_stack)
+    }
+
+    /// Decode and run the next instruction, returning `true` if the symbol
+    /// entered last returned or the run halted
+    fn dispatch(&mut self) -> Result<bool, BytecodeError> {
+        emit_log!(
+            trace,
+            "module {} offset {}: {:#06x}",
+            self.context.module_id(),
+            self.context.offset(),
+            self.context.module().bytecode[self.context.offset()]
+        );
+        let opcode = self.context.next().unwrap();
+        match opcode {
+            op::NOP | op::BREAKPOINT => (),
+            op::HALT => {
+                self.halted = Some(popstack1!(self, opcode));
+            }
+            op::PRINT_STACK
+            | op::PRINT_U64
+            | op::PRINT_I64
+            | op::PRINT_F32
+            | op::PRINT_F64
+            | op::ARG_COUNT
+            | op::ARG_GET
+            | op::ENV_GET
+            | op::OPEN
+            | op::READ
+            | op::WRITE
+            | op::CLOSE
+            | op::CONNECT
+            | op::LOG
+            | op::RANDOM
+            | op::RANDOM_BELOW
+            | op::TIME
+            | op::FAULT => self.op_host(opcode)?,
+            op::CONST_0 => pushstack!(self, opcode, 0),
+            op::CONST_1 => pushstack!(self, opcode, 1),
+            op::CONST_2 => pushstack!(self, opcode, 2),
+            op::CONST_3 => pushstack!(self, opcode, 3),
+            op::CONST_4 => pushstack!(self, opcode, 4),
+            op::CONST_8 => pushstack!(self, opcode, 8),
+            op::CONST_16 => pushstack!(self, opcode, 16),
+            op::CONST_32 => pushstack!(self, opcode, 32),
+            op::CONST_64 => pushstack!(self, opcode, 64),
+            op::CONST_128 => pushstack!(self, opcode, 128),
+            op::CONST_N1 => pushstack!(self, opcode, -1_i64),
+            op::CONST_U16 => pushstack!(self, opcode, self.context.cval_u16()?),
+            op::CONST_U32 => pushstack!(self, opcode, self.context.cval_u32()?),
+            op::CONST_U64 => pushstack!(self, opcode, self.context.cval_u64()?),
+            op::CONST_I16 => {
+                pushstack!(self, opcode, ((self.context.cval_u16()? as i16) as i64))
+            }
+            op::CONST_I32 => {
+                pushstack!(self, opcode, ((self.context.cval_u32()? as i32) as i64))
+            }
+            op::DUPE => {
+                let num = popstack1!(self, opcode);
+                checkstack!(self, opcode, num);
+                let base = self.window(opcode, num)?;
+                self.stack.extend_from_within(base..);
+            }
+            op::DUPE_1 => {
+                checkstack!(self, opcode, 1);
+                if self.stack.is_empty() {
+                    return Err(BytecodeError::stack_underflow(opcode, 1));
+                }
+                self.stack.push(self.stack[self.stack.len() - 1]);
+            }
+            op::DUPE_C => {
+                let num = self.context.cval_u16()? as u64;
+                checkstack!(self, opcode, num);
+                let base = self.window(opcode, num)?;
+                self.stack.extend_from_within(base..);
+            }
+            op::SWAP => {
+                let num = popstack1!(self, opcode);
+                let base = self.window(opcode, num)?;
+                self.stack[base..].reverse();
+            }
+            op::SWAP_1 => {
+                let base = self.window(opcode, 2)?;
+                self.stack.swap(base, base + 1);
+            }
+            op::SWAP_C => {
+                let num = self.context.cval_u16()? as u64;
+                let base = self.window(opcode, num)?;
+                self.stack[base..].reverse();
+            }
+            op::ROTATE => {
+                let (position, num) = popstack2!(self, opcode);
+                let base = self.window(opcode, num)?;
+                self.rotate(base, position)?;
+            }
+            op::ROTATE_1 => {
+                let num = popstack1!(self, opcode);
+                let base = self.window(opcode, num)?;
+                self.rotate(base, num)?;
+            }
+            op::ROTATE_C => {
+                let num = self.context.cval_u16()? as u64;
+                let position = popstack1!(self, opcode);
+                let base = self.window(opcode, num)?;
+                self.rotate(base, position)?;
             }
-            bytecode::math::DIV => {
+            op::ROTATE_1_C => {
+                let num = self.context.cval_u16()? as u64;
+                let base = self.window(opcode, num)?;
+                self.rotate(base, num)?;
+            }
+            op::POP => {
+                let num = popstack1!(self, opcode);
+                let base = self.window(opcode, num)?;
+                self.stack.truncate(base);
+            }
+            op::POP_1 => {
+                popstack1!(self, opcode);
+            }
+            op::POP_C => {
+                let num = self.context.cval_u16()? as u64;
+                let base = self.window(opcode, num)?;
+                self.stack.truncate(base);
+            }
+            op::GET_U8
+            | op::GET_U16
+            | op::GET_U32
+            | op::GET_U64
+            | op::GET_I8
+            | op::GET_I16
+            | op::GET_I32
+            | op::GET_F32 => {
+                let index = popstack1!(self, opcode);
+                pushstack!(self, opcode, self.get_local(opcode, index)?);
+            }
+            op::GET_U8_C
+            | op::GET_U16_C
+            | op::GET_U32_C
+            | op::GET_U64_C
+            | op::GET_I8_C
+            | op::GET_I16_C
+            | op::GET_I32_C
+            | op::GET_F32_C => {
+                let index = self.context.cval_u16()? as u64;
+                pushstack!(self, opcode, self.get_local(opcode, index)?);
+            }
+            op::SET_8 | op::SET_16 | op::SET_32 | op::SET_64 | op::SET_F32 => {
+                let (index, local) = popstack2!(self, opcode);
+                self.set_local(opcode, index, local)?;
+            }
+            op::SET_8_C | op::SET_16_C | op::SET_32_C | op::SET_64_C | op::SET_F32_C => {
+                let index = self.context.cval_u16()? as u64;
+                let local = popstack1!(self, opcode);
+                self.set_local(opcode, index, local)?;
+            }
+            op::RESERVE_C => {
+                let count = self.context.cval_u16()? as i16;
+                self.reserve(opcode, count as i64)?;
+            }
+            op::RESERVE_N => {
+                let count = popstack1!(self, opcode);
+                self.reserve(opcode, count as i64)?;
+            }
+            op::DATA_GET => {
+                let index = popstack1!(self, opcode);
+                pushstack!(self, opcode, self.read_data(index)?);
+            }
+            op::DATA_GET_C => {
+                let index = self.context.cval_u16()? as u64;
+                pushstack!(self, opcode, self.read_data(index)?);
+            }
+            op::DATA_RW_GET => {
+                let index = popstack1!(self, opcode);
+                let value = *self.writable_data(index)?;
+                pushstack!(self, opcode, value);
+            }
+            op::DATA_RW_GET_C => {
+                let index = self.context.cval_u16()? as u64;
+                let value = *self.writable_data(index)?;
+                pushstack!(self, opcode, value);
+            }
+            op::DATA_RW_SET => {
+                let (index, value) = popstack2!(self, opcode);
+                *self.writable_data(index)? = value;
+            }
+            op::DATA_RW_SET_C => {
+                let index = self.context.cval_u16()? as u64;
+                let value = popstack1!(self, opcode);
+                *self.writable_data(index)? = value;
+            }
+            op::GLOBAL_GET_C => {
+                let index = self.context.cval_u16()?;
+                let value = *self.global(self.context.module_id(), index as u32)?;
+                pushstack!(self, opcode, value);
+            }
+            op::GLOBAL_SET_C => {
+                let index = self.context.cval_u16()?;
+                let value = popstack1!(self, opcode);
+                *self.global(self.context.module_id(), index as u32)? = value;
+            }
+            op::GLOBAL_EXT_GET_C => {
+                let index = self.context.cval_u16()?;
+                let (module_id, global_id) = self.external_global(index)?;
+                let value = *self.global(module_id, global_id)?;
+                pushstack!(self, opcode, value);
+            }
+            op::GLOBAL_EXT_SET_C => {
+                let index = self.context.cval_u16()?;
+                let (module_id, global_id) = self.external_global(index)?;
+                let value = popstack1!(self, opcode);
+                *self.global(module_id, global_id)? = value;
+            }
+            op::ADD => {
+                let (v1, v2) = popstack2!(self, opcode);
+                self.push_integer(opcode, v1.overflowing_add(v2), u64::MAX)?;
+            }
+            op::ADD_C => {
+                let c = self.context.cval_u16()? as u64;
+                let v = popstack1!(self, opcode);
+                self.push_integer(opcode, c.overflowing_add(v), u64::MAX)?;
+            }
+            op::SUB => {
+                let (v1, v2) = popstack2!(self, opcode);
+                self.push_integer(opcode, v1.overflowing_sub(v2), 0)?;
+            }
+            op::SUB_C => {
+                let c = self.context.cval_u16()? as u64;
+                let v = popstack1!(self, opcode);
+                self.push_integer(opcode, v.overflowing_sub(c), 0)?;
+            }
+            op::MUL => {
+                let (v1, v2) = popstack2!(self, opcode);
+                self.push_integer(opcode, v1.overflowing_mul(v2), u64::MAX)?;
+            }
+            op::MUL_C => {
+                let c = self.context.cval_u16()? as u64;
+                let v = popstack1!(self, opcode);
+                self.push_integer(opcode, v.overflowing_mul(c), u64::MAX)?;
+            }
+            op::DIV => {
                 let (v1, v2) = popstack2!(self, opcode);
-                self.stack.push((Wrapping(v1) / Wrapping(v2)).0);
+                let quotient = v1.checked_div(v2).ok_or(BytecodeError::DivideByZero(opcode))?;
+                self.stack.push(quotient);
             }
-            bytecode::math::DIV_C => {
+            op::DIV_C => {
                 let c = self.context.cval_u16()? as u64;
                 let v = popstack1!(self, opcode);
-                self.stack.push((Wrapping(v) / Wrapping(c)).0);
+                let quotient = v.checked_div(c).ok_or(BytecodeError::DivideByZero(opcode))?;
+                self.stack.push(quotient);
+            }
+            op::MOD => {
+                let (v1, v2) = popstack2!(self, opcode);
+                let remainder = v1.checked_rem(v2).ok_or(BytecodeError::DivideByZero(opcode))?;
+                self.stack.push(remainder);
+            }
+            op::IDIV => {
+                let (v1, v2) = popstack2!(self, opcode);
+                let (v1, v2) = (v1 as i64, v2 as i64);
+                if v2 == 0 {
+                    return Err(BytecodeError::DivideByZero(opcode));
+                }
+                // Only `i64::MIN / -1` overflows, saturating to `i64::MAX`
+                let (quotient, overflowed) = v1.overflowing_div(v2);
+                self.push_integer(opcode, (quotient as u64, overflowed), i64::MAX as u64)?;
+            }
+            op::IMOD => {
+                let (v1, v2) = popstack2!(self, opcode);
+                let (v1, v2) = (v1 as i64, v2 as i64);
+                if v2 == 0 {
+                    return Err(BytecodeError::DivideByZero(opcode));
+                }
+                // The remainder of `i64::MIN % -1` is 0, which does not overflow
+                self.stack.push(v1.wrapping_rem(v2) as u64);
+            }
+            op::FADD => {
+                let (v1, v2) = popstack2!(self, opcode);
+                let (v1, v2) = (f64::from_bits(v1), f64::from_bits(v2));
+                self.push_float(opcode, v1 + v2, &[v1, v2])?;
+            }
+            op::FSUB => {
+                let (v1, v2) = popstack2!(self, opcode);
+                let (v1, v2) = (f64::from_bits(v1), f64::from_bits(v2));
+                self.push_float(opcode, v1 - v2, &[v1, v2])?;
+            }
+            op::FMUL => {
+                let (v1, v2) = popstack2!(self, opcode);
+                let (v1, v2) = (f64::from_bits(v1), f64::from_bits(v2));
+                self.push_float(opcode, v1 * v2, &[v1, v2])?;
+            }
+            op::FDIV => {
+                let (v1, v2) = popstack2!(self, opcode);
+                let (v1, v2) = (f64::from_bits(v1), f64::from_bits(v2));
+                self.push_float(opcode, v1 / v2, &[v1, v2])?;
+            }
+            op::FNEG => {
+                let v = f64::from_bits(popstack1!(self, opcode));
+                self.push_float(opcode, -v, &[v])?;
+            }
+            op::ITOF => {
+                let v = popstack1!(self, opcode);
+                self.stack.push((v as i64 as f64).to_bits());
+            }
+            op::FTOI => {
+                let v = popstack1!(self, opcode);
+                self.stack.push(f64::from_bits(v) as i64 as u64);
+            }
+            op::CALL_C => {
+                let symbol_id = self.context.cval_u16()? as u32;
+                self.call(opcode, self.context.module_id(), symbol_id, false)?;
+            }
+            op::CALL_EXT_C => {
+                let index = self.context.cval_u16()?;
+                let func = self.external_symbol(index)?;
+                self.call(opcode, func.module_id, func.symbol_id, false)?;
+            }
+            op::FUNC_REF_C => {
+                let symbol_id = self.context.cval_u16()? as u32;
+                let func = FuncRef::new(self.context.module_id(), symbol_id);
+                pushstack!(self, opcode, func.value());
+            }
+            op::FUNC_REF_EXT_C => {
+                let index = self.context.cval_u16()?;
+                let func = self.external_symbol(index)?;
+                pushstack!(self, opcode, func.value());
+            }
+            op::CALL_REF => {
+                let func = FuncRef::from_value(popstack1!(self, opcode));
+                if func.module_id != self.context.module_id() {
+                    let exported = self
+                        .symtabs
+                        .get(func.module_id as usize)
+                        .and_then(|symtab| symtab.get(func.symbol_id))
+                        .map(|s| s.exported);
+                    if exported == Some(false) {
+                        let error = BytecodeError::PrivateSymbol(func.symbol_id);
+                        return Err(self.symbol_fault(func.module_id, func.symbol_id, error));
+                    }
+                }
+                self.call(opcode, func.module_id, func.symbol_id, false)?;
+            }
+            op::RETURN => match self.frames.pop() {
+                Some(frame) => {
+                    let module_id = self.context.module_id();
+                    self.check_outputs(frame.symbol_id, frame.signature, frame.base)
+                        .map_err(|error| self.symbol_fault(module_id, frame.symbol_id, error))?;
+                    self.context = frame.caller;
+                    self.locals.truncate(self.locals_base);
+                    self.locals_base = frame.locals;
+                    self.proven_locals = frame.proven;
+                    self.trace_return();
+                    return Ok(frame.host);
+                }
+                None => {
+                    self.trace_return();
+                    return Ok(true);
+                }
+            },
+            _ if opcode >> 8 == groups::JUMP as u16 => self.jump(opcode)?,
+            _ => {
+                // Unimplemented
+                return Err(BytecodeError::BadOpcode(opcode));
+            }
+        }
+        // A halt ends every call, including those made before a native
+        // function called back into bytecode
+        Ok(self.halted.is_some())
+    }
+
+    /// Run a system instruction which reaches out to the host
+    ///
+    /// These are kept out of `dispatch`, as their state would make every
+    /// instruction, and every nested call from a native function, take more
+    /// of the native stack.
+    fn op_host(&mut self, opcode: u16) -> Result<(), BytecodeError> {
+        match opcode {
+            op::PRINT_STACK => {
+                self.policy.check(Capability::Io)?;
+                let _ = writeln!(self.stdout, "Stack: {:?}", self.stack);
+            }
+            op::PRINT_U64 => {
+                self.policy.check(Capability::Io)?;
+                let value = popstack1!(self, opcode);
+                self.print(format_args!("PRINT: {}", value));
+            }
+            op::PRINT_I64 => {
+                self.policy.check(Capability::Io)?;
+                let value = popstack1!(self, opcode);
+                self.print(format_args!("PRINT: {}", value as i64));
+            }
+            op::PRINT_F32 => {
+                self.policy.check(Capability::Io)?;
+                let value = popstack1!(self, opcode);
+                self.print(format_args!("PRINT: {}", f32::from_bits(value as u32)));
+            }
+            op::PRINT_F64 => {
+                self.policy.check(Capability::Io)?;
+                let value = popstack1!(self, opcode);
+                self.print(format_args!("PRINT: {}", f64::from_bits(value)));
+            }
+            op::ARG_COUNT => {
+                self.policy.check(Capability::Syscall)?;
+                pushstack!(self, opcode, self.args.len());
+            }
+            op::ARG_GET => {
+                self.policy.check(Capability::Syscall)?;
+                let capacity = popsingle!(self, opcode, 3);
+                let dest = popsingle!(self, opcode, 3);
+                let index = popsingle!(self, opcode, 3);
+                // The arguments are set aside rather than copied while the
+                // value is written
+                let args = std::mem::take(&mut self.args);
+                let value = usize::try_from(index).ok().and_then(|i| args.get(i));
+                let len = self.write_string(value.map(String::as_str), dest, capacity);
+                self.args = args;
+                pushstack!(self, opcode, len?);
+            }
+            op::ENV_GET => {
+                self.policy.check(Capability::Syscall)?;
+                let capacity = popsingle!(self, opcode, 4);
+                let dest = popsingle!(self, opcode, 4);
+                let size = popsingle!(self, opcode, 4);
+                let name = popsingle!(self, opcode, 4);
+                let name = self.read_bytes(name, size)?;
+                let env = std::mem::take(&mut self.env);
+                let value = std::str::from_utf8(&name).ok().and_then(|n| env.get(n));
+                let len = self.write_string(value.map(String::as_str), dest, capacity);
+                self.env = env;
+                self.scratch = name;
+                pushstack!(self, opcode, len?);
+            }
+            op::OPEN => {
+                self.policy.check(Capability::Io)?;
+                let mode = popsingle!(self, opcode, 3);
+                let size = popsingle!(self, opcode, 3);
+                let path = popsingle!(self, opcode, 3);
+                let path = self.read_bytes(path, size)?;
+                let file = std::str::from_utf8(&path)
+                    .ok()
+                    .zip(OpenMode::from_value(mode))
+                    .and_then(|(path, mode)| {
+                        Some((stream::resolve(&self.preopens, path, mode)?, mode))
+                    })
+                    .and_then(|(path, mode)| stream::open(&path, mode).ok());
+                let handle = match file {
+                    Some(file) => self.insert_stream(file),
+                    None => 0,
+                };
+                pushstack!(self, opcode, handle);
+            }
+            op::READ => {
+                self.policy.check(Capability::Io)?;
+                let capacity = popsingle!(self, opcode, 3);
+                let dest = popsingle!(self, opcode, 3);
+                let handle = popsingle!(self, opcode, 3);
+                // Never read more than fits in the data segment
+                let available = self.data[self.context.module_id() as usize].len() as u64;
+                if capacity > 0 && dest >= available {
+                    return Err(BytecodeError::InvalidData(dest));
+                }
+                let words = capacity.min(available.saturating_sub(dest));
+                let mut buffer = std::mem::take(&mut self.scratch);
+                buffer.clear();
+                buffer.resize(words as usize * 8, 0);
+                let read = self.stream(handle)?.read(&mut buffer);
+                let result = match read {
+                    Ok(read) => self.write_bytes(&buffer[..read], dest, words)?,
+                    Err(_) => u64::MAX,
+                };
+                self.scratch = buffer;
+                pushstack!(self, opcode, result);
+            }
+            op::WRITE => {
+                self.policy.check(Capability::Io)?;
+                let size = popsingle!(self, opcode, 3);
+                let source = popsingle!(self, opcode, 3);
+                let handle = popsingle!(self, opcode, 3);
+                let bytes = self.read_bytes(source, size)?;
+                let result = match self.stream(handle)?.write_all(&bytes) {
+                    Ok(()) => size,
+                    Err(_) => u64::MAX,
+                };
+                self.scratch = bytes;
+                pushstack!(self, opcode, result);
+            }
+            op::CLOSE => {
+                self.policy.check(Capability::Io)?;
+                let handle = popstack1!(self, opcode);
+                if self.handles.remove::<StreamBox>(handle).is_none() {
+                    return Err(BytecodeError::InvalidHandle(handle));
+                }
+            }
+            op::CONNECT => {
+                self.policy.check(Capability::Network)?;
+                let size = popsingle!(self, opcode, 2);
+                let address = popsingle!(self, opcode, 2);
+                let address = self.read_bytes(address, size)?;
+                let connection = std::str::from_utf8(&address)
+                    .ok()
+                    .and_then(|address| std::net::TcpStream::connect(address).ok());
+                let handle = match connection {
+                    Some(connection) => self.insert_stream(connection),
+                    None => 0,
+                };
+                pushstack!(self, opcode, handle);
+            }
+            op::LOG => {
+                self.policy.check(Capability::Io)?;
+                let size = popsingle!(self, opcode, 2);
+                let source = popsingle!(self, opcode, 2);
+                let mut line = self.read_bytes(source, size)?;
+                line.push(b'\n');
+                let _ = self.stderr.write_all(&line);
+                self.scratch = line;
+            }
+            op::RANDOM => {
+                self.policy.check(Capability::Random)?;
+                let value = self.random.next_u64();
+                pushstack!(self, opcode, value);
+            }
+            op::RANDOM_BELOW => {
+                self.policy.check(Capability::Random)?;
+                let bound = popstack1!(self, opcode);
+                let value = self.random_below(bound);
+                pushstack!(self, opcode, value);
+            }
+            op::TIME => {
+                self.policy.check(Capability::Clock)?;
+                let nanos = self.clock.now().as_nanos() as u64;
+                pushstack!(self, opcode, nanos);
+            }
+            op::FAULT => {
+                let index = self.context.cval_u16()? as usize;
+                // Verification ensures the message exists
+                let message = self.context.module().strings.get(index).map(String::from);
+                let message = message.ok_or(BytecodeError::BadOpcode(opcode))?;
+                let count = popsingle!(self, opcode, 2);
+                if (self.stack.len() as u64) <= count {
+                    return Err(BytecodeError::stack_underflow(opcode, count.saturating_add(2)));
+                }
+                let values = self.stack.split_off(self.stack.len() - count as usize);
+                let code = popsingle!(self, opcode, 2);
+                return Err(BytecodeError::UserFault { code, message, values });
             }
             _ => {
                 return Err(BytecodeError::BadOpcode(opcode));
@@ -245,4 +2261,485 @@ impl Engine {
         }
         Ok(())
     }
+
+    /// Check the stack holds the inputs of a symbol, returning the depth of
+    /// the stack below them
+    fn check_inputs(
+        &self,
+        symbol_id: u32,
+        signature: Option<Signature>,
+    ) -> Result<usize, BytecodeError> {
+        match signature {
+            Some(sig) if self.stack.len() < sig.inputs as usize => Err(BytecodeError::bad_inputs(
+                symbol_id,
+                sig.inputs as u64,
+                self.stack.len() as u64,
+            )),
+            Some(sig) => Ok(self.stack.len() - sig.inputs as usize),
+            None => Ok(self.stack.len()),
+        }
+    }
+
+    /// Check a symbol left as many values above `base` as it declares
+    fn check_outputs(
+        &self,
+        symbol_id: u32,
+        signature: Option<Signature>,
+        base: usize,
+    ) -> Result<(), BytecodeError> {
+        let returned = self.stack.len() as i64 - base as i64;
+        match signature {
+            Some(sig) if returned != sig.outputs as i64 => Err(BytecodeError::bad_outputs(
+                symbol_id,
+                sig.outputs as u64,
+                returned.max(0) as u64,
+            )),
+            None if self.strict.unused_values && returned > 0 => {
+                Err(BytecodeError::unused_values(symbol_id, returned as u64))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Run a jump instruction, moving to its target unless it is conditional
+    /// and its condition does not hold
+    ///
+    /// A jump taking its target from the stack pops the target before the
+    /// values its condition tests.
+    fn jump(&mut self, opcode: u16) -> Result<(), BytecodeError> {
+        let data = opcode as u8;
+        let relative = data & jump::MODE_MASK == jump::MODE_RELATIVE;
+        // Relative targets are signed, and are added to the offset following
+        // the jump with wrapping arithmetic
+        let value = match data & jump::SRC_MASK {
+            jump::SRC_C16 if relative => self.context.cval_u16()? as i16 as u64,
+            jump::SRC_C16 => self.context.cval_u16()? as u64,
+            jump::SRC_C32 if relative => self.context.cval_u32()? as i32 as u64,
+            jump::SRC_C32 => self.context.cval_u32()? as u64,
+            jump::SRC_C64 => self.context.cval_u64()?,
+            _ => popstack1!(self, opcode),
+        };
+        if data & jump::CONDITIONAL_MASK == jump::CONDITIONAL_TRUE
+            && !self.condition(opcode, data & jump::TYPE_MASK)?
+        {
+            return Ok(());
+        }
+        let target = match relative {
+            true => (self.context.offset() as u64).wrapping_add(value),
+            false => value,
+        };
+        let target = usize::try_from(target).unwrap_or(usize::MAX);
+        self.context.jump(target)
+    }
+
+    /// Pop the values a jump condition tests, returning if it holds
+    fn condition(&mut self, opcode: u16, condition: u8) -> Result<bool, BytecodeError> {
+        let holds = match condition {
+            jump::TYPE_Z
+            | jump::TYPE_NZ
+            | jump::TYPE_POS
+            | jump::TYPE_NEG
+            | jump::TYPE_GZ
+            | jump::TYPE_LZ => {
+                let value = popstack1!(self, opcode);
+                match condition {
+                    jump::TYPE_Z => value == 0,
+                    jump::TYPE_NZ => value != 0,
+                    jump::TYPE_POS => value as i64 > 0,
+                    jump::TYPE_NEG => (value as i64) < 0,
+                    jump::TYPE_GZ => value as i64 >= 0,
+                    _ => value as i64 <= 0,
+                }
+            }
+            _ => {
+                // The first value is the top of the stack, `stack[-1]`
+                let (a, b) = popstack2!(self, opcode);
+                match condition {
+                    jump::TYPE_EQ => a == b,
+                    jump::TYPE_NEQ => a != b,
+                    jump::TYPE_GT => a > b,
+                    jump::TYPE_GTS => a as i64 > b as i64,
+                    jump::TYPE_LT => a < b,
+                    jump::TYPE_LTS => (a as i64) < b as i64,
+                    jump::TYPE_GE => a >= b,
+                    jump::TYPE_GES => a as i64 >= b as i64,
+                    jump::TYPE_LE => a <= b,
+                    _ => a as i64 <= b as i64,
+                }
+            }
+        };
+        Ok(holds)
+    }
+
+    /// Call a symbol, running it to completion if it is a native function
+    fn call(
+        &mut self,
+        opcode: u16,
+        module_id: u32,
+        symbol_id: u32,
+        host: bool,
+    ) -> Result<(), BytecodeError> {
+        let symbol = match self.symtabs.get(module_id as usize) {
+            Some(symtab) => symtab.get(symbol_id).ok_or(BytecodeError::InvalidSymbol(symbol_id))?,
+            None => return Err(BytecodeError::InvalidModule(module_id)),
+        };
+        let base = self
+            .check_inputs(symbol_id, symbol.signature)
+            .map_err(|error| self.symbol_fault(module_id, symbol_id, error))?;
+        self.metrics.calls += 1;
+        if let Some(native) = symbol.native.then(|| self.native(module_id, symbol_id)).flatten() {
+            self.policy.check(Capability::Native)?;
+            self.trace_call(module_id, symbol_id);
+            native(&mut StackView::new(self, opcode))?;
+            self.trace_return();
+            return self
+                .check_outputs(symbol_id, symbol.signature, base)
+                .map_err(|error| self.symbol_fault(module_id, symbol_id, error));
+        }
+        if self.frames.len() >= self.maxframes {
+            return Err(BytecodeError::FrameOverflow(opcode));
+        }
+        if self.frames.len() == self.frames.capacity() && !self.grow_frames() {
+            return Err(BytecodeError::OutOfMemory(opcode));
+        }
+        let module = Rc::clone(&self.modules[module_id as usize]);
+        let callee = Context::new(module, module_id, symbol.offset as usize)?;
+        let proven = self.proven_locals;
+        self.enter_locals(opcode, symbol.locals)?;
+        self.trace_call(module_id, symbol_id);
+        let caller = std::mem::replace(&mut self.context, callee);
+        let locals = std::mem::replace(&mut self.locals_base, self.locals.len());
+        self.frames.push(Frame {
+            caller,
+            symbol_id,
+            signature: symbol.signature,
+            base,
+            locals,
+            proven,
+            host,
+        });
+        Ok(())
+    }
+
+    /// Call an exported symbol from a native function, returning its results
+    ///
+    /// The arguments are pushed and the symbol is run to completion; the
+    /// values it leaves above the stack depth before the call are popped and
+    /// returned. If the call faults, the calls it made are abandoned and the
+    /// stack is restored to its depth before the call.
+    pub(crate) fn reenter(
+        &mut self,
+        opcode: u16,
+        module_id: u32,
+        symbol_id: u32,
+        args: &[u64],
+    ) -> Result<Vec<u64>, BytecodeError> {
+        if self.reentries >= self.maxreentry {
+            return Err(BytecodeError::FrameOverflow(opcode));
+        }
+        let exported = self
+            .symtabs
+            .get(module_id as usize)
+            .and_then(|symtab| symtab.get(symbol_id))
+            .map(|s| s.exported);
+        match exported {
+            Some(true) => (),
+            Some(false) => {
+                let error = BytecodeError::PrivateSymbol(symbol_id);
+                return Err(self.symbol_fault(module_id, symbol_id, error));
+            }
+            None => return Err(BytecodeError::InvalidSymbol(symbol_id)),
+        }
+        checkstack!(self, opcode, args.len() as u64);
+        let start = self.stack.len();
+        self.stack.extend_from_slice(args);
+
+        let (floor, locals) = (self.frames.len(), self.locals.len());
+        self.reentries += 1;
+        let mut result = self.call(opcode, module_id, symbol_id, true);
+        if result.is_ok() && self.frames.len() > floor {
+            result = self.interpret();
+        }
+        self.reentries -= 1;
+        if let Err(error) = result {
+            if let Some(frame) = self.frames.drain(floor..).next() {
+                self.context = frame.caller;
+                self.locals.truncate(locals);
+                self.locals_base = frame.locals;
+                self.proven_locals = frame.proven;
+            }
+            self.stack.truncate(start);
+            return Err(error);
+        }
+        Ok(self.stack.split_off(start.min(self.stack.len())))
+    }
+
+    /// Get the depth of the stack below its topmost `num` values, which an
+    /// instruction operates on as a whole
+    fn window(&self, opcode: u16, num: u64) -> Result<usize, BytecodeError> {
+        if (self.stack.len() as u64) < num {
+            return Err(BytecodeError::stack_underflow(opcode, num));
+        }
+        // The stack holds at least `num` values, so it fits in a usize
+        Ok(self.stack.len() - num as usize)
+    }
+
+    /// Rotate the values of the stack from `base` so that the one at the
+    /// 1-based `position` among them comes first
+    fn rotate(&mut self, base: usize, position: u64) -> Result<(), BytecodeError> {
+        let window = &mut self.stack[base..];
+        if position == 0 || position > window.len() as u64 {
+            return Err(BytecodeError::InvalidRotation(position));
+        }
+        window.rotate_left(position as usize - 1);
+        Ok(())
+    }
+
+    /// Start the locals of a call of a symbol, making room up front for those
+    /// it is proven to need
+    fn enter_locals(&mut self, opcode: u16, proven: Option<u8>) -> Result<(), BytecodeError> {
+        if let Some(count) = proven {
+            let len = self.locals.len() + count as usize;
+            if len > self.locals.capacity() && !self.grow_locals(len) {
+                return Err(BytecodeError::OutOfMemory(opcode));
+            }
+        }
+        self.proven_locals = proven.is_some();
+        Ok(())
+    }
+
+    /// Find the local holding the value of `bits` bits at a packed index,
+    /// returning its index within the locals of the engine and the shift of
+    /// the value within it
+    fn local_slot(&self, index: u64, bits: u32) -> Result<(usize, u32), BytecodeError> {
+        let packed = 64 / bits as u64;
+        if self.proven_locals {
+            // Verification proved the index within the locals reserved
+            let slot = self.locals_base + (index / packed) as usize;
+            return Ok((slot, (index % packed) as u32 * bits));
+        }
+        let slot = usize::try_from(index / packed)
+            .ok()
+            .map(|slot| self.locals_base.saturating_add(slot))
+            .filter(|&slot| slot < self.locals.len());
+        match slot {
+            Some(slot) => Ok((slot, (index % packed) as u32 * bits)),
+            None => Err(BytecodeError::InvalidLocal(index)),
+        }
+    }
+
+    /// Read a local for the get instruction with the given opcode
+    fn get_local(&self, opcode: u16, index: u64) -> Result<u64, BytecodeError> {
+        let (bits, signed) = match opcode {
+            op::GET_U8 | op::GET_U8_C => (8, false),
+            op::GET_U16 | op::GET_U16_C => (16, false),
+            op::GET_U32 | op::GET_U32_C => (32, false),
+            op::GET_I8 | op::GET_I8_C => (8, true),
+            op::GET_I16 | op::GET_I16_C => (16, true),
+            op::GET_I32 | op::GET_I32_C => (32, true),
+            op::GET_F32 | op::GET_F32_C => (32, false),
+            _ => (64, false),
+        };
+        let (slot, shift) = self.local_slot(index, bits)?;
+        let local = self.locals[slot] >> shift;
+        Ok(match opcode {
+            op::GET_F32 | op::GET_F32_C => self.float_bits(f32::from_bits(local as u32) as f64),
+            _ if bits == 64 => local,
+            _ if signed => (((local << (64 - bits)) as i64) >> (64 - bits)) as u64,
+            _ => local & (u64::MAX >> (64 - bits)),
+        })
+    }
+
+    /// Write a local for the set instruction with the given opcode
+    fn set_local(&mut self, opcode: u16, index: u64, local: u64) -> Result<(), BytecodeError> {
+        let (bits, local) = match opcode {
+            op::SET_8 | op::SET_8_C => (8, local),
+            op::SET_16 | op::SET_16_C => (16, local),
+            op::SET_32 | op::SET_32_C => (32, local),
+            op::SET_F32 | op::SET_F32_C => {
+                let local = f64::from_bits(local) as f32;
+                match self.canonicalize_nan && local.is_nan() {
+                    true => (32, CANONICAL_NAN_F32 as u64),
+                    false => (32, local.to_bits() as u64),
+                }
+            }
+            _ => (64, local),
+        };
+        let (slot, shift) = self.local_slot(index, bits)?;
+        let mask = (u64::MAX >> (64 - bits)) << shift;
+        self.locals[slot] = (self.locals[slot] & !mask) | ((local << shift) & mask);
+        Ok(())
+    }
+
+    /// Extend or reduce the locals reserved by the innermost call
+    fn reserve(&mut self, opcode: u16, count: i64) -> Result<(), BytecodeError> {
+        let reserved = ((self.locals.len() - self.locals_base) as i64).saturating_add(count);
+        if !(0..=MAX_LOCALS as i64).contains(&reserved) {
+            return Err(BytecodeError::InvalidReserve(reserved));
+        }
+        let len = self.locals_base + reserved as usize;
+        if len > self.locals.capacity() && !self.grow_locals(len) {
+            return Err(BytecodeError::OutOfMemory(opcode));
+        }
+        self.locals.resize(len, 0);
+        Ok(())
+    }
+
+    fn read_data(&self, index: u64) -> Result<u64, BytecodeError> {
+        let data = &self.context.module().data;
+        match usize::try_from(index).ok().and_then(|i| data.get(i)) {
+            Some(value) => Ok(*value),
+            None => Err(BytecodeError::InvalidData(index)),
+        }
+    }
+
+    fn writable_data(&mut self, index: u64) -> Result<&mut u64, BytecodeError> {
+        let data = &mut self.data[self.context.module_id() as usize];
+        match usize::try_from(index).ok().and_then(|i| data.get_mut(i)) {
+            Some(value) => Ok(value),
+            None => Err(BytecodeError::InvalidData(index)),
+        }
+    }
+
+    /// Read `size` bytes packed into the writable data segment from `index`
+    ///
+    /// The bytes are read into the scratch buffer of the engine, which the
+    /// caller should give back once it is done with them, so that host
+    /// instructions reuse one buffer rather than allocating their own.
+    fn read_bytes(&mut self, index: u64, size: u64) -> Result<Vec<u8>, BytecodeError> {
+        let mut bytes = std::mem::take(&mut self.scratch);
+        bytes.clear();
+        let data = &self.data[self.context.module_id() as usize];
+        for word in 0..size.div_ceil(8) {
+            let index = index.checked_add(word).ok_or(BytecodeError::InvalidData(index))?;
+            let value = usize::try_from(index).ok().and_then(|i| data.get(i));
+            bytes.extend_from_slice(&value.ok_or(BytecodeError::InvalidData(index))?.to_le_bytes());
+        }
+        bytes.truncate(size as usize);
+        Ok(bytes)
+    }
+
+    /// Write a string packed into at most `capacity` values of the writable
+    /// data segment from `index`, returning its length or `-1` if it is `None`
+    fn write_string(
+        &mut self,
+        value: Option<&str>,
+        index: u64,
+        capacity: u64,
+    ) -> Result<u64, BytecodeError> {
+        match value {
+            Some(value) => self.write_bytes(value.as_bytes(), index, capacity),
+            None => Ok(u64::MAX),
+        }
+    }
+
+    /// Write bytes packed into at most `capacity` values of the writable data
+    /// segment from `index`, returning their length
+    fn write_bytes(
+        &mut self,
+        value: &[u8],
+        index: u64,
+        capacity: u64,
+    ) -> Result<u64, BytecodeError> {
+        for (word, chunk) in value.chunks(8).enumerate() {
+            if word as u64 >= capacity {
+                break;
+            }
+            let mut bytes = [0; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            let index = index.checked_add(word as u64).ok_or(BytecodeError::InvalidData(index))?;
+            *self.writable_data(index)? = u64::from_le_bytes(bytes);
+        }
+        Ok(value.len() as u64)
+    }
+
+    /// Get the stream a handle refers to
+    fn stream(&mut self, handle: u64) -> Result<&mut StreamBox, BytecodeError> {
+        match handle {
+            stream::STDIN => Ok(&mut self.stdin),
+            stream::STDOUT => Ok(&mut self.stdout),
+            stream::STDERR => Ok(&mut self.stderr),
+            _ => self.handles.get_mut(handle).ok_or(BytecodeError::InvalidHandle(handle)),
+        }
+    }
+
+    /// Write a line to the standard output stream
+    ///
+    /// Printing is for debugging, so failing to write is not a fault.
+    pub(crate) fn print(&mut self, line: std::fmt::Arguments) {
+        let _ = writeln!(self.stdout, "{}", line);
+    }
+
+    fn global(&mut self, module_id: u32, global_id: u32) -> Result<&mut u64, BytecodeError> {
+        let globals = match self.globals.get_mut(module_id as usize) {
+            Some(globals) => globals,
+            None => return Err(BytecodeError::InvalidModule(module_id)),
+        };
+        match globals.get_mut(global_id as usize) {
+            Some(value) => Ok(value),
+            None => Err(BytecodeError::InvalidGlobal(global_id)),
+        }
+    }
+
+    fn external_symbol(&self, index: u16) -> Result<FuncRef, BytecodeError> {
+        match self.symtabs[self.context.module_id() as usize].external(index) {
+            Some(func) => Ok(func),
+            None => Err(BytecodeError::InvalidSymbol(index as u32)),
+        }
+    }
+
+    fn external_global(&self, index: u16) -> Result<(u32, u32), BytecodeError> {
+        match self.context.module().external_globals.get(index as usize) {
+            Some(ext) => Ok((ext.module_id, ext.global_id)),
+            None => Err(BytecodeError::InvalidGlobal(index as u32)),
+        }
+    }
+
+    /// Push the result of integer arithmetic and if it wrapped around,
+    /// handling it as set by `overflow` if it did
+    ///
+    /// `bound` is the result saturating arithmetic gives instead.
+    #[inline]
+    fn push_integer(
+        &mut self,
+        opcode: u16,
+        result: (u64, bool),
+        bound: u64,
+    ) -> Result<(), BytecodeError> {
+        let value = match result {
+            (value, false) => value,
+            _ if self.strict.overflow => return Err(BytecodeError::Overflow(opcode)),
+            (value, true) => match self.overflow {
+                Overflow::Wrap => value,
+                Overflow::Saturate => bound,
+                Overflow::Fault => return Err(BytecodeError::Overflow(opcode)),
+            },
+        };
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// Get the bits of a float pushed by an instruction
+    #[inline]
+    fn float_bits(&self, value: f64) -> u64 {
+        match self.canonicalize_nan && value.is_nan() {
+            true => CANONICAL_NAN,
+            false => value.to_bits(),
+        }
+    }
+
+    /// Push the result of floating point arithmetic on the given operands,
+    /// faulting if it is a new NaN in strict mode
+    fn push_float(
+        &mut self,
+        opcode: u16,
+        result: f64,
+        operands: &[f64],
+    ) -> Result<(), BytecodeError> {
+        if result.is_nan() && self.strict.nan && !operands.iter().any(|v| v.is_nan()) {
+            return Err(BytecodeError::NotANumber(opcode));
+        }
+        self.stack.push(self.float_bits(result));
+        Ok(())
+    }
 }
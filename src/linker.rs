@@ -0,0 +1,397 @@
+//! Linking of external symbols between modules
+//!
+//! Modules refer to symbols in other modules by name through their
+//! `ExternalSymbol` table. Before such a symbol can be used, the module and
+//! symbol IDs it refers to must be looked up in the set of loaded modules;
+//! this is the job of the linker.
+//!
+//! Additionally, a static linker is provided by `merge`, which combines several
+//! modules into a single module using their relocation records.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::bytecode::{read_words, write_words};
+use crate::debuginfo::{DebugInfo, LineEntry, LocalName};
+use crate::errors::ModuleError;
+use crate::format::Strings;
+use crate::graph::find_cycle;
+use crate::module::{
+    ExternalGlobal, ExternalSymbol, Global, LocalSymbol, Module, Relocation, RelocationKind,
+};
+
+fn get_string(module: &Module, id: u32) -> Result<&str, ModuleError> {
+    match module.strings.get(id as usize) {
+        Some(s) => Ok(s),
+        None => Err(ModuleError::InvalidString(id)),
+    }
+}
+
+/// Resolve the external symbols of every module in `modules`
+///
+/// The `lookup` table maps module names to indices in `modules`. Each
+/// external symbol has its `module_id` and `symbol_id` fields updated to
+/// point at the symbol it names. If the external symbol has a version
+/// requirement, the module it names must satisfy it; if both the external
+/// symbol and the symbol it names declare a signature, they must match.
+/// External globals are resolved in the same way, and must name an exported
+/// global of the same type. Modules which are shared (e.g. held by a running
+/// context) are cloned before being updated, so any outstanding references
+/// continue to see the old links.
+///
+/// Every import is checked before an error is returned. If a single import
+/// can not be resolved its error is returned as-is, and if several can not be
+/// their errors are returned together as `ModuleError::UnresolvedImports`.
+/// Once all imports resolve, the modules must not depend on each other in a
+/// cycle, though a module may import its own symbols; a cycle is reported as
+/// `ModuleError::DependencyCycle`. The modules are only updated if linking
+/// succeeds.
+pub fn link(modules: &mut [Rc<Module>], lookup: &HashMap<String, u32>) -> Result<(), ModuleError> {
+    let mut errors = Vec::new();
+    let mut links = Vec::with_capacity(modules.len());
+    let mut global_links = Vec::with_capacity(modules.len());
+    for module in modules.iter() {
+        let mut resolved = Vec::with_capacity(module.external_symbols.len());
+        for ext in module.external_symbols.iter() {
+            match resolve(modules, lookup, module, ext) {
+                Ok(ids) => resolved.push(ids),
+                Err(e) => errors.push((module.name.clone(), e)),
+            }
+        }
+        links.push(resolved);
+        let mut resolved = Vec::with_capacity(module.external_globals.len());
+        for ext in module.external_globals.iter() {
+            match resolve_global(modules, lookup, module, ext) {
+                Ok(ids) => resolved.push(ids),
+                Err(e) => errors.push((module.name.clone(), e)),
+            }
+        }
+        global_links.push(resolved);
+    }
+    match errors.len() {
+        0 => (),
+        1 => return Err(errors.pop().unwrap().1),
+        _ => return Err(ModuleError::UnresolvedImports(errors)),
+    }
+
+    let edges: Vec<Vec<usize>> = links
+        .iter()
+        .zip(global_links.iter())
+        .map(|(symbols, globals)| symbols.iter().chain(globals).map(|(m, _)| *m as usize).collect())
+        .collect();
+    if let Some(cycle) = find_cycle(&edges) {
+        return Err(ModuleError::DependencyCycle(
+            cycle.into_iter().map(|i| modules[i].name.clone()).collect(),
+        ));
+    }
+
+    for (index, (resolved, globals)) in links.into_iter().zip(global_links).enumerate() {
+        let unchanged = modules[index]
+            .external_symbols
+            .iter()
+            .zip(resolved.iter())
+            .all(|(ext, (m, s))| ext.module_id == *m && ext.symbol_id == *s)
+            && modules[index]
+                .external_globals
+                .iter()
+                .zip(globals.iter())
+                .all(|(ext, (m, g))| ext.module_id == *m && ext.global_id == *g);
+        if !unchanged {
+            let module = Rc::make_mut(&mut modules[index]);
+            for (ext, (m, s)) in module.external_symbols.iter_mut().zip(resolved) {
+                ext.module_id = m;
+                ext.symbol_id = s;
+            }
+            for (ext, (m, g)) in module.external_globals.iter_mut().zip(globals) {
+                ext.module_id = m;
+                ext.global_id = g;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Find the module and symbol IDs an external symbol of `module` refers to
+fn resolve(
+    modules: &[Rc<Module>],
+    lookup: &HashMap<String, u32>,
+    module: &Module,
+    ext: &ExternalSymbol,
+) -> Result<(u32, u32), ModuleError> {
+    let module_name = get_string(module, ext.module_name_id)?;
+    let symbol_name = get_string(module, ext.symbol_name_id)?;
+    let module_id = match lookup.get(module_name) {
+        Some(id) => *id,
+        None => return Err(ModuleError::UnknownModule(String::from(module_name))),
+    };
+    if let Some(req) = &ext.version_req {
+        let version = modules[module_id as usize].version;
+        if !req.matches(&version) {
+            return Err(ModuleError::VersionMismatch(
+                String::from(module_name),
+                req.to_string(),
+                version.to_string(),
+            ));
+        }
+    }
+    let symbol_id = match modules[module_id as usize].find_symbol(symbol_name) {
+        Some(id) => id,
+        None => {
+            return Err(ModuleError::UnresolvedSymbol(
+                String::from(module_name),
+                String::from(symbol_name),
+            ))
+        }
+    };
+    let symbol = &modules[module_id as usize].local_symbols[symbol_id as usize];
+    if !symbol.exported {
+        return Err(ModuleError::PrivateSymbol(
+            String::from(module_name),
+            String::from(symbol_name),
+        ));
+    }
+    if let (Some(expected), Some(actual)) = (ext.signature, symbol.signature) {
+        if expected != actual {
+            return Err(ModuleError::SignatureMismatch(
+                String::from(module_name),
+                String::from(symbol_name),
+            ));
+        }
+    }
+    Ok((module_id, symbol_id))
+}
+
+/// Find the module and global IDs an external global of `module` refers to
+fn resolve_global(
+    modules: &[Rc<Module>],
+    lookup: &HashMap<String, u32>,
+    module: &Module,
+    ext: &ExternalGlobal,
+) -> Result<(u32, u32), ModuleError> {
+    let module_name = get_string(module, ext.module_name_id)?;
+    let global_name = get_string(module, ext.global_name_id)?;
+    let module_id = match lookup.get(module_name) {
+        Some(id) => *id,
+        None => return Err(ModuleError::UnknownModule(String::from(module_name))),
+    };
+    let target = &modules[module_id as usize];
+    let global_id = match target.find_global(global_name) {
+        Some(id) => id,
+        None => {
+            return Err(ModuleError::UnresolvedGlobal(
+                String::from(module_name),
+                String::from(global_name),
+            ))
+        }
+    };
+    let global = &target.globals[global_id as usize];
+    if !global.exported {
+        return Err(ModuleError::PrivateGlobal(
+            String::from(module_name),
+            String::from(global_name),
+        ));
+    }
+    if global.ty != ext.ty {
+        return Err(ModuleError::GlobalTypeMismatch(
+            String::from(module_name),
+            String::from(global_name),
+        ));
+    }
+    Ok((module_id, global_id))
+}
+
+/// Apply a single relocation to a module, adding `base` to the value
+fn relocate(bytecode: &mut [u16], reloc: &Relocation, base: u64) -> Result<(), ModuleError> {
+    let offset = reloc.offset as usize;
+    let words = reloc.words as usize;
+    if !matches!(words, 1 | 2 | 4) || offset + words > bytecode.len() {
+        return Err(ModuleError::InvalidRelocation(reloc.offset));
+    }
+    let value = match read_words(bytecode, offset, words).checked_add(base) {
+        Some(value) => value,
+        None => return Err(ModuleError::InvalidRelocation(reloc.offset)),
+    };
+    if words < 4 && value >> (16 * words) != 0 {
+        return Err(ModuleError::RelocationOverflow(reloc.offset));
+    }
+    write_words(bytecode, offset, words, value);
+    Ok(())
+}
+
+/// Combine several modules into a single module
+///
+/// The tables and bytecode of each module are concatenated in order, and each
+/// module's relocations are applied so that the values they mark refer to the
+/// same entries in the combined module. The combined module takes the name and
+/// version of the first module given.
+///
+/// External symbols which refer to one of the modules being merged are
+/// rewritten to refer to the combined module instead; their version
+/// requirements are checked at this point. External symbols naming any other
+/// module are kept as-is, to be linked when the combined module is loaded.
+///
+/// Exported symbols must have unique names across all of the merged modules.
+/// Private symbols may share names with each other and with exported symbols,
+/// so only the exported symbols are entered into the `symbol_lookup` of the
+/// combined module, and imports always resolve to them.
+pub fn merge(modules: &[Module]) -> Result<Module, ModuleError> {
+    let first = match modules.first() {
+        Some(m) => m,
+        None => return Err(ModuleError::NoModules),
+    };
+
+    let mut merged = Module {
+        name: first.name.clone(),
+        version: first.version,
+        strings: Strings::new(),
+        data: vec![],
+        writable_data: vec![],
+        local_symbols: vec![],
+        external_symbols: vec![],
+        globals: vec![],
+        external_globals: vec![],
+        bytecode: Cow::Owned(vec![]),
+        relocations: vec![],
+        symbol_lookup: HashMap::new(),
+        debug: None,
+    };
+    let merged_names: HashSet<&str> = modules.iter().map(|m| m.name.as_str()).collect();
+    let mut self_refs = Vec::new();
+    let mut exported = HashSet::new();
+    let mut global_self_refs = Vec::new();
+    let mut exported_globals = HashSet::new();
+
+    for module in modules {
+        let code_base = merged.bytecode.len() as u64;
+        let string_base = merged.strings.len() as u64;
+        let data_base = merged.data.len() as u64;
+        let writable_base = merged.writable_data.len() as u64;
+        let local_base = merged.local_symbols.len() as u64;
+        let external_base = merged.external_symbols.len() as u64;
+        let global_base = merged.globals.len() as u64;
+        let external_global_base = merged.external_globals.len() as u64;
+
+        let mut bytecode = module.bytecode.to_vec();
+        for reloc in module.relocations.iter() {
+            let base = match reloc.kind {
+                RelocationKind::Code => code_base,
+                RelocationKind::LocalSymbol => local_base,
+                RelocationKind::ExternalSymbol => external_base,
+                RelocationKind::String => string_base,
+                RelocationKind::Data => data_base,
+                RelocationKind::WritableData => writable_base,
+                RelocationKind::Global => global_base,
+                RelocationKind::ExternalGlobal => external_global_base,
+            };
+            relocate(&mut bytecode, reloc, base)?;
+            merged.relocations.push(Relocation {
+                offset: reloc.offset + code_base as u32,
+                words: reloc.words,
+                kind: reloc.kind,
+            });
+        }
+        merged.bytecode.to_mut().extend(bytecode);
+        merged.strings.append(&module.strings);
+        merged.data.extend(module.data.iter().cloned());
+        merged.writable_data.extend(module.writable_data.iter().cloned());
+
+        for sym in module.local_symbols.iter() {
+            if sym.exported {
+                let name = get_string(module, sym.name_id)?;
+                if !exported.insert(name) {
+                    return Err(ModuleError::DuplicateSymbol(String::from(name)));
+                }
+                let id = merged.local_symbols.len() as u32;
+                merged.symbol_lookup.insert(String::from(name), id);
+            }
+            merged.local_symbols.push(LocalSymbol {
+                name_id: sym.name_id + string_base as u32,
+                code_offset: sym.code_offset + code_base as u32,
+                exported: sym.exported,
+                signature: sym.signature,
+            });
+        }
+
+        for ext in module.external_symbols.iter() {
+            let module_name = get_string(module, ext.module_name_id)?;
+            let mut ext = ExternalSymbol {
+                module_name_id: ext.module_name_id + string_base as u32,
+                module_id: 0,
+                symbol_name_id: ext.symbol_name_id + string_base as u32,
+                symbol_id: 0,
+                signature: ext.signature,
+                version_req: ext.version_req.clone(),
+                alias: ext.alias.map(|id| id + string_base as u32),
+            };
+            if merged_names.contains(module_name) {
+                let target = modules.iter().find(|m| m.name == module_name).unwrap();
+                if let Some(req) = &ext.version_req {
+                    if !req.matches(&target.version) {
+                        return Err(ModuleError::VersionMismatch(
+                            String::from(module_name),
+                            req.to_string(),
+                            target.version.to_string(),
+                        ));
+                    }
+                }
+                ext.version_req = None;
+                self_refs.push(merged.external_symbols.len());
+            }
+            merged.external_symbols.push(ext);
+        }
+
+        for global in module.globals.iter() {
+            if global.exported {
+                let name = get_string(module, global.name_id)?;
+                if !exported_globals.insert(name) {
+                    return Err(ModuleError::DuplicateSymbol(String::from(name)));
+                }
+            }
+            merged.globals.push(Global { name_id: global.name_id + string_base as u32, ..*global });
+        }
+        for ext in module.external_globals.iter() {
+            let module_name = get_string(module, ext.module_name_id)?;
+            if merged_names.contains(module_name) {
+                global_self_refs.push(merged.external_globals.len());
+            }
+            merged.external_globals.push(ExternalGlobal {
+                module_name_id: ext.module_name_id + string_base as u32,
+                module_id: 0,
+                global_name_id: ext.global_name_id + string_base as u32,
+                global_id: 0,
+                ty: ext.ty,
+            });
+        }
+
+        if let Some(debug) = &module.debug {
+            let merged_debug = merged.debug.get_or_insert_with(DebugInfo::default);
+            merged_debug.lines.extend(debug.lines.iter().map(|e| LineEntry {
+                offset: e.offset + code_base as u32,
+                file_id: e.file_id + string_base as u32,
+                line: e.line,
+                column: e.column,
+            }));
+            merged_debug.locals.extend(debug.locals.iter().map(|l| LocalName {
+                symbol_id: l.symbol_id + local_base as u32,
+                index: l.index,
+                name_id: l.name_id + string_base as u32,
+            }));
+        }
+    }
+
+    // The name of the merged module is only added to the string table once all
+    // of the other strings are in place, so it doesn't disturb their indices.
+    if !self_refs.is_empty() || !global_self_refs.is_empty() {
+        let id = merged.strings.len() as u32;
+        merged.strings.push(merged.name.clone());
+        for index in self_refs {
+            merged.external_symbols[index].module_name_id = id;
+        }
+        for index in global_self_refs {
+            merged.external_globals[index].module_name_id = id;
+        }
+    }
+
+    Ok(merged)
+}
@@ -0,0 +1,189 @@
+//! Checks for suspicious but legal bytecode
+//!
+//! Where `verify` rejects modules which can not run correctly, linting finds
+//! code which runs but is likely not what was meant, and reports it as
+//! warnings:
+//!
+//! * instructions after an unconditional jump, `HALT`, `FAULT`, or `RETURN`
+//!   can never run unless they start a symbol or are the target of a jump,
+//! * a value which is popped straight after being pushed is never used, and
+//! * a division or remainder by a constant zero always faults.
+//!
+//! ```
+//! use std::path::Path;
+//! use tstack::diagnostics::DiagnosticKind;
+//!
+//! let module = tstack::asm::assemble(
+//!     "main",
+//!     ".export main\n CONST_1\n POP_1\n RETURN\n CONST_2",
+//!     Path::new("."),
+//! )
+//! .unwrap();
+//! let kinds: Vec<_> = tstack::lint::lint(&module).iter().map(|d| d.kind).collect();
+//! assert_eq!(kinds, vec![DiagnosticKind::UnusedValue, DiagnosticKind::UnreachableCode]);
+//! ```
+//!
+//! Values are followed through straight-line code only, from one jump, jump
+//! target, or call to the next. Jumps which take their target from the stack
+//! may reach any instruction, so modules with such jumps are not checked for
+//! unreachable code.
+
+use std::collections::HashSet;
+
+use crate::bytecode::{function, groups, jump, math, read_words, stack, sys, Instruction};
+use crate::diagnostics::{Diagnostic, DiagnosticKind, Diagnostics, Severity};
+use crate::module::{Module, RelocationKind};
+use crate::optimize::{constant_value, flow, instruction_len, split};
+
+/// Find the offsets control may reach other than by falling through from the
+/// previous instruction, and if any jump takes its target from the stack
+fn entry_points(module: &Module) -> (HashSet<usize>, bool) {
+    let code = &module.bytecode;
+    let mut entries: HashSet<usize> =
+        module.local_symbols.iter().map(|s| s.code_offset as usize).collect();
+    for reloc in module.relocations.iter().filter(|r| r.kind == RelocationKind::Code) {
+        entries.insert(read_words(code, reloc.offset as usize, reloc.words as usize) as usize);
+    }
+    let mut dynamic = false;
+    let mut offset = 0;
+    while offset < code.len() {
+        let len = instruction_len(code, offset).unwrap_or(1);
+        if split(code[offset]).0 == groups::JUMP {
+            let flow = flow(code, offset, len);
+            dynamic |= flow.dynamic;
+            entries.extend(flow.targets);
+        }
+        offset += len;
+    }
+    (entries, dynamic)
+}
+
+/// Describe a run of instructions which can never run
+fn unreachable(code: &[u16], stop: usize, count: usize) -> String {
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{} instruction{} after {} can never run", count, plural, describe(code, stop))
+}
+
+/// Disassemble the instruction at an offset
+fn describe(code: &[u16], offset: usize) -> String {
+    match Instruction::decode(&code[offset..]) {
+        Ok((instruction, _)) => instruction.to_string(),
+        Err(_) => format!("{:#06x}", code[offset]),
+    }
+}
+
+/// Get the number of values a math instruction pops and pushes, and if it
+/// divides by the value below the top of the stack or by its constant
+///
+/// Returns `None` for instructions which are not followed.
+fn math_effect(data: u8) -> Option<(usize, usize, bool)> {
+    match data {
+        math::ADD | math::SUB | math::MUL => Some((2, 1, false)),
+        math::ADD_C | math::SUB_C | math::MUL_C => Some((1, 1, false)),
+        math::DIV | math::IDIV | math::MOD | math::IMOD => Some((2, 1, true)),
+        math::DIV_C | math::IDIV_C | math::MOD_C | math::IMOD_C => Some((1, 1, true)),
+        math::DIVMOD | math::IDIVMOD => Some((2, 2, true)),
+        math::DIVMOD_C | math::IDIVMOD_C => Some((1, 2, true)),
+        _ => None,
+    }
+}
+
+/// Check a module for suspicious code, returning a warning for each
+/// instance found, in order of offset
+pub fn lint(module: &Module) -> Diagnostics {
+    let code = &module.bytecode;
+    let (entries, dynamic) = entry_points(module);
+    let mut found = Vec::new();
+    let mut warn = |kind, offset: usize, message| {
+        let symbol = module.symbol_at(offset as u32).map(|s| s.id());
+        let diagnostic = Diagnostic::at_offset(kind, Severity::Warning, message, offset as u32);
+        found.push(Diagnostic { symbol, ..diagnostic });
+    };
+
+    // The offsets of the instructions which pushed the topmost values on the
+    // stack, with the values if they are constant
+    let mut values: Vec<(usize, Option<u64>)> = Vec::new();
+    // The offset of the last instruction control can not pass, and the start
+    // and number of the instructions after it which can not run
+    let mut stopped: Option<usize> = None;
+    let mut dead: Option<(usize, usize)> = None;
+    let mut offset = 0;
+    while offset < code.len() {
+        let len = match instruction_len(code, offset) {
+            Some(len) => len,
+            None => {
+                values.clear();
+                offset += 1;
+                continue;
+            }
+        };
+        if entries.contains(&offset) {
+            if let (Some(stop), Some((start, count))) = (stopped, dead.take()) {
+                warn(DiagnosticKind::UnreachableCode, start, unreachable(code, stop, count));
+            }
+            stopped = None;
+            values.clear();
+        }
+        if stopped.is_some() {
+            let (_, count) = dead.get_or_insert((offset, 0));
+            *count += 1;
+            offset += len;
+            continue;
+        }
+
+        let (group, data) = split(code[offset]);
+        if let Some(value) = constant_value(code, offset) {
+            values.push((offset, Some(value)));
+        } else if group == groups::STACK && data == stack::DUPE_1 {
+            let value = values.last().and_then(|v| v.1);
+            values.push((offset, value));
+        } else if group == groups::STACK && matches!(data, stack::POP_1 | stack::POP_C) {
+            let count = if data == stack::POP_1 { 1 } else { code[offset + 1] as usize };
+            for _ in 0..count {
+                match values.pop() {
+                    Some((pushed, _)) => {
+                        let message =
+                            format!("the value pushed by {} is never used", describe(code, pushed));
+                        warn(DiagnosticKind::UnusedValue, pushed, message);
+                    }
+                    None => break,
+                }
+            }
+        } else if let Some((pops, pushes, divides)) =
+            math_effect(data).filter(|_| group == groups::MATH)
+        {
+            let divisor = match pops {
+                1 => Some(code[offset + 1] as u64),
+                _ => values.len().checked_sub(2).and_then(|index| values[index].1),
+            };
+            if divides && divisor == Some(0) {
+                let message = format!("{} divides by a constant zero", describe(code, offset));
+                warn(DiagnosticKind::DivideByZero, offset, message);
+            }
+            match values.len().checked_sub(pops) {
+                Some(len) => values.truncate(len),
+                None => values.clear(),
+            }
+            values.extend(std::iter::repeat_n((offset, None), pushes));
+        } else {
+            values.clear();
+        }
+
+        let returns = group == groups::FUNCTION && data == function::RETURN;
+        let halts = group == groups::SYSTEM && matches!(data, sys::HALT | sys::FAULT);
+        let jumps =
+            group == groups::JUMP && data & jump::CONDITIONAL_MASK != jump::CONDITIONAL_TRUE;
+        if !dynamic && (returns || halts || jumps) {
+            stopped = Some(offset);
+        }
+        offset += len;
+    }
+    if let (Some(stop), Some((start, count))) = (stopped, dead) {
+        warn(DiagnosticKind::UnreachableCode, start, unreachable(code, stop, count));
+    }
+
+    found.sort_by_key(|d| d.offset);
+    let mut diagnostics = Diagnostics::new();
+    diagnostics.extend(found);
+    diagnostics
+}
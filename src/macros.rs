@@ -1,7 +1,8 @@
 macro_rules! checkstack {
     ($engine:expr, $opcode:expr, $count:expr) => {
-        if (($engine.maxstack - $engine.stack.len()) as u64) < $count {
-            return Err(BytecodeError::stack_overflow($opcode));
+        let count: u64 = $count;
+        if !$engine.check_stack(count) {
+            return Err($engine.stack_fault($opcode, count));
         }
     };
 }
@@ -48,3 +49,11 @@ macro_rules! popstack2 {
         (v1, v2)
     }};
 }
+
+/// Emit a record through the `log` crate, if the `log` feature is enabled
+macro_rules! emit_log {
+    ($level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::$level!(target: "tstack", $($arg)+);
+    }};
+}
@@ -0,0 +1,129 @@
+//! Budgets for the memory an engine grows into
+//!
+//! The stack, the calls in progress, and their locals are the memory an engine
+//! grows as it runs. An engine given a `MemoryPool` with
+//! `Engine::set_memory_pool` takes the memory for them from the pool before it
+//! grows them, and gives it back when it releases them or is dropped. An
+//! instruction needing more memory than the pool will give faults with
+//! `BytecodeError::OutOfMemory`, so hosts running many engines may hold them
+//! all to one budget:
+//!
+//! ```
+//! use std::path::Path;
+//! use std::rc::Rc;
+//! use tstack::errors::BytecodeError;
+//! use tstack::memory::Budget;
+//!
+//! let source = ".export main\n .rept 100\n CONST_1\n .endr\n RETURN";
+//! let module = Rc::new(tstack::asm::assemble("main", source, Path::new(".")).unwrap());
+//! let budget = Budget::new(1500);
+//! let mut engines = [tstack::Engine::new(), tstack::Engine::new()];
+//! for engine in engines.iter_mut() {
+//!     engine.add_module(Rc::clone(&module)).unwrap();
+//!     engine.set_memory_pool(budget.clone());
+//! }
+//!
+//! engines[0].run(0, 0).unwrap();
+//! let fault = engines[1].run(0, 0).unwrap_err();
+//! assert!(matches!(fault.error(), BytecodeError::OutOfMemory(_)));
+//! ```
+//!
+//! Pools only account for memory, they do not allocate it: the memory itself
+//! comes from the global allocator, which a host directing memory into pools
+//! of its own replaces with `#[global_allocator]`. Only the stack, the calls
+//! in progress, and their locals are counted against a pool. The modules and
+//! their data segments, handles, symbol tables, compiled blocks, and the
+//! scratch buffers of instructions are allocated without consulting it, so a
+//! budget bounds what running code may grow into rather than everything the
+//! engine holds. Memory the engine holds when it is given a pool is taken from
+//! the pool the next time it grows.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// A budget the memory an engine grows into is counted against
+pub trait MemoryPool {
+    /// Take `bytes` more from the pool, returning `false` if it can not
+    /// spare them
+    fn acquire(&mut self, bytes: usize) -> bool;
+
+    /// Give back `bytes` taken from the pool
+    fn release(&mut self, bytes: usize);
+}
+
+/// A pool of a fixed number of bytes
+///
+/// Clones share the same bytes, so a host may give clones of a budget to
+/// several engines to bound the memory of them all, and keep a clone to see
+/// how much is left.
+#[derive(Clone, Debug, Default)]
+pub struct Budget {
+    remaining: Rc<Cell<usize>>,
+}
+
+impl Budget {
+    /// Create a budget of `bytes` bytes
+    pub fn new(bytes: usize) -> Budget {
+        Budget { remaining: Rc::new(Cell::new(bytes)) }
+    }
+
+    /// Get the number of bytes left in the budget
+    pub fn remaining(&self) -> usize {
+        self.remaining.get()
+    }
+}
+
+impl MemoryPool for Budget {
+    fn acquire(&mut self, bytes: usize) -> bool {
+        match self.remaining.get().checked_sub(bytes) {
+            Some(remaining) => {
+                self.remaining.set(remaining);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn release(&mut self, bytes: usize) {
+        self.remaining.set(self.remaining.get().saturating_add(bytes));
+    }
+}
+
+/// The pool of an engine, and the number of bytes the engine holds from it
+#[derive(Default)]
+pub(crate) struct Memory {
+    pool: Option<Box<dyn MemoryPool>>,
+    held: usize,
+}
+
+impl Memory {
+    /// Replace the pool, giving back what was held from the old one
+    pub(crate) fn set_pool(&mut self, pool: Box<dyn MemoryPool>) {
+        self.hold(0);
+        self.pool = Some(pool);
+    }
+
+    /// Take memory from or give it back to the pool so that `bytes` are
+    /// held, returning `false` if the pool can not spare them
+    pub(crate) fn hold(&mut self, bytes: usize) -> bool {
+        let pool = match self.pool.as_mut() {
+            Some(pool) => pool,
+            None => return true,
+        };
+        if bytes > self.held {
+            if !pool.acquire(bytes - self.held) {
+                return false;
+            }
+        } else {
+            pool.release(self.held - bytes);
+        }
+        self.held = bytes;
+        true
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        self.hold(0);
+    }
+}
@@ -0,0 +1,41 @@
+//! Counters describing the work done by an engine
+//!
+//! An engine counts the instructions it executes, the symbols it calls, the
+//! faults it raises, and the fuel it consumes, and tracks the deepest its
+//! stack has been. The counters are totals over the life of the engine, in
+//! the manner of Prometheus counters, and may be read at any time with
+//! `Engine::metrics`.
+//!
+//! Hosts exporting the counters register a `MetricsSink` with
+//! `Engine::set_metrics_sink`, which is given the counters at the end of
+//! every run, and optionally every so many instructions during a run.
+
+/// The counters of an engine
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// The number of instructions executed
+    pub instructions: u64,
+    /// The number of symbols called, including entry points and native
+    /// functions
+    pub calls: u64,
+    /// The number of runs which ended in a fault
+    pub faults: u64,
+    /// The amount of fuel consumed; see `Engine::fuel`
+    pub fuel: u64,
+    /// The greatest number of values the stack has held
+    pub max_stack: usize,
+}
+
+/// A receiver of the counters of an engine
+///
+/// This is implemented for closures taking the counters.
+pub trait MetricsSink {
+    /// Receive the current counters
+    fn record(&mut self, metrics: &Metrics);
+}
+
+impl<F: FnMut(&Metrics)> MetricsSink for F {
+    fn record(&mut self, metrics: &Metrics) {
+        self(metrics)
+    }
+}
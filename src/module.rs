@@ -9,8 +9,33 @@
 //! A Module in this context is therefore a collection of symbols which may
 //! be executed in some fashion.
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 
+use crate::bytecode::{Instruction, Jump, JumpSource};
+use crate::debuginfo::{DebugInfo, SourceLocation};
+use crate::errors::ModuleError;
+use crate::format::Strings;
+use crate::optimize;
+use crate::version::{Version, VersionReq};
+
+/// The declared stack effect of a symbol
+///
+/// A signature states how many values a symbol consumes from the stack when
+/// it is called, and how many it leaves behind when it returns. Symbols with
+/// a declared signature are checked against it when they are entered and
+/// exited, and the linker ensures external symbols agree with the signature of
+/// the local symbol they bind to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Signature {
+    /// The number of values the symbol takes from the stack
+    pub inputs: u16,
+
+    /// The number of values the symbol leaves on the stack
+    pub outputs: u16,
+}
+
 /// A symbol 'local' to the current module
 ///
 /// The `LocalSymbol` type defines a symbol which is local to the Module it is
@@ -20,7 +45,8 @@ use std::collections::HashMap;
 /// As the module is understood to be the same one it is defined in, it does not
 /// need any definitions for an external module. Additionally, no 'linking' of
 /// the symbol needs to be done.
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LocalSymbol {
     /// The index within the module string table of the symbol name
     pub name_id: u32,
@@ -28,6 +54,16 @@ pub struct LocalSymbol {
     /// The offset within the bytecode of this module that this symbol starts
     /// at
     pub code_offset: u32,
+
+    /// If the symbol may be used from outside of the module
+    ///
+    /// Symbols which are not exported are private helpers of the module; the
+    /// linker will refuse to bind external symbols to them, and the engine
+    /// will refuse to start execution at them.
+    pub exported: bool,
+
+    /// The declared signature of the symbol, if any
+    pub signature: Option<Signature>,
 }
 
 /// A symbol defined in some other module
@@ -46,7 +82,8 @@ pub struct LocalSymbol {
 /// return an error - said error may be ignored, though if the symbol is used by
 /// the bytecode upon execution the virtual machine will fault due to an invalid
 /// module/symbol ID.
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExternalSymbol {
     /// The index within the module string table of the external module name
     pub module_name_id: u32,
@@ -57,24 +94,494 @@ pub struct ExternalSymbol {
     /// The symbol ID of the symbol within an external module, looked up at
     /// runtime
     pub symbol_id: u32,
+    /// The signature the importing module expects the symbol to have, if any
+    pub signature: Option<Signature>,
+    /// The versions of the external module this symbol may be bound from
+    pub version_req: Option<VersionReq>,
+    /// The index within the module string table of the name the importing
+    /// module knows the symbol by, if it differs from the symbol name
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub alias: Option<u32>,
+}
+
+/// The type of the value held by a global
+///
+/// Globals hold a single 64-bit value like any stack element; the type states
+/// how the value is to be interpreted, and must agree between a global and
+/// the modules importing it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GlobalType {
+    /// A 64-bit integer
+    Int,
+    /// A 64-bit floating point value, stored as its bits
+    Float,
+}
+
+/// A named global variable defined by a module
+///
+/// Each engine the module is loaded into gets its own copy of the module's
+/// globals, starting at their initial values. Globals are read and written by
+/// the `GLOBAL_GET_C` and `GLOBAL_SET_C` instructions.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Global {
+    /// The index within the module string table of the global name
+    pub name_id: u32,
+    /// The type of the global
+    pub ty: GlobalType,
+    /// If the global may be imported by other modules
+    pub exported: bool,
+    /// The initial value of the global
+    pub initial: u64,
+}
+
+/// A global defined in some other module
+///
+/// External globals are resolved by name when modules are linked, in the same
+/// way as external symbols, and are read and written by the
+/// `GLOBAL_EXT_GET_C` and `GLOBAL_EXT_SET_C` instructions.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExternalGlobal {
+    /// The index within the module string table of the external module name
+    pub module_name_id: u32,
+    /// The module ID of the global, looked up at runtime
+    pub module_id: u32,
+    /// The index within the module string table of the global name
+    pub global_name_id: u32,
+    /// The ID of the global within the external module, looked up at runtime
+    pub global_id: u32,
+    /// The type the importing module expects the global to have
+    pub ty: GlobalType,
+}
+
+/// The kind of value a relocation refers to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RelocationKind {
+    /// An absolute offset into the bytecode, such as a jump target
+    Code,
+    /// An index into the local symbol table
+    LocalSymbol,
+    /// An index into the external symbol table
+    ExternalSymbol,
+    /// An index into the string table
+    String,
+    /// An index into the read-only data segment
+    Data,
+    /// An index into the writable data segment
+    WritableData,
+    /// An index into the global table
+    Global,
+    /// An index into the external global table
+    ExternalGlobal,
+}
+
+/// A record of a value in the bytecode which depends on the module layout
+///
+/// Relocations mark inline constants which refer to positions in the bytecode
+/// or to entries in one of the module tables. When modules are combined, the
+/// tables and bytecode of each are concatenated; relocations allow the static
+/// linker to adjust every such value by the position its table ended up at.
+///
+/// The value is stored in `words` consecutive bytecode values starting at
+/// `offset`, most significant word first, as read by the `CONST_U16`,
+/// `CONST_U32`, and `CONST_U64` instructions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Relocation {
+    /// The offset within the bytecode of the first word of the value
+    pub offset: u32,
+    /// The number of bytecode words the value occupies; one of 1, 2, or 4
+    pub words: u8,
+    /// What the value refers to
+    pub kind: RelocationKind,
+}
+
+/// The definition of a local symbol built by `Module::from_instructions`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SymbolDef {
+    /// The name of the symbol
+    pub name: String,
+    /// If the symbol may be used from outside of the module
+    pub exported: bool,
+    /// The declared signature of the symbol, if any
+    pub signature: Option<Signature>,
+}
+
+impl SymbolDef {
+    /// Define a symbol which is not exported and has no signature
+    pub fn new(name: &str) -> SymbolDef {
+        SymbolDef { name: String::from(name), exported: false, signature: None }
+    }
 }
 
 /// A collection of symbols and the supporting data for running them
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Module {
     /// The name of the module
+    ///
+    /// Names may be namespaced, as in `path.like.name`; see `is_valid_name`.
     pub name: String,
+    /// The version of the module
+    pub version: Version,
     /// The constant string table used by the module
-    pub strings: Vec<String>,
-    /// A collection of constant data values
+    ///
+    /// Strings are decoded as they are used, so a module read from the binary
+    /// format does not pay for strings it never looks at.
+    pub strings: Strings<'static>,
+    /// The read-only data segment
+    ///
+    /// Constant data is shared by every engine the module is loaded into, and
+    /// can only be read by the `DATA_GET` instructions.
     pub data: Vec<u64>,
+    /// The initial values of the writable data segment
+    ///
+    /// Each engine the module is loaded into gets its own copy of these values,
+    /// which the `DATA_RW_GET` and `DATA_RW_SET` instructions operate on. The
+    /// copy is only taken when the engine first writes to it.
+    pub writable_data: Vec<u64>,
     /// The collection of internal symbols that the module defines
     pub local_symbols: Vec<LocalSymbol>,
     /// The collection of external symbols that the module uses
     pub external_symbols: Vec<ExternalSymbol>,
+    /// The globals the module defines
+    pub globals: Vec<Global>,
+    /// The globals the module imports from other modules
+    pub external_globals: Vec<ExternalGlobal>,
     /// The bytecode as a flat array of u16 opcodes
-    pub bytecode: Vec<u16>,
+    ///
+    /// Modules read with `format::read_static` borrow their bytecode from the
+    /// buffer they were read from when it is laid out as native `u16` values;
+    /// it is copied the first time it is modified.
+    pub bytecode: Cow<'static, [u16]>,
+    /// Records of layout dependent values in the bytecode
+    pub relocations: Vec<Relocation>,
 
     /// A symbol lookup table, used to link modules together after loading them
     pub symbol_lookup: HashMap<String, u32>,
+
+    /// Optional debug information mapping the module back to its source
+    pub debug: Option<DebugInfo>,
+}
+
+/// A view of a local symbol within its module
+#[derive(Clone, Copy, Debug)]
+pub struct Symbol<'a> {
+    module: &'a Module,
+    id: u32,
+}
+
+impl<'a> Symbol<'a> {
+    /// Get the ID of the symbol within its module
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Get the name of the symbol
+    ///
+    /// Returns `None` if the name is not a valid string ID.
+    pub fn name(&self) -> Option<&'a str> {
+        self.module.strings.get(self.local().name_id as usize)
+    }
+
+    /// Get the offset of the start of the symbol in the bytecode
+    pub fn offset(&self) -> u32 {
+        self.local().code_offset
+    }
+
+    /// Get the length of the symbol's code in words
+    ///
+    /// A symbol is taken to extend up to the start of the next symbol in the
+    /// bytecode, or to the end of the bytecode.
+    pub fn len(&self) -> usize {
+        let start = self.offset();
+        let end = self
+            .module
+            .local_symbols
+            .iter()
+            .map(|s| s.code_offset)
+            .filter(|offset| *offset > start)
+            .min()
+            .map(|offset| offset as usize)
+            .unwrap_or(self.module.bytecode.len());
+        end.saturating_sub(start as usize)
+    }
+
+    /// Check if the symbol has no code
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the code of the symbol
+    pub fn code(&self) -> &'a [u16] {
+        let start = (self.offset() as usize).min(self.module.bytecode.len());
+        &self.module.bytecode[start..start + self.len()]
+    }
+
+    /// Check if the symbol may be used from outside of its module
+    pub fn exported(&self) -> bool {
+        self.local().exported
+    }
+
+    /// Get the declared signature of the symbol
+    pub fn signature(&self) -> Option<Signature> {
+        self.local().signature
+    }
+
+    fn local(&self) -> &'a LocalSymbol {
+        &self.module.local_symbols[self.id as usize]
+    }
+}
+
+impl Module {
+    /// Construct an empty module with the given name
+    pub fn new(name: &str) -> Module {
+        Module {
+            name: String::from(name),
+            version: Default::default(),
+            strings: Strings::new(),
+            data: vec![],
+            writable_data: vec![],
+            local_symbols: vec![],
+            external_symbols: vec![],
+            globals: vec![],
+            external_globals: vec![],
+            bytecode: Cow::Owned(vec![]),
+            relocations: vec![],
+            symbol_lookup: HashMap::new(),
+            debug: None,
+        }
+    }
+
+    /// Construct a module from the definitions and instructions of its symbols
+    ///
+    /// Symbols are laid out in order, with their IDs given by their position.
+    /// Absolute jumps with a constant target are taken to be relative to the
+    /// start of their symbol, and are adjusted to the offset the symbol ends up
+    /// at; relocations are recorded for these jumps and for the operands of
+    /// `CALL_C`, `CALL_EXT_C`, `FUNC_REF_C` and `FUNC_REF_EXT_C`. The external
+    /// symbols used are not declared, and must be added to the module before
+    /// it is used.
+    pub fn from_instructions(
+        name: &str,
+        symbols: impl IntoIterator<Item = (SymbolDef, Vec<Instruction>)>,
+    ) -> Result<Module, ModuleError> {
+        let mut module = Module::new(name);
+        for (def, instructions) in symbols {
+            if module.strings.contains(&def.name) {
+                return Err(ModuleError::DuplicateSymbol(def.name));
+            }
+            let start = module.bytecode.len() as u64;
+            module.strings.push(def.name);
+            module.local_symbols.push(LocalSymbol {
+                name_id: module.strings.len() as u32 - 1,
+                code_offset: start as u32,
+                exported: def.exported,
+                signature: def.signature,
+            });
+            for instruction in instructions {
+                let operand = module.bytecode.len() as u32 + 1;
+                let (instruction, relocation) = match instruction {
+                    Instruction::CallC(_) | Instruction::FuncRefC(_) => {
+                        (instruction, Some((1, RelocationKind::LocalSymbol)))
+                    }
+                    Instruction::CallExtC(_) | Instruction::FuncRefExtC(_) => {
+                        (instruction, Some((1, RelocationKind::ExternalSymbol)))
+                    }
+                    Instruction::Jump(Jump { source, relative: false, condition }) => {
+                        let source = match source {
+                            JumpSource::C16(v) => u16::try_from(v as u64 + start)
+                                .map(JumpSource::C16)
+                                .map_err(|_| ModuleError::RelocationOverflow(operand))?,
+                            JumpSource::C32(v) => u32::try_from(v as u64 + start)
+                                .map(JumpSource::C32)
+                                .map_err(|_| ModuleError::RelocationOverflow(operand))?,
+                            JumpSource::C64(v) => JumpSource::C64(v.wrapping_add(start)),
+                            JumpSource::Dynamic => JumpSource::Dynamic,
+                        };
+                        let relocation = match source {
+                            JumpSource::C16(_) => Some((1, RelocationKind::Code)),
+                            JumpSource::C32(_) => Some((2, RelocationKind::Code)),
+                            JumpSource::C64(_) => Some((4, RelocationKind::Code)),
+                            JumpSource::Dynamic => None,
+                        };
+                        (Instruction::Jump(Jump { source, relative: false, condition }), relocation)
+                    }
+                    _ => (instruction, None),
+                };
+                if let Some((words, kind)) = relocation {
+                    module.relocations.push(Relocation { offset: operand, words, kind });
+                }
+                instruction.encode(module.bytecode.to_mut());
+            }
+        }
+        Ok(module)
+    }
+
+    /// Iterate over the local symbols of the module
+    pub fn symbols(&self) -> impl ExactSizeIterator<Item = Symbol<'_>> {
+        (0..self.local_symbols.len()).map(move |id| Symbol { module: self, id: id as u32 })
+    }
+
+    /// Get the local symbol with the given ID
+    pub fn symbol(&self, id: u32) -> Option<Symbol<'_>> {
+        if (id as usize) < self.local_symbols.len() {
+            Some(Symbol { module: self, id })
+        } else {
+            None
+        }
+    }
+
+    /// Get the local symbol the code at an offset in the bytecode belongs to
+    pub fn symbol_at(&self, offset: u32) -> Option<Symbol<'_>> {
+        self.symbols().find(|s| s.offset() <= offset && offset < s.offset() + s.len() as u32)
+    }
+
+    /// Look up the ID of a local symbol by name
+    ///
+    /// The `symbol_lookup` table is consulted first; if the symbol is not
+    /// present there, the local symbols are scanned and their names resolved
+    /// through the string table.
+    pub fn find_symbol(&self, name: &str) -> Option<u32> {
+        if let Some(id) = self.symbol_lookup.get(name) {
+            return Some(*id);
+        }
+        self.local_symbols
+            .iter()
+            .position(|s| self.strings.get(s.name_id as usize).map(|n| n == name).unwrap_or(false))
+            .map(|id| id as u32)
+    }
+
+    /// Build a table of the names of the local symbols, mapped to their IDs
+    ///
+    /// Each name maps to the ID `find_symbol` finds for it.
+    pub fn symbol_index(&self) -> HashMap<String, u32> {
+        let mut index = HashMap::with_capacity(self.local_symbols.len());
+        for (id, symbol) in self.local_symbols.iter().enumerate() {
+            if let Some(name) = self.strings.get(symbol.name_id as usize) {
+                index.entry(String::from(name)).or_insert(id as u32);
+            }
+        }
+        index.extend(self.symbol_lookup.iter().map(|(name, id)| (name.clone(), *id)));
+        index
+    }
+
+    /// Look up the ID of an external symbol by the name it is imported as
+    ///
+    /// External symbols with an alias are found by their alias, and all
+    /// others by their symbol name.
+    pub fn find_import(&self, name: &str) -> Option<u32> {
+        self.external_symbols
+            .iter()
+            .position(|s| {
+                let id = s.alias.unwrap_or(s.symbol_name_id);
+                self.strings.get(id as usize).map(|n| n == name).unwrap_or(false)
+            })
+            .map(|id| id as u32)
+    }
+
+    /// Look up the ID of a global by name
+    pub fn find_global(&self, name: &str) -> Option<u32> {
+        self.globals
+            .iter()
+            .position(|g| self.strings.get(g.name_id as usize).map(|n| n == name).unwrap_or(false))
+            .map(|id| id as u32)
+    }
+
+    /// Look up the source location of the given bytecode offset
+    ///
+    /// Returns `None` if the module carries no debug information, the offset
+    /// is not covered by the line table, or the file name is not a valid
+    /// string ID.
+    pub fn source_location(&self, offset: u32) -> Option<SourceLocation<'_>> {
+        let entry = self.debug.as_ref()?.line_entry(offset)?;
+        let file = self.strings.get(entry.file_id as usize)?;
+        Some(SourceLocation { file, line: entry.line, column: entry.column })
+    }
+
+    /// Disassemble the instruction at an offset in the bytecode
+    ///
+    /// Operands which refer to a symbol, global, or string of the module are
+    /// followed by what they refer to as a comment, as in
+    /// `CALL_EXT_C 0  ; lib.add`, where it can be found. Words which do not
+    /// decode to an instruction are given in hexadecimal.
+    pub fn disassemble(&self, offset: usize) -> String {
+        let instruction = match self.bytecode.get(offset..).map(Instruction::decode) {
+            Some(Ok((instruction, _))) => instruction,
+            _ => return format!("{:#06x}", self.bytecode.get(offset).copied().unwrap_or(0)),
+        };
+        let string = |id: u32| self.strings.get(id as usize);
+        let qualified =
+            |module: u32, name: u32| Some(format!("{}.{}", string(module)?, string(name)?));
+        let name = match instruction {
+            Instruction::CallC(id) | Instruction::FuncRefC(id) => {
+                self.symbol(id as u32).and_then(|s| s.name()).map(String::from)
+            }
+            Instruction::CallExtC(id) | Instruction::FuncRefExtC(id) => {
+                let ext = self.external_symbols.get(id as usize);
+                ext.and_then(|e| qualified(e.module_name_id, e.symbol_name_id))
+            }
+            Instruction::GlobalGetC(id) | Instruction::GlobalSetC(id) => {
+                self.globals.get(id as usize).and_then(|g| string(g.name_id)).map(String::from)
+            }
+            Instruction::GlobalExtGetC(id) | Instruction::GlobalExtSetC(id) => {
+                let ext = self.external_globals.get(id as usize);
+                ext.and_then(|e| qualified(e.module_name_id, e.global_name_id))
+            }
+            Instruction::Fault(id) => string(id as u32).map(|s| format!("{:?}", s)),
+            _ => None,
+        };
+        match name {
+            Some(name) => format!("{}  ; {}", instruction, name),
+            None => instruction.to_string(),
+        }
+    }
+
+    /// Produce an optimized copy of the module
+    ///
+    /// The optimization level selects the passes which are run:
+    ///
+    /// * `0` performs no optimization,
+    /// * `1` folds constant expressions and applies the built-in peephole
+    ///   rewrites, and
+    /// * `2` and above additionally remove dead code, keeping every exported
+    ///   symbol and everything reachable from them.
+    pub fn optimize(&self, level: u8) -> Result<Module, ModuleError> {
+        if level == 0 {
+            return Ok(self.clone());
+        }
+        let module = optimize::fold_constants(self)?;
+        let module = optimize::Peephole::with_builtins().run(&module)?;
+        if level == 1 {
+            return Ok(module);
+        }
+        let roots: Vec<u32> = module
+            .local_symbols
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.exported)
+            .map(|(id, _)| id as u32)
+            .collect();
+        optimize::eliminate_dead_code(&module, &roots)
+    }
+
+    /// Look up the name of a local of the given symbol
+    pub fn local_name(&self, symbol_id: u32, index: u32) -> Option<&str> {
+        let name_id = self.debug.as_ref()?.local_name_id(symbol_id, index)?;
+        self.strings.get(name_id as usize)
+    }
+}
+
+/// Check if a name is valid as a module name or module alias
+///
+/// Names are one or more segments separated by `.`, such as `std.io`; each
+/// segment is made up of ASCII letters, digits, `_`, and `-`.
+pub fn is_valid_name(name: &str) -> bool {
+    name.split('.').all(|segment| {
+        !segment.is_empty()
+            && segment.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    })
 }
@@ -0,0 +1,469 @@
+//! Native functions callable from bytecode
+//!
+//! A host registers native functions with `Engine::register_native`, naming
+//! the module and symbol they are found under. Native modules are loaded into
+//! the engine like any other module, so bytecode calls a native function by
+//! importing it as an external symbol and calling it with `CALL_EXT_C`:
+//!
+//! ```
+//! use tstack::native::StackView;
+//!
+//! let mut engine = tstack::Engine::new();
+//! engine
+//!     .register_native("host", "square", |stack: &mut StackView| {
+//!         let value = stack.pop()?;
+//!         stack.push(value * value)
+//!     })
+//!     .unwrap();
+//! ```
+//!
+//! A native function takes its arguments from the stack and pushes its
+//! results, in the same way as a symbol implemented in bytecode.
+//!
+//! Functions with typed arguments and results may be registered with
+//! `Engine::register_fn` instead, which pops and pushes the values and
+//! converts them with the `FromStack` and `ToStack` traits:
+//!
+//! ```
+//! let mut engine = tstack::Engine::new();
+//! engine.register_fn("host", "scale", |x: u64, factor: f64| (x as f64 * factor) as i64).unwrap();
+//! ```
+//!
+//! Values are converted as the instructions operating on them would:
+//!
+//!| Type                      | Conversion
+//!|---------------------------|-----------
+//!| `u64 i64 usize`           | The value as is, reinterpreted as the type
+//!| `u32 u16 u8 i32 i16 i8`   | The low bits of the value; signed values are sign extended when pushed
+//!| `bool`                    | `true` for any value other than `0`, and pushed as `1` or `0`
+//!| `f64`                     | The bits of the value
+//!| `f32`                     | The low 32 bits of the value
+//!| `FuncRef`                 | The module and symbol IDs packed into the value
+//!
+//! A function may return a single value, `()` to push nothing, or a tuple of
+//! up to four values, which are pushed in order. Any of these may be wrapped
+//! in a `Result` to fault the calling code; the error may be anything which
+//! converts into a `BytecodeError`, and strings become `BytecodeError::NativeError`.
+//!
+//! The `native_fn!` macro wraps such a function into the signature taken by
+//! `Engine::register_native`, for hosts which need the wrapped function
+//! itself:
+//!
+//! ```
+//! use tstack::native_fn;
+//!
+//! fn parse(digit: u8) -> Result<u64, String> {
+//!     (digit as char).to_digit(10).map(u64::from).ok_or(format!("{} is not a digit", digit))
+//! }
+//!
+//! let mut engine = tstack::Engine::new();
+//! engine.register_native("host", "parse", native_fn!(parse)).unwrap();
+//! engine.register_native("host", "add", native_fn!(|a: u64, b: u64| a + b)).unwrap();
+//! ```
+//!
+//! Bytecode may hand a function of its own to the host as a `FuncRef`, pushed
+//! with `FUNC_REF_C` or `FUNC_REF_EXT_C`. A native function can store the
+//! reference, such as to register an event handler, and later call it with
+//! `StackView::call_ref` or `Engine::call_ref`.
+
+use std::any::Any;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::errors::BytecodeError;
+use crate::host::Capability;
+use crate::module::Signature;
+use crate::stream::Stream;
+use crate::{Engine, Value};
+
+/// A function implemented by the host
+pub type NativeFn = Rc<dyn Fn(&mut StackView) -> Result<(), BytecodeError>>;
+
+/// The view of the engine given to a native function
+///
+/// Values are popped and pushed through the view with the same checks as
+/// instructions; a native function which pops from an empty stack or grows
+/// the stack beyond its maximum depth faults.
+pub struct StackView<'a> {
+    engine: &'a mut Engine,
+    /// The opcode of the instruction calling the function, used in faults
+    opcode: u16,
+}
+
+impl<'a> StackView<'a> {
+    pub(crate) fn new(engine: &'a mut Engine, opcode: u16) -> StackView<'a> {
+        StackView { engine, opcode }
+    }
+
+    /// Get the number of values on the stack
+    pub fn len(&self) -> usize {
+        self.engine.stack.len()
+    }
+
+    /// Check if the stack is empty
+    pub fn is_empty(&self) -> bool {
+        self.engine.stack.is_empty()
+    }
+
+    /// Get the values on the stack, with the top of the stack last
+    pub fn values(&self) -> &[u64] {
+        &self.engine.stack
+    }
+
+    /// Get the value `depth` values below the top of the stack
+    pub fn peek(&self, depth: usize) -> Option<u64> {
+        let len = self.engine.stack.len();
+        if depth < len {
+            Some(self.engine.stack[len - depth - 1])
+        } else {
+            None
+        }
+    }
+
+    /// Pop the value on the top of the stack
+    pub fn pop(&mut self) -> Result<u64, BytecodeError> {
+        match self.engine.stack.pop() {
+            Some(value) => {
+                self.sync_tags();
+                Ok(value)
+            }
+            None => Err(BytecodeError::stack_underflow(self.opcode, 1)),
+        }
+    }
+
+    /// Pop `N` values from the stack, returning them in the order they were
+    /// pushed
+    pub fn pop_many<const N: usize>(&mut self) -> Result<[u64; N], BytecodeError> {
+        let len = self.engine.stack.len();
+        if len < N {
+            return Err(BytecodeError::stack_underflow(self.opcode, N as u64));
+        }
+        let mut values = [0; N];
+        values.copy_from_slice(&self.engine.stack[len - N..]);
+        self.engine.stack.truncate(len - N);
+        self.sync_tags();
+        Ok(values)
+    }
+
+    /// Push a value onto the stack
+    pub fn push(&mut self, value: u64) -> Result<(), BytecodeError> {
+        if self.engine.stack.len() >= self.engine.maxstack {
+            return Err(BytecodeError::stack_overflow(self.opcode));
+        }
+        if !self.engine.reserve_stack(1) {
+            return Err(BytecodeError::OutOfMemory(self.opcode));
+        }
+        self.engine.stack.push(value);
+        self.sync_tags();
+        Ok(())
+    }
+
+    /// Keep the tags of the stack in step with the values popped and pushed,
+    /// tagging those pushed as `Tag::Any`
+    fn sync_tags(&mut self) {
+        if self.engine.tag_stack {
+            self.engine.sync_tags();
+        }
+    }
+
+    /// Look up a symbol of a loaded module by name
+    ///
+    /// See `Engine::find_symbol`.
+    pub fn find_symbol(&self, module: &str, symbol: &str) -> Option<FuncRef> {
+        self.engine.find_symbol(module, symbol)
+    }
+
+    /// Call an exported symbol by name, returning its results
+    ///
+    /// The arguments are pushed in order and the symbol is run to completion
+    /// before this returns, with the values it leaves above the current depth
+    /// of the stack popped and returned. This allows native functions to call
+    /// back into bytecode, such as to run a comparison function given by the
+    /// caller. Calls nest like any other, and fault with
+    /// `BytecodeError::FrameOverflow` beyond the limits of the engine; if the
+    /// called symbol faults, the stack is restored to its depth before the
+    /// call.
+    ///
+    /// A symbol called often may be looked up once with `find_symbol` and
+    /// called with `call_ref`, rather than by name.
+    pub fn call(
+        &mut self,
+        module: &str,
+        symbol: &str,
+        args: &[u64],
+    ) -> Result<Vec<u64>, BytecodeError> {
+        let unresolved =
+            || BytecodeError::UnresolvedSymbol(String::from(module), String::from(symbol));
+        let func = self.engine.find_symbol(module, symbol).ok_or_else(unresolved)?;
+        self.call_ref(func, args)
+    }
+
+    /// Call an exported symbol by module and symbol ID, returning its results
+    ///
+    /// See `call`.
+    pub fn call_id(
+        &mut self,
+        module_id: u32,
+        symbol_id: u32,
+        args: &[u64],
+    ) -> Result<Vec<u64>, BytecodeError> {
+        self.engine.reenter(self.opcode, module_id, symbol_id, args)
+    }
+
+    /// Call the symbol a function reference refers to, returning its results
+    ///
+    /// See `call`.
+    pub fn call_ref(&mut self, func: FuncRef, args: &[u64]) -> Result<Vec<u64>, BytecodeError> {
+        self.call_id(func.module_id, func.symbol_id, args)
+    }
+
+    /// Get the time read from the clock of the engine
+    ///
+    /// Faults with `BytecodeError::PermissionDenied` unless the policy of the
+    /// engine grants `Capability::Clock`.
+    pub fn now(&self) -> Result<Duration, BytecodeError> {
+        self.engine.policy.check(Capability::Clock)?;
+        Ok(self.engine.clock.now())
+    }
+
+    /// Draw a value from the random source of the engine
+    ///
+    /// Faults unless the policy of the engine grants `Capability::Random`.
+    pub fn random(&mut self) -> Result<u64, BytecodeError> {
+        self.engine.policy.check(Capability::Random)?;
+        Ok(self.engine.random.next_u64())
+    }
+
+    /// Write a line to the standard output stream of the engine
+    ///
+    /// Faults unless the policy of the engine grants `Capability::Io`.
+    pub fn write_line(&mut self, line: &str) -> Result<(), BytecodeError> {
+        self.engine.policy.check(Capability::Io)?;
+        self.engine.print(format_args!("{}", line));
+        Ok(())
+    }
+
+    /// Add a host object to the handle table of the engine, returning its
+    /// handle
+    pub fn insert_handle<T: Any>(&mut self, value: T) -> u64 {
+        self.engine.insert_handle(value)
+    }
+
+    /// Add a stream to the handle table of the engine, returning its handle
+    pub fn insert_stream(&mut self, stream: impl Stream + 'static) -> u64 {
+        self.engine.insert_stream(stream)
+    }
+
+    /// Get the host object a handle refers to
+    ///
+    /// Faults with `BytecodeError::InvalidHandle` if the handle is invalid or
+    /// the object is not a `T`.
+    pub fn get_handle<T: Any>(&self, handle: u64) -> Result<&T, BytecodeError> {
+        self.engine.get_handle(handle).ok_or(BytecodeError::InvalidHandle(handle))
+    }
+
+    /// Get the host object a handle refers to mutably
+    pub fn get_handle_mut<T: Any>(&mut self, handle: u64) -> Result<&mut T, BytecodeError> {
+        self.engine.get_handle_mut(handle).ok_or(BytecodeError::InvalidHandle(handle))
+    }
+
+    /// Remove a host object from the handle table of the engine, returning it
+    pub fn remove_handle<T: Any>(&mut self, handle: u64) -> Result<T, BytecodeError> {
+        self.engine.remove_handle(handle).ok_or(BytecodeError::InvalidHandle(handle))
+    }
+}
+
+/// A reference to a symbol, which may be passed between bytecode and the host
+///
+/// On the stack, a reference packs the ID of the module into the high 32 bits
+/// and the ID of the symbol into the low 32 bits. References are not checked
+/// until they are called, so a reference to a module which has since been
+/// replaced calls the symbol with the same ID in the new module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FuncRef {
+    pub module_id: u32,
+    pub symbol_id: u32,
+}
+
+impl FuncRef {
+    /// Create a reference to a symbol
+    pub fn new(module_id: u32, symbol_id: u32) -> FuncRef {
+        FuncRef { module_id, symbol_id }
+    }
+
+    /// Read a reference from a stack value
+    pub fn from_value(value: u64) -> FuncRef {
+        FuncRef { module_id: (value >> 32) as u32, symbol_id: value as u32 }
+    }
+
+    /// Get the stack value of the reference
+    pub fn value(self) -> u64 {
+        ((self.module_id as u64) << 32) | self.symbol_id as u64
+    }
+}
+
+/// A type which can be taken from a stack value
+pub trait FromStack: Sized {
+    /// Convert a stack value into the type
+    fn from_stack(value: u64) -> Self;
+}
+
+/// A type which can be pushed onto the stack as zero or more values
+pub trait ToStack {
+    /// The number of values pushed
+    const COUNT: u16;
+
+    /// Push the values onto the stack
+    fn to_stack(self, stack: &mut StackView) -> Result<(), BytecodeError>;
+}
+
+macro_rules! impl_scalar {
+    ($($ty:ty => $from:expr, $to:expr;)*) => {
+        $(
+            impl FromStack for $ty {
+                fn from_stack(value: u64) -> Self {
+                    $from(value)
+                }
+            }
+
+            impl ToStack for $ty {
+                const COUNT: u16 = 1;
+
+                fn to_stack(self, stack: &mut StackView) -> Result<(), BytecodeError> {
+                    stack.push($to(self))
+                }
+            }
+        )*
+    };
+}
+
+impl_scalar! {
+    u64 => |v| v, |v| v;
+    i64 => |v| v as i64, |v| v as u64;
+    usize => |v| v as usize, |v| v as u64;
+    u32 => |v| v as u32, |v| v as u64;
+    u16 => |v| v as u16, |v| v as u64;
+    u8 => |v| v as u8, |v| v as u64;
+    i32 => |v| v as i32, |v| v as i64 as u64;
+    i16 => |v| v as i16, |v| v as i64 as u64;
+    i8 => |v| v as i8, |v| v as i64 as u64;
+    bool => |v| v != 0, |v| v as u64;
+    f64 => f64::from_bits, f64::to_bits;
+    f32 => |v| f32::from_bits(v as u32), |v: f32| v.to_bits() as u64;
+}
+
+impl FromStack for FuncRef {
+    fn from_stack(value: u64) -> Self {
+        FuncRef::from_value(value)
+    }
+}
+
+impl ToStack for FuncRef {
+    const COUNT: u16 = 1;
+
+    fn to_stack(self, stack: &mut StackView) -> Result<(), BytecodeError> {
+        stack.push(self.value())
+    }
+}
+
+impl ToStack for Value {
+    const COUNT: u16 = 1;
+
+    fn to_stack(self, stack: &mut StackView) -> Result<(), BytecodeError> {
+        stack.push(self.to_bits())
+    }
+}
+
+impl ToStack for () {
+    const COUNT: u16 = 0;
+
+    fn to_stack(self, _: &mut StackView) -> Result<(), BytecodeError> {
+        Ok(())
+    }
+}
+
+impl<T: ToStack, E: Into<BytecodeError>> ToStack for Result<T, E> {
+    const COUNT: u16 = T::COUNT;
+
+    fn to_stack(self, stack: &mut StackView) -> Result<(), BytecodeError> {
+        self.map_err(Into::into)?.to_stack(stack)
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($ty:ident $value:ident),+) => {
+        impl<$($ty: ToStack),+> ToStack for ($($ty,)+) {
+            const COUNT: u16 = 0 $(+ $ty::COUNT)+;
+
+            fn to_stack(self, stack: &mut StackView) -> Result<(), BytecodeError> {
+                let ($($value,)+) = self;
+                $($value.to_stack(stack)?;)+
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_tuple!(A a, B b);
+impl_tuple!(A a, B b, C c);
+impl_tuple!(A a, B b, C c, D d);
+
+/// A function which can be registered with `Engine::register_fn`
+///
+/// This is implemented for functions and closures of up to eight arguments
+/// implementing `FromStack`, returning a type implementing `ToStack`. `Args`
+/// is the tuple of the argument types.
+pub trait NativeFunction<Args> {
+    /// Get the signature of the function
+    fn signature(&self) -> Signature;
+
+    /// Pop the arguments of the function, call it, and push its results
+    fn call(&self, stack: &mut StackView) -> Result<(), BytecodeError>;
+}
+
+macro_rules! impl_native_function {
+    ($count:literal $(, $ty:ident $value:ident)*) => {
+        impl<Func, Ret, $($ty),*> NativeFunction<($($ty,)*)> for Func
+        where
+            Func: Fn($($ty),*) -> Ret,
+            Ret: ToStack,
+            $($ty: FromStack,)*
+        {
+            fn signature(&self) -> Signature {
+                Signature { inputs: $count, outputs: Ret::COUNT }
+            }
+
+            fn call(&self, stack: &mut StackView) -> Result<(), BytecodeError> {
+                let [$($value),*] = stack.pop_many::<$count>()?;
+                self($($ty::from_stack($value)),*).to_stack(stack)
+            }
+        }
+    };
+}
+
+impl_native_function!(0);
+impl_native_function!(1, A a);
+impl_native_function!(2, A a, B b);
+impl_native_function!(3, A a, B b, C c);
+impl_native_function!(4, A a, B b, C c, D d);
+impl_native_function!(5, A a, B b, C c, D d, E e);
+impl_native_function!(6, A a, B b, C c, D d, E e, F f);
+impl_native_function!(7, A a, B b, C c, D d, E e, F f, G g);
+impl_native_function!(8, A a, B b, C c, D d, E e, F f, G g, H h);
+
+/// Wrap a typed function into a native function
+///
+/// The function may be any function or closure implementing
+/// `NativeFunction`, such as `fn(u64, f64) -> i64`; closures must declare the
+/// types of their arguments. The result is a closure which may be given to
+/// `Engine::register_native`, popping the arguments, calling the function,
+/// and pushing its results or converting its error into a fault.
+#[macro_export]
+macro_rules! native_fn {
+    ($function:expr) => {{
+        let function = $function;
+        move |stack: &mut $crate::native::StackView| -> ::std::result::Result<(), $crate::errors::BytecodeError> {
+            $crate::native::NativeFunction::call(&function, stack)
+        }
+    }};
+}
@@ -0,0 +1,1005 @@
+//! Optimization passes over modules
+//!
+//! Each pass takes a module and produces a new, equivalent module. Passes rely
+//! on the relocation records of a module to find the values in the bytecode
+//! which refer to symbols, strings, data, and code offsets; a module whose
+//! relocations are incomplete may be broken by optimization.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+use crate::bytecode::{self, groups, jump, math, read_words, stack, sys, write_words, Instruction};
+use crate::debuginfo::{LineEntry, LocalName};
+use crate::errors::ModuleError;
+use crate::format::Strings;
+use crate::inst_stack;
+use crate::module::{Module, Relocation, RelocationKind};
+
+/// The control flow out of a single instruction
+pub(crate) struct Flow {
+    /// The offsets the instruction may transfer control to, other than the
+    /// following instruction
+    pub(crate) targets: Vec<usize>,
+    /// If execution may continue with the following instruction
+    pub(crate) falls_through: bool,
+    /// If the instruction may transfer control to an unknown location
+    pub(crate) dynamic: bool,
+}
+
+/// Get the length in words of the instruction at `offset`
+///
+/// Returns `None` if the opcode is not a defined instruction or its operands
+/// run past the end of the bytecode.
+pub(crate) fn instruction_len(code: &[u16], offset: usize) -> Option<usize> {
+    let len = 1 + bytecode::operand_count(code[offset])?;
+    if offset + len > code.len() {
+        return None;
+    }
+    Some(len)
+}
+
+/// Decode the target of a jump with a constant source
+///
+/// Relative jumps are relative to the offset following the jump instruction.
+/// Returns `None` for jumps with a dynamic source, or targets which are out of
+/// range.
+fn jump_target(code: &[u16], offset: usize, len: usize) -> Option<usize> {
+    let (_, data) = split(code[offset]);
+    let words = len - 1;
+    if data & jump::SRC_MASK == jump::SRC_DYN {
+        return None;
+    }
+    let value = read_words(code, offset + 1, words);
+    if data & jump::MODE_MASK == jump::MODE_RELATIVE {
+        let delta = match words {
+            1 => value as u16 as i16 as i64,
+            2 => value as u32 as i32 as i64,
+            _ => value as i64,
+        };
+        usize::try_from((offset + len) as i64 + delta).ok()
+    } else {
+        usize::try_from(value).ok()
+    }
+}
+
+/// Get the group and data bytes of an opcode
+pub(crate) fn split(opcode: u16) -> (u8, u8) {
+    (
+        ((opcode & bytecode::GROUP_MASK) >> bytecode::GROUP_SHIFT) as u8,
+        ((opcode & bytecode::DATA_MASK) >> bytecode::DATA_SHIFT) as u8,
+    )
+}
+
+pub(crate) fn flow(code: &[u16], offset: usize, len: usize) -> Flow {
+    let (group, data) = split(code[offset]);
+    match group {
+        groups::SYSTEM if matches!(data, bytecode::sys::HALT | bytecode::sys::FAULT) => {
+            Flow { targets: vec![], falls_through: false, dynamic: false }
+        }
+        groups::JUMP => {
+            let conditional = data & jump::CONDITIONAL_MASK == jump::CONDITIONAL_TRUE;
+            match jump_target(code, offset, len) {
+                Some(target) => {
+                    Flow { targets: vec![target], falls_through: conditional, dynamic: false }
+                }
+                None => Flow { targets: vec![], falls_through: conditional, dynamic: true },
+            }
+        }
+        _ => Flow { targets: vec![], falls_through: true, dynamic: false },
+    }
+}
+
+fn check_relocations(module: &Module) -> Result<(), ModuleError> {
+    for reloc in module.relocations.iter() {
+        if !matches!(reloc.words, 1 | 2 | 4)
+            || reloc.offset as usize + reloc.words as usize > module.bytecode.len()
+        {
+            return Err(ModuleError::InvalidRelocation(reloc.offset));
+        }
+    }
+    Ok(())
+}
+
+/// Find the offsets at which control may enter the bytecode other than by
+/// falling through from the previous instruction, and the offsets of every
+/// word covered by a relocation
+///
+/// Symbol starts, jump targets, Code relocation targets, and the instruction
+/// following each jump are all treated as entry points.
+fn boundaries(module: &Module) -> (HashSet<usize>, HashSet<usize>) {
+    let code = &module.bytecode;
+    let mut relocated = HashSet::new();
+    let mut boundaries: HashSet<usize> =
+        module.local_symbols.iter().map(|s| s.code_offset as usize).collect();
+    for reloc in module.relocations.iter() {
+        let (offset, words) = (reloc.offset as usize, reloc.words as usize);
+        relocated.extend(offset..offset + words);
+        if reloc.kind == RelocationKind::Code {
+            boundaries.insert(read_words(code, offset, words) as usize);
+        }
+    }
+    let mut offset = 0;
+    while offset < code.len() {
+        let len = instruction_len(code, offset).unwrap_or(1);
+        if split(code[offset]).0 == groups::JUMP {
+            boundaries.extend(flow(code, offset, len).targets);
+            boundaries.insert(offset + len);
+        }
+        offset += len;
+    }
+    (boundaries, relocated)
+}
+
+/// A replacement of a range of bytecode
+///
+/// Edits always start at an instruction boundary. Any jumps within the
+/// replacement code target offsets in the original bytecode, with relative
+/// jumps interpreted as if the code was placed at `offset`; relocations of
+/// values within the replaced range are dropped.
+struct Edit {
+    offset: usize,
+    len: usize,
+    code: Vec<u16>,
+}
+
+/// Apply a sorted, non-overlapping list of edits to the bytecode of a module
+///
+/// Everything in the module which refers to code offsets (symbols, jumps,
+/// relocations, and debug info) is updated to refer to the same positions in
+/// the new bytecode. Offsets within a replaced range map to the start of its
+/// replacement.
+fn apply_edits(module: &Module, edits: &[Edit]) -> Module {
+    let code = &module.bytecode;
+    let mut offset_map = vec![0u32; code.len() + 1];
+    let mut removed = vec![false; code.len()];
+    let mut new_code = Vec::with_capacity(code.len());
+    // The original target, new offset, and length of each constant jump
+    let mut jumps = Vec::new();
+    let mut patched = HashSet::new();
+
+    let mut edits = edits.iter().peekable();
+    let mut offset = 0;
+    while offset < code.len() {
+        if let Some(edit) = edits.next_if(|e| e.offset == offset) {
+            offset_map[offset..offset + edit.len].fill(new_code.len() as u32);
+            removed[offset..offset + edit.len].fill(true);
+            let mut pos = 0;
+            while pos < edit.code.len() {
+                let len = instruction_len(&edit.code, pos).unwrap_or(1);
+                if split(edit.code[pos]).0 == groups::JUMP && len > 1 {
+                    if let Some(target) = jump_target(&edit.code, pos, len) {
+                        let (_, data) = split(edit.code[pos]);
+                        let target = if data & jump::MODE_MASK == jump::MODE_RELATIVE {
+                            target + edit.offset
+                        } else {
+                            target
+                        };
+                        jumps.push((target, new_code.len() + pos, len));
+                    }
+                }
+                pos += len;
+            }
+            new_code.extend_from_slice(&edit.code);
+            offset += edit.len;
+            continue;
+        }
+
+        let len = instruction_len(code, offset).unwrap_or(1);
+        if split(code[offset]).0 == groups::JUMP && len > 1 {
+            if let Some(target) = jump_target(code, offset, len) {
+                jumps.push((target, new_code.len(), len));
+                patched.insert(offset + 1);
+            }
+        }
+        for (i, o) in offset_map[offset..offset + len].iter_mut().enumerate() {
+            *o = (new_code.len() + i) as u32;
+        }
+        new_code.extend_from_slice(&code[offset..offset + len]);
+        offset += len;
+    }
+    offset_map[code.len()] = new_code.len() as u32;
+
+    for (target, new_offset, len) in jumps {
+        if target > code.len() {
+            continue;
+        }
+        let (_, data) = split(new_code[new_offset]);
+        let new_target = offset_map[target] as i64;
+        let value = if data & jump::MODE_MASK == jump::MODE_RELATIVE {
+            new_target - (new_offset + len) as i64
+        } else {
+            new_target
+        };
+        write_words(&mut new_code, new_offset + 1, len - 1, value as u64);
+    }
+
+    let mut relocations = Vec::new();
+    for reloc in module.relocations.iter() {
+        let old = reloc.offset as usize;
+        if removed[old] {
+            continue;
+        }
+        let new = offset_map[old] as usize;
+        if reloc.kind == RelocationKind::Code && !patched.contains(&old) {
+            let words = reloc.words as usize;
+            let value = read_words(code, old, words) as usize;
+            if value <= code.len() {
+                write_words(&mut new_code, new, words, offset_map[value] as u64);
+            }
+        }
+        relocations.push(Relocation { offset: new as u32, ..*reloc });
+    }
+
+    let mut result = module.clone();
+    for symbol in result.local_symbols.iter_mut() {
+        symbol.code_offset = offset_map[(symbol.code_offset as usize).min(code.len())];
+    }
+    if let Some(debug) = result.debug.as_mut() {
+        let mut lines: Vec<LineEntry> = Vec::new();
+        for entry in debug.lines.iter() {
+            let offset = offset_map[(entry.offset as usize).min(code.len())];
+            if offset as usize >= new_code.len() {
+                continue;
+            }
+            if lines.last().map(|l| l.offset == offset).unwrap_or(false) {
+                lines.pop();
+            }
+            lines.push(LineEntry { offset, ..entry.clone() });
+        }
+        debug.lines = lines;
+    }
+    result.bytecode = Cow::Owned(new_code);
+    result.relocations = relocations;
+    result
+}
+
+/// Remove symbols, bytecode, strings, and data which can not be used
+///
+/// Starting from the given root symbols, every instruction which may be
+/// executed is found by following the control flow of the bytecode, including
+/// the fall-through from one symbol into the next. Code offsets and local
+/// symbols referenced through relocations of reachable instructions are
+/// followed as well. Everything which is not reached is dropped from the
+/// returned module:
+///
+/// * local symbols which are neither roots nor referenced,
+/// * instructions which can not be executed,
+/// * strings which are not referenced by a remaining symbol, relocation, or
+///   debug entry, and
+/// * data values in either segment which are not referenced by a remaining
+///   relocation.
+///
+/// External symbols are always kept. If a reachable jump takes its target from
+/// the stack, the bytecode is kept as-is, as the set of reachable instructions
+/// can not be determined; symbols, strings, and data are still removed.
+pub fn eliminate_dead_code(module: &Module, roots: &[u32]) -> Result<Module, ModuleError> {
+    check_relocations(module)?;
+    let code = &module.bytecode;
+    let relocs: HashMap<usize, &Relocation> =
+        module.relocations.iter().map(|r| (r.offset as usize, r)).collect();
+
+    let mut live_symbols = HashSet::new();
+    let mut worklist = Vec::new();
+    for root in roots {
+        let symbol = match module.local_symbols.get(*root as usize) {
+            Some(s) => s,
+            None => return Err(ModuleError::InvalidSymbol(*root)),
+        };
+        live_symbols.insert(*root);
+        worklist.push(symbol.code_offset as usize);
+    }
+
+    // Walk the control flow, recording the length of each reachable
+    // instruction.
+    let mut reached: Vec<Option<usize>> = vec![None; code.len()];
+    let mut dynamic = false;
+    while let Some(offset) = worklist.pop() {
+        if offset >= code.len() || reached[offset].is_some() {
+            continue;
+        }
+        let len = match instruction_len(code, offset) {
+            Some(len) => len,
+            None => {
+                // The engine faults on this instruction, so nothing past it is
+                // reachable from here.
+                reached[offset] = Some(1);
+                continue;
+            }
+        };
+        reached[offset] = Some(len);
+
+        for word in offset + 1..offset + len {
+            if let Some(reloc) = relocs.get(&word) {
+                let value = read_words(code, word, reloc.words as usize);
+                match reloc.kind {
+                    RelocationKind::Code => worklist.push(value as usize),
+                    RelocationKind::LocalSymbol => {
+                        if let Some(symbol) = module.local_symbols.get(value as usize) {
+                            live_symbols.insert(value as u32);
+                            worklist.push(symbol.code_offset as usize);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        let flow = flow(code, offset, len);
+        dynamic |= flow.dynamic;
+        worklist.extend(flow.targets);
+        if flow.falls_through {
+            worklist.push(offset + len);
+        }
+    }
+
+    // Without knowing every jump target, fall back to keeping all of the code
+    // and treating every relocation as live.
+    let module = if dynamic {
+        for reloc in module.relocations.iter() {
+            if reloc.kind == RelocationKind::LocalSymbol {
+                let value = read_words(code, reloc.offset as usize, reloc.words as usize);
+                live_symbols.insert(value as u32);
+            }
+        }
+        module.clone()
+    } else {
+        let mut edits: Vec<Edit> = Vec::new();
+        let mut offset = 0;
+        while offset < code.len() {
+            match reached[offset] {
+                Some(len) => offset += len,
+                None => {
+                    match edits.last_mut() {
+                        Some(edit) if edit.offset + edit.len == offset => edit.len += 1,
+                        _ => edits.push(Edit { offset, len: 1, code: vec![] }),
+                    }
+                    offset += 1;
+                }
+            }
+        }
+        apply_edits(module, &edits)
+    };
+    compact_tables(module, &live_symbols)
+}
+
+/// Drop local symbols which are not live, and any strings and data which are
+/// no longer referenced, renumbering everything which remains
+fn compact_tables(mut module: Module, live_symbols: &HashSet<u32>) -> Result<Module, ModuleError> {
+    let mut symbol_map = HashMap::new();
+    let mut symbols = Vec::new();
+    for (id, symbol) in module.local_symbols.iter().enumerate() {
+        if live_symbols.contains(&(id as u32)) {
+            symbol_map.insert(id as u32, symbols.len() as u32);
+            symbols.push(symbol.clone());
+        }
+    }
+
+    let mut used_strings: HashSet<u32> = symbols.iter().map(|s| s.name_id).collect();
+    let mut used_data = HashSet::new();
+    let mut used_writable = HashSet::new();
+    for ext in module.external_symbols.iter() {
+        used_strings.insert(ext.module_name_id);
+        used_strings.insert(ext.symbol_name_id);
+        used_strings.extend(ext.alias);
+    }
+    // Globals are kept, as they may be imported by other modules
+    used_strings.extend(module.globals.iter().map(|g| g.name_id));
+    for ext in module.external_globals.iter() {
+        used_strings.insert(ext.module_name_id);
+        used_strings.insert(ext.global_name_id);
+    }
+    for reloc in module.relocations.iter() {
+        let value =
+            read_words(&module.bytecode, reloc.offset as usize, reloc.words as usize) as u32;
+        match reloc.kind {
+            RelocationKind::String => {
+                used_strings.insert(value);
+            }
+            RelocationKind::Data => {
+                used_data.insert(value);
+            }
+            RelocationKind::WritableData => {
+                used_writable.insert(value);
+            }
+            _ => (),
+        }
+    }
+    if let Some(debug) = &module.debug {
+        used_strings.extend(debug.lines.iter().map(|e| e.file_id));
+        for local in debug.locals.iter() {
+            if live_symbols.contains(&local.symbol_id) {
+                used_strings.insert(local.name_id);
+            }
+        }
+    }
+
+    let mut string_map = HashMap::new();
+    let mut strings = Strings::new();
+    for id in 0..module.strings.len() {
+        if used_strings.contains(&(id as u32)) {
+            string_map.insert(id as u32, strings.len() as u32);
+            strings.push_bytes(module.strings.bytes(id).unwrap());
+        }
+    }
+    let mut data_map = HashMap::new();
+    let mut data = Vec::new();
+    for (id, d) in module.data.iter().enumerate() {
+        if used_data.contains(&(id as u32)) {
+            data_map.insert(id as u32, data.len() as u32);
+            data.push(*d);
+        }
+    }
+    let mut writable_map = HashMap::new();
+    let mut writable_data = Vec::new();
+    for (id, d) in module.writable_data.iter().enumerate() {
+        if used_writable.contains(&(id as u32)) {
+            writable_map.insert(id as u32, writable_data.len() as u32);
+            writable_data.push(*d);
+        }
+    }
+    let map_string = |id: u32| match string_map.get(&id) {
+        Some(id) => Ok(*id),
+        None => Err(ModuleError::InvalidString(id)),
+    };
+
+    for reloc in module.relocations.iter() {
+        let map = match reloc.kind {
+            RelocationKind::LocalSymbol => &symbol_map,
+            RelocationKind::String => &string_map,
+            RelocationKind::Data => &data_map,
+            RelocationKind::WritableData => &writable_map,
+            _ => continue,
+        };
+        let (offset, words) = (reloc.offset as usize, reloc.words as usize);
+        let value = read_words(&module.bytecode, offset, words) as u32;
+        if let Some(new) = map.get(&value) {
+            write_words(module.bytecode.to_mut(), offset, words, *new as u64);
+        }
+    }
+
+    for symbol in symbols.iter_mut() {
+        symbol.name_id = map_string(symbol.name_id)?;
+    }
+    for ext in module.external_symbols.iter_mut() {
+        ext.module_name_id = map_string(ext.module_name_id)?;
+        ext.symbol_name_id = map_string(ext.symbol_name_id)?;
+        ext.alias = ext.alias.map(map_string).transpose()?;
+    }
+    for global in module.globals.iter_mut() {
+        global.name_id = map_string(global.name_id)?;
+    }
+    for ext in module.external_globals.iter_mut() {
+        ext.module_name_id = map_string(ext.module_name_id)?;
+        ext.global_name_id = map_string(ext.global_name_id)?;
+    }
+    module.symbol_lookup = module
+        .symbol_lookup
+        .iter()
+        .filter_map(|(k, v)| symbol_map.get(v).map(|v| (k.clone(), *v)))
+        .collect();
+    if let Some(debug) = module.debug.as_mut() {
+        for entry in debug.lines.iter_mut() {
+            entry.file_id = map_string(entry.file_id)?;
+        }
+        let mut locals = Vec::new();
+        for local in debug.locals.iter() {
+            if let Some(symbol_id) = symbol_map.get(&local.symbol_id) {
+                locals.push(LocalName {
+                    symbol_id: *symbol_id,
+                    index: local.index,
+                    name_id: map_string(local.name_id)?,
+                });
+            }
+        }
+        debug.locals = locals;
+    }
+
+    module.local_symbols = symbols;
+    module.strings = strings;
+    module.data = data;
+    module.writable_data = writable_data;
+    Ok(module)
+}
+
+/// Get the value pushed by a constant instruction, if it is one
+pub(crate) fn constant_value(code: &[u16], offset: usize) -> Option<u64> {
+    let (group, data) = split(code[offset]);
+    if group != groups::STACK {
+        return None;
+    }
+    let value = match data {
+        stack::CONST_0 => 0,
+        stack::CONST_1 => 1,
+        stack::CONST_2 => 2,
+        stack::CONST_3 => 3,
+        stack::CONST_4 => 4,
+        stack::CONST_8 => 8,
+        stack::CONST_16 => 16,
+        stack::CONST_32 => 32,
+        stack::CONST_64 => 64,
+        stack::CONST_128 => 128,
+        stack::CONST_N1 => u64::MAX,
+        stack::CONST_U16 => code[offset + 1] as u64,
+        stack::CONST_U32 => read_words(code, offset + 1, 2),
+        stack::CONST_U64 => read_words(code, offset + 1, 4),
+        stack::CONST_I16 => code[offset + 1] as i16 as u64,
+        stack::CONST_I32 => read_words(code, offset + 1, 2) as u32 as i32 as u64,
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Encode the shortest instruction sequence pushing the given constant
+pub(crate) fn encode_constant(value: u64) -> Vec<u16> {
+    let small = match value {
+        0 => Some(inst_stack!(CONST_0)),
+        1 => Some(inst_stack!(CONST_1)),
+        2 => Some(inst_stack!(CONST_2)),
+        3 => Some(inst_stack!(CONST_3)),
+        4 => Some(inst_stack!(CONST_4)),
+        8 => Some(inst_stack!(CONST_8)),
+        16 => Some(inst_stack!(CONST_16)),
+        32 => Some(inst_stack!(CONST_32)),
+        64 => Some(inst_stack!(CONST_64)),
+        128 => Some(inst_stack!(CONST_128)),
+        u64::MAX => Some(inst_stack!(CONST_N1)),
+        _ => None,
+    };
+    if let Some(opcode) = small {
+        return vec![opcode];
+    }
+    let signed = value as i64;
+    if value <= 0xFFFF {
+        vec![inst_stack!(CONST_U16), value as u16]
+    } else if signed >= i16::MIN as i64 && signed < 0 {
+        vec![inst_stack!(CONST_I16), value as u16]
+    } else if value <= 0xFFFF_FFFF {
+        let mut code = vec![inst_stack!(CONST_U32)];
+        bytecode::encode_u32(&mut code, value as u32);
+        code
+    } else if signed >= i32::MIN as i64 && signed < 0 {
+        let mut code = vec![inst_stack!(CONST_I32)];
+        bytecode::encode_u32(&mut code, value as u32);
+        code
+    } else {
+        let mut code = vec![inst_stack!(CONST_U64)];
+        bytecode::encode_u64(&mut code, value);
+        code
+    }
+}
+
+/// Evaluate a math instruction taking two values from the stack
+///
+/// `top` is the topmost stack value. Returns `None` if the instruction is not
+/// foldable, would fault, or overflows, as what overflow does depends on the
+/// `Overflow` policy of the engine running the code.
+fn eval_binary(data: u8, top: u64, second: u64) -> Option<u64> {
+    let result = match data {
+        math::ADD => top.overflowing_add(second),
+        math::SUB => top.overflowing_sub(second),
+        math::MUL => top.overflowing_mul(second),
+        math::DIV => (top.checked_div(second)?, false),
+        _ => return None,
+    };
+    match result {
+        (value, false) => Some(value),
+        (_, true) => None,
+    }
+}
+
+/// Evaluate a math instruction taking one value from the stack and an inline
+/// constant, under the same conditions as `eval_binary`
+fn eval_constant(data: u8, value: u64, c: u64) -> Option<u64> {
+    let result = match data {
+        math::ADD_C => c.overflowing_add(value),
+        math::SUB_C => value.overflowing_sub(c),
+        math::MUL_C => value.overflowing_mul(c),
+        math::DIV_C => (value.checked_div(c)?, false),
+        _ => return None,
+    };
+    match result {
+        (value, false) => Some(value),
+        (_, true) => None,
+    }
+}
+
+/// Replace the code from `start` to `end` with a push of `value`, subsuming any
+/// edits already made within that range
+fn fold(edits: &mut Vec<Edit>, start: usize, end: usize, value: u64) {
+    while edits.last().map(|e| e.offset >= start).unwrap_or(false) {
+        edits.pop();
+    }
+    edits.push(Edit { offset: start, len: end - start, code: encode_constant(value) });
+}
+
+/// Evaluate arithmetic on constant values ahead of time
+///
+/// Sequences of constant pushes followed by integer math instructions are
+/// replaced by a single push of the result. For example,
+/// `CONST_U16 5; CONST_U16 3; ADD` becomes `CONST_8`. Folding never crosses
+/// the start of a symbol or a jump target, and never folds an operation which
+/// would fault, such as a division by zero, or which overflows, as the result
+/// of those depends on the `Overflow` policy and strictness of the engine.
+/// Constants marked by a relocation are left untouched, as their value
+/// depends on the layout of the module.
+///
+/// As folded code uses fewer stack slots, a program which previously
+/// overflowed the stack while evaluating a constant expression may no longer
+/// do so.
+pub fn fold_constants(module: &Module) -> Result<Module, ModuleError> {
+    check_relocations(module)?;
+    let code = &module.bytecode;
+    let (boundaries, relocated) = boundaries(module);
+
+    // The start offset and value of each constant on the stack, most recently
+    // pushed last.
+    let mut pending: Vec<(usize, u64)> = Vec::new();
+    let mut edits = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let len = match instruction_len(code, offset) {
+            Some(len) => len,
+            None => {
+                pending.clear();
+                offset += 1;
+                continue;
+            }
+        };
+        if boundaries.contains(&offset) {
+            pending.clear();
+        }
+        let end = offset + len;
+        if (offset + 1..end).any(|o| relocated.contains(&o)) {
+            pending.clear();
+            offset = end;
+            continue;
+        }
+        if let Some(value) = constant_value(code, offset) {
+            pending.push((offset, value));
+            offset = end;
+            continue;
+        }
+
+        let (group, data) = split(code[offset]);
+        let folded = match (group, pending.len()) {
+            (groups::SYSTEM, _) if data == sys::NOP => {
+                offset = end;
+                continue;
+            }
+            (groups::MATH, n) if n >= 2 && len == 1 => {
+                let (start, second) = pending[n - 2];
+                eval_binary(data, pending[n - 1].1, second).map(|v| (start, v, 2))
+            }
+            (groups::MATH, n) if n >= 1 && len == 2 => {
+                let (start, value) = pending[n - 1];
+                eval_constant(data, value, code[offset + 1] as u64).map(|v| (start, v, 1))
+            }
+            _ => None,
+        };
+        match folded {
+            Some((start, value, consumed)) => {
+                pending.truncate(pending.len() - consumed);
+                fold(&mut edits, start, end, value);
+                pending.push((start, value));
+            }
+            None => pending.clear(),
+        }
+        offset = end;
+    }
+
+    Ok(apply_edits(module, &edits))
+}
+
+/// The maximum number of times the peephole rewrites are applied to a module
+const MAX_PEEPHOLE_PASSES: usize = 16;
+
+/// A decoded instruction within a window matched by a rewrite
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instr<'a> {
+    /// The offset of the instruction within the bytecode
+    pub offset: usize,
+    /// The opcode and operand words of the instruction
+    pub words: &'a [u16],
+}
+
+impl<'a> Instr<'a> {
+    /// Get the opcode of the instruction
+    pub fn opcode(&self) -> u16 {
+        self.words[0]
+    }
+
+    /// Get the group byte of the opcode
+    pub fn group(&self) -> u8 {
+        split(self.words[0]).0
+    }
+
+    /// Get the data byte of the opcode
+    pub fn data(&self) -> u8 {
+        split(self.words[0]).1
+    }
+
+    /// Get the operand words following the opcode
+    pub fn operands(&self) -> &'a [u16] {
+        &self.words[1..]
+    }
+
+    /// Get the offset following the instruction
+    pub fn end(&self) -> usize {
+        self.offset + self.words.len()
+    }
+
+    /// Decode the instruction
+    pub fn instruction(&self) -> Instruction {
+        // Windows only ever hold defined instructions with all operands
+        Instruction::decode(self.words).map(|(i, _)| i).unwrap()
+    }
+}
+
+/// A rewrite applied by the peephole optimizer
+///
+/// The optimizer slides a window of consecutive instructions over the
+/// bytecode and offers it to each rewrite in turn. Windows never span an entry
+/// point (a symbol start, jump target, or the instruction after a jump) other
+/// than at their first instruction, never contain an undefined opcode, and
+/// never contain a relocated operand.
+pub trait Rewrite {
+    /// The number of consecutive instructions the rewrite inspects
+    fn window(&self) -> usize;
+
+    /// Produce the replacement for a window of instructions
+    ///
+    /// `code` is the full bytecode being optimized. Returns `None` if the
+    /// rewrite does not apply. Jumps within the replacement target offsets in
+    /// the original bytecode; relative jumps are interpreted as if the
+    /// replacement was placed at the offset of the first instruction.
+    fn rewrite(&self, window: &[Instr], code: &[u16]) -> Option<Vec<u16>>;
+}
+
+/// A test against a single instruction of a pattern
+#[derive(Clone, Copy)]
+pub enum Match {
+    /// Match any instruction
+    Any,
+    /// Match an instruction with exactly the given opcode
+    Opcode(u16),
+    /// Match any instruction in the given group
+    Group(u8),
+    /// Match instructions for which the function returns true
+    Test(fn(&Instr) -> bool),
+}
+
+impl Match {
+    /// Check if the instruction matches
+    pub fn matches(&self, instr: &Instr) -> bool {
+        match self {
+            Match::Any => true,
+            Match::Opcode(opcode) => instr.opcode() == *opcode,
+            Match::Group(group) => instr.group() == *group,
+            Match::Test(test) => test(instr),
+        }
+    }
+}
+
+/// A rewrite replacing a fixed sequence of instructions
+///
+/// For example, a pattern removing a duplicate which is immediately dropped:
+///
+/// ```
+/// use tstack::optimize::{Match, Pattern};
+///
+/// let pattern = Pattern::new(
+///     vec![Match::Opcode(tstack::inst_stack!(DUPE_1)), Match::Opcode(tstack::inst_stack!(POP_1))],
+///     |_| vec![],
+/// );
+/// ```
+pub struct Pattern {
+    matches: Vec<Match>,
+    replace: fn(&[Instr]) -> Vec<u16>,
+}
+
+impl Pattern {
+    /// Create a pattern which replaces instructions matching `matches` with
+    /// the code produced by `replace`
+    pub fn new(matches: Vec<Match>, replace: fn(&[Instr]) -> Vec<u16>) -> Pattern {
+        Pattern { matches, replace }
+    }
+}
+
+impl Rewrite for Pattern {
+    fn window(&self) -> usize {
+        self.matches.len()
+    }
+
+    fn rewrite(&self, window: &[Instr], _code: &[u16]) -> Option<Vec<u16>> {
+        if self.matches.iter().zip(window.iter()).all(|(m, i)| m.matches(i)) {
+            Some((self.replace)(window))
+        } else {
+            None
+        }
+    }
+}
+
+/// Redirect jumps which land on an unconditional constant jump to the final
+/// target of the chain
+///
+/// The rewritten jump keeps its opcode, so a chain is only threaded if the
+/// final target can be encoded with the same source width. Cycles of jumps are
+/// left alone.
+pub struct JumpThreading;
+
+impl Rewrite for JumpThreading {
+    fn window(&self) -> usize {
+        1
+    }
+
+    fn rewrite(&self, window: &[Instr], code: &[u16]) -> Option<Vec<u16>> {
+        let instr = &window[0];
+        if instr.group() != groups::JUMP {
+            return None;
+        }
+        let first = jump_target(code, instr.offset, instr.words.len())?;
+        let mut target = first;
+        let mut visited = HashSet::new();
+        while target < code.len() {
+            let (group, data) = split(code[target]);
+            if group != groups::JUMP || data & jump::CONDITIONAL_MASK == jump::CONDITIONAL_TRUE {
+                break;
+            }
+            let len = instruction_len(code, target)?;
+            let next = match jump_target(code, target, len) {
+                Some(next) => next,
+                None => break,
+            };
+            if !visited.insert(target) {
+                return None;
+            }
+            target = next;
+        }
+        if target == first {
+            return None;
+        }
+
+        let words = instr.words.len() - 1;
+        let value = if instr.data() & jump::MODE_MASK == jump::MODE_RELATIVE {
+            let delta = target as i64 - instr.end() as i64;
+            let fits = match words {
+                1 => i16::try_from(delta).is_ok(),
+                2 => i32::try_from(delta).is_ok(),
+                _ => true,
+            };
+            if !fits {
+                return None;
+            }
+            delta as u64
+        } else {
+            let fits = match words {
+                1 => u16::try_from(target).is_ok(),
+                2 => u32::try_from(target).is_ok(),
+                _ => true,
+            };
+            if !fits {
+                return None;
+            }
+            target as u64
+        };
+        let mut replacement = instr.words.to_vec();
+        write_words(&mut replacement, 1, words, value);
+        Some(replacement)
+    }
+}
+
+fn is_constant(instr: &Instr) -> bool {
+    instr.group() == groups::STACK && instr.data() <= stack::CONST_I32
+}
+
+/// A set of rewrites applied to windows of instructions
+///
+/// Rewrites are tried in the order they were added; the first one which
+/// applies to a window wins, and the instructions it replaced are not
+/// offered to any other rewrite in the same pass. Passes are repeated until
+/// no rewrite applies.
+#[derive(Default)]
+pub struct Peephole {
+    rules: Vec<Box<dyn Rewrite>>,
+}
+
+impl Peephole {
+    /// Create an optimizer without any rewrites
+    pub fn new() -> Peephole {
+        Peephole { rules: Vec::new() }
+    }
+
+    /// Create an optimizer with the built-in rewrites
+    ///
+    /// * a constant push immediately followed by `POP_1` is removed,
+    /// * `DUPE_1` immediately followed by `POP_1` is removed, and
+    /// * jumps to unconditional jumps are threaded to the final target.
+    ///
+    /// Removing a push and pop pair also removes any stack overflow or
+    /// underflow fault the pair would have raised.
+    pub fn with_builtins() -> Peephole {
+        let mut peephole = Peephole::new();
+        peephole.add(Pattern::new(
+            vec![Match::Test(is_constant), Match::Opcode(inst_stack!(POP_1))],
+            |_| vec![],
+        ));
+        peephole.add(Pattern::new(
+            vec![Match::Opcode(inst_stack!(DUPE_1)), Match::Opcode(inst_stack!(POP_1))],
+            |_| vec![],
+        ));
+        peephole.add(JumpThreading);
+        peephole
+    }
+
+    /// Add a rewrite, to be tried after all previously added rewrites
+    pub fn add<R: Rewrite + 'static>(&mut self, rule: R) {
+        self.rules.push(Box::new(rule));
+    }
+
+    /// Apply the rewrites to a module until none apply
+    pub fn run(&self, module: &Module) -> Result<Module, ModuleError> {
+        check_relocations(module)?;
+        let mut module = module.clone();
+        for _ in 0..MAX_PEEPHOLE_PASSES {
+            let edits = self.pass(&module);
+            if edits.is_empty() {
+                break;
+            }
+            module = apply_edits(&module, &edits);
+        }
+        Ok(module)
+    }
+
+    fn pass(&self, module: &Module) -> Vec<Edit> {
+        let code = &module.bytecode;
+        let (boundaries, relocated) = boundaries(module);
+
+        // The offset and length of each instruction, or `None` for the length
+        // of undefined opcodes
+        let mut decoded = Vec::new();
+        let mut offset = 0;
+        while offset < code.len() {
+            let len = instruction_len(code, offset);
+            decoded.push((offset, len));
+            offset += len.unwrap_or(1);
+        }
+
+        let mut edits = Vec::new();
+        let mut i = 0;
+        'outer: while i < decoded.len() {
+            for rule in self.rules.iter() {
+                let n = rule.window();
+                if n == 0 || i + n > decoded.len() {
+                    continue;
+                }
+                let mut window = Vec::with_capacity(n);
+                for (j, (offset, len)) in decoded[i..i + n].iter().enumerate() {
+                    let len = match len {
+                        Some(len) => *len,
+                        None => break,
+                    };
+                    if (j > 0 && boundaries.contains(offset))
+                        || (offset + 1..offset + len).any(|o| relocated.contains(&o))
+                    {
+                        break;
+                    }
+                    window.push(Instr { offset: *offset, words: &code[*offset..offset + len] });
+                }
+                if window.len() != n {
+                    continue;
+                }
+
+                let (start, end) = (window[0].offset, window[n - 1].end());
+                if let Some(replacement) = rule.rewrite(&window, code) {
+                    if replacement[..] != code[start..end] {
+                        edits.push(Edit { offset: start, len: end - start, code: replacement });
+                        i += n;
+                        continue 'outer;
+                    }
+                }
+            }
+            i += 1;
+        }
+        edits
+    }
+}
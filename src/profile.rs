@@ -0,0 +1,160 @@
+//! Counting where an engine spends its time
+//!
+//! A `Profile` is built from the `TraceEvent`s of an engine, and counts the
+//! instructions run by each symbol and the number of times each opcode ran:
+//!
+//! ```
+//! use std::cell::RefCell;
+//! use std::path::Path;
+//! use std::rc::Rc;
+//! use tstack::profile::Profile;
+//! use tstack::trace::TraceEvent;
+//!
+//! let module = tstack::asm::assemble(
+//!     "main",
+//!     ".export main\n CONST_1\n CONST_2\n ADD\n RETURN",
+//!     Path::new("."),
+//! )
+//! .unwrap();
+//! let mut engine = tstack::Engine::new();
+//! engine.add_module(Rc::new(module)).unwrap();
+//!
+//! let profile = Rc::new(RefCell::new(Profile::new()));
+//! let recorder = Rc::clone(&profile);
+//! engine.set_trace_hook(move |event: &TraceEvent| recorder.borrow_mut().record(event));
+//! engine.run(0, 0).unwrap();
+//!
+//! let profile = profile.borrow();
+//! assert_eq!(profile.symbols()[0].instructions, 4);
+//! assert_eq!(profile.opcodes()[0], ("ADD".to_string(), 1));
+//! ```
+//!
+//! Instructions are counted, not timed, so profiles are the same for every
+//! run of a program. Native functions run no instructions of their own, but
+//! are counted as called.
+
+use std::collections::HashMap;
+
+use crate::trace::TraceEvent;
+
+/// The counts of a single symbol
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolProfile {
+    /// The name of the module of the symbol
+    pub module: String,
+    /// The name of the symbol, or its ID if it has no name
+    pub symbol: String,
+    /// The number of times the symbol was called
+    pub calls: u64,
+    /// The number of instructions run by the symbol itself
+    pub instructions: u64,
+    /// The number of instructions run while the symbol was being called,
+    /// including those run by the symbols it called
+    pub total_instructions: u64,
+}
+
+/// A call in progress
+#[derive(Clone, Debug)]
+struct Frame {
+    /// The index of the symbol in `Profile::symbols`
+    symbol: usize,
+    /// The number of instructions run before the call
+    start: u64,
+}
+
+/// Instruction counts by symbol and by opcode
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    symbols: Vec<SymbolProfile>,
+    /// Indices into `symbols` by module and symbol ID
+    index: HashMap<(u32, u32), usize>,
+    /// The number of calls in progress of each symbol, so recursive calls
+    /// are not counted twice in `total_instructions`
+    active: Vec<u32>,
+    frames: Vec<Frame>,
+    opcodes: HashMap<String, u64>,
+    instructions: u64,
+}
+
+impl Profile {
+    /// Create an empty profile
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    /// Count an event
+    pub fn record(&mut self, event: &TraceEvent) {
+        match event {
+            TraceEvent::Instruction { .. } => {
+                self.instructions += 1;
+                if let Some(frame) = self.frames.last() {
+                    self.symbols[frame.symbol].instructions += 1;
+                }
+                if let Some(instruction) = event.instruction() {
+                    *self.opcodes.entry(instruction.mnemonic()).or_insert(0) += 1;
+                }
+            }
+            TraceEvent::Call { module, module_id, symbol_id, depth } => {
+                // Calls left in progress by a fault end with the run
+                self.unwind(*depth);
+                let symbols = &mut self.symbols;
+                let symbol = *self.index.entry((*module_id, *symbol_id)).or_insert_with(|| {
+                    let name = module.symbol(*symbol_id).and_then(|s| s.name().map(String::from));
+                    symbols.push(SymbolProfile {
+                        module: module.name.clone(),
+                        symbol: name.unwrap_or_else(|| symbol_id.to_string()),
+                        ..SymbolProfile::default()
+                    });
+                    symbols.len() - 1
+                });
+                self.active.resize(self.symbols.len(), 0);
+                self.symbols[symbol].calls += 1;
+                self.active[symbol] += 1;
+                self.frames.push(Frame { symbol, start: self.instructions });
+            }
+            TraceEvent::Return { depth } => self.unwind(*depth),
+        }
+    }
+
+    /// End calls until `depth` calls are in progress
+    fn unwind(&mut self, depth: usize) {
+        while self.frames.len() > depth {
+            let frame = self.frames.pop().unwrap();
+            self.active[frame.symbol] -= 1;
+            if self.active[frame.symbol] == 0 {
+                self.symbols[frame.symbol].total_instructions += self.instructions - frame.start;
+            }
+        }
+    }
+
+    /// Get the total number of instructions run
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Get the counts of every symbol called, in order of the number of
+    /// instructions they ran, most first
+    ///
+    /// Calls still in progress, as when a run halts or faults, count the
+    /// instructions run so far towards `total_instructions`.
+    pub fn symbols(&self) -> Vec<SymbolProfile> {
+        let mut profile = self.clone();
+        profile.unwind(0);
+        let mut symbols = profile.symbols;
+        symbols.sort_by(|a, b| {
+            b.instructions
+                .cmp(&a.instructions)
+                .then(b.calls.cmp(&a.calls))
+                .then(a.symbol.cmp(&b.symbol))
+        });
+        symbols
+    }
+
+    /// Get the number of times each opcode ran, by mnemonic, most first
+    pub fn opcodes(&self) -> Vec<(String, u64)> {
+        let mut opcodes: Vec<(String, u64)> =
+            self.opcodes.iter().map(|(name, count)| (name.clone(), *count)).collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        opcodes
+    }
+}
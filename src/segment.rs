@@ -0,0 +1,161 @@
+//! Copy-on-write segments of module state
+//!
+//! Each engine a module is loaded into gets its own writable data segment and
+//! globals, starting from the initial values in the module. Rather than copy
+//! them when the module is loaded, a segment reads from the shared module
+//! until it is first written to, and only then takes a copy of its own. Many
+//! engines can load one module and only pay for the segments they change.
+
+use std::fmt;
+use std::ops::Index;
+use std::rc::Rc;
+
+use crate::module::Module;
+
+/// Where the values of a segment are read from
+#[derive(Clone)]
+enum Contents {
+    /// The initial writable data of a module
+    Data(Rc<Module>),
+    /// The initial values of the globals of a module
+    Globals(Rc<Module>),
+    /// A copy owned by the segment
+    Owned(Vec<u64>),
+}
+
+/// The writable values of one module loaded into an engine
+///
+/// A segment compares equal to a `Vec<u64>` or slice of the same values, and
+/// may be indexed like one.
+#[derive(Clone)]
+pub struct Segment {
+    contents: Contents,
+}
+
+impl Segment {
+    /// Create a segment sharing the initial writable data of a module
+    pub fn data(module: &Rc<Module>) -> Segment {
+        Segment { contents: Contents::Data(Rc::clone(module)) }
+    }
+
+    /// Create a segment sharing the initial values of the globals of a module
+    pub fn globals(module: &Rc<Module>) -> Segment {
+        Segment { contents: Contents::Globals(Rc::clone(module)) }
+    }
+
+    /// Get the number of values in the segment
+    pub fn len(&self) -> usize {
+        match &self.contents {
+            Contents::Data(module) => module.writable_data.len(),
+            Contents::Globals(module) => module.globals.len(),
+            Contents::Owned(values) => values.len(),
+        }
+    }
+
+    /// Check if the segment has no values
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Check if the segment still reads from the module it was created from
+    ///
+    /// This is true until the segment is first written to.
+    pub fn is_shared(&self) -> bool {
+        !matches!(self.contents, Contents::Owned(_))
+    }
+
+    /// Get the value at an index
+    pub fn get(&self, index: usize) -> Option<u64> {
+        match &self.contents {
+            Contents::Data(module) => module.writable_data.get(index).copied(),
+            Contents::Globals(module) => module.globals.get(index).map(|g| g.initial),
+            Contents::Owned(values) => values.get(index).copied(),
+        }
+    }
+
+    /// Get a mutable reference to the value at an index
+    ///
+    /// The first call with a valid index copies the values out of the module
+    /// the segment shares.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut u64> {
+        if index >= self.len() {
+            return None;
+        }
+        self.values_mut().get_mut(index)
+    }
+
+    /// Get the values of the segment as a mutable slice, copying them out of
+    /// the module the segment shares if needed
+    pub fn values_mut(&mut self) -> &mut [u64] {
+        if self.is_shared() {
+            self.contents = Contents::Owned(self.to_vec());
+        }
+        match &mut self.contents {
+            Contents::Owned(values) => values,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Copy the values of the segment into a vector
+    pub fn to_vec(&self) -> Vec<u64> {
+        match &self.contents {
+            Contents::Data(module) => module.writable_data.clone(),
+            Contents::Globals(module) => module.globals.iter().map(|g| g.initial).collect(),
+            Contents::Owned(values) => values.clone(),
+        }
+    }
+
+    /// Read from `module` instead of the module the segment was created from,
+    /// if the segment is still shared
+    ///
+    /// The engine uses this when linking replaces a module with an updated
+    /// copy, so that the segment does not keep the old copy alive.
+    pub(crate) fn rebind(&mut self, module: &Rc<Module>) {
+        match &mut self.contents {
+            Contents::Data(shared) | Contents::Globals(shared) => *shared = Rc::clone(module),
+            Contents::Owned(_) => {}
+        }
+    }
+}
+
+impl From<Vec<u64>> for Segment {
+    fn from(values: Vec<u64>) -> Segment {
+        Segment { contents: Contents::Owned(values) }
+    }
+}
+
+impl Index<usize> for Segment {
+    type Output = u64;
+
+    fn index(&self, index: usize) -> &u64 {
+        match &self.contents {
+            Contents::Data(module) => &module.writable_data[index],
+            Contents::Globals(module) => &module.globals[index].initial,
+            Contents::Owned(values) => &values[index],
+        }
+    }
+}
+
+impl PartialEq for Segment {
+    fn eq(&self, other: &Segment) -> bool {
+        self.len() == other.len() && (0..self.len()).all(|i| self[i] == other[i])
+    }
+}
+
+impl PartialEq<[u64]> for Segment {
+    fn eq(&self, other: &[u64]) -> bool {
+        self.len() == other.len() && other.iter().enumerate().all(|(i, v)| self[i] == *v)
+    }
+}
+
+impl PartialEq<Vec<u64>> for Segment {
+    fn eq(&self, other: &Vec<u64>) -> bool {
+        *self == other[..]
+    }
+}
+
+impl fmt::Debug for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries((0..self.len()).map(|i| self[i])).finish()
+    }
+}
@@ -0,0 +1,162 @@
+//! Byte streams handed to bytecode
+//!
+//! Bytecode reads and writes bytes through streams held in the handle table
+//! of the engine, with the `READ`, `WRITE` and `CLOSE` instructions. A stream
+//! is anything implementing `Stream`, such as a file or socket; hosts hand
+//! their own streams to bytecode with `Engine::insert_stream`.
+//!
+//! The standard streams of the engine are always open, under the handles
+//! `STDIN`, `STDOUT` and `STDERR`. These are never the handles of objects in
+//! the handle table, and can not be closed.
+//!
+//! Bytecode may open TCP connections itself with `CONNECT` only if the policy
+//! of the engine grants `Capability::Network`, which it does not by default.
+//! Hosts which should not give bytecode access to the network at large
+//! instead open the connections bytecode needs and hand it their handles.
+//!
+//! Bytecode opens files itself with `OPEN`, but only beneath directories the
+//! host has preopened with `Engine::preopen`, in the manner of WASI. A path
+//! given to `OPEN` starts with the name the directory was preopened as,
+//! followed by a path relative to the directory, as in `data/input.txt`.
+//! Paths which are absolute, contain `..`, or lead outside of the directory
+//! through a symbolic link are refused, so bytecode has no access to the
+//! filesystem beyond what it was given.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// A stream of bytes which bytecode may read from and write to
+///
+/// This is implemented for every type implementing both `Read` and `Write`.
+/// Streams which only support one direction fail the other with an error.
+pub trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+/// The object held in the handle table for a stream
+pub(crate) type StreamBox = Box<dyn Stream>;
+
+/// The handle of the standard input stream of the engine
+pub const STDIN: u64 = 1;
+/// The handle of the standard output stream of the engine
+pub const STDOUT: u64 = 2;
+/// The handle of the standard error stream of the engine
+pub const STDERR: u64 = 3;
+
+/// A stream which can only be read
+pub(crate) struct ReadOnly<R>(pub R);
+
+impl<R: Read> Read for ReadOnly<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<R> Write for ReadOnly<R> {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A stream which can only be written
+pub(crate) struct WriteOnly<W>(pub W);
+
+impl<W> Read for WriteOnly<W> {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl<W: Write> Write for WriteOnly<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// How a file is opened by `OPEN`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpenMode {
+    /// Open an existing file for reading
+    Read,
+    /// Create or truncate a file for writing
+    Write,
+    /// Create or extend a file for writing at its end
+    Append,
+}
+
+impl OpenMode {
+    /// Get the mode given by an operand of `OPEN`
+    pub fn from_value(value: u64) -> Option<OpenMode> {
+        match value {
+            0 => Some(OpenMode::Read),
+            1 => Some(OpenMode::Write),
+            2 => Some(OpenMode::Append),
+            _ => None,
+        }
+    }
+}
+
+/// A directory bytecode may open files beneath
+#[derive(Clone, Debug)]
+pub(crate) struct Preopen {
+    pub name: String,
+    pub dir: PathBuf,
+    pub writable: bool,
+}
+
+/// Resolve a path given to `OPEN` against the preopened directories
+///
+/// Returns `None` if the path does not lie beneath a preopened directory
+/// which permits the mode.
+pub(crate) fn resolve(preopens: &[Preopen], path: &str, mode: OpenMode) -> Option<PathBuf> {
+    let (name, rest) = path.split_once('/')?;
+    let preopen = preopens.iter().rev().find(|p| p.name == name)?;
+    if mode != OpenMode::Read && !preopen.writable {
+        return None;
+    }
+    let mut resolved = preopen.dir.clone();
+    for component in Path::new(rest).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+
+    // Symbolic links may still lead outside of the directory, so check where
+    // the file, or the directory a new file is created in, really is
+    let root = preopen.dir.canonicalize().ok()?;
+    let real = match resolved.canonicalize() {
+        Ok(real) => real,
+        // A new file is only created where nothing exists yet, as a dangling
+        // link would otherwise have the file created wherever it points
+        Err(_) if mode != OpenMode::Read && resolved.symlink_metadata().is_err() => {
+            let parent = resolved.parent()?.canonicalize().ok()?;
+            parent.join(resolved.file_name()?)
+        }
+        Err(_) => return None,
+    };
+    if real.starts_with(&root) && real != root {
+        Some(real)
+    } else {
+        None
+    }
+}
+
+/// Open a file resolved by `resolve`
+pub(crate) fn open(path: &Path, mode: OpenMode) -> std::io::Result<File> {
+    match mode {
+        OpenMode::Read => File::open(path),
+        OpenMode::Write => File::create(path),
+        OpenMode::Append => OpenOptions::new().append(true).create(true).open(path),
+    }
+}
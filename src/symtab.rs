@@ -0,0 +1,93 @@
+//! Dense tables of the symbols of loaded modules
+//!
+//! Calls are among the most frequent instructions, and each needs only a few
+//! facts about the symbol it calls: where its code starts, its signature, the
+//! locals it is proven to need, and whether it may be called from other
+//! modules or is a native function. The
+//! engine keeps these in parallel arrays for each module, along with the
+//! symbols the external symbols of the module are bound to, so a call reads a
+//! few small arrays rather than the symbol and import records of the module.
+
+use crate::module::{Module, Signature};
+use crate::native::FuncRef;
+use crate::verify;
+
+/// The symbol may be called from other modules
+const EXPORTED: u8 = 1;
+/// The symbol is a native function
+const NATIVE: u8 = 2;
+
+/// What a call needs to know about a local symbol
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Entry {
+    pub offset: u32,
+    pub signature: Option<Signature>,
+    /// The most locals the symbol reserves, if verification proved each of
+    /// its accesses of a local within those reserved
+    pub locals: Option<u8>,
+    pub exported: bool,
+    pub native: bool,
+}
+
+/// The local and external symbols of one module, as parallel arrays indexed by
+/// symbol ID
+#[derive(Clone, Default)]
+pub(crate) struct SymbolTable {
+    offsets: Vec<u32>,
+    signatures: Vec<Option<Signature>>,
+    locals: Vec<Option<u8>>,
+    flags: Vec<u8>,
+    externals: Vec<FuncRef>,
+}
+
+impl SymbolTable {
+    /// Build the table of the symbols of a module
+    pub(crate) fn new(module: &Module) -> SymbolTable {
+        let symbols = &module.local_symbols;
+        let mut table = SymbolTable {
+            offsets: symbols.iter().map(|s| s.code_offset).collect(),
+            signatures: symbols.iter().map(|s| s.signature).collect(),
+            locals: verify::max_locals(module),
+            flags: symbols.iter().map(|s| if s.exported { EXPORTED } else { 0 }).collect(),
+            externals: vec![],
+        };
+        table.bind(module);
+        table
+    }
+
+    /// Get the entry of a local symbol
+    #[inline]
+    pub(crate) fn get(&self, symbol_id: u32) -> Option<Entry> {
+        let index = symbol_id as usize;
+        let flags = *self.flags.get(index)?;
+        Some(Entry {
+            offset: self.offsets[index],
+            signature: self.signatures[index],
+            locals: self.locals[index],
+            exported: flags & EXPORTED != 0,
+            native: flags & NATIVE != 0,
+        })
+    }
+
+    /// Get the symbol an external symbol is bound to
+    #[inline]
+    pub(crate) fn external(&self, index: u16) -> Option<FuncRef> {
+        self.externals.get(index as usize).copied()
+    }
+
+    /// Add an exported native function as the next local symbol
+    pub(crate) fn push_native(&mut self, signature: Option<Signature>) {
+        self.offsets.push(0);
+        self.signatures.push(signature);
+        self.locals.push(None);
+        self.flags.push(EXPORTED | NATIVE);
+    }
+
+    /// Update the bindings of the external symbols after the module is linked
+    pub(crate) fn bind(&mut self, module: &Module) {
+        self.externals.clear();
+        self.externals.extend(
+            module.external_symbols.iter().map(|ext| FuncRef::new(ext.module_id, ext.symbol_id)),
+        );
+    }
+}
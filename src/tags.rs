@@ -0,0 +1,349 @@
+//! Type tags of stack values, for debugging code generators
+//!
+//! Stack values are untyped 64 bit words, so bytecode adding a float to an
+//! integer, or calling a handle as a function, runs on with a wrong result.
+//! With `Engine::tag_stack` set, the engine keeps a tag of the type of each
+//! value alongside the stack, set by the instruction pushing it, and faults
+//! with `BytecodeError::TagMismatch` at the first instruction consuming a
+//! value of the wrong type:
+//!
+//! ```
+//! use std::path::Path;
+//! use std::rc::Rc;
+//! use tstack::errors::BytecodeError;
+//!
+//! let module = tstack::asm::assemble(
+//!     "main",
+//!     ".export main\n CONST_1\n CONST_2\n ITOF\n ADD\n RETURN",
+//!     Path::new("."),
+//! )
+//! .unwrap();
+//! let mut engine = tstack::Engine::new();
+//! engine.tag_stack = true;
+//! engine.add_module(Rc::new(module)).unwrap();
+//! let fault = engine.run(0, 0).unwrap_err();
+//! assert!(matches!(fault.error(), BytecodeError::TagMismatch(_)));
+//! ```
+//!
+//! Values whose type the engine does not know are tagged `Tag::Any`, which
+//! any instruction may consume: those pushed by the host or native
+//! functions, the 32 and 64 bit constants floats are written as, and those
+//! loaded from locals, data, and globals as 64 bit words.
+//!
+//! Tagging runs instructions one at a time, so it is off by default.
+
+use std::fmt;
+
+use crate::bytecode::{jump, Instruction, JumpSource};
+use crate::errors::BytecodeError;
+
+/// The type of a stack value
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Tag {
+    /// A value of unknown type, which may be consumed as any type
+    Any,
+    /// A signed or unsigned integer, or a count, index, or address
+    Int,
+    /// A 32 bit float, in the low bits of the value
+    F32,
+    /// A 64 bit float
+    F64,
+    /// A function reference, as pushed by `FUNC_REF_C`
+    FuncRef,
+    /// A handle to a host object, as pushed by `OPEN`
+    Handle,
+}
+
+impl Tag {
+    /// Check if a value of this tag may be consumed as a value of `expected`
+    pub fn matches(self, expected: Tag) -> bool {
+        self == expected || self == Tag::Any || expected == Tag::Any
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Tag::Any => "any",
+            Tag::Int => "int",
+            Tag::F32 => "f32",
+            Tag::F64 => "f64",
+            Tag::FuncRef => "funcref",
+            Tag::Handle => "handle",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The tags of the stack as an instruction runs
+///
+/// The tags and the stack start the same length. Values are popped from the
+/// tags only, so the stack holds the values of the counts and positions
+/// popped by instructions taking them from the stack.
+struct Effect<'a> {
+    opcode: u16,
+    stack: &'a [u64],
+    tags: &'a mut Vec<Tag>,
+}
+
+impl Effect<'_> {
+    /// Pop a value which must match `expected`, returning its value
+    ///
+    /// Popping an empty stack is left to the instruction to fault.
+    fn pop(&mut self, expected: Tag) -> Result<u64, BytecodeError> {
+        let tag = self.tags.pop().unwrap_or(Tag::Any);
+        if !tag.matches(expected) {
+            return Err(BytecodeError::tag_mismatch(self.opcode, expected, tag));
+        }
+        Ok(self.stack.get(self.tags.len()).copied().unwrap_or(0))
+    }
+
+    /// Pop `count` values which must match `expected`
+    fn pop_n(&mut self, count: usize, expected: Tag) -> Result<(), BytecodeError> {
+        for _ in 0..count {
+            self.pop(expected)?;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, tag: Tag) {
+        self.tags.push(tag);
+    }
+
+    /// Get the index of the first of the top `count` tags, or `None` if
+    /// there are fewer, which the instruction faults on
+    fn window(&self, count: u64) -> Option<usize> {
+        let len = self.tags.len() as u64;
+        (count <= len).then(|| (len - count) as usize)
+    }
+
+    /// Copy the top `count` tags as `DUPE` does
+    fn dupe(&mut self, count: u64) {
+        if let Some(base) = self.window(count) {
+            self.tags.extend_from_within(base..);
+        }
+    }
+
+    /// Reverse the top `count` tags as `SWAP` does
+    fn swap(&mut self, count: u64) {
+        if let Some(base) = self.window(count) {
+            self.tags[base..].reverse();
+        }
+    }
+
+    /// Drop the top `count` tags as `POP` does
+    fn drop(&mut self, count: u64) {
+        if let Some(base) = self.window(count) {
+            self.tags.truncate(base);
+        }
+    }
+
+    /// Rotate the top `count` tags as `ROTATE` does
+    fn rotate(&mut self, count: u64, position: u64) {
+        if let Some(base) = self.window(count) {
+            let window = &mut self.tags[base..];
+            if position != 0 && position <= window.len() as u64 {
+                window.rotate_left(position as usize - 1);
+            }
+        }
+    }
+}
+
+/// Check the tags of the values an instruction consumes, and update `tags`
+/// to those of the stack after it runs
+///
+/// `tags` must be as long as `stack`. An instruction which faults for any
+/// other reason may leave `tags` as if it ran.
+pub(crate) fn apply(
+    instruction: &Instruction,
+    stack: &[u64],
+    tags: &mut Vec<Tag>,
+) -> Result<(), BytecodeError> {
+    use Instruction as I;
+    use Tag::*;
+
+    let mut effect = Effect { opcode: instruction.opcode(), stack, tags };
+    match *instruction {
+        I::Halt | I::PrintU64 | I::PrintI64 | I::ReserveN => {
+            effect.pop(Int)?;
+        }
+        I::PrintF32 => {
+            effect.pop(F32)?;
+        }
+        I::PrintF64 => {
+            effect.pop(F64)?;
+        }
+        I::ArgGet => {
+            effect.pop_n(3, Int)?;
+            effect.push(Int);
+        }
+        I::EnvGet => {
+            effect.pop_n(4, Int)?;
+            effect.push(Int);
+        }
+        I::Open => {
+            effect.pop_n(3, Int)?;
+            effect.push(Handle);
+        }
+        I::Read | I::Write => {
+            effect.pop_n(2, Int)?;
+            effect.pop(Handle)?;
+            effect.push(Int);
+        }
+        I::Close => {
+            effect.pop(Handle)?;
+        }
+        I::Connect => {
+            effect.pop_n(2, Int)?;
+            effect.push(Handle);
+        }
+        I::Log => effect.pop_n(2, Int)?,
+        I::Fault(_) => {
+            let count = effect.pop(Int)?;
+            effect.pop_n(count.min(stack.len() as u64) as usize, Any)?;
+            effect.pop(Int)?;
+        }
+        I::ArgCount
+        | I::Random
+        | I::Time
+        | I::Const0
+        | I::Const1
+        | I::Const2
+        | I::Const3
+        | I::Const4
+        | I::Const8
+        | I::Const16
+        | I::Const32
+        | I::Const64
+        | I::Const128
+        | I::ConstN1
+        | I::ConstU16(_)
+        | I::ConstI16(_)
+        | I::ConstI32(_) => effect.push(Int),
+        // Float constants are written as the bits of the float
+        I::ConstU32(_) | I::ConstU64(_) => effect.push(Any),
+        I::RandomBelow => {
+            effect.pop(Int)?;
+            effect.push(Int);
+        }
+        I::Dupe => {
+            let count = effect.pop(Int)?;
+            effect.dupe(count);
+        }
+        I::Dupe1 => effect.dupe(1),
+        I::DupeC(count) => effect.dupe(count as u64),
+        I::Swap => {
+            let count = effect.pop(Int)?;
+            effect.swap(count);
+        }
+        I::Swap1 => effect.swap(2),
+        I::SwapC(count) => effect.swap(count as u64),
+        I::Rotate => {
+            let position = effect.pop(Int)?;
+            let count = effect.pop(Int)?;
+            effect.rotate(count, position);
+        }
+        I::Rotate1 => {
+            let count = effect.pop(Int)?;
+            effect.rotate(count, count);
+        }
+        I::RotateC(count) => {
+            let position = effect.pop(Int)?;
+            effect.rotate(count as u64, position);
+        }
+        I::Rotate1C(count) => effect.rotate(count as u64, count as u64),
+        I::Pop => {
+            let count = effect.pop(Int)?;
+            effect.drop(count);
+        }
+        I::PopC(count) => effect.drop(count as u64),
+        I::Pop1 => {
+            effect.pop(Any)?;
+        }
+        I::GetU8 | I::GetU16 | I::GetU32 | I::GetI8 | I::GetI16 | I::GetI32 => {
+            effect.pop(Int)?;
+            effect.push(Int);
+        }
+        I::GetU8C(_)
+        | I::GetU16C(_)
+        | I::GetU32C(_)
+        | I::GetI8C(_)
+        | I::GetI16C(_)
+        | I::GetI32C(_) => effect.push(Int),
+        I::GetU64 | I::DataGet | I::DataRwGet => {
+            effect.pop(Int)?;
+            effect.push(Any);
+        }
+        I::GetU64C(_)
+        | I::DataGetC(_)
+        | I::DataRwGetC(_)
+        | I::GlobalGetC(_)
+        | I::GlobalExtGetC(_) => effect.push(Any),
+        I::GetF32 => {
+            effect.pop(Int)?;
+            effect.push(F64);
+        }
+        I::GetF32C(_) => effect.push(F64),
+        I::Set8 | I::Set16 | I::Set32 => effect.pop_n(2, Int)?,
+        I::Set64 | I::DataRwSet => {
+            effect.pop(Int)?;
+            effect.pop(Any)?;
+        }
+        I::SetF32 => {
+            effect.pop(Int)?;
+            effect.pop(F64)?;
+        }
+        I::Set8C(_) | I::Set16C(_) | I::Set32C(_) => {
+            effect.pop(Int)?;
+        }
+        I::Set64C(_) | I::DataRwSetC(_) | I::GlobalSetC(_) | I::GlobalExtSetC(_) => {
+            effect.pop(Any)?;
+        }
+        I::SetF32C(_) => {
+            effect.pop(F64)?;
+        }
+        I::Add | I::Sub | I::Mul | I::Div => {
+            effect.pop_n(2, Int)?;
+            effect.push(Int);
+        }
+        I::AddC(_) | I::SubC(_) | I::MulC(_) | I::DivC(_) => {
+            effect.pop(Int)?;
+            effect.push(Int);
+        }
+        I::Ftoi => {
+            effect.pop(F64)?;
+            effect.push(Int);
+        }
+        I::Fadd | I::Fsub | I::Fmul | I::Fdiv => {
+            effect.pop_n(2, F64)?;
+            effect.push(F64);
+        }
+        I::Fneg => {
+            effect.pop(F64)?;
+            effect.push(F64);
+        }
+        I::Itof => {
+            effect.pop(Int)?;
+            effect.push(F64);
+        }
+        I::FuncRefC(_) | I::FuncRefExtC(_) => effect.push(FuncRef),
+        I::CallRef => {
+            effect.pop(FuncRef)?;
+        }
+        I::Jump(j) => {
+            if j.source == JumpSource::Dynamic {
+                effect.pop(Int)?;
+            }
+            match j.condition {
+                Some(condition) if condition < jump::TYPE_EQ => effect.pop(Int).map(|_| ())?,
+                Some(_) => effect.pop_n(2, Int)?,
+                None => (),
+            }
+        }
+        // Calls and returns leave the stack to the symbols called, and the
+        // instructions left fault when dispatched
+        _ => (),
+    }
+    Ok(())
+}
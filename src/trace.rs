@@ -0,0 +1,76 @@
+//! Observing execution as it happens
+//!
+//! A host may follow what an engine runs by setting a trace hook with
+//! `Engine::set_trace_hook`. The hook is given a `TraceEvent` before every
+//! instruction and at every call and return, which is enough to print an
+//! execution trace, build a profile, or record coverage:
+//!
+//! ```
+//! use std::cell::Cell;
+//! use std::rc::Rc;
+//! use tstack::trace::TraceEvent;
+//!
+//! let mut engine = tstack::Engine::new();
+//! let count = Rc::new(Cell::new(0));
+//! let counter = Rc::clone(&count);
+//! engine.set_trace_hook(move |event: &TraceEvent| {
+//!     if let TraceEvent::Instruction { .. } = event {
+//!         counter.set(counter.get() + 1);
+//!     }
+//! });
+//! ```
+//!
+//! Engines without a hook pay only for checking that there is none.
+
+use crate::bytecode::Instruction;
+use crate::module::Module;
+
+/// Something an engine did while running
+#[derive(Clone, Copy, Debug)]
+pub enum TraceEvent<'a> {
+    /// An instruction is about to run
+    Instruction {
+        /// The module the instruction is in
+        module: &'a Module,
+        /// The ID of the module
+        module_id: u32,
+        /// The offset of the instruction in the bytecode of the module
+        offset: usize,
+        /// The stack before the instruction runs
+        stack: &'a [u64],
+    },
+    /// A symbol was called, including entry points and native functions
+    Call {
+        /// The module of the called symbol
+        module: &'a Module,
+        /// The ID of the module
+        module_id: u32,
+        /// The ID of the symbol within the module
+        symbol_id: u32,
+        /// The number of calls in progress below this one
+        depth: usize,
+    },
+    /// The symbol called last returned
+    Return {
+        /// The number of calls in progress below the returning one
+        depth: usize,
+    },
+}
+
+impl TraceEvent<'_> {
+    /// Decode the instruction of an `Instruction` event
+    ///
+    /// Returns `None` for other events, or if the instruction can not be
+    /// decoded.
+    pub fn instruction(&self) -> Option<Instruction> {
+        match self {
+            TraceEvent::Instruction { module, offset, .. } => {
+                Instruction::decode(module.bytecode.get(*offset..)?).ok().map(|(i, _)| i)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A hook given every `TraceEvent` of an engine
+pub type TraceHook = Box<dyn FnMut(&TraceEvent)>;
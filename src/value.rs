@@ -0,0 +1,119 @@
+//! Typed values exchanged between the host and bytecode
+//!
+//! Every stack value is a 64 bit word, which bytecode reads as an integer, a
+//! float, a function reference, or a handle depending on the instruction. A
+//! `Value` pairs a word with its type, so hosts need not convert the bits
+//! themselves:
+//!
+//! ```
+//! use tstack::Value;
+//!
+//! let mut engine = tstack::Engine::new();
+//! engine.push_value(1.5f64);
+//! engine.push_value(-2i64);
+//! assert_eq!(engine.stack, vec![1.5f64.to_bits(), -2i64 as u64]);
+//!
+//! engine.tag_stack = true;
+//! assert_eq!(engine.stack_values(), vec![Value::U64(1.5f64.to_bits()), Value::U64(-2i64 as u64)]);
+//! engine.push_value(0.25f32);
+//! assert_eq!(engine.stack_values()[2], Value::F32(0.25));
+//! ```
+//!
+//! The stack does not record the types of its values unless
+//! `Engine::tag_stack` is set, so `Engine::stack_values` reads values as
+//! `Value::U64` unless they are tagged with another type; see the `tags`
+//! module.
+
+use crate::native::FuncRef;
+use crate::tags::Tag;
+
+/// A stack value and its type
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    /// An unsigned integer, or a value of unknown type
+    U64(u64),
+    /// A signed integer
+    I64(i64),
+    /// A 32 bit float, held in the low bits of the stack value
+    F32(f32),
+    /// A 64 bit float
+    F64(f64),
+    /// A reference to a symbol
+    FuncRef(FuncRef),
+    /// A handle to a host object; see the `handle` module
+    Handle(u64),
+}
+
+impl Value {
+    /// Read a stack value as the type given by its tag
+    ///
+    /// Integers are read as `Value::U64`, as tags do not record if they are
+    /// signed, as are values tagged `Tag::Any`.
+    pub fn from_bits(bits: u64, tag: Tag) -> Value {
+        match tag {
+            Tag::Any | Tag::Int => Value::U64(bits),
+            Tag::F32 => Value::F32(f32::from_bits(bits as u32)),
+            Tag::F64 => Value::F64(f64::from_bits(bits)),
+            Tag::FuncRef => Value::FuncRef(FuncRef::from_value(bits)),
+            Tag::Handle => Value::Handle(bits),
+        }
+    }
+
+    /// Get the stack value holding the value
+    pub fn to_bits(self) -> u64 {
+        match self {
+            Value::U64(value) | Value::Handle(value) => value,
+            Value::I64(value) => value as u64,
+            Value::F32(value) => value.to_bits() as u64,
+            Value::F64(value) => value.to_bits(),
+            Value::FuncRef(func) => func.value(),
+        }
+    }
+
+    /// Get the tag of the type of the value
+    pub fn tag(self) -> Tag {
+        match self {
+            Value::U64(_) | Value::I64(_) => Tag::Int,
+            Value::F32(_) => Tag::F32,
+            Value::F64(_) => Tag::F64,
+            Value::FuncRef(_) => Tag::FuncRef,
+            Value::Handle(_) => Tag::Handle,
+        }
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Value {
+        Value::U64(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Value {
+        Value::I64(value)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(value: f32) -> Value {
+        Value::F32(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Value {
+        Value::F64(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Value {
+        Value::U64(value as u64)
+    }
+}
+
+impl From<FuncRef> for Value {
+    fn from(value: FuncRef) -> Value {
+        Value::FuncRef(value)
+    }
+}
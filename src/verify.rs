@@ -0,0 +1,395 @@
+//! Static checks of module bytecode
+//!
+//! Modules are verified when they are added to an engine, so that some
+//! classes of malformed bytecode are rejected before any of it is run rather
+//! than faulting part way through execution.
+//!
+//! The bytecode is scanned from start to end, decoding one instruction after
+//! another; words which do not decode to an instruction are skipped. The
+//! following rules are checked:
+//!
+//! * `DATA_GET_C` must refer to an index within the read-only data segment,
+//! * `DATA_RW_GET_C` and `DATA_RW_SET_C` must refer to an index within the
+//!   writable data segment, and
+//! * `GLOBAL_GET_C` and `GLOBAL_SET_C` must refer to a global of the module,
+//!   and `GLOBAL_EXT_GET_C` and `GLOBAL_EXT_SET_C` to an external global,
+//! * `FAULT` must refer to a string of the module for its message,
+//! * every symbol must start within the bytecode, or at its end,
+//! * a symbol declaring a signature must never pop below the values it takes
+//!   and must return with as many values as it declares it leaves,
+//! * the names of symbols and globals must refer to strings of the module,
+//!   and
+//! * relocations must cover 1, 2, or 4 words within the bytecode.
+//!
+//! `diagnose` reports every problem found, along with words which do not
+//! decode as warnings, while `verify` fails with the first error.
+//!
+//! The stack effect of a symbol is followed from its start to its first
+//! `RETURN`. A symbol whose effect depends on values only known when it runs,
+//! such as one which jumps, halts, calls a reference or a symbol without a
+//! signature, or runs off the end of the bytecode, is left to be checked as
+//! it runs.
+//!
+//! `max_locals` follows each symbol the same way to find the locals it needs,
+//! so the engine may size the locals of a call up front and skip checking
+//! the accesses of symbols proven to stay within them.
+
+use crate::bytecode::stack::MAX_LOCALS;
+use crate::bytecode::Instruction;
+use crate::diagnostics::{Diagnostic, DiagnosticKind, Diagnostics, Severity};
+use crate::errors::ModuleError;
+use crate::module::{Module, Signature};
+
+/// Check the bytecode of a module against the verification rules
+pub fn verify(module: &Module) -> Result<(), ModuleError> {
+    let diagnostics = diagnose(module);
+    let error = match diagnostics.errors().next() {
+        Some(error) => error,
+        None => return Ok(()),
+    };
+    let at = |index: Option<u32>| index.unwrap_or(0);
+    Err(match error.kind {
+        DiagnosticKind::InvalidDataAccess => ModuleError::InvalidDataAccess(at(error.offset)),
+        DiagnosticKind::InvalidGlobalAccess => ModuleError::InvalidGlobalAccess(at(error.offset)),
+        DiagnosticKind::InvalidRelocation => ModuleError::InvalidRelocation(at(error.offset)),
+        DiagnosticKind::InvalidStackEffect => ModuleError::InvalidStackEffect(at(error.symbol)),
+        DiagnosticKind::InvalidString => ModuleError::InvalidString(at(error.string)),
+        DiagnosticKind::InvalidSymbol => ModuleError::InvalidSymbol(at(error.symbol)),
+        kind => unreachable!("{:?} is not an error of verification", kind),
+    })
+}
+
+/// Check a module against the verification rules, reporting every problem
+/// found
+pub fn diagnose(module: &Module) -> Diagnostics {
+    let mut diagnostics = Diagnostics::new();
+    check_bytecode(module, &mut diagnostics);
+    check_symbols(module, &mut diagnostics);
+    check_stack_effects(module, &mut diagnostics);
+    check_relocations(module, &mut diagnostics);
+    diagnostics
+}
+
+fn check_bytecode(module: &Module, diagnostics: &mut Diagnostics) {
+    let mut offset = 0;
+    while offset < module.bytecode.len() {
+        let at = |kind, severity, message| {
+            let symbol = module.symbol_at(offset as u32).map(|s| s.id());
+            Diagnostic { symbol, ..Diagnostic::at_offset(kind, severity, message, offset as u32) }
+        };
+        let (instruction, len) = match Instruction::decode(&module.bytecode[offset..]) {
+            Ok(decoded) => decoded,
+            Err(_) => {
+                let message =
+                    format!("word {:#06x} is not an instruction", module.bytecode[offset]);
+                diagnostics.push(at(DiagnosticKind::BadOpcode, Severity::Warning, message));
+                offset += 1;
+                continue;
+            }
+        };
+        let limit = match instruction {
+            Instruction::DataGetC(index) => Some((
+                DiagnosticKind::InvalidDataAccess,
+                "read-only data",
+                index,
+                module.data.len(),
+            )),
+            Instruction::DataRwGetC(index) | Instruction::DataRwSetC(index) => Some((
+                DiagnosticKind::InvalidDataAccess,
+                "writable data",
+                index,
+                module.writable_data.len(),
+            )),
+            Instruction::GlobalGetC(index) | Instruction::GlobalSetC(index) => {
+                Some((DiagnosticKind::InvalidGlobalAccess, "globals", index, module.globals.len()))
+            }
+            Instruction::GlobalExtGetC(index) | Instruction::GlobalExtSetC(index) => Some((
+                DiagnosticKind::InvalidGlobalAccess,
+                "external globals",
+                index,
+                module.external_globals.len(),
+            )),
+            _ => None,
+        };
+        if let Some((kind, what, index, count)) = limit {
+            if index as usize >= count {
+                let message =
+                    format!("{} is past the end of the {} ({})", instruction, what, count);
+                diagnostics.push(at(kind, Severity::Error, message));
+            }
+        }
+        if let Instruction::Fault(index) = instruction {
+            if index as usize >= module.strings.len() {
+                let message = format!(
+                    "the message of {} is string {}, which does not exist",
+                    instruction, index
+                );
+                let diagnostic = at(DiagnosticKind::InvalidString, Severity::Error, message);
+                diagnostics.push(Diagnostic { string: Some(index as u32), ..diagnostic });
+            }
+        }
+        offset += len;
+    }
+}
+
+fn check_symbols(module: &Module, diagnostics: &mut Diagnostics) {
+    let strings = module.strings.len() as u32;
+    let mut check_name = |what: String, id: u32, symbol: Option<u32>| {
+        if id >= strings {
+            let message = format!("the name of {} is string {}, which does not exist", what, id);
+            diagnostics.push(Diagnostic {
+                symbol,
+                string: Some(id),
+                ..Diagnostic::new(DiagnosticKind::InvalidString, Severity::Error, message)
+            });
+        }
+    };
+    for (id, symbol) in module.local_symbols.iter().enumerate() {
+        check_name(format!("symbol {}", id), symbol.name_id, Some(id as u32));
+    }
+    for (id, symbol) in module.external_symbols.iter().enumerate() {
+        check_name(format!("the module of external symbol {}", id), symbol.module_name_id, None);
+        check_name(format!("external symbol {}", id), symbol.symbol_name_id, None);
+    }
+    for (id, global) in module.globals.iter().enumerate() {
+        check_name(format!("global {}", id), global.name_id, None);
+    }
+    for (id, global) in module.external_globals.iter().enumerate() {
+        check_name(format!("the module of external global {}", id), global.module_name_id, None);
+        check_name(format!("external global {}", id), global.global_name_id, None);
+    }
+    for (id, symbol) in module.local_symbols.iter().enumerate() {
+        if symbol.code_offset as usize > module.bytecode.len() {
+            let message = format!(
+                "symbol {} starts at offset {}, past the end of the bytecode",
+                id, symbol.code_offset
+            );
+            diagnostics.push(Diagnostic {
+                symbol: Some(id as u32),
+                ..Diagnostic::new(DiagnosticKind::InvalidSymbol, Severity::Error, message)
+            });
+        }
+    }
+}
+
+/// Find the most locals each local symbol reserves at once, for the symbols
+/// whose every access of a local is proven to be within those reserved
+///
+/// Each symbol is followed from its start to its first `RETURN`, `HALT`, or
+/// `FAULT`, counting the locals reserved by `RESERVE_C`. A symbol which
+/// jumps, reserves a count or accesses an index taken from the stack, may
+/// access a local it has not reserved, or runs off the end of the bytecode
+/// is `None`, and has each access checked as it runs.
+pub fn max_locals(module: &Module) -> Vec<Option<u8>> {
+    module.local_symbols.iter().map(|s| symbol_locals(module, s.code_offset as usize)).collect()
+}
+
+fn symbol_locals(module: &Module, start: usize) -> Option<u8> {
+    use Instruction as I;
+
+    let (mut reserved, mut max) = (0usize, 0usize);
+    let mut offset = start;
+    loop {
+        let (instruction, len) = Instruction::decode(module.bytecode.get(offset..)?).ok()?;
+        offset += len;
+        // The packed index and width of the local accessed, if any
+        let (index, bits) = match instruction {
+            I::Return | I::Halt | I::Fault(_) => return Some(max as u8),
+            I::ReserveC(count) => {
+                reserved = reserved
+                    .checked_add_signed(count as i16 as isize)
+                    .filter(|&reserved| reserved <= MAX_LOCALS)?;
+                max = max.max(reserved);
+                continue;
+            }
+            I::GetU8C(index) | I::GetI8C(index) | I::Set8C(index) => (index, 8),
+            I::GetU16C(index) | I::GetI16C(index) | I::Set16C(index) => (index, 16),
+            I::GetU32C(index)
+            | I::GetI32C(index)
+            | I::GetF32C(index)
+            | I::Set32C(index)
+            | I::SetF32C(index) => (index, 32),
+            I::GetU64C(index) | I::Set64C(index) => (index, 64),
+            I::GetU8
+            | I::GetU16
+            | I::GetU32
+            | I::GetU64
+            | I::GetI8
+            | I::GetI16
+            | I::GetI32
+            | I::GetF32
+            | I::Set8
+            | I::Set16
+            | I::Set32
+            | I::Set64
+            | I::SetF32
+            | I::ReserveN
+            | I::Jump(_) => return None,
+            _ => continue,
+        };
+        if index as usize / (64 / bits) >= reserved {
+            return None;
+        }
+    }
+}
+
+/// Get the number of values an instruction pops and pushes
+///
+/// Returns `None` for instructions whose effect is not known until they run,
+/// and for those which do not continue to the next instruction.
+fn stack_effect(module: &Module, instruction: &Instruction) -> Option<(u32, u32)> {
+    use Instruction as I;
+
+    let signature =
+        |signature: Option<Signature>| signature.map(|s| (s.inputs as u32, s.outputs as u32));
+    Some(match *instruction {
+        I::Nop | I::PrintStack | I::Breakpoint | I::ReserveC(_) => (0, 0),
+        I::PrintU64 | I::PrintI64 | I::PrintF32 | I::PrintF64 | I::Close | I::ReserveN => (1, 0),
+        I::Log => (2, 0),
+        I::ArgCount | I::Random | I::Time => (0, 1),
+        I::RandomBelow => (1, 1),
+        I::Connect => (2, 1),
+        I::ArgGet | I::Open | I::Read | I::Write => (3, 1),
+        I::EnvGet => (4, 1),
+        I::Const0
+        | I::Const1
+        | I::Const2
+        | I::Const3
+        | I::Const4
+        | I::Const8
+        | I::Const16
+        | I::Const32
+        | I::Const64
+        | I::Const128
+        | I::ConstN1
+        | I::ConstU16(_)
+        | I::ConstU32(_)
+        | I::ConstU64(_)
+        | I::ConstI16(_)
+        | I::ConstI32(_) => (0, 1),
+        I::Dupe1 => (1, 2),
+        I::DupeC(count) => (count as u32, 2 * count as u32),
+        I::Swap1 => (2, 2),
+        I::SwapC(count) | I::Rotate1C(count) => (count as u32, count as u32),
+        I::RotateC(count) => (count as u32 + 1, count as u32),
+        I::Pop1 => (1, 0),
+        I::PopC(count) => (count as u32, 0),
+        I::GetU8C(_)
+        | I::GetU16C(_)
+        | I::GetU32C(_)
+        | I::GetU64C(_)
+        | I::GetI8C(_)
+        | I::GetI16C(_)
+        | I::GetI32C(_)
+        | I::GetF32C(_)
+        | I::DataGetC(_)
+        | I::DataRwGetC(_)
+        | I::GlobalGetC(_)
+        | I::GlobalExtGetC(_)
+        | I::FuncRefC(_)
+        | I::FuncRefExtC(_) => (0, 1),
+        I::GetU8
+        | I::GetU16
+        | I::GetU32
+        | I::GetU64
+        | I::GetI8
+        | I::GetI16
+        | I::GetI32
+        | I::GetF32
+        | I::DataGet
+        | I::DataRwGet => (1, 1),
+        I::Set8C(_)
+        | I::Set16C(_)
+        | I::Set32C(_)
+        | I::Set64C(_)
+        | I::SetF32C(_)
+        | I::DataRwSetC(_)
+        | I::GlobalSetC(_)
+        | I::GlobalExtSetC(_) => (1, 0),
+        I::Set8 | I::Set16 | I::Set32 | I::Set64 | I::SetF32 | I::DataRwSet => (2, 0),
+        I::Add | I::Sub | I::Mul | I::Div | I::Fadd | I::Fsub | I::Fmul | I::Fdiv => (2, 1),
+        I::AddC(_) | I::SubC(_) | I::MulC(_) | I::DivC(_) | I::Fneg | I::Itof | I::Ftoi => (1, 1),
+        I::CallC(id) => signature(module.local_symbols.get(id as usize)?.signature)?,
+        I::CallExtC(id) => signature(module.external_symbols.get(id as usize)?.signature)?,
+        // Counts taken from the stack, jumps, halts, calls of references, and
+        // the instructions left, which fault when dispatched
+        _ => return None,
+    })
+}
+
+/// Follow the stack effect of a symbol with a signature, returning the
+/// offset and description of the first place it breaks its signature
+fn check_stack_effect(
+    module: &Module,
+    start: usize,
+    signature: Signature,
+) -> Option<(usize, String)> {
+    let mut depth = signature.inputs as u32;
+    let mut offset = start;
+    while offset < module.bytecode.len() {
+        let (instruction, len) = Instruction::decode(&module.bytecode[offset..]).ok()?;
+        if instruction == Instruction::Return {
+            if depth != signature.outputs as u32 {
+                let message = format!(
+                    "returns with {} values, but declares it leaves {}",
+                    depth, signature.outputs
+                );
+                return Some((offset, message));
+            }
+            return None;
+        }
+        let (pops, pushes) = stack_effect(module, &instruction)?;
+        if pops > depth {
+            let message = format!(
+                "{} pops {} values, but only {} are left of the {} it takes",
+                instruction, pops, depth, signature.inputs
+            );
+            return Some((offset, message));
+        }
+        depth = depth - pops + pushes;
+        offset += len;
+    }
+    None
+}
+
+fn check_stack_effects(module: &Module, diagnostics: &mut Diagnostics) {
+    for (id, symbol) in module.local_symbols.iter().enumerate() {
+        let signature = match symbol.signature {
+            Some(signature) => signature,
+            None => continue,
+        };
+        if let Some((offset, message)) =
+            check_stack_effect(module, symbol.code_offset as usize, signature)
+        {
+            let message = format!("symbol {} {}", id, message);
+            diagnostics.push(Diagnostic {
+                symbol: Some(id as u32),
+                ..Diagnostic::at_offset(
+                    DiagnosticKind::InvalidStackEffect,
+                    Severity::Error,
+                    message,
+                    offset as u32,
+                )
+            });
+        }
+    }
+}
+
+fn check_relocations(module: &Module, diagnostics: &mut Diagnostics) {
+    for reloc in module.relocations.iter() {
+        if !matches!(reloc.words, 1 | 2 | 4)
+            || reloc.offset as usize + reloc.words as usize > module.bytecode.len()
+        {
+            let message = format!(
+                "relocation of {} words at offset {} is outside of the bytecode",
+                reloc.words, reloc.offset
+            );
+            diagnostics.push(Diagnostic::at_offset(
+                DiagnosticKind::InvalidRelocation,
+                Severity::Error,
+                message,
+                reloc.offset,
+            ));
+        }
+    }
+}
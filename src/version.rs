@@ -0,0 +1,250 @@
+//! Module versions and version requirements
+//!
+//! Modules carry a semantic version of the form `major.minor.patch`, and
+//! imports may constrain the version of the module they bind to with a
+//! requirement. Requirements follow the same rules as Cargo: a requirement is
+//! a comma separated list of comparators, all of which must match. Each
+//! comparator is an operator followed by a (possibly partial) version:
+//!
+//!| Operator | Example    | Matches
+//!|----------|------------|--------
+//!| `^`      | `^1.2`     | `>=1.2.0, <2.0.0`
+//!| `~`      | `~1.2`     | `>=1.2.0, <1.3.0`
+//!| `=`      | `=1.2`     | `>=1.2.0, <1.3.0`
+//!| `>`      | `>1.2`     | `>=1.3.0`
+//!| `>=`     | `>=1.2.3`  | `>=1.2.3`
+//!| `<`      | `<1.2`     | `<1.2.0`
+//!| `<=`     | `<=1.2`    | `<1.3.0`
+//!| `*`      | `1.*`      | `>=1.0.0, <2.0.0`
+//!
+//! A comparator without an operator is treated as a caret requirement.
+
+use std::cmp::Ordering;
+
+/// A semantic version number
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Version {
+    /// The major version, incremented for incompatible changes
+    pub major: u32,
+    /// The minor version, incremented for backwards compatible additions
+    pub minor: u32,
+    /// The patch version, incremented for backwards compatible fixes
+    pub patch: u32,
+}
+
+impl Version {
+    /// Create a new version
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Version {
+        Version { major, minor, patch }
+    }
+
+    /// Parse a full `major.minor.patch` version string
+    pub fn parse(text: &str) -> Option<Version> {
+        let mut parts = text.trim().split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        let patch = parts.next()?.parse().ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Version { major, minor, patch })
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Tilde,
+    Caret,
+    Wildcard,
+    Any,
+}
+
+/// A single comparison against a possibly partial version
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Comparator {
+    op: Op,
+    major: u32,
+    minor: Option<u32>,
+    patch: Option<u32>,
+}
+
+impl Comparator {
+    fn parse(text: &str) -> Option<Comparator> {
+        let text = text.trim();
+        if text == "*" {
+            return Some(Comparator { op: Op::Any, major: 0, minor: None, patch: None });
+        }
+        let (mut op, rest) = if let Some(rest) = text.strip_prefix(">=") {
+            (Op::GreaterEq, rest)
+        } else if let Some(rest) = text.strip_prefix("<=") {
+            (Op::LessEq, rest)
+        } else if let Some(rest) = text.strip_prefix('>') {
+            (Op::Greater, rest)
+        } else if let Some(rest) = text.strip_prefix('<') {
+            (Op::Less, rest)
+        } else if let Some(rest) = text.strip_prefix('=') {
+            (Op::Exact, rest)
+        } else if let Some(rest) = text.strip_prefix('~') {
+            (Op::Tilde, rest)
+        } else if let Some(rest) = text.strip_prefix('^') {
+            (Op::Caret, rest)
+        } else {
+            (Op::Caret, text)
+        };
+
+        let mut parts = rest.trim().split('.');
+        let major = parts.next()?.parse().ok()?;
+        let mut minor = None;
+        let mut patch = None;
+        if let Some(part) = parts.next() {
+            if part == "*" {
+                op = Op::Wildcard;
+            } else {
+                minor = Some(part.parse().ok()?);
+                if let Some(part) = parts.next() {
+                    if part == "*" {
+                        op = Op::Wildcard;
+                    } else {
+                        patch = Some(part.parse().ok()?);
+                    }
+                }
+            }
+        }
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Comparator { op, major, minor, patch })
+    }
+
+    /// Compare a version against the (partial) version of this comparator,
+    /// only considering the components which are present
+    fn cmp_partial(&self, version: &Version) -> Ordering {
+        version
+            .major
+            .cmp(&self.major)
+            .then_with(|| self.minor.map(|m| version.minor.cmp(&m)).unwrap_or(Ordering::Equal))
+            .then_with(|| self.patch.map(|p| version.patch.cmp(&p)).unwrap_or(Ordering::Equal))
+    }
+
+    fn matches(&self, version: &Version) -> bool {
+        let ord = self.cmp_partial(version);
+        match self.op {
+            Op::Any => true,
+            Op::Exact | Op::Wildcard => ord == Ordering::Equal,
+            Op::Greater => ord == Ordering::Greater,
+            Op::GreaterEq => ord != Ordering::Less,
+            Op::Less => ord == Ordering::Less,
+            Op::LessEq => ord != Ordering::Greater,
+            Op::Tilde => {
+                let lower =
+                    Version::new(self.major, self.minor.unwrap_or(0), self.patch.unwrap_or(0));
+                *version >= lower
+                    && version.major == self.major
+                    && self.minor.map(|m| version.minor == m).unwrap_or(true)
+            }
+            Op::Caret => {
+                let lower =
+                    Version::new(self.major, self.minor.unwrap_or(0), self.patch.unwrap_or(0));
+                if *version < lower || version.major != self.major {
+                    return false;
+                }
+                if self.major > 0 {
+                    return true;
+                }
+                match (self.minor, self.patch) {
+                    (None, _) => true,
+                    (Some(minor), None) => version.minor == minor,
+                    (Some(minor), Some(patch)) => {
+                        version.minor == minor && (minor > 0 || version.patch == patch)
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Comparator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let op = match self.op {
+            Op::Exact => "=",
+            Op::Greater => ">",
+            Op::GreaterEq => ">=",
+            Op::Less => "<",
+            Op::LessEq => "<=",
+            Op::Tilde => "~",
+            Op::Caret => "^",
+            Op::Wildcard => "",
+            Op::Any => return write!(f, "*"),
+        };
+        write!(f, "{}{}", op, self.major)?;
+        match (self.minor, self.patch) {
+            (Some(minor), Some(patch)) => write!(f, ".{}.{}", minor, patch),
+            (Some(minor), None) if self.op == Op::Wildcard => write!(f, ".{}.*", minor),
+            (Some(minor), None) => write!(f, ".{}", minor),
+            _ if self.op == Op::Wildcard => write!(f, ".*"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A requirement on the version of a module
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionReq {
+    comparators: Vec<Comparator>,
+}
+
+impl VersionReq {
+    /// Parse a version requirement
+    ///
+    /// Returns `None` if the requirement is malformed.
+    pub fn parse(text: &str) -> Option<VersionReq> {
+        let comparators = text.split(',').map(Comparator::parse).collect::<Option<Vec<_>>>()?;
+        Some(VersionReq { comparators })
+    }
+
+    /// Check if the given version satisfies this requirement
+    pub fn matches(&self, version: &Version) -> bool {
+        self.comparators.iter().all(|c| c.matches(version))
+    }
+}
+
+impl std::fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, c) in self.comparators.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for VersionReq {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for VersionReq {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<VersionReq, D::Error> {
+        let text = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        VersionReq::parse(&text).ok_or_else(|| {
+            serde::de::Error::custom(format!("invalid version requirement {}", text))
+        })
+    }
+}
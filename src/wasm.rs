@@ -0,0 +1,1042 @@
+//! Compiling a subset of the WebAssembly text format into modules
+//!
+//! The frontend accepts a module in the WebAssembly text format (WAT) and
+//! translates it into a tstack module, so guest code can be produced with
+//! existing toolchains. Only the core of the language is supported:
+//!
+//! * function imports and definitions, with inline or module level exports,
+//! * `i64` and `f64` parameters, results, and locals,
+//! * `i64` constants, arithmetic, and comparisons, and `f64` constants,
+//!   arithmetic, and conversions to and from `i64`,
+//! * `local.get`, `local.set`, `local.tee`, `call`, `drop`, and `nop`, and
+//! * `block`, `loop`, `if`/`else`, `br`, `br_if`, and `return`.
+//!
+//! Instructions may be written in either the flat or the folded form. Anything
+//! else, such as memories, tables, globals, or type definitions, fails with
+//! `CompileError::Unsupported`.
+//!
+//! Each function becomes a local symbol whose signature is taken from its
+//! parameters and results. The symbol is named after the first export of the
+//! function, or else its identifier without the leading `$`, or else
+//! `func{index}`; every export name is also added to the symbol lookup table.
+//! Imported functions become external symbols, and calls are encoded as
+//! `CALL_C` or `CALL_EXT_C` with relocations for their operands. Branches are
+//! encoded as relative jumps, so the bytecode of a function does not depend on
+//! where it is placed.
+//!
+//! Parameters and declared locals are held in the locals of the symbol: a
+//! function starts by reserving them, moving its arguments from the stack
+//! into the parameters, and zeroing the rest.
+//!
+//! ```
+//! let module = tstack::wasm::compile(
+//!     "math",
+//!     r#"(module
+//!         (func $square (export "square") (param $x i64) (result i64)
+//!             (i64.mul (local.get $x) (local.get $x))))"#,
+//! )
+//! .unwrap();
+//! assert_eq!(module.find_symbol("square"), Some(0));
+//! ```
+
+use std::collections::HashMap;
+
+use crate::builder::{self, BytecodeBuilder, Label};
+use crate::bytecode::stack::MAX_LOCALS;
+use crate::bytecode::{jump, Instruction};
+use crate::errors::CompileError;
+use crate::module::{ExternalSymbol, LocalSymbol, Module, Signature};
+use crate::optimize;
+
+/// The deepest lists may be nested in the source
+const MAX_DEPTH: usize = 256;
+
+/// Compile the text of a WebAssembly module into a module with the given name
+///
+/// The source may either be a single `module` form, or the fields of a module
+/// without the enclosing form.
+pub fn compile(name: &str, source: &str) -> Result<Module, CompileError> {
+    let items = parse(tokenize(source)?)?;
+    let fields = match items.as_slice() {
+        [Sexp::List(list, _)] if keyword(list) == Some("module") => {
+            let start = if list.get(1).and_then(Sexp::id).is_some() { 2 } else { 1 };
+            &list[start..]
+        }
+        _ => items.as_slice(),
+    };
+
+    let mut funcs: Vec<Func> = vec![];
+    let mut exports = vec![];
+    for field in fields.iter() {
+        let (list, line) = match field {
+            Sexp::List(list, line) => (list, *line),
+            other => return Err(syntax(other.line(), "expected a module field")),
+        };
+        let func = match keyword(list) {
+            Some("func") => Func::parse(list, line)?,
+            Some("import") => Func::parse_import(list, line)?,
+            Some("export") => {
+                exports.push(parse_export(list, line)?);
+                continue;
+            }
+            Some(other) => return Err(CompileError::Unsupported(line, format!("`{}`", other))),
+            None => return Err(syntax(line, "expected a module field")),
+        };
+        if func.import.is_some() && funcs.iter().any(|f| f.import.is_none()) {
+            return Err(syntax(line, "imports must come before function definitions"));
+        }
+        funcs.push(func);
+    }
+
+    let mut ids = HashMap::new();
+    for (index, func) in funcs.iter().enumerate() {
+        if let Some(id) = &func.id {
+            if ids.insert(id.clone(), index as u32).is_some() {
+                return Err(CompileError::DuplicateName(func.line, id.clone()));
+            }
+        }
+    }
+    for (export, target, line) in exports {
+        let index = resolve(&ids, funcs.len(), &target, line)?;
+        funcs[index as usize].exports.push(export);
+    }
+
+    let mut module = Module::new(name);
+    let imports = funcs.iter().take_while(|f| f.import.is_some()).count();
+    for func in funcs[..imports].iter() {
+        let (from, symbol) = func.import.clone().unwrap();
+        module.strings.push(from);
+        module.strings.push(symbol);
+        module.external_symbols.push(ExternalSymbol {
+            module_name_id: module.strings.len() as u32 - 2,
+            module_id: 0,
+            symbol_name_id: module.strings.len() as u32 - 1,
+            symbol_id: 0,
+            signature: Some(func.signature()),
+            version_req: None,
+            alias: None,
+        });
+    }
+
+    let mut names: Vec<String> = vec![];
+    for (index, func) in funcs.iter().enumerate().skip(imports) {
+        let name = match (func.exports.first(), &func.id) {
+            (Some(export), _) => export.clone(),
+            (None, Some(id)) => String::from(&id[1..]),
+            (None, None) => format!("func{}", index),
+        };
+        if func.exports.is_empty() && names.contains(&name) {
+            return Err(CompileError::DuplicateName(func.line, name));
+        }
+        for export in func.exports.iter() {
+            if names.contains(export) {
+                return Err(CompileError::DuplicateName(func.line, export.clone()));
+            }
+            names.push(export.clone());
+            module.symbol_lookup.insert(export.clone(), module.local_symbols.len() as u32);
+        }
+        names.push(name.clone());
+
+        let code = FuncCompiler::new(&funcs, &ids, imports, func)?.compile()?;
+        module.strings.push(name);
+        module.local_symbols.push(LocalSymbol {
+            name_id: module.strings.len() as u32 - 1,
+            code_offset: module.bytecode.len() as u32,
+            exported: !func.exports.is_empty(),
+            signature: Some(func.signature()),
+        });
+        let offset = module.bytecode.len() as u32;
+        for mut relocation in builder::symbol_relocations(&code) {
+            relocation.offset += offset;
+            module.relocations.push(relocation);
+        }
+        module.bytecode.to_mut().extend_from_slice(&code);
+    }
+    Ok(module)
+}
+
+fn syntax(line: u32, message: &str) -> CompileError {
+    CompileError::InvalidSyntax(line, String::from(message))
+}
+
+/// A token of the text format, and the line it starts on
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Open(u32),
+    Close(u32),
+    Atom(String, u32),
+    Str(String, u32),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, CompileError> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => (),
+            '(' if chars.peek() == Some(&';') => {
+                // Block comments may be nested
+                let start = line;
+                chars.next();
+                let mut depth = 1;
+                while depth > 0 {
+                    match chars.next() {
+                        Some('(') if chars.peek() == Some(&';') => {
+                            chars.next();
+                            depth += 1;
+                        }
+                        Some(';') if chars.peek() == Some(&')') => {
+                            chars.next();
+                            depth -= 1;
+                        }
+                        Some('\n') => line += 1,
+                        Some(_) => (),
+                        None => return Err(syntax(start, "unterminated block comment")),
+                    }
+                }
+            }
+            '(' => tokens.push(Token::Open(line)),
+            ')' => tokens.push(Token::Close(line)),
+            ';' if chars.peek() == Some(&';') => while chars.next_if(|c| *c != '\n').is_some() {},
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            Some('r') => value.push('\r'),
+                            Some(c @ ('\\' | '"' | '\'')) => value.push(c),
+                            Some(hi) => {
+                                let lo = chars.next().unwrap_or('\0');
+                                match (hi.to_digit(16), lo.to_digit(16)) {
+                                    (Some(hi), Some(lo)) => {
+                                        value.push(char::from((hi * 16 + lo) as u8))
+                                    }
+                                    _ => return Err(syntax(line, "invalid string escape")),
+                                }
+                            }
+                            None => return Err(syntax(line, "unterminated string")),
+                        },
+                        Some('\n') | None => return Err(syntax(line, "unterminated string")),
+                        Some(c) => value.push(c),
+                    }
+                }
+                tokens.push(Token::Str(value, line));
+            }
+            c => {
+                let mut atom = String::from(c);
+                while let Some(c) =
+                    chars.next_if(|c| !c.is_whitespace() && !matches!(c, '(' | ')' | '"' | ';'))
+                {
+                    atom.push(c);
+                }
+                tokens.push(Token::Atom(atom, line));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// An S-expression of the text format, and the line it starts on
+#[derive(Clone, Debug, PartialEq)]
+enum Sexp {
+    List(Vec<Sexp>, u32),
+    Atom(String, u32),
+    Str(String, u32),
+}
+
+impl Sexp {
+    fn line(&self) -> u32 {
+        match self {
+            Sexp::List(_, line) | Sexp::Atom(_, line) | Sexp::Str(_, line) => *line,
+        }
+    }
+
+    fn atom(&self) -> Option<&str> {
+        match self {
+            Sexp::Atom(atom, _) => Some(atom),
+            _ => None,
+        }
+    }
+
+    /// Get the identifier, if this is an atom starting with `$`
+    fn id(&self) -> Option<&str> {
+        self.atom().filter(|a| a.starts_with('$'))
+    }
+
+    fn string(&self) -> Result<&str, CompileError> {
+        match self {
+            Sexp::Str(value, _) => Ok(value),
+            other => Err(syntax(other.line(), "expected a string")),
+        }
+    }
+
+    /// Get the contents of the list, if this is a list with the given keyword
+    fn form(&self, name: &str) -> Option<&[Sexp]> {
+        match self {
+            Sexp::List(list, _) if keyword(list) == Some(name) => Some(&list[1..]),
+            _ => None,
+        }
+    }
+}
+
+fn keyword(list: &[Sexp]) -> Option<&str> {
+    list.first().and_then(Sexp::atom)
+}
+
+fn parse(tokens: Vec<Token>) -> Result<Vec<Sexp>, CompileError> {
+    let mut stack: Vec<(Vec<Sexp>, u32)> = vec![(vec![], 0)];
+    for token in tokens {
+        match token {
+            Token::Open(line) => {
+                if stack.len() > MAX_DEPTH {
+                    let message = format!("lists nested deeper than {} levels", MAX_DEPTH);
+                    return Err(CompileError::Unsupported(line, message));
+                }
+                stack.push((vec![], line));
+            }
+            Token::Close(line) => {
+                if stack.len() == 1 {
+                    return Err(syntax(line, "unexpected `)`"));
+                }
+                let (list, start) = stack.pop().unwrap();
+                stack.last_mut().unwrap().0.push(Sexp::List(list, start));
+            }
+            Token::Atom(atom, line) => stack.last_mut().unwrap().0.push(Sexp::Atom(atom, line)),
+            Token::Str(value, line) => stack.last_mut().unwrap().0.push(Sexp::Str(value, line)),
+        }
+    }
+    if stack.len() > 1 {
+        return Err(syntax(stack.last().unwrap().1, "unclosed `(`"));
+    }
+    Ok(stack.pop().unwrap().0)
+}
+
+/// A reference to a function, local, or label by identifier or index
+#[derive(Clone, Debug, PartialEq)]
+enum Ref {
+    Id(String),
+    Index(u32),
+}
+
+fn parse_ref(item: Option<&Sexp>, line: u32) -> Result<Ref, CompileError> {
+    let atom = match item.and_then(Sexp::atom) {
+        Some(atom) => atom,
+        None => return Err(syntax(line, "expected an identifier or index")),
+    };
+    if atom.starts_with('$') {
+        return Ok(Ref::Id(String::from(atom)));
+    }
+    match parse_int(atom).and_then(|v| u32::try_from(v).ok()) {
+        Some(index) => Ok(Ref::Index(index)),
+        None => Err(syntax(line, &format!("invalid index `{}`", atom))),
+    }
+}
+
+fn resolve(
+    ids: &HashMap<String, u32>,
+    count: usize,
+    target: &Ref,
+    line: u32,
+) -> Result<u32, CompileError> {
+    match target {
+        Ref::Id(id) => ids.get(id).copied().ok_or(CompileError::UndefinedName(line, id.clone())),
+        Ref::Index(index) if (*index as usize) < count => Ok(*index),
+        Ref::Index(index) => Err(CompileError::UndefinedName(line, index.to_string())),
+    }
+}
+
+/// Parse an integer literal, giving the bits of its two's complement value
+fn parse_int(text: &str) -> Option<u64> {
+    let text = text.replace('_', "");
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(&text)),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<u64>().ok()?,
+    };
+    if negative {
+        if value > 1 << 63 {
+            return None;
+        }
+        Some(value.wrapping_neg())
+    } else {
+        Some(value)
+    }
+}
+
+fn parse_float(text: &str) -> Option<f64> {
+    let text = text.replace('_', "");
+    let (negative, body) = match text.strip_prefix('-') {
+        Some(body) => (true, body),
+        None => (false, text.strip_prefix('+').unwrap_or(&text)),
+    };
+    let value = match body {
+        "inf" => f64::INFINITY,
+        "nan" => f64::NAN,
+        _ if body.starts_with("0x") || body.starts_with("nan:") => return None,
+        _ => body.parse::<f64>().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Check that the atoms of a `param`, `result`, or `local` form are supported
+/// value types, returning how many there are
+fn value_types(items: &[Sexp], line: u32) -> Result<usize, CompileError> {
+    for item in items.iter() {
+        match item.atom() {
+            Some("i64" | "f64") => (),
+            Some(other) => {
+                return Err(CompileError::Unsupported(line, format!("value type `{}`", other)))
+            }
+            None => return Err(syntax(line, "expected a value type")),
+        }
+    }
+    Ok(items.len())
+}
+
+/// Parse the `param` or `local` forms of a function, adding their names
+fn named_values(
+    items: &[Sexp],
+    line: u32,
+    names: &mut Vec<Option<String>>,
+) -> Result<(), CompileError> {
+    match items.first().and_then(Sexp::id) {
+        Some(id) => {
+            if items.len() != 2 {
+                return Err(syntax(line, "a named value must have exactly one type"));
+            }
+            value_types(&items[1..], line)?;
+            names.push(Some(String::from(id)));
+        }
+        None => {
+            let count = value_types(items, line)?;
+            names.extend((0..count).map(|_| None));
+        }
+    }
+    Ok(())
+}
+
+fn parse_export(list: &[Sexp], line: u32) -> Result<(String, Ref, u32), CompileError> {
+    let name = match list.get(1) {
+        Some(item) => item.string()?,
+        None => return Err(syntax(line, "expected an export name")),
+    };
+    match list.get(2) {
+        Some(Sexp::List(desc, desc_line)) if desc.len() == 2 => match keyword(desc) {
+            Some("func") => Ok((String::from(name), parse_ref(desc.get(1), *desc_line)?, line)),
+            Some(other) => Err(CompileError::Unsupported(line, format!("`{}` export", other))),
+            None => Err(syntax(line, "expected an export description")),
+        },
+        _ => Err(syntax(line, "expected an export description")),
+    }
+}
+
+/// A function definition or import
+struct Func {
+    id: Option<String>,
+    exports: Vec<String>,
+    /// The module and symbol names of an imported function
+    import: Option<(String, String)>,
+    params: Vec<Option<String>>,
+    results: usize,
+    locals: Vec<Option<String>>,
+    body: Vec<Sexp>,
+    line: u32,
+}
+
+impl Func {
+    /// Parse a `func` form
+    fn parse(list: &[Sexp], line: u32) -> Result<Func, CompileError> {
+        let mut func = Func {
+            id: None,
+            exports: vec![],
+            import: None,
+            params: vec![],
+            results: 0,
+            locals: vec![],
+            body: vec![],
+            line,
+        };
+        let mut rest = &list[1..];
+        if let Some(id) = rest.first().and_then(Sexp::id) {
+            func.id = Some(String::from(id));
+            rest = &rest[1..];
+        }
+        while let Some(item) = rest.first() {
+            if let Some(export) = item.form("export") {
+                match export {
+                    [name] => func.exports.push(String::from(name.string()?)),
+                    _ => return Err(syntax(item.line(), "expected an export name")),
+                }
+            } else if let Some(import) = item.form("import") {
+                match import {
+                    [from, symbol] => {
+                        func.import =
+                            Some((String::from(from.string()?), String::from(symbol.string()?)))
+                    }
+                    _ => return Err(syntax(item.line(), "expected a module and symbol name")),
+                }
+            } else if let Some(params) = item.form("param") {
+                named_values(params, item.line(), &mut func.params)?;
+            } else if let Some(results) = item.form("result") {
+                func.results += value_types(results, item.line())?;
+            } else if let Some(locals) = item.form("local") {
+                named_values(locals, item.line(), &mut func.locals)?;
+            } else if item.form("type").is_some() {
+                return Err(CompileError::Unsupported(item.line(), String::from("type uses")));
+            } else {
+                break;
+            }
+            rest = &rest[1..];
+        }
+        func.body = rest.to_vec();
+        if func.import.is_some() && !(func.body.is_empty() && func.locals.is_empty()) {
+            return Err(syntax(line, "an imported function can not have a body"));
+        }
+        Ok(func)
+    }
+
+    /// Parse an `import` form
+    fn parse_import(list: &[Sexp], line: u32) -> Result<Func, CompileError> {
+        let (from, symbol, desc) = match list {
+            [_, from, symbol, desc] => (from.string()?, symbol.string()?, desc),
+            _ => return Err(syntax(line, "expected a module name, symbol name, and description")),
+        };
+        let mut func = match desc {
+            Sexp::List(desc, desc_line) if keyword(desc) == Some("func") => {
+                Func::parse(desc, *desc_line)?
+            }
+            Sexp::List(desc, _) if keyword(desc).is_some() => {
+                let kind = keyword(desc).unwrap();
+                return Err(CompileError::Unsupported(line, format!("`{}` import", kind)));
+            }
+            _ => return Err(syntax(line, "expected an import description")),
+        };
+        if func.import.is_some() || !func.body.is_empty() || !func.locals.is_empty() {
+            return Err(syntax(line, "an imported function can not have a body"));
+        }
+        func.import = Some((String::from(from), String::from(symbol)));
+        func.line = line;
+        Ok(func)
+    }
+
+    fn signature(&self) -> Signature {
+        Signature { inputs: self.params.len() as u16, outputs: self.results as u16 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BlockKind {
+    Block,
+    Loop,
+    If,
+    Function,
+}
+
+/// A block being compiled
+struct Block {
+    kind: BlockKind,
+    label: Option<String>,
+    /// The stack height at the start of the block
+    base: usize,
+    /// The number of values the block leaves on the stack
+    results: usize,
+    /// Where branches to the block jump to
+    target: Label,
+    end: Label,
+    /// The start of the `else` branch of an `if` block
+    otherwise: Option<Label>,
+}
+
+/// The compiler for the body of a single function
+struct FuncCompiler<'a> {
+    funcs: &'a [Func],
+    ids: &'a HashMap<String, u32>,
+    imports: usize,
+    func: &'a Func,
+    locals: HashMap<String, u16>,
+    builder: BytecodeBuilder,
+    blocks: Vec<Block>,
+    /// The number of values on the stack
+    height: usize,
+    /// Whether the code being compiled can not be reached
+    unreachable: bool,
+}
+
+impl<'a> FuncCompiler<'a> {
+    fn new(
+        funcs: &'a [Func],
+        ids: &'a HashMap<String, u32>,
+        imports: usize,
+        func: &'a Func,
+    ) -> Result<FuncCompiler<'a>, CompileError> {
+        let count = func.params.len() + func.locals.len();
+        if count > MAX_LOCALS {
+            let message = format!("{} parameters and locals", count);
+            return Err(CompileError::Unsupported(func.line, message));
+        }
+        let mut locals = HashMap::new();
+        for (index, name) in func.params.iter().chain(func.locals.iter()).enumerate() {
+            if let Some(name) = name {
+                if locals.insert(name.clone(), index as u16).is_some() {
+                    return Err(CompileError::DuplicateName(func.line, name.clone()));
+                }
+            }
+        }
+        Ok(FuncCompiler {
+            funcs,
+            ids,
+            imports,
+            func,
+            locals,
+            builder: BytecodeBuilder::new(),
+            blocks: vec![],
+            height: 0,
+            unreachable: false,
+        })
+    }
+
+    fn compile(mut self) -> Result<Vec<u16>, CompileError> {
+        let params = self.func.params.len();
+        let count = params + self.func.locals.len();
+        if count > 0 {
+            self.emit(Instruction::ReserveC(count as u16));
+        }
+        for index in (0..params).rev() {
+            self.emit(Instruction::Set64C(index as u16));
+        }
+        for index in params..count {
+            self.emit(Instruction::Const0);
+            self.emit(Instruction::Set64C(index as u16));
+        }
+
+        let end = self.builder.label();
+        self.blocks.push(Block {
+            kind: BlockKind::Function,
+            label: None,
+            base: 0,
+            results: self.func.results,
+            target: end,
+            end,
+            otherwise: None,
+        });
+        let func = self.func;
+        self.instructions(&func.body)?;
+        self.end(self.func.line)?;
+        self.emit(Instruction::Return);
+        // Every label is bound by the block it belongs to
+        Ok(self.builder.build().unwrap())
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.builder.emit_instruction(instruction);
+    }
+
+    /// Record the stack effect of an instruction
+    fn effect(&mut self, pops: usize, pushes: usize, line: u32) -> Result<(), CompileError> {
+        let base = self.blocks.last().map_or(0, |b| b.base);
+        if self.height < base + pops {
+            if !self.unreachable {
+                return Err(syntax(line, "not enough values on the stack"));
+            }
+            self.height = base + pops;
+        }
+        self.height = self.height - pops + pushes;
+        Ok(())
+    }
+
+    /// Compile a sequence of instructions in the flat or folded form
+    fn instructions(&mut self, items: &[Sexp]) -> Result<(), CompileError> {
+        let mut rest = items;
+        while let Some(item) = rest.first() {
+            rest = &rest[1..];
+            match item {
+                Sexp::List(list, line) => self.folded(list, *line)?,
+                Sexp::Atom(op, line) => match op.as_str() {
+                    "block" | "loop" | "if" => {
+                        let (label, results, used) = block_header(rest, *line)?;
+                        rest = &rest[used..];
+                        self.begin(op, label, results, *line)?;
+                    }
+                    "else" | "end" => {
+                        if rest.first().and_then(Sexp::id).is_some() {
+                            rest = &rest[1..];
+                        }
+                        if op == "else" {
+                            self.otherwise(*line)?;
+                        } else {
+                            if self.blocks.len() == 1 {
+                                return Err(syntax(*line, "`end` without a block"));
+                            }
+                            self.end(*line)?;
+                        }
+                    }
+                    _ => {
+                        let mut immediate = None;
+                        if takes_immediate(op) {
+                            immediate = rest.first();
+                            rest = rest.get(1..).unwrap_or(&[]);
+                        }
+                        self.op(op, immediate, *line)?;
+                    }
+                },
+                Sexp::Str(_, line) => return Err(syntax(*line, "unexpected string")),
+            }
+        }
+        Ok(())
+    }
+
+    /// Compile a folded instruction
+    fn folded(&mut self, list: &[Sexp], line: u32) -> Result<(), CompileError> {
+        let op = match keyword(list) {
+            Some(op) => op,
+            None => return Err(syntax(line, "expected an instruction")),
+        };
+        match op {
+            "block" | "loop" => {
+                let (label, results, used) = block_header(&list[1..], line)?;
+                self.begin(op, label, results, line)?;
+                self.instructions(&list[1 + used..])?;
+                self.end(line)
+            }
+            "if" => {
+                let (label, results, used) = block_header(&list[1..], line)?;
+                let rest = &list[1 + used..];
+                let then = match rest.iter().position(|item| item.form("then").is_some()) {
+                    Some(then) => then,
+                    None => return Err(syntax(line, "expected a `then` branch")),
+                };
+                self.instructions(&rest[..then])?;
+                self.begin(op, label, results, line)?;
+                self.instructions(rest[then].form("then").unwrap())?;
+                match &rest[then + 1..] {
+                    [] => (),
+                    [otherwise] if otherwise.form("else").is_some() => {
+                        self.otherwise(line)?;
+                        self.instructions(otherwise.form("else").unwrap())?;
+                    }
+                    _ => return Err(syntax(line, "expected an `else` branch")),
+                }
+                self.end(line)
+            }
+            _ => {
+                let immediate = list.get(1).filter(|item| item.atom().is_some());
+                if immediate.is_some() && !takes_immediate(op) {
+                    return Err(syntax(line, &format!("`{}` takes no immediates", op)));
+                }
+                let operands = &list[1 + immediate.is_some() as usize..];
+                self.instructions(operands)?;
+                self.op(op, immediate, line)
+            }
+        }
+    }
+
+    /// Start a block
+    fn begin(
+        &mut self,
+        op: &str,
+        label: Option<String>,
+        results: usize,
+        line: u32,
+    ) -> Result<(), CompileError> {
+        let kind = match op {
+            "block" => BlockKind::Block,
+            "loop" => BlockKind::Loop,
+            _ => BlockKind::If,
+        };
+        let mut otherwise = None;
+        if kind == BlockKind::If {
+            self.effect(1, 0, line)?;
+            let label = self.builder.label();
+            self.builder.jump(jump::MODE_RELATIVE, Some(jump::TYPE_Z), label);
+            otherwise = Some(label);
+        }
+        let end = self.builder.label();
+        let target = if kind == BlockKind::Loop {
+            let start = self.builder.label();
+            self.builder.bind(start).unwrap();
+            start
+        } else {
+            end
+        };
+        let base = self.height;
+        self.blocks.push(Block { kind, label, base, results, target, end, otherwise });
+        Ok(())
+    }
+
+    /// Start the `else` branch of the innermost block
+    fn otherwise(&mut self, line: u32) -> Result<(), CompileError> {
+        let block = self.blocks.last().unwrap();
+        let otherwise = match (block.kind, block.otherwise) {
+            (BlockKind::If, Some(otherwise)) => otherwise,
+            _ => return Err(syntax(line, "`else` outside of an `if` block")),
+        };
+        self.check_height(line)?;
+        let end = block.end;
+        self.builder.jump(jump::MODE_RELATIVE, None, end);
+        self.builder.bind(otherwise).unwrap();
+        let block = self.blocks.last_mut().unwrap();
+        block.otherwise = None;
+        self.height = block.base;
+        self.unreachable = false;
+        Ok(())
+    }
+
+    /// End the innermost block
+    fn end(&mut self, line: u32) -> Result<(), CompileError> {
+        self.check_height(line)?;
+        let block = self.blocks.pop().unwrap();
+        if let Some(otherwise) = block.otherwise {
+            if block.results > 0 {
+                return Err(syntax(line, "an `if` block with results must have an `else`"));
+            }
+            self.builder.bind(otherwise).unwrap();
+        }
+        self.builder.bind(block.end).unwrap();
+        self.height = block.base + block.results;
+        self.unreachable = false;
+        Ok(())
+    }
+
+    /// Check that the innermost block leaves the values it declares
+    fn check_height(&self, line: u32) -> Result<(), CompileError> {
+        let block = self.blocks.last().unwrap();
+        if !self.unreachable && self.height != block.base + block.results {
+            let message = format!(
+                "block leaves {} values, but declares {}",
+                self.height.saturating_sub(block.base),
+                block.results
+            );
+            return Err(CompileError::InvalidSyntax(line, message));
+        }
+        Ok(())
+    }
+
+    /// Compile an instruction with at most one immediate
+    fn op(&mut self, op: &str, immediate: Option<&Sexp>, line: u32) -> Result<(), CompileError> {
+        match op {
+            "local.get" => {
+                let index = self.local(immediate, line)?;
+                self.emit(Instruction::GetU64C(index));
+                self.effect(0, 1, line)
+            }
+            "local.set" => {
+                let index = self.local(immediate, line)?;
+                self.emit(Instruction::Set64C(index));
+                self.effect(1, 0, line)
+            }
+            "local.tee" => {
+                let index = self.local(immediate, line)?;
+                self.emit(Instruction::Dupe1);
+                self.emit(Instruction::Set64C(index));
+                self.effect(1, 1, line)
+            }
+            "i64.const" | "f64.const" => {
+                let text = immediate.and_then(Sexp::atom).unwrap_or("");
+                let value = if op == "i64.const" {
+                    parse_int(text)
+                } else {
+                    parse_float(text).map(f64::to_bits)
+                };
+                match value {
+                    Some(value) => self.builder.emit_words(&optimize::encode_constant(value)),
+                    None => return Err(syntax(line, &format!("invalid constant `{}`", text))),
+                }
+                self.effect(0, 1, line)
+            }
+            "call" => {
+                let target = parse_ref(immediate, line)?;
+                let index = resolve(self.ids, self.funcs.len(), &target, line)? as usize;
+                if index < self.imports {
+                    self.emit(Instruction::CallExtC(index as u16));
+                } else {
+                    self.emit(Instruction::CallC((index - self.imports) as u16));
+                }
+                let callee = &self.funcs[index];
+                self.effect(callee.params.len(), callee.results, line)
+            }
+            "br" => {
+                let depth = self.depth(immediate, line)?;
+                self.branch(depth, line)?;
+                self.unreachable = true;
+                Ok(())
+            }
+            "br_if" => {
+                let depth = self.depth(immediate, line)?;
+                self.effect(1, 0, line)?;
+                let skip = self.builder.label();
+                self.builder.jump(jump::MODE_RELATIVE, Some(jump::TYPE_Z), skip);
+                self.branch(depth, line)?;
+                self.builder.bind(skip).unwrap();
+                Ok(())
+            }
+            "return" => {
+                self.branch(0, line)?;
+                self.unreachable = true;
+                Ok(())
+            }
+            "drop" => {
+                self.emit(Instruction::Pop1);
+                self.effect(1, 0, line)
+            }
+            "nop" => {
+                self.emit(Instruction::Nop);
+                Ok(())
+            }
+            _ => {
+                if let Some((condition, pops)) = comparison(op) {
+                    self.builder.condition_value(condition);
+                    return self.effect(pops, 1, line);
+                }
+                let (code, pops): (&[Instruction], usize) = match op {
+                    "i64.add" => (&[Instruction::Add], 2),
+                    "i64.sub" => (&[Instruction::Swap1, Instruction::Sub], 2),
+                    "i64.mul" => (&[Instruction::Mul], 2),
+                    "i64.div_s" => (&[Instruction::Swap1, Instruction::Idiv], 2),
+                    "i64.div_u" => (&[Instruction::Swap1, Instruction::Div], 2),
+                    "i64.rem_s" => (&[Instruction::Swap1, Instruction::Imod], 2),
+                    "i64.rem_u" => (&[Instruction::Swap1, Instruction::Mod], 2),
+                    "f64.add" => (&[Instruction::Fadd], 2),
+                    "f64.sub" => (&[Instruction::Swap1, Instruction::Fsub], 2),
+                    "f64.mul" => (&[Instruction::Fmul], 2),
+                    "f64.div" => (&[Instruction::Swap1, Instruction::Fdiv], 2),
+                    "f64.neg" => (&[Instruction::Fneg], 1),
+                    "f64.convert_i64_s" => (&[Instruction::Itof], 1),
+                    "i64.trunc_f64_s" | "i64.trunc_sat_f64_s" => (&[Instruction::Ftoi], 1),
+                    _ => {
+                        return Err(CompileError::Unsupported(line, format!("`{}`", op)));
+                    }
+                };
+                for instruction in code.iter() {
+                    self.emit(*instruction);
+                }
+                self.effect(pops, 1, line)
+            }
+        }
+    }
+
+    fn local(&self, immediate: Option<&Sexp>, line: u32) -> Result<u16, CompileError> {
+        let count = self.func.params.len() + self.func.locals.len();
+        match parse_ref(immediate, line)? {
+            Ref::Id(id) => {
+                self.locals.get(&id).copied().ok_or(CompileError::UndefinedName(line, id))
+            }
+            Ref::Index(index) if (index as usize) < count => Ok(index as u16),
+            Ref::Index(index) => Err(CompileError::UndefinedName(line, index.to_string())),
+        }
+    }
+
+    /// Get the index into `blocks` of the block a branch refers to
+    fn depth(&self, immediate: Option<&Sexp>, line: u32) -> Result<usize, CompileError> {
+        let depth = match parse_ref(immediate, line)? {
+            Ref::Id(id) => {
+                let found = self.blocks.iter().rposition(|b| b.label.as_deref() == Some(&id));
+                return found.ok_or(CompileError::UndefinedName(line, id));
+            }
+            Ref::Index(depth) => depth as usize,
+        };
+        match self.blocks.len().checked_sub(depth + 1) {
+            Some(index) => Ok(index),
+            None => Err(CompileError::UndefinedName(line, depth.to_string())),
+        }
+    }
+
+    /// Jump to the target of a block, discarding any values on the stack
+    /// between the block's base and the values it takes
+    fn branch(&mut self, index: usize, line: u32) -> Result<(), CompileError> {
+        let block = &self.blocks[index];
+        let arity = if block.kind == BlockKind::Loop { 0 } else { block.results };
+        let target = block.target;
+        let kind = block.kind;
+        let base = block.base;
+        if !self.unreachable && self.height < base + arity {
+            return Err(syntax(line, "not enough values on the stack"));
+        }
+        let excess = self.height.saturating_sub(base + arity);
+        if excess > 0 && !self.unreachable {
+            match arity {
+                0 => (),
+                1 => self.emit(Instruction::Rotate1C(excess as u16 + 1)),
+                _ => {
+                    let message = String::from("branches discarding values below several results");
+                    return Err(CompileError::Unsupported(line, message));
+                }
+            }
+            self.emit(Instruction::PopC(excess as u16));
+        }
+        if kind == BlockKind::Function {
+            self.emit(Instruction::Return);
+        } else {
+            self.builder.jump(jump::MODE_RELATIVE, None, target);
+        }
+        Ok(())
+    }
+}
+
+/// Check if an instruction is followed by an immediate in the flat form
+fn takes_immediate(op: &str) -> bool {
+    matches!(
+        op,
+        "local.get"
+            | "local.set"
+            | "local.tee"
+            | "call"
+            | "br"
+            | "br_if"
+            | "i64.const"
+            | "f64.const"
+    )
+}
+
+/// Parse the optional label and result types following a block instruction
+///
+/// Returns the label, the number of results, and the number of items used.
+fn block_header(items: &[Sexp], line: u32) -> Result<(Option<String>, usize, usize), CompileError> {
+    let mut used = 0;
+    let label = items.first().and_then(Sexp::id).map(String::from);
+    if label.is_some() {
+        used += 1;
+    }
+    let mut results = 0;
+    while let Some(item) = items.get(used) {
+        if let Some(types) = item.form("result") {
+            results += value_types(types, item.line())?;
+        } else if item.form("param").is_some() || item.form("type").is_some() {
+            return Err(CompileError::Unsupported(line, String::from("block parameters")));
+        } else {
+            break;
+        }
+        used += 1;
+    }
+    Ok((label, results, used))
+}
+
+/// Get the jump condition computing a comparison, and the number of operands
+///
+/// The jump conditions compare the topmost value against the one below it,
+/// which is the right hand operand of the comparison, so the ordered
+/// conditions are reversed.
+fn comparison(op: &str) -> Option<(u8, usize)> {
+    let comparison = match op {
+        "i64.eqz" => (jump::TYPE_Z, 1),
+        "i64.eq" => (jump::TYPE_EQ, 2),
+        "i64.ne" => (jump::TYPE_NEQ, 2),
+        "i64.lt_s" => (jump::TYPE_GTS, 2),
+        "i64.lt_u" => (jump::TYPE_GT, 2),
+        "i64.gt_s" => (jump::TYPE_LTS, 2),
+        "i64.gt_u" => (jump::TYPE_LT, 2),
+        "i64.le_s" => (jump::TYPE_GES, 2),
+        "i64.le_u" => (jump::TYPE_GE, 2),
+        "i64.ge_s" => (jump::TYPE_LES, 2),
+        "i64.ge_u" => (jump::TYPE_LE, 2),
+        _ => return None,
+    };
+    Some(comparison)
+}
@@ -0,0 +1,100 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+
+/// Counts the allocations made by each thread
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Count the allocations made by a function
+fn allocations(f: impl FnOnce()) -> u64 {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+const PROGRAM: &str = ".func square
+        RESERVE_C 1
+        SET_64_C 0
+        GET_U64_C 0
+        GET_U64_C 0
+        MUL
+        RETURN
+    .func sum
+        CALL_C square
+        SWAP_1
+        CALL_C square
+        ADD
+        RETURN
+    .export main
+        CONST_U16 3
+        CONST_4
+        CALL_C sum
+        CONST_8
+        CONST_16
+        CALL_C sum
+        DUPE_C 2
+        ROTATE_1_C 3
+        POP_C 2
+        ADD
+        RETURN
+    .export host
+        CONST_0
+        CONST_1
+        CONST_1
+        ARG_GET
+        PRINT_U64
+        CONST_0
+        CONST_U16 13
+        LOG
+        PRINT_STACK
+        RETURN";
+
+#[test]
+fn test_run_without_allocating() {
+    let mut module = asm::assemble("main", PROGRAM, Path::new(".")).unwrap();
+    module.writable_data = vec![u64::from_le_bytes(*b"no alloc"), 0];
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.set_stdout(std::io::sink());
+    engine.set_stderr(std::io::sink());
+    engine.args = vec![String::from("arg")];
+    let main = engine.modules[0].find_symbol("main").unwrap();
+    let host = engine.modules[0].find_symbol("host").unwrap();
+
+    // The first runs grow the stack, calls, and locals, and copy the data
+    // segment written to by `ARG_GET`
+    engine.run(0, main).unwrap();
+    engine.run(0, host).unwrap();
+    engine.stack.clear();
+    assert_eq!(allocations(|| engine.run(0, main).unwrap()), 0);
+    assert_eq!(engine.stack, vec![345]);
+    engine.stack.clear();
+    assert_eq!(allocations(|| engine.run(0, host).unwrap()), 0);
+    assert!(engine.stack.is_empty());
+}
@@ -0,0 +1,225 @@
+mod common;
+
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::errors::CompileError;
+use tstack::module::{Relocation, RelocationKind};
+
+use common::{listing, test_dir};
+
+#[test]
+fn test_run_program() {
+    let module = asm::assemble(
+        "main",
+        "; Compute (6 * 7) + 16
+        .export main
+            CONST_U16 6
+            CONST_U16 7
+            MUL
+            CONST_16
+            ADD     ; comments may follow code
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    assert_eq!(module.symbol(0).unwrap().name(), Some("main"));
+    assert!(module.symbol(0).unwrap().exported());
+    assert_eq!(module.symbol(0).unwrap().signature(), None);
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![58]);
+}
+
+#[test]
+fn test_constants_and_repetition() {
+    let module = asm::assemble(
+        "main",
+        ".const BASE 3
+        .const COUNT BASE
+        .const NEGATIVE -0x10
+        .export main
+            CONST_U16 BASE
+        .rept COUNT
+            DUPE_1
+            .rept 2
+                CONST_I16 NEGATIVE
+            .endr
+            ADD
+            ADD
+        .endr
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    assert_eq!(
+        listing(&module, 0),
+        vec!["CONST_U16 3"]
+            .into_iter()
+            .chain(["DUPE_1", "CONST_I16 -16", "CONST_I16 -16", "ADD", "ADD"].repeat(3))
+            .chain(["RETURN"])
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_include() {
+    let dir = test_dir("include");
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    std::fs::write(dir.join("lib/consts.tasm"), ".const SIX 6\n").unwrap();
+    std::fs::write(
+        dir.join("lib/square.tasm"),
+        ".include \"consts.tasm\"\n.func square\n    DUPE_1\n    MUL\n    RETURN\n",
+    )
+    .unwrap();
+    let module = asm::assemble(
+        "main",
+        ".include \"lib/square.tasm\" ; resolved against the directory
+        .export main
+            CONST_U16 SIX
+            CALL_C square
+            RETURN",
+        &dir,
+    )
+    .unwrap();
+    assert_eq!(module.symbol(0).unwrap().name(), Some("square"));
+    assert!(!module.symbol(0).unwrap().exported());
+    assert_eq!(listing(&module, 1), vec!["CONST_U16 6", "CALL_C 0", "RETURN"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_labels_and_calls() {
+    let module = asm::assemble(
+        "app",
+        ".export main
+            CONST_4
+        top:
+            CALL_EXT_C host.log
+            CALL_C helper
+            JMP_NZ.REL top
+            JMP.REL done
+            CALL_EXT_C host.log
+        done:
+            RETURN
+        .func helper
+        top:
+            JMP.C16.REL -2",
+        Path::new("."),
+    )
+    .unwrap();
+    assert_eq!(
+        listing(&module, 0),
+        vec![
+            "CONST_4",
+            "CALL_EXT_C 0",
+            "CALL_C 1",
+            "JMP_NZ.C16.REL -6",
+            "JMP.C16.REL +2",
+            "CALL_EXT_C 0",
+            "RETURN",
+        ]
+    );
+    assert_eq!(module.external_symbols.len(), 1);
+    let ext = &module.external_symbols[0];
+    assert_eq!(&module.strings[ext.module_name_id as usize], "host");
+    assert_eq!(&module.strings[ext.symbol_name_id as usize], "log");
+    assert_eq!(
+        module.relocations[..2],
+        [
+            Relocation { offset: 2, words: 1, kind: RelocationKind::ExternalSymbol },
+            Relocation { offset: 4, words: 1, kind: RelocationKind::LocalSymbol },
+        ]
+    );
+}
+
+#[test]
+fn test_run_labelled_loop() {
+    let module = asm::assemble(
+        "app",
+        ".export main
+            CONST_4
+        top:
+            CALL_EXT_C host.log
+            SUB_C 1
+            DUPE_1
+            JMP_NZ.REL top
+            JMP.REL done
+            CONST_U16 99
+        done:
+            CALL_C helper
+            RETURN
+        .func helper
+            CONST_1
+            JMP_Z.REL skip
+            ADD_C 5
+        skip:
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    let logged = Rc::new(std::cell::RefCell::new(vec![]));
+    let mut engine = tstack::Engine::new();
+    let log = Rc::clone(&logged);
+    engine
+        .register_fn("host", "log", move |n: u64| {
+            log.borrow_mut().push(n);
+            n
+        })
+        .unwrap();
+    let app = engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+    engine.run(app, 0).unwrap();
+    assert_eq!(*logged.borrow(), vec![4, 3, 2, 1]);
+    assert_eq!(engine.stack, vec![5]);
+}
+
+#[test]
+fn test_assemble_errors() {
+    let error = |source: &str| asm::assemble("m", source, Path::new(".")).unwrap_err();
+
+    assert!(matches!(error("CONST_0"), CompileError::InvalidSyntax(1, _)));
+    assert!(matches!(error(".func f\n  CONST_9"), CompileError::InvalidSyntax(2, _)));
+    assert!(matches!(error(".func f\n  CONST_U16 70000"), CompileError::InvalidSyntax(2, _)));
+    assert!(matches!(
+        error(".func f\n  CONST_U16 SIZE"),
+        CompileError::UndefinedName(2, name) if name == "SIZE"
+    ));
+    assert!(matches!(
+        error(".func f\n  JMP.REL nowhere\n  RETURN"),
+        CompileError::UndefinedName(2, name) if name == "nowhere"
+    ));
+    assert!(matches!(
+        error(".func f\n  CALL_C g"),
+        CompileError::UndefinedName(2, name) if name == "g"
+    ));
+    assert!(matches!(error(".func f\n  JMP.C16 top\ntop:"), CompileError::InvalidSyntax(2, _)));
+    assert!(matches!(
+        error(".func f\n.export f"),
+        CompileError::DuplicateName(2, name) if name == "f"
+    ));
+    assert!(matches!(
+        error(".func f\na:\na:"),
+        CompileError::DuplicateName(3, name) if name == "a"
+    ));
+    assert!(matches!(
+        error(".const A 1\n.const A 2"),
+        CompileError::DuplicateName(2, name) if name == "A"
+    ));
+    assert!(matches!(error(".rept 2\n.const A 1\n.endr"), CompileError::DuplicateName(2, _)));
+    assert!(matches!(error(".func f\n.rept 2\n  NOP"), CompileError::InvalidSyntax(2, _)));
+    assert!(matches!(error(".endr"), CompileError::InvalidSyntax(1, _)));
+    assert!(matches!(error(".rept -1\n.endr"), CompileError::InvalidSyntax(1, _)));
+    assert!(matches!(error(".data 1"), CompileError::Unsupported(1, _)));
+    assert!(matches!(error(".include missing.tasm"), CompileError::InvalidSyntax(1, _)));
+    assert!(matches!(error("\n.include \"missing.tasm\""), CompileError::InvalidInclude(2, _)));
+    assert!(matches!(error(".func f\n  CONST_U16 \"1\""), CompileError::InvalidSyntax(2, _)));
+
+    let dir = test_dir("recursive");
+    std::fs::write(dir.join("self.tasm"), ".include \"self.tasm\"\n").unwrap();
+    let error = asm::assemble("m", ".include \"self.tasm\"", &dir).unwrap_err();
+    assert!(matches!(error, CompileError::InvalidSyntax(1, _)));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
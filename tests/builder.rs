@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use tstack::builder::BytecodeBuilder;
+use tstack::bytecode::{jump, Instruction};
+use tstack::errors::BuildError;
+use tstack::module::{LocalSymbol, Module};
+
+#[test]
+fn test_forward_and_backward_labels() {
+    let mut b = BytecodeBuilder::new();
+    let top = b.label();
+    let end = b.label();
+    b.bind(top).unwrap();
+    b.emit(tstack::inst_stack!(CONST_1));
+    b.jump(jump::MODE_ABSOLUTE, Some(jump::TYPE_Z), end);
+    b.jump(jump::MODE_RELATIVE, None, top);
+    b.bind(end).unwrap();
+    b.emit(tstack::inst_sys!(HALT));
+
+    assert_eq!(
+        b.build().unwrap(),
+        vec![
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_jump!(SRC_C16, MODE_ABSOLUTE, TYPE_Z),
+            5,
+            tstack::inst_jump!(SRC_C16, MODE_RELATIVE),
+            -5i16 as u16,
+            tstack::inst_sys!(HALT),
+        ]
+    );
+}
+
+#[test]
+fn test_widens_far_jumps() {
+    let mut b = BytecodeBuilder::new();
+    let far = b.label();
+    let near = b.label();
+    b.jump(jump::MODE_ABSOLUTE, None, far);
+    b.jump(jump::MODE_RELATIVE, None, near);
+    b.bind(near).unwrap();
+    b.emit_words(&vec![tstack::inst_sys!(NOP); 0x10000]);
+    b.bind(far).unwrap();
+    b.emit(tstack::inst_sys!(HALT));
+
+    let code = b.build().unwrap();
+    assert_eq!(code[0], tstack::inst_jump!(SRC_C32, MODE_ABSOLUTE));
+    assert_eq!(&code[1..3], &[0x0001, 0x0005]);
+    assert_eq!(code[3], tstack::inst_jump!(SRC_C16, MODE_RELATIVE));
+    assert_eq!(code[4], 0);
+    assert_eq!(code[0x10005], tstack::inst_sys!(HALT));
+}
+
+#[test]
+fn test_widening_cascades() {
+    // Widening the second jump pushes the target of the first out of range
+    let mut b = BytecodeBuilder::new();
+    let first = b.label();
+    let second = b.label();
+    b.jump(jump::MODE_RELATIVE, None, first);
+    b.emit_words(&vec![tstack::inst_sys!(NOP); 0x7FFD]);
+    b.jump(jump::MODE_RELATIVE, None, second);
+    b.bind(first).unwrap();
+    b.emit_words(&vec![tstack::inst_sys!(NOP); 0x8000]);
+    b.bind(second).unwrap();
+
+    let code = b.build().unwrap();
+    assert_eq!(code[0], tstack::inst_jump!(SRC_C32, MODE_RELATIVE));
+    assert_eq!(code[0x8000], tstack::inst_jump!(SRC_C32, MODE_RELATIVE));
+}
+
+#[test]
+fn test_unbound_label() {
+    let mut b = BytecodeBuilder::new();
+    let label = b.label();
+    b.jump(jump::MODE_ABSOLUTE, None, label);
+    assert!(matches!(b.build(), Err(BuildError::UnboundLabel(0))));
+}
+
+#[test]
+fn test_duplicate_label() {
+    let mut b = BytecodeBuilder::new();
+    let label = b.label();
+    b.bind(label).unwrap();
+    assert!(matches!(b.bind(label), Err(BuildError::DuplicateLabel(0))));
+}
+
+#[test]
+fn test_condition_value() {
+    let mut b = BytecodeBuilder::new();
+    b.condition_value(jump::TYPE_LT);
+    assert_eq!(
+        b.build().unwrap(),
+        vec![
+            tstack::inst_jump!(SRC_C16, MODE_RELATIVE, TYPE_LT),
+            3,
+            tstack::inst_stack!(CONST_0),
+            tstack::inst_jump!(SRC_C16, MODE_RELATIVE),
+            1,
+            tstack::inst_stack!(CONST_1),
+        ]
+    );
+}
+
+#[test]
+fn test_run_loop() {
+    let mut b = BytecodeBuilder::new();
+    let (start, top, done) = (b.label(), b.label(), b.label());
+    // Skipping far ahead needs a wide jump, as does jumping back to `top`
+    b.jump(jump::MODE_ABSOLUTE, None, start);
+    b.emit_words(&vec![tstack::inst_sys!(NOP); 0x10000]);
+    b.bind(start).unwrap();
+    b.emit_instruction(Instruction::Const0);
+    b.emit_instruction(Instruction::ConstU16(5));
+    b.bind(top).unwrap();
+    b.emit_instruction(Instruction::Dupe1);
+    b.jump(jump::MODE_RELATIVE, Some(jump::TYPE_Z), done);
+    b.emit_instruction(Instruction::Swap1);
+    b.emit_instruction(Instruction::AddC(3));
+    b.emit_instruction(Instruction::Swap1);
+    b.emit_instruction(Instruction::SubC(1));
+    b.jump(jump::MODE_ABSOLUTE, None, top);
+    b.bind(done).unwrap();
+    b.emit_instruction(Instruction::Pop1);
+    b.emit_instruction(Instruction::Dupe1);
+    b.emit_instruction(Instruction::ConstU16(20));
+    b.condition_value(jump::TYPE_GT);
+    b.emit_instruction(Instruction::Return);
+    let code = b.build().unwrap();
+    assert_eq!(code[0], tstack::inst_jump!(SRC_C32, MODE_ABSOLUTE));
+
+    let module = Module {
+        name: String::from("main"),
+        version: Default::default(),
+        strings: vec![String::from("main")].into(),
+        data: vec![],
+        writable_data: vec![],
+        local_symbols: vec![LocalSymbol {
+            name_id: 0,
+            code_offset: 0,
+            exported: true,
+            signature: None,
+        }],
+        external_symbols: vec![],
+        globals: vec![],
+        external_globals: vec![],
+        bytecode: code.into(),
+        relocations: vec![],
+        symbol_lookup: HashMap::new(),
+        debug: None,
+    };
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.run(0, 0).unwrap();
+    // Five passes add 3 each, and 20 is greater than the total
+    assert_eq!(engine.stack, vec![15, 1]);
+}
@@ -0,0 +1,92 @@
+#![cfg(feature = "capi")]
+
+use std::ffi::{c_void, CStr, CString};
+use std::path::Path;
+
+use tstack::asm;
+use tstack::capi::*;
+
+extern "C" fn scale(stack: *mut TsStack, user_data: *mut c_void) -> i32 {
+    let factor = unsafe { *(user_data as *const u64) };
+    let mut value = 0;
+    unsafe {
+        if ts_stack_pop(stack, &mut value) != TS_OK {
+            return TS_ERROR;
+        }
+        ts_stack_push(stack, value * factor)
+    }
+}
+
+extern "C" fn fail(_: *mut TsStack, _: *mut c_void) -> i32 {
+    7
+}
+
+fn error(engine: *const TsEngine) -> String {
+    unsafe { CStr::from_ptr(ts_engine_error(engine)).to_str().unwrap().to_string() }
+}
+
+#[test]
+fn test_capi() {
+    let module = asm::assemble(
+        "main",
+        ".export main
+            CONST_U16 6
+            CALL_EXT_C host.scale
+            RETURN
+        .export broken
+            CALL_EXT_C host.fail
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    let bytes = module.to_bytes();
+    let host = CString::new("host").unwrap();
+    let main = CString::new("main").unwrap();
+    let factor = 7u64;
+
+    unsafe {
+        let engine = ts_engine_new();
+        let symbol = CString::new("scale").unwrap();
+        let user_data = &factor as *const u64 as *mut c_void;
+        assert_eq!(ts_engine_register(engine, host.as_ptr(), symbol.as_ptr(), scale, user_data), 0);
+        let symbol = CString::new("fail").unwrap();
+        let id = ts_engine_register(engine, host.as_ptr(), symbol.as_ptr(), fail, user_data);
+        assert_eq!(id, 1);
+        assert_eq!(ts_engine_load(engine, bytes.as_ptr(), bytes.len()), 1);
+        assert_eq!(ts_engine_link(engine), TS_OK);
+        assert_eq!(error(engine), "");
+
+        let (mut module_id, mut symbol_id) = (0, 0);
+        assert_eq!(
+            ts_engine_find(engine, main.as_ptr(), main.as_ptr(), &mut module_id, &mut symbol_id),
+            TS_OK
+        );
+        assert_eq!((module_id, symbol_id), (1, 0));
+        assert_eq!(ts_engine_run(engine, module_id, symbol_id), TS_OK);
+        assert_eq!(ts_engine_stack_len(engine), 1);
+        assert_eq!(*ts_engine_stack(engine), 42);
+
+        ts_engine_clear(engine);
+        assert_eq!(ts_engine_run(engine, 1, 1), TS_ERROR);
+        assert_eq!(
+            error(engine),
+            "native function failed: native function returned 7 in main:broken at offset 5"
+        );
+        assert_eq!(ts_engine_run(engine, 0, 0), TS_ERROR);
+        assert!(error(engine).starts_with("too few operands"));
+        assert_eq!(ts_engine_push(engine, 3), TS_OK);
+        assert_eq!(ts_engine_run(engine, 0, 0), TS_OK);
+        assert_eq!(*ts_engine_stack(engine), 21);
+
+        let missing = CString::new("missing").unwrap();
+        assert_eq!(
+            ts_engine_find(engine, main.as_ptr(), missing.as_ptr(), &mut module_id, &mut symbol_id),
+            TS_ERROR
+        );
+        assert_eq!(error(engine), "symbol missing of module main is not loaded");
+        assert_eq!(ts_engine_load(engine, bytes.as_ptr(), 3), TS_ERROR as i64);
+        assert_eq!(ts_engine_load_file(engine, missing.as_ptr()), TS_ERROR as i64);
+        assert!(error(engine).starts_with("can not read missing"));
+        ts_engine_free(engine);
+    }
+}
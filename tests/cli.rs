@@ -0,0 +1,581 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use tstack::asm;
+
+use common::test_dir;
+
+/// Assemble a module and write it to a file in `dir`
+fn write_module(dir: &Path, name: &str, source: &str) -> PathBuf {
+    let module = asm::assemble(name, source, Path::new(".")).unwrap();
+    let path = dir.join(format!("{}.tsb", name));
+    std::fs::write(&path, module.to_bytes()).unwrap();
+    path
+}
+
+fn tstack(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_tstack")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn test_run() {
+    let dir = test_dir("run");
+    let lib = write_module(
+        &dir,
+        "lib",
+        ".export answer
+            CONST_U16 42
+            RETURN",
+    );
+    let main = write_module(
+        &dir,
+        "main",
+        ".export main
+            CALL_EXT_C lib.answer
+            PRINT_U64
+            RETURN
+        .export broken
+            CONST_1
+            ADD
+            RETURN
+        .export nested
+            CALL_C broken
+            RETURN",
+    );
+    let (main, lib) = (main.to_str().unwrap(), lib.to_str().unwrap());
+
+    let output = tstack(&["run", main, lib]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("PRINT: 42\n"));
+
+    let output = tstack(&["run", main, lib, "--entry", "main:broken"]);
+    assert_eq!(output.status.code(), Some(1));
+    let error = stderr(&output);
+    assert!(error.starts_with("error: too few operands"), "{}", error);
+    assert!(error.contains("\n  in main:broken at offset "), "{}", error);
+
+    let output = tstack(&["run", main, lib, "--entry", "main:nested"]);
+    let error = stderr(&output);
+    assert!(
+        error.contains("\n  in main:broken at offset 5\n  called from main:nested at offset 7\n"),
+        "{}",
+        error
+    );
+
+    let output = tstack(&["run", main]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("lib"));
+
+    let output = tstack(&["run", main, lib, "--entry", "main:missing"]);
+    assert_eq!(stderr(&output), "error: module main has no symbol missing\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_usage() {
+    for args in [&[][..], &["run"], &["frobnicate"], &["run", "x.tsb", "--entry", "main"]] {
+        let output = tstack(args);
+        assert_eq!(output.status.code(), Some(2));
+        assert!(stderr(&output).contains("usage: tstack run"));
+    }
+    let output = tstack(&["run", "missing.tsb"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).starts_with("error: can not read missing.tsb"));
+    assert!(tstack(&["help"]).status.success());
+}
+
+#[test]
+fn test_repl() {
+    let dir = test_dir("repl");
+    let lib = write_module(
+        &dir,
+        "lib",
+        ".export double
+            CONST_2
+            MUL
+            RETURN",
+    );
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tstack"))
+        .args(["repl", lib.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"CONST_U16 20\nCALL_EXT_C lib.double\nCONST_2\nADD\nFOO\n:lang\n6 * 7\n:clear\n:quit\nCONST_1\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let lines: Vec<String> = stdout(&output)
+        .split("> ")
+        .filter_map(|entry| entry.lines().last().map(String::from))
+        .collect();
+    assert_eq!(lines, vec!["[20]", "[40]", "[40, 2]", "[42]", "[42]", "[42]", "[42, 42]", "[]",]);
+    assert!(stdout(&output).contains("error: line 2: invalid syntax"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_trace() {
+    let dir = test_dir("trace");
+    let main = write_module(
+        &dir,
+        "main",
+        ".func helper
+            CONST_2
+            ADD
+            RETURN
+        .export main
+            CONST_1
+            CALL_C helper
+            PRINT_U64
+            RETURN",
+    );
+    let main = main.to_str().unwrap();
+
+    let output = tstack(&["run", main]);
+    assert_eq!(stdout(&output), "PRINT: 3\n");
+    assert_eq!(stderr(&output), "");
+
+    let output = tstack(&["run", main, "--trace"]);
+    assert_eq!(stdout(&output), "PRINT: 3\n");
+    assert_eq!(
+        stderr(&output),
+        "main:0003 CONST_1\nmain:0004 CALL_C 0\nmain:0000 CONST_2\nmain:0001 ADD\n\
+         main:0002 RETURN\nmain:0006 PRINT_U64\nmain:0007 RETURN\n"
+    );
+
+    let output = tstack(&["run", main, "--trace=stack"]);
+    assert!(stderr(&output).contains("main:0001 ADD                      [1, 2]\n"));
+
+    let output = tstack(&["run", main, "--trace=calls"]);
+    assert_eq!(stderr(&output), "call main:main\n  call main:helper\n  return\nreturn\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_limits() {
+    let dir = test_dir("limits");
+    let main = write_module(
+        &dir,
+        "main",
+        ".func helper
+            RETURN
+        .export main
+            CONST_1
+            CONST_2
+            CALL_C helper
+            RETURN",
+    );
+    let main = main.to_str().unwrap();
+    let run = |limits: &[&str]| {
+        let output = tstack(&[&["run", main], limits].concat());
+        (output.status.code(), stderr(&output))
+    };
+
+    assert_eq!(run(&["--max-stack", "2", "--fuel", "5", "--timeout", "10s"]), (Some(0), "".into()));
+    let (status, error) = run(&["--max-stack", "1"]);
+    assert_eq!(status, Some(1));
+    assert!(error.starts_with("error: stack size exceeded"), "{}", error);
+    let (_, error) = run(&["--fuel", "4"]);
+    assert!(error.starts_with("error: out of fuel before opcode"), "{}", error);
+    let (_, error) = run(&["--max-call-depth", "0"]);
+    assert!(error.starts_with("error: call depth exceeded"), "{}", error);
+    let (_, error) = run(&["--timeout", "0ms"]);
+    assert!(error.starts_with("error: deadline exceeded"), "{}", error);
+
+    for limits in [&["--fuel"][..], &["--fuel", "-1"], &["--timeout", "soon"]] {
+        let (status, error) = run(limits);
+        assert_eq!(status, Some(2));
+        assert!(error.starts_with("error: "), "{}", error);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_halt_status() {
+    let dir = test_dir("halt");
+    let main = write_module(
+        &dir,
+        "main",
+        ".func helper
+            CONST_U16 42
+            HALT
+            RETURN
+        .export main
+            CALL_C helper
+            CONST_1
+            PRINT_U64
+            RETURN
+        .export large
+            CONST_U16 1000
+            HALT
+        .export negative
+            CONST_N1
+            HALT",
+    );
+    let main = main.to_str().unwrap();
+    let output = tstack(&["run", main]);
+    assert_eq!(output.status.code(), Some(42));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(tstack(&["run", main, "--entry", "main:large"]).status.code(), Some(255));
+    assert_eq!(tstack(&["run", main, "--entry", "main:negative"]).status.code(), Some(255));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_program_args() {
+    let dir = test_dir("args");
+    let main = write_module(
+        &dir,
+        "main",
+        ".export main
+            ARG_COUNT
+            HALT",
+    );
+    let main = main.to_str().unwrap();
+    assert_eq!(tstack(&["run", main]).status.code(), Some(0));
+    let output = tstack(&["run", main, "--fuel", "10", "--", "a", "--fuel", "--", "b"]);
+    assert_eq!(output.status.code(), Some(4), "{}", stderr(&output));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_debug() {
+    let dir = test_dir("debug");
+    let main = write_module(
+        &dir,
+        "main",
+        ".func helper
+            CONST_2
+            ADD
+            RETURN
+        .export main
+            CONST_1
+            CALL_C helper
+            BREAKPOINT
+            CONST_U16 7
+            HALT",
+    );
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tstack"))
+        .args(["debug", main.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(
+            b"break helper\nbreakpoints\ncontinue\nlist\nstack\nstep 3\nwhere\nc\nfoo\nstep\n",
+        )
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(7));
+    let out = stdout(&output);
+    let replies: Vec<&str> = out.split("(tdb) ").collect();
+    assert_eq!(replies[0], "main:main+0  CONST_1\n");
+    assert_eq!(replies[1], "breakpoint set at main:helper+0\n");
+    assert_eq!(replies[2], "main:helper+0\n");
+    assert_eq!(replies[3], "breakpoint at main:helper+0  CONST_2\n");
+    assert_eq!(replies[4], "=> 0000  CONST_2\n   0001  ADD\n   0002  RETURN\n");
+    assert_eq!(replies[5], "[1]\n");
+    assert_eq!(replies[6], "main:main+3  BREAKPOINT\n");
+    assert_eq!(replies[7], "main:main+3  BREAKPOINT\n");
+    assert_eq!(replies[8], "program halted with 7\nstack: [3]\n");
+    assert!(replies[9].starts_with("unknown command foo"));
+    assert_eq!(replies[10], "the program has finished\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_profile() {
+    let dir = test_dir("profile");
+    let main = write_module(
+        &dir,
+        "main",
+        ".func helper
+            CONST_2
+            ADD
+            RETURN
+        .export main
+            CONST_1
+            CALL_C helper
+            CALL_C helper
+            RETURN",
+    );
+    let main = main.to_str().unwrap();
+    let output = tstack(&["profile", main]);
+    assert!(output.status.success());
+    let text = stdout(&output);
+    assert!(text.starts_with("instructions: 10\n"));
+    assert!(text.contains("main:helper"));
+
+    let output = tstack(&["profile", main, "--json"]);
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(report["instructions"], 10);
+    assert_eq!(
+        report["symbols"],
+        serde_json::json!([
+            {"module": "main", "symbol": "helper", "calls": 2, "instructions": 6, "total_instructions": 6},
+            {"module": "main", "symbol": "main", "calls": 1, "instructions": 4, "total_instructions": 10},
+        ])
+    );
+    assert_eq!(report["opcodes"][0], serde_json::json!({"opcode": "RETURN", "count": 3}));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_link() {
+    let dir = test_dir("link");
+    let lib = write_module(
+        &dir,
+        "lib",
+        ".export answer
+            CONST_U16 42
+            RETURN",
+    );
+    let main = write_module(
+        &dir,
+        "main",
+        ".export main
+            CALL_EXT_C lib.answer
+            PRINT_U64
+            RETURN",
+    );
+    let app = dir.join("app.tsb");
+    let (main, lib, app) = (main.to_str().unwrap(), lib.to_str().unwrap(), app.to_str().unwrap());
+
+    let output = tstack(&["link", main, lib, "-o", app]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = tstack(&["run", app]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("PRINT: 42\n"));
+
+    assert_eq!(tstack(&["link", main, lib]).status.code(), Some(2));
+    assert_eq!(tstack(&["link", main, main, "-o", app]).status.code(), Some(1));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_stats_and_coverage() {
+    let dir = test_dir("coverage");
+    let main = write_module(
+        &dir,
+        "main",
+        ".export main
+            CONST_1
+            CONST_2
+            ADD
+            RETURN
+        .export unused
+            CONST_1
+            RETURN
+        .export broken
+            ADD
+            RETURN",
+    );
+    let coverage = dir.join("coverage.json");
+    let (main, path) = (main.to_str().unwrap(), coverage.to_str().unwrap());
+
+    let output = tstack(&["run", main, "--stats", "--coverage", path]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let stats = stderr(&output);
+    assert!(stats.starts_with("instructions: 4\n"), "{}", stats);
+    assert!(stats.contains("\nADD "), "{}", stats);
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&coverage).unwrap()).unwrap();
+    assert_eq!(
+        report,
+        serde_json::json!({"modules": [{
+            "module": "main",
+            "instructions": 8,
+            "covered": 4,
+            "counts": [
+                {"offset": 0, "count": 1},
+                {"offset": 1, "count": 1},
+                {"offset": 2, "count": 1},
+                {"offset": 3, "count": 1},
+            ],
+            "uncovered": [4, 5, 6, 7],
+        }]})
+    );
+
+    let output = tstack(&["run", main, "--entry", "main:broken", "--coverage", path]);
+    assert_eq!(output.status.code(), Some(1));
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&coverage).unwrap()).unwrap();
+    assert_eq!(report["modules"][0]["counts"], serde_json::json!([{"offset": 6, "count": 1}]));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_analyze() {
+    let dir = test_dir("analyze");
+    let main = write_module(
+        &dir,
+        "main",
+        ".export main
+            CONST_1
+            CONST_2
+            ADD
+            CONST_2
+            ADD
+            RETURN",
+    );
+    let main = main.to_str().unwrap();
+    let trace = dir.join("trace.txt");
+    let stats = dir.join("stats.txt");
+    std::fs::write(&trace, tstack(&["run", main, "--trace=stack"]).stderr).unwrap();
+    let output = tstack(&["run", main, "--stats"]);
+    assert!(stderr(&output).contains("\nCONST_2 + ADD "), "{}", stderr(&output));
+    std::fs::write(&stats, output.stderr).unwrap();
+
+    // The counts of the trace and the statistics are merged
+    let output = tstack(&["analyze", trace.to_str().unwrap(), stats.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let lines: Vec<String> = stdout(&output).lines().map(String::from).collect();
+    assert_eq!(lines[0], "pairs");
+    assert!(lines[1].starts_with("CONST_2 + ADD "), "{}", lines[1]);
+    assert!(lines[1].split_whitespace().any(|word| word == "4"), "{}", lines[1]);
+    assert!(lines.contains(&String::from("triples")));
+
+    let output = tstack(&["analyze", trace.to_str().unwrap(), "--top", "1"]);
+    assert_eq!(stdout(&output).lines().count(), 4);
+    assert_eq!(tstack(&["analyze"]).status.code(), Some(2));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Run `tstack` with the given arguments and standard input
+fn tstack_with_input(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tstack"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_stdin() {
+    let dir = test_dir("stdin");
+    let lib = write_module(
+        &dir,
+        "lib",
+        ".export answer
+            CONST_U16 42
+            RETURN",
+    );
+    let lib = lib.to_str().unwrap();
+    let source = ".export main
+        CALL_EXT_C lib.answer
+        PRINT_U64
+        RETURN";
+
+    let module = asm::assemble("main", source, Path::new(".")).unwrap();
+    let output = tstack_with_input(&["run", "-", lib], &module.to_bytes());
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("PRINT: 42\n"));
+
+    let output = tstack_with_input(&["run", "--asm", "-", lib], source.as_bytes());
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("PRINT: 42\n"));
+
+    let tasm = dir.join("lib.tasm");
+    std::fs::write(&tasm, ".export answer\nCONST_U16 7\nRETURN").unwrap();
+    let output =
+        tstack_with_input(&["run", "--asm", "-", tasm.to_str().unwrap()], source.as_bytes());
+    assert!(stdout(&output).contains("PRINT: 7\n"), "{}", stderr(&output));
+
+    let output = tstack_with_input(&["run", "-", lib], source.as_bytes());
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).starts_with("error: can not load standard input"));
+
+    let output = tstack_with_input(&["run", "--asm", "-"], b"FOO");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).starts_with("error: can not assemble standard input"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_watch() {
+    let dir = test_dir("watch");
+    let path = dir.join("main.tasm");
+    std::fs::write(&path, ".export main\nCONST_1\nPRINT_U64\nRETURN").unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tstack"))
+        .args(["run", "--watch", "--fuel", "10", path.to_str().unwrap()])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut next_print =
+        || lines.find(|line| line.as_ref().unwrap().starts_with("PRINT")).unwrap().unwrap();
+    assert_eq!(next_print(), "PRINT: 1");
+
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    std::fs::write(&path, ".export main\nCONST_2\nPRINT_U64\nRETURN").unwrap();
+    assert_eq!(next_print(), "PRINT: 2");
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let output = tstack(&["debug", "--watch", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_invalid_module() {
+    let dir = test_dir("invalid");
+    let main = write_module(
+        &dir,
+        "main",
+        ".export main
+            DATA_GET_C 4
+            GLOBAL_GET_C 1
+            RETURN",
+    );
+    let output = tstack(&["run", main.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert_eq!(
+        stderr(&output),
+        "error: module main is invalid
+  main:0: error: DATA_GET_C 4 is past the end of the read-only data (0)
+  main:2: error: GLOBAL_GET_C 1 is past the end of the globals (0)\n"
+    );
+}
+
+#[test]
+fn test_lint_warnings() {
+    let dir = test_dir("lint");
+    let main = write_module(
+        &dir,
+        "main",
+        ".export main
+            RETURN
+            CONST_1
+            RETURN",
+    );
+    let output = tstack(&["run", main.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "main:1: warning: 2 instructions after RETURN can never run\n");
+}
@@ -0,0 +1,33 @@
+//! Helpers shared by the integration tests
+//!
+//! Each test binary only uses some of these, so unused ones are allowed.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use tstack::bytecode::Instruction;
+use tstack::module::Module;
+
+/// Disassemble the code of a symbol
+pub fn listing(module: &Module, symbol: u32) -> Vec<String> {
+    let code = module.symbol(symbol).unwrap().code();
+    let mut out = vec![];
+    let mut offset = 0;
+    while offset < code.len() {
+        let (instruction, len) = Instruction::decode(&code[offset..]).unwrap();
+        out.push(instruction.to_string());
+        offset += len;
+    }
+    out
+}
+
+/// Create an empty directory for the files of a test
+///
+/// The directory is named after the test binary's process, so tests in
+/// different binaries may share a name.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tstack-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,46 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::coverage::{Coverage, ModuleCoverage};
+use tstack::trace::TraceEvent;
+
+#[test]
+fn test_coverage() {
+    let mut engine = tstack::Engine::new();
+    let module = asm::assemble(
+        "main",
+        ".func helper
+            CONST_U16 300
+            ADD
+            RETURN
+        .export main
+            CONST_1
+            CALL_C helper
+            CALL_C helper
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+
+    let coverage = Rc::new(RefCell::new(Coverage::new()));
+    let recorder = Rc::clone(&coverage);
+    engine.set_trace_hook(move |event: &TraceEvent| recorder.borrow_mut().record(event));
+    engine.run(0, 1).unwrap();
+    let coverage = coverage.borrow();
+    assert_eq!(coverage.count(0, 2), 2);
+    assert_eq!(coverage.count(0, 1), 0);
+    assert_eq!(coverage.count(1, 0), 0);
+    assert_eq!(
+        coverage.report(0, &engine.modules[0]),
+        ModuleCoverage {
+            module: String::from("main"),
+            instructions: 7,
+            counts: vec![(0, 2), (2, 2), (3, 2), (4, 1), (5, 1), (7, 1), (9, 1)],
+            uncovered: vec![],
+        }
+    );
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use tstack::errors::{BytecodeError, Fault, ModuleError};
+use tstack::module::{LocalSymbol, Module, Relocation, RelocationKind};
+
+fn module(name: &str, data: &[u64], writable_data: &[u64], bytecode: &[u16]) -> Module {
+    Module {
+        name: String::from(name),
+        version: Default::default(),
+        strings: vec![String::from("main")].into(),
+        data: data.to_vec(),
+        writable_data: writable_data.to_vec(),
+        local_symbols: vec![LocalSymbol {
+            name_id: 0,
+            code_offset: 0,
+            exported: true,
+            signature: None,
+        }],
+        external_symbols: vec![],
+        globals: vec![],
+        external_globals: vec![],
+        bytecode: bytecode.to_vec().into(),
+        relocations: vec![],
+        symbol_lookup: HashMap::new(),
+        debug: None,
+    }
+}
+
+/// Bytecode which increments the first writable data value
+fn increment() -> Vec<u16> {
+    vec![
+        tstack::inst_stack!(DATA_RW_GET_C),
+        0,
+        tstack::inst_math!(ADD_C),
+        1,
+        tstack::inst_stack!(DATA_RW_SET_C),
+        0,
+    ]
+}
+
+#[test]
+fn test_data_get() {
+    let code = [
+        tstack::inst_stack!(CONST_1),
+        tstack::inst_stack!(DATA_GET),
+        tstack::inst_stack!(DATA_GET_C),
+        0,
+    ];
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module("main", &[10, 20], &[], &code))).unwrap();
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![20, 10]);
+}
+
+#[test]
+fn test_writable_data_set() {
+    let code = [
+        tstack::inst_stack!(CONST_U16),
+        9,
+        tstack::inst_stack!(CONST_1),
+        tstack::inst_stack!(DATA_RW_SET),
+        tstack::inst_stack!(CONST_1),
+        tstack::inst_stack!(DATA_RW_GET),
+    ];
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module("main", &[], &[0, 0], &code))).unwrap();
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![9]);
+    assert_eq!(engine.data[0], vec![0, 9]);
+}
+
+#[test]
+fn test_writable_data_per_engine() {
+    let shared = Rc::new(module("main", &[], &[5], &increment()));
+    let mut first = tstack::Engine::new();
+    let mut second = tstack::Engine::new();
+    first.add_module(Rc::clone(&shared)).unwrap();
+    second.add_module(Rc::clone(&shared)).unwrap();
+
+    first.run(0, 0).unwrap();
+    first.run(0, 0).unwrap();
+    second.run(0, 0).unwrap();
+    assert_eq!(first.data[0], vec![7]);
+    assert_eq!(second.data[0], vec![6]);
+    assert_eq!(shared.writable_data, vec![5]);
+}
+
+#[test]
+fn test_writable_data_copy_on_write() {
+    let shared = Rc::new(module("main", &[], &[5, 8], &increment()));
+    let mut first = tstack::Engine::new();
+    let mut second = tstack::Engine::new();
+    first.add_module(Rc::clone(&shared)).unwrap();
+    second.add_module(Rc::clone(&shared)).unwrap();
+    assert!(first.data[0].is_shared() && first.globals[0].is_shared());
+    assert_eq!(first.data[0], vec![5, 8]);
+
+    first.run(0, 0).unwrap();
+    assert!(!first.data[0].is_shared());
+    assert!(first.globals[0].is_shared());
+    assert!(second.data[0].is_shared());
+    assert_eq!(first.data[0], vec![6, 8]);
+    assert_eq!(second.data[0], vec![5, 8]);
+    assert_eq!(second.data[0].get(2), None);
+}
+
+#[test]
+fn test_replace_module_resets_data() {
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module("main", &[], &[5], &increment()))).unwrap();
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.data[0], vec![6]);
+    engine.replace_module("main", Rc::new(module("main", &[], &[1], &increment()))).unwrap();
+    assert_eq!(engine.data[0], vec![1]);
+}
+
+#[test]
+fn test_data_out_of_range() {
+    let code = [tstack::inst_stack!(CONST_4), tstack::inst_stack!(DATA_GET)];
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module("main", &[1], &[], &code))).unwrap();
+    assert!(matches!(
+        engine.run(0, 0).map_err(Fault::into_error),
+        Err(BytecodeError::InvalidData(4))
+    ));
+
+    let code = [tstack::inst_stack!(CONST_1), tstack::inst_stack!(DATA_RW_GET)];
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module("main", &[1], &[2], &code))).unwrap();
+    assert!(matches!(
+        engine.run(0, 0).map_err(Fault::into_error),
+        Err(BytecodeError::InvalidData(1))
+    ));
+}
+
+#[test]
+fn test_verify_data_access() {
+    let code = [tstack::inst_sys!(NOP), tstack::inst_stack!(DATA_GET_C), 1];
+    let mut engine = tstack::Engine::new();
+    let r = engine.add_module(Rc::new(module("main", &[1], &[1], &code)));
+    assert!(matches!(r, Err(ModuleError::InvalidDataAccess(1))));
+
+    // The writable segment is checked separately from the read-only segment
+    let code = [tstack::inst_stack!(DATA_RW_SET_C), 0];
+    let r = engine.add_module(Rc::new(module("main", &[1], &[], &code)));
+    assert!(matches!(r, Err(ModuleError::InvalidDataAccess(0))));
+    assert!(engine.modules.is_empty());
+}
+
+#[test]
+fn test_merge_writable_data() {
+    let mut lib = module("lib", &[3], &[4, 5], &[tstack::inst_stack!(DATA_RW_GET_C), 1]);
+    lib.strings.set(0, "get");
+    lib.relocations.push(Relocation { offset: 1, words: 1, kind: RelocationKind::WritableData });
+    let merged = tstack::linker::merge(&[module("main", &[1], &[2], &increment()), lib]).unwrap();
+    assert_eq!(merged.data, vec![1, 3]);
+    assert_eq!(merged.writable_data, vec![2, 4, 5]);
+    assert_eq!(merged.bytecode[7], 2);
+}
@@ -0,0 +1,52 @@
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::debugger::{DebugSession, Location, Stop};
+
+#[test]
+fn test_debug_session() {
+    let mut engine = tstack::Engine::new();
+    let module = asm::assemble(
+        "main",
+        ".func helper
+            CONST_2
+            ADD
+            RETURN
+        .export main
+            CONST_1
+            CALL_C helper
+            RETURN
+        .export fault
+            ADD
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+
+    let mut session = DebugSession::new(&mut engine, 0, 1).unwrap();
+    assert_eq!(session.location(), Some(Location { module_id: 0, offset: 3 }));
+    assert_eq!(session.step().unwrap(), Stop::Step);
+    assert_eq!(session.engine().stack, vec![1]);
+
+    let helper = Location { module_id: 0, offset: 0 };
+    assert!(session.add_breakpoint(helper));
+    assert!(!session.add_breakpoint(helper));
+    assert_eq!(session.resume().unwrap(), Stop::Breakpoint);
+    assert_eq!(session.location(), Some(helper));
+    assert!(session.remove_breakpoint(helper));
+    assert_eq!(session.breakpoints().count(), 0);
+
+    assert_eq!(session.resume().unwrap(), Stop::Finished);
+    assert!(session.is_finished());
+    assert_eq!(session.location(), None);
+    assert_eq!(session.step().unwrap(), Stop::Finished);
+    assert_eq!(engine.stack, vec![3]);
+
+    let mut session = DebugSession::new(&mut engine, 0, 2).unwrap();
+    assert!(session.step().is_err());
+    assert!(session.is_finished());
+    assert_eq!(engine.metrics().faults, 1);
+}
@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+
+use tstack::debuginfo::{DebugInfo, LineEntry, LocalName};
+use tstack::module::Module;
+
+fn module_with_debug() -> Module {
+    Module {
+        name: String::from("main"),
+        version: Default::default(),
+        strings: vec![String::from("main.src"), String::from("count")].into(),
+        data: vec![],
+        writable_data: vec![],
+        local_symbols: vec![],
+        external_symbols: vec![],
+        globals: vec![],
+        external_globals: vec![],
+        bytecode: vec![].into(),
+        relocations: vec![],
+        symbol_lookup: HashMap::new(),
+        debug: Some(DebugInfo {
+            lines: vec![
+                LineEntry { offset: 2, file_id: 0, line: 1, column: 5 },
+                LineEntry { offset: 6, file_id: 0, line: 3, column: 0 },
+            ],
+            locals: vec![LocalName { symbol_id: 0, index: 1, name_id: 1 }],
+        }),
+    }
+}
+
+#[test]
+fn test_source_location() {
+    let module = module_with_debug();
+    assert_eq!(module.source_location(0), None);
+    assert_eq!(module.source_location(2).unwrap().to_string(), "main.src:1:5");
+    assert_eq!(module.source_location(5).unwrap().line, 1);
+    assert_eq!(module.source_location(6).unwrap().to_string(), "main.src:3");
+    assert_eq!(module.source_location(100).unwrap().line, 3);
+}
+
+#[test]
+fn test_source_location_without_debug() {
+    let mut module = module_with_debug();
+    module.debug = None;
+    assert_eq!(module.source_location(2), None);
+    assert_eq!(module.local_name(0, 1), None);
+}
+
+#[test]
+fn test_local_name() {
+    let module = module_with_debug();
+    assert_eq!(module.local_name(0, 1), Some("count"));
+    assert_eq!(module.local_name(0, 0), None);
+    assert_eq!(module.local_name(1, 1), None);
+}
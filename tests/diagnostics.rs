@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use tstack::asm;
+use tstack::diagnostics::{DiagnosticKind, Severity};
+use tstack::errors::ModuleError;
+use tstack::module::{Relocation, RelocationKind, Signature};
+use tstack::verify;
+
+#[test]
+fn test_diagnose() {
+    let mut module = asm::assemble(
+        "main",
+        ".export main
+            DATA_GET_C 4
+            GLOBAL_GET_C 1
+            RETURN
+        .export helper
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    module.bytecode.to_mut()[0] = tstack::inst_stack!(DATA_RW_GET_C);
+    module.bytecode.to_mut().push(0xffff);
+    module.local_symbols[1].name_id = 99;
+    module.relocations.push(Relocation { offset: 6, words: 2, kind: RelocationKind::Data });
+
+    let diagnostics = verify::diagnose(&module);
+    let found: Vec<_> =
+        diagnostics.iter().map(|d| (d.kind, d.severity, d.symbol, d.offset, d.string)).collect();
+    assert_eq!(
+        found,
+        vec![
+            (DiagnosticKind::InvalidDataAccess, Severity::Error, Some(0), Some(0), None),
+            (DiagnosticKind::InvalidGlobalAccess, Severity::Error, Some(0), Some(2), None),
+            (DiagnosticKind::BadOpcode, Severity::Warning, Some(1), Some(6), None),
+            (DiagnosticKind::InvalidString, Severity::Error, Some(1), None, Some(99)),
+            (DiagnosticKind::InvalidRelocation, Severity::Error, None, Some(6), None),
+        ]
+    );
+    assert_eq!(diagnostics.errors().count(), 4);
+    assert_eq!(diagnostics.warnings().count(), 1);
+
+    let rendered: Vec<_> = diagnostics.iter().map(|d| d.render(&module)).collect();
+    assert_eq!(
+        rendered[0],
+        "main:0: error: DATA_RW_GET_C 4 is past the end of the writable data (0)"
+    );
+    assert_eq!(
+        rendered[3],
+        "main:symbol 1: error: the name of symbol 1 is string 99, which does not exist"
+    );
+
+    // Verifying fails with the first error
+    assert!(matches!(verify::verify(&module), Err(ModuleError::InvalidDataAccess(0))));
+
+    // The message of a fault must be a string of the module
+    let mut module =
+        asm::assemble("main", ".export main\n FAULT \"failed\"", Path::new(".")).unwrap();
+    assert!(verify::verify(&module).is_ok());
+    module.bytecode.to_mut()[1] = 99;
+    assert!(matches!(verify::verify(&module), Err(ModuleError::InvalidString(99))));
+}
+
+#[test]
+fn test_stack_effects() {
+    let source = ".export main
+            CONST_1
+            CONST_2
+            CALL_C add
+            RETURN
+        .export add
+            ADD
+            RETURN";
+    let module = |main: (u16, u16), add: (u16, u16)| {
+        let mut module = asm::assemble("main", source, Path::new(".")).unwrap();
+        let signature = |(inputs, outputs)| Some(Signature { inputs, outputs });
+        module.local_symbols[0].signature = signature(main);
+        module.local_symbols[1].signature = signature(add);
+        module
+    };
+    assert!(verify::verify(&module((0, 1), (2, 1))).is_ok());
+
+    // Returning with more values than declared
+    let diagnostics = verify::diagnose(&module((0, 0), (2, 0)));
+    let diagnostic = diagnostics.errors().next().unwrap();
+    assert_eq!(diagnostic.kind, DiagnosticKind::InvalidStackEffect);
+    assert_eq!((diagnostic.symbol, diagnostic.offset), (Some(1), Some(6)));
+    assert_eq!(diagnostic.message, "symbol 1 returns with 1 values, but declares it leaves 0");
+    assert!(matches!(
+        verify::verify(&module((0, 0), (2, 0))),
+        Err(ModuleError::InvalidStackEffect(1))
+    ));
+
+    // Popping values the symbol does not take
+    let diagnostics = verify::diagnose(&module((0, 2), (1, 1)));
+    let diagnostic = diagnostics.errors().next().unwrap();
+    assert_eq!((diagnostic.symbol, diagnostic.offset), (Some(1), Some(5)));
+    assert_eq!(
+        diagnostic.message,
+        "symbol 1 ADD pops 2 values, but only 1 are left of the 1 it takes"
+    );
+
+    // Calls follow the signature of the symbol called
+    let diagnostics = verify::diagnose(&module((0, 2), (2, 1)));
+    let diagnostic = diagnostics.errors().next().unwrap();
+    assert_eq!((diagnostic.symbol, diagnostic.offset), (Some(0), Some(4)));
+
+    // Calls of symbols without a signature are left to be checked as they run
+    let mut unsigned = module((0, 2), (2, 1));
+    unsigned.local_symbols[1].signature = None;
+    assert!(verify::verify(&unsigned).is_ok());
+}
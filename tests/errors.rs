@@ -0,0 +1,80 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::errors::{BytecodeError, ErrorCategory, ModuleError};
+use tstack::tags::Tag;
+
+#[test]
+fn test_bytecode_error_codes() {
+    let module = asm::assemble("main", ".export main\n ADD\n RETURN", Path::new(".")).unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    let fault = engine.run(0, 0).unwrap_err();
+    assert_eq!(fault.code(), fault.error().code());
+    assert_eq!(fault.code().number(), 101);
+    assert_eq!(fault.code().to_string(), "E0101");
+    assert_eq!(fault.code().category(), ErrorCategory::Stack);
+
+    let errors = [
+        (BytecodeError::InvalidRotation(0), "E0106", ErrorCategory::Stack),
+        (BytecodeError::tag_mismatch(0, Tag::Int, Tag::F64), "E0108", ErrorCategory::Stack),
+        (BytecodeError::BadOpcode(0xffff), "E0201", ErrorCategory::Decode),
+        (BytecodeError::InvalidHandle(3), "E0303", ErrorCategory::Memory),
+        (BytecodeError::InvalidReserve(-1), "E0305", ErrorCategory::Memory),
+        (BytecodeError::InvalidModule(9), "E0401", ErrorCategory::Link),
+        (BytecodeError::OutOfFuel(0), "E0503", ErrorCategory::Host),
+        (BytecodeError::OutOfMemory(0), "E0505", ErrorCategory::Host),
+        (BytecodeError::DisallowedOpcode(0), "E0506", ErrorCategory::Host),
+        (BytecodeError::DivideByZero(0), "E0601", ErrorCategory::Arithmetic),
+        (BytecodeError::NotANumber(0), "E0603", ErrorCategory::Arithmetic),
+        (BytecodeError::unused_values(0, 1), "E0107", ErrorCategory::Stack),
+    ];
+    for (error, code, category) in errors {
+        assert_eq!(error.code().to_string(), code);
+        assert_eq!(error.code().category(), category);
+    }
+}
+
+#[test]
+fn test_module_error_codes() {
+    let module =
+        asm::assemble("main", ".export main\n CALL_EXT_C lib.missing\n RETURN", Path::new("."))
+            .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    let error = engine.link().unwrap_err();
+    assert_eq!(error.code().to_string(), "E0418");
+    assert_eq!(error.code().category(), ErrorCategory::Link);
+
+    let name = || String::from("main");
+    let errors = [
+        ModuleError::DependencyCycle(vec![name()]),
+        ModuleError::DuplicateSymbol(name()),
+        ModuleError::GlobalTypeMismatch(name(), name()),
+        ModuleError::InvalidDataAccess(0),
+        ModuleError::InvalidGlobalAccess(0),
+        ModuleError::InvalidJson(name()),
+        ModuleError::InvalidName(name()),
+        ModuleError::InvalidRelocation(0),
+        ModuleError::InvalidStackEffect(0),
+        ModuleError::InvalidString(0),
+        ModuleError::InvalidSymbol(0),
+        ModuleError::NameCollision(name()),
+        ModuleError::NoModules,
+        ModuleError::PrivateGlobal(name(), name()),
+        ModuleError::PrivateSymbol(name(), name()),
+        ModuleError::RelocationOverflow(0),
+        ModuleError::SignatureMismatch(name(), name()),
+        ModuleError::UnknownModule(name()),
+        ModuleError::UnresolvedGlobal(name(), name()),
+        ModuleError::UnresolvedImports(Vec::new()),
+        ModuleError::UnresolvedSymbol(name(), name()),
+        ModuleError::VerificationFailed(name(), name()),
+        ModuleError::VersionMismatch(name(), name(), name()),
+    ];
+    let codes: HashSet<_> = errors.iter().map(ModuleError::code).collect();
+    assert_eq!(codes.len(), errors.len());
+    assert_eq!(ModuleError::InvalidJson(name()).code().category(), ErrorCategory::Decode);
+}
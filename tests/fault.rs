@@ -0,0 +1,290 @@
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::debuginfo::{DebugInfo, LineEntry};
+use tstack::errors::{BytecodeError, FaultLocation, FaultSnapshot};
+use tstack::module::Signature;
+
+fn engine() -> tstack::Engine {
+    let mut engine = tstack::Engine::new();
+    engine
+        .register_fn("host", "fail", || -> Result<u64, String> { Err(String::from("failed")) })
+        .unwrap();
+    let lib = asm::assemble(
+        "lib",
+        ".export add
+            CONST_1
+            ADD
+            ADD
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    let main = asm::assemble(
+        "main",
+        ".export main
+            CONST_1
+            CALL_EXT_C lib.add
+            RETURN
+        .export native
+            CONST_1
+            CALL_EXT_C host.fail
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    engine.add_module(Rc::new(lib)).unwrap();
+    engine.add_module(Rc::new(main)).unwrap();
+    engine.link().unwrap();
+    engine
+}
+
+fn location(module: &str, symbol: &str, offset: usize) -> FaultLocation {
+    FaultLocation {
+        module: String::from(module),
+        symbol: Some(String::from(symbol)),
+        offset,
+        source: None,
+    }
+}
+
+#[test]
+fn test_fault_location() {
+    let mut engine = engine();
+    let fault = engine.run(2, 0).unwrap_err();
+    assert!(fault.error().is_stack_underflow());
+    assert_eq!(fault.location(), Some(&location("lib", "add", 2)));
+    assert!(fault.to_string().ends_with(" in lib:add at offset 2"), "{}", fault);
+
+    let fault = engine.run(2, 1).unwrap_err();
+    assert!(matches!(fault.error(), BytecodeError::NativeError(m) if m == "failed"));
+    assert_eq!(fault.location(), Some(&location("main", "native", 5)));
+
+    let fault = engine.run(0, 0).unwrap_err();
+    assert_eq!(fault.location(), Some(&location("host", "fail", 0)));
+
+    let fault = engine.run(9, 0).unwrap_err();
+    assert!(matches!(fault.error(), BytecodeError::InvalidModule(9)));
+    assert_eq!(fault.location(), None);
+    assert_eq!(fault.to_string(), fault.error().to_string());
+}
+
+#[test]
+fn test_backtrace() {
+    let mut engine = engine();
+    let fault = engine.run(2, 0).unwrap_err();
+    assert_eq!(fault.backtrace(), &[location("lib", "add", 2), location("main", "main", 1)]);
+    assert_eq!(
+        fault.format_backtrace(),
+        "   0: lib:add at offset 2\n   1: main:main at offset 1\n"
+    );
+
+    // Faults in calls made by a native function are located at its call
+    engine
+        .register_native("host", "call", |stack| stack.call("lib", "add", &[1]).map(|_| ()))
+        .unwrap();
+    let module = asm::assemble(
+        "callback",
+        ".export main
+            CALL_EXT_C host.call
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    let module_id = engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+    let fault = engine.run(module_id, 0).unwrap_err();
+    assert!(fault.error().is_stack_underflow());
+    assert_eq!(fault.backtrace(), &[location("callback", "main", 0)]);
+}
+
+#[test]
+fn test_report() {
+    let mut engine = engine();
+    let fault = engine.run(2, 0).unwrap_err();
+    assert_eq!(
+        fault.report(&engine),
+        "error[E0101]: too few operands for 0x0300; 2 values required
+  in lib:add at offset 2
+  called from main:main at offset 1
+
+code:
+     0000  CONST_1
+     0001  ADD
+  => 0002  ADD
+     0003  RETURN
+
+stack: empty
+"
+    );
+
+    engine.stack = (0..10).collect();
+    let report = fault.report(&engine);
+    assert!(report.contains("\nstack (top 8 of 10):\n  0x0000000000000009  9\n"), "{}", report);
+    assert!(report.ends_with("  0x0000000000000002  2\n"), "{}", report);
+}
+
+#[test]
+fn test_snapshot() {
+    let mut engine = engine();
+    assert!(engine.run(2, 1).unwrap_err().snapshot().is_none());
+
+    engine.snapshot_faults = true;
+    engine.stack.clear();
+    let fault = engine.run(2, 1).unwrap_err();
+    let snapshot = FaultSnapshot { module_id: 2, offset: 5, stack: vec![1], locals: vec![] };
+    assert_eq!(fault.snapshot(), Some(&snapshot));
+
+    // The snapshot is kept when the engine is reset and run again
+    engine.reset();
+    engine.run(2, 0).unwrap_err();
+    assert_eq!(fault.snapshot(), Some(&snapshot));
+    assert!(fault.report(&engine).ends_with("\nstack (1):\n  0x0000000000000001  1\n"));
+
+    // Faults raised before any instruction ran are at the entry point
+    let fault = engine.run(0, 0).unwrap_err();
+    assert_eq!(fault.snapshot().map(|s| (s.module_id, s.offset)), Some((0, 0)));
+}
+
+#[test]
+fn test_snapshot_locals() {
+    let module = asm::assemble(
+        "main",
+        ".export main
+            RESERVE_C 2
+            CONST_U16 7
+            SET_64_C 1
+            CALL_C helper
+            RETURN
+        .func helper
+            RESERVE_C 1
+            CONST_U16 3
+            SET_64_C 0
+            ADD
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.snapshot_faults = true;
+
+    // The locals are those of the innermost call, as they were at the fault
+    let fault = engine.run(0, 0).unwrap_err();
+    assert_eq!(fault.snapshot().map(|s| s.locals.as_slice()), Some(&[3][..]));
+    assert!(engine.locals().is_empty());
+    let report = fault.report(&engine);
+    assert!(report.ends_with("\nlocals (1):\n     0: 0x0000000000000003  3\n"), "{}", report);
+}
+
+#[test]
+fn test_user_fault() {
+    let module = asm::assemble(
+        "main",
+        ".export main
+            CONST_U16 42
+            CONST_3
+            CONST_4
+            CONST_2
+            FAULT \"expected 3, found 4\"
+            RETURN
+        .export underflow
+            CONST_1
+            CONST_2
+            FAULT \"expected 3, found 4\"
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    assert_eq!(module.strings, vec!["main", "underflow", "expected 3, found 4"]);
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+
+    let fault = engine.run(0, 0).unwrap_err();
+    assert!(matches!(
+        fault.error(),
+        BytecodeError::UserFault { code: 42, message, values }
+            if message == "expected 3, found 4" && *values == vec![3, 4]
+    ));
+    assert_eq!(fault.error().to_string(), "fault 42: expected 3, found 4 (3, 4)");
+    assert_eq!(fault.code().to_string(), "E0701");
+    assert_eq!(fault.location().map(|l| l.offset), Some(5));
+    assert!(engine.stack.is_empty());
+
+    // The code is missing below the values
+    let fault = engine.run(0, 1).unwrap_err();
+    assert!(matches!(fault.error(), BytecodeError::StackUnderflow(_)));
+}
+
+#[test]
+fn test_symbol_names() {
+    let mut lib = asm::assemble("lib", ".export add\n ADD\n RETURN", Path::new(".")).unwrap();
+    lib.local_symbols[0].signature = Some(Signature { inputs: 2, outputs: 1 });
+    let main = asm::assemble("main", ".export main\n CALL_EXT_C lib.add\n RETURN", Path::new("."))
+        .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(lib)).unwrap();
+    engine.add_module(Rc::new(main)).unwrap();
+    engine.link().unwrap();
+
+    let fault = engine.run(1, 0).unwrap_err();
+    assert_eq!(fault.symbol(), Some(("lib", "add")));
+    assert_eq!(fault.error().to_string(), "symbol ID 0 requires 2 inputs; 0 values available");
+    assert_eq!(fault.message(), "symbol add of module lib requires 2 inputs; 0 values available");
+    assert_eq!(fault.to_string(), format!("{} in main:main at offset 0", fault.message()));
+    assert!(fault.report(&engine).contains("=> 0000  CALL_EXT_C 0  ; lib.add\n"));
+
+    // Entry points are named too
+    engine.stack.clear();
+    let fault = engine.run(0, 0).unwrap_err();
+    assert_eq!(fault.message(), "symbol add of module lib requires 2 inputs; 0 values available");
+}
+
+#[test]
+fn test_source_locations() {
+    let mut lib =
+        asm::assemble("lib", ".export add\n CONST_1\n ADD\n RETURN", Path::new(".")).unwrap();
+    lib.strings.push(String::from("lib.src"));
+    lib.debug = Some(DebugInfo {
+        lines: vec![LineEntry { offset: 1, file_id: 1, line: 4, column: 9 }],
+        locals: vec![],
+    });
+    let main = asm::assemble("main", ".export main\n CALL_EXT_C lib.add\n RETURN", Path::new("."))
+        .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(lib)).unwrap();
+    engine.add_module(Rc::new(main)).unwrap();
+    engine.link().unwrap();
+
+    let fault = engine.run(1, 0).unwrap_err();
+    assert_eq!(fault.location().unwrap().source.as_deref(), Some("lib.src:4:9"));
+    assert_eq!(fault.location().unwrap().offset, 1);
+    // Locations without a line entry keep their offset
+    assert_eq!(
+        fault.format_backtrace(),
+        "   0: lib:add at lib.src:4:9\n   1: main:main at offset 0\n"
+    );
+    assert!(fault.report(&engine).contains("\n  in lib:add at lib.src:4:9\n"));
+}
+
+#[test]
+fn test_fault_isolation() {
+    let mut engine = engine();
+    // The fault is raised within lib.add, called by main.main
+    assert!(engine.run(2, 0).is_err());
+    assert!(engine.stack.is_empty());
+
+    // Other symbols may be run once the faulting run is unwound
+    engine.stack.extend([7, 5]);
+    engine.run(1, 0).unwrap();
+    assert_eq!(engine.stack, vec![13]);
+
+    // Values below the inputs of the run are kept
+
+    engine.stack.push(1);
+    assert!(engine.run(2, 1).is_err());
+    assert_eq!(engine.stack, vec![13, 1]);
+    engine.run(1, 0).unwrap();
+    assert_eq!(engine.stack, vec![15]);
+}
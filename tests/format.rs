@@ -0,0 +1,349 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use tstack::debuginfo::{DebugInfo, LineEntry, LocalName};
+use tstack::errors::FormatError;
+use tstack::format::{self, Compression, Strings};
+use tstack::module::{
+    ExternalSymbol, Global, GlobalType, LocalSymbol, Module, Relocation, RelocationKind, Signature,
+};
+use tstack::version::{Version, VersionReq};
+
+fn module() -> Module {
+    Module {
+        name: String::from("main"),
+        version: Version::new(2, 1, 0),
+        strings: vec![String::from("main"), String::from("lib"), String::from("f")].into(),
+        data: vec![1, u64::MAX],
+        writable_data: vec![0, 5],
+        local_symbols: vec![LocalSymbol {
+            name_id: 0,
+            code_offset: 0,
+            exported: true,
+            signature: Some(Signature { inputs: 1, outputs: 2 }),
+        }],
+        external_symbols: vec![ExternalSymbol {
+            module_name_id: 1,
+            module_id: 0,
+            symbol_name_id: 2,
+            symbol_id: 0,
+            signature: None,
+            version_req: VersionReq::parse("~2.1"),
+            alias: None,
+        }],
+        globals: vec![],
+        external_globals: vec![],
+        bytecode: vec![tstack::inst_stack!(CONST_U16), 1, tstack::inst_sys!(HALT)].into(),
+        relocations: vec![Relocation { offset: 1, words: 1, kind: RelocationKind::Data }],
+        symbol_lookup: HashMap::from([(String::from("main"), 0), (String::from("alias"), 0)]),
+        debug: Some(DebugInfo {
+            lines: vec![LineEntry { offset: 0, file_id: 0, line: 3, column: 1 }],
+            locals: vec![LocalName { symbol_id: 0, index: 0, name_id: 2 }],
+        }),
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    let m = module();
+    let bytes = m.to_bytes();
+    assert_eq!(&bytes[0..4], b"TSTK");
+    assert_eq!(Module::from_bytes(&bytes).unwrap(), m);
+    assert_eq!(module().to_bytes(), bytes);
+
+    let mut m = module();
+    m.external_symbols[0].alias = Some(0);
+    assert_eq!(Module::from_bytes(&m.to_bytes()).unwrap(), m);
+}
+
+#[test]
+fn test_bad_magic() {
+    assert!(matches!(Module::from_bytes(b"NOPE\x01\x00\x00\x00"), Err(FormatError::BadMagic)));
+    assert!(matches!(Module::from_bytes(b"TS"), Err(FormatError::BadMagic)));
+}
+
+#[test]
+fn test_unsupported_version() {
+    let mut bytes = module().to_bytes();
+    bytes[4] = 9;
+    assert!(matches!(Module::from_bytes(&bytes), Err(FormatError::UnsupportedVersion(9))));
+}
+
+#[test]
+fn test_header() {
+    let m = module();
+    let header = format::read_header(&m.to_bytes()).unwrap();
+    assert_eq!(header.version, format::FORMAT_VERSION);
+    assert_eq!(header.compression, Compression::None);
+    assert_eq!(header.abi_version, tstack::Engine::ABI_VERSION);
+    assert_eq!(header.hash, Some(m.content_hash()));
+}
+
+#[test]
+fn test_unsupported_abi() {
+    let mut bytes = module().to_bytes();
+    bytes[8] = 0xFF;
+    let header = format::read_header(&bytes).unwrap();
+    assert!(header.abi_version > tstack::Engine::ABI_VERSION);
+    assert!(matches!(
+        Module::from_bytes(&bytes),
+        Err(FormatError::UnsupportedAbi(v)) if v == header.abi_version
+    ));
+    bytes[8] = 0;
+    assert!(matches!(Module::from_bytes(&bytes), Err(FormatError::UnsupportedAbi(0))));
+}
+
+/// Find the first byte after the header where the encodings of two modules
+/// differ
+fn first_difference(a: &Module, b: &Module) -> usize {
+    let (a, b) = (a.to_bytes(), b.to_bytes());
+    (42..a.len()).find(|i| a[*i] != b[*i]).unwrap()
+}
+
+/// Convert a module encoded in the current format to format version 1
+fn to_version_1(m: &Module) -> Vec<u8> {
+    let mut bytes = m.to_bytes();
+    // Version 1 has no globals sections, and no alias flag on the external
+    // symbol; these are found by comparing against modules which have them
+    assert!(m.globals.is_empty() && m.external_globals.is_empty());
+    assert!(m.external_symbols.len() == 1 && m.external_symbols[0].alias.is_none());
+    let mut with_global = m.clone();
+    with_global.globals.push(Global {
+        name_id: 0,
+        ty: GlobalType::Int,
+        exported: false,
+        initial: 0,
+    });
+    let globals = first_difference(m, &with_global);
+    bytes.drain(globals..globals + 8);
+    let mut with_alias = m.clone();
+    with_alias.external_symbols[0].alias = Some(0);
+    bytes.remove(first_difference(m, &with_alias));
+    // There is also no ABI field, and no writable data section, which follows
+    // the name, version, strings, and read-only data
+    let strings: usize = m.strings.iter().map(|s| 4 + s.unwrap().len()).sum();
+    let writable = 42 + 4 + m.name.len() + 12 + 4 + strings + 4 + 8 * m.data.len();
+    bytes.drain(writable..writable + 4 + 8 * m.writable_data.len());
+    bytes.drain(8..10);
+    bytes[4] = 1;
+    let hash = tstack::hash::sha256(&bytes[40..]);
+    bytes[8..40].copy_from_slice(&hash);
+    bytes
+}
+
+#[test]
+fn test_version_1() {
+    let mut m = module();
+    let mut bytes = to_version_1(&m);
+    assert_eq!(format::read_header(&bytes).unwrap().abi_version, 1);
+    m.writable_data.clear();
+    assert_eq!(Module::from_bytes(&bytes).unwrap(), m);
+
+    bytes[4] = 0;
+    assert!(matches!(Module::from_bytes(&bytes), Err(FormatError::UnsupportedVersion(0))));
+}
+
+#[test]
+fn test_truncated() {
+    let bytes = module().to_bytes();
+    for len in 8..bytes.len() {
+        assert!(Module::from_bytes(&bytes[..len]).is_err(), "length {}", len);
+    }
+}
+
+#[test]
+fn test_trailing_data() {
+    let mut bytes = module().to_bytes();
+    bytes.push(0);
+    assert!(matches!(Module::from_bytes(&bytes), Err(FormatError::InvalidValue(_))));
+}
+
+#[test]
+fn test_unknown_compression() {
+    let mut bytes = module().to_bytes();
+    bytes[6] = 3;
+    assert!(matches!(Module::from_bytes(&bytes), Err(FormatError::UnsupportedCompression(3))));
+}
+
+#[test]
+fn test_content_hash() {
+    let m = module();
+    let bytes = m.to_bytes();
+    assert_eq!(format::stored_hash(&bytes), Some(m.content_hash()));
+
+    let mut other = module();
+    other.bytecode.to_mut()[1] = 2;
+    assert_ne!(other.content_hash(), m.content_hash());
+}
+
+#[test]
+fn test_hash_mismatch() {
+    let mut bytes = module().to_bytes();
+    let last = bytes.len() - 1;
+    bytes[last - 40] ^= 1;
+    assert!(matches!(Module::from_bytes(&bytes), Err(FormatError::HashMismatch)));
+
+    let mut bytes = module().to_bytes();
+    bytes[10] ^= 1;
+    assert!(matches!(Module::from_bytes(&bytes), Err(FormatError::HashMismatch)));
+}
+
+#[test]
+fn test_unhashed() {
+    let m = module();
+    let mut bytes = m.to_bytes();
+    bytes[6] &= !(format::FLAG_HASH as u8);
+    bytes.drain(10..42);
+    assert_eq!(format::stored_hash(&bytes), None);
+    assert_eq!(Module::from_bytes(&bytes).unwrap(), m);
+}
+
+#[test]
+fn test_view_borrows() {
+    let m = module();
+    let bytes = m.to_bytes();
+    let view = format::view(&bytes).unwrap();
+    assert!(view.strings.is_borrowed());
+    assert_eq!(view.strings.len(), 3);
+    assert_eq!(view.string(2), Some("f"));
+    assert_eq!(view.string(3), None);
+    assert_eq!(view.bytecode.len(), 3);
+    assert_eq!(view.bytecode.get(1), Some(1));
+    assert_eq!(view.bytecode.get(3), None);
+    assert_eq!(view.bytecode.iter().collect::<Vec<_>>(), m.bytecode.to_vec());
+    assert_eq!(view.to_module(), m);
+}
+
+#[test]
+fn test_read_static() {
+    // The bytecode starts at an even or odd offset depending on the length of
+    // the name, so one of these modules can use it in place
+    let mut borrowed = 0;
+    for name in ["main", "mains"] {
+        let mut m = module();
+        m.name = String::from(name);
+        let bytes: &'static [u8] = Box::leak(m.to_bytes().into_boxed_slice());
+        let read = Module::from_static_bytes(bytes).unwrap();
+        assert_eq!(read, m);
+        if let Cow::Borrowed(words) = read.bytecode {
+            let range = bytes.as_ptr_range();
+            assert!(range.contains(&(words.as_ptr() as *const u8)));
+            borrowed += 1;
+        }
+    }
+    assert_eq!(borrowed, cfg!(target_endian = "little") as usize);
+}
+
+#[test]
+fn test_view_decodes_strings_on_use() {
+    let mut bytes = module().to_bytes();
+    bytes[6] &= !(format::FLAG_HASH as u8);
+    bytes.drain(10..42);
+    let at = bytes.windows(5).position(|w| w == b"\x01\0\0\0f").unwrap();
+    bytes[at + 4] = 0xff;
+
+    let view = format::view(&bytes).unwrap();
+    assert_eq!(view.string(0), Some("main"));
+    assert_eq!(view.string(2), None);
+    assert!(
+        matches!(view.strings.decode(2), Some(Err(FormatError::InvalidUtf8(offset))) if offset == at)
+    );
+    assert!(view.strings.decode(3).is_none());
+
+    // Modules keep the table undecoded, and write the string back as it was
+    let m = format::read(&bytes).unwrap();
+    assert_eq!(m, view.to_module());
+    assert_eq!(m.strings.get(0), Some("main"));
+    assert!(
+        matches!(m.strings.decode(2), Some(Err(FormatError::InvalidUtf8(offset))) if offset == at)
+    );
+    assert_eq!(Module::from_bytes(&m.to_bytes()).unwrap(), m);
+}
+
+#[test]
+fn test_strings() {
+    let bytes = module().to_bytes();
+    let mut strings = format::view(&bytes).unwrap().strings.into_owned();
+    assert!(!strings.is_borrowed());
+    assert!(strings.contains("lib"));
+    strings.set(1, "other");
+    strings.push("g");
+    assert_eq!(strings, vec!["main", "other", "f", "g"]);
+    assert!(!strings.contains("lib"));
+    assert_eq!(&strings[3], "g");
+
+    let mut table = Strings::from(vec!["x"]);
+    table.append(&strings);
+    assert_eq!(table, vec!["x", "main", "other", "f", "g"]);
+    assert_eq!(format!("{:?}", table), r#"["x", "main", "other", "f", "g"]"#);
+}
+
+#[test]
+fn test_view_errors() {
+    let mut bytes = module().to_bytes();
+    bytes[10] ^= 1;
+    assert!(matches!(format::view(&bytes), Err(FormatError::HashMismatch)));
+    assert!(matches!(format::view(&bytes[..20]), Err(FormatError::Truncated(_))));
+}
+
+#[cfg(feature = "deflate")]
+#[test]
+fn test_deflate() {
+    let mut m = module();
+    m.bytecode = vec![tstack::inst_sys!(NOP); 4096].into();
+    let bytes = format::write(&m, Compression::Deflate).unwrap();
+    assert!(bytes.len() < m.to_bytes().len() / 4);
+    assert_eq!(format::stored_hash(&bytes), format::stored_hash(&m.to_bytes()));
+    assert_eq!(format::view(&bytes).unwrap().to_module(), m);
+    assert_eq!(format::read(&bytes).unwrap(), m);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_zstd() {
+    let mut m = module();
+    m.bytecode = vec![tstack::inst_sys!(NOP); 4096].into();
+    let bytes = format::write(&m, Compression::Zstd).unwrap();
+    assert!(bytes.len() < m.to_bytes().len() / 4);
+    assert_eq!(format::read(&bytes).unwrap(), m);
+}
+
+/// Change the uncompressed length stored for the string table of a module
+#[cfg(any(feature = "deflate", feature = "zstd"))]
+fn claim_length(bytes: &[u8], len: u32) -> Vec<u8> {
+    // The string table follows the name and the three parts of the version
+    let offset = bytes.windows(4).position(|w| w == b"main").unwrap() + 4 + 12;
+    let mut bytes = bytes.to_vec();
+    bytes[offset..offset + 4].copy_from_slice(&len.to_le_bytes());
+    bytes
+}
+
+#[cfg(any(feature = "deflate", feature = "zstd"))]
+#[test]
+fn test_compressed_length_mismatch() {
+    let methods = [
+        #[cfg(feature = "deflate")]
+        Compression::Deflate,
+        #[cfg(feature = "zstd")]
+        Compression::Zstd,
+    ];
+    for compression in methods {
+        let bytes = format::write(&module(), compression).unwrap();
+        assert_eq!(format::read(&claim_length(&bytes, 24)).unwrap(), module());
+        for len in [23, 25, u32::MAX] {
+            assert!(matches!(
+                format::read(&claim_length(&bytes, len)),
+                Err(FormatError::Compression(_))
+            ));
+        }
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+#[test]
+fn test_disabled_compression() {
+    assert!(matches!(
+        format::write(&module(), Compression::Zstd),
+        Err(FormatError::UnsupportedCompression(2))
+    ));
+}
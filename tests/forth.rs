@@ -0,0 +1,172 @@
+mod common;
+
+use std::rc::Rc;
+
+use tstack::errors::{BytecodeError, CompileError};
+use tstack::forth;
+use tstack::module::{Relocation, RelocationKind};
+
+use common::listing;
+
+#[test]
+fn test_run_main() {
+    let module = forth::compile("main", "2 3 + ( five ) dup * \\ squared\n$10 -1 * .").unwrap();
+    assert_eq!(module.symbol(0).unwrap().name(), Some("main"));
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![25]);
+}
+
+#[test]
+fn test_definitions() {
+    let module = forth::compile(
+        "words",
+        ": square ( n -- n*n ) dup * ;
+         : cube dup square * ;
+         3 cube .",
+    )
+    .unwrap();
+    let names: Vec<_> = module.symbols().map(|s| s.name().unwrap()).collect();
+    assert_eq!(names, vec!["square", "cube", "main"]);
+    assert!(module.symbols().all(|s| s.exported() && s.signature().is_none()));
+    assert_eq!(listing(&module, 0), vec!["DUPE_1", "MUL", "RETURN"]);
+    assert_eq!(listing(&module, 1), vec!["DUPE_1", "CALL_C 0", "MUL", "RETURN"]);
+    assert_eq!(listing(&module, 2), vec!["CONST_3", "CALL_C 1", "PRINT_I64", "RETURN"]);
+
+    let cube = module.local_symbols[1].code_offset;
+    let main = module.local_symbols[2].code_offset;
+    assert_eq!(
+        module.relocations,
+        vec![
+            Relocation { offset: cube + 2, words: 1, kind: RelocationKind::LocalSymbol },
+            Relocation { offset: main + 2, words: 1, kind: RelocationKind::LocalSymbol },
+        ]
+    );
+}
+
+#[test]
+fn test_control_words() {
+    let module = forth::compile(
+        "m",
+        ": countdown begin dup . 1 - dup 0= until drop ;
+         : sign dup 0 < if drop -1 else 0 > if 1 else 0 then then ;
+         : fact dup 1 > if dup 1 - recurse * then ;
+         : forever begin again ;
+         : halve begin dup 1 > while 2 / repeat exit ;",
+    )
+    .unwrap();
+    assert_eq!(
+        listing(&module, 0),
+        vec![
+            "DUPE_1",
+            "PRINT_I64",
+            "CONST_1",
+            "SWAP_1",
+            "SUB",
+            "DUPE_1",
+            "JMP_Z.C16.REL +3",
+            "CONST_0",
+            "JMP.C16.REL +1",
+            "CONST_1",
+            "JMP_Z.C16.REL -14",
+            "POP_1",
+            "RETURN",
+        ]
+    );
+    assert_eq!(
+        listing(&module, 2)[6..],
+        ["JMP_Z.C16.REL +7", "DUPE_1", "CONST_1", "SWAP_1", "SUB", "CALL_C 2", "MUL", "RETURN"]
+    );
+    assert_eq!(listing(&module, 3), vec!["JMP.C16.REL -2", "RETURN"]);
+    assert_eq!(
+        listing(&module, 4)[6..],
+        ["JMP_Z.C16.REL +5", "CONST_2", "SWAP_1", "IDIV", "JMP.C16.REL -15", "RETURN", "RETURN"]
+    );
+}
+
+#[test]
+fn test_run_control_words() {
+    let module = forth::compile(
+        "m",
+        ": countdown begin dup . 1 - dup 0= until drop ;
+         : sign dup 0 < if drop -1 else 0 > if 1 else 0 then then ;
+         : fact dup 1 > if dup 1 - recurse * then ;
+         : down begin dup 0 > while 3 - repeat ;
+         : early 1 exit 2 ;",
+    )
+    .unwrap();
+    let mut engine = tstack::Engine::new();
+    let output = tstack::host::SharedBuffer::new();
+    engine.set_stdout(output.clone());
+    engine.add_module(Rc::new(module)).unwrap();
+    let mut run = |word: &str, input: i64| {
+        let symbol = engine.modules[0].find_symbol(word).unwrap();
+        engine.stack = vec![input as u64];
+        engine.run(0, symbol).unwrap();
+        engine.stack.iter().map(|v| *v as i64).collect::<Vec<_>>()
+    };
+
+    assert_eq!(run("countdown", 3), Vec::<i64>::new());
+    assert_eq!(run("sign", -5), vec![-1]);
+    assert_eq!(run("sign", 0), vec![0]);
+    assert_eq!(run("sign", 7), vec![1]);
+    assert_eq!(run("fact", 5), vec![120]);
+    assert_eq!(run("fact", 0), vec![0]);
+    assert_eq!(run("down", 10), vec![-2]);
+    assert_eq!(run("early", 0), vec![0, 1]);
+    assert_eq!(output.take_string(), "PRINT: 3\nPRINT: 2\nPRINT: 1\n");
+}
+
+#[test]
+fn test_run_division() {
+    let module = forth::compile(
+        "m",
+        ": halve 2 / ;
+         : parity 2 mod ;
+         : divmod 7 3 / . 7 3 mod . ;",
+    )
+    .unwrap();
+    let mut engine = tstack::Engine::new();
+    let output = tstack::host::SharedBuffer::new();
+    engine.set_stdout(output.clone());
+    engine.add_module(Rc::new(module)).unwrap();
+    let mut run = |word: &str, input: i64| {
+        let symbol = engine.modules[0].find_symbol(word).unwrap();
+        engine.stack = vec![input as u64];
+        engine.run(0, symbol).map(|_| engine.stack.iter().map(|v| *v as i64).collect::<Vec<_>>())
+    };
+
+    assert_eq!(run("halve", 7).unwrap(), vec![3]);
+    assert_eq!(run("halve", -7).unwrap(), vec![-3]);
+    assert_eq!(run("parity", 7).unwrap(), vec![1]);
+    assert_eq!(run("parity", -7).unwrap(), vec![-1]);
+    assert_eq!(run("divmod", 0).unwrap(), vec![0]);
+    assert_eq!(output.take_string(), "PRINT: 2\nPRINT: 1\n");
+
+    let module = forth::compile("z", ": zero 0 / ;").unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    let symbol = engine.modules[0].find_symbol("zero").unwrap();
+    engine.stack = vec![1];
+    let fault = engine.run(0, symbol).unwrap_err();
+    assert!(matches!(fault.error(), BytecodeError::DivideByZero(_)));
+}
+
+#[test]
+fn test_compile_errors() {
+    let error = |source: &str| forth::compile("m", source).unwrap_err();
+
+    assert!(matches!(error("1\n  foo"), CompileError::UndefinedName(2, w) if w == "foo"));
+    assert!(matches!(error(": a ;\n: a ;"), CompileError::DuplicateName(2, w) if w == "a"));
+    assert!(matches!(error(": dup ;"), CompileError::DuplicateName(1, w) if w == "dup"));
+    assert!(matches!(error(": main ; 1"), CompileError::DuplicateName(1, w) if w == "main"));
+    assert!(matches!(error(": a\n 1 2"), CompileError::InvalidSyntax(1, _)));
+    assert!(matches!(error(": a : b ; ;"), CompileError::InvalidSyntax(1, _)));
+    assert!(matches!(error("1 ;"), CompileError::InvalidSyntax(1, _)));
+    assert!(matches!(error(": a\n if ;"), CompileError::InvalidSyntax(2, _)));
+    assert!(matches!(error(": a then ;"), CompileError::InvalidSyntax(1, _)));
+    assert!(matches!(error(": a begin repeat ;"), CompileError::InvalidSyntax(1, _)));
+    assert!(matches!(error("recurse"), CompileError::InvalidSyntax(1, _)));
+    assert!(matches!(error("( unterminated"), CompileError::InvalidSyntax(1, _)));
+}
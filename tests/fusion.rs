@@ -0,0 +1,86 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::bytecode::op;
+use tstack::fusion::{self, Sequences};
+use tstack::optimize::{Match, Pattern, Peephole};
+use tstack::trace::TraceEvent;
+
+/// Count the sequences run by an assembled program
+fn sequences(source: &str) -> Sequences {
+    let module = tstack::asm::assemble("main", source, Path::new(".")).unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    let sequences = Rc::new(RefCell::new(Sequences::new()));
+    let recorder = Rc::clone(&sequences);
+    engine.set_trace_hook(move |event: &TraceEvent| recorder.borrow_mut().record(event));
+    engine.run(0, 0).unwrap();
+    let sequences = sequences.borrow().clone();
+    sequences
+}
+
+#[test]
+fn test_count_sequences() {
+    let sequences = sequences(
+        ".export main
+            CONST_1
+            CONST_2
+            ADD
+            CONST_2
+            ADD
+            RETURN",
+    );
+    let pairs = sequences.candidates(2);
+    assert_eq!(pairs[0].opcodes, vec![op::CONST_2, op::ADD]);
+    assert_eq!(pairs[0].count, 2);
+    assert_eq!(pairs.len(), 4);
+    let triples = sequences.candidates(3);
+    assert_eq!(triples.len(), 4);
+    assert!(triples.iter().all(|t| t.count == 1));
+    assert_eq!(triples[0].to_string(), "CONST_1 + CONST_2 + ADD");
+}
+
+#[test]
+fn test_sequences_end_with_block() {
+    // The call ends the block of `main`, and the instructions of `helper`
+    // start their own
+    let sequences = sequences(
+        ".export main
+            CONST_1
+            CALL_C helper
+            CONST_2
+            RETURN
+        .func helper
+            CONST_4
+            RETURN",
+    );
+    let pairs: Vec<String> = sequences.candidates(2).iter().map(|c| c.to_string()).collect();
+    assert_eq!(pairs, vec!["CONST_1 + CALL_C", "CONST_2 + RETURN", "CONST_4 + RETURN"]);
+    assert!(sequences.candidates(3).is_empty());
+}
+
+#[test]
+fn test_candidate_pattern() {
+    let mut sequences = Sequences::new();
+    let pair = fusion::parse_sequence("DUPE_1 + POP_1").unwrap();
+    sequences.add(&pair, 3);
+    sequences.add(&pair, 2);
+    let candidate = &sequences.candidates(2)[0];
+    assert_eq!(candidate.count, 5);
+    assert_eq!(candidate.mnemonics(), vec!["DUPE_1", "POP_1"]);
+    assert_eq!(fusion::parse_sequence("DUPE_1 + NOT_AN_OPCODE"), None);
+
+    assert!(matches!(
+        candidate.pattern()[..],
+        [Match::Opcode(op::DUPE_1), Match::Opcode(op::POP_1)]
+    ));
+
+    // The pattern of a candidate feeds a rewrite of the peephole optimizer
+    let source = ".export main\n CONST_1\n DUPE_1\n POP_1\n RETURN";
+    let module = tstack::asm::assemble("main", source, Path::new(".")).unwrap();
+    let mut peephole = Peephole::new();
+    peephole.add(Pattern::new(candidate.pattern(), |_| vec![]));
+    let module = peephole.run(&module).unwrap();
+    assert_eq!(module.bytecode, vec![op::CONST_1, op::RETURN]);
+}
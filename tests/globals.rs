@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use tstack::errors::{BytecodeError, Fault, ModuleError};
+use tstack::module::{
+    ExternalGlobal, Global, GlobalType, LocalSymbol, Module, Relocation, RelocationKind,
+};
+
+fn module(name: &str, globals: &[(&str, GlobalType, bool, u64)], bytecode: &[u16]) -> Module {
+    let mut strings = vec![String::from("main")];
+    let mut defined = vec![];
+    for (global, ty, exported, initial) in globals.iter() {
+        defined.push(Global {
+            name_id: strings.len() as u32,
+            ty: *ty,
+            exported: *exported,
+            initial: *initial,
+        });
+        strings.push(String::from(*global));
+    }
+    Module {
+        name: String::from(name),
+        version: Default::default(),
+        strings: strings.into(),
+        data: vec![],
+        writable_data: vec![],
+        local_symbols: vec![LocalSymbol {
+            name_id: 0,
+            code_offset: 0,
+            exported: true,
+            signature: None,
+        }],
+        external_symbols: vec![],
+        globals: defined,
+        external_globals: vec![],
+        bytecode: bytecode.to_vec().into(),
+        relocations: vec![],
+        symbol_lookup: HashMap::new(),
+        debug: None,
+    }
+}
+
+/// Add an import of the named global to a module
+fn import(module: &mut Module, from: &str, global: &str, ty: GlobalType) {
+    module.strings.push(String::from(from));
+    module.strings.push(String::from(global));
+    module.external_globals.push(ExternalGlobal {
+        module_name_id: module.strings.len() as u32 - 2,
+        module_id: 0,
+        global_name_id: module.strings.len() as u32 - 1,
+        global_id: 0,
+        ty,
+    });
+}
+
+/// Bytecode which increments the first global
+fn increment() -> Vec<u16> {
+    vec![
+        tstack::inst_stack!(GLOBAL_GET_C),
+        0,
+        tstack::inst_math!(ADD_C),
+        1,
+        tstack::inst_stack!(GLOBAL_SET_C),
+        0,
+    ]
+}
+
+#[test]
+fn test_global_get_set() {
+    let code = [
+        tstack::inst_stack!(CONST_U16),
+        9,
+        tstack::inst_stack!(GLOBAL_SET_C),
+        1,
+        tstack::inst_stack!(GLOBAL_GET_C),
+        0,
+        tstack::inst_stack!(GLOBAL_GET_C),
+        1,
+    ];
+    let globals = [("a", GlobalType::Int, false, 3), ("b", GlobalType::Int, false, 0)];
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module("main", &globals, &code))).unwrap();
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![3, 9]);
+    assert_eq!(engine.globals[0], vec![3, 9]);
+}
+
+#[test]
+fn test_globals_per_engine() {
+    let globals = [("count", GlobalType::Int, false, 5)];
+    let shared = Rc::new(module("main", &globals, &increment()));
+    let mut first = tstack::Engine::new();
+    let mut second = tstack::Engine::new();
+    first.add_module(Rc::clone(&shared)).unwrap();
+    second.add_module(Rc::clone(&shared)).unwrap();
+    first.run(0, 0).unwrap();
+    first.run(0, 0).unwrap();
+    second.run(0, 0).unwrap();
+    assert_eq!(first.globals[0], vec![7]);
+    assert_eq!(second.globals[0], vec![6]);
+    assert_eq!(shared.globals[0].initial, 5);
+
+    first.replace_module("main", Rc::new(module("main", &globals, &increment()))).unwrap();
+    assert_eq!(first.globals[0], vec![5]);
+}
+
+#[test]
+fn test_import_global() {
+    let lib = module("lib", &[("count", GlobalType::Int, true, 5)], &increment());
+    let code = [
+        tstack::inst_stack!(GLOBAL_EXT_GET_C),
+        0,
+        tstack::inst_math!(ADD_C),
+        10,
+        tstack::inst_stack!(GLOBAL_EXT_SET_C),
+        0,
+    ];
+    let mut main = module("main", &[], &code);
+    import(&mut main, "lib", "count", GlobalType::Int);
+
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(main)).unwrap();
+    engine.add_module(Rc::new(lib)).unwrap();
+    engine.link().unwrap();
+    engine.run(0, 0).unwrap();
+    engine.run(1, 0).unwrap();
+    assert_eq!(engine.globals[1], vec![16]);
+}
+
+#[test]
+fn test_import_global_errors() {
+    let lib = module(
+        "lib",
+        &[("public", GlobalType::Float, true, 0), ("private", GlobalType::Int, false, 0)],
+        &[],
+    );
+    let link = |global: &str, ty: GlobalType| {
+        let mut main = module("main", &[], &[]);
+        import(&mut main, "lib", global, ty);
+        let mut engine = tstack::Engine::new();
+        engine.add_module(Rc::new(main)).unwrap();
+        engine.add_module(Rc::new(lib.clone())).unwrap();
+        engine.link()
+    };
+
+    assert!(link("public", GlobalType::Float).is_ok());
+    assert!(matches!(
+        link("missing", GlobalType::Int),
+        Err(ModuleError::UnresolvedGlobal(m, g)) if m == "lib" && g == "missing"
+    ));
+    assert!(matches!(
+        link("private", GlobalType::Int),
+        Err(ModuleError::PrivateGlobal(m, g)) if m == "lib" && g == "private"
+    ));
+    assert!(matches!(
+        link("public", GlobalType::Int),
+        Err(ModuleError::GlobalTypeMismatch(m, g)) if m == "lib" && g == "public"
+    ));
+}
+
+#[test]
+fn test_global_out_of_range() {
+    let code = [tstack::inst_stack!(GLOBAL_GET_C), 1];
+    let mut engine = tstack::Engine::new();
+    let r = engine.add_module(Rc::new(module("main", &[("a", GlobalType::Int, false, 0)], &code)));
+    assert!(matches!(r, Err(ModuleError::InvalidGlobalAccess(0))));
+
+    let code = [tstack::inst_sys!(NOP), tstack::inst_stack!(GLOBAL_EXT_SET_C), 0];
+    let r = engine.add_module(Rc::new(module("main", &[], &code)));
+    assert!(matches!(r, Err(ModuleError::InvalidGlobalAccess(1))));
+    assert!(engine.modules.is_empty());
+
+    // Modules which are not verified still fault at runtime
+    engine.add_module(Rc::new(module("main", &[], &[]))).unwrap();
+    engine.modules[0] = Rc::new(module("main", &[], &[tstack::inst_stack!(GLOBAL_GET_C), 0]));
+    assert!(matches!(
+        engine.run(0, 0).map_err(Fault::into_error),
+        Err(BytecodeError::InvalidGlobal(0))
+    ));
+}
+
+#[test]
+fn test_merge_globals() {
+    let mut lib = module("lib", &[("count", GlobalType::Int, true, 4)], &increment());
+    lib.strings.set(0, "increment");
+    lib.relocations.push(Relocation { offset: 1, words: 1, kind: RelocationKind::Global });
+    let mut main = module("main", &[("total", GlobalType::Int, true, 1)], &[]);
+    import(&mut main, "lib", "count", GlobalType::Int);
+    let merged = tstack::linker::merge(&[main, lib]).unwrap();
+
+    assert_eq!(merged.globals.len(), 2);
+    assert_eq!(merged.globals[1].initial, 4);
+    assert_eq!(merged.find_global("count"), Some(1));
+    assert_eq!(merged.bytecode[1], 1);
+    // The import of a merged module now refers to the merged module itself
+    let ext = &merged.external_globals[0];
+    assert_eq!(&merged.strings[ext.module_name_id as usize], "main");
+
+    let mut dup = module("lib", &[("total", GlobalType::Int, true, 0)], &[]);
+    dup.strings.set(0, "increment");
+    let main = module("main", &[("total", GlobalType::Int, true, 1)], &[]);
+    let r = tstack::linker::merge(&[main, dup]);
+    assert!(matches!(r, Err(ModuleError::DuplicateSymbol(name)) if name == "total"));
+}
+
+#[test]
+fn test_globals_round_trip() {
+    let mut main = module(
+        "main",
+        &[("a", GlobalType::Int, true, 7), ("b", GlobalType::Float, false, 2.5f64.to_bits())],
+        &increment(),
+    );
+    import(&mut main, "lib", "c", GlobalType::Float);
+    let bytes = main.to_bytes();
+    assert_eq!(tstack::format::read(&bytes).unwrap(), main);
+
+    #[cfg(feature = "json")]
+    assert_eq!(Module::from_json(&main.to_json()).unwrap(), main);
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use tstack::graph::{DependencyGraph, Import, ImportKind};
+use tstack::module::{ExternalSymbol, LocalSymbol, Module};
+use tstack::version::VersionReq;
+
+fn module(name: &str, imports: &[(&str, &str)]) -> Module {
+    let mut strings = vec![String::from("f")];
+    let mut external_symbols = vec![];
+    for (module, symbol) in imports.iter() {
+        strings.push(String::from(*module));
+        strings.push(String::from(*symbol));
+        external_symbols.push(ExternalSymbol {
+            module_name_id: strings.len() as u32 - 2,
+            module_id: 0,
+            symbol_name_id: strings.len() as u32 - 1,
+            symbol_id: 0,
+            signature: None,
+            version_req: None,
+            alias: None,
+        });
+    }
+    Module {
+        name: String::from(name),
+        version: Default::default(),
+        strings: strings.into(),
+        data: vec![],
+        writable_data: vec![],
+        local_symbols: vec![LocalSymbol {
+            name_id: 0,
+            code_offset: 0,
+            exported: true,
+            signature: None,
+        }],
+        external_symbols,
+        globals: vec![],
+        external_globals: vec![],
+        bytecode: vec![tstack::inst_sys!(NOP)].into(),
+        relocations: vec![],
+        symbol_lookup: HashMap::new(),
+        debug: None,
+    }
+}
+
+fn import(symbol: &str) -> Import {
+    Import { kind: ImportKind::Symbol, symbol: String::from(symbol), version_req: None }
+}
+
+#[test]
+fn test_dependency_graph() {
+    let mut engine = tstack::Engine::new();
+    engine
+        .add_module(Rc::new(module("main", &[("io", "print"), ("math", "sqrt"), ("io", "read")])))
+        .unwrap();
+    engine.add_module(Rc::new(module("math", &[("io", "print"), ("io", "print")]))).unwrap();
+    engine.add_module(Rc::new(module("io", &[]))).unwrap();
+
+    let graph = engine.dependency_graph().unwrap();
+    assert_eq!(graph.modules, vec!["main", "math", "io"]);
+    assert_eq!(graph.dependencies.len(), 3);
+    let main: Vec<_> = graph.dependencies_of("main").collect();
+    assert_eq!(main.len(), 2);
+    assert_eq!(main[0].to, "io");
+    assert_eq!(main[0].imports, vec![import("print"), import("read")]);
+    assert_eq!(main[1].to, "math");
+    assert_eq!(main[1].imports, vec![import("sqrt")]);
+    let io: Vec<_> = graph.dependents_of("io").map(|d| d.from.as_str()).collect();
+    assert_eq!(io, vec!["main", "math"]);
+    assert!(graph.missing_modules().is_empty());
+}
+
+#[test]
+fn test_dependency_graph_unlinked() {
+    let mut m = module("main", &[("lib", "f"), ("other", "g")]);
+    m.external_symbols[1].version_req = VersionReq::parse("^1.2");
+    let graph = DependencyGraph::new(&[Rc::new(m)]).unwrap();
+    assert_eq!(graph.missing_modules(), vec!["lib", "other"]);
+    assert_eq!(graph.dependencies[1].imports[0].version_req, VersionReq::parse("^1.2"));
+
+    let mut m = module("main", &[("lib", "f")]);
+    m.external_symbols[0].symbol_name_id = 9;
+    assert!(DependencyGraph::new(&[Rc::new(m)]).is_err());
+}
+
+#[test]
+fn test_dependency_graph_dot() {
+    let graph =
+        DependencyGraph::new(&[Rc::new(module("main", &[("lib", "f"), ("lib", "g")]))]).unwrap();
+    assert_eq!(
+        graph.to_dot(),
+        "digraph modules {\n    \"main\";\n    \"main\" -> \"lib\" [label=\"f, g\"];\n}\n"
+    );
+}
@@ -0,0 +1,126 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::errors::BytecodeError;
+use tstack::handle::HandleTable;
+use tstack::native::StackView;
+
+#[test]
+fn test_handle_table() {
+    let mut table = HandleTable::new();
+    assert!(table.is_empty());
+    let name = table.insert(String::from("name"));
+    let count = table.insert(5u32);
+    assert_ne!(name, 0);
+    assert_ne!(name, count);
+    assert_eq!(table.len(), 2);
+    assert_eq!(table.get::<String>(name).map(|s| s.as_str()), Some("name"));
+    assert_eq!(table.get::<u32>(name), None);
+    *table.get_mut::<u32>(count).unwrap() += 1;
+    assert_eq!(table.get::<u32>(count), Some(&6));
+
+    assert_eq!(table.remove::<u64>(count), None);
+    assert!(table.contains(count));
+    assert_eq!(table.remove::<u32>(count), Some(6));
+    assert!(!table.contains(count));
+    assert_eq!(table.len(), 1);
+
+    // The slot is reused with a new generation, so the old handle stays invalid
+    let reused = table.insert(7u32);
+    assert_eq!(reused & 0xFFFF_FFFF, count & 0xFFFF_FFFF);
+    assert_ne!(reused, count);
+    assert_eq!(table.get::<u32>(count), None);
+    assert_eq!(table.get::<u32>(reused), Some(&7));
+    assert_eq!(table.get::<u32>(0), None);
+}
+
+#[test]
+fn test_handles_from_natives() {
+    let mut engine = tstack::Engine::new();
+    engine
+        .register_native("host", "new", |stack: &mut StackView| {
+            let handle = stack.insert_handle(Vec::<u64>::new());
+            stack.push(handle)
+        })
+        .unwrap();
+    engine
+        .register_native("host", "append", |stack: &mut StackView| {
+            let [handle, value] = stack.pop_many()?;
+            stack.get_handle_mut::<Vec<u64>>(handle)?.push(value);
+            stack.push(handle)
+        })
+        .unwrap();
+    let sum = engine
+        .register_native("host", "sum", |stack: &mut StackView| {
+            let handle = stack.pop()?;
+            let values = stack.remove_handle::<Vec<u64>>(handle)?;
+            stack.push(values.iter().sum())
+        })
+        .unwrap();
+    let module = asm::assemble(
+        "main",
+        ".export main
+            CALL_EXT_C host.new
+            CONST_3
+            CALL_EXT_C host.append
+            CONST_4
+            CALL_EXT_C host.append
+            DUPE_1
+            CALL_EXT_C host.sum
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+    engine.run(1, 0).unwrap();
+    assert_eq!(engine.stack.len(), 2);
+    assert_eq!(engine.stack[1], 7);
+    assert!(engine.handles().is_empty());
+
+    engine.stack.truncate(1);
+    let handle = engine.stack[0];
+    let error = engine.run(0, sum).unwrap_err().into_error();
+    assert!(matches!(error, BytecodeError::InvalidHandle(h) if h == handle));
+
+    let handle = engine.insert_handle(vec![1u64]);
+    assert_eq!(engine.get_handle::<Vec<u64>>(handle), Some(&vec![1]));
+    engine.get_handle_mut::<Vec<u64>>(handle).unwrap().push(2);
+    assert_eq!(engine.remove_handle::<Vec<u64>>(handle), Some(vec![1, 2]));
+}
+
+#[test]
+fn test_release_handles() {
+    let released = Rc::new(RefCell::new(vec![]));
+    let mut engine = tstack::Engine::new();
+    let record = Rc::clone(&released);
+    engine.set_handle_drop_hook(move |id: u32| record.borrow_mut().push(id));
+    let fail = engine
+        .register_native("host", "fail", |_: &mut StackView| {
+            Err(BytecodeError::NativeError(String::from("failed")))
+        })
+        .unwrap();
+
+    // Objects taken out of the table are not released
+    let handle = engine.insert_handle(1u32);
+    assert_eq!(engine.remove_handle::<u32>(handle), Some(1));
+    let handle = engine.insert_handle(2u32);
+    let name = engine.insert_handle(String::from("dropped"));
+    engine.reset();
+    assert_eq!(*released.borrow(), vec![2]);
+    assert!(engine.handles().is_empty());
+    assert_eq!(engine.get_handle::<u32>(handle), None);
+    assert_eq!(engine.get_handle::<String>(name), None);
+
+    engine.insert_handle(3u32);
+    engine.stack.push(1);
+    engine.run(0, fail).unwrap_err();
+    assert_eq!(*released.borrow(), vec![2, 3]);
+    assert_eq!(engine.stack, vec![1]);
+
+    engine.insert_handle(4u32);
+    drop(engine);
+    assert_eq!(*released.borrow(), vec![2, 3, 4]);
+}
@@ -0,0 +1,40 @@
+use tstack::hash::{sha256, to_hex, Sha256};
+
+#[test]
+fn test_sha256_empty() {
+    assert_eq!(
+        to_hex(&sha256(b"")),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+}
+
+#[test]
+fn test_sha256_short() {
+    assert_eq!(
+        to_hex(&sha256(b"abc")),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+}
+
+#[test]
+fn test_sha256_multi_block() {
+    assert_eq!(
+        to_hex(&sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+    assert_eq!(
+        to_hex(&sha256(&[b'a'; 1000])),
+        "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+    );
+}
+
+#[test]
+fn test_sha256_incremental() {
+    let input: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    for split in [0, 1, 55, 63, 64, 65, 130, 300] {
+        let mut hasher = Sha256::new();
+        hasher.update(&input[..split]);
+        hasher.update(&input[split..]);
+        assert_eq!(hasher.finish(), sha256(&input), "split {}", split);
+    }
+}
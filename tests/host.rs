@@ -0,0 +1,272 @@
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+
+use tstack::asm;
+use tstack::errors::{BytecodeError, Fault};
+use tstack::host::{
+    Capability, Clock, EntropySource, HostPolicy, ManualClock, RandomSource, RecordingSource,
+    ReplaySource, SharedBuffer, SystemClock, Xoshiro256,
+};
+use tstack::native::StackView;
+
+struct FixedClock(Duration);
+
+impl Clock for FixedClock {
+    fn now(&self) -> Duration {
+        self.0
+    }
+}
+
+struct Counter(u64);
+
+impl RandomSource for Counter {
+    fn next_u64(&mut self) -> u64 {
+        self.0 += 1;
+        self.0
+    }
+}
+
+#[test]
+fn test_host_services() {
+    let mut engine = tstack::Engine::new();
+    let output = SharedBuffer::new();
+    engine.set_stdout(output.clone());
+    engine.set_clock(FixedClock(Duration::from_millis(1500)));
+    engine.set_random_source(Counter(10));
+    engine
+        .register_native("host", "now", |stack: &mut StackView| {
+            let millis = stack.now()?.as_millis() as u64;
+            stack.push(millis)
+        })
+        .unwrap();
+    engine
+        .register_native("host", "roll", |stack: &mut StackView| {
+            let value = stack.random()?;
+            stack.write_line("rolled")?;
+            stack.push(value)
+        })
+        .unwrap();
+    let module = asm::assemble(
+        "main",
+        ".export main
+            CALL_EXT_C host.now
+            PRINT_U64
+            CALL_EXT_C host.roll
+            CALL_EXT_C host.roll
+            PRINT_STACK
+            CONST_N1
+            PRINT_I64
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+    engine.run(1, 0).unwrap();
+    assert_eq!(output.take_string(), "PRINT: 1500\nrolled\nrolled\nStack: [11, 12]\nPRINT: -1\n");
+    assert!(output.contents().is_empty());
+}
+
+#[test]
+fn test_default_services() {
+    let clock = SystemClock::new();
+    let start = clock.now();
+    assert!(clock.now() >= start);
+
+    let mut random = EntropySource::new();
+    let values: Vec<u64> = (0..4).map(|_| random.next_u64()).collect();
+    let mut distinct = values.clone();
+    distinct.dedup();
+    assert_eq!(distinct.len(), 4);
+}
+
+#[test]
+fn test_host_policy() {
+    let policy = HostPolicy::deny_all().grant(Capability::Io).grant(Capability::Clock);
+    assert!(policy.allows(Capability::Io));
+    assert!(!policy.allows(Capability::Random));
+    assert!(!policy.deny(Capability::Io).allows(Capability::Io));
+    assert_eq!(HostPolicy::default(), HostPolicy::allow_all().deny(Capability::Network));
+
+    let mut engine = tstack::Engine::new();
+    let output = SharedBuffer::new();
+    engine.set_stdout(output.clone());
+    let roll = engine.register_fn("host", "roll", || 4u64).unwrap();
+    let draw = engine
+        .register_native("host", "draw", |stack: &mut StackView| {
+            let value = stack.random()?;
+            stack.push(value)
+        })
+        .unwrap();
+    let module = asm::assemble(
+        "main",
+        ".export print\n  CONST_2\n  PRINT_U64\n  RETURN
+        .export roll\n  CALL_EXT_C host.roll\n  RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+
+    engine.policy = HostPolicy::deny_all().grant(Capability::Native);
+    let error = engine.run(1, 0).unwrap_err().into_error();
+    assert!(matches!(error, BytecodeError::PermissionDenied(Capability::Io)));
+    assert_eq!(error.to_string(), "permission denied: I/O is not allowed");
+    assert!(matches!(
+        engine.run(0, draw).map_err(Fault::into_error),
+        Err(BytecodeError::PermissionDenied(Capability::Random))
+    ));
+    assert!(output.contents().is_empty());
+
+    engine.policy = HostPolicy::allow_all().deny(Capability::Native);
+    engine.run(1, 0).unwrap();
+    assert_eq!(output.take_string(), "PRINT: 2\n");
+    assert!(matches!(
+        engine.run(1, 1).map_err(Fault::into_error),
+        Err(BytecodeError::PermissionDenied(Capability::Native))
+    ));
+    assert!(matches!(
+        engine.run(0, roll).map_err(Fault::into_error),
+        Err(BytecodeError::PermissionDenied(Capability::Native))
+    ));
+}
+
+#[test]
+fn test_args_and_env() {
+    let mut module = asm::assemble(
+        "main",
+        ".export main
+            ARG_COUNT
+            CONST_1
+            CONST_1
+            CONST_1
+            ARG_GET
+            CONST_4
+            CONST_0
+            CONST_1
+            ARG_GET
+            CONST_0
+            CONST_4
+            CONST_2
+            CONST_4
+            ENV_GET
+            CONST_0
+            CONST_3
+            CONST_2
+            CONST_4
+            ENV_GET
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    module.writable_data = vec![0; 8];
+    module.writable_data[0] = u64::from_le_bytes(*b"HOME\0\0\0\0");
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.args = vec![String::from("prog"), String::from("a somewhat long argument")];
+    engine.env.insert(String::from("HOME"), String::from("/home/user"));
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![2, 24, u64::MAX, 10, u64::MAX]);
+    assert_eq!(engine.data[0][0], u64::from_le_bytes(*b"HOME\0\0\0\0"));
+    assert_eq!(engine.data[0][1], u64::from_le_bytes(*b"a somewh"));
+    assert_eq!(engine.data[0][2], u64::from_le_bytes(*b"/home/us"));
+    assert_eq!(engine.data[0][3], u64::from_le_bytes(*b"er\0\0\0\0\0\0"));
+    assert_eq!(engine.data[0][4], 0);
+
+    engine.stack.clear();
+    engine.policy = HostPolicy::allow_all().deny(Capability::Syscall);
+    assert!(matches!(
+        engine.run(0, 0).map_err(Fault::into_error),
+        Err(BytecodeError::PermissionDenied(Capability::Syscall))
+    ));
+}
+
+#[test]
+fn test_random_sources() {
+    // The first values of xoshiro256** seeded by SplitMix64 from zero
+    let mut random = Xoshiro256::new(0);
+    let values: Vec<u64> = (0..3).map(|_| random.next_u64()).collect();
+    assert_eq!(values, vec![0x99EC5F36CB75F2B4, 0xBF6E1F784956452A, 0x1A5F849D4933E6E0]);
+    assert_eq!(Xoshiro256::new(0).next_u64(), values[0]);
+    assert_ne!(Xoshiro256::new(1).next_u64(), values[0]);
+
+    let module = asm::assemble(
+        "main",
+        ".export main
+            RANDOM
+            CONST_U16 6
+            RANDOM_BELOW
+            CONST_0
+            RANDOM_BELOW
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    let recording = RecordingSource::new(Xoshiro256::new(7));
+    engine.set_random_source(recording.clone());
+    engine.run(0, 0).unwrap();
+    let drawn = std::mem::take(&mut engine.stack);
+    assert!(drawn[1] < 6);
+    assert_eq!(recording.values().len(), 3);
+    assert_eq!(recording.values()[0], drawn[0]);
+
+    engine.set_random_source(ReplaySource::new(recording.values()));
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, drawn);
+
+    engine.stack.clear();
+    engine.policy = HostPolicy::default().deny(Capability::Random);
+    assert!(matches!(
+        engine.run(0, 0).map_err(Fault::into_error),
+        Err(BytecodeError::PermissionDenied(Capability::Random))
+    ));
+
+    let mut replay = ReplaySource::new(vec![1, 2]);
+    assert_eq!((0..3).map(|_| replay.next_u64()).collect::<Vec<_>>(), vec![1, 2, 1]);
+    assert_eq!(ReplaySource::default().next_u64(), 0);
+}
+
+#[test]
+fn test_manual_clock() {
+    let mut engine = tstack::Engine::new();
+    let clock = ManualClock::new();
+    engine.set_clock(clock.clone());
+    let tick = clock.clone();
+    engine.register_fn("host", "tick", move || tick.advance(Duration::from_millis(250))).unwrap();
+    let module = asm::assemble(
+        "main",
+        ".export main
+            TIME
+            CALL_EXT_C host.tick
+            TIME
+            CALL_EXT_C host.tick
+            TIME
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+
+    clock.set(Duration::from_secs(1));
+    engine.run(1, 0).unwrap();
+    assert_eq!(engine.stack, vec![1_000_000_000, 1_250_000_000, 1_500_000_000]);
+
+    // The deadline passes during the second call
+    engine.stack.clear();
+    engine.set_timeout(Duration::from_millis(400));
+    let error = engine.run(1, 0).unwrap_err().into_error();
+    assert!(matches!(error, BytecodeError::DeadlineExceeded(_)));
+    assert!(error.to_string().starts_with("deadline exceeded"));
+    assert!(engine.stack.is_empty());
+
+    engine.deadline = None;
+    engine.policy = HostPolicy::default().deny(Capability::Clock);
+    assert!(matches!(
+        engine.run(1, 0).map_err(Fault::into_error),
+        Err(BytecodeError::PermissionDenied(Capability::Clock))
+    ));
+}
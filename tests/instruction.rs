@@ -0,0 +1,123 @@
+use tstack::bytecode::{self, groups, jump, Instruction, Jump, JumpSource};
+
+#[test]
+fn test_round_trip_every_opcode() {
+    for opcode in 0..=u16::MAX {
+        let operands = match bytecode::operand_count(opcode) {
+            Some(count) => count,
+            None => {
+                assert!(Instruction::decode(&[opcode]).is_err());
+                continue;
+            }
+        };
+        // The condition type of unconditional jumps is ignored, and dropped
+        // when encoding
+        let data = opcode as u8;
+        if opcode >> 8 == groups::JUMP as u16
+            && data & jump::CONDITIONAL_MASK == 0
+            && data >> 4 != 0
+        {
+            continue;
+        }
+        let mut code = vec![opcode];
+        code.extend((0..operands).map(|i| 0x1234 + i as u16));
+
+        let (instruction, len) = Instruction::decode(&code).unwrap();
+        assert_eq!(len, code.len(), "length of {:#06x}", opcode);
+        assert_eq!(instruction.size(), len);
+        assert_eq!(instruction.opcode(), opcode);
+        let mut encoded = vec![];
+        instruction.encode(&mut encoded);
+        assert_eq!(encoded, code, "encoding of {}", instruction);
+    }
+}
+
+#[test]
+fn test_decode_operands() {
+    let code = [tstack::inst_stack!(CONST_U32), 0x0001, 0x0002, tstack::inst_sys!(HALT)];
+    assert_eq!(Instruction::decode(&code).unwrap(), (Instruction::ConstU32(0x0001_0002), 3));
+
+    let code = [tstack::inst_math!(ICLAMP_C), 0xFFFF, 0x0005];
+    assert_eq!(Instruction::decode(&code).unwrap().0, Instruction::IclampC(-1, 5));
+
+    let code = [tstack::inst_jump!(SRC_C16, MODE_RELATIVE, TYPE_NZ), 0xFFFE];
+    assert_eq!(
+        Instruction::decode(&code).unwrap().0,
+        Instruction::Jump(Jump {
+            source: JumpSource::C16(0xFFFE),
+            relative: true,
+            condition: Some(jump::TYPE_NZ),
+        })
+    );
+}
+
+#[test]
+fn test_encode_words() {
+    let mut code = vec![];
+    bytecode::encode_u32(&mut code, 0x0001_0002);
+    bytecode::encode_u64(&mut code, 0x0003_0004_0005_0006);
+    assert_eq!(code, vec![1, 2, 3, 4, 5, 6]);
+    assert_eq!(bytecode::decode_u32(&code), Some(0x0001_0002));
+    assert_eq!(bytecode::decode_u64(&code[2..]), Some(0x0003_0004_0005_0006));
+    assert_eq!(bytecode::decode_u32(&code[5..]), None);
+    assert_eq!(bytecode::decode_u64(&code[3..]), None);
+
+    // Instructions use the same order for their operands
+    let mut code = vec![tstack::inst_stack!(CONST_U64)];
+    bytecode::encode_u64(&mut code, 0x0102_0304_0506_0708);
+    assert_eq!(code[1..], [0x0102, 0x0304, 0x0506, 0x0708]);
+    assert_eq!(Instruction::decode(&code).unwrap().0, Instruction::ConstU64(0x0102_0304_0506_0708));
+}
+
+#[test]
+fn test_decode_errors() {
+    assert!(Instruction::decode(&[]).is_err());
+    assert!(Instruction::decode(&[0xFFFF]).unwrap_err().is_bad_opcode());
+    assert!(Instruction::decode(&[tstack::inst_stack!(CONST_U64), 0, 0])
+        .unwrap_err()
+        .is_code_data());
+}
+
+#[test]
+fn test_display() {
+    assert_eq!(Instruction::Nop.to_string(), "NOP");
+    assert_eq!(Instruction::ConstI16(-3).to_string(), "CONST_I16 -3");
+    assert_eq!(Instruction::ClampC(10, 2).to_string(), "CLAMP_C 10, 2");
+
+    let jump =
+        |source, relative, condition| Instruction::Jump(Jump { source, relative, condition });
+    assert_eq!(jump(JumpSource::C16(8), false, None).to_string(), "JMP.C16 8");
+    assert_eq!(
+        jump(JumpSource::C16(0xFFFD), true, Some(jump::TYPE_Z)).to_string(),
+        "JMP_Z.C16.REL -3"
+    );
+    assert_eq!(
+        jump(JumpSource::C32(4), true, Some(jump::TYPE_LES)).to_string(),
+        "JMP_LES.C32.REL +4"
+    );
+    assert_eq!(jump(JumpSource::Dynamic, true, None).to_string(), "JMP.DYN.REL");
+}
+
+#[test]
+fn test_parse_round_trip() {
+    for opcode in 0..=u16::MAX {
+        let operands = match bytecode::operand_count(opcode) {
+            Some(count) => count,
+            None => continue,
+        };
+        let mut code = vec![opcode];
+        code.extend((0..operands).map(|i| 0xFFF0 + i as u16));
+        let (instruction, _) = Instruction::decode(&code).unwrap();
+        assert_eq!(Instruction::parse(&instruction.to_string()), Some(instruction));
+    }
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!(Instruction::parse("CONST_U16 65536"), None);
+    assert_eq!(Instruction::parse("CONST_U16"), None);
+    assert_eq!(Instruction::parse("NOP 1"), None);
+    assert_eq!(Instruction::parse("JMP.C16.REL 40000"), None);
+    assert_eq!(Instruction::parse("JMP_XX.C16 1"), None);
+    assert_eq!(Instruction::parse("FROB"), None);
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use tstack::bytecode::{jump, Instruction, Jump, JumpSource};
+use tstack::errors::ModuleError;
+use tstack::module::{LocalSymbol, Module, Relocation, RelocationKind, Signature, SymbolDef};
+use tstack::native::FuncRef;
+
+fn module(name: &str) -> Module {
+    Module {
+        name: String::from(name),
+        version: Default::default(),
+        strings: vec![String::from("main"), String::from("helper"), String::from("alias")].into(),
+        data: vec![],
+        writable_data: vec![],
+        local_symbols: vec![
+            LocalSymbol {
+                name_id: 0,
+                code_offset: 0,
+                exported: true,
+                signature: Some(Signature { inputs: 0, outputs: 1 }),
+            },
+            LocalSymbol { name_id: 1, code_offset: 3, exported: false, signature: None },
+            LocalSymbol { name_id: 2, code_offset: 3, exported: true, signature: None },
+        ],
+        external_symbols: vec![],
+        globals: vec![],
+        external_globals: vec![],
+        bytecode: vec![
+            tstack::inst_stack!(CONST_U16),
+            7,
+            tstack::inst_sys!(HALT),
+            tstack::inst_sys!(NOP),
+            tstack::inst_sys!(HALT),
+        ]
+        .into(),
+        relocations: vec![],
+        symbol_lookup: HashMap::new(),
+        debug: None,
+    }
+}
+
+#[test]
+fn test_symbols() {
+    let m = module("main");
+    let symbols: Vec<_> = m.symbols().collect();
+    assert_eq!(symbols.len(), 3);
+
+    assert_eq!(symbols[0].id(), 0);
+    assert_eq!(symbols[0].name(), Some("main"));
+    assert_eq!(symbols[0].offset(), 0);
+    assert_eq!(symbols[0].len(), 3);
+    assert_eq!(symbols[0].code(), &m.bytecode[0..3]);
+    assert!(symbols[0].exported());
+    assert_eq!(symbols[0].signature(), Some(Signature { inputs: 0, outputs: 1 }));
+
+    assert_eq!(symbols[1].name(), Some("helper"));
+    assert!(!symbols[1].exported());
+    assert_eq!(symbols[1].len(), 2);
+    assert_eq!(symbols[2].code(), symbols[1].code());
+
+    assert_eq!(m.symbol(2).unwrap().name(), Some("alias"));
+    assert!(m.symbol(3).is_none());
+}
+
+#[test]
+fn test_symbol_out_of_range() {
+    let mut m = module("main");
+    m.local_symbols[1].code_offset = 10;
+    m.local_symbols[1].name_id = 10;
+    let symbol = m.symbol(1).unwrap();
+    assert_eq!(symbol.name(), None);
+    assert!(symbol.is_empty());
+    assert!(symbol.code().is_empty());
+}
+
+#[test]
+fn test_engine_modules() {
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module("main"))).unwrap();
+    engine.add_module(Rc::new(module("lib"))).unwrap();
+
+    let names: Vec<_> = engine.modules().map(|m| m.name.as_str()).collect();
+    assert_eq!(names, vec!["main", "lib"]);
+    assert_eq!(engine.module("lib").unwrap().symbols().count(), 3);
+    assert!(engine.module("other").is_none());
+}
+
+#[test]
+fn test_find_symbol() {
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module("main"))).unwrap();
+    let mut lib = module("lib");
+    lib.symbol_lookup.insert(String::from("entry"), 2);
+    engine.add_module(Rc::new(lib)).unwrap();
+    engine.register_fn("host", "answer", || 42u64).unwrap();
+
+    assert_eq!(engine.find_symbol("main", "helper"), Some(FuncRef::new(0, 1)));
+    assert_eq!(engine.find_symbol("lib", "entry"), Some(FuncRef::new(1, 2)));
+    assert_eq!(engine.find_symbol("host", "answer"), Some(FuncRef::new(2, 0)));
+    assert_eq!(engine.find_symbol("lib", "missing"), None);
+    assert_eq!(engine.find_symbol("other", "main"), None);
+
+    // Replacing a module looks up the symbols of the new module
+    let mut lib = module("lib");
+    lib.strings.set(1, "renamed");
+    engine.replace_module("lib", Rc::new(lib)).unwrap();
+    assert_eq!(engine.find_symbol("lib", "helper"), None);
+    assert_eq!(engine.find_symbol("lib", "renamed"), Some(FuncRef::new(1, 1)));
+
+    let answer = engine.find_symbol("host", "answer").unwrap();
+    assert_eq!(engine.call_ref(answer, &[]).unwrap(), vec![42]);
+}
+
+#[test]
+fn test_from_instructions() {
+    let absolute = |target| {
+        Instruction::Jump(Jump {
+            source: JumpSource::C16(target),
+            relative: false,
+            condition: Some(jump::TYPE_Z),
+        })
+    };
+    let module = Module::from_instructions(
+        "main",
+        vec![
+            (
+                SymbolDef {
+                    exported: true,
+                    signature: Some(Signature { inputs: 0, outputs: 1 }),
+                    ..SymbolDef::new("main")
+                },
+                vec![Instruction::ConstU16(6), Instruction::Return],
+            ),
+            (
+                SymbolDef::new("helper"),
+                vec![Instruction::Dupe1, absolute(5), Instruction::CallC(0), Instruction::Return],
+            ),
+        ],
+    )
+    .unwrap();
+    assert_eq!(module.symbol(0).unwrap().name(), Some("main"));
+    assert!(module.symbol(0).unwrap().exported());
+    assert_eq!(module.symbol(0).unwrap().signature(), Some(Signature { inputs: 0, outputs: 1 }));
+    assert_eq!(module.symbol(1).unwrap().offset(), 3);
+    assert!(!module.symbol(1).unwrap().exported());
+    assert_eq!(
+        module.symbol(1).unwrap().code()[1..3],
+        [tstack::inst_jump!(SRC_C16, MODE_ABSOLUTE, TYPE_Z), 8]
+    );
+    assert_eq!(
+        module.relocations,
+        vec![
+            Relocation { offset: 5, words: 1, kind: RelocationKind::Code },
+            Relocation { offset: 7, words: 1, kind: RelocationKind::LocalSymbol },
+        ]
+    );
+
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![6]);
+}
+
+#[test]
+fn test_from_instructions_errors() {
+    let error = Module::from_instructions(
+        "main",
+        vec![(SymbolDef::new("a"), vec![]), (SymbolDef::new("a"), vec![Instruction::Return])],
+    )
+    .unwrap_err();
+    assert!(matches!(error, ModuleError::DuplicateSymbol(name) if name == "a"));
+
+    let far = Instruction::Jump(Jump {
+        source: JumpSource::C16(u16::MAX),
+        relative: false,
+        condition: None,
+    });
+    let error = Module::from_instructions(
+        "main",
+        vec![(SymbolDef::new("a"), vec![Instruction::Nop]), (SymbolDef::new("b"), vec![far])],
+    )
+    .unwrap_err();
+    assert!(matches!(error, ModuleError::RelocationOverflow(2)));
+}
+
+#[test]
+fn test_disassemble() {
+    let mut module = tstack::asm::assemble(
+        "main",
+        ".export main
+            CALL_C helper
+            CALL_EXT_C lib.add
+            FAULT \"failed\"
+        .func helper
+            CALL_C 9
+            RETURN",
+        std::path::Path::new("."),
+    )
+    .unwrap();
+    module.bytecode.to_mut().push(0xffff);
+    let listing: Vec<_> = [0, 2, 4, 6, 8, 9].iter().map(|&o| module.disassemble(o)).collect();
+    assert_eq!(
+        listing,
+        vec![
+            "CALL_C 1  ; helper",
+            "CALL_EXT_C 0  ; lib.add",
+            "FAULT 2  ; \"failed\"",
+            "CALL_C 9",
+            "RETURN",
+            "0xffff",
+        ]
+    );
+}
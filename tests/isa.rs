@@ -0,0 +1,58 @@
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::bytecode::{groups, op};
+use tstack::errors::{BytecodeError, Fault};
+use tstack::isa::InstructionSet;
+
+fn restricted(source: &str, set: InstructionSet) -> tstack::Engine {
+    let module = tstack::asm::assemble("main", source, Path::new(".")).unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.instruction_set = Some(set);
+    engine
+}
+
+#[test]
+fn test_instruction_sets() {
+    assert_eq!(InstructionSet::all().len(), 0x10000);
+    assert!(InstructionSet::none().is_empty());
+    assert_eq!(InstructionSet::default(), InstructionSet::all());
+
+    let set = InstructionSet::none().allow_group(groups::MATH).allow(op::RETURN).deny(op::DIV);
+    assert!(set.allows(op::ADD));
+    assert!(!set.allows(op::DIV));
+    assert!(set.allows(op::RETURN));
+    assert!(!set.allows(op::CALL_C));
+    assert_eq!(set.len(), 256);
+
+    let compute = InstructionSet::compute();
+    assert!(compute.allows(op::CALL_EXT_C));
+    assert!(!compute.allows(op::PRINT_U64));
+    assert!(!compute.allows(op::HALT));
+    assert!(matches!(compute.check(op::OPEN), Err(BytecodeError::DisallowedOpcode(op::OPEN))));
+}
+
+#[test]
+fn test_disallowed_opcode() {
+    let source = ".export main
+            CONST_1
+            CONST_2
+            MUL_C 3
+            ADD
+            RETURN";
+    let set = InstructionSet::none().allow_group(groups::STACK).allow(op::ADD).allow(op::RETURN);
+    let mut engine = restricted(source, set.clone());
+    let error = engine.run(0, 0).map_err(Fault::into_error).unwrap_err();
+    assert!(matches!(error, BytecodeError::DisallowedOpcode(op::MUL_C)));
+
+    // Instructions run one at a time are checked the same as whole blocks
+    engine.set_trace_hook(|_: &tstack::trace::TraceEvent| ());
+    let fault = engine.run(0, 0).unwrap_err();
+    assert!(matches!(fault.error(), BytecodeError::DisallowedOpcode(op::MUL_C)));
+    assert_eq!(fault.location().unwrap().offset, 2);
+
+    let mut engine = restricted(source, set.allow(op::MUL_C));
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![7]);
+}
@@ -0,0 +1,100 @@
+#![cfg(feature = "json")]
+
+use std::collections::HashMap;
+
+use tstack::errors::ModuleError;
+use tstack::module::{ExternalSymbol, LocalSymbol, Module, Relocation, RelocationKind, Signature};
+use tstack::version::{Version, VersionReq};
+
+fn module() -> Module {
+    Module {
+        name: String::from("main"),
+        version: Version::new(0, 3, 1),
+        strings: vec![String::from("main"), String::from("lib"), String::from("f")].into(),
+        data: vec![42],
+        writable_data: vec![9],
+        local_symbols: vec![LocalSymbol {
+            name_id: 0,
+            code_offset: 0,
+            exported: true,
+            signature: Some(Signature { inputs: 0, outputs: 1 }),
+        }],
+        external_symbols: vec![ExternalSymbol {
+            module_name_id: 1,
+            module_id: 0,
+            symbol_name_id: 2,
+            symbol_id: 0,
+            signature: None,
+            version_req: VersionReq::parse("^0.3"),
+            alias: None,
+        }],
+        globals: vec![],
+        external_globals: vec![],
+        bytecode: vec![
+            tstack::inst_stack!(CONST_U16),
+            0,
+            tstack::inst_jump!(SRC_C16, MODE_RELATIVE, TYPE_Z),
+            0xFFFC,
+            tstack::inst_sys!(HALT),
+            0xFFFF,
+        ]
+        .into(),
+        relocations: vec![Relocation { offset: 1, words: 1, kind: RelocationKind::Data }],
+        symbol_lookup: HashMap::from([(String::from("main"), 0)]),
+        debug: None,
+    }
+}
+
+#[test]
+fn test_json_round_trip() {
+    let m = module();
+    assert_eq!(Module::from_json(&m.to_json()).unwrap(), m);
+}
+
+#[test]
+fn test_json_schema() {
+    let value: serde_json::Value = serde_json::from_str(&module().to_json()).unwrap();
+    assert_eq!(value["version"], "0.3.1");
+    assert_eq!(value["local_symbols"][0]["name"], 0);
+    assert_eq!(value["external_symbols"][0]["version_req"], "^0.3");
+    assert_eq!(
+        value["bytecode"],
+        serde_json::json!([
+            {"op": "CONST_U16", "args": [0]},
+            {"op": "JMP_Z.C16.REL", "args": [-4]},
+            {"op": "HALT"},
+            {"raw": 65535},
+        ])
+    );
+    assert!(value.get("debug").is_none());
+}
+
+#[test]
+fn test_json_hand_written() {
+    let text = r#"{
+        "name": "hand",
+        "version": "1.0.0",
+        "strings": ["main"],
+        "data": [],
+        "local_symbols": [{"name": 0, "offset": 0, "exported": true}],
+        "external_symbols": [],
+        "bytecode": [
+            {"op": "CONST_2"},
+            {"op": "ADD_C", "args": [3]},
+            {"op": "HALT"}
+        ]
+    }"#;
+    let m = Module::from_json(text).unwrap();
+    assert_eq!(
+        m.bytecode,
+        vec![tstack::inst_stack!(CONST_2), tstack::inst_math!(ADD_C), 3, tstack::inst_sys!(HALT)]
+    );
+}
+
+#[test]
+fn test_json_invalid_instruction() {
+    let text = module().to_json().replace("CONST_U16", "CONST_U17");
+    assert!(matches!(Module::from_json(&text), Err(ModuleError::InvalidJson(_))));
+    let text = module().to_json().replace("\"0.3.1\"", "\"zero\"");
+    assert!(matches!(Module::from_json(&text), Err(ModuleError::InvalidJson(_))));
+}
@@ -0,0 +1,338 @@
+mod common;
+
+use std::rc::Rc;
+
+use tstack::errors::{BytecodeError, CompileError};
+use tstack::lang;
+use tstack::module::{Relocation, RelocationKind, Signature};
+
+use common::listing;
+
+#[test]
+fn test_run_expression() {
+    let module = lang::compile(
+        "main",
+        "// Straight line code
+        export fn main() {
+            print 6 * 7;
+            return 2 + 3 * (4 + 0x10);
+        }",
+    )
+    .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![62]);
+}
+
+/// Compile and run the `main` function of a program, returning the stack
+fn run(source: &str, configure: impl FnOnce(&mut tstack::Engine)) -> Vec<u64> {
+    let module = lang::compile("main", source).unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    configure(&mut engine);
+    engine.run(0, 0).unwrap();
+    engine.stack
+}
+
+#[test]
+fn test_run_division() {
+    let source = "export fn main() {
+            let a = 7;
+            let b = -7;
+            print a / 2;
+            print a % 2;
+            print b / 2;
+            return b % 2;
+        }";
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(lang::compile("main", source).unwrap())).unwrap();
+    let output = tstack::host::SharedBuffer::new();
+    engine.set_stdout(output.clone());
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![-1i64 as u64]);
+    assert_eq!(output.take_string(), "PRINT: 3\nPRINT: 1\nPRINT: -3\n");
+
+    let module = lang::compile("main", "export fn main() { let a = 0; return 1 / a; }").unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    let fault = engine.run(0, 0).unwrap_err();
+    assert!(matches!(fault.error(), BytecodeError::DivideByZero(_)));
+}
+
+#[test]
+fn test_run_control_flow() {
+    let source = "export fn main() {
+            let n = 10;
+            let total = 0;
+            while n > 0 {
+                total = total + square(n);
+                n = n - 1;
+            }
+            if total >= 100 && !(total == 385) {
+                return -1;
+            } else {
+                if n < 0 || total != 385 { return -2; }
+            }
+            return total + (3 <= 3) + (-1 < 0) * 10;
+        }
+        fn square(x) { return x * x; }";
+    assert_eq!(run(source, |_| ()), vec![396]);
+    // Running instructions one at a time takes the same jumps
+    assert_eq!(
+        run(source, |engine| engine.set_trace_hook(|_: &tstack::trace::TraceEvent| ())),
+        vec![396]
+    );
+    assert_eq!(run(source, |engine| engine.fuel = Some(1000)), vec![396]);
+
+    // Every instruction run, including each jump, consumes fuel
+    let module = lang::compile("main", source).unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.fuel = Some(100);
+    let fault = engine.run(0, 0).unwrap_err();
+    assert!(matches!(fault.error(), tstack::errors::BytecodeError::OutOfFuel(_)));
+
+    // Jumps are checked against the instruction set like any instruction
+    let set = tstack::isa::InstructionSet::all().deny_group(tstack::bytecode::groups::JUMP);
+    engine.instruction_set = Some(set);
+    engine.fuel = None;
+    let fault = engine.run(0, 0).unwrap_err();
+    assert!(matches!(fault.error(), tstack::errors::BytecodeError::DisallowedOpcode(_)));
+}
+
+#[test]
+fn test_run_example() {
+    // The program of the module documentation
+    let source = "export fn main() {
+            let n = 10;
+            let total = 0;
+            while n > 0 {
+                total = total + square(n);
+                n = n - 1;
+            }
+            if total >= 100 && !(total == 385) {
+                print total;
+            } else {
+                print -1;
+            }
+            return host.log(total);
+        }
+
+        fn square(x) {
+            return x * x;
+        }";
+    let module = lang::compile("main", source).unwrap();
+    let mut engine = tstack::Engine::new();
+    let output = tstack::host::SharedBuffer::new();
+    engine.set_stdout(output.clone());
+    engine.register_fn("host", "log", |total: u64| total + 1).unwrap();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+    let main = engine.module_lookup["main"];
+    engine.run(main, 0).unwrap();
+    assert_eq!(engine.stack, vec![386]);
+    assert_eq!(output.take_string(), "PRINT: -1\n");
+}
+
+#[test]
+fn test_compile_function() {
+    let module = lang::compile(
+        "math",
+        "fn sub(a, b) {
+            let c = a - b;
+            return -c;
+        }",
+    )
+    .unwrap();
+    let symbol = module.symbol(0).unwrap();
+    assert_eq!(symbol.name(), Some("sub"));
+    assert!(!symbol.exported());
+    assert_eq!(symbol.signature(), Some(Signature { inputs: 2, outputs: 1 }));
+    assert_eq!(
+        listing(&module, 0),
+        vec![
+            "RESERVE_C 3",
+            "SET_64_C 1",
+            "SET_64_C 0",
+            "GET_U64_C 0",
+            "GET_U64_C 1",
+            "SWAP_1",
+            "SUB",
+            "SET_64_C 2",
+            "GET_U64_C 2",
+            "CONST_0",
+            "SUB",
+            "RETURN",
+        ]
+    );
+}
+
+#[test]
+fn test_control_flow() {
+    let module = lang::compile(
+        "m",
+        "export fn count(n) {
+            while n > 0 && !(n == 5) {
+                n = n - 1;
+            }
+            if n < 0 { return 1; } else if n { return 2; }
+        }",
+    )
+    .unwrap();
+    assert_eq!(
+        listing(&module, 0),
+        vec![
+            "RESERVE_C 1",
+            "SET_64_C 0",
+            // n > 0
+            "GET_U64_C 0",
+            "CONST_0",
+            "JMP_LTS.C16.REL +3",
+            "CONST_0",
+            "JMP.C16.REL +1",
+            "CONST_1",
+            // &&
+            "JMP_Z.C16.REL +21",
+            // !(n == 5)
+            "GET_U64_C 0",
+            "CONST_U16 5",
+            "JMP_EQ.C16.REL +3",
+            "CONST_0",
+            "JMP.C16.REL +1",
+            "CONST_1",
+            "JMP_Z.C16.REL +3",
+            "CONST_0",
+            "JMP.C16.REL +1",
+            "CONST_1",
+            "JMP_Z.C16.REL +3",
+            "CONST_1",
+            "JMP.C16.REL +1",
+            "CONST_0",
+            // while
+            "JMP_Z.C16.REL +9",
+            "GET_U64_C 0",
+            "CONST_1",
+            "SWAP_1",
+            "SUB",
+            "SET_64_C 0",
+            "JMP.C16.REL -44",
+            // if n < 0
+            "GET_U64_C 0",
+            "CONST_0",
+            "JMP_GTS.C16.REL +3",
+            "CONST_0",
+            "JMP.C16.REL +1",
+            "CONST_1",
+            "JMP_Z.C16.REL +4",
+            "CONST_1",
+            "RETURN",
+            "JMP.C16.REL +6",
+            // else if n
+            "GET_U64_C 0",
+            "JMP_Z.C16.REL +2",
+            "CONST_2",
+            "RETURN",
+            "CONST_0",
+            "RETURN",
+        ]
+    );
+}
+
+#[test]
+fn test_calls_and_imports() {
+    let module = lang::compile(
+        "app",
+        "export fn main() {
+            host.io.log(twice(4));
+            return host.io.log(1);
+        }
+        fn twice(x) { return x + x; }",
+    )
+    .unwrap();
+    assert_eq!(module.symbol(1).unwrap().name(), Some("twice"));
+    assert_eq!(module.external_symbols.len(), 1);
+    let ext = &module.external_symbols[0];
+    assert_eq!(&module.strings[ext.module_name_id as usize], "host.io");
+    assert_eq!(&module.strings[ext.symbol_name_id as usize], "log");
+    assert_eq!(ext.signature, Some(Signature { inputs: 1, outputs: 1 }));
+    assert_eq!(
+        listing(&module, 0),
+        vec!["CONST_4", "CALL_C 1", "CALL_EXT_C 0", "POP_1", "CONST_1", "CALL_EXT_C 0", "RETURN"]
+    );
+    assert_eq!(
+        module.relocations,
+        vec![
+            Relocation { offset: 2, words: 1, kind: RelocationKind::LocalSymbol },
+            Relocation { offset: 4, words: 1, kind: RelocationKind::ExternalSymbol },
+            Relocation { offset: 8, words: 1, kind: RelocationKind::ExternalSymbol },
+        ]
+    );
+}
+
+#[test]
+fn test_compile_errors() {
+    let error = |source: &str| lang::compile("m", source).unwrap_err();
+
+    assert!(matches!(
+        error("fn f() {\n  return x;\n}"),
+        CompileError::UndefinedName(2, name) if name == "x"
+    ));
+    assert!(matches!(
+        error("fn f() { g(); }"),
+        CompileError::UndefinedName(1, name) if name == "g"
+    ));
+    assert!(matches!(
+        error("fn f() {}\nfn f() {}"),
+        CompileError::DuplicateName(2, name) if name == "f"
+    ));
+    assert!(matches!(
+        error("fn f(a, a) {}"),
+        CompileError::DuplicateName(1, name) if name == "a"
+    ));
+    assert!(matches!(
+        error("fn f() { let a = 1; let a = 2; }"),
+        CompileError::DuplicateName(1, name) if name == "a"
+    ));
+    assert!(lang::compile("m", "fn f() { let a = 1; if a { let a = 2; } }").is_ok());
+    assert!(matches!(error("fn f(a) { f(); }"), CompileError::InvalidSyntax(1, _)));
+    assert!(matches!(error("fn f() { m.g(1); m.g(); }"), CompileError::InvalidSyntax(1, _)));
+    assert!(matches!(
+        error("fn f() {\n  let = 1;\n}"),
+        CompileError::InvalidSyntax(2, message) if message == "expected a name, found `=`"
+    ));
+    assert!(matches!(error("fn f() {\n  1 $ 2;\n}"), CompileError::InvalidSyntax(2, _)));
+    assert!(matches!(error("fn f() {"), CompileError::InvalidSyntax(1, _)));
+}
+
+#[test]
+fn test_nesting_limit() {
+    let nested = |depth: usize| {
+        let source = format!("fn f() {{ return {}1{}; }}", "(".repeat(depth), ")".repeat(depth));
+        lang::compile("m", &source)
+    };
+    assert!(nested(127).is_ok());
+    assert!(matches!(nested(10_000).unwrap_err(), CompileError::Unsupported(1, _)));
+
+    let error = |source: String| lang::compile("m", &source).unwrap_err();
+    assert!(matches!(
+        error(format!("fn f() {{ return {}1; }}", "-!".repeat(5_000))),
+        CompileError::Unsupported(1, message) if message == "nesting deeper than 128 levels"
+    ));
+    assert!(matches!(
+        error(format!("fn f() {{ return 1{}; }}", " + 1".repeat(10_000))),
+        CompileError::Unsupported(1, _)
+    ));
+    assert!(matches!(
+        error(format!("fn f() {{ return {}1{}; }}", "f(".repeat(10_000), ")".repeat(10_000))),
+        CompileError::Unsupported(1, _)
+    ));
+    assert!(matches!(
+        error(format!("fn f() {{ {}{} }}", "while 1 { ".repeat(10_000), "}".repeat(10_000))),
+        CompileError::Unsupported(1, _)
+    ));
+    assert!(matches!(
+        error(format!("fn f() {{ if 1 {{}}{} }}", " else if 1 {}".repeat(10_000))),
+        CompileError::Unsupported(1, _)
+    ));
+}
@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use tstack::module::{ExternalSymbol, LocalSymbol, Module, Relocation, RelocationKind, Signature};
+use tstack::version::{Version, VersionReq};
+
+fn library(name: &str, symbols: &[&str]) -> Module {
+    Module {
+        name: String::from(name),
+        version: Default::default(),
+        strings: symbols.iter().map(|s| String::from(*s)).collect(),
+        data: vec![],
+        writable_data: vec![],
+        local_symbols: (0..symbols.len())
+            .map(|i| LocalSymbol {
+                name_id: i as u32,
+                code_offset: i as u32,
+                exported: true,
+                signature: None,
+            })
+            .collect(),
+        external_symbols: vec![],
+        globals: vec![],
+        external_globals: vec![],
+        bytecode: symbols.iter().map(|_| tstack::inst_sys!(NOP)).collect(),
+        relocations: vec![],
+        symbol_lookup: HashMap::new(),
+        debug: None,
+    }
+}
+
+fn importer(name: &str, module: &str, symbol: &str) -> Module {
+    Module {
+        name: String::from(name),
+        version: Default::default(),
+        strings: vec![String::from("main"), String::from(module), String::from(symbol)].into(),
+        data: vec![],
+        writable_data: vec![],
+        local_symbols: vec![LocalSymbol {
+            name_id: 0,
+            code_offset: 0,
+            exported: true,
+            signature: None,
+        }],
+        external_symbols: vec![ExternalSymbol {
+            module_name_id: 1,
+            module_id: 0,
+            symbol_name_id: 2,
+            symbol_id: 0,
+            signature: None,
+            version_req: None,
+            alias: None,
+        }],
+        globals: vec![],
+        external_globals: vec![],
+        bytecode: vec![tstack::inst_sys!(NOP)].into(),
+        relocations: vec![],
+        symbol_lookup: HashMap::new(),
+        debug: None,
+    }
+}
+
+#[test]
+fn test_link_resolves_external_symbols() {
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(importer("main", "lib", "b"))).unwrap();
+    engine.add_module(Rc::new(library("lib", &["a", "b"]))).unwrap();
+    engine.link().unwrap();
+
+    let ext = &engine.modules[0].external_symbols[0];
+    assert_eq!(ext.module_id, 1);
+    assert_eq!(ext.symbol_id, 1);
+}
+
+#[test]
+fn test_link_unknown_module() {
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(importer("main", "lib", "a"))).unwrap();
+    match engine.link() {
+        Err(tstack::errors::ModuleError::UnknownModule(name)) => assert_eq!(name, "lib"),
+        _ => panic!("expected unknown module error"),
+    }
+}
+
+#[test]
+fn test_link_reports_all_unresolved_imports() {
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(importer("main", "lib", "c"))).unwrap();
+    engine.add_module(Rc::new(importer("tool", "other", "a"))).unwrap();
+    engine.add_module(Rc::new(library("lib", &["a", "b"]))).unwrap();
+    let err = engine.link().unwrap_err();
+    match &err {
+        tstack::errors::ModuleError::UnresolvedImports(errors) => {
+            assert_eq!(errors.len(), 2);
+            assert_eq!(errors[0].0, "main");
+            assert_eq!(errors[1].0, "tool");
+        }
+        _ => panic!("expected unresolved imports error"),
+    }
+    assert_eq!(
+        err.to_string(),
+        "2 unresolved imports\n  in module main: unresolved symbol c in module lib\n  in \
+         module tool: module other is not loaded"
+    );
+    assert_eq!(engine.modules[0].external_symbols[0].symbol_id, 0);
+}
+
+#[test]
+fn test_link_dependency_cycle() {
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(importer("main", "a", "main"))).unwrap();
+    engine.add_module(Rc::new(importer("a", "b", "main"))).unwrap();
+    engine.add_module(Rc::new(importer("b", "a", "main"))).unwrap();
+    let err = engine.link().unwrap_err();
+    match &err {
+        tstack::errors::ModuleError::DependencyCycle(path) => assert_eq!(path, &["a", "b", "a"]),
+        _ => panic!("expected dependency cycle error"),
+    }
+    assert_eq!(err.to_string(), "dependency cycle a -> b -> a");
+    assert_eq!(engine.dependency_graph().unwrap().find_cycle().unwrap(), vec!["a", "b", "a"]);
+}
+
+#[test]
+fn test_link_self_import() {
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(importer("main", "main", "main"))).unwrap();
+    engine.link().unwrap();
+    assert_eq!(engine.dependency_graph().unwrap().find_cycle(), None);
+}
+
+#[test]
+fn test_link_namespaced_modules() {
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(importer("app.main", "std.io", "print"))).unwrap();
+    engine.add_module(Rc::new(library("std.io", &["print"]))).unwrap();
+    engine.link().unwrap();
+    assert_eq!(engine.modules[0].external_symbols[0].module_id, 1);
+
+    for name in ["", "std.", ".io", "std..io", "std io"] {
+        let r = engine.add_module(Rc::new(library(name, &[])));
+        assert!(matches!(r, Err(tstack::errors::ModuleError::InvalidName(n)) if n == name));
+    }
+}
+
+#[test]
+fn test_link_module_alias() {
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(importer("main", "net", "send"))).unwrap();
+    engine.add_module(Rc::new(library("net", &["send"]))).unwrap();
+    engine.add_module(Rc::new(library("net.mock", &["recv", "send"]))).unwrap();
+
+    // The alias takes precedence over the module of the same name
+    engine.alias_module("net", "net.mock").unwrap();
+    engine.link().unwrap();
+    let ext = &engine.modules[0].external_symbols[0];
+    assert_eq!((ext.module_id, ext.symbol_id), (2, 1));
+
+    assert_eq!(engine.remove_alias("net").as_deref(), Some("net.mock"));
+    engine.link().unwrap();
+    let ext = &engine.modules[0].external_symbols[0];
+    assert_eq!((ext.module_id, ext.symbol_id), (1, 0));
+
+    engine.alias_module("net", "net.missing").unwrap();
+    match engine.link() {
+        Err(tstack::errors::ModuleError::UnknownModule(name)) => assert_eq!(name, "net.missing"),
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert!(engine.alias_module("net", "not valid").is_err());
+}
+
+#[test]
+fn test_import_alias() {
+    let mut main = importer("main", "lib", "a");
+    main.strings.push(String::from("local_a"));
+    main.external_symbols[0].alias = Some(3);
+    assert_eq!(main.find_import("local_a"), Some(0));
+    assert_eq!(main.find_import("a"), None);
+    assert_eq!(importer("main", "lib", "a").find_import("a"), Some(0));
+
+    // Aliases are rebased along with the rest of the string table
+    let merged = tstack::linker::merge(&[library("other", &["x"]), main]).unwrap();
+    assert_eq!(merged.find_import("local_a"), Some(0));
+}
+
+#[test]
+fn test_link_unresolved_symbol() {
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(importer("main", "lib", "c"))).unwrap();
+    engine.add_module(Rc::new(library("lib", &["a", "b"]))).unwrap();
+    match engine.link() {
+        Err(tstack::errors::ModuleError::UnresolvedSymbol(module, symbol)) => {
+            assert_eq!(module, "lib");
+            assert_eq!(symbol, "c");
+        }
+        _ => panic!("expected unresolved symbol error"),
+    }
+}
+
+#[test]
+fn test_replace_module_relinks_dependents() {
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(importer("main", "lib", "b"))).unwrap();
+    engine.add_module(Rc::new(library("lib", &["a", "b"]))).unwrap();
+    engine.link().unwrap();
+
+    let id = engine.replace_module("lib", Rc::new(library("lib", &["b", "c", "a"]))).unwrap();
+    assert_eq!(id, 1);
+    assert_eq!(engine.modules[1].local_symbols.len(), 3);
+    assert_eq!(engine.modules[0].external_symbols[0].symbol_id, 0);
+}
+
+#[test]
+fn test_replace_module_failure_is_atomic() {
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(importer("main", "lib", "b"))).unwrap();
+    engine.add_module(Rc::new(library("lib", &["a", "b"]))).unwrap();
+    engine.link().unwrap();
+
+    let r = engine.replace_module("lib", Rc::new(library("lib", &["a"])));
+    assert!(r.is_err());
+    assert_eq!(engine.modules[1].local_symbols.len(), 2);
+    assert_eq!(engine.modules[0].external_symbols[0].symbol_id, 1);
+}
+
+#[test]
+fn test_module_verifier() {
+    let trusted = library("lib", &["a"]).content_hash();
+    let mut engine = tstack::Engine::new();
+    engine.set_module_verifier(move |_, hash| {
+        if *hash == trusted {
+            Ok(())
+        } else {
+            Err(String::from("untrusted"))
+        }
+    });
+
+    let r = engine.add_module(Rc::new(library("other", &["a"])));
+    assert!(matches!(r, Err(tstack::errors::ModuleError::VerificationFailed(_, _))));
+    assert!(engine.modules.is_empty());
+    engine.add_module(Rc::new(library("lib", &["a"]))).unwrap();
+
+    let r = engine.replace_module("lib", Rc::new(library("lib", &["a", "b"])));
+    assert!(matches!(r, Err(tstack::errors::ModuleError::VerificationFailed(_, _))));
+    assert_eq!(engine.modules[0].local_symbols.len(), 1);
+}
+
+#[test]
+fn test_add_modules() {
+    let mut engine = tstack::Engine::new();
+    let ids = engine
+        .add_modules(vec![
+            Rc::new(importer("main", "lib", "b")),
+            Rc::new(library("lib", &["a", "b"])),
+        ])
+        .unwrap();
+    assert_eq!(ids, vec![0, 1]);
+    engine.link().unwrap();
+    assert_eq!(engine.modules[0].external_symbols[0].symbol_id, 1);
+    assert_eq!(engine.find_symbol("lib", "b").map(|f| f.symbol_id), Some(1));
+
+    let r = engine
+        .add_modules(vec![Rc::new(library("other", &["a"])), Rc::new(library("other", &["b"]))]);
+    assert!(matches!(r, Err(tstack::errors::ModuleError::NameCollision(_))));
+    let mut broken = library("broken", &["a"]);
+    broken.local_symbols[0].name_id = 9;
+    let r = engine.add_modules(vec![Rc::new(library("other", &["a"])), Rc::new(broken)]);
+    assert!(r.is_err());
+    assert_eq!(engine.modules.len(), 2);
+    assert!(engine.module("other").is_none());
+}
+
+#[test]
+fn test_replace_module_unknown() {
+    let mut engine = tstack::Engine::new();
+    let r = engine.replace_module("lib", Rc::new(library("lib", &["a"])));
+    assert!(matches!(r, Err(tstack::errors::ModuleError::UnknownModule(_))));
+}
+
+#[test]
+fn test_link_private_symbol() {
+    let mut lib = library("lib", &["a", "b"]);
+    lib.local_symbols[1].exported = false;
+
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(importer("main", "lib", "b"))).unwrap();
+    engine.add_module(Rc::new(lib)).unwrap();
+    assert!(matches!(engine.link(), Err(tstack::errors::ModuleError::PrivateSymbol(_, _))));
+}
+
+#[test]
+fn test_link_signature_mismatch() {
+    let mut lib = library("lib", &["a"]);
+    lib.local_symbols[0].signature = Some(Signature { inputs: 2, outputs: 1 });
+    let mut main = importer("main", "lib", "a");
+    main.external_symbols[0].signature = Some(Signature { inputs: 1, outputs: 1 });
+
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(main)).unwrap();
+    engine.add_module(Rc::new(lib)).unwrap();
+    assert!(matches!(engine.link(), Err(tstack::errors::ModuleError::SignatureMismatch(_, _))));
+}
+
+#[test]
+fn test_link_signature_match() {
+    let mut lib = library("lib", &["a"]);
+    lib.local_symbols[0].signature = Some(Signature { inputs: 2, outputs: 1 });
+    let mut main = importer("main", "lib", "a");
+    main.external_symbols[0].signature = Some(Signature { inputs: 2, outputs: 1 });
+
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(main)).unwrap();
+    engine.add_module(Rc::new(lib)).unwrap();
+    engine.link().unwrap();
+}
+
+#[test]
+fn test_link_version_requirement() {
+    let mut lib = library("lib", &["a"]);
+    lib.version = Version::new(1, 4, 0);
+    let mut main = importer("main", "lib", "a");
+    main.external_symbols[0].version_req = VersionReq::parse("^1.2");
+
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(main)).unwrap();
+    engine.add_module(Rc::new(lib)).unwrap();
+    engine.link().unwrap();
+}
+
+#[test]
+fn test_link_version_mismatch() {
+    let mut lib = library("lib", &["a"]);
+    lib.version = Version::new(2, 0, 0);
+    let mut main = importer("main", "lib", "a");
+    main.external_symbols[0].version_req = VersionReq::parse("^1.2");
+
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(main)).unwrap();
+    engine.add_module(Rc::new(lib)).unwrap();
+    match engine.link() {
+        Err(tstack::errors::ModuleError::VersionMismatch(module, req, version)) => {
+            assert_eq!(module, "lib");
+            assert_eq!(req, "^1.2");
+            assert_eq!(version, "2.0.0");
+        }
+        _ => panic!("expected version mismatch error"),
+    }
+}
+
+#[test]
+fn test_merge_applies_relocations() {
+    let mut a = library("a", &["first"]);
+    a.bytecode = vec![tstack::inst_stack!(CONST_U16), 0x0000, tstack::inst_sys!(NOP)].into();
+    a.relocations = vec![Relocation { offset: 1, words: 1, kind: RelocationKind::Code }];
+
+    let mut b = library("b", &["second"]);
+    b.bytecode =
+        vec![tstack::inst_stack!(CONST_U32), 0x0000, 0x0001, tstack::inst_stack!(CONST_U16), 0]
+            .into();
+    b.data = vec![42];
+    b.relocations = vec![
+        Relocation { offset: 1, words: 2, kind: RelocationKind::Code },
+        Relocation { offset: 4, words: 1, kind: RelocationKind::Data },
+    ];
+    a.data = vec![7, 8];
+
+    let merged = tstack::linker::merge(&[a, b]).unwrap();
+    assert_eq!(merged.name, "a");
+    assert_eq!(
+        merged.bytecode,
+        vec![
+            tstack::inst_stack!(CONST_U16),
+            0x0000,
+            tstack::inst_sys!(NOP),
+            tstack::inst_stack!(CONST_U32),
+            0x0000,
+            0x0004,
+            tstack::inst_stack!(CONST_U16),
+            2,
+        ]
+    );
+    assert_eq!(merged.data, vec![7, 8, 42]);
+    assert_eq!(merged.strings, vec![String::from("first"), String::from("second")]);
+    assert_eq!(merged.local_symbols[1].code_offset, 3);
+    assert_eq!(merged.local_symbols[1].name_id, 1);
+    assert_eq!(merged.relocations[1].offset, 4);
+    assert_eq!(merged.relocations[2].offset, 7);
+}
+
+#[test]
+fn test_merge_internal_imports() {
+    let main = importer("main", "lib", "b");
+    let lib = library("lib", &["a", "b"]);
+    let merged = tstack::linker::merge(&[main, lib]).unwrap();
+
+    let ext = &merged.external_symbols[0];
+    assert_eq!(&merged.strings[ext.module_name_id as usize], "main");
+    assert_eq!(&merged.strings[ext.symbol_name_id as usize], "b");
+
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(merged)).unwrap();
+    engine.link().unwrap();
+    assert_eq!(engine.modules[0].external_symbols[0].symbol_id, 2);
+}
+
+#[test]
+fn test_merge_private_symbols() {
+    // `a` has a private symbol with the name of the one it imports from `b`
+    let mut a = importer("a", "b", "helper");
+    a.strings.push(String::from("helper"));
+    a.local_symbols.push(LocalSymbol {
+        name_id: 3,
+        code_offset: 0,
+        exported: false,
+        signature: None,
+    });
+    a.symbol_lookup = HashMap::from([(String::from("main"), 0), (String::from("helper"), 1)]);
+    let b = library("b", &["helper"]);
+    let merged = tstack::linker::merge(&[a, b]).unwrap();
+    assert_eq!(merged.find_symbol("helper"), Some(2));
+    assert_eq!(merged.symbol_lookup.len(), 2);
+
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(merged)).unwrap();
+    engine.link().unwrap();
+    assert_eq!(engine.modules[0].external_symbols[0].symbol_id, 2);
+}
+
+#[test]
+fn test_merge_keeps_outside_imports() {
+    let main = importer("main", "other", "b");
+    let lib = library("lib", &["a"]);
+    let merged = tstack::linker::merge(&[main, lib]).unwrap();
+    let ext = &merged.external_symbols[0];
+    assert_eq!(&merged.strings[ext.module_name_id as usize], "other");
+}
+
+#[test]
+fn test_merge_duplicate_symbol() {
+    let a = library("a", &["f", "g"]);
+    let b = library("b", &["g"]);
+    match tstack::linker::merge(&[a, b]) {
+        Err(tstack::errors::ModuleError::DuplicateSymbol(name)) => assert_eq!(name, "g"),
+        _ => panic!("expected duplicate symbol error"),
+    }
+}
+
+#[test]
+fn test_merge_relocation_overflow() {
+    let a = library("a", &["f"]);
+    let mut b = library("b", &["g"]);
+    b.bytecode = vec![tstack::inst_stack!(CONST_U16), 0xFFFF].into();
+    b.relocations = vec![Relocation { offset: 1, words: 1, kind: RelocationKind::Code }];
+    assert!(matches!(
+        tstack::linker::merge(&[a, b]),
+        Err(tstack::errors::ModuleError::RelocationOverflow(1))
+    ));
+}
+
+#[test]
+fn test_merge_invalid_relocation() {
+    let mut a = library("a", &["f"]);
+    a.relocations = vec![Relocation { offset: 1, words: 2, kind: RelocationKind::String }];
+    assert!(matches!(
+        tstack::linker::merge(&[a]),
+        Err(tstack::errors::ModuleError::InvalidRelocation(1))
+    ));
+}
+
+#[test]
+fn test_merge_relocation_wraps() {
+    let a = library("a", &["f"]);
+    let mut b = library("b", &["g"]);
+    b.bytecode = vec![tstack::inst_stack!(CONST_U64), 0xFFFF, 0xFFFF, 0xFFFF, 0xFFFF].into();
+    b.relocations = vec![Relocation { offset: 1, words: 4, kind: RelocationKind::Code }];
+    assert!(matches!(
+        tstack::linker::merge(&[a, b]),
+        Err(tstack::errors::ModuleError::InvalidRelocation(1))
+    ));
+}
+
+#[test]
+fn test_merge_empty() {
+    assert!(matches!(tstack::linker::merge(&[]), Err(tstack::errors::ModuleError::NoModules)));
+}
@@ -0,0 +1,85 @@
+use std::path::Path;
+
+use tstack::asm;
+use tstack::diagnostics::{DiagnosticKind, Severity};
+use tstack::lint::lint;
+
+fn warnings(source: &str) -> Vec<String> {
+    let module = asm::assemble("main", source, Path::new(".")).unwrap();
+    let diagnostics = lint(&module);
+    assert!(diagnostics.iter().all(|d| d.severity == Severity::Warning));
+    diagnostics.iter().map(|d| d.render(&module)).collect()
+}
+
+#[test]
+fn test_unreachable_code() {
+    assert_eq!(
+        warnings(
+            ".export main
+                CONST_1
+                JMP_NZ.REL done
+                JMP.REL done
+                CONST_2
+                CONST_3
+            done:
+                RETURN
+                CONST_4
+            .func helper
+                RETURN"
+        ),
+        vec![
+            "main:5: warning: 2 instructions after JMP.C16.REL +2 can never run",
+            "main:8: warning: 1 instruction after RETURN can never run",
+        ]
+    );
+
+    // Code after a jump to a dynamic target may be reached by any jump
+    assert!(warnings(".export main\n CONST_2\n JMP.DYN.REL\n CONST_1\n RETURN").is_empty());
+}
+
+#[test]
+fn test_unused_value() {
+    assert_eq!(
+        warnings(
+            ".export main
+                CONST_1
+                CONST_2
+                DUPE_1
+                POP_C 2
+                CONST_3
+                CALL_C main
+                POP_1
+                ADD
+                POP_1
+                RETURN"
+        ),
+        vec![
+            "main:1: warning: the value pushed by CONST_2 is never used",
+            "main:2: warning: the value pushed by DUPE_1 is never used",
+            "main:9: warning: the value pushed by ADD is never used",
+        ]
+    );
+}
+
+#[test]
+fn test_divide_by_zero() {
+    let module = asm::assemble(
+        "main",
+        ".export main
+            CONST_0
+            CONST_4
+            DIV
+            DIV_C 0
+            CONST_4
+            CONST_0
+            DIV
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    let found: Vec<_> = lint(&module).iter().map(|d| (d.kind, d.offset)).collect();
+    assert_eq!(
+        found,
+        vec![(DiagnosticKind::DivideByZero, Some(2)), (DiagnosticKind::DivideByZero, Some(3))]
+    );
+}
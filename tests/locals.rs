@@ -0,0 +1,131 @@
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::errors::{BytecodeError, Fault};
+use tstack::verify;
+
+fn engine(source: &str) -> tstack::Engine {
+    let module = asm::assemble("main", source, Path::new(".")).unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine
+}
+
+#[test]
+fn test_packed_locals() {
+    let mut engine = engine(
+        ".export main
+            RESERVE_C 2
+            CONST_N1
+            SET_8_C 1
+            GET_U8_C 1
+            GET_I8_C 1
+            GET_U64_C 0
+            CONST_U64 0x3ff8000000000000
+            SET_F32_C 3
+            GET_F32_C 3
+            GET_U64_C 1
+            RETURN",
+    );
+    engine.run(0, 0).unwrap();
+    assert_eq!(
+        engine.stack,
+        vec![0xff, -1i64 as u64, 0xff00, 1.5f64.to_bits(), (1.5f32.to_bits() as u64) << 32]
+    );
+}
+
+#[test]
+fn test_frame_locals() {
+    let mut engine = engine(
+        ".export main
+            RESERVE_C 1
+            CONST_U16 5
+            SET_64_C 0
+            CALL_C helper
+            GET_U64_C 0
+            RETURN
+        .func helper
+            RESERVE_C 3
+            GET_U64_C 0
+            CONST_U16 9
+            SET_64_C 2
+            RETURN
+        .export halt
+            RESERVE_C 1
+            CONST_U16 9
+            SET_64_C 0
+            CONST_0
+            HALT",
+    );
+    // Each call starts with its own zeroed locals, released when it returns
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![0, 5]);
+
+    engine.stack.clear();
+    engine.run(0, 2).unwrap();
+    assert_eq!(engine.locals(), &[9]);
+}
+
+#[test]
+fn test_invalid_locals() {
+    let mut engine = engine(
+        ".export get
+            RESERVE_C 1
+            GET_U32_C 2
+            RETURN
+        .export reserve
+            RESERVE_C 200
+            RESERVE_C 56
+            RETURN
+        .export release
+            RESERVE_C 1
+            CONST_N1
+            RESERVE_N
+            CONST_N1
+            RESERVE_N
+            RETURN",
+    );
+    let error = |engine: &mut tstack::Engine, symbol| {
+        engine.run(0, symbol).map_err(Fault::into_error).unwrap_err()
+    };
+    assert!(matches!(error(&mut engine, 0), BytecodeError::InvalidLocal(2)));
+    assert!(matches!(error(&mut engine, 1), BytecodeError::InvalidReserve(256)));
+    assert!(matches!(error(&mut engine, 2), BytecodeError::InvalidReserve(-1)));
+    assert!(engine.locals().is_empty());
+}
+
+#[test]
+fn test_proven_locals() {
+    let source = ".export main
+            RESERVE_C 2
+            CONST_U16 7
+            SET_16_C 7
+            CALL_C helper
+            GET_U16_C 7
+            RESERVE_C 65535
+            RETURN
+        .func helper
+            RESERVE_C 3
+            CONST_U16 9
+            SET_64_C 2
+            CONST_0
+            GET_U64
+            RETURN
+        .export past
+            RESERVE_C 1
+            RESERVE_C 65535
+            GET_U8_C 0
+            RETURN";
+    let module = asm::assemble("main", source, Path::new(".")).unwrap();
+    // Symbols accessing indices from the stack or locals they may not have
+    // reserved are left to be checked as they run
+    assert_eq!(verify::max_locals(&module), vec![Some(2), None, None]);
+
+    let mut engine = engine(source);
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![0, 7]);
+    assert_eq!(engine.locals(), &[0]);
+    let error = engine.run(0, 2).map_err(Fault::into_error).unwrap_err();
+    assert!(matches!(error, BytecodeError::InvalidLocal(0)));
+}
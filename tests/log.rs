@@ -0,0 +1,61 @@
+#![cfg(feature = "log")]
+
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Mutex;
+
+use log::{Level, Log, Metadata, Record};
+use tstack::asm;
+
+/// A logger recording the messages of the engine
+struct Recorder(Mutex<Vec<(Level, String)>>);
+
+impl Log for Recorder {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target() == "tstack"
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.0.lock().unwrap().push((record.level(), record.args().to_string()));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static RECORDER: Recorder = Recorder(Mutex::new(Vec::new()));
+
+#[test]
+fn test_log_records() {
+    log::set_logger(&RECORDER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let module = asm::assemble(
+        "main",
+        ".export main\n  CONST_1\n  RETURN\n.export broken\n  ADD\n  RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+    engine.run(0, 0).unwrap();
+    engine.run(0, 1).unwrap_err();
+    let importer =
+        asm::assemble("importer", ".export main\n  CALL_EXT_C missing.f\n  RETURN", Path::new("."))
+            .unwrap();
+    engine.add_module(Rc::new(importer)).unwrap();
+    engine.link().unwrap_err();
+
+    let records = RECORDER.0.lock().unwrap();
+    let find = |level: Level, text: &str| {
+        records.iter().any(|(l, message)| *l == level && message.starts_with(text))
+    };
+    assert!(find(Level::Debug, "loaded module main as ID 0"));
+    assert!(find(Level::Debug, "linked 1 modules"));
+    assert!(find(Level::Trace, "module 0 offset 0: 0x0101"));
+    assert!(find(Level::Trace, "module 0 offset 1: 0x0502"));
+    assert!(find(Level::Debug, "fault running symbol 1 of module 0: too few operands"));
+    assert!(find(Level::Warn, "linking failed: "));
+}
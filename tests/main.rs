@@ -1,5 +1,3 @@
-use tstack;
-
 use std::collections::HashMap;
 use std::rc::Rc;
 
@@ -10,22 +8,37 @@ macro_rules! stack {
     }}
 }
 
-fn test_stack(bytecode: &[u16], expected: Vec<u64>) {
-    let mut engine = tstack::Engine::new();
-    let r = engine.add_module(Rc::new(tstack::module::Module {
+fn test_module(bytecode: &[u16]) -> tstack::module::Module {
+    tstack::module::Module {
         name: String::from("testmain"),
-        strings: vec![String::from("main")],
+        version: Default::default(),
+        strings: vec![String::from("main")].into(),
         data: vec![],
-        local_symbols: vec![tstack::module::LocalSymbol { name_id: 0, code_offset: 0 }],
+        writable_data: vec![],
+        local_symbols: vec![tstack::module::LocalSymbol {
+            name_id: 0,
+            code_offset: 0,
+            exported: true,
+            signature: None,
+        }],
         external_symbols: vec![],
-        bytecode: bytecode.to_vec(),
+        globals: vec![],
+        external_globals: vec![],
+        bytecode: bytecode.to_vec().into(),
+        relocations: vec![],
         symbol_lookup: HashMap::new(),
-    }));
+        debug: None,
+    }
+}
+
+fn test_stack(bytecode: &[u16], expected: Vec<u64>) {
+    let mut engine = tstack::Engine::new();
+    let r = engine.add_module(Rc::new(test_module(bytecode)));
     if let Err(e) = r {
-        assert!(false, "Unexpected error adding test module: {}", e);
+        panic!("Unexpected error adding test module: {}", e);
     }
     if let Err(e) = engine.run(0, 0) {
-        assert!(false, "Unexpected error: {}", e);
+        panic!("Unexpected error: {}", e);
     }
     assert_eq!(engine.stack, expected);
 }
@@ -39,25 +52,17 @@ fn test_fail(
     if let Some(initfn) = init {
         initfn(&mut engine);
     }
-    let r = engine.add_module(Rc::new(tstack::module::Module {
-        name: String::from("testmain"),
-        strings: vec![String::from("main")],
-        data: vec![],
-        local_symbols: vec![tstack::module::LocalSymbol { name_id: 0, code_offset: 0 }],
-        external_symbols: vec![],
-        bytecode: bytecode.to_vec(),
-        symbol_lookup: HashMap::new(),
-    }));
+    let r = engine.add_module(Rc::new(test_module(bytecode)));
     if let Err(e) = r {
-        assert!(false, "Unexpected error adding test module: {}", e);
+        panic!("Unexpected error adding test module: {}", e);
     }
     if let Err(e) = engine.run(0, 0) {
         if let Some(errfn) = errcheck {
-            assert!(errfn(e), "incorrect error");
+            assert!(errfn(e.into_error()), "incorrect error");
         }
         return;
     }
-    assert!(false, "error expected");
+    panic!("error expected");
 }
 
 #[test]
@@ -74,6 +79,19 @@ fn test_const_0_stack_overflow() {
     );
 }
 
+#[test]
+fn test_const_0_stack_over_limit() {
+    // A stack already deeper than the limit faults rather than panicking
+    test_fail(
+        Some(|engine| {
+            engine.maxstack = 1;
+            engine.stack = stack![1, 2];
+        }),
+        Some(|bce| bce.is_stack_overflow()),
+        &[tstack::inst_stack!(CONST_0)],
+    );
+}
+
 #[test]
 fn test_const_1() {
     test_stack(&[tstack::inst_stack!(CONST_1)], stack![1]);
@@ -270,3 +288,606 @@ fn test_dupe_c_insufficient_args() {
         &[tstack::inst_stack!(CONST_8), tstack::inst_stack!(DUPE_C), 4],
     );
 }
+
+#[test]
+fn test_swap() {
+    test_stack(
+        &[
+            tstack::inst_stack!(CONST_0),
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_stack!(SWAP),
+        ],
+        stack![0, 3, 2, 1],
+    );
+}
+
+#[test]
+fn test_swap_1() {
+    test_stack(
+        &[tstack::inst_stack!(CONST_1), tstack::inst_stack!(CONST_2), tstack::inst_stack!(SWAP_1)],
+        stack![2, 1],
+    );
+    test_fail(
+        None,
+        Some(|e| e.is_stack_underflow()),
+        &[tstack::inst_stack!(CONST_1), tstack::inst_stack!(SWAP_1)],
+    );
+}
+
+#[test]
+fn test_swap_c() {
+    test_stack(
+        &[
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_stack!(SWAP_C),
+            3,
+        ],
+        stack![3, 2, 1],
+    );
+}
+
+#[test]
+fn test_rotate() {
+    test_stack(
+        &[
+            tstack::inst_stack!(CONST_0),
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_stack!(ROTATE),
+        ],
+        stack![0, 2, 3, 1],
+    );
+    test_stack(
+        &[
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(ROTATE_C),
+            3,
+        ],
+        stack![1, 2, 3],
+    );
+}
+
+#[test]
+fn test_rotate_1() {
+    test_stack(
+        &[
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_stack!(ROTATE_1),
+        ],
+        stack![3, 1, 2],
+    );
+    test_stack(
+        &[
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_stack!(ROTATE_1_C),
+            2,
+        ],
+        stack![1, 3, 2],
+    );
+}
+
+#[test]
+fn test_rotate_invalid_position() {
+    use tstack::errors::BytecodeError;
+
+    test_fail(
+        None,
+        Some(|e| matches!(e, BytecodeError::InvalidRotation(3))),
+        &[
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_stack!(ROTATE_C),
+            2,
+        ],
+    );
+    test_fail(
+        None,
+        Some(|e| matches!(e, BytecodeError::InvalidRotation(0))),
+        &[
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(CONST_0),
+            tstack::inst_stack!(ROTATE_C),
+            1,
+        ],
+    );
+}
+
+#[test]
+fn test_pop() {
+    test_stack(
+        &[
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_stack!(POP),
+        ],
+        stack![1],
+    );
+    test_stack(
+        &[
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_stack!(POP_1),
+            tstack::inst_stack!(POP_C),
+            1,
+        ],
+        stack![1],
+    );
+    test_fail(
+        None,
+        Some(|e| e.is_stack_underflow()),
+        &[tstack::inst_stack!(CONST_1), tstack::inst_stack!(POP_C), 2],
+    );
+}
+
+#[test]
+fn test_run_private_symbol() {
+    let mut module = test_module(&[tstack::inst_stack!(CONST_1)]);
+    module.local_symbols[0].exported = false;
+
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    let fault = engine.run(0, 0).unwrap_err();
+    assert!(matches!(fault.error(), tstack::errors::BytecodeError::PrivateSymbol(0)));
+    assert!(engine.stack.is_empty());
+}
+
+#[test]
+fn test_run_signature() {
+    let mut module = test_module(&[tstack::inst_math!(ADD)]);
+    module.local_symbols[0].signature = Some(tstack::module::Signature { inputs: 2, outputs: 1 });
+
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.stack = stack![7, 3, 4];
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, stack![7, 7]);
+}
+
+#[test]
+fn test_run_signature_missing_inputs() {
+    let mut module = test_module(&[tstack::inst_math!(ADD)]);
+    module.local_symbols[0].signature = Some(tstack::module::Signature { inputs: 2, outputs: 1 });
+
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.stack = stack![3];
+    assert!(engine.run(0, 0).unwrap_err().error().is_bad_inputs());
+}
+
+#[test]
+fn test_run_signature_bad_outputs() {
+    let mut module = test_module(&[tstack::inst_stack!(CONST_1), tstack::inst_stack!(CONST_2)]);
+    module.local_symbols[0].signature = Some(tstack::module::Signature { inputs: 0, outputs: 1 });
+
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    assert!(engine.run(0, 0).unwrap_err().error().is_bad_outputs());
+}
+
+#[test]
+fn test_jump_sources() {
+    test_stack(
+        &[
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_jump!(SRC_C16, MODE_ABSOLUTE),
+            4,
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_jump!(SRC_C32, MODE_RELATIVE),
+            0,
+            1,
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_jump!(SRC_C64, MODE_ABSOLUTE),
+            0,
+            0,
+            0,
+            14,
+            tstack::inst_stack!(CONST_4),
+            tstack::inst_stack!(CONST_U16),
+            19,
+            tstack::inst_jump!(SRC_DYN, MODE_ABSOLUTE),
+            tstack::inst_stack!(CONST_8),
+            tstack::inst_stack!(CONST_8),
+            tstack::inst_stack!(CONST_16),
+            tstack::inst_stack!(CONST_U16),
+            1,
+            tstack::inst_jump!(SRC_DYN, MODE_RELATIVE),
+            tstack::inst_stack!(CONST_32),
+            tstack::inst_stack!(CONST_64),
+        ],
+        stack![1, 16, 64],
+    );
+}
+
+#[test]
+fn test_jump_loop() {
+    // Count down from 5, jumping back while the count is not zero
+    test_stack(
+        &[
+            tstack::inst_stack!(CONST_U16),
+            5,
+            tstack::inst_math!(SUB_C),
+            1,
+            tstack::inst_stack!(DUPE_1),
+            tstack::inst_jump!(SRC_C16, MODE_RELATIVE, TYPE_NZ),
+            -5i16 as u16,
+        ],
+        stack![0],
+    );
+}
+
+#[test]
+fn test_jump_conditions() {
+    use tstack::bytecode::jump;
+
+    let cases: [(u8, u64, u64, bool); 26] = [
+        (jump::TYPE_Z, 0, 0, true),
+        (jump::TYPE_Z, 1, 0, false),
+        (jump::TYPE_NZ, 2, 0, true),
+        (jump::TYPE_POS, 1, 0, true),
+        (jump::TYPE_POS, -1i64 as u64, 0, false),
+        (jump::TYPE_NEG, -1i64 as u64, 0, true),
+        (jump::TYPE_NEG, 0, 0, false),
+        (jump::TYPE_GZ, 0, 0, true),
+        (jump::TYPE_GZ, -1i64 as u64, 0, false),
+        (jump::TYPE_LZ, 0, 0, true),
+        (jump::TYPE_LZ, 1, 0, false),
+        (jump::TYPE_EQ, 3, 3, true),
+        (jump::TYPE_EQ, 3, 4, false),
+        (jump::TYPE_NEQ, 3, 4, true),
+        (jump::TYPE_GT, 4, 3, true),
+        (jump::TYPE_GT, -1i64 as u64, 3, true),
+        (jump::TYPE_GTS, -1i64 as u64, 3, false),
+        (jump::TYPE_LT, 3, 4, true),
+        (jump::TYPE_LTS, -1i64 as u64, 3, true),
+        (jump::TYPE_LT, -1i64 as u64, 3, false),
+        (jump::TYPE_GE, 3, 3, true),
+        (jump::TYPE_GE, 2, 3, false),
+        (jump::TYPE_GES, 3, -1i64 as u64, true),
+        (jump::TYPE_LE, 3, 3, true),
+        (jump::TYPE_LE, 4, 3, false),
+        (jump::TYPE_LES, -1i64 as u64, 0, true),
+    ];
+    for (condition, top, below, expected) in cases {
+        // Push 1 if the jump is taken, and 0 otherwise
+        let bytecode = [
+            tstack::inst_jump!(SRC_C16, MODE_RELATIVE, TYPE_Z) | condition as u16,
+            3,
+            tstack::inst_stack!(CONST_0),
+            tstack::inst_jump!(SRC_C16, MODE_ABSOLUTE),
+            6,
+            tstack::inst_stack!(CONST_1),
+        ];
+        let mut engine = tstack::Engine::new();
+        engine.add_module(Rc::new(test_module(&bytecode))).unwrap();
+        // The top of the stack is `stack[-1]`, and `below` is `stack[-2]`
+        engine.stack = stack![below, top];
+        engine.run(0, 0).unwrap();
+        // Conditions testing one value leave the value below it
+        let result = match condition < jump::TYPE_EQ {
+            true => stack![below, expected as u64],
+            false => stack![expected as u64],
+        };
+        assert_eq!(engine.stack, result, "condition {:#04x} with {} and {}", condition, top, below);
+    }
+}
+
+#[test]
+fn test_jump_faults() {
+    use tstack::errors::BytecodeError;
+
+    test_fail(
+        None,
+        Some(|e| matches!(e, BytecodeError::InvalidAddress(100))),
+        &[tstack::inst_jump!(SRC_C16, MODE_ABSOLUTE), 100],
+    );
+    test_fail(
+        Some(|engine| engine.stack = stack![1]),
+        Some(|e| e.is_stack_underflow()),
+        &[tstack::inst_jump!(SRC_C16, MODE_ABSOLUTE, TYPE_EQ), 0],
+    );
+    // A backwards relative jump past the start of the bytecode
+    test_fail(
+        None,
+        Some(|e| matches!(e, BytecodeError::InvalidAddress(_))),
+        &[tstack::inst_jump!(SRC_C16, MODE_RELATIVE), -5i16 as u16],
+    );
+}
+
+#[test]
+fn test_fpmath() {
+    let float = |v: f64| v.to_bits();
+    let push = |v: f64| {
+        let bits = v.to_bits();
+        [
+            tstack::inst_stack!(CONST_U64),
+            (bits >> 48) as u16,
+            (bits >> 32) as u16,
+            (bits >> 16) as u16,
+            bits as u16,
+        ]
+    };
+    let mut bytecode = vec![];
+    bytecode.extend(push(1.5));
+    bytecode.extend(push(4.0));
+    bytecode.push(tstack::inst_fpmath!(FADD));
+    bytecode.extend(push(1.5));
+    bytecode.extend(push(4.0));
+    bytecode.push(tstack::inst_fpmath!(FSUB));
+    bytecode.extend(push(1.5));
+    bytecode.extend(push(4.0));
+    bytecode.push(tstack::inst_fpmath!(FMUL));
+    bytecode.extend(push(1.5));
+    bytecode.extend(push(6.0));
+    bytecode.push(tstack::inst_fpmath!(FDIV));
+    bytecode.extend(push(2.5));
+    bytecode.push(tstack::inst_fpmath!(FNEG));
+    bytecode.push(tstack::inst_stack!(CONST_N1));
+    bytecode.push(tstack::inst_fpmath!(ITOF));
+    bytecode.extend(push(-2.75));
+    bytecode.push(tstack::inst_fpmath!(FTOI));
+    // The topmost value is the left hand side of binary operations
+    test_stack(
+        &bytecode,
+        stack![
+            float(5.5),
+            float(2.5),
+            float(6.0),
+            float(4.0),
+            float(-2.5),
+            float(-1.0),
+            -2i64 as u64
+        ],
+    );
+}
+
+#[test]
+fn test_return() {
+    test_stack(
+        &[
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_function!(RETURN),
+            tstack::inst_stack!(CONST_2),
+        ],
+        stack![1],
+    );
+    test_fail(None, Some(|e| e.is_bad_opcode()), &[tstack::inst_fpmath!(FTOI) | 0xFF]);
+}
+
+#[test]
+fn test_div() {
+    test_stack(
+        &[
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_stack!(CONST_8),
+            tstack::inst_math!(DIV),
+            tstack::inst_math!(DIV_C),
+            2,
+        ],
+        stack![2],
+    );
+    test_stack(
+        &[tstack::inst_stack!(CONST_3), tstack::inst_stack!(CONST_8), tstack::inst_math!(MOD)],
+        stack![2],
+    );
+}
+
+#[test]
+fn test_div_by_zero() {
+    use tstack::errors::BytecodeError;
+
+    test_fail(
+        None,
+        Some(|bce| matches!(bce, BytecodeError::DivideByZero(_))),
+        &[tstack::inst_stack!(CONST_0), tstack::inst_stack!(CONST_8), tstack::inst_math!(DIV)],
+    );
+    test_fail(
+        None,
+        Some(|bce| matches!(bce, BytecodeError::DivideByZero(_))),
+        &[tstack::inst_stack!(CONST_8), tstack::inst_math!(DIV_C), 0],
+    );
+    test_fail(
+        None,
+        Some(|bce| matches!(bce, BytecodeError::DivideByZero(_))),
+        &[tstack::inst_stack!(CONST_0), tstack::inst_stack!(CONST_8), tstack::inst_math!(MOD)],
+    );
+}
+
+#[test]
+fn test_idiv_imod() {
+    use tstack::config::Overflow;
+    use tstack::errors::BytecodeError;
+
+    // -7 / 2 and -7 % 2, truncating towards zero
+    let operands = [tstack::inst_stack!(CONST_2), tstack::inst_stack!(CONST_I16), -7i16 as u16];
+    test_stack(&[&operands[..], &[tstack::inst_math!(IDIV)]].concat(), stack![-3i64 as u64]);
+    test_stack(&[&operands[..], &[tstack::inst_math!(IMOD)]].concat(), stack![-1i64 as u64]);
+    for op in [tstack::inst_math!(IDIV), tstack::inst_math!(IMOD)] {
+        test_fail(
+            None,
+            Some(|bce| matches!(bce, BytecodeError::DivideByZero(_))),
+            &[tstack::inst_stack!(CONST_0), tstack::inst_stack!(CONST_8), op],
+        );
+    }
+
+    // i64::MIN / -1 overflows, while i64::MIN % -1 is 0
+    let mut min = vec![tstack::inst_stack!(CONST_N1), tstack::inst_stack!(CONST_U64)];
+    tstack::bytecode::encode_u64(&mut min, i64::MIN as u64);
+    let idiv = [&min[..], &[tstack::inst_math!(IDIV)]].concat();
+    test_stack(&[&min[..], &[tstack::inst_math!(IMOD)]].concat(), stack![0]);
+    test_stack(&idiv, stack![i64::MIN as u64]);
+    let mut engine = tstack::Engine::new();
+    engine.overflow = Overflow::Saturate;
+    engine.add_module(Rc::new(test_module(&idiv))).unwrap();
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![i64::MAX as u64]);
+    test_fail(
+        Some(|engine| engine.overflow = Overflow::Fault),
+        Some(|bce| matches!(bce, BytecodeError::Overflow(_))),
+        &idiv,
+    );
+}
+
+#[test]
+fn test_strict_overflow() {
+    use tstack::errors::BytecodeError;
+
+    let code =
+        [tstack::inst_stack!(CONST_1), tstack::inst_stack!(CONST_N1), tstack::inst_math!(ADD)];
+    test_stack(&code, stack![0]);
+    test_fail(
+        Some(|engine| engine.strict.overflow = true),
+        Some(|bce| matches!(bce, BytecodeError::Overflow(_))),
+        &code,
+    );
+    test_fail(
+        Some(|engine| engine.strict.overflow = true),
+        Some(|bce| matches!(bce, BytecodeError::Overflow(_))),
+        &[tstack::inst_stack!(CONST_0), tstack::inst_math!(SUB_C), 1],
+    );
+}
+
+#[test]
+fn test_overflow_policy() {
+    use tstack::config::Overflow;
+    use tstack::errors::BytecodeError;
+
+    let add =
+        [tstack::inst_stack!(CONST_N1), tstack::inst_stack!(CONST_2), tstack::inst_math!(ADD)];
+    let sub = [tstack::inst_stack!(CONST_0), tstack::inst_math!(SUB_C), 3];
+    let mul = [tstack::inst_stack!(CONST_N1), tstack::inst_math!(MUL_C), 2];
+    let fits = [tstack::inst_stack!(CONST_3), tstack::inst_math!(MUL_C), 2];
+    test_stack(&add, stack![1]);
+    test_stack(&sub, stack![-3i64 as u64]);
+    test_stack(&mul, stack![-2i64 as u64]);
+
+    for (code, saturated) in [(&add[..], u64::MAX), (&sub, 0), (&mul, u64::MAX), (&fits, 6)] {
+        let mut engine = tstack::Engine::new();
+        engine.overflow = Overflow::Saturate;
+        engine.add_module(Rc::new(test_module(code))).unwrap();
+        engine.run(0, 0).unwrap();
+        assert_eq!(engine.stack, vec![saturated]);
+    }
+    for code in [&add[..], &sub, &mul] {
+        test_fail(
+            Some(|engine| engine.overflow = Overflow::Fault),
+            Some(|bce| matches!(bce, BytecodeError::Overflow(_))),
+            code,
+        );
+    }
+}
+
+#[test]
+fn test_strict_nan() {
+    use tstack::errors::BytecodeError;
+
+    let zero = [tstack::inst_stack!(CONST_0), tstack::inst_fpmath!(ITOF)];
+    let code = [&zero[..], &zero[..], &[tstack::inst_fpmath!(FDIV)]].concat();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(test_module(&code))).unwrap();
+    engine.run(0, 0).unwrap();
+    assert!(f64::from_bits(engine.stack[0]).is_nan());
+
+    engine.strict = tstack::config::Strictness::all();
+    let fault = engine.run(0, 0).unwrap_err();
+    assert!(matches!(fault.error(), BytecodeError::NotANumber(_)));
+
+    // NaN operands are passed on
+    let code = [tstack::inst_stack!(CONST_0), tstack::inst_fpmath!(FADD)];
+    let mut engine = tstack::Engine::new();
+    engine.strict.nan = true;
+    engine.add_module(Rc::new(test_module(&code))).unwrap();
+    engine.stack = vec![f64::NAN.to_bits()];
+    engine.run(0, 0).unwrap();
+}
+
+#[test]
+fn test_canonicalize_nan() {
+    use tstack::config::{CANONICAL_NAN, CANONICAL_NAN_F32};
+
+    // A negative NaN with a payload
+    let nan = 0xfff8_0000_0000_0123;
+    let code = [
+        tstack::inst_stack!(CONST_0),
+        tstack::inst_fpmath!(ITOF),
+        tstack::inst_fpmath!(FADD),
+        tstack::inst_fpmath!(FNEG),
+    ];
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(test_module(&code))).unwrap();
+    engine.stack = vec![nan];
+    engine.run(0, 0).unwrap();
+    assert!(f64::from_bits(engine.stack[0]).is_nan());
+
+    engine.canonicalize_nan = true;
+    engine.stack = vec![nan];
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![CANONICAL_NAN]);
+
+    let code = [
+        tstack::inst_stack!(RESERVE_C),
+        1,
+        tstack::inst_stack!(SET_F32_C),
+        0,
+        tstack::inst_stack!(GET_F32_C),
+        0,
+        tstack::inst_stack!(GET_U32_C),
+        0,
+    ];
+    let mut engine = tstack::Engine::with_config(tstack::config::EngineConfig {
+        canonicalize_nan: true,
+        ..Default::default()
+    });
+    engine.add_module(Rc::new(test_module(&code))).unwrap();
+    engine.stack = vec![nan];
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![CANONICAL_NAN, CANONICAL_NAN_F32 as u64]);
+}
+
+#[test]
+fn test_strict_unused_values() {
+    use tstack::errors::BytecodeError;
+
+    let code = [
+        tstack::inst_stack!(CONST_1),
+        tstack::inst_stack!(CONST_2),
+        tstack::inst_function!(RETURN),
+    ];
+    let mut engine = tstack::Engine::new();
+    engine.strict.unused_values = true;
+    engine.add_module(Rc::new(test_module(&code))).unwrap();
+    let fault = engine.run(0, 0).unwrap_err();
+    assert!(matches!(fault.error(), BytecodeError::UnusedValues(_)));
+    assert_eq!(
+        fault.message(),
+        "symbol main of module testmain left 2 values it does not declare as outputs"
+    );
+
+    // Values below the depth the symbol was called at are not its own
+    let code = [tstack::inst_math!(ADD), tstack::inst_function!(RETURN)];
+    let mut engine = tstack::Engine::new();
+    engine.strict.unused_values = true;
+    engine.add_module(Rc::new(test_module(&code))).unwrap();
+    engine.stack = vec![1, 2, 3];
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![1, 5]);
+}
@@ -0,0 +1,59 @@
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::errors::BytecodeError;
+use tstack::memory::Budget;
+
+fn pooled(source: &str, budget: &Budget) -> tstack::Engine {
+    let module = asm::assemble("main", source, Path::new(".")).unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.set_memory_pool(budget.clone());
+    engine
+}
+
+#[test]
+fn test_budget_exhausted() {
+    let source = ".export main\n .rept 100\n CONST_1\n .endr\n RETURN";
+    let budget = Budget::new(256);
+    let mut engine = pooled(source, &budget);
+    let fault = engine.run(0, 0).unwrap_err();
+    assert!(matches!(fault.error(), BytecodeError::OutOfMemory(_)));
+    assert!(budget.remaining() < 256);
+
+    let budget = Budget::new(1 << 16);
+    let mut engine = pooled(source, &budget);
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack.len(), 100);
+}
+
+#[test]
+fn test_locals_budget() {
+    let source = ".export main\n RESERVE_C 200\n RETURN";
+    let budget = Budget::new(1024);
+    let mut engine = pooled(source, &budget);
+    let fault = engine.run(0, 0).unwrap_err();
+    assert!(matches!(fault.error(), BytecodeError::OutOfMemory(_)));
+
+    let budget = Budget::new(1 << 16);
+    let mut engine = pooled(source, &budget);
+    engine.run(0, 0).unwrap();
+    assert!(budget.remaining() <= (1 << 16) - 200 * 8);
+}
+
+#[test]
+fn test_budget_released() {
+    let budget = Budget::new(1 << 16);
+    let mut first = pooled(".export main\n RESERVE_C 16\n CONST_1\n CONST_2\n RETURN", &budget);
+    let mut second = pooled(".export main\n CONST_1\n RETURN", &budget);
+    first.run(0, 0).unwrap();
+    let left = budget.remaining();
+    assert!(left < 1 << 16);
+    second.run(0, 0).unwrap();
+    assert!(budget.remaining() < left);
+
+    drop(first);
+    drop(second);
+    assert_eq!(budget.remaining(), 1 << 16);
+}
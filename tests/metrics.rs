@@ -0,0 +1,135 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::errors::BytecodeError;
+use tstack::metrics::Metrics;
+
+fn engine() -> tstack::Engine {
+    let module = asm::assemble(
+        "main",
+        ".func helper
+            CONST_2
+            ADD
+            RETURN
+        .export main
+            CONST_1
+            CONST_1
+            CALL_C helper
+            RETURN
+        .export broken
+            ADD
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine
+}
+
+#[test]
+fn test_metrics() {
+    let mut engine = engine();
+    let reports = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&reports);
+    engine.set_metrics_sink(move |metrics: &Metrics| sink.borrow_mut().push(metrics.clone()), None);
+
+    engine.run(0, 1).unwrap();
+    assert_eq!(engine.stack, vec![1, 3]);
+    let expected = Metrics { instructions: 7, calls: 2, faults: 0, fuel: 0, max_stack: 3 };
+    assert_eq!(*engine.metrics(), expected);
+    assert_eq!(*reports.borrow(), vec![expected]);
+
+    engine.stack.clear();
+    assert!(engine.run(0, 2).is_err());
+    assert_eq!(engine.metrics().faults, 1);
+    assert_eq!(engine.metrics().calls, 3);
+    assert_eq!(reports.borrow().len(), 2);
+}
+
+#[test]
+fn test_metrics_interval() {
+    let mut engine = engine();
+    let reports = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&reports);
+    engine.set_metrics_sink(
+        move |metrics: &Metrics| sink.borrow_mut().push(metrics.instructions),
+        Some(2),
+    );
+    engine.run(0, 1).unwrap();
+    assert_eq!(*reports.borrow(), vec![2, 4, 6, 7]);
+}
+
+#[test]
+fn test_fuel() {
+    let mut engine = engine();
+    engine.fuel = Some(4);
+    let error = engine.run(0, 1).unwrap_err().into_error();
+    assert!(matches!(error, BytecodeError::OutOfFuel(_)));
+    assert_eq!(error.to_string(), "out of fuel before opcode 0x0300");
+    assert_eq!(engine.fuel, Some(0));
+    assert_eq!(engine.metrics().fuel, 4);
+    assert_eq!(engine.metrics().faults, 1);
+
+    engine.stack.clear();
+    engine.fuel = Some(7);
+    engine.run(0, 1).unwrap();
+    assert_eq!(engine.fuel, Some(0));
+    assert_eq!(engine.metrics().fuel, 11);
+}
+
+#[test]
+fn test_fuel_of_faults() {
+    // Only the instructions run before a fault consume fuel
+    let mut engine = engine();
+    engine.fuel = Some(10);
+    assert!(engine.run(0, 2).is_err());
+    assert_eq!(engine.fuel, Some(9));
+    assert_eq!(engine.metrics().fuel, 1);
+    assert_eq!(engine.metrics().instructions, 1);
+
+    // Fuel is counted the same whether instructions run as blocks or not
+    engine.fuel = Some(10);
+    engine.run(0, 1).unwrap();
+    let metered = engine.metrics().clone();
+    engine.set_metrics_sink(|_: &Metrics| (), Some(1));
+    engine.fuel = Some(10);
+    engine.run(0, 1).unwrap();
+    assert_eq!(engine.fuel, Some(3));
+    assert_eq!(engine.metrics().fuel, metered.fuel + 7);
+    assert_eq!(engine.metrics().instructions, metered.instructions + 7);
+}
+
+#[test]
+fn test_fuel_of_loops() {
+    // Each pass of a loop is its own block, and consumes its own fuel
+    let module = asm::assemble(
+        "main",
+        ".export main
+            CONST_U16 3
+        top:
+            SUB_C 1
+            DUPE_1
+            JMP_NZ.REL top
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.fuel = Some(11);
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![0]);
+    assert_eq!(engine.fuel, Some(0));
+    assert_eq!(engine.metrics().instructions, 11);
+
+    // Running out part of the way through a pass faults within the loop
+    engine.stack.clear();
+    engine.fuel = Some(6);
+    let fault = engine.run(0, 0).unwrap_err();
+    assert!(matches!(fault.error(), BytecodeError::OutOfFuel(_)));
+    assert_eq!(fault.location().map(|l| l.offset), Some(5));
+    assert_eq!(engine.fuel, Some(0));
+}
@@ -0,0 +1,425 @@
+use std::cell::{Cell, RefCell};
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::bytecode::Instruction;
+use tstack::errors::{BytecodeError, Fault, ModuleError};
+use tstack::module::{Module, Signature, SymbolDef};
+use tstack::native::{FuncRef, StackView};
+use tstack::native_fn;
+
+fn engine_with(source: &str) -> tstack::Engine {
+    let mut engine = tstack::Engine::new();
+    engine
+        .register_native("host", "square", |stack: &mut StackView| {
+            let value = stack.pop()?;
+            stack.push(value * value)
+        })
+        .unwrap();
+    let module = asm::assemble("main", source, Path::new(".")).unwrap();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+    engine
+}
+
+#[test]
+fn test_call_native() {
+    let mut engine = engine_with(
+        ".export main
+            CONST_U16 7
+            CALL_EXT_C host.square
+            CONST_2
+            CALL_EXT_C host.square
+            ADD
+            RETURN",
+    );
+    engine.run(1, 0).unwrap();
+    assert_eq!(engine.stack, vec![53]);
+}
+
+#[test]
+fn test_call_bytecode() {
+    let mut engine = engine_with(
+        ".export main
+            CONST_3
+            CALL_C twice
+            CALL_C twice
+            PRINT_STACK
+            RETURN
+        .func twice
+            DUPE_1
+            ADD
+            RETURN",
+    );
+    engine.run(1, 0).unwrap();
+    assert_eq!(engine.stack, vec![12]);
+}
+
+#[test]
+fn test_native_state_and_errors() {
+    let mut engine = tstack::Engine::new();
+    let calls = Rc::new(Cell::new(0));
+    let counter = Rc::clone(&calls);
+    engine
+        .register_native("host", "count", move |stack: &mut StackView| {
+            counter.set(counter.get() + 1);
+            stack.push(counter.get())
+        })
+        .unwrap();
+    let fail = engine
+        .register_native("host", "fail", |stack: &mut StackView| {
+            let value = stack.pop()?;
+            Err(BytecodeError::NativeError(format!("bad value {}", value)))
+        })
+        .unwrap();
+    assert_eq!(fail, 1);
+    let host = engine.module("host").unwrap();
+    assert_eq!(host.find_symbol("count"), Some(0));
+    assert!(host.symbol(1).unwrap().exported());
+
+    engine.run(0, 0).unwrap();
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![1, 2]);
+    assert_eq!(calls.get(), 2);
+
+    let error = engine.run(0, fail).unwrap_err();
+    assert_eq!(error.error().to_string(), "native function failed: bad value 2");
+    engine.stack.clear();
+    assert!(matches!(
+        engine.run(0, fail).map_err(Fault::into_error),
+        Err(BytecodeError::StackUnderflow(_))
+    ));
+}
+
+#[test]
+fn test_register_native_errors() {
+    let mut engine = engine_with(".export main\n  RETURN");
+    let noop = |_: &mut StackView| Ok(());
+    assert!(matches!(
+        engine.register_native("main", "f", noop),
+        Err(ModuleError::NameCollision(name)) if name == "main"
+    ));
+    assert!(matches!(
+        engine.register_native("host", "square", noop),
+        Err(ModuleError::DuplicateSymbol(name)) if name == "square"
+    ));
+    assert!(matches!(
+        engine.register_native("bad name", "f", noop),
+        Err(ModuleError::InvalidName(_))
+    ));
+    let module = asm::assemble("host", ".export square\n  RETURN", Path::new(".")).unwrap();
+    assert!(matches!(
+        engine.replace_module("host", Rc::new(module)),
+        Err(ModuleError::NameCollision(_))
+    ));
+}
+
+#[test]
+fn test_call_signature() {
+    let module = Module::from_instructions(
+        "main",
+        vec![
+            (
+                SymbolDef { exported: true, ..SymbolDef::new("main") },
+                vec![Instruction::Const1, Instruction::CallC(1), Instruction::Return],
+            ),
+            (
+                SymbolDef {
+                    signature: Some(Signature { inputs: 1, outputs: 1 }),
+                    ..SymbolDef::new("pair")
+                },
+                // A count from the stack hides the effect from verification
+                vec![Instruction::Const1, Instruction::Dupe, Instruction::Return],
+            ),
+        ],
+    )
+    .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    assert!(matches!(
+        engine.run(0, 0).map_err(Fault::into_error),
+        Err(BytecodeError::BadOutputs(_))
+    ));
+}
+
+#[test]
+fn test_register_fn() {
+    let mut engine = tstack::Engine::new();
+    let scale = engine
+        .register_fn("host", "scale", |x: u64, factor: f64| (x as f64 * factor) as i64)
+        .unwrap();
+    let split = engine.register_fn("host", "split", |v: u32| (v as u16, v >> 16, v == 0)).unwrap();
+    let check = engine
+        .register_fn("host", "check", |v: i16| {
+            if v < 0 {
+                Err(BytecodeError::NativeError(String::from("negative")))
+            } else {
+                Ok(())
+            }
+        })
+        .unwrap();
+    let host = engine.module("host").unwrap();
+    assert_eq!(host.symbol(scale).unwrap().signature(), Some(Signature { inputs: 2, outputs: 1 }));
+    assert_eq!(host.symbol(split).unwrap().signature(), Some(Signature { inputs: 1, outputs: 3 }));
+    assert_eq!(host.symbol(check).unwrap().signature(), Some(Signature { inputs: 1, outputs: 0 }));
+
+    engine.stack = vec![3, (-2.5f64).to_bits()];
+    engine.run(0, scale).unwrap();
+    assert_eq!(engine.stack, vec![-7i64 as u64]);
+
+    engine.stack = vec![0xFFFF_0000_0003_0004];
+    engine.run(0, split).unwrap();
+    assert_eq!(engine.stack, vec![4, 3, 0]);
+
+    engine.stack = vec![0xFFFF];
+    let error = engine.run(0, check).unwrap_err();
+    assert_eq!(error.error().to_string(), "native function failed: negative");
+    engine.stack.clear();
+    assert!(matches!(
+        engine.run(0, check).map_err(Fault::into_error),
+        Err(BytecodeError::BadInputs(_))
+    ));
+}
+
+fn checked_div(a: i64, b: i64) -> Result<i64, String> {
+    a.checked_div(b).ok_or(format!("can not divide {} by {}", a, b))
+}
+
+#[test]
+fn test_native_fn_macro() {
+    let mut engine = tstack::Engine::new();
+    let div = engine.register_native("host", "div", native_fn!(checked_div)).unwrap();
+    let sum = engine
+        .register_native(
+            "host",
+            "sum",
+            native_fn!(|a: u8, b: u8, c: u8| a as u64 + b as u64 + c as u64),
+        )
+        .unwrap();
+    let fail = engine
+        .register_native("host", "fail", native_fn!(|| -> Result<(), &str> { Err("failed") }))
+        .unwrap();
+    assert_eq!(engine.module("host").unwrap().symbol(div).unwrap().signature(), None);
+
+    engine.stack = vec![-9i64 as u64, 2];
+    engine.run(0, div).unwrap();
+    assert_eq!(engine.stack, vec![-4i64 as u64]);
+    engine.stack = vec![1, 0];
+    let error = engine.run(0, div).unwrap_err();
+    assert_eq!(error.error().to_string(), "native function failed: can not divide 1 by 0");
+
+    engine.stack = vec![0x101, 2, 3];
+    engine.run(0, sum).unwrap();
+    assert_eq!(engine.stack, vec![6]);
+    engine.stack.clear();
+    assert!(matches!(
+        engine.run(0, sum).map_err(Fault::into_error),
+        Err(BytecodeError::StackUnderflow(_))
+    ));
+    assert!(
+        matches!(engine.run(0, fail).map_err(Fault::into_error), Err(BytecodeError::NativeError(m)) if m == "failed")
+    );
+}
+
+#[test]
+fn test_reentrant_calls() {
+    let mut engine = tstack::Engine::new();
+    engine
+        .register_native("host", "apply", |stack: &mut StackView| {
+            let value = stack.pop()?;
+            let results = stack.call("main", "twice", &[value])?;
+            for result in results {
+                stack.push(result + 1)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    engine
+        .register_native("host", "recover", |stack: &mut StackView| {
+            let depth = stack.len();
+            let error = stack.call("main", "fail", &[1, 2]).unwrap_err();
+            assert!(matches!(error, BytecodeError::InvalidData(5)));
+            assert_eq!(stack.len(), depth);
+            let error = stack.call("main", "missing", &[]).unwrap_err();
+            assert_eq!(error.to_string(), "symbol missing of module main is not loaded");
+            assert!(matches!(
+                stack.call("main", "hidden", &[]),
+                Err(BytecodeError::PrivateSymbol(_))
+            ));
+            stack.push(7)
+        })
+        .unwrap();
+    engine
+        .register_native("host", "forever", |stack: &mut StackView| {
+            stack.call("main", "again", &[])?;
+            Ok(())
+        })
+        .unwrap();
+    let module = asm::assemble(
+        "main",
+        ".export main
+            CONST_3
+            CALL_EXT_C host.apply
+            CALL_EXT_C host.recover
+            RETURN
+        .export twice
+            DUPE_1
+            CALL_C double
+            RETURN
+        .func double
+            DUPE_1
+            ADD
+            RETURN
+        .export fail
+            CALL_C hidden
+            CONST_U16 5
+            DATA_GET
+            RETURN
+        .func hidden
+            RETURN
+        .export again
+            CALL_EXT_C host.forever
+            RETURN
+        .export recurse
+            CALL_C recurse
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+
+    engine.run(1, 0).unwrap();
+    assert_eq!(engine.stack, vec![4, 7, 7]);
+
+    engine.stack.clear();
+    let again = engine.module("main").unwrap().find_symbol("again").unwrap();
+    assert!(matches!(
+        engine.run(1, again).map_err(Fault::into_error),
+        Err(BytecodeError::FrameOverflow(_))
+    ));
+    assert!(engine.stack.is_empty());
+
+    engine.maxframes = 16;
+    let recurse = engine.module("main").unwrap().find_symbol("recurse").unwrap();
+    assert!(matches!(
+        engine.run(1, recurse).map_err(Fault::into_error),
+        Err(BytecodeError::FrameOverflow(_))
+    ));
+}
+
+#[test]
+fn test_func_refs() {
+    let mut engine = tstack::Engine::new();
+    let handlers = Rc::new(RefCell::new(vec![]));
+    let registered = Rc::clone(&handlers);
+    engine
+        .register_fn("host", "on", move |handler: FuncRef| registered.borrow_mut().push(handler))
+        .unwrap();
+    engine
+        .register_native("host", "emit", |stack: &mut StackView| {
+            let [handler, value] = stack.pop_many()?;
+            for result in stack.call_ref(FuncRef::from_value(handler), &[value])? {
+                stack.push(result)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    engine.register_fn("host", "square", |v: u64| v * v).unwrap();
+    let module = asm::assemble(
+        "main",
+        ".export main
+            FUNC_REF_C handler
+            CALL_EXT_C host.on
+            FUNC_REF_C hidden
+            CALL_EXT_C host.on
+            FUNC_REF_C handler
+            CONST_4
+            CALL_EXT_C host.emit
+            CONST_3
+            FUNC_REF_C hidden
+            CALL_REF
+            FUNC_REF_EXT_C host.square
+            CALL_REF
+            RETURN
+        .export handler
+            CONST_1
+            ADD
+            RETURN
+        .func hidden
+            DUPE_1
+            ADD
+            RETURN
+        .export forged
+            CONST_U64 0xFFFF00000000
+            CALL_REF
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+
+    let main = 1;
+    engine.run(main, 0).unwrap();
+    assert_eq!(engine.stack, vec![5, 36]);
+    assert_eq!(*handlers.borrow(), vec![FuncRef::new(main, 1), FuncRef::new(main, 2)]);
+
+    // The host may call the stored handlers after the run which gave them
+    engine.stack.clear();
+    let handler = handlers.borrow()[0];
+    assert_eq!(FuncRef::from_value(handler.value()), handler);
+    assert_eq!(engine.call_ref(handler, &[9]).unwrap(), vec![10]);
+    let hidden = handlers.borrow()[1];
+    assert!(matches!(
+        engine.call_ref(hidden, &[1]).map_err(Fault::into_error),
+        Err(BytecodeError::PrivateSymbol(2))
+    ));
+    assert!(engine.stack.is_empty());
+
+    assert!(matches!(
+        engine.run(main, 3).map_err(Fault::into_error),
+        Err(BytecodeError::InvalidModule(0xFFFF))
+    ));
+}
+
+#[test]
+fn test_halt() {
+    let mut engine = tstack::Engine::new();
+    engine
+        .register_native("host", "callback", |stack: &mut StackView| {
+            let func = FuncRef::from_value(stack.pop()?);
+            stack.call_ref(func, &[])?;
+            stack.push(99u64)
+        })
+        .unwrap();
+    let module = asm::assemble(
+        "main",
+        ".export stop
+            CONST_U16 7
+            HALT
+            CONST_1
+            RETURN
+        .export main
+            CONST_1
+            FUNC_REF_C stop
+            CALL_EXT_C host.callback
+            CONST_2
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+
+    // The halt ends the calls made before the native function too
+    engine.run(1, 1).unwrap();
+    assert_eq!(engine.halt_value(), Some(7));
+    assert_eq!(engine.stack, vec![1, 99]);
+
+    engine.stack.clear();
+    engine.run(1, 0).unwrap();
+    assert_eq!(engine.halt_value(), Some(7));
+    assert_eq!(engine.stack, Vec::<u64>::new());
+}
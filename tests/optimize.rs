@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+
+use tstack::config::Overflow;
+use tstack::module::{LocalSymbol, Module, Relocation, RelocationKind};
+use tstack::optimize::{eliminate_dead_code, fold_constants, Match, Pattern, Peephole};
+
+fn symbol(name_id: u32, code_offset: u32) -> LocalSymbol {
+    LocalSymbol { name_id, code_offset, exported: true, signature: None }
+}
+
+fn module(strings: &[&str], symbols: Vec<LocalSymbol>, bytecode: Vec<u16>) -> Module {
+    Module {
+        name: String::from("test"),
+        version: Default::default(),
+        strings: strings.iter().map(|s| String::from(*s)).collect(),
+        data: vec![],
+        writable_data: vec![],
+        local_symbols: symbols,
+        external_symbols: vec![],
+        globals: vec![],
+        external_globals: vec![],
+        bytecode: bytecode.into(),
+        relocations: vec![],
+        symbol_lookup: HashMap::new(),
+        debug: None,
+    }
+}
+
+#[test]
+fn test_dce_unreferenced_symbol() {
+    let m = module(
+        &["main", "helper"],
+        vec![symbol(0, 0), symbol(1, 2)],
+        vec![
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_sys!(HALT),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_sys!(HALT),
+        ],
+    );
+    let out = eliminate_dead_code(&m, &[0]).unwrap();
+    assert_eq!(out.bytecode, vec![tstack::inst_stack!(CONST_1), tstack::inst_sys!(HALT)]);
+    assert_eq!(out.local_symbols.len(), 1);
+    assert_eq!(out.strings, vec![String::from("main")]);
+}
+
+#[test]
+fn test_dce_fall_through() {
+    // Without a HALT, execution continues into the following symbol
+    let m = module(
+        &["main", "next"],
+        vec![symbol(0, 0), symbol(1, 1)],
+        vec![tstack::inst_stack!(CONST_1), tstack::inst_stack!(CONST_2)],
+    );
+    let out = eliminate_dead_code(&m, &[0]).unwrap();
+    assert_eq!(out.bytecode, m.bytecode);
+    assert_eq!(out.local_symbols.len(), 1);
+}
+
+#[test]
+fn test_dce_absolute_jump() {
+    let mut m = module(
+        &["main"],
+        vec![symbol(0, 0)],
+        vec![
+            tstack::inst_jump!(SRC_C16, MODE_ABSOLUTE),
+            4,
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_sys!(HALT),
+        ],
+    );
+    m.relocations = vec![Relocation { offset: 1, words: 1, kind: RelocationKind::Code }];
+    let out = eliminate_dead_code(&m, &[0]).unwrap();
+    assert_eq!(
+        out.bytecode,
+        vec![
+            tstack::inst_jump!(SRC_C16, MODE_ABSOLUTE),
+            2,
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_sys!(HALT),
+        ]
+    );
+    assert_eq!(
+        out.relocations,
+        vec![Relocation { offset: 1, words: 1, kind: RelocationKind::Code }]
+    );
+}
+
+#[test]
+fn test_dce_relative_jump() {
+    let m = module(
+        &["main"],
+        vec![symbol(0, 0)],
+        vec![
+            tstack::inst_jump!(SRC_C32, MODE_RELATIVE),
+            0,
+            2,
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_sys!(HALT),
+        ],
+    );
+    let out = eliminate_dead_code(&m, &[0]).unwrap();
+    assert_eq!(
+        out.bytecode,
+        vec![tstack::inst_jump!(SRC_C32, MODE_RELATIVE), 0, 0, tstack::inst_sys!(HALT)]
+    );
+}
+
+#[test]
+fn test_dce_conditional_jump() {
+    let m = module(
+        &["main"],
+        vec![symbol(0, 0)],
+        vec![
+            tstack::inst_jump!(SRC_C16, MODE_ABSOLUTE, TYPE_Z),
+            4,
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_sys!(HALT),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_sys!(HALT),
+            tstack::inst_stack!(CONST_3),
+        ],
+    );
+    let out = eliminate_dead_code(&m, &[0]).unwrap();
+    assert_eq!(out.bytecode, m.bytecode[..6].to_vec());
+}
+
+#[test]
+fn test_dce_strings_and_data() {
+    let mut m = module(
+        &["unused", "main", "text"],
+        vec![symbol(1, 0)],
+        vec![tstack::inst_stack!(CONST_U16), 2, tstack::inst_stack!(CONST_U16), 1],
+    );
+    m.data = vec![10, 20];
+    m.relocations = vec![
+        Relocation { offset: 1, words: 1, kind: RelocationKind::String },
+        Relocation { offset: 3, words: 1, kind: RelocationKind::Data },
+    ];
+    let out = eliminate_dead_code(&m, &[0]).unwrap();
+    assert_eq!(out.strings, vec![String::from("main"), String::from("text")]);
+    assert_eq!(out.data, vec![20]);
+    assert_eq!(out.local_symbols[0].name_id, 0);
+    assert_eq!(
+        out.bytecode,
+        vec![tstack::inst_stack!(CONST_U16), 1, tstack::inst_stack!(CONST_U16), 0]
+    );
+}
+
+#[test]
+fn test_dce_symbol_reference() {
+    let mut m = module(
+        &["a", "b", "c"],
+        vec![symbol(0, 0), symbol(1, 3), symbol(2, 4)],
+        vec![
+            tstack::inst_stack!(CONST_U16),
+            2,
+            tstack::inst_sys!(HALT),
+            tstack::inst_sys!(HALT),
+            tstack::inst_sys!(NOP),
+            tstack::inst_sys!(HALT),
+        ],
+    );
+    m.relocations = vec![Relocation { offset: 1, words: 1, kind: RelocationKind::LocalSymbol }];
+    let out = eliminate_dead_code(&m, &[0]).unwrap();
+    assert_eq!(out.local_symbols.len(), 2);
+    assert_eq!(out.strings, vec![String::from("a"), String::from("c")]);
+    assert_eq!(out.local_symbols[1].code_offset, 3);
+    assert_eq!(out.bytecode[1], 1);
+}
+
+#[test]
+fn test_dce_dynamic_jump_keeps_code() {
+    let m = module(
+        &["main", "other"],
+        vec![symbol(0, 0), symbol(1, 3)],
+        vec![
+            tstack::inst_stack!(CONST_4),
+            tstack::inst_jump!(SRC_DYN, MODE_ABSOLUTE),
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_sys!(HALT),
+        ],
+    );
+    let out = eliminate_dead_code(&m, &[0]).unwrap();
+    assert_eq!(out.bytecode, m.bytecode);
+    assert_eq!(out.local_symbols.len(), 1);
+}
+
+#[test]
+fn test_dce_invalid_root() {
+    let m = module(&["main"], vec![symbol(0, 0)], vec![tstack::inst_sys!(HALT)]);
+    assert!(matches!(
+        eliminate_dead_code(&m, &[3]),
+        Err(tstack::errors::ModuleError::InvalidSymbol(3))
+    ));
+}
+
+fn run(module: Module) -> Vec<u64> {
+    let mut engine = tstack::Engine::new();
+    engine.add_module(std::rc::Rc::new(module)).unwrap();
+    engine.run(0, 0).unwrap();
+    engine.stack
+}
+
+#[test]
+fn test_fold_add() {
+    let m = module(
+        &["main"],
+        vec![symbol(0, 0)],
+        vec![
+            tstack::inst_stack!(CONST_U16),
+            5,
+            tstack::inst_stack!(CONST_U16),
+            3,
+            tstack::inst_math!(ADD),
+        ],
+    );
+    let out = fold_constants(&m).unwrap();
+    assert_eq!(out.bytecode, vec![tstack::inst_stack!(CONST_8)]);
+}
+
+#[test]
+fn test_fold_chain() {
+    let m = module(
+        &["main"],
+        vec![symbol(0, 0)],
+        vec![
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(CONST_4),
+            tstack::inst_stack!(CONST_U16),
+            300,
+            tstack::inst_math!(SUB),
+            tstack::inst_math!(MUL_C),
+            2,
+            tstack::inst_math!(DIV),
+        ],
+    );
+    let out = fold_constants(&m).unwrap();
+    assert_eq!(out.bytecode, vec![tstack::inst_stack!(CONST_U16), 592]);
+    assert_eq!(run(out), run(m));
+}
+
+#[test]
+fn test_fold_matches_engine_semantics() {
+    let m = module(
+        &["main"],
+        vec![symbol(0, 0)],
+        vec![
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_stack!(CONST_U16),
+            7,
+            tstack::inst_math!(SUB),
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_math!(SUB_C),
+            9,
+            tstack::inst_stack!(CONST_I32),
+            0x8000,
+            0x0000,
+            tstack::inst_math!(ADD_C),
+            0xFFFF,
+        ],
+    );
+    let out = fold_constants(&m).unwrap();
+    assert!(out.bytecode.len() < m.bytecode.len());
+    assert_eq!(run(out), run(m));
+}
+
+#[test]
+fn test_fold_skips_overflow() {
+    let m = module(
+        &["main"],
+        vec![symbol(0, 0)],
+        vec![
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_stack!(CONST_U16),
+            2,
+            tstack::inst_math!(MUL),
+            tstack::inst_stack!(CONST_N1),
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_math!(ADD),
+            tstack::inst_stack!(CONST_0),
+            tstack::inst_math!(SUB_C),
+            1,
+            tstack::inst_stack!(CONST_N1),
+            tstack::inst_math!(MUL_C),
+            2,
+        ],
+    );
+    let out = fold_constants(&m).unwrap();
+    assert_eq!(out.bytecode[..2], [tstack::inst_stack!(CONST_U16), 6]);
+    assert_eq!(out.bytecode[2..], m.bytecode[4..]);
+
+    // The folded code behaves the same as the original under every policy
+    let run_with = |module: &Module, overflow: Overflow, strict: bool| {
+        let mut engine = tstack::Engine::new();
+        engine.add_module(std::rc::Rc::new(module.clone())).unwrap();
+        engine.overflow = overflow;
+        engine.strict.overflow = strict;
+        engine.run(0, 0).map(|()| engine.stack).map_err(|fault| fault.into_error().code())
+    };
+    for (overflow, strict) in [
+        (Overflow::Wrap, false),
+        (Overflow::Saturate, false),
+        (Overflow::Fault, false),
+        (Overflow::Wrap, true),
+    ] {
+        assert_eq!(run_with(&out, overflow, strict), run_with(&m, overflow, strict));
+    }
+    assert_eq!(run_with(&out, Overflow::Saturate, false), Ok(vec![6, u64::MAX, 0, u64::MAX]));
+    assert!(run_with(&out, Overflow::Fault, false).is_err());
+}
+
+#[test]
+fn test_fold_skips_division_by_zero() {
+    let m = module(
+        &["main"],
+        vec![symbol(0, 0)],
+        vec![tstack::inst_stack!(CONST_0), tstack::inst_stack!(CONST_4), tstack::inst_math!(DIV)],
+    );
+    assert_eq!(fold_constants(&m).unwrap().bytecode, m.bytecode);
+}
+
+#[test]
+fn test_fold_stops_at_boundaries() {
+    let m = module(
+        &["main", "entry"],
+        vec![symbol(0, 0), symbol(1, 1)],
+        vec![tstack::inst_stack!(CONST_1), tstack::inst_stack!(CONST_2), tstack::inst_math!(ADD)],
+    );
+    assert_eq!(fold_constants(&m).unwrap().bytecode, m.bytecode);
+}
+
+#[test]
+fn test_fold_skips_relocated_constants() {
+    let mut m = module(
+        &["main", "text"],
+        vec![symbol(0, 0)],
+        vec![tstack::inst_stack!(CONST_U16), 1, tstack::inst_math!(ADD_C), 1],
+    );
+    m.relocations = vec![Relocation { offset: 1, words: 1, kind: RelocationKind::String }];
+    assert_eq!(fold_constants(&m).unwrap().bytecode, m.bytecode);
+}
+
+#[test]
+fn test_fold_updates_jumps() {
+    let m = module(
+        &["main"],
+        vec![symbol(0, 0)],
+        vec![
+            tstack::inst_stack!(CONST_U16),
+            5,
+            tstack::inst_stack!(CONST_U16),
+            3,
+            tstack::inst_math!(ADD),
+            tstack::inst_jump!(SRC_C16, MODE_ABSOLUTE),
+            8,
+            tstack::inst_sys!(NOP),
+            tstack::inst_sys!(HALT),
+        ],
+    );
+    let out = fold_constants(&m).unwrap();
+    assert_eq!(
+        out.bytecode,
+        vec![
+            tstack::inst_stack!(CONST_8),
+            tstack::inst_jump!(SRC_C16, MODE_ABSOLUTE),
+            4,
+            tstack::inst_sys!(NOP),
+            tstack::inst_sys!(HALT),
+        ]
+    );
+}
+
+#[test]
+fn test_peephole_push_pop() {
+    let m = module(
+        &["main"],
+        vec![symbol(0, 0)],
+        vec![
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(CONST_U16),
+            7,
+            tstack::inst_stack!(POP_1),
+            tstack::inst_stack!(DUPE_1),
+            tstack::inst_stack!(POP_1),
+            tstack::inst_sys!(HALT),
+        ],
+    );
+    let out = Peephole::with_builtins().run(&m).unwrap();
+    assert_eq!(out.bytecode, vec![tstack::inst_stack!(CONST_1), tstack::inst_sys!(HALT)]);
+}
+
+#[test]
+fn test_peephole_respects_jump_targets() {
+    let m = module(
+        &["main"],
+        vec![symbol(0, 0)],
+        vec![
+            tstack::inst_jump!(SRC_C16, MODE_ABSOLUTE),
+            3,
+            tstack::inst_stack!(CONST_1),
+            tstack::inst_stack!(POP_1),
+            tstack::inst_sys!(HALT),
+        ],
+    );
+    let out = Peephole::with_builtins().run(&m).unwrap();
+    assert_eq!(out.bytecode, m.bytecode);
+}
+
+#[test]
+fn test_peephole_jump_threading() {
+    let m = module(
+        &["main"],
+        vec![symbol(0, 0)],
+        vec![
+            tstack::inst_jump!(SRC_C16, MODE_ABSOLUTE, TYPE_Z),
+            4,
+            tstack::inst_sys!(HALT),
+            tstack::inst_sys!(NOP),
+            tstack::inst_jump!(SRC_C16, MODE_RELATIVE),
+            1,
+            tstack::inst_sys!(NOP),
+            tstack::inst_sys!(HALT),
+        ],
+    );
+    let out = Peephole::with_builtins().run(&m).unwrap();
+    assert_eq!(out.bytecode[1], 7);
+    assert_eq!(out.bytecode[4..], m.bytecode[4..]);
+}
+
+#[test]
+fn test_peephole_jump_cycle() {
+    let m = module(
+        &["main"],
+        vec![symbol(0, 0)],
+        vec![
+            tstack::inst_jump!(SRC_C16, MODE_ABSOLUTE),
+            2,
+            tstack::inst_jump!(SRC_C16, MODE_ABSOLUTE),
+            0,
+        ],
+    );
+    let out = Peephole::with_builtins().run(&m).unwrap();
+    assert_eq!(out.bytecode, m.bytecode);
+}
+
+#[test]
+fn test_peephole_custom_pattern() {
+    let mut peephole = Peephole::new();
+    peephole.add(Pattern::new(
+        vec![Match::Opcode(tstack::inst_stack!(CONST_0)), Match::Opcode(tstack::inst_math!(ADD))],
+        |_| vec![],
+    ));
+    let m = module(
+        &["main"],
+        vec![symbol(0, 0)],
+        vec![
+            tstack::inst_stack!(CONST_4),
+            tstack::inst_stack!(CONST_0),
+            tstack::inst_math!(ADD),
+            tstack::inst_sys!(HALT),
+        ],
+    );
+    let out = peephole.run(&m).unwrap();
+    assert_eq!(out.bytecode, vec![tstack::inst_stack!(CONST_4), tstack::inst_sys!(HALT)]);
+}
+
+#[test]
+fn test_module_optimize_levels() {
+    let mut m = module(
+        &["main", "unused"],
+        vec![symbol(0, 0), symbol(1, 5)],
+        vec![
+            tstack::inst_stack!(CONST_2),
+            tstack::inst_stack!(CONST_3),
+            tstack::inst_math!(MUL),
+            tstack::inst_stack!(POP_1),
+            tstack::inst_sys!(HALT),
+            tstack::inst_sys!(NOP),
+        ],
+    );
+    m.local_symbols[1].exported = false;
+
+    assert_eq!(m.optimize(0).unwrap().bytecode, m.bytecode);
+    let out = m.optimize(1).unwrap();
+    assert_eq!(out.bytecode, vec![tstack::inst_sys!(HALT), tstack::inst_sys!(NOP)]);
+    assert_eq!(out.local_symbols.len(), 2);
+    let out = m.optimize(2).unwrap();
+    assert_eq!(out.bytecode, vec![tstack::inst_sys!(HALT)]);
+    assert_eq!(out.local_symbols.len(), 1);
+}
@@ -0,0 +1,62 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::profile::{Profile, SymbolProfile};
+use tstack::trace::TraceEvent;
+
+#[test]
+fn test_profile() {
+    let mut engine = tstack::Engine::new();
+    engine.register_fn("host", "seven", || 7u64).unwrap();
+    let module = asm::assemble(
+        "main",
+        ".func helper
+            CALL_EXT_C host.seven
+            ADD
+            RETURN
+        .export main
+            CONST_1
+            CALL_C helper
+            CALL_C helper
+            RETURN
+        .export halts
+            CONST_1
+            CALL_C helper
+            HALT",
+        Path::new("."),
+    )
+    .unwrap();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+
+    let profile = Rc::new(RefCell::new(Profile::new()));
+    let recorder = Rc::clone(&profile);
+    engine.set_trace_hook(move |event: &TraceEvent| recorder.borrow_mut().record(event));
+    engine.run(1, 1).unwrap();
+    let symbol = |symbol: &str, calls, instructions, total_instructions| SymbolProfile {
+        module: String::from(if symbol == "seven" { "host" } else { "main" }),
+        symbol: String::from(symbol),
+        calls,
+        instructions,
+        total_instructions,
+    };
+    assert_eq!(profile.borrow().instructions(), 10);
+    assert_eq!(
+        profile.borrow().symbols(),
+        vec![symbol("helper", 2, 6, 6), symbol("main", 1, 4, 10), symbol("seven", 2, 0, 0)]
+    );
+    assert_eq!(profile.borrow().opcodes()[0], (String::from("RETURN"), 3));
+
+    engine.run(1, 2).unwrap();
+    assert_eq!(
+        profile.borrow().symbols(),
+        vec![
+            symbol("helper", 3, 9, 9),
+            symbol("main", 1, 4, 10),
+            symbol("halts", 1, 3, 6),
+            symbol("seven", 3, 0, 0)
+        ]
+    );
+}
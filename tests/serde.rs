@@ -0,0 +1,96 @@
+#![cfg(feature = "serde")]
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::debuginfo::{DebugInfo, LineEntry};
+use tstack::errors::{BytecodeError, Fault, ModuleError};
+use tstack::module::{ExternalSymbol, LocalSymbol, Module, Relocation, RelocationKind, Signature};
+use tstack::version::{Version, VersionReq};
+
+fn module() -> Module {
+    Module {
+        name: String::from("main"),
+        version: Version::new(1, 2, 3),
+        strings: vec![String::from("main"), String::from("lib"), String::from("f")].into(),
+        data: vec![7],
+        writable_data: vec![3],
+        local_symbols: vec![LocalSymbol {
+            name_id: 0,
+            code_offset: 0,
+            exported: true,
+            signature: Some(Signature { inputs: 0, outputs: 1 }),
+        }],
+        external_symbols: vec![ExternalSymbol {
+            module_name_id: 1,
+            module_id: 0,
+            symbol_name_id: 2,
+            symbol_id: 0,
+            signature: None,
+            version_req: VersionReq::parse(">=1.0, <2"),
+            alias: None,
+        }],
+        globals: vec![],
+        external_globals: vec![],
+        bytecode: vec![tstack::inst_stack!(CONST_U16), 0, tstack::inst_sys!(HALT)].into(),
+        relocations: vec![Relocation { offset: 1, words: 1, kind: RelocationKind::Data }],
+        symbol_lookup: HashMap::from([(String::from("main"), 0)]),
+        debug: Some(DebugInfo {
+            lines: vec![LineEntry { offset: 0, file_id: 0, line: 1, column: 0 }],
+            locals: vec![],
+        }),
+    }
+}
+
+#[test]
+fn test_module_round_trip() {
+    let m = module();
+    let text = serde_json::to_string(&m).unwrap();
+    let out: Module = serde_json::from_str(&text).unwrap();
+    assert_eq!(out, m);
+}
+
+#[test]
+fn test_version_req_as_string() {
+    let value = serde_json::to_value(module()).unwrap();
+    assert_eq!(value["external_symbols"][0]["version_req"], ">=1.0, <2");
+}
+
+#[test]
+fn test_invalid_version_req() {
+    let mut value = serde_json::to_value(module()).unwrap();
+    value["external_symbols"][0]["version_req"] = serde_json::json!("not a version");
+    assert!(serde_json::from_value::<Module>(value).is_err());
+}
+
+#[test]
+fn test_fault_round_trip() {
+    let module =
+        tstack::asm::assemble("main", ".export main\n CONST_1\n ADD\n RETURN", Path::new("."))
+            .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.snapshot_faults = true;
+    engine.add_module(Rc::new(module)).unwrap();
+    let fault = engine.run(0, 0).unwrap_err();
+
+    let value = serde_json::to_value(&fault).unwrap();
+    assert_eq!(value["error"]["StackUnderflow"]["instruction"], tstack::inst_math!(ADD));
+    assert_eq!(value["backtrace"][0]["offset"], 1);
+    assert_eq!(value["snapshot"]["offset"], 1);
+
+    let out: Fault = serde_json::from_value(value).unwrap();
+    assert!(matches!(out.error(), BytecodeError::StackUnderflow(_)));
+    assert_eq!(out.to_string(), fault.to_string());
+    assert_eq!(out.snapshot(), fault.snapshot());
+}
+
+#[test]
+fn test_module_error_round_trip() {
+    let error = ModuleError::UnresolvedSymbol(String::from("lib"), String::from("f"));
+    let value = serde_json::to_value(&error).unwrap();
+    assert_eq!(value, serde_json::json!({"UnresolvedSymbol": ["lib", "f"]}));
+    let out: ModuleError = serde_json::from_value(value).unwrap();
+    assert_eq!(out.to_string(), error.to_string());
+    assert_eq!(out.code(), error.code());
+}
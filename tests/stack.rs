@@ -0,0 +1,138 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::config::{EngineConfig, StackGrowth};
+use tstack::errors::BytecodeError;
+
+/// Create an engine with a program which pushes `depth` values, then pops
+/// them all
+fn pusher(stack_growth: StackGrowth, depth: usize) -> tstack::Engine {
+    let source = format!(
+        ".export main
+            .rept {}
+                CONST_1
+            .endr
+            CONST_U16 {}
+            POP
+            RETURN",
+        depth, depth
+    );
+    let module = asm::assemble("main", &source, Path::new(".")).unwrap();
+    let mut engine = tstack::Engine::with_config(EngineConfig {
+        max_stack: 1024,
+        stack_growth,
+        ..EngineConfig::default()
+    });
+    engine.add_module(Rc::new(module)).unwrap();
+    engine
+}
+
+#[test]
+fn test_reserve_stack() {
+    let mut engine = pusher(StackGrowth::Reserve, 1000);
+    assert!(engine.stack.capacity() >= 1024);
+    let buffer = engine.stack.as_ptr();
+    engine.run(0, 0).unwrap();
+    assert!(engine.stack.is_empty());
+    assert_eq!(engine.stack.as_ptr(), buffer);
+
+    // Raising the limit grows the reserved stack when it is next full
+    engine.maxstack = 2048;
+    engine.stack.resize(1024, 0);
+    engine.run(0, 0).unwrap();
+    assert!(engine.stack.capacity() >= 2048);
+}
+
+#[test]
+fn test_reserve_huge_stack() {
+    // Reserving a stack larger than memory can count faults rather than
+    // overflowing
+    let mut engine = tstack::Engine::with_config(EngineConfig {
+        max_stack: usize::MAX / 4,
+        stack_growth: StackGrowth::Reserve,
+        ..EngineConfig::default()
+    });
+    let module = asm::assemble("main", ".export main\n CONST_1\n RETURN", Path::new(".")).unwrap();
+    engine.add_module(Rc::new(module.clone())).unwrap();
+    let fault = engine.run(0, 0).unwrap_err();
+    assert!(matches!(fault.error(), BytecodeError::OutOfMemory(_)));
+    assert!(engine.stack.is_empty());
+
+    // As does one which can be counted, but not allocated
+    let mut engine = tstack::Engine::with_config(EngineConfig {
+        max_stack: usize::MAX / 16,
+        stack_growth: StackGrowth::Reserve,
+        ..EngineConfig::default()
+    });
+    engine.add_module(Rc::new(module)).unwrap();
+    let fault = engine.run(0, 0).unwrap_err();
+    assert!(matches!(fault.error(), BytecodeError::OutOfMemory(_)));
+    assert!(engine.stack.is_empty());
+}
+
+#[test]
+fn test_chunked_stack() {
+    let mut engine = pusher(StackGrowth::Chunked(16), 40);
+    engine.run(0, 0).unwrap();
+    assert!((41..=64).contains(&engine.stack.capacity()), "{}", engine.stack.capacity());
+
+    // Runs as deep as the last keep the stack they grew
+    let capacity = engine.stack.capacity();
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack.capacity(), capacity);
+
+    // A shallower run releases what it did not need, but for a chunk
+    let mut shallow = pusher(StackGrowth::Chunked(16), 2);
+    shallow.stack.reserve_exact(200);
+    shallow.run(0, 0).unwrap();
+    assert!((19..=34).contains(&shallow.stack.capacity()), "{}", shallow.stack.capacity());
+
+    // The stack never grows past the limit
+    let mut engine = pusher(StackGrowth::Chunked(1000), 30);
+    engine.maxstack = 100;
+    engine.run(0, 0).unwrap();
+    assert!(engine.stack.capacity() < 1000);
+}
+
+#[test]
+fn test_stack_growth_faults_at_limit() {
+    let mut engine = pusher(StackGrowth::Chunked(4), 30);
+    engine.maxstack = 20;
+    assert!(engine.run(0, 0).unwrap_err().error().is_stack_overflow());
+    assert!(engine.stack.is_empty());
+}
+
+#[test]
+fn test_soft_stack_limit() {
+    let mut engine = pusher(StackGrowth::OnDemand, 30);
+    engine.soft_stack_limit = Some(10);
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+    let recorder = Rc::clone(&warnings);
+    engine.set_stack_limit_hook(move |depth| recorder.borrow_mut().push(depth));
+
+    // The hook is called once a run, when the stack first passes the limit
+    engine.run(0, 0).unwrap();
+    engine.run(0, 0).unwrap();
+    assert_eq!(*warnings.borrow(), vec![11, 11]);
+
+    engine.soft_stack_limit = Some(40);
+    engine.run(0, 0).unwrap();
+    assert_eq!(warnings.borrow().len(), 2);
+}
+
+#[test]
+fn test_stack_high_water() {
+    let mut engine = pusher(StackGrowth::OnDemand, 30);
+    assert_eq!(engine.stack_high_water(), 0);
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack_high_water(), 31);
+
+    // The mark is kept per run, and counts values below the run
+    let mut shallow = pusher(StackGrowth::OnDemand, 2);
+    shallow.stack = vec![7; 5];
+    shallow.run(0, 0).unwrap();
+    assert_eq!(shallow.stack_high_water(), 8);
+    assert_eq!(shallow.metrics().max_stack, 8);
+}
@@ -0,0 +1,331 @@
+mod common;
+
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::errors::{BytecodeError, Fault};
+use tstack::host::{Capability, HostPolicy, SharedBuffer};
+
+use common::test_dir;
+
+/// Pack a string into data segment values
+fn pack(text: &str) -> Vec<u64> {
+    text.as_bytes()
+        .chunks(8)
+        .map(|chunk| {
+            let mut bytes = [0; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(bytes)
+        })
+        .collect()
+}
+
+/// A stream sharing its buffer with the test
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Read for Shared {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn engine_with(dir: &Path) -> tstack::Engine {
+    let mut module = asm::assemble(
+        "main",
+        ".export write
+            CONST_0
+            CONST_U16 14
+            CONST_1
+            OPEN
+            DATA_RW_SET_C 15
+            DATA_RW_GET_C 15
+            CONST_4
+            CONST_U16 12
+            WRITE
+            DATA_RW_GET_C 15
+            CLOSE
+            RETURN
+        .export read
+            CONST_0
+            CONST_U16 14
+            CONST_0
+            OPEN
+            DATA_RW_SET_C 15
+            DATA_RW_GET_C 15
+            CONST_8
+            CONST_1
+            READ
+            DATA_RW_GET_C 15
+            CONST_8
+            CONST_4
+            READ
+            DATA_RW_GET_C 15
+            CONST_8
+            CONST_4
+            READ
+            DATA_RW_GET_C 15
+            CLOSE
+            DATA_RW_GET_C 15
+            CLOSE
+            RETURN
+        .export refused
+            CONST_U16 10
+            CONST_8
+            CONST_1
+            OPEN
+            CONST_U16 12
+            CONST_U16 10
+            CONST_1
+            OPEN
+            RETURN
+        .export stream
+            DATA_RW_SET_C 15
+            DATA_RW_GET_C 15
+            CONST_4
+            CONST_U16 12
+            WRITE
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    let mut data = vec![0; 16];
+    for (index, text) in
+        [(0, "out/result.txt"), (4, "hello, world"), (10, "out/../x"), (12, "ro/new.txt")]
+    {
+        let packed = pack(text);
+        data[index..index + packed.len()].copy_from_slice(&packed);
+    }
+    module.writable_data = data;
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    std::fs::create_dir_all(dir.join("out")).unwrap();
+    engine.preopen("out", dir.join("out"), true);
+    engine.preopen("ro", dir, false);
+    engine
+}
+
+#[test]
+fn test_file_io() {
+    let dir = test_dir("files");
+    let mut engine = engine_with(&dir);
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![12]);
+    assert_eq!(std::fs::read_to_string(dir.join("out/result.txt")).unwrap(), "hello, world");
+    assert!(engine.handles().is_empty());
+
+    // Closing the stream twice faults
+    engine.stack.clear();
+    engine.snapshot_faults = true;
+    let fault = engine.run(0, 1).unwrap_err();
+    assert_eq!(fault.snapshot().unwrap().stack, vec![8, 4, 0]);
+    assert!(matches!(fault.into_error(), BytecodeError::InvalidHandle(_)));
+    assert!(engine.stack.is_empty());
+    assert_eq!(engine.data[0][8], pack("orld")[0]);
+
+    // Paths outside of the preopened directories can not be opened
+    engine.stack.clear();
+    engine.run(0, 2).unwrap();
+    assert_eq!(engine.stack, vec![0, 0]);
+    assert!(!dir.join("x").exists());
+    assert!(!dir.join("new.txt").exists());
+
+    engine.policy = HostPolicy::allow_all().deny(Capability::Io);
+    assert!(matches!(
+        engine.run(0, 0).map_err(Fault::into_error),
+        Err(BytecodeError::PermissionDenied(Capability::Io))
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_host_streams() {
+    let dir = test_dir("host");
+    let mut engine = engine_with(&dir);
+    let shared = Shared::default();
+    let handle = engine.insert_stream(shared.clone());
+    engine.stack.push(handle);
+    engine.run(0, 3).unwrap();
+    assert_eq!(engine.stack, vec![12]);
+    assert_eq!(*shared.0.borrow(), b"hello, world");
+
+    // Handles to other objects are not streams
+    let handle = engine.insert_handle(5u32);
+    engine.stack = vec![handle];
+    assert!(
+        matches!(engine.run(0, 3).map_err(Fault::into_error), Err(BytecodeError::InvalidHandle(h)) if h == handle)
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_connect() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let server = std::thread::spawn(move || {
+        let (mut connection, _) = listener.accept().unwrap();
+        let mut request = [0; 4];
+        connection.read_exact(&mut request).unwrap();
+        assert_eq!(&request, b"ping");
+        connection.write_all(b"pong").unwrap();
+    });
+
+    let source = format!(
+        ".export main
+            CONST_0
+            CONST_U16 {}
+            CONNECT
+            DATA_RW_SET_C 15
+            DATA_RW_GET_C 15
+            CONST_4
+            CONST_4
+            WRITE
+            DATA_RW_GET_C 15
+            CONST_8
+            CONST_1
+            READ
+            DATA_RW_GET_C 15
+            CLOSE
+            RETURN",
+        address.len()
+    );
+    let mut module = asm::assemble("main", &source, Path::new(".")).unwrap();
+    let mut data = vec![0; 16];
+    let packed = pack(&address);
+    data[..packed.len()].copy_from_slice(&packed);
+    data[4] = pack("ping")[0];
+    module.writable_data = data;
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+
+    let error = engine.run(0, 0).unwrap_err().into_error();
+    assert!(matches!(error, BytecodeError::PermissionDenied(Capability::Network)));
+    engine.stack.clear();
+    engine.policy = engine.policy.grant(Capability::Network);
+    engine.run(0, 0).unwrap();
+    server.join().unwrap();
+    assert_eq!(engine.stack, vec![4, 4]);
+    assert_eq!(engine.data[0][8], pack("pong")[0]);
+}
+
+#[test]
+fn test_standard_streams() {
+    let mut module = asm::assemble(
+        "main",
+        ".export main
+            CONST_1
+            CONST_0
+            CONST_2
+            READ
+            CONST_2
+            CONST_0
+            CONST_U16 5
+            WRITE
+            CONST_0
+            CONST_3
+            LOG
+            CONST_1
+            CONST_0
+            CONST_2
+            READ
+            CONST_2
+            CLOSE
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    module.writable_data = vec![0; 4];
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    let (stdin, stdout, stderr) =
+        (SharedBuffer::with_contents(b"input"), SharedBuffer::new(), SharedBuffer::new());
+    engine.set_stdin(stdin.clone());
+    engine.set_stdout(stdout.clone());
+    engine.set_stderr(stderr.clone());
+
+    engine.snapshot_faults = true;
+    let fault = engine.run(0, 0).unwrap_err();
+    assert_eq!(fault.snapshot().unwrap().stack, vec![5, 5, 0]);
+    assert!(matches!(fault.into_error(), BytecodeError::InvalidHandle(2)));
+    assert!(stdin.contents().is_empty());
+    assert_eq!(stdout.take_string(), "input");
+    assert_eq!(stderr.take_string(), "inp\n");
+}
+
+/// Try to open a path with a mode, leaving the handle, or 0 if it is refused
+fn try_open(dir: &Path, path: &str, mode: u16) -> u64 {
+    let source = format!(
+        ".export main
+            CONST_0
+            CONST_U16 {}
+            CONST_U16 {}
+            OPEN
+            RETURN",
+        path.len(),
+        mode
+    );
+    let mut module = asm::assemble("main", &source, Path::new(".")).unwrap();
+    module.writable_data = pack(path);
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    std::fs::create_dir_all(dir.join("out")).unwrap();
+    engine.preopen("out", dir.join("out"), true);
+    engine.run(0, 0).unwrap();
+    engine.stack[0]
+}
+
+#[test]
+fn test_absolute_paths() {
+    let dir = test_dir("absolute");
+    let outside = dir.join("outside.txt").display().to_string();
+    for mode in 0..3 {
+        assert_eq!(try_open(&dir, &outside, mode), 0);
+        assert_eq!(try_open(&dir, &format!("out/{}", outside), mode), 0);
+        assert_eq!(try_open(&dir, &format!("out/.{}", outside), mode), 0);
+    }
+    assert!(!dir.join("outside.txt").exists());
+    assert_ne!(try_open(&dir, "out/./inside.txt", 1), 0);
+    assert!(dir.join("out/inside.txt").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlink_escapes() {
+    use std::os::unix::fs::symlink;
+
+    let dir = test_dir("symlinks");
+    std::fs::create_dir_all(dir.join("out")).unwrap();
+    std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+    symlink(dir.join("secret.txt"), dir.join("out/secret")).unwrap();
+    symlink(dir.join("escaped.txt"), dir.join("out/dangling")).unwrap();
+    symlink(&dir, dir.join("out/parent")).unwrap();
+
+    for mode in 0..3 {
+        assert_eq!(try_open(&dir, "out/secret", mode), 0);
+        assert_eq!(try_open(&dir, "out/dangling", mode), 0);
+        assert_eq!(try_open(&dir, "out/parent/escaped.txt", mode), 0);
+    }
+    assert!(!dir.join("escaped.txt").exists());
+    assert_eq!(std::fs::read_to_string(dir.join("secret.txt")).unwrap(), "secret");
+
+    // Links which stay beneath the directory may still be followed
+    std::fs::write(dir.join("out/inside.txt"), "inside").unwrap();
+    symlink(dir.join("out/inside.txt"), dir.join("out/link")).unwrap();
+    assert_ne!(try_open(&dir, "out/link", 0), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
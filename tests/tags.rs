@@ -0,0 +1,116 @@
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::config::EngineConfig;
+use tstack::errors::{BytecodeError, Fault};
+use tstack::native::StackView;
+use tstack::tags::Tag;
+
+fn tagged(source: &str) -> tstack::Engine {
+    let module = asm::assemble("main", source, Path::new(".")).unwrap();
+    let mut engine =
+        tstack::Engine::with_config(EngineConfig { tag_stack: true, ..EngineConfig::default() });
+    engine.add_module(Rc::new(module)).unwrap();
+    engine
+}
+
+/// Run the `main` symbol, returning the error it faults with
+fn fault(source: &str) -> BytecodeError {
+    tagged(source).run(0, 0).map_err(Fault::into_error).unwrap_err()
+}
+
+#[test]
+fn test_stack_tags() {
+    let mut engine = tagged(
+        ".export main
+            CONST_2
+            CONST_3
+            ITOF
+            FUNC_REF_C main
+            CONST_U64 0x3ff8000000000000
+            ROTATE_1_C 4
+            DUPE_C 2
+            SWAP_C 4
+            RETURN",
+    );
+    engine.run(0, 0).unwrap();
+    let three = 3.0f64.to_bits();
+    assert_eq!(engine.stack[..2], [1.5f64.to_bits(), 2]);
+    assert_eq!(engine.stack[3..], [three, engine.stack[2], three]);
+    let tags = [Tag::Any, Tag::Int, Tag::FuncRef, Tag::F64, Tag::FuncRef, Tag::F64];
+    assert_eq!(engine.stack_tags(), tags);
+
+    engine.stack.push(7);
+    assert_eq!(engine.stack_tags().last(), Some(&Tag::Any));
+}
+
+#[test]
+fn test_tag_mismatch() {
+    let error = fault(".export main\n CONST_1\n CONST_2\n ITOF\n ADD\n RETURN");
+    assert!(matches!(error, BytecodeError::TagMismatch(_)));
+    assert_eq!(error.to_string(), "opcode 0x0300 requires a value tagged int; found f64");
+
+    let error = fault(".export main\n CONST_1\n CALL_REF\n RETURN");
+    assert_eq!(error.to_string(), "opcode 0x0505 requires a value tagged funcref; found int");
+
+    let error = fault(".export main\n FUNC_REF_C main\n CONST_1\n FADD\n RETURN");
+    assert!(matches!(error, BytecodeError::TagMismatch(_)));
+
+    // Counts taken from the stack are integers
+    let error = fault(".export main\n CONST_1\n CONST_1\n ITOF\n POP\n RETURN");
+    assert!(matches!(error, BytecodeError::TagMismatch(_)));
+}
+
+#[test]
+fn test_untagged_values() {
+    // Values of unknown type may be consumed as any type
+    let mut engine = tagged(
+        ".export main
+            CONST_U64 0x3ff8000000000000
+            CONST_1
+            ITOF
+            FADD
+            RESERVE_C 1
+            SET_64_C 0
+            GET_U64_C 0
+            FTOI
+            RETURN",
+    );
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![2]);
+
+    // Without tagging, mismatched values run on
+    let mut untagged = tagged(".export main\n CONST_1\n CONST_2\n ITOF\n ADD\n RETURN");
+    untagged.tag_stack = false;
+    untagged.run(0, 0).unwrap();
+}
+
+#[test]
+fn test_native_tags() {
+    let module = asm::assemble(
+        "main",
+        ".export main
+            CONST_2
+            ITOF
+            CALL_EXT_C host.half
+            FADD
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.tag_stack = true;
+    engine
+        .register_native("host", "half", |stack: &mut StackView| {
+            let value = f64::from_bits(stack.pop()?);
+            stack.push(value.to_bits())?;
+            stack.push((value / 2.0).to_bits())
+        })
+        .unwrap();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+    engine.run(1, 0).unwrap();
+    assert_eq!(engine.stack, vec![3.0f64.to_bits()]);
+    assert_eq!(engine.stack_tags(), [Tag::F64]);
+}
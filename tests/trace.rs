@@ -0,0 +1,57 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::trace::TraceEvent;
+
+#[test]
+fn test_trace_hook() {
+    let mut engine = tstack::Engine::new();
+    engine.register_fn("host", "seven", || 7u64).unwrap();
+    let module = asm::assemble(
+        "main",
+        ".export main
+            CALL_EXT_C host.seven
+            CONST_1
+            ADD
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.link().unwrap();
+
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let log = Rc::clone(&events);
+    engine.set_trace_hook(move |event: &TraceEvent| {
+        let entry = match event {
+            TraceEvent::Instruction { offset, stack, .. } => {
+                format!("{} {} {:?}", offset, event.instruction().unwrap(), stack)
+            }
+            TraceEvent::Call { module, symbol_id, depth, .. } => {
+                format!("call {}:{} at {}", module.name, symbol_id, depth)
+            }
+            TraceEvent::Return { depth } => format!("return to {}", depth),
+        };
+        log.borrow_mut().push(entry);
+    });
+    engine.run(1, 0).unwrap();
+    assert_eq!(
+        *events.borrow(),
+        vec![
+            "call main:0 at 0",
+            "0 CALL_EXT_C 0 []",
+            "call host:0 at 1",
+            "return to 1",
+            "2 CONST_1 [7]",
+            "3 ADD [7, 1]",
+            "4 RETURN [8]",
+            "return to 0",
+        ]
+    );
+
+    assert!(engine.clear_trace_hook().is_some());
+    engine.run(1, 0).unwrap();
+    assert_eq!(events.borrow().len(), 8);
+}
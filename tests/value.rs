@@ -0,0 +1,79 @@
+use std::path::Path;
+use std::rc::Rc;
+
+use tstack::asm;
+use tstack::native::FuncRef;
+use tstack::tags::Tag;
+use tstack::Value;
+
+#[test]
+fn test_value_bits() {
+    let func = FuncRef::new(2, 7);
+    let values = [
+        (Value::U64(u64::MAX), u64::MAX, Tag::Int),
+        (Value::I64(-1), u64::MAX, Tag::Int),
+        (Value::F32(1.5), 1.5f32.to_bits() as u64, Tag::F32),
+        (Value::F64(-0.5), (-0.5f64).to_bits(), Tag::F64),
+        (Value::FuncRef(func), func.value(), Tag::FuncRef),
+        (Value::Handle(9), 9, Tag::Handle),
+    ];
+    for (value, bits, tag) in values {
+        assert_eq!(value.to_bits(), bits);
+        assert_eq!(value.tag(), tag);
+    }
+    assert_eq!(Value::from_bits(1.5f32.to_bits() as u64, Tag::F32), Value::F32(1.5));
+    assert_eq!(Value::from_bits(func.value(), Tag::FuncRef), Value::FuncRef(func));
+    assert_eq!(Value::from_bits(5, Tag::Any), Value::U64(5));
+}
+
+#[test]
+fn test_stack_values() {
+    let module = asm::assemble(
+        "main",
+        ".export main
+            ITOF
+            FADD
+            FUNC_REF_C main
+            RETURN",
+        Path::new("."),
+    )
+    .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.tag_stack = true;
+    engine.push_value(0.5f64);
+    engine.push_value(2i64);
+    assert_eq!(engine.stack_tags(), [Tag::F64, Tag::Int]);
+    engine.run(0, 0).unwrap();
+    let main = FuncRef::new(0, 0);
+    assert_eq!(engine.stack_values(), [Value::F64(2.5), Value::FuncRef(main)]);
+
+    // Untagged stacks hold untyped words
+    engine.tag_stack = false;
+    assert_eq!(engine.stack_values(), [Value::U64(2.5f64.to_bits()), Value::U64(main.value())]);
+}
+
+#[test]
+fn test_typed_push_pop() {
+    let mut engine = tstack::Engine::new();
+    let func = FuncRef::new(1, 3);
+    engine.push_u64(u64::MAX);
+    engine.push_i64(-3);
+    engine.push_f32(-1.25);
+    engine.push_f64(0.1);
+    engine.push_bool(true);
+    engine.push_func_ref(func);
+    assert_eq!(engine.stack[..3], [u64::MAX, -3i64 as u64, (-1.25f32).to_bits() as u64]);
+
+    assert_eq!(engine.pop_func_ref().unwrap(), func);
+    assert!(engine.pop_bool().unwrap());
+    assert_eq!(engine.pop_f64().unwrap(), 0.1);
+    assert_eq!(engine.pop_f32().unwrap(), -1.25);
+    assert_eq!(engine.pop_i64().unwrap(), -3);
+    assert_eq!(engine.pop_u64().unwrap(), u64::MAX);
+    let error = engine.pop_u64().unwrap_err();
+    assert!(error.is_stack_underflow());
+
+    engine.push_u64(2);
+    assert!(engine.pop_bool().unwrap());
+}
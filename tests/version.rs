@@ -0,0 +1,64 @@
+use tstack::version::{Version, VersionReq};
+
+fn matches(req: &str, version: &str) -> bool {
+    VersionReq::parse(req).unwrap().matches(&Version::parse(version).unwrap())
+}
+
+#[test]
+fn test_version_parse() {
+    assert_eq!(Version::parse("1.2.3"), Some(Version::new(1, 2, 3)));
+    assert_eq!(Version::parse("1.2"), None);
+    assert_eq!(Version::parse("1.2.3.4"), None);
+    assert_eq!(Version::parse("1.x.3"), None);
+    assert_eq!(Version::new(1, 2, 3).to_string(), "1.2.3");
+}
+
+#[test]
+fn test_req_parse() {
+    assert!(VersionReq::parse("^1.2").is_some());
+    assert!(VersionReq::parse(">=1.0, <2.0").is_some());
+    assert!(VersionReq::parse("").is_none());
+    assert!(VersionReq::parse("~a").is_none());
+    assert_eq!(VersionReq::parse(">=1.0,<2").unwrap().to_string(), ">=1.0, <2");
+}
+
+#[test]
+fn test_req_caret() {
+    assert!(matches("1.2", "1.2.0"));
+    assert!(matches("^1.2", "1.9.9"));
+    assert!(!matches("^1.2", "1.1.9"));
+    assert!(!matches("^1.2", "2.0.0"));
+    assert!(matches("^0.2.3", "0.2.5"));
+    assert!(!matches("^0.2.3", "0.3.0"));
+    assert!(matches("^0.0.3", "0.0.3"));
+    assert!(!matches("^0.0.3", "0.0.4"));
+}
+
+#[test]
+fn test_req_tilde() {
+    assert!(matches("~1.2.3", "1.2.9"));
+    assert!(!matches("~1.2.3", "1.3.0"));
+    assert!(matches("~1", "1.9.0"));
+    assert!(!matches("~1", "2.0.0"));
+}
+
+#[test]
+fn test_req_comparisons() {
+    assert!(matches("=1.2", "1.2.7"));
+    assert!(!matches("=1.2.3", "1.2.4"));
+    assert!(matches(">1.2", "1.3.0"));
+    assert!(!matches(">1.2", "1.2.9"));
+    assert!(matches("<=1.2", "1.2.9"));
+    assert!(!matches("<1.2", "1.2.0"));
+    assert!(matches(">=1.0, <2.0", "1.5.0"));
+    assert!(!matches(">=1.0, <2.0", "2.0.0"));
+}
+
+#[test]
+fn test_req_wildcard() {
+    assert!(matches("*", "7.1.0"));
+    assert!(matches("1.*", "1.4.0"));
+    assert!(!matches("1.*", "2.0.0"));
+    assert!(matches("1.2.*", "1.2.8"));
+    assert!(!matches("1.2.*", "1.3.0"));
+}
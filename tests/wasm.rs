@@ -0,0 +1,372 @@
+#![cfg(feature = "wasm-frontend")]
+
+mod common;
+
+use std::rc::Rc;
+
+use tstack::errors::{BytecodeError, CompileError};
+use tstack::module::{Relocation, RelocationKind, Signature};
+use tstack::wasm;
+
+use common::listing;
+
+#[test]
+fn test_compile_function() {
+    let module = wasm::compile(
+        "math",
+        r#"(module
+            ;; Square a number
+            (func $square (export "square") (param $x i64) (result i64) (local $y i64)
+                (local.set $y (i64.mul (local.get $x) (local.get $x)))
+                local.get $y))"#,
+    )
+    .unwrap();
+    assert_eq!(module.name, "math");
+    assert_eq!(module.local_symbols.len(), 1);
+    let symbol = module.symbol(0).unwrap();
+    assert_eq!(symbol.name(), Some("square"));
+    assert!(symbol.exported());
+    assert_eq!(symbol.signature(), Some(Signature { inputs: 1, outputs: 1 }));
+    assert_eq!(
+        listing(&module, 0),
+        vec![
+            "RESERVE_C 2",
+            "SET_64_C 0",
+            "CONST_0",
+            "SET_64_C 1",
+            "GET_U64_C 0",
+            "GET_U64_C 0",
+            "MUL",
+            "SET_64_C 1",
+            "GET_U64_C 1",
+            "RETURN",
+        ]
+    );
+}
+
+#[test]
+fn test_flat_and_folded_forms() {
+    let flat = wasm::compile(
+        "m",
+        "(func (param i64 i64) (result i64)
+            local.get 0
+            local.get 1
+            i64.sub
+            i64.const -2
+            i64.div_s)",
+    )
+    .unwrap();
+    let folded = wasm::compile(
+        "m",
+        "(func (param i64 i64) (result i64)
+            (i64.div_s (i64.sub (local.get 0) (local.get 1)) (i64.const -2)))",
+    )
+    .unwrap();
+    assert_eq!(flat, folded);
+    assert_eq!(flat.symbol(0).unwrap().name(), Some("func0"));
+    assert!(!flat.symbol(0).unwrap().exported());
+    assert_eq!(
+        listing(&flat, 0)[5..],
+        ["SWAP_1", "SUB", "CONST_I16 -2", "SWAP_1", "IDIV", "RETURN"]
+    );
+}
+
+#[test]
+fn test_run_arithmetic() {
+    let module = wasm::compile(
+        "main",
+        r#"(func (export "int") (result i64)
+               (i64.add (i64.mul (i64.const 6) (i64.const 7)) (i64.const 0x10)))
+           (func (export "float") (result i64)
+               (i64.trunc_f64_s
+                   (f64.neg (f64.mul (f64.convert_i64_s (i64.const 3)) (f64.const 2.5)))))"#,
+    )
+    .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.run(0, 0).unwrap();
+    assert_eq!(engine.stack, vec![58]);
+    engine.run(0, 1).unwrap();
+    assert_eq!(engine.stack, vec![58, -7i64 as u64]);
+}
+
+#[test]
+fn test_run_division() {
+    let module = wasm::compile(
+        "main",
+        r#"(func (export "div_s") (param $a i64) (param $b i64) (result i64)
+               (i64.div_s (local.get $a) (local.get $b)))
+           (func (export "div_u") (param $a i64) (param $b i64) (result i64)
+               (i64.div_u (local.get $a) (local.get $b)))
+           (func (export "rem_s") (param $a i64) (param $b i64) (result i64)
+               (i64.rem_s (local.get $a) (local.get $b)))
+           (func (export "rem_u") (param $a i64) (param $b i64) (result i64)
+               (i64.rem_u (local.get $a) (local.get $b)))"#,
+    )
+    .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    let mut run = |symbol: u32, a: i64, b: i64| {
+        engine.stack = vec![a as u64, b as u64];
+        engine.run(0, symbol).map(|_| engine.stack.clone())
+    };
+
+    assert_eq!(run(0, 7, 2).unwrap(), vec![3]);
+    assert_eq!(run(0, -7, 2).unwrap(), vec![-3i64 as u64]);
+    assert_eq!(run(1, 7, 2).unwrap(), vec![3]);
+    assert_eq!(run(1, -8, 2).unwrap(), vec![(-8i64 as u64) / 2]);
+    assert_eq!(run(2, 7, 2).unwrap(), vec![1]);
+    assert_eq!(run(2, -7, 2).unwrap(), vec![-1i64 as u64]);
+    assert_eq!(run(3, 7, 2).unwrap(), vec![1]);
+    assert_eq!(run(3, -7, 3).unwrap(), vec![(-7i64 as u64) % 3]);
+    for symbol in 0..4 {
+        let fault = run(symbol, 7, 0).unwrap_err();
+        assert!(matches!(fault.error(), BytecodeError::DivideByZero(_)));
+    }
+}
+
+#[test]
+fn test_run_locals() {
+    let module = wasm::compile(
+        "math",
+        r#"(func $square (param $x i64) (result i64) (local $y i64)
+               (local.set $y (i64.mul (local.get $x) (local.get $x)))
+               local.get $y)
+           (func (export "sum_squares") (param $a i64) (param $b i64) (result i64)
+               (i64.add (call $square (local.get $a)) (call $square (local.get $b))))"#,
+    )
+    .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    engine.stack.extend([3, 4]);
+    engine.run(0, 1).unwrap();
+    assert_eq!(engine.stack, vec![25]);
+}
+
+#[test]
+fn test_calls_and_imports() {
+    let module = wasm::compile(
+        "app",
+        r#"(module $app
+            (import "host" "log" (func $log (param i64)))
+            (func $twice (param i64) (result i64)
+                (i64.add (local.get 0) (local.get 0)))
+            (func $main
+                (call $log (call $twice (i64.const 4))))
+            (export "main" (func $main))
+            (export "start" (func 2)))"#,
+    )
+    .unwrap();
+    assert_eq!(module.external_symbols.len(), 1);
+    let ext = &module.external_symbols[0];
+    assert_eq!(&module.strings[ext.module_name_id as usize], "host");
+    assert_eq!(&module.strings[ext.symbol_name_id as usize], "log");
+    assert_eq!(ext.signature, Some(Signature { inputs: 1, outputs: 0 }));
+
+    assert_eq!(module.symbol(0).unwrap().name(), Some("twice"));
+    assert_eq!(module.symbol(1).unwrap().name(), Some("main"));
+    assert_eq!(module.find_symbol("start"), Some(1));
+    assert_eq!(listing(&module, 1), vec!["CONST_4", "CALL_C 0", "CALL_EXT_C 0", "RETURN"]);
+
+    let main = module.local_symbols[1].code_offset;
+    assert_eq!(
+        module.relocations,
+        vec![
+            Relocation { offset: main + 2, words: 1, kind: RelocationKind::LocalSymbol },
+            Relocation { offset: main + 4, words: 1, kind: RelocationKind::ExternalSymbol },
+        ]
+    );
+}
+
+#[test]
+fn test_control_flow() {
+    let module = wasm::compile(
+        "m",
+        "(func (param $n i64) (result i64) (local $sum i64)
+            (block $done
+                (loop $next
+                    (br_if $done (i64.eqz (local.get $n)))
+                    (local.set $sum (i64.add (local.get $sum) (local.get $n)))
+                    (local.set $n (i64.sub (local.get $n) (i64.const 1)))
+                    (br $next)))
+            (if (result i64) (i64.lt_s (local.get $sum) (i64.const 100))
+                (then (local.get $sum))
+                (else (i64.const 100))))",
+    )
+    .unwrap();
+    assert_eq!(
+        listing(&module, 0),
+        vec![
+            "RESERVE_C 2",
+            "SET_64_C 0",
+            "CONST_0",
+            "SET_64_C 1",
+            // i64.eqz
+            "GET_U64_C 0",
+            "JMP_Z.C16.REL +3",
+            "CONST_0",
+            "JMP.C16.REL +1",
+            "CONST_1",
+            // br_if $done
+            "JMP_Z.C16.REL +2",
+            "JMP.C16.REL +16",
+            "GET_U64_C 1",
+            "GET_U64_C 0",
+            "ADD",
+            "SET_64_C 1",
+            "GET_U64_C 0",
+            "CONST_1",
+            "SWAP_1",
+            "SUB",
+            "SET_64_C 0",
+            "JMP.C16.REL -28",
+            // i64.lt_s
+            "GET_U64_C 1",
+            "CONST_U16 100",
+            "JMP_GTS.C16.REL +3",
+            "CONST_0",
+            "JMP.C16.REL +1",
+            "CONST_1",
+            // if
+            "JMP_Z.C16.REL +4",
+            "GET_U64_C 1",
+            "JMP.C16.REL +2",
+            "CONST_U16 100",
+            "RETURN",
+        ]
+    );
+}
+
+#[test]
+fn test_run_control_flow() {
+    let module = wasm::compile(
+        "m",
+        "(func (export \"sum\") (param $n i64) (result i64) (local $sum i64)
+            (block $done
+                (loop $next
+                    (br_if $done (i64.eqz (local.get $n)))
+                    (local.set $sum (i64.add (local.get $sum) (local.get $n)))
+                    (local.set $n (i64.sub (local.get $n) (i64.const 1)))
+                    (br $next)))
+            (if (result i64) (i64.lt_s (local.get $sum) (i64.const 100))
+                (then (local.get $sum))
+                (else (i64.const 100))))
+         (func (export \"discard\") (result i64)
+            i64.const 1
+            (block (result i64)
+                i64.const 2
+                i64.const 3
+                br 0)
+            i64.add
+            i64.const 4
+            i64.const 5
+            return)",
+    )
+    .unwrap();
+    let mut engine = tstack::Engine::new();
+    engine.add_module(Rc::new(module)).unwrap();
+    for (n, sum) in [(0, 0), (10, 55), (20, 100)] {
+        engine.stack = vec![n];
+        engine.run(0, 0).unwrap();
+        assert_eq!(engine.stack, vec![sum]);
+    }
+    // Branches and returns drop the values below their results
+    engine.stack.clear();
+    engine.run(0, 1).unwrap();
+    assert_eq!(engine.stack, vec![5]);
+}
+
+#[test]
+fn test_branch_discards_values() {
+    let module = wasm::compile(
+        "m",
+        "(func (result i64)
+            i64.const 1
+            (block (result i64)
+                i64.const 2
+                i64.const 3
+                br 0)
+            i64.add
+            i64.const 4
+            i64.const 5
+            return)",
+    )
+    .unwrap();
+    assert_eq!(
+        listing(&module, 0),
+        vec![
+            "CONST_1",
+            "CONST_2",
+            "CONST_3",
+            "ROTATE_1_C 2",
+            "POP_C 1",
+            "JMP.C16.REL +0",
+            "ADD",
+            "CONST_4",
+            "CONST_U16 5",
+            "ROTATE_1_C 3",
+            "POP_C 2",
+            "RETURN",
+            "RETURN",
+        ]
+    );
+}
+
+#[test]
+fn test_compile_errors() {
+    let error = |source: &str| wasm::compile("m", source).unwrap_err();
+
+    assert!(matches!(error("(memory 1)"), CompileError::Unsupported(1, _)));
+    assert!(matches!(
+        error("(func (param i32))"),
+        CompileError::Unsupported(1, t) if t == "value type `i32`"
+    ));
+    assert!(matches!(
+        error("(func\n  (i64.popcnt (i64.const 1)))"),
+        CompileError::Unsupported(2, op) if op == "`i64.popcnt`"
+    ));
+    assert!(matches!(
+        error("(func (result i64)\n  local.get $x)"),
+        CompileError::UndefinedName(2, name) if name == "$x"
+    ));
+    assert!(matches!(
+        error("(func (call 3))"),
+        CompileError::UndefinedName(1, name) if name == "3"
+    ));
+    assert!(matches!(
+        error("(func $f)\n(func $f)"),
+        CompileError::DuplicateName(2, name) if name == "$f"
+    ));
+    assert!(matches!(
+        error(r#"(func (export "a"))(func (export "a"))"#),
+        CompileError::DuplicateName(1, name) if name == "a"
+    ));
+    assert!(matches!(
+        error("(func\n  (; comment ;)\n  i64.add)"),
+        CompileError::InvalidSyntax(3, _)
+    ));
+    assert!(matches!(error("(func (result i64))"), CompileError::InvalidSyntax(1, _)));
+    assert!(matches!(error("(module\n  (func)"), CompileError::InvalidSyntax(1, _)));
+    assert!(matches!(
+        error(r#"(func) (import "a" "b" (func))"#),
+        CompileError::InvalidSyntax(1, _)
+    ));
+}
+
+#[test]
+fn test_nesting_limit() {
+    let nested = |depth: usize| {
+        let source = format!(
+            "(module (func (result i64) {}(i64.const 1){}))",
+            "(i64.eqz ".repeat(depth),
+            ")".repeat(depth)
+        );
+        wasm::compile("m", &source)
+    };
+    assert!(nested(253).is_ok());
+    assert!(matches!(
+        nested(254).unwrap_err(),
+        CompileError::Unsupported(1, message) if message == "lists nested deeper than 256 levels"
+    ));
+    assert!(matches!(nested(100_000).unwrap_err(), CompileError::Unsupported(1, _)));
+}